@@ -0,0 +1,128 @@
+//! Time per broadcast to topics with many subscribers.
+//!
+//! Run with `cargo bench --bench fanout`. Each topic size is measured the way broadcasts
+//! worked before, copying the topic's clients out of storage and queueing the message for
+//! one after the other, and through the topic's shared recipients and the sharded fan-out.
+//! Shards only run in parallel with more than one worker thread.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use morpheus::{
+    core::{
+        fanout,
+        msg::{Outgoing, Qos, ServerMessage},
+        queue::{BackpressurePolicy, QueueConfig, QueueReceiver},
+        storage::{Client, InMemoryStorage, Storage},
+    },
+    testing::client_record,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    hint::black_box,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+
+const TOPIC: &str = "news";
+/// Broadcasts timed between emptying the subscribers' queues, which is not timed.
+const BROADCASTS_BETWEEN_DRAINS: u64 = 16;
+
+/// A topic with `subscribers` clients, with the receiving ends of their queues.
+fn topic(subscribers: usize) -> (Arc<dyn Storage>, Vec<QueueReceiver>) {
+    let storage: Arc<dyn Storage> = Arc::new(InMemoryStorage::new());
+    let receivers = (0..subscribers)
+        .map(|_| {
+            // Room for every broadcast between drains, so none is dropped for a full queue.
+            let queue = QueueConfig {
+                capacity: BROADCASTS_BETWEEN_DRAINS as usize,
+                policy: BackpressurePolicy::DropNewest,
+            };
+            let id = Uuid::new_v4();
+            let (client, receiver) = client_record(id, queue);
+            storage.add_client(Client {
+                accepted_terms: HashMap::from([(TOPIC.to_string(), 1)]),
+                ..client
+            });
+            storage.subscribe_client_to_topic(&id, TOPIC.to_string());
+            receiver
+        })
+        .collect();
+    (storage, receivers)
+}
+
+/// Times `broadcast` `iterations` times, emptying the queues every
+/// `BROADCASTS_BETWEEN_DRAINS` broadcasts.
+fn time_broadcasts(
+    iterations: u64,
+    receivers: &mut [QueueReceiver],
+    mut broadcast: impl FnMut(),
+) -> Duration {
+    let mut elapsed = Duration::ZERO;
+    let mut left = iterations;
+    while left > 0 {
+        let batch = left.min(BROADCASTS_BETWEEN_DRAINS);
+        let start = Instant::now();
+        for _ in 0..batch {
+            broadcast();
+        }
+        elapsed += start.elapsed();
+        left -= batch;
+        for receiver in receivers.iter_mut() {
+            while receiver.try_recv_shared().is_ok() {}
+        }
+    }
+    elapsed
+}
+
+fn broadcasts(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let message = ServerMessage::Topic {
+        id: Uuid::new_v4(),
+        topic: TOPIC.to_string(),
+        seq: 1,
+        sender: Uuid::new_v4().to_string(),
+        content: "The quick brown fox jumps over the lazy dog".repeat(4),
+        headers: BTreeMap::new(),
+        qos: Qos::FireAndForget,
+        reply_to: None,
+        retained: false,
+    };
+
+    let mut group = c.benchmark_group("broadcast");
+    group.sample_size(10);
+    for subscribers in [1_000, 10_000, 50_000] {
+        let (storage, mut receivers) = topic(subscribers);
+        let id = BenchmarkId::new("copy clients, queue in turn (before)", subscribers);
+        group.bench_with_input(id, &subscribers, |b, _| {
+            b.iter_custom(|iterations| {
+                time_broadcasts(iterations, &mut receivers, || {
+                    let shared = Arc::new(Outgoing::new(message.clone()));
+                    for client in storage.get_clients_in_topic(TOPIC) {
+                        black_box(client.sender.send_shared(shared.clone()).is_ok());
+                    }
+                })
+            })
+        });
+        let id = BenchmarkId::new("shared recipients, sharded (after)", subscribers);
+        group.bench_with_input(id, &subscribers, |b, _| {
+            b.iter_custom(|iterations| {
+                time_broadcasts(iterations, &mut receivers, || {
+                    let shared = Arc::new(Outgoing::new(message.clone()));
+                    let recipients = storage.topic_recipients(TOPIC);
+                    let outcomes = runtime.block_on(fanout::fan_out(
+                        storage.clone(),
+                        recipients,
+                        None,
+                        shared,
+                    ));
+                    black_box(outcomes);
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, broadcasts);
+criterion_main!(benches);
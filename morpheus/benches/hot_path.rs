@@ -0,0 +1,153 @@
+//! Allocations and time per message on the receive and broadcast paths.
+//!
+//! Run with `cargo bench --bench hot_path`. Each path is measured next to the way it
+//! worked before frames were decoded in place, broadcasts were serialized once and the
+//! unacknowledged messages of session clients shared that copy.
+
+use morpheus::core::msg::{self, ClientMessage, Envelope, Outgoing, Qos, ServerMessage};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    collections::BTreeMap,
+    hint::black_box,
+    sync::Arc,
+    time::Instant,
+};
+use uuid::Uuid;
+
+const ITERATIONS: u32 = 20_000;
+const RECIPIENTS: usize = 100;
+
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+    static BYTES: Cell<u64> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        BYTES.with(|bytes| bytes.set(bytes.get() + layout.size() as u64));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        BYTES.with(|bytes| bytes.set(bytes.get() + new_size as u64));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Runs `f` `ITERATIONS` times and prints the allocations, bytes and time of one run.
+fn measure(name: &str, mut f: impl FnMut()) {
+    // Warm up so lazily initialized state is not counted.
+    f();
+    let allocations = ALLOCATIONS.with(Cell::get);
+    let bytes = BYTES.with(Cell::get);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.with(Cell::get) - allocations;
+    let bytes = BYTES.with(Cell::get) - bytes;
+    println!(
+        "{:<40} {:>10.1} allocs {:>10.0} bytes {:>10.2?}",
+        name,
+        allocations as f64 / f64::from(ITERATIONS),
+        bytes as f64 / f64::from(ITERATIONS),
+        elapsed / ITERATIONS,
+    );
+}
+
+/// The way frames were decoded before: the payload becomes a `Value`, which is put back
+/// into a new `Value` and only then turned into the message.
+fn decode_via_value(text: &str) -> serde_json::Result<ClientMessage> {
+    #[derive(Deserialize)]
+    struct RawMessage {
+        #[serde(rename = "type")]
+        kind: String,
+        #[serde(default)]
+        payload: Value,
+    }
+    let raw = serde_json::from_str::<Envelope<RawMessage>>(text)?.message;
+    serde_json::from_value(serde_json::json!({ "type": raw.kind, "payload": raw.payload }))
+}
+
+fn main() {
+    let frame = msg::encode(&ClientMessage::Message {
+        topic: "news".to_string(),
+        content: "The quick brown fox jumps over the lazy dog".repeat(4),
+        headers: BTreeMap::from([("correlation-id".to_string(), Uuid::new_v4().to_string())]),
+        qos: Qos::FireAndForget,
+        id: None,
+        reply_to: None,
+        retain: false,
+    })
+    .unwrap();
+    let broadcast = ServerMessage::Topic {
+        id: Uuid::new_v4(),
+        topic: "news".to_string(),
+        seq: 1,
+        sender: Uuid::new_v4().to_string(),
+        content: "The quick brown fox jumps over the lazy dog".repeat(4),
+        headers: BTreeMap::new(),
+        qos: Qos::FireAndForget,
+        reply_to: None,
+        retained: false,
+    };
+
+    println!("Receiving one frame:");
+    measure("decode via Value (before)", || {
+        black_box(decode_via_value(black_box(&frame)).unwrap());
+    });
+    measure("decode in place (after)", || {
+        black_box(msg::decode::<ClientMessage>(black_box(&frame)).unwrap());
+    });
+
+    println!("Broadcasting to {} recipients:", RECIPIENTS);
+    measure("copy and encode per recipient (before)", || {
+        for _ in 0..RECIPIENTS {
+            let message = broadcast.clone();
+            black_box(msg::encode(&message).unwrap());
+        }
+    });
+    measure("shared and encoded once (after)", || {
+        let shared = Arc::new(Outgoing::new(broadcast.clone()));
+        for _ in 0..RECIPIENTS {
+            let queued = shared.clone();
+            // The WebSocket takes its own copy of the text.
+            black_box(queued.frame().unwrap().to_owned());
+        }
+    });
+
+    println!("Broadcasting to {} session recipients:", RECIPIENTS);
+    measure("copy per recipient for resume (before)", || {
+        let shared = Arc::new(Outgoing::new(broadcast.clone()));
+        let mut pending = Vec::with_capacity(RECIPIENTS);
+        for _ in 0..RECIPIENTS {
+            pending.push(shared.message.clone());
+            black_box(shared.frame().unwrap().to_owned());
+        }
+        black_box(pending);
+    });
+    measure("shared copy kept for resume (after)", || {
+        let shared = Arc::new(Outgoing::new(broadcast.clone()));
+        let mut pending = Vec::with_capacity(RECIPIENTS);
+        for _ in 0..RECIPIENTS {
+            pending.push(shared.clone());
+            black_box(shared.frame().unwrap().to_owned());
+        }
+        black_box(pending);
+    });
+}
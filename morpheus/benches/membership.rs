@@ -0,0 +1,75 @@
+//! Time per subscription change in topics with many subscribers.
+//!
+//! Run with `cargo bench --bench membership`. Each topic size is measured under churn, a
+//! subscriber leaving and another joining, the way topic members were kept before, in a
+//! list scanned on every leave, and through storage's indexed members.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use dashmap::DashMap;
+use morpheus::{
+    core::{
+        queue::QueueConfig,
+        storage::{Client, InMemoryStorage, Storage},
+    },
+    testing::client_record,
+};
+use std::hint::black_box;
+use uuid::Uuid;
+
+const TOPIC: &str = "news";
+
+fn client(id: Uuid) -> Client {
+    client_record(id, QueueConfig::default()).0
+}
+
+fn churn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("churn");
+    for subscribers in [1_000, 10_000, 100_000] {
+        let clients: DashMap<Uuid, Client> = DashMap::new();
+        let topics: DashMap<String, Vec<Uuid>> = DashMap::new();
+        let mut members: Vec<Uuid> = (0..subscribers).map(|_| Uuid::new_v4()).collect();
+        for id in &members {
+            clients.insert(*id, client(*id));
+        }
+        topics.insert(TOPIC.to_string(), members.clone());
+        let mut next = 0;
+        let id = BenchmarkId::new("list, scanned on leave (before)", subscribers);
+        group.bench_with_input(id, &subscribers, |b, _| {
+            b.iter(|| {
+                // The oldest subscriber leaves, which scans the whole list.
+                let leaving = members[next % subscribers];
+                black_box(clients.remove(&leaving));
+                topics.get_mut(TOPIC).unwrap().retain(|id| *id != leaving);
+                let joining = Uuid::new_v4();
+                clients.insert(joining, client(joining));
+                topics.get_mut(TOPIC).unwrap().push(joining);
+                members[next % subscribers] = joining;
+                next += 1;
+            })
+        });
+
+        let storage = InMemoryStorage::new();
+        let mut members: Vec<Uuid> = (0..subscribers).map(|_| Uuid::new_v4()).collect();
+        for id in &members {
+            storage.add_client(client(*id));
+            storage.subscribe_client_to_topic(id, TOPIC.to_string());
+        }
+        let mut next = 0;
+        let id = BenchmarkId::new("indexed members (after)", subscribers);
+        group.bench_with_input(id, &subscribers, |b, _| {
+            b.iter(|| {
+                let leaving = members[next % subscribers];
+                black_box(storage.remove_client(&leaving));
+                let joining = Uuid::new_v4();
+                storage.add_client(client(joining));
+                storage.subscribe_client_to_topic(&joining, TOPIC.to_string());
+                members[next % subscribers] = joining;
+                next += 1;
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, churn);
+criterion_main!(benches);
@@ -0,0 +1,188 @@
+//! Time per operation on the routing hot paths, to catch performance regressions.
+//!
+//! Run with `cargo bench --bench routing`. It times the storage operations every message
+//! or connection goes through, `ClientManager::broadcast_to_topic` to topics of 10, 1 000
+//! and 100 000 subscribers, and encoding and decoding messages. Criterion compares each
+//! run with the one before, so run it before and after a change to the routing code.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use morpheus::{
+    core::{
+        client_manager::ClientManager,
+        msg::{self, ClientMessage, Outgoing, Qos, ServerMessage},
+        queue::{BackpressurePolicy, QueueConfig, QueueReceiver},
+        storage::{InMemoryStorage, Storage},
+    },
+    testing::client_record,
+};
+use std::{
+    collections::BTreeMap,
+    hint::black_box,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+
+const TOPIC: &str = "news";
+/// Broadcasts timed between emptying the subscribers' queues, which is not timed.
+const BROADCASTS_BETWEEN_DRAINS: u64 = 64;
+
+fn topic_message(content: String) -> ServerMessage {
+    ServerMessage::Topic {
+        id: Uuid::new_v4(),
+        topic: TOPIC.to_string(),
+        seq: 1,
+        sender: Uuid::new_v4().to_string(),
+        content,
+        headers: BTreeMap::new(),
+        qos: Qos::FireAndForget,
+        reply_to: None,
+        retained: false,
+    }
+}
+
+/// Times `broadcast` `iterations` times, emptying the queues every
+/// `BROADCASTS_BETWEEN_DRAINS` broadcasts.
+fn time_broadcasts(
+    iterations: u64,
+    receivers: &mut [QueueReceiver],
+    mut broadcast: impl FnMut(),
+) -> Duration {
+    let mut elapsed = Duration::ZERO;
+    let mut left = iterations;
+    while left > 0 {
+        let batch = left.min(BROADCASTS_BETWEEN_DRAINS);
+        let start = Instant::now();
+        for _ in 0..batch {
+            broadcast();
+        }
+        elapsed += start.elapsed();
+        left -= batch;
+        for receiver in receivers.iter_mut() {
+            while receiver.try_recv_shared().is_ok() {}
+        }
+    }
+    elapsed
+}
+
+fn storage_operations(c: &mut Criterion) {
+    let storage = InMemoryStorage::new();
+    let ids: Vec<Uuid> = (0..10_000).map(|_| Uuid::new_v4()).collect();
+    let _receivers: Vec<QueueReceiver> = ids
+        .iter()
+        .map(|id| {
+            let (client, receiver) = client_record(*id, QueueConfig::default());
+            storage.add_client(client);
+            storage.subscribe_client_to_topic(id, TOPIC.to_string());
+            receiver
+        })
+        .collect();
+    let message = Arc::new(Outgoing::new(topic_message("hello".to_string())));
+    let msg_id = message.message.id().unwrap();
+
+    let mut group = c.benchmark_group(format!("storage with {} clients", ids.len()));
+    let mut next = 0;
+    let mut pick = || {
+        next = (next + 1) % ids.len();
+        ids[next]
+    };
+    group.bench_function("get client", |b| {
+        b.iter(|| black_box(storage.get_client(&pick())))
+    });
+    group.bench_function("subscribe to another topic and back", |b| {
+        b.iter(|| {
+            let id = pick();
+            storage.subscribe_client_to_topic(&id, "sports".to_string());
+            storage.subscribe_client_to_topic(&id, TOPIC.to_string());
+        })
+    });
+    group.bench_function("topic recipients, unchanged topic", |b| {
+        b.iter(|| black_box(storage.topic_recipients(TOPIC)))
+    });
+    group.bench_function("next topic sequence number", |b| {
+        b.iter(|| black_box(storage.next_topic_seq(TOPIC)))
+    });
+    group.bench_function("add and remove a pending ack", |b| {
+        b.iter(|| {
+            let id = pick();
+            storage.add_pending_ack(&id, message.clone());
+            black_box(storage.remove_pending_ack(&id, &msg_id))
+        })
+    });
+    group.bench_function("connect, subscribe and disconnect", |b| {
+        b.iter(|| {
+            let (client, _receiver) = client_record(Uuid::new_v4(), QueueConfig::default());
+            let id = client.id;
+            storage.add_client(client);
+            storage.subscribe_client_to_topic(&id, TOPIC.to_string());
+            black_box(storage.remove_client(&id))
+        })
+    });
+    group.finish();
+}
+
+fn broadcasts(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let _guard = runtime.enter();
+    let content = "The quick brown fox jumps over the lazy dog".repeat(4);
+    let mut group = c.benchmark_group("broadcast through the client manager");
+    group.sample_size(10);
+    for subscribers in [10, 1_000, 100_000] {
+        // Room for every broadcast between drains, so none is dropped for a full queue.
+        let manager =
+            ClientManager::new(Arc::new(InMemoryStorage::new())).with_queue_config(QueueConfig {
+                capacity: BROADCASTS_BETWEEN_DRAINS as usize,
+                policy: BackpressurePolicy::DropNewest,
+            });
+        let mut receivers: Vec<QueueReceiver> = (0..subscribers)
+            .map(|_| {
+                let (id, receiver) = manager.add_internal_client();
+                manager
+                    .subscribe_client_to_topic(&id, TOPIC.to_string())
+                    .unwrap();
+                receiver
+            })
+            .collect();
+        let id = BenchmarkId::new("subscribers", subscribers);
+        group.bench_with_input(id, &subscribers, |b, _| {
+            b.iter_custom(|iterations| {
+                time_broadcasts(iterations, &mut receivers, || {
+                    let message = topic_message(content.clone());
+                    black_box(runtime.block_on(manager.broadcast_to_topic(TOPIC, message, None)));
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+fn serialization(c: &mut Criterion) {
+    let frame = msg::encode(&ClientMessage::Message {
+        topic: TOPIC.to_string(),
+        content: "The quick brown fox jumps over the lazy dog".repeat(4),
+        headers: BTreeMap::from([("correlation-id".to_string(), Uuid::new_v4().to_string())]),
+        qos: Qos::AtLeastOnce,
+        id: Some(Uuid::new_v4()),
+        reply_to: None,
+        retain: false,
+    })
+    .unwrap();
+    let message = topic_message("The quick brown fox jumps over the lazy dog".repeat(4));
+    let text = msg::encode(&message).unwrap();
+
+    let mut group = c.benchmark_group("serialization");
+    group.bench_function("decode a client message", |b| {
+        b.iter(|| msg::decode::<ClientMessage>(black_box(&frame)).unwrap())
+    });
+    group.bench_function("encode a topic message", |b| {
+        b.iter(|| msg::encode(black_box(&message)).unwrap())
+    });
+    group.bench_function("decode a topic message", |b| {
+        b.iter(|| msg::decode::<ServerMessage>(black_box(&text)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, storage_operations, broadcasts, serialization);
+criterion_main!(benches);
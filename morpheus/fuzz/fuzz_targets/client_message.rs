@@ -0,0 +1,18 @@
+//! Decodes arbitrary frames the way the server reads what clients send.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use morpheus::core::msg::{self, ClientMessage};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    let _ = msg::version(text);
+    let _ = msg::decode_untyped(text);
+    if let Ok(message) = msg::decode::<ClientMessage>(text) {
+        // Whatever decodes must encode again.
+        msg::encode(&message).unwrap();
+    }
+});
@@ -0,0 +1,52 @@
+//! Feeds arbitrary frames to the connection handler. Each input is one connection: its
+//! lines are its frames, sent as text when they are UTF-8 and as binary otherwise. A
+//! connection that takes too long to handle its frames counts as wedged.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use morpheus::{
+    core::{client_manager::ClientManager, storage::InMemoryStorage},
+    ws::handler,
+};
+use std::{
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+use tokio::runtime::{Builder, Runtime};
+use warp::ws::Message;
+
+const WEDGED_AFTER: Duration = Duration::from_secs(10);
+
+/// The runtime and server shared by every input, so state left behind by one connection
+/// is met by the next.
+fn server() -> &'static (Runtime, Arc<ClientManager>) {
+    static SERVER: OnceLock<(Runtime, Arc<ClientManager>)> = OnceLock::new();
+    SERVER.get_or_init(|| {
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        let client_manager = runtime
+            .block_on(async { Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new()))) });
+        (runtime, client_manager)
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    let (runtime, client_manager) = server();
+    let frames: Vec<Message> = data
+        .split(|byte| *byte == b'\n')
+        .map(|frame| match std::str::from_utf8(frame) {
+            Ok(text) => Message::text(text),
+            Err(_) => Message::binary(frame),
+        })
+        .collect();
+    runtime.block_on(async {
+        let (client_id, _rx) = client_manager.add_internal_client();
+        let client_id = tokio::time::timeout(
+            WEDGED_AFTER,
+            handler::handle_frames(client_id, frames, client_manager),
+        )
+        .await
+        .expect("the connection was wedged");
+        client_manager.remove_client(&client_id);
+    });
+});
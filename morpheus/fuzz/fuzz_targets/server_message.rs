@@ -0,0 +1,17 @@
+//! Decodes arbitrary frames the way mirrors and federation links read what a remote
+//! server sends.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use morpheus::core::msg::{self, ServerMessage};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(message) = msg::decode::<ServerMessage>(text) {
+        // Whatever decodes must encode again.
+        msg::encode(&message).unwrap();
+    }
+});
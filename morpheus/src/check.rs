@@ -0,0 +1,508 @@
+use crate::{
+    config::Config,
+    core::{
+        alerts::AlertConfig, bans::BanList, identities::ConnectionHistory, jwt::Jwt,
+        webhooks::Template,
+    },
+};
+use regex::Regex;
+use std::{
+    collections::HashSet,
+    fmt,
+    net::{SocketAddr, TcpListener},
+    path::Path,
+};
+use uuid::Uuid;
+
+/// The outcome of one check. Only failures make the report fail; warnings point at
+/// settings that work but are probably not what was meant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            Self::Ok => " OK ",
+            Self::Warn => "WARN",
+            Self::Fail => "FAIL",
+        };
+        f.write_str(label)
+    }
+}
+
+/// One finding of a check; a check reports one per problem, or a single `Ok`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub check: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+/// Everything `morpheus check` found, printed one finding per line.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// A report for a config that could not even be read, so nothing else was checked.
+    pub fn unreadable(error: String) -> Self {
+        let mut report = Self::default();
+        report.push("config", Status::Fail, error);
+        report
+    }
+
+    pub fn passed(&self) -> bool {
+        self.findings.iter().all(|f| f.status != Status::Fail)
+    }
+
+    /// The findings that are not `Ok`.
+    pub fn problems(&self) -> impl Iterator<Item = &Finding> {
+        self.findings.iter().filter(|f| f.status != Status::Ok)
+    }
+
+    fn push(&mut self, check: &'static str, status: Status, detail: impl Into<String>) {
+        self.findings.push(Finding {
+            check,
+            status,
+            detail: detail.into(),
+        });
+    }
+
+    /// Records `check` as passed with `detail` unless it already reported a problem.
+    fn pass(&mut self, check: &'static str, detail: impl Into<String>) {
+        if !self.findings.iter().any(|f| f.check == check) {
+            self.push(check, Status::Ok, detail);
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for finding in &self.findings {
+            writeln!(
+                f,
+                "[{}] {:<8} {}",
+                finding.status, finding.check, finding.detail
+            )?;
+        }
+        let failed = self
+            .findings
+            .iter()
+            .filter(|f| f.status == Status::Fail)
+            .count();
+        match failed {
+            0 => write!(f, "All checks passed."),
+            n => write!(f, "{} check(s) failed.", n),
+        }
+    }
+}
+
+/// Validates everything the server needs before it can serve with `config`: the settings
+/// themselves, the files it persists to, the TLS material, the access rules and the ports.
+/// Nothing is changed on disk and the ports are released again.
+pub fn run(config: &Config) -> Report {
+    let mut report = Report::default();
+    check_config(config, &mut report);
+    check_storage(config, &mut report);
+    check_tls(config, &mut report);
+    check_acl(config, &mut report);
+    for listener in config.listeners() {
+        check_port(listener.addr(), &mut report);
+    }
+    report
+}
+
+fn check_config(config: &Config, report: &mut Report) {
+    if config.limits.max_message_bytes == 0 {
+        report.push("config", Status::Fail, "limits.max_message_bytes is 0");
+    }
+    if config.queue.capacity == 0 {
+        report.push("config", Status::Fail, "queue.capacity is 0");
+    }
+    if config.log.max_file_bytes == Some(0) {
+        report.push("config", Status::Fail, "log.max_file_bytes is 0");
+    }
+    if config.log.max_files == Some(0) {
+        report.push("config", Status::Fail, "log.max_files is 0");
+    }
+    if let Err(e) = config.ws_path_segments() {
+        report.push("config", Status::Fail, e);
+    }
+    let mut addrs = HashSet::new();
+    for listener in config.listeners() {
+        if config.proxy.proxy_protocol && listener.tls.is_some() {
+            let detail = format!(
+                "Listener {} cannot use TLS with the PROXY protocol; terminate TLS at the proxy",
+                listener.addr()
+            );
+            report.push("config", Status::Fail, detail);
+        }
+        // Port 0 picks a free port for every listener.
+        if listener.port != 0 && !addrs.insert(listener.addr()) {
+            let detail = format!("Listener {} is given twice", listener.addr());
+            report.push("config", Status::Fail, detail);
+        }
+    }
+    if let Some(path) = &config.alert_rules {
+        if let Err(e) = AlertConfig::load(path) {
+            report.push("config", Status::Fail, e);
+        }
+    }
+    for webhook in &config.webhooks {
+        if let Err(e) = webhook.url.parse::<hyper::Uri>() {
+            let detail = format!("Invalid webhook URL {}: {}", webhook.url, e);
+            report.push("config", Status::Fail, detail);
+        }
+        for filter in &webhook.topics {
+            if let Err(e) = Regex::new(filter) {
+                let detail = format!("Invalid topic filter for webhook {}: {}", webhook.url, e);
+                report.push("config", Status::Fail, detail);
+            }
+        }
+        if let Some(Err(e)) = webhook.template.as_deref().map(Template::parse) {
+            let detail = format!("Invalid template for webhook {}: {}", webhook.url, e);
+            report.push("config", Status::Fail, detail);
+        }
+    }
+    for mirror in &config.mirrors {
+        if !(mirror.url.starts_with("ws://") || mirror.url.starts_with("wss://")) {
+            let detail = format!("Mirror URL {} is not a ws:// or wss:// URL", mirror.url);
+            report.push("config", Status::Fail, detail);
+        }
+        if mirror.topic.trim().is_empty() {
+            let detail = format!("Mirror to {} has an empty topic", mirror.url);
+            report.push("config", Status::Fail, detail);
+        }
+    }
+    for link in &config.federation {
+        if !(link.url.starts_with("ws://") || link.url.starts_with("wss://")) {
+            let detail = format!("Federated server {} is not a ws:// or wss:// URL", link.url);
+            report.push("config", Status::Fail, detail);
+        }
+        if link.topics.is_empty() || link.topics.iter().any(|t| t.trim().is_empty()) {
+            let detail = format!("Federation with {} needs non-empty topics", link.url);
+            report.push("config", Status::Fail, detail);
+        }
+    }
+    for peer in &config.cluster.peers {
+        if !(peer.starts_with("ws://") || peer.starts_with("wss://")) {
+            let detail = format!("Cluster peer {} is not a ws:// or wss:// URL", peer);
+            report.push("config", Status::Fail, detail);
+        }
+    }
+    report.pass(
+        "config",
+        format!(
+            "{} topic policies, {} webhook(s), {} mirror(s), {} federated server(s), {} cluster peer(s)",
+            config.topics.len(),
+            config.webhooks.len(),
+            config.mirrors.len(),
+            config.federation.len(),
+            config.cluster.peers.len()
+        ),
+    );
+}
+
+fn check_storage(config: &Config, report: &mut Report) {
+    if let Err(e) = BanList::load(&config.ban_list) {
+        report.push("storage", Status::Fail, e);
+    }
+    if let Err(e) = ConnectionHistory::load(&config.connection_history) {
+        report.push("storage", Status::Fail, e);
+    }
+    if let Some(database) = &config.database {
+        if !cfg!(feature = "sqlite") {
+            report.push(
+                "storage",
+                Status::Fail,
+                format!(
+                    "{} needs a server built with the sqlite feature",
+                    database.display()
+                ),
+            );
+        }
+    }
+    let files = [
+        &config.ban_list,
+        &config.connection_history,
+        &config.audit_log,
+    ];
+    let dead_letters = config
+        .webhooks
+        .iter()
+        .filter_map(|w| w.dead_letter.as_ref());
+    for file in files
+        .into_iter()
+        .chain(dead_letters)
+        .chain(config.database.as_ref())
+    {
+        let directory = file.parent().filter(|dir| !dir.as_os_str().is_empty());
+        if let Err(e) = probe_writable(directory.unwrap_or(Path::new("."))) {
+            report.push("storage", Status::Fail, e);
+        }
+    }
+    if let Err(e) = probe_writable(existing_ancestor(&config.log.directory)) {
+        report.push("storage", Status::Fail, e);
+    }
+    report.pass(
+        "storage",
+        format!(
+            "{}, {}, {} and {} are writable",
+            config.ban_list.display(),
+            config.connection_history.display(),
+            config.audit_log.display(),
+            config.log.directory.display()
+        ),
+    );
+}
+
+/// The directory itself if it exists, otherwise the closest parent that does, which is
+/// where the server will create it.
+fn existing_ancestor(directory: &Path) -> &Path {
+    directory
+        .ancestors()
+        .find(|dir| !dir.as_os_str().is_empty() && dir.is_dir())
+        .unwrap_or(Path::new("."))
+}
+
+fn probe_writable(directory: &Path) -> Result<(), String> {
+    let probe = directory.join(format!(".morpheus-check-{}", Uuid::new_v4()));
+    std::fs::write(&probe, b"")
+        .and_then(|_| std::fs::remove_file(&probe))
+        .map_err(|e| format!("{} is not writable: {}", directory.display(), e))
+}
+
+fn check_tls(config: &Config, report: &mut Report) {
+    let tls: Vec<_> = config
+        .listeners()
+        .into_iter()
+        .filter_map(|listener| listener.tls)
+        .collect();
+    if tls.is_empty() {
+        report.push("tls", Status::Ok, "disabled");
+        return;
+    }
+    let pem_files = tls.iter().flat_map(|tls| {
+        [
+            (&tls.cert, "CERTIFICATE-----", "certificate"),
+            (&tls.key, "PRIVATE KEY-----", "private key"),
+        ]
+    });
+    for (path, marker, what) in pem_files {
+        match std::fs::read_to_string(path) {
+            Ok(pem) if pem.contains("-----BEGIN ") && pem.contains(marker) => {}
+            Ok(_) => report.push(
+                "tls",
+                Status::Fail,
+                format!("{} has no PEM {}", path.display(), what),
+            ),
+            Err(e) => report.push(
+                "tls",
+                Status::Fail,
+                format!("Failed to read {}: {}", path.display(), e),
+            ),
+        }
+    }
+    let pairs: Vec<_> = tls
+        .iter()
+        .map(|tls| format!("{} and {}", tls.cert.display(), tls.key.display()))
+        .collect();
+    report.pass("tls", pairs.join(", "));
+}
+
+fn check_acl(config: &Config, report: &mut Report) {
+    let mut tokens = HashSet::new();
+    for token in &config.auth.tokens {
+        if token.trim().is_empty() {
+            report.push("acl", Status::Fail, "An auth token is empty");
+        } else if !tokens.insert(token) {
+            report.push("acl", Status::Warn, "An auth token is listed twice");
+        }
+    }
+    let mut role_tokens: Vec<_> = config.auth.roles.keys().collect();
+    role_tokens.sort();
+    for token in role_tokens {
+        if token.trim().is_empty() {
+            report.push("acl", Status::Fail, "A token in auth.roles is empty");
+        } else if tokens.contains(token) {
+            let detail = "A token is listed in both auth.tokens and auth.roles; its role applies";
+            report.push("acl", Status::Warn, detail);
+        } else {
+            tokens.insert(token);
+        }
+    }
+    if let Some(Err(e)) = config.auth.jwt.as_ref().map(Jwt::new) {
+        report.push("acl", Status::Fail, e);
+    }
+    if tokens.is_empty() && config.auth.jwt.is_none() {
+        let public = config
+            .listeners()
+            .into_iter()
+            .find(|listener| !listener.address.is_loopback());
+        if let Some(listener) = public {
+            let detail = format!(
+                "Authentication is disabled while listening on {}",
+                listener.address
+            );
+            report.push("acl", Status::Warn, detail);
+        }
+    }
+    let limits = &config.limits;
+    if let (Some(per_topic), Some(total)) = (limits.max_clients_per_topic, limits.max_connections) {
+        if per_topic > total {
+            let detail = format!(
+                "max_clients_per_topic ({}) exceeds max_connections ({})",
+                per_topic, total
+            );
+            report.push("acl", Status::Warn, detail);
+        }
+    }
+    let mut topics: Vec<_> = config.topics.iter().collect();
+    topics.sort_by_key(|(topic, _)| *topic);
+    for (topic, policy) in topics {
+        match &policy.rules {
+            Some(rules) if rules.trim().is_empty() => {
+                let detail = format!("Topic '{}' has empty rules", topic);
+                report.push("acl", Status::Fail, detail);
+            }
+            None if policy.rules_version > 0 => {
+                let detail = format!("Topic '{}' sets rules_version but has no rules", topic);
+                report.push("acl", Status::Warn, detail);
+            }
+            _ => {}
+        }
+        if policy.retention == Some(0) {
+            let detail = format!("Topic '{}' keeps no history (retention = 0)", topic);
+            report.push("acl", Status::Warn, detail);
+        }
+    }
+    report.pass(
+        "acl",
+        format!(
+            "{} auth token(s), {} topic policies",
+            tokens.len(),
+            config.topics.len()
+        ),
+    );
+}
+
+fn check_port(addr: SocketAddr, report: &mut Report) {
+    match TcpListener::bind(addr) {
+        Ok(_) => report.push("port", Status::Ok, format!("{} is free", addr)),
+        Err(e) => report.push("port", Status::Fail, format!("Cannot bind {}: {}", addr, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{ListenerConfig, TlsConfig},
+        core::jwt::JwtConfig,
+    };
+
+    fn free_config() -> Config {
+        let dir = std::env::temp_dir();
+        Config {
+            port: 0,
+            ban_list: dir.join(format!("morpheus-check-bans-{}.json", Uuid::new_v4())),
+            connection_history: dir.join(format!("morpheus-check-conns-{}.json", Uuid::new_v4())),
+            audit_log: dir.join(format!("morpheus-check-audit-{}.log", Uuid::new_v4())),
+            ..Config::default()
+        }
+    }
+
+    fn statuses(report: &Report, check: &str) -> Vec<Status> {
+        report
+            .findings
+            .iter()
+            .filter(|f| f.check == check)
+            .map(|f| f.status)
+            .collect()
+    }
+
+    #[test]
+    fn test_default_config_passes() {
+        let report = run(&free_config());
+        assert!(report.passed(), "{}", report);
+        assert_eq!(report.problems().count(), 0, "{}", report);
+    }
+
+    #[test]
+    fn test_failures_are_reported_per_check() {
+        let mut config = free_config();
+        config.tls = Some(TlsConfig {
+            cert: "/nonexistent/cert.pem".into(),
+            key: "/nonexistent/key.pem".into(),
+        });
+        config.auth.tokens = vec!["secret".to_string(), "secret".to_string()];
+        // Neither a secret nor a public key.
+        config.auth.jwt = Some(JwtConfig::default());
+        config.cluster.peers = vec!["http://peer:8080/cluster".to_string()];
+        let report = run(&config);
+
+        assert!(!report.passed());
+        assert_eq!(statuses(&report, "tls"), vec![Status::Fail, Status::Fail]);
+        assert_eq!(statuses(&report, "acl"), vec![Status::Warn, Status::Fail]);
+        assert_eq!(statuses(&report, "config"), vec![Status::Fail]);
+        assert_eq!(statuses(&report, "storage"), vec![Status::Ok]);
+    }
+
+    #[test]
+    fn test_database_needs_the_sqlite_feature() {
+        let mut config = free_config();
+        config.database = Some(std::env::temp_dir().join("morpheus-check.db"));
+        let report = run(&config);
+
+        let expected = if cfg!(feature = "sqlite") {
+            Status::Ok
+        } else {
+            Status::Fail
+        };
+        assert_eq!(statuses(&report, "storage"), vec![expected]);
+    }
+
+    #[test]
+    fn test_every_listener_is_checked() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut config = free_config();
+        config.ws_path = "/admin".to_string();
+        config.listeners = vec![
+            ListenerConfig {
+                address: [0, 0, 0, 0].into(),
+                port: 0,
+                tls: Some(TlsConfig {
+                    cert: "/nonexistent/cert.pem".into(),
+                    key: "/nonexistent/key.pem".into(),
+                }),
+            },
+            ListenerConfig {
+                address: [127, 0, 0, 1].into(),
+                port: taken.local_addr().unwrap().port(),
+                tls: None,
+            },
+        ];
+        let report = run(&config);
+
+        assert_eq!(statuses(&report, "config"), vec![Status::Fail]);
+        assert_eq!(statuses(&report, "tls"), vec![Status::Fail, Status::Fail]);
+        assert_eq!(statuses(&report, "acl"), vec![Status::Warn]);
+        assert_eq!(
+            statuses(&report, "port"),
+            vec![Status::Ok, Status::Ok, Status::Fail]
+        );
+    }
+
+    #[test]
+    fn test_port_in_use_fails() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut report = Report::default();
+        check_port(listener.local_addr().unwrap(), &mut report);
+        assert!(!report.passed());
+    }
+}
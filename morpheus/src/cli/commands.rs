@@ -1,3 +1,13 @@
+use crate::core::{
+    alerts::parse_duration,
+    bans::BanTarget,
+    forwards::{ForwardSpec, Transform},
+    polls::DEFAULT_POLL_DURATION,
+    query::Query,
+    scheduler::Schedule,
+};
+use std::{path::PathBuf, time::Duration};
+use tracing::level_filters::LevelFilter;
 use uuid::Uuid;
 
 /// Represents a command issued by the server administrator.
@@ -7,10 +17,95 @@ pub enum Command {
     List(ListScope),
     /// Send a global message to all clients.
     Global(String),
+    /// Send the content of a file as a global message.
+    GlobalFile(PathBuf),
     /// Send a message to a specific topic.
     Topic { topic: String, content: String },
+    /// Send the content of a file to a specific topic.
+    TopicFile { topic: String, path: PathBuf },
+    /// Move all subscribers and history of one topic into another.
+    MergeTopics { from: String, into: String },
+    /// Move the subscribers of a topic whose client ID matches `filter` into another topic.
+    SplitTopic {
+        source: String,
+        destination: String,
+        filter: String,
+    },
     /// Send a private message to a specific client.
     Private { client_id: Uuid, content: String },
+    /// Disconnect a client, telling it why.
+    Kick {
+        client_id: Uuid,
+        reason: Option<String>,
+    },
+    /// Ban a client ID or IP address, kicking matching clients.
+    Ban(BanTarget),
+    /// Lift a ban.
+    Unban(BanTarget),
+    /// Show when a session identity was connected and from where.
+    Whois(Uuid),
+    /// Show a message from history together with its provenance chain.
+    Inspect(Uuid),
+    /// Show traffic counters for one topic, or for every topic.
+    Stats(Option<String>),
+    /// Show the server's uptime and totals since it started.
+    ServerStats,
+    /// Show the sizes and kinds of recently published content.
+    Analytics,
+    /// Show how long broadcasts take by subscriber count and content size.
+    Latency,
+    /// Answer an ad-hoc question about the clients or history, as a table or JSON.
+    Query { query: Query, json: bool },
+    /// Start `count` simulated clients publishing to a topic, each at `rate` messages per second.
+    Simulate {
+        topic: String,
+        count: usize,
+        rate: Option<f64>,
+    },
+    /// Stop all simulated clients.
+    SimulateStop,
+    /// Send a message to a topic later, once or on a cron schedule.
+    Schedule {
+        schedule: Schedule,
+        topic: String,
+        content: String,
+    },
+    /// Open a poll in a topic.
+    Poll {
+        topic: String,
+        question: String,
+        options: Vec<String>,
+        duration: Duration,
+    },
+    /// Forward the messages published to one topic to another.
+    Forward(ForwardSpec),
+    /// Show the forwarding rules.
+    ForwardList,
+    /// Remove a forwarding rule.
+    ForwardRemove(Uuid),
+    /// Show the scheduled messages.
+    ScheduleList,
+    /// Cancel a scheduled message.
+    ScheduleCancel(Uuid),
+    /// Show the log level, or change it until the next reload.
+    LogLevel(Option<LevelFilter>),
+    /// Re-read the config file and apply the settings that can change at runtime.
+    Reload,
+    /// Show the most recent entries of the audit log.
+    AuditTail(usize),
+    /// Show the last `count` messages of a topic's history, publishing them again if
+    /// `broadcast` is set.
+    Replay {
+        topic: String,
+        count: usize,
+        broadcast: bool,
+    },
+    /// Write the topics, retained messages, bans and scheduled messages to a file.
+    SnapshotSave(PathBuf),
+    /// Add the state saved in a snapshot file to the server's.
+    SnapshotLoad(PathBuf),
+    /// Stop accepting connections and move the clients to other nodes.
+    Drain,
     /// Show help message.
     Help,
     /// Exit the application.
@@ -24,8 +119,15 @@ pub enum ListScope {
     All,
     Topic(String),
     Topics,
+    Bans,
 }
 
+/// How many audit log entries `/audit tail` shows when not told otherwise.
+pub const DEFAULT_AUDIT_TAIL: usize = 20;
+
+/// How many messages `/replay` shows when not told otherwise.
+pub const DEFAULT_REPLAY_COUNT: usize = 20;
+
 /// Parses a string from the user into a `Command`.
 pub fn parse_command(input: &str) -> Command {
     let mut parts = input.trim().splitn(3, ' ');
@@ -34,16 +136,35 @@ pub fn parse_command(input: &str) -> Command {
     match command.as_str() {
         "/help" | "/h" => Command::Help,
         "/exit" | "/e" => Command::Exit,
+        "/reload" => Command::Reload,
+        "/drain" => Command::Drain,
+        "/loglevel" => match parts.next() {
+            None => Command::LogLevel(None),
+            Some(level) => match level.parse() {
+                Ok(level) => Command::LogLevel(Some(level)),
+                Err(_) => Command::Unknown(format!(
+                    "Invalid log level: {} (expected off, error, warn, info, debug or trace)",
+                    level
+                )),
+            },
+        },
         "/list" | "/l" => {
             let scope = parts.next().unwrap_or("all");
             match scope {
                 "all" => Command::List(ListScope::All),
                 "topics" => Command::List(ListScope::Topics),
+                "bans" => Command::List(ListScope::Bans),
                 topic => Command::List(ListScope::Topic(topic.to_string())),
             }
         }
         "/global" | "/g" => {
             let content = parts.collect::<Vec<&str>>().join(" ");
+            if let Some(path) = file_argument(&content) {
+                return match path {
+                    Some(path) => Command::GlobalFile(path),
+                    None => Command::Unknown("Usage: /global --file <path>".to_string()),
+                };
+            }
             if content.is_empty() {
                 Command::Unknown("Global message content cannot be empty.".to_string())
             } else {
@@ -53,6 +174,20 @@ pub fn parse_command(input: &str) -> Command {
         "/topic" | "/t" => {
             let topic = parts.next().unwrap_or("");
             let content = parts.next().unwrap_or("");
+            match topic {
+                "merge" => return parse_merge(content),
+                "split" => return parse_split(content),
+                _ => {}
+            }
+            if let Some(path) = file_argument(content) {
+                return match path {
+                    Some(path) if !topic.is_empty() => Command::TopicFile {
+                        topic: topic.to_string(),
+                        path,
+                    },
+                    _ => Command::Unknown("Usage: /topic <topic_name> --file <path>".to_string()),
+                };
+            }
             if topic.is_empty() || content.is_empty() {
                 Command::Unknown("Usage: /topic <topic_name> <content>".to_string())
             } else {
@@ -77,11 +212,317 @@ pub fn parse_command(input: &str) -> Command {
                 }
             }
         }
+        "/kick" | "/k" => {
+            let client_id_str = parts.next().unwrap_or("");
+            let reason = parts
+                .next()
+                .map(str::trim)
+                .filter(|reason| !reason.is_empty());
+            if client_id_str.is_empty() {
+                Command::Unknown("Usage: /kick <client_id> [reason]".to_string())
+            } else {
+                match Uuid::parse_str(client_id_str) {
+                    Ok(client_id) => Command::Kick {
+                        client_id,
+                        reason: reason.map(str::to_string),
+                    },
+                    Err(_) => Command::Unknown(format!("Invalid client ID: {}", client_id_str)),
+                }
+            }
+        }
+        "/ban" | "/unban" => {
+            let target = parts.next().unwrap_or("");
+            if target.is_empty() {
+                return Command::Unknown(format!("Usage: {} <client_id|ip>", command));
+            }
+            match target.parse() {
+                Ok(target) if command == "/ban" => Command::Ban(target),
+                Ok(target) => Command::Unban(target),
+                Err(e) => Command::Unknown(e),
+            }
+        }
+        "/whois" | "/w" => {
+            let identity_str = parts.next().unwrap_or("");
+            if identity_str.is_empty() {
+                Command::Unknown("Usage: /whois <identity>".to_string())
+            } else {
+                match Uuid::parse_str(identity_str) {
+                    Ok(identity) => Command::Whois(identity),
+                    Err(_) => Command::Unknown(format!("Invalid identity: {}", identity_str)),
+                }
+            }
+        }
+        "/inspect" | "/i" => {
+            let msg_id_str = parts.next().unwrap_or("");
+            if msg_id_str.is_empty() {
+                Command::Unknown("Usage: /inspect <msg_id>".to_string())
+            } else {
+                match Uuid::parse_str(msg_id_str) {
+                    Ok(msg_id) => Command::Inspect(msg_id),
+                    Err(_) => Command::Unknown(format!("Invalid message ID: {}", msg_id_str)),
+                }
+            }
+        }
+        "/stats" => match parts.next() {
+            Some("server") => Command::ServerStats,
+            Some("analytics") => Command::Analytics,
+            Some("latency") => Command::Latency,
+            topic => Command::Stats(topic.map(str::to_string)),
+        },
+        "/audit" => match (parts.next(), parts.next().map(str::trim)) {
+            (Some("tail"), None) => Command::AuditTail(DEFAULT_AUDIT_TAIL),
+            (Some("tail"), Some(count)) => match count.parse() {
+                Ok(count) if count > 0 => Command::AuditTail(count),
+                _ => Command::Unknown(format!("Invalid entry count: {}", count)),
+            },
+            _ => Command::Unknown("Usage: /audit tail [count]".to_string()),
+        },
+        "/snapshot" => match (parts.next(), parts.next().map(str::trim)) {
+            (Some("save"), Some(path)) if !path.is_empty() => {
+                Command::SnapshotSave(PathBuf::from(path))
+            }
+            (Some("load"), Some(path)) if !path.is_empty() => {
+                Command::SnapshotLoad(PathBuf::from(path))
+            }
+            _ => Command::Unknown("Usage: /snapshot save|load <path>".to_string()),
+        },
+        "/simulate" | "/s" => parse_simulate(&parts.collect::<Vec<&str>>().join(" ")),
+        "/schedule" => parse_schedule(&parts.collect::<Vec<&str>>().join(" ")),
+        "/replay" => parse_replay(&parts.collect::<Vec<&str>>().join(" ")),
+        "/poll" => parse_poll(&parts.collect::<Vec<&str>>().join(" ")),
+        "/forward" => parse_forward(&parts.collect::<Vec<&str>>().join(" ")),
+        "/query" | "/q" => parse_query(&parts.collect::<Vec<&str>>().join(" ")),
         "" => Command::Unknown("".to_string()), // Ignore empty input
         _ => Command::Unknown(format!("Unknown command: {}", command)),
     }
 }
 
+fn parse_merge(args: &str) -> Command {
+    match args.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [from, into] => Command::MergeTopics {
+            from: from.to_string(),
+            into: into.to_string(),
+        },
+        _ => Command::Unknown("Usage: /topic merge <from> <into>".to_string()),
+    }
+}
+
+fn parse_split(args: &str) -> Command {
+    match args.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [source, destination, "--filter", filter] => Command::SplitTopic {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            filter: filter.to_string(),
+        },
+        _ => Command::Unknown(
+            "Usage: /topic split <source> <destination> --filter <regex>".to_string(),
+        ),
+    }
+}
+
+fn parse_forward(args: &str) -> Command {
+    const USAGE: &str = "Usage: /forward <from> <to> [--filter <regex>] \
+                         [--replace <regex> <replacement>] | list | remove <id>";
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let (from, to, mut options) = match words.as_slice() {
+        ["list"] => return Command::ForwardList,
+        ["remove", id] => {
+            return match Uuid::parse_str(id) {
+                Ok(id) => Command::ForwardRemove(id),
+                Err(_) => Command::Unknown(format!("Invalid rule ID: {}", id)),
+            }
+        }
+        [from, to, options @ ..] => (*from, *to, options),
+        _ => return Command::Unknown(USAGE.to_string()),
+    };
+    let mut spec = ForwardSpec {
+        from: from.to_string(),
+        to: to.to_string(),
+        filter: None,
+        transform: None,
+    };
+    loop {
+        options = match options {
+            [] => return Command::Forward(spec),
+            ["--filter", filter, rest @ ..] if spec.filter.is_none() => {
+                spec.filter = Some(filter.to_string());
+                rest
+            }
+            ["--replace", pattern, replacement, rest @ ..] if spec.transform.is_none() => {
+                spec.transform = Some(Transform {
+                    pattern: pattern.to_string(),
+                    replacement: replacement.to_string(),
+                });
+                rest
+            }
+            _ => return Command::Unknown(USAGE.to_string()),
+        };
+    }
+}
+
+fn parse_simulate(args: &str) -> Command {
+    const USAGE: &str = "Usage: /simulate <topic> <count> [rate] | /simulate stop";
+    let (topic, count, rate) = match args.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["stop"] => return Command::SimulateStop,
+        [topic, count] => (*topic, *count, None),
+        [topic, count, rate] => (*topic, *count, Some(*rate)),
+        _ => return Command::Unknown(USAGE.to_string()),
+    };
+    let count = match count.parse::<usize>() {
+        Ok(count) if count > 0 => count,
+        _ => return Command::Unknown(format!("Invalid client count: {}", count)),
+    };
+    let rate = match rate {
+        None => None,
+        Some(rate) => match rate.parse::<f64>() {
+            Ok(rate) if rate.is_finite() && rate > 0.0 => Some(rate),
+            _ => return Command::Unknown(format!("Invalid rate: {}", rate)),
+        },
+    };
+    Command::Simulate {
+        topic: topic.to_string(),
+        count,
+        rate,
+    }
+}
+
+fn parse_schedule(args: &str) -> Command {
+    const USAGE: &str = "Usage: /schedule <delay|\"cron\"> <topic> <message> | list | cancel <id>";
+    let args = args.trim();
+    match args.split_once(' ').unwrap_or((args, "")) {
+        ("list", "") => return Command::ScheduleList,
+        ("cancel", id) => {
+            return match Uuid::parse_str(id.trim()) {
+                Ok(id) => Command::ScheduleCancel(id),
+                Err(_) => Command::Unknown(format!("Invalid schedule ID: {}", id.trim())),
+            }
+        }
+        _ => {}
+    }
+    // A cron expression has spaces of its own, so it is quoted.
+    let (when, rest) = match args.strip_prefix('"') {
+        Some(quoted) => match quoted.split_once('"') {
+            Some(split) => split,
+            None => return Command::Unknown(USAGE.to_string()),
+        },
+        None => args.split_once(' ').unwrap_or((args, "")),
+    };
+    let (topic, content) = rest.trim_start().split_once(' ').unwrap_or((rest, ""));
+    if when.is_empty() || topic.is_empty() || content.trim().is_empty() {
+        return Command::Unknown(USAGE.to_string());
+    }
+    match Schedule::parse(when) {
+        Ok(schedule) => Command::Schedule {
+            schedule,
+            topic: topic.to_string(),
+            content: content.trim().to_string(),
+        },
+        Err(e) => Command::Unknown(e),
+    }
+}
+
+fn parse_replay(args: &str) -> Command {
+    const USAGE: &str = "Usage: /replay <topic> [count] [--broadcast]";
+    let mut words: Vec<&str> = args.split_whitespace().collect();
+    let broadcast = match words.iter().position(|word| *word == "--broadcast") {
+        Some(flag) => {
+            words.remove(flag);
+            true
+        }
+        None => false,
+    };
+    let (topic, count) = match words.as_slice() {
+        [topic] => (*topic, DEFAULT_REPLAY_COUNT),
+        [topic, count] => match count.parse() {
+            Ok(count) if count > 0 => (*topic, count),
+            _ => return Command::Unknown(format!("Invalid message count: {}", count)),
+        },
+        _ => return Command::Unknown(USAGE.to_string()),
+    };
+    Command::Replay {
+        topic: topic.to_string(),
+        count,
+        broadcast,
+    }
+}
+
+fn parse_poll(args: &str) -> Command {
+    const USAGE: &str =
+        "Usage: /poll <topic> \"<question>\" <option> <option>... [--for <duration>]";
+    let Some(mut words) = split_quoted(args) else {
+        return Command::Unknown(USAGE.to_string());
+    };
+    let mut duration = DEFAULT_POLL_DURATION;
+    if let Some(flag) = words.iter().position(|word| word == "--for") {
+        let Some(value) = words.get(flag + 1) else {
+            return Command::Unknown(USAGE.to_string());
+        };
+        duration = match parse_duration(value) {
+            Ok(duration) => duration,
+            Err(e) => return Command::Unknown(e),
+        };
+        words.drain(flag..flag + 2);
+    }
+    let mut words = words.into_iter();
+    match (words.next(), words.next()) {
+        (Some(topic), Some(question)) => Command::Poll {
+            topic,
+            question,
+            options: words.collect(),
+            duration,
+        },
+        _ => Command::Unknown(USAGE.to_string()),
+    }
+}
+
+fn parse_query(args: &str) -> Command {
+    const USAGE: &str = "Usage: /query [--json] \"SELECT ... FROM clients|messages ...\"";
+    let args = args.trim();
+    let (json, query) = match args.strip_prefix("--json") {
+        Some(query) => (true, query.trim_start()),
+        None => (false, args),
+    };
+    // The quotes are optional, since the query is the rest of the line either way.
+    let query = query
+        .strip_prefix('"')
+        .and_then(|query| query.strip_suffix('"'))
+        .unwrap_or(query);
+    if query.is_empty() {
+        return Command::Unknown(USAGE.to_string());
+    }
+    match query.parse() {
+        Ok(query) => Command::Query { query, json },
+        Err(e) => Command::Unknown(e),
+    }
+}
+
+/// Splits on whitespace, keeping double-quoted phrases together. Returns `None` if a
+/// quote is not closed.
+fn split_quoted(args: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut rest = args.trim_start();
+    while !rest.is_empty() {
+        let (word, after) = match rest.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"')?,
+            None => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+        };
+        words.push(word.to_string());
+        rest = after.trim_start();
+    }
+    Some(words)
+}
+
+/// The path of a `--file <path>` argument: `None` if `args` is not one, `Some(None)` if
+/// the path is missing.
+fn file_argument(args: &str) -> Option<Option<PathBuf>> {
+    let path = args.strip_prefix("--file")?;
+    if !path.is_empty() && !path.starts_with(' ') {
+        return None;
+    }
+    let path = path.trim();
+    Some((!path.is_empty()).then(|| PathBuf::from(path)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -92,10 +533,35 @@ mod tests {
         assert_eq!(parse_command("/h"), Command::Help);
     }
 
+    #[test]
+    fn test_parse_query() {
+        let query = "SELECT id FROM clients WHERE topic = 'ops'";
+        assert_eq!(
+            parse_command(&format!("/query \"{}\"", query)),
+            Command::Query {
+                query: query.parse().unwrap(),
+                json: false,
+            }
+        );
+        assert_eq!(
+            parse_command(&format!("/q --json {}", query)),
+            Command::Query {
+                query: query.parse().unwrap(),
+                json: true,
+            }
+        );
+        assert!(matches!(
+            parse_command("/query SELECT * FROM nowhere"),
+            Command::Unknown(e) if e.contains("Unknown source")
+        ));
+        assert!(matches!(parse_command("/query"), Command::Unknown(_)));
+    }
+
     #[test]
     fn test_parse_exit() {
         assert_eq!(parse_command("/exit"), Command::Exit);
         assert_eq!(parse_command("/e"), Command::Exit);
+        assert_eq!(parse_command("/drain"), Command::Drain);
     }
 
     #[test]
@@ -157,6 +623,288 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_file_content() {
+        assert_eq!(
+            parse_command("/global --file notes/release 2.0.md"),
+            Command::GlobalFile(PathBuf::from("notes/release 2.0.md"))
+        );
+        assert_eq!(
+            parse_command("/t ops --file motd.txt"),
+            Command::TopicFile {
+                topic: "ops".to_string(),
+                path: PathBuf::from("motd.txt")
+            }
+        );
+        assert_eq!(
+            parse_command("/g --file"),
+            Command::Unknown("Usage: /global --file <path>".to_string())
+        );
+        // Only a separate `--file` flag names a file.
+        assert_eq!(
+            parse_command("/g --files are attached"),
+            Command::Global("--files are attached".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_topic_merge_and_split() {
+        assert_eq!(
+            parse_command("/topic merge a b"),
+            Command::MergeTopics {
+                from: "a".to_string(),
+                into: "b".to_string()
+            }
+        );
+        assert_eq!(
+            parse_command("/t merge a"),
+            Command::Unknown("Usage: /topic merge <from> <into>".to_string())
+        );
+        assert_eq!(
+            parse_command("/topic split a b --filter ^ab"),
+            Command::SplitTopic {
+                source: "a".to_string(),
+                destination: "b".to_string(),
+                filter: "^ab".to_string()
+            }
+        );
+        assert_eq!(
+            parse_command("/topic split a b"),
+            Command::Unknown(
+                "Usage: /topic split <source> <destination> --filter <regex>".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_simulate() {
+        assert_eq!(
+            parse_command("/simulate general 5 2.5"),
+            Command::Simulate {
+                topic: "general".to_string(),
+                count: 5,
+                rate: Some(2.5)
+            }
+        );
+        assert_eq!(
+            parse_command("/s general 3"),
+            Command::Simulate {
+                topic: "general".to_string(),
+                count: 3,
+                rate: None
+            }
+        );
+        assert_eq!(parse_command("/simulate stop"), Command::SimulateStop);
+        assert_eq!(
+            parse_command("/simulate general 0"),
+            Command::Unknown("Invalid client count: 0".to_string())
+        );
+        assert_eq!(
+            parse_command("/simulate general 2 fast"),
+            Command::Unknown("Invalid rate: fast".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_schedule() {
+        assert_eq!(
+            parse_command("/schedule 10m general Maintenance at noon"),
+            Command::Schedule {
+                schedule: Schedule::Delay(std::time::Duration::from_secs(600)),
+                topic: "general".to_string(),
+                content: "Maintenance at noon".to_string()
+            }
+        );
+        assert_eq!(
+            parse_command("/schedule \"0 9 * * 1-5\" standup Stand-up in 5 minutes"),
+            Command::Schedule {
+                schedule: Schedule::parse("0 9 * * 1-5").unwrap(),
+                topic: "standup".to_string(),
+                content: "Stand-up in 5 minutes".to_string()
+            }
+        );
+        assert_eq!(parse_command("/schedule list"), Command::ScheduleList);
+        let id = Uuid::new_v4();
+        assert_eq!(
+            parse_command(&format!("/schedule cancel {}", id)),
+            Command::ScheduleCancel(id)
+        );
+        assert_eq!(
+            parse_command("/schedule 10m general"),
+            Command::Unknown(
+                "Usage: /schedule <delay|\"cron\"> <topic> <message> | list | cancel <id>"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            parse_command("/schedule later general Hi"),
+            Command::Unknown("Invalid duration 'later' (expected e.g. 30s, 5m or 1h)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_replay() {
+        assert_eq!(
+            parse_command("/replay news"),
+            Command::Replay {
+                topic: "news".to_string(),
+                count: DEFAULT_REPLAY_COUNT,
+                broadcast: false
+            }
+        );
+        assert_eq!(
+            parse_command("/replay news 5 --broadcast"),
+            Command::Replay {
+                topic: "news".to_string(),
+                count: 5,
+                broadcast: true
+            }
+        );
+        assert_eq!(
+            parse_command("/replay news --broadcast"),
+            Command::Replay {
+                topic: "news".to_string(),
+                count: DEFAULT_REPLAY_COUNT,
+                broadcast: true
+            }
+        );
+        assert_eq!(
+            parse_command("/replay news 0"),
+            Command::Unknown("Invalid message count: 0".to_string())
+        );
+        assert_eq!(
+            parse_command("/replay"),
+            Command::Unknown("Usage: /replay <topic> [count] [--broadcast]".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_snapshot() {
+        assert_eq!(
+            parse_command("/snapshot save backups/state 1.json"),
+            Command::SnapshotSave(PathBuf::from("backups/state 1.json"))
+        );
+        assert_eq!(
+            parse_command("/snapshot load state.json"),
+            Command::SnapshotLoad(PathBuf::from("state.json"))
+        );
+        for input in [
+            "/snapshot",
+            "/snapshot save",
+            "/snapshot restore state.json",
+        ] {
+            assert_eq!(
+                parse_command(input),
+                Command::Unknown("Usage: /snapshot save|load <path>".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_forward() {
+        assert_eq!(
+            parse_command("/forward alerts ops --replace ^CRIT critical --filter ^CRIT"),
+            Command::Forward(ForwardSpec {
+                from: "alerts".to_string(),
+                to: "ops".to_string(),
+                filter: Some("^CRIT".to_string()),
+                transform: Some(Transform {
+                    pattern: "^CRIT".to_string(),
+                    replacement: "critical".to_string()
+                }),
+            })
+        );
+        assert!(matches!(
+            parse_command("/forward alerts ops"),
+            Command::Forward(ForwardSpec {
+                filter: None,
+                transform: None,
+                ..
+            })
+        ));
+        assert_eq!(parse_command("/forward list"), Command::ForwardList);
+        let id = Uuid::new_v4();
+        assert_eq!(
+            parse_command(&format!("/forward remove {}", id)),
+            Command::ForwardRemove(id)
+        );
+        assert!(matches!(
+            parse_command("/forward alerts"),
+            Command::Unknown(_)
+        ));
+        assert!(matches!(
+            parse_command("/forward alerts ops --filter a --filter b"),
+            Command::Unknown(_)
+        ));
+        assert!(matches!(
+            parse_command("/forward alerts ops --replace CRIT"),
+            Command::Unknown(_)
+        ));
+    }
+
+    #[test]
+    fn test_parse_poll() {
+        assert_eq!(
+            parse_command("/poll general \"Where to for lunch?\" Pizza \"Sushi bar\" --for 2m"),
+            Command::Poll {
+                topic: "general".to_string(),
+                question: "Where to for lunch?".to_string(),
+                options: vec!["Pizza".to_string(), "Sushi bar".to_string()],
+                duration: Duration::from_secs(120)
+            }
+        );
+        assert_eq!(
+            parse_command("/poll general Ready? Yes No"),
+            Command::Poll {
+                topic: "general".to_string(),
+                question: "Ready?".to_string(),
+                options: vec!["Yes".to_string(), "No".to_string()],
+                duration: DEFAULT_POLL_DURATION
+            }
+        );
+        let usage = Command::Unknown(
+            "Usage: /poll <topic> \"<question>\" <option> <option>... [--for <duration>]"
+                .to_string(),
+        );
+        assert_eq!(parse_command("/poll general \"Unclosed Yes No"), usage);
+        assert_eq!(parse_command("/poll general"), usage);
+        assert_eq!(parse_command("/poll general Q? Yes No --for"), usage);
+    }
+
+    #[test]
+    fn test_parse_reload() {
+        assert_eq!(parse_command("/reload"), Command::Reload);
+        assert_eq!(parse_command("/loglevel"), Command::LogLevel(None));
+        assert_eq!(
+            parse_command("/loglevel debug"),
+            Command::LogLevel(Some(LevelFilter::DEBUG))
+        );
+        assert_eq!(
+            parse_command("/loglevel loud"),
+            Command::Unknown(
+                "Invalid log level: loud (expected off, error, warn, info, debug or trace)"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_audit() {
+        assert_eq!(
+            parse_command("/audit tail"),
+            Command::AuditTail(DEFAULT_AUDIT_TAIL)
+        );
+        assert_eq!(parse_command("/audit tail 5"), Command::AuditTail(5));
+        assert_eq!(
+            parse_command("/audit tail none"),
+            Command::Unknown("Invalid entry count: none".to_string())
+        );
+        assert_eq!(
+            parse_command("/audit"),
+            Command::Unknown("Usage: /audit tail [count]".to_string())
+        );
+    }
+
     #[test]
     fn test_parse_private() {
         let client_id = Uuid::new_v4();
@@ -189,6 +937,105 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_kick() {
+        let client_id = Uuid::new_v4();
+        assert_eq!(
+            parse_command(&format!("/kick {}", client_id)),
+            Command::Kick {
+                client_id,
+                reason: None
+            }
+        );
+        assert_eq!(
+            parse_command(&format!("/k {} spamming the topic", client_id)),
+            Command::Kick {
+                client_id,
+                reason: Some("spamming the topic".to_string())
+            }
+        );
+        assert_eq!(
+            parse_command("/kick"),
+            Command::Unknown("Usage: /kick <client_id> [reason]".to_string())
+        );
+        assert_eq!(
+            parse_command("/kick 12345"),
+            Command::Unknown("Invalid client ID: 12345".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_whois() {
+        let identity = Uuid::new_v4();
+        assert_eq!(
+            parse_command(&format!("/whois {}", identity)),
+            Command::Whois(identity)
+        );
+        assert_eq!(
+            parse_command("/w"),
+            Command::Unknown("Usage: /whois <identity>".to_string())
+        );
+        assert_eq!(
+            parse_command("/whois nobody"),
+            Command::Unknown("Invalid identity: nobody".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_ban() {
+        let client_id = Uuid::new_v4();
+        assert_eq!(
+            parse_command(&format!("/ban {}", client_id)),
+            Command::Ban(BanTarget::Client(client_id))
+        );
+        assert_eq!(
+            parse_command("/unban 10.0.0.1"),
+            Command::Unban(BanTarget::Ip("10.0.0.1".parse().unwrap()))
+        );
+        assert_eq!(parse_command("/list bans"), Command::List(ListScope::Bans));
+        assert_eq!(
+            parse_command("/ban"),
+            Command::Unknown("Usage: /ban <client_id|ip>".to_string())
+        );
+        assert_eq!(
+            parse_command("/unban nobody"),
+            Command::Unknown("Not a client ID or IP address: nobody".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_inspect() {
+        let msg_id = Uuid::new_v4();
+        assert_eq!(
+            parse_command(&format!("/inspect {}", msg_id)),
+            Command::Inspect(msg_id)
+        );
+        assert_eq!(
+            parse_command(&format!("/i {}", msg_id)),
+            Command::Inspect(msg_id)
+        );
+        assert_eq!(
+            parse_command("/inspect"),
+            Command::Unknown("Usage: /inspect <msg_id>".to_string())
+        );
+        assert_eq!(
+            parse_command("/inspect 12345"),
+            Command::Unknown("Invalid message ID: 12345".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_stats() {
+        assert_eq!(parse_command("/stats"), Command::Stats(None));
+        assert_eq!(
+            parse_command("/stats general"),
+            Command::Stats(Some("general".to_string()))
+        );
+        assert_eq!(parse_command("/stats server"), Command::ServerStats);
+        assert_eq!(parse_command("/stats analytics"), Command::Analytics);
+        assert_eq!(parse_command("/stats latency"), Command::Latency);
+    }
+
     #[test]
     fn test_parse_empty() {
         assert_eq!(parse_command(""), Command::Unknown("".to_string()));
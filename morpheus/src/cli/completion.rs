@@ -0,0 +1,118 @@
+use crate::core::client_manager::ClientManager;
+use rustyline::{
+    completion::Completer, highlight::Highlighter, hint::Hinter, validate::Validator, Context,
+    Helper,
+};
+use std::sync::Arc;
+
+/// Completes the first argument of `/topic`, `/list`, `/forward`, `/replay` and `/private`
+/// in the server CLI from the topics and clients connected right now.
+pub struct CommandHelper {
+    client_manager: Arc<ClientManager>,
+}
+
+impl CommandHelper {
+    pub fn new(client_manager: Arc<ClientManager>) -> Self {
+        Self { client_manager }
+    }
+
+    /// Everything the first argument of `command` can be.
+    fn arguments(&self, command: &str) -> Vec<String> {
+        let keywords: &[&str] = match command {
+            "/topic" | "/t" => &["merge", "split"],
+            "/list" | "/l" => &["all", "topics", "bans"],
+            "/forward" => &["list", "remove"],
+            "/replay" => &[],
+            "/private" | "/p" => {
+                return self
+                    .client_manager
+                    .get_all_clients()
+                    .into_iter()
+                    .map(|client| client.id.to_string())
+                    .collect()
+            }
+            _ => return Vec::new(),
+        };
+        let mut arguments: Vec<String> = keywords.iter().map(|k| k.to_string()).collect();
+        arguments.extend(self.client_manager.get_all_topics());
+        arguments
+    }
+}
+
+/// Where the word under the cursor at `pos` starts, and the candidates for it if it is
+/// the first argument of a command. `arguments` gives the possible arguments of the
+/// command and is only asked when there is one to complete.
+pub fn complete(
+    line: &str,
+    pos: usize,
+    arguments: impl FnOnce(&str) -> Vec<String>,
+) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start = before.rfind(' ').map_or(0, |space| space + 1);
+    let mut words = before[..start].split_whitespace();
+    let (Some(command), None) = (words.next(), words.next()) else {
+        return (start, Vec::new());
+    };
+    let prefix = &before[start..];
+    let mut candidates: Vec<String> = arguments(command)
+        .into_iter()
+        .filter(|argument| argument.starts_with(prefix))
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+    (start, candidates)
+}
+
+impl Completer for CommandHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(line, pos, |command| self.arguments(command)))
+    }
+}
+
+impl Hinter for CommandHelper {
+    type Hint = String;
+}
+
+impl Highlighter for CommandHelper {}
+
+impl Validator for CommandHelper {}
+
+impl Helper for CommandHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topics(command: &str) -> Vec<String> {
+        match command {
+            "/topic" => vec!["ops".into(), "general".into(), "merge".into(), "ops".into()],
+            _ => vec!["unused".into()],
+        }
+    }
+
+    #[test]
+    fn test_completes_the_first_argument_only() {
+        assert_eq!(
+            complete("/topic ", 7, topics),
+            (7, vec!["general".to_string(), "merge".into(), "ops".into()])
+        );
+        assert_eq!(
+            complete("/topic o", 8, topics),
+            (7, vec!["ops".to_string()])
+        );
+        assert_eq!(complete("/topic ops hel", 14, topics), (11, vec![]));
+        assert_eq!(complete("/top", 4, topics), (0, vec![]));
+        // Only what is left of the cursor counts.
+        assert_eq!(
+            complete("/topic g rest", 8, topics),
+            (7, vec!["general".to_string()])
+        );
+    }
+}
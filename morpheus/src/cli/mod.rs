@@ -1,2 +1,5 @@
 pub mod commands;
+pub mod completion;
+#[cfg(unix)]
+pub mod tui;
 pub mod ui;
@@ -0,0 +1,635 @@
+use crate::{
+    cli::{
+        commands::{self, Command},
+        ui,
+    },
+    core::{client_manager::ClientManager, metrics::Metrics, stats::TopicStats},
+    log::recent,
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+        execute,
+        terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Layout, Rect},
+    style::{Modifier, Style},
+    widgets::{Block, Borders, Cell, Paragraph, Row, Sparkline, Table, TableState},
+    Frame, Terminal,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    os::unix::io::{AsRawFd, FromRawFd},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+
+/// How often the panels are refreshed.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+/// Set to make the TUI quit from outside, e.g. when a signal stops the server.
+static QUIT: AtomicBool = AtomicBool::new(false);
+
+/// Makes the TUI restore the terminal and return from `run` within a refresh.
+pub fn quit() {
+    QUIT.store(true, Ordering::Relaxed);
+}
+
+/// How many seconds of throughput the sparkline shows.
+const THROUGHPUT_HISTORY: usize = 120;
+
+/// How many lines of output the output panel shows.
+const OUTPUT_LINES: u16 = 8;
+
+/// What a key press asks the event loop to do.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Action {
+    Continue,
+    Quit,
+    /// Run a command typed into the command box.
+    Run(String),
+}
+
+/// Which panel the arrow keys move in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Focus {
+    Topics,
+    Clients,
+}
+
+/// A client row of the clients panel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ClientRow {
+    id: String,
+    topic: String,
+    ip: String,
+    queued: usize,
+}
+
+/// Everything the panels show, taken from the client manager once per refresh.
+#[derive(Default)]
+struct Snapshot {
+    metrics: Metrics,
+    in_flight: usize,
+    topics: Vec<(String, TopicStats)>,
+    clients: Vec<ClientRow>,
+}
+
+impl Snapshot {
+    fn take(client_manager: &ClientManager) -> Self {
+        let mut clients: Vec<_> = client_manager
+            .get_all_clients()
+            .into_iter()
+            .map(|client| ClientRow {
+                id: client.id.to_string(),
+                topic: client.topic.unwrap_or_default(),
+                ip: client
+                    .ip
+                    .map_or("internal".to_string(), |ip| ip.to_string()),
+                queued: client.sender.len(),
+            })
+            .collect();
+        clients.sort_by(|a, b| (&a.topic, &a.id).cmp(&(&b.topic, &b.id)));
+        Self {
+            metrics: client_manager.metrics(),
+            in_flight: client_manager.in_flight_count(),
+            topics: client_manager.all_topic_stats(),
+            clients,
+        }
+    }
+}
+
+/// Messages per second, overall and per topic, derived from the topic counters.
+#[derive(Debug, Default)]
+struct Throughput {
+    last_counts: HashMap<String, u64>,
+    last_at: Option<Instant>,
+    per_topic: HashMap<String, f64>,
+    /// Overall messages per second of the most recent refreshes, oldest first.
+    history: VecDeque<u64>,
+}
+
+impl Throughput {
+    fn update(&mut self, topics: &[(String, TopicStats)], now: Instant) {
+        let elapsed = self
+            .last_at
+            .map(|at| now.duration_since(at).as_secs_f64())
+            .filter(|elapsed| *elapsed > 0.0);
+        let mut total = 0.0;
+        self.per_topic.clear();
+        for (topic, stats) in topics {
+            let previous = self.last_counts.insert(topic.clone(), stats.messages);
+            if let (Some(previous), Some(elapsed)) = (previous, elapsed) {
+                let rate = stats.messages.saturating_sub(previous) as f64 / elapsed;
+                total += rate;
+                self.per_topic.insert(topic.clone(), rate);
+            }
+        }
+        self.last_at = Some(now);
+        if elapsed.is_some() {
+            self.history.push_back(total.round() as u64);
+            if self.history.len() > THROUGHPUT_HISTORY {
+                self.history.pop_front();
+            }
+        }
+    }
+
+    fn rate(&self, topic: &str) -> f64 {
+        self.per_topic.get(topic).copied().unwrap_or_default()
+    }
+
+    fn total(&self) -> u64 {
+        self.history.back().copied().unwrap_or_default()
+    }
+}
+
+/// The state of the TUI between frames.
+struct App {
+    snapshot: Snapshot,
+    throughput: Throughput,
+    focus: Focus,
+    topics: TableState,
+    clients: TableState,
+    /// Only the clients of this topic are listed, set with Enter on a topic.
+    topic_filter: Option<String>,
+    /// The command being typed, while the command box has the keyboard.
+    input: Option<String>,
+}
+
+impl App {
+    fn new(client_manager: &ClientManager) -> Self {
+        let mut app = Self {
+            snapshot: Snapshot::default(),
+            throughput: Throughput::default(),
+            focus: Focus::Topics,
+            topics: TableState::default().with_selected(0),
+            clients: TableState::default().with_selected(0),
+            topic_filter: None,
+            input: None,
+        };
+        app.refresh(client_manager);
+        app
+    }
+
+    fn refresh(&mut self, client_manager: &ClientManager) {
+        self.snapshot = Snapshot::take(client_manager);
+        self.throughput
+            .update(&self.snapshot.topics, Instant::now());
+        clamp(&mut self.topics, self.snapshot.topics.len());
+        let visible = self.visible_clients().len();
+        clamp(&mut self.clients, visible);
+    }
+
+    fn visible_clients(&self) -> Vec<&ClientRow> {
+        self.snapshot
+            .clients
+            .iter()
+            .filter(|client| {
+                self.topic_filter
+                    .as_ref()
+                    .is_none_or(|topic| client.topic == *topic)
+            })
+            .collect()
+    }
+
+    /// Handles a key press, typing into the command box while it has the keyboard.
+    fn on_key(&mut self, code: KeyCode) -> Action {
+        if let Some(input) = &mut self.input {
+            match code {
+                KeyCode::Char(c) => input.push(c),
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Esc => self.input = None,
+                KeyCode::Enter => {
+                    let line = self.input.take().unwrap_or_default();
+                    return match commands::parse_command(line.trim()) {
+                        _ if line.trim().is_empty() => Action::Continue,
+                        Command::Exit => Action::Quit,
+                        _ => Action::Run(line),
+                    };
+                }
+                _ => {}
+            }
+            return Action::Continue;
+        }
+        match code {
+            KeyCode::Char('q') => return Action::Quit,
+            KeyCode::Char('/') => self.input = Some("/".to_string()),
+            KeyCode::Char(':') => self.input = Some(String::new()),
+            KeyCode::Tab | KeyCode::BackTab => {
+                self.focus = match self.focus {
+                    Focus::Topics => Focus::Clients,
+                    Focus::Clients => Focus::Topics,
+                }
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Enter if self.focus == Focus::Topics => {
+                self.topic_filter = self
+                    .topics
+                    .selected()
+                    .and_then(|index| self.snapshot.topics.get(index))
+                    .map(|(topic, _)| topic.clone());
+                self.clients.select(Some(0));
+                let visible = self.visible_clients().len();
+                clamp(&mut self.clients, visible);
+                self.focus = Focus::Clients;
+            }
+            KeyCode::Esc => self.topic_filter = None,
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let (state, len) = match self.focus {
+            Focus::Topics => (&mut self.topics, self.snapshot.topics.len()),
+            Focus::Clients => {
+                let len = self.visible_clients().len();
+                (&mut self.clients, len)
+            }
+        };
+        if len == 0 {
+            return;
+        }
+        let current = state.selected().unwrap_or(0) as isize;
+        state.select(Some((current + delta).rem_euclid(len as isize) as usize));
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, chart, panels, output, input, footer] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Length(5),
+            Constraint::Min(5),
+            Constraint::Length(OUTPUT_LINES + 2),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [topics, clients] =
+            Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)])
+                .areas(panels);
+
+        let metrics = &self.snapshot.metrics;
+        let summary = format!(
+            "clients {}  topics {}  pending acks {}  queued {}  in flight {}  {} msg/s",
+            metrics.connected_clients,
+            metrics.active_topics,
+            metrics.pending_acks,
+            metrics.queued_messages,
+            self.snapshot.in_flight,
+            self.throughput.total()
+        );
+        frame.render_widget(
+            Paragraph::new(summary)
+                .block(Block::default().borders(Borders::ALL).title(" morpheus ")),
+            header,
+        );
+
+        let history: Vec<u64> = self.throughput.history.iter().copied().collect();
+        frame.render_widget(
+            Sparkline::default()
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(" throughput (msg/s) "),
+                )
+                .data(&history),
+            chart,
+        );
+
+        self.draw_topics(frame, topics);
+        self.draw_clients(frame, clients);
+
+        let lines = recent::last(output.height.saturating_sub(2) as usize).join("\n");
+        frame.render_widget(
+            Paragraph::new(lines).block(panel(" log and output ", false)),
+            output,
+        );
+        self.draw_input(frame, input);
+
+        let keys = if self.input.is_some() {
+            "enter run command  esc cancel"
+        } else {
+            "q quit  / type a command  tab switch panel  ↑/↓ select  enter show topic's clients  esc show all clients"
+        };
+        frame.render_widget(Paragraph::new(keys), footer);
+    }
+
+    fn draw_input(&self, frame: &mut Frame, area: Rect) {
+        let block = panel(" command ", self.input.is_some());
+        match &self.input {
+            Some(input) => {
+                let x = area.x + 1 + input.chars().count() as u16;
+                frame.set_cursor_position((x.min(area.right().saturating_sub(2)), area.y + 1));
+                frame.render_widget(Paragraph::new(input.as_str()).block(block), area);
+            }
+            None => frame.render_widget(
+                Paragraph::new("press / to type a command, e.g. /list topics").block(block),
+                area,
+            ),
+        }
+    }
+
+    fn draw_topics(&mut self, frame: &mut Frame, area: Rect) {
+        let rows = self.snapshot.topics.iter().map(|(topic, stats)| {
+            Row::new(vec![
+                Cell::from(topic.clone()),
+                Cell::from(stats.clients.to_string()),
+                Cell::from(stats.messages.to_string()),
+                Cell::from(format!("{:.1}", self.throughput.rate(topic))),
+                Cell::from(stats.bytes_in.to_string()),
+                Cell::from(stats.bytes_out.to_string()),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Min(12),
+                Constraint::Length(7),
+                Constraint::Length(9),
+                Constraint::Length(7),
+                Constraint::Length(10),
+                Constraint::Length(10),
+            ],
+        )
+        .header(header_row(&[
+            "topic",
+            "clients",
+            "messages",
+            "msg/s",
+            "bytes in",
+            "bytes out",
+        ]))
+        .block(panel(" topics ", self.focus == Focus::Topics))
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, area, &mut self.topics);
+    }
+
+    fn draw_clients(&mut self, frame: &mut Frame, area: Rect) {
+        let title = match &self.topic_filter {
+            Some(topic) => format!(" clients in '{}' ", topic),
+            None => " clients ".to_string(),
+        };
+        let rows: Vec<_> = self
+            .visible_clients()
+            .into_iter()
+            .map(|client| {
+                Row::new(vec![
+                    Cell::from(client.id.clone()),
+                    Cell::from(client.topic.clone()),
+                    Cell::from(client.ip.clone()),
+                    Cell::from(client.queued.to_string()),
+                ])
+            })
+            .collect();
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(36),
+                Constraint::Min(8),
+                Constraint::Length(15),
+                Constraint::Length(6),
+            ],
+        )
+        .header(header_row(&["id", "topic", "address", "queued"]))
+        .block(panel(&title, self.focus == Focus::Clients))
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, area, &mut self.clients);
+    }
+}
+
+fn header_row(names: &[&'static str]) -> Row<'static> {
+    Row::new(names.iter().copied().map(Cell::from))
+        .style(Style::default().add_modifier(Modifier::BOLD))
+}
+
+fn panel(title: &str, focused: bool) -> Block<'static> {
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(title.to_string());
+    if focused {
+        block.border_style(Style::default().add_modifier(Modifier::BOLD))
+    } else {
+        block
+    }
+}
+
+/// Keeps a table's selection inside its `len` rows.
+fn clamp(state: &mut TableState, len: usize) {
+    match len {
+        0 => state.select(None),
+        len => state.select(Some(state.selected().unwrap_or(0).min(len - 1))),
+    }
+}
+
+/// Shows live panels of topics, clients, throughput and pending acks in place of the
+/// command line until the operator quits with `q` or `/exit`. Commands typed into the
+/// command box are sent to `commands`. The terminal is drawn through `/dev/tty`; while
+/// the TUI runs, what the server would print to stdout and stderr, and its log, is shown
+/// in the output panel instead so it cannot corrupt the screen.
+pub fn run(client_manager: Arc<ClientManager>, commands: mpsc::Sender<String>) -> io::Result<()> {
+    let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    let _captured = CapturedOutput::start()?;
+    ui::use_editor_prompt();
+    enable_raw_mode()?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(tty))?;
+    execute!(terminal.backend_mut(), EnterAlternateScreen)?;
+    let result = event_loop(&mut terminal, &client_manager, &commands);
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    result
+}
+
+fn event_loop(
+    terminal: &mut Terminal<CrosstermBackend<File>>,
+    client_manager: &ClientManager,
+    commands: &mpsc::Sender<String>,
+) -> io::Result<()> {
+    let mut app = App::new(client_manager);
+    let mut refreshed = Instant::now();
+    loop {
+        if QUIT.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        terminal.draw(|frame| app.draw(frame))?;
+        let timeout = REFRESH_INTERVAL.saturating_sub(refreshed.elapsed());
+        if event::poll(timeout)? {
+            if let Event::Key(key) = event::read()? {
+                let interrupted =
+                    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if interrupted {
+                    return Ok(());
+                }
+                if key.kind == KeyEventKind::Press {
+                    match app.on_key(key.code) {
+                        Action::Continue => {}
+                        Action::Quit => return Ok(()),
+                        Action::Run(line) => {
+                            recent::push(&format!("morpheus> {}", line));
+                            if commands.blocking_send(line).is_err() {
+                                return Ok(());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if refreshed.elapsed() >= REFRESH_INTERVAL {
+            app.refresh(client_manager);
+            refreshed = Instant::now();
+        }
+    }
+}
+
+/// Points stdout and stderr at a pipe whose lines are kept as recent lines, and keeps
+/// log lines as well, until dropped.
+struct CapturedOutput {
+    saved_stdout: i32,
+    saved_stderr: i32,
+    /// The write end of the pipe, only closed after stdout and stderr are restored.
+    _pipe: File,
+}
+
+impl CapturedOutput {
+    fn start() -> io::Result<Self> {
+        let mut fds = [0; 2];
+        // SAFETY: `fds` has room for the two descriptors `pipe` creates.
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: both ends were just created and are owned by the `File`s from here on.
+        let (reader, pipe) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        std::thread::spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                match line {
+                    Ok(line) => recent::push(&line),
+                    Err(_) => break,
+                }
+            }
+        });
+        io::stdout().flush()?;
+        // SAFETY: only file descriptors owned by this process are duplicated.
+        let (saved_stdout, saved_stderr) = unsafe {
+            (
+                libc::dup(libc::STDOUT_FILENO),
+                libc::dup(libc::STDERR_FILENO),
+            )
+        };
+        let redirected = saved_stdout >= 0
+            && saved_stderr >= 0
+            && unsafe { libc::dup2(pipe.as_raw_fd(), libc::STDOUT_FILENO) } >= 0
+            && unsafe { libc::dup2(pipe.as_raw_fd(), libc::STDERR_FILENO) } >= 0;
+        let captured = Self {
+            saved_stdout,
+            saved_stderr,
+            _pipe: pipe,
+        };
+        if !redirected {
+            return Err(io::Error::last_os_error());
+        }
+        recent::capture(true);
+        Ok(captured)
+    }
+}
+
+impl Drop for CapturedOutput {
+    fn drop(&mut self) {
+        recent::capture(false);
+        let _ = io::stdout().flush();
+        // SAFETY: the saved descriptors are the duplicates of stdout and stderr taken in
+        // `start`; once they are restored only `_pipe` holds the write end, so the reader
+        // thread ends when it is closed.
+        unsafe {
+            for (saved, fd) in [
+                (self.saved_stdout, libc::STDOUT_FILENO),
+                (self.saved_stderr, libc::STDERR_FILENO),
+            ] {
+                if saved >= 0 {
+                    libc::dup2(saved, fd);
+                    libc::close(saved);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(messages: u64) -> TopicStats {
+        TopicStats {
+            messages,
+            ..TopicStats::default()
+        }
+    }
+
+    #[test]
+    fn test_throughput_from_counters() {
+        let mut throughput = Throughput::default();
+        let start = Instant::now();
+        throughput.update(&[("a".to_string(), stats(10))], start);
+        assert_eq!(throughput.total(), 0);
+        assert!(throughput.history.is_empty());
+
+        throughput.update(
+            &[("a".to_string(), stats(30)), ("b".to_string(), stats(4))],
+            start + Duration::from_secs(2),
+        );
+        assert_eq!(throughput.rate("a"), 10.0);
+        // A topic seen for the first time has no rate yet.
+        assert_eq!(throughput.rate("b"), 0.0);
+        assert_eq!(throughput.total(), 10);
+
+        throughput.update(
+            &[("a".to_string(), stats(30)), ("b".to_string(), stats(8))],
+            start + Duration::from_secs(3),
+        );
+        assert_eq!(throughput.rate("b"), 4.0);
+        assert_eq!(throughput.history, VecDeque::from([10, 4]));
+    }
+
+    #[test]
+    fn test_command_box_takes_the_keyboard() {
+        use crate::core::storage::InMemoryStorage;
+
+        let client_manager = ClientManager::new(Arc::new(InMemoryStorage::new()));
+        let mut app = App::new(&client_manager);
+        assert_eq!(app.on_key(KeyCode::Char('/')), Action::Continue);
+        for c in "lq".chars() {
+            assert_eq!(app.on_key(KeyCode::Char(c)), Action::Continue);
+        }
+        app.on_key(KeyCode::Backspace);
+        assert_eq!(app.on_key(KeyCode::Enter), Action::Run("/l".to_string()));
+        assert_eq!(app.input, None);
+
+        app.on_key(KeyCode::Char(':'));
+        app.on_key(KeyCode::Esc);
+        assert_eq!(app.input, None);
+        for key in [KeyCode::Char('/'), KeyCode::Char('e')] {
+            app.on_key(key);
+        }
+        assert_eq!(app.on_key(KeyCode::Enter), Action::Quit);
+        assert_eq!(app.on_key(KeyCode::Char('q')), Action::Quit);
+    }
+
+    #[test]
+    fn test_clamp_selection() {
+        let mut state = TableState::default().with_selected(5);
+        clamp(&mut state, 3);
+        assert_eq!(state.selected(), Some(2));
+        clamp(&mut state, 0);
+        assert_eq!(state.selected(), None);
+        clamp(&mut state, 2);
+        assert_eq!(state.selected(), Some(0));
+    }
+}
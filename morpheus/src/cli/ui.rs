@@ -1,8 +1,31 @@
-use std::io::{self, Write};
+use std::{
+    io::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-/// Prints a standard prompt for the server CLI.
+/// The prompt of the server CLI.
+pub const PROMPT: &str = "morpheus> ";
+
+/// Set once the line editor or the TUI's command box draws the prompt itself, or when
+/// there is no command line to prompt for.
+static EDITOR_PROMPT: AtomicBool = AtomicBool::new(false);
+
+/// Leaves the prompt to the line editor or the TUI from now on.
+pub fn use_editor_prompt() {
+    EDITOR_PROMPT.store(true, Ordering::Relaxed);
+}
+
+/// Stops printing the prompt, for a server running without a command line.
+pub fn hide_prompt() {
+    EDITOR_PROMPT.store(true, Ordering::Relaxed);
+}
+
+/// Prints a standard prompt for the server CLI, unless the line editor shows it.
 pub fn print_prompt() {
-    print!("\nmorpheus> ");
+    if EDITOR_PROMPT.load(Ordering::Relaxed) {
+        return;
+    }
+    print!("\n{}", PROMPT);
     io::stdout().flush().unwrap();
 }
 
@@ -18,6 +41,12 @@ pub fn print_error(msg: &str) {
     print_prompt();
 }
 
+/// Prints a warning, such as a triggered alert, to the console.
+pub fn print_warning(msg: &str) {
+    eprintln!("\n[WARNING] {}\n", msg);
+    print_prompt();
+}
+
 /// Prints a confirmation of a sent message.
 pub fn print_confirmation(msg: &str) {
     println!("\n[SENT] {}\n", msg);
@@ -0,0 +1,981 @@
+use crate::{
+    core::{
+        admission::ReconnectConfig,
+        canary::CanaryConfig,
+        client_manager::ClientManager,
+        cluster::ClusterConfig,
+        compression::CompressionConfig,
+        federation::FederationConfig,
+        history::DEFAULT_RETENTION,
+        jwt::{Jwt, JwtConfig},
+        mirror::MirrorConfig,
+        policy::{Delivery, Limits, Policies, RateLimit, Role, TopicPolicy, DEFAULT_BURST},
+        queue::QueueConfig,
+        webhooks::WebhookConfig,
+    },
+    log::{
+        json::LogFormat,
+        rotation::{Rotation, RotationPolicy},
+    },
+    proxy::Cidr,
+};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::level_filters::LevelFilter;
+
+/// The prefix of every environment variable that overrides a config value.
+pub const ENV_PREFIX: &str = "MORPHEUS_";
+
+/// Where clients open their WebSocket unless `ws_path` says otherwise.
+pub const DEFAULT_WS_PATH: &str = "/ws";
+
+/// The first path segments taken by the other endpoints, which the WebSocket path must not
+/// start with.
+const RESERVED_PATHS: [&str; 4] = ["sse", "whois", "admin", "cluster"];
+
+/// Server settings, read from a TOML file given with `--config`.
+///
+/// Values are resolved in order of increasing precedence: built-in defaults, the
+/// config file, `MORPHEUS_*` environment variables and finally command line flags.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub address: IpAddr,
+    pub port: u16,
+    /// Serve `wss://` with this certificate instead of plain `ws://`.
+    pub tls: Option<TlsConfig>,
+    /// Listeners served alongside `address` and `port`, e.g. a public TLS one next to a
+    /// plain one on localhost.
+    pub listeners: Vec<ListenerConfig>,
+    /// Where clients open their WebSocket, e.g. `/ws` or `/chat/socket`.
+    pub ws_path: String,
+    /// Load balancers and reverse proxies in front of the server.
+    pub proxy: ProxyConfig,
+    pub auth: AuthConfig,
+    /// Limits how fast each connection may publish; unlimited when absent.
+    pub rate_limit: Option<RateLimit>,
+    /// Caps on concurrent connections and clients per topic.
+    pub limits: Limits,
+    pub log: LogConfig,
+    pub queue: QueueConfig,
+    /// Compression of large frames for clients that negotiate it.
+    pub compression: CompressionConfig,
+    /// Reconnect jitter advertised to clients and connection pacing after startup.
+    pub reconnect: ReconnectConfig,
+    pub history: HistoryConfig,
+    pub cluster: ClusterSection,
+    /// JSON file with alerting rules.
+    pub alert_rules: Option<PathBuf>,
+    /// Probe the server through its own WebSocket endpoint; disabled when absent.
+    pub canary: Option<CanarySection>,
+    /// The message of the day sent to clients when they subscribe.
+    pub motd: Option<String>,
+    /// Where banned client IDs and addresses are saved.
+    pub ban_list: PathBuf,
+    /// Where the connection history of session identities is saved.
+    pub connection_history: PathBuf,
+    /// Where operator actions are recorded.
+    pub audit_log: PathBuf,
+    /// Where the commands typed into the server CLI are kept across restarts.
+    pub cli_history: PathBuf,
+    /// The SQLite database keeping sessions, subscriptions, retained messages and history
+    /// across restarts; they are kept in memory when absent.
+    pub database: Option<PathBuf>,
+    /// Endpoints that topic messages are forwarded to.
+    pub webhooks: Vec<WebhookConfig>,
+    /// Topics republished on other morpheus instances.
+    pub mirrors: Vec<MirrorConfig>,
+    /// Independent morpheus servers whose topics are relayed in both directions.
+    pub federation: Vec<FederationConfig>,
+    /// Per-topic policies, keyed by topic name.
+    pub topics: HashMap<String, TopicPolicy>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            address: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            port: 8080,
+            tls: None,
+            listeners: Vec::new(),
+            ws_path: DEFAULT_WS_PATH.to_string(),
+            proxy: ProxyConfig::default(),
+            auth: AuthConfig::default(),
+            rate_limit: None,
+            limits: Limits::default(),
+            log: LogConfig::default(),
+            queue: QueueConfig::default(),
+            compression: CompressionConfig::default(),
+            reconnect: ReconnectConfig::default(),
+            history: HistoryConfig::default(),
+            cluster: ClusterSection::default(),
+            alert_rules: None,
+            canary: None,
+            motd: None,
+            ban_list: PathBuf::from("bans.json"),
+            connection_history: PathBuf::from("connections.json"),
+            audit_log: PathBuf::from("audit.log"),
+            cli_history: PathBuf::from("cli_history.txt"),
+            database: None,
+            webhooks: Vec::new(),
+            mirrors: Vec::new(),
+            federation: Vec::new(),
+            topics: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain.
+    pub cert: PathBuf,
+    /// PEM file with the private key.
+    pub key: PathBuf,
+}
+
+/// An address and port the server accepts connections on.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerConfig {
+    pub address: IpAddr,
+    pub port: u16,
+    /// Serve `wss://` on this listener with this certificate.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+}
+
+impl ListenerConfig {
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.address, self.port)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// Addresses or ranges such as `10.0.0.0/8` whose `X-Forwarded-For` is believed.
+    pub trusted: Vec<Cidr>,
+    /// Every connection starts with a PROXY protocol header naming the client.
+    pub proxy_protocol: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Tokens clients may present when connecting, granting every permission.
+    /// Authentication is disabled when there are none, no `roles` and no `jwt`.
+    pub tokens: Vec<String>,
+    /// Tokens clients may present when connecting, each granting the permissions of a role.
+    pub roles: HashMap<String, Role>,
+    /// JSON Web Tokens clients may present instead, granting the permissions in their claims.
+    pub jwt: Option<JwtConfig>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Where the message and access logs are written.
+    pub directory: PathBuf,
+    /// The most verbose level written to the message log, e.g. `info` or `debug`.
+    #[serde(deserialize_with = "deserialize_level")]
+    pub level: LevelFilter,
+    /// Whether HTTP requests are recorded in the access log.
+    pub access_log: bool,
+    /// Whether events are also written to standard output.
+    pub stdout: bool,
+    /// `text` or `json` lines.
+    pub format: LogFormat,
+    /// Start a new file `hourly` or `daily`; `never` leaves it to `max_file_bytes`.
+    pub rotation: Rotation,
+    /// Start a new file before one grows beyond this many bytes; unlimited when unset.
+    pub max_file_bytes: Option<u64>,
+    /// How many files each log keeps, the current one included; older files are deleted.
+    /// Unlimited when unset.
+    pub max_files: Option<usize>,
+    /// Per-module overrides keyed by module path, e.g. `morpheus::ws` for wire logs. Each
+    /// applies to the module and its submodules; the most specific one wins.
+    pub targets: BTreeMap<String, LogTarget>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("logs"),
+            level: LevelFilter::INFO,
+            access_log: true,
+            stdout: false,
+            format: LogFormat::default(),
+            rotation: Rotation::default(),
+            max_file_bytes: None,
+            max_files: None,
+            targets: BTreeMap::new(),
+        }
+    }
+}
+
+/// The level and destination for the events of one module.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogTarget {
+    /// The most verbose level written for the module; the log's `level` if unset.
+    #[serde(deserialize_with = "deserialize_optional_level")]
+    pub level: Option<LevelFilter>,
+    /// A file in the log directory to write the module's events to instead of the
+    /// message log.
+    pub file: Option<PathBuf>,
+}
+
+fn deserialize_optional_level<'de, D: serde::Deserializer<'de>>(
+    d: D,
+) -> Result<Option<LevelFilter>, D::Error> {
+    deserialize_level(d).map(Some)
+}
+
+fn deserialize_level<'de, D: serde::Deserializer<'de>>(d: D) -> Result<LevelFilter, D::Error> {
+    let level = String::deserialize(d)?;
+    level.parse().map_err(serde::de::Error::custom)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// How many messages are kept per topic unless the topic's policy says otherwise.
+    pub retention: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            retention: DEFAULT_RETENTION,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClusterSection {
+    pub node_id: Option<String>,
+    pub peers: Vec<String>,
+    pub secret: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CanarySection {
+    /// Seconds between probes.
+    pub interval_secs: u64,
+    /// Seconds a probe may take before it counts as failed.
+    pub timeout_secs: u64,
+}
+
+impl Default for CanarySection {
+    fn default() -> Self {
+        let defaults = CanaryConfig::default();
+        Self {
+            interval_secs: defaults.interval.as_secs(),
+            timeout_secs: defaults.timeout.as_secs(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads a config file, without applying environment overrides.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+    }
+
+    /// Applies `MORPHEUS_*` overrides from the process environment.
+    pub fn apply_env(&mut self) -> Result<(), String> {
+        self.apply_overrides(|key| std::env::var(format!("{}{}", ENV_PREFIX, key)).ok())
+    }
+
+    /// Applies overrides looked up by their key without the `MORPHEUS_` prefix.
+    /// List values such as `AUTH_TOKENS` and `PEERS` are comma-separated.
+    pub fn apply_overrides(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+        fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("Invalid value for {}{}: '{}'", ENV_PREFIX, key, value))
+        }
+        fn list(value: &str) -> Vec<String> {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect()
+        }
+
+        if let Some(value) = var("ADDRESS") {
+            self.address = parse("ADDRESS", &value)?;
+        }
+        if let Some(value) = var("PORT") {
+            self.port = parse("PORT", &value)?;
+        }
+        if let Some(value) = var("WS_PATH") {
+            self.ws_path = value;
+        }
+        match (var("TLS_CERT"), var("TLS_KEY")) {
+            (Some(cert), Some(key)) => {
+                self.tls = Some(TlsConfig {
+                    cert: cert.into(),
+                    key: key.into(),
+                })
+            }
+            (None, None) => {}
+            _ => return Err("MORPHEUS_TLS_CERT and MORPHEUS_TLS_KEY must be set together".into()),
+        }
+        if let Some(value) = var("TRUSTED_PROXIES") {
+            self.proxy.trusted = list(&value)
+                .iter()
+                .map(|range| parse("TRUSTED_PROXIES", range))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(value) = var("PROXY_PROTOCOL") {
+            self.proxy.proxy_protocol = parse("PROXY_PROTOCOL", &value)?;
+        }
+        if let Some(value) = var("AUTH_TOKENS") {
+            self.auth.tokens = list(&value);
+        }
+        if let Some(value) = var("JWT_SECRET") {
+            let jwt = self.auth.jwt.get_or_insert_with(JwtConfig::default);
+            jwt.secret = Some(value);
+        }
+        if let Some(value) = var("RATE_LIMIT") {
+            let messages_per_second = parse("RATE_LIMIT", &value)?;
+            let burst = match var("RATE_LIMIT_BURST") {
+                Some(burst) => parse("RATE_LIMIT_BURST", &burst)?,
+                None => self.rate_limit.map_or(DEFAULT_BURST, |limit| limit.burst),
+            };
+            self.rate_limit = Some(RateLimit {
+                messages_per_second,
+                burst,
+            });
+        }
+        if let Some(value) = var("MAX_CONNECTIONS") {
+            self.limits.max_connections = Some(parse("MAX_CONNECTIONS", &value)?);
+        }
+        if let Some(value) = var("MAX_CLIENTS_PER_TOPIC") {
+            self.limits.max_clients_per_topic = Some(parse("MAX_CLIENTS_PER_TOPIC", &value)?);
+        }
+        if let Some(value) = var("MAX_MESSAGE_BYTES") {
+            self.limits.max_message_bytes = parse("MAX_MESSAGE_BYTES", &value)?;
+        }
+        if let Some(value) = var("MAX_ATTACHMENT_BYTES") {
+            self.limits.max_attachment_bytes = parse("MAX_ATTACHMENT_BYTES", &value)?;
+        }
+        if let Some(value) = var("LOG_DIR") {
+            self.log.directory = value.into();
+        }
+        if let Some(value) = var("LOG_LEVEL") {
+            self.log.level = parse("LOG_LEVEL", &value)?;
+        }
+        if let Some(value) = var("LOG_STDOUT") {
+            self.log.stdout = parse("LOG_STDOUT", &value)?;
+        }
+        if let Some(value) = var("LOG_FORMAT") {
+            self.log.format = parse("LOG_FORMAT", &value)?;
+        }
+        if let Some(value) = var("LOG_ROTATION") {
+            self.log.rotation = parse("LOG_ROTATION", &value)?;
+        }
+        if let Some(value) = var("LOG_MAX_FILE_BYTES") {
+            self.log.max_file_bytes = Some(parse("LOG_MAX_FILE_BYTES", &value)?);
+        }
+        if let Some(value) = var("LOG_MAX_FILES") {
+            self.log.max_files = Some(parse("LOG_MAX_FILES", &value)?);
+        }
+        if let Some(value) = var("QUEUE_CAPACITY") {
+            self.queue.capacity = parse("QUEUE_CAPACITY", &value)?;
+        }
+        if let Some(value) = var("BACKPRESSURE") {
+            self.queue.policy = parse("BACKPRESSURE", &value)?;
+        }
+        if let Some(value) = var("COMPRESSION") {
+            self.compression.enabled = parse("COMPRESSION", &value)?;
+        }
+        if let Some(value) = var("COMPRESSION_MIN_BYTES") {
+            self.compression.min_bytes = parse("COMPRESSION_MIN_BYTES", &value)?;
+        }
+        if let Some(value) = var("HISTORY_RETENTION") {
+            self.history.retention = parse("HISTORY_RETENTION", &value)?;
+        }
+        if let Some(value) = var("NODE_ID") {
+            self.cluster.node_id = Some(value);
+        }
+        if let Some(value) = var("PEERS") {
+            self.cluster.peers = list(&value);
+        }
+        if let Some(value) = var("CLUSTER_SECRET") {
+            self.cluster.secret = Some(value);
+        }
+        if let Some(value) = var("ALERT_RULES") {
+            self.alert_rules = Some(value.into());
+        }
+        if let Some(value) = var("CANARY_INTERVAL") {
+            let canary = self.canary.get_or_insert_with(CanarySection::default);
+            canary.interval_secs = parse("CANARY_INTERVAL", &value)?;
+        }
+        if let Some(value) = var("MOTD") {
+            self.motd = Some(value).filter(|motd| !motd.is_empty());
+        }
+        if let Some(value) = var("BAN_LIST") {
+            self.ban_list = value.into();
+        }
+        if let Some(value) = var("CONNECTION_HISTORY") {
+            self.connection_history = value.into();
+        }
+        if let Some(value) = var("AUDIT_LOG") {
+            self.audit_log = value.into();
+        }
+        if let Some(value) = var("CLI_HISTORY") {
+            self.cli_history = value.into();
+        }
+        if let Some(value) = var("DATABASE") {
+            self.database = Some(value.into());
+        }
+        Ok(())
+    }
+
+    /// Every listener: the one of `address`, `port` and `tls` first, then `listeners`.
+    pub fn listeners(&self) -> Vec<ListenerConfig> {
+        let primary = ListenerConfig {
+            address: self.address,
+            port: self.port,
+            tls: self.tls.clone(),
+        };
+        std::iter::once(primary)
+            .chain(self.listeners.iter().cloned())
+            .collect()
+    }
+
+    /// The segments of `ws_path`, or why it cannot be served.
+    pub fn ws_path_segments(&self) -> Result<Vec<String>, String> {
+        let segments: Vec<String> = self
+            .ws_path
+            .trim_matches('/')
+            .split('/')
+            .map(String::from)
+            .collect();
+        let invalid = |why: &str| Err(format!("ws_path '{}' {}", self.ws_path, why));
+        if segments.iter().any(|segment| segment.is_empty()) {
+            return invalid("has an empty segment");
+        }
+        if segments
+            .iter()
+            .any(|segment| segment.contains(['?', '#', '%', ' ']))
+        {
+            return invalid("may only contain plain path segments");
+        }
+        if RESERVED_PATHS.contains(&segments[0].as_str()) {
+            return invalid("is taken by another endpoint");
+        }
+        Ok(segments)
+    }
+
+    /// The rules the client manager enforces on connections, or why the JWT key cannot be
+    /// loaded.
+    pub fn policies(&self) -> Result<Policies, String> {
+        Ok(Policies {
+            auth_tokens: self.auth.tokens.clone(),
+            auth_roles: self.auth.roles.clone(),
+            jwt: self.auth.jwt.as_ref().map(Jwt::new).transpose()?,
+            rate_limit: self.rate_limit,
+            limits: self.limits,
+            topics: self.topics.clone(),
+            trusted_proxies: self.proxy.trusted.clone(),
+        })
+    }
+
+    /// Retention overrides for topics whose policy sets one.
+    pub fn topic_retention(&self) -> HashMap<String, usize> {
+        self.topics
+            .iter()
+            .filter_map(|(topic, policy)| Some((topic.clone(), policy.retention?)))
+            .collect()
+    }
+
+    /// The canary settings, if the canary is enabled.
+    pub fn canary_config(&self) -> Option<CanaryConfig> {
+        let canary = self.canary.as_ref()?;
+        Some(CanaryConfig {
+            interval: Duration::from_secs(canary.interval_secs.max(1)),
+            timeout: Duration::from_secs(canary.timeout_secs.max(1)),
+        })
+    }
+
+    /// The cluster settings, if this server is part of a cluster.
+    /// The node ID defaults to a random one when only peers are given.
+    pub fn cluster_config(&self) -> Option<ClusterConfig> {
+        if self.cluster.node_id.is_none() && self.cluster.peers.is_empty() {
+            return None;
+        }
+        Some(ClusterConfig {
+            node_id: self
+                .cluster
+                .node_id
+                .clone()
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+            peers: self.cluster.peers.clone(),
+            secret: self.cluster.secret.clone(),
+        })
+    }
+}
+
+/// What a config reload changed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReloadSummary {
+    /// Settings that changed and are now in effect.
+    pub applied: Vec<&'static str>,
+    /// Settings that changed but only take effect after a restart.
+    pub needs_restart: Vec<&'static str>,
+}
+
+/// Re-reads the config file on `/reload` or SIGHUP and applies the settings that can
+/// change at runtime (auth tokens and JWT keys, rate and connection limits, topic policies, MOTD and
+/// log level)
+/// without touching active connections.
+pub struct Reloader {
+    path: PathBuf,
+    /// The file and environment settings last applied, before command line flags.
+    current: Mutex<Config>,
+    client_manager: Arc<ClientManager>,
+}
+
+impl Reloader {
+    pub fn new(path: PathBuf, current: Config, client_manager: Arc<ClientManager>) -> Self {
+        Self {
+            path,
+            current: Mutex::new(current),
+            client_manager,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Applies the config file again, recording the attempt in the audit log.
+    pub fn reload(&self) -> Result<ReloadSummary, String> {
+        let result = self.apply();
+        let outcome = match &result {
+            Ok(summary) if summary.applied.is_empty() && summary.needs_restart.is_empty() => {
+                Ok("Nothing changed".to_string())
+            }
+            Ok(summary) => Ok(format!(
+                "Applied: {}; restart required for: {}",
+                list_or_none(&summary.applied),
+                list_or_none(&summary.needs_restart)
+            )),
+            Err(e) => Err(e.clone()),
+        };
+        let detail = self.path.display().to_string();
+        self.client_manager
+            .audit()
+            .record("reload", &detail, outcome);
+        result
+    }
+
+    fn apply(&self) -> Result<ReloadSummary, String> {
+        let mut config = Config::load(&self.path)?;
+        config.apply_env()?;
+        let policies = config.policies()?;
+
+        let mut current = self.current.lock().unwrap();
+        let summary = current.diff(&config);
+        self.client_manager.set_policies(policies);
+        self.client_manager.set_motd(config.motd.clone());
+        crate::log::middleware::set_log_filters(&config.log);
+        *current = config;
+        Ok(summary)
+    }
+}
+
+fn list_or_none(settings: &[&str]) -> String {
+    match settings {
+        [] => "none".to_string(),
+        settings => settings.join(", "),
+    }
+}
+
+impl LogConfig {
+    fn target_levels(&self) -> BTreeMap<&str, Option<LevelFilter>> {
+        self.targets
+            .iter()
+            .map(|(module, target)| (module.as_str(), target.level))
+            .collect()
+    }
+
+    /// When every log file except the access log starts a new file, and how many are kept.
+    pub fn rotation_policy(&self) -> RotationPolicy {
+        RotationPolicy {
+            rotation: self.rotation,
+            max_file_bytes: self.max_file_bytes,
+            max_files: self.max_files,
+        }
+    }
+
+    /// The files the log writes to besides the message log; opened once at startup.
+    pub fn target_files(&self) -> BTreeSet<&Path> {
+        self.targets
+            .values()
+            .filter_map(|target| target.file.as_deref())
+            .collect()
+    }
+}
+
+impl Config {
+    /// The parts of the topic policies that a reload applies: read-only flags, rules and
+    /// delivery modes.
+    fn live_topic_policies(&self) -> BTreeMap<&str, (bool, Option<&str>, u32, Delivery)> {
+        self.topics
+            .iter()
+            .filter(|(_, policy)| {
+                policy.read_only || policy.rules.is_some() || policy.delivery != Delivery::Broadcast
+            })
+            .map(|(topic, policy)| {
+                let rules = policy.rules.as_deref();
+                (
+                    topic.as_str(),
+                    (
+                        policy.read_only,
+                        rules,
+                        policy.rules_version,
+                        policy.delivery,
+                    ),
+                )
+            })
+            .collect()
+    }
+
+    fn diff(&self, new: &Config) -> ReloadSummary {
+        let mut summary = ReloadSummary::default();
+        let mut check = |changed: bool, name, live: bool| {
+            if changed {
+                if live {
+                    summary.applied.push(name);
+                } else {
+                    summary.needs_restart.push(name);
+                }
+            }
+        };
+        check(
+            (&self.auth.tokens, &self.auth.roles) != (&new.auth.tokens, &new.auth.roles),
+            "auth tokens",
+            true,
+        );
+        check(self.auth.jwt != new.auth.jwt, "JWT", true);
+        check(self.rate_limit != new.rate_limit, "rate limits", true);
+        check(self.limits != new.limits, "connection limits", true);
+        check(
+            self.live_topic_policies() != new.live_topic_policies(),
+            "topic policies",
+            true,
+        );
+        check(self.motd != new.motd, "MOTD", true);
+        check(
+            self.proxy.trusted != new.proxy.trusted,
+            "trusted proxies",
+            true,
+        );
+        check(
+            self.proxy.proxy_protocol != new.proxy.proxy_protocol,
+            "PROXY protocol",
+            false,
+        );
+        check(
+            self.log.level != new.log.level || self.log.target_levels() != new.log.target_levels(),
+            "log levels",
+            true,
+        );
+        check(
+            (self.address, self.port) != (new.address, new.port),
+            "bind address",
+            false,
+        );
+        check(self.tls != new.tls, "TLS", false);
+        check(self.listeners != new.listeners, "listeners", false);
+        check(self.ws_path != new.ws_path, "WebSocket path", false);
+        check(self.queue != new.queue, "queue", false);
+        check(self.compression != new.compression, "compression", false);
+        check(self.reconnect != new.reconnect, "reconnect", false);
+        check(
+            self.history != new.history || self.topic_retention() != new.topic_retention(),
+            "history retention",
+            false,
+        );
+        check(self.cluster != new.cluster, "cluster", false);
+        check(self.alert_rules != new.alert_rules, "alert rules", false);
+        check(self.ban_list != new.ban_list, "ban list", false);
+        check(
+            self.connection_history != new.connection_history,
+            "connection history",
+            false,
+        );
+        check(self.audit_log != new.audit_log, "audit log", false);
+        check(self.cli_history != new.cli_history, "CLI history", false);
+        check(self.database != new.database, "database", false);
+        check(self.canary != new.canary, "canary", false);
+        check(self.webhooks != new.webhooks, "webhooks", false);
+        check(self.mirrors != new.mirrors, "mirrors", false);
+        check(self.federation != new.federation, "federation", false);
+        check(
+            (&self.log.directory, self.log.access_log) != (&new.log.directory, new.log.access_log)
+                || self.log.target_files() != new.log.target_files()
+                || self.log.stdout != new.log.stdout
+                || self.log.format != new.log.format
+                || self.log.rotation_policy() != new.log.rotation_policy(),
+            "log files",
+            false,
+        );
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{audit::AuditLog, queue::BackpressurePolicy};
+
+    #[test]
+    fn test_parse_full_config() {
+        let config: Config = toml::from_str(
+            r#"
+            address = "0.0.0.0"
+            port = 9000
+            alert_rules = "alerts.json"
+
+            [tls]
+            cert = "cert.pem"
+            key = "key.pem"
+
+            [auth]
+            tokens = ["secret"]
+
+            [auth.roles]
+            feed-token = "publisher"
+
+            [rate_limit]
+            messages_per_second = 5.0
+
+            [log]
+            directory = "/var/log/morpheus"
+            level = "debug"
+            format = "json"
+            rotation = "daily"
+            max_files = 7
+
+            [log.targets."morpheus::ws"]
+            level = "trace"
+            file = "wire.log"
+
+            [log.targets."morpheus::core::storage"]
+            level = "warn"
+
+            [queue]
+            capacity = 50
+            policy = "disconnect"
+
+            [compression]
+            min_bytes = 4096
+
+            [history]
+            retention = 200
+
+            [cluster]
+            node_id = "a"
+            peers = ["ws://10.0.0.2:9000/cluster"]
+
+            [topics.announcements]
+            read_only = true
+            retention = 10
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.address, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.tls.as_ref().unwrap().key, PathBuf::from("key.pem"));
+        assert_eq!(config.auth.tokens, vec!["secret"]);
+        assert_eq!(config.auth.roles["feed-token"], Role::Publisher);
+        assert_eq!(config.rate_limit.unwrap().burst, 10);
+        assert_eq!(config.log.level, LevelFilter::DEBUG);
+        assert!(config.log.access_log);
+        assert_eq!(config.log.format, LogFormat::Json);
+        assert_eq!(
+            config.log.rotation_policy(),
+            RotationPolicy {
+                rotation: Rotation::Daily,
+                max_file_bytes: None,
+                max_files: Some(7),
+            }
+        );
+        assert_eq!(
+            config.log.targets["morpheus::ws"],
+            LogTarget {
+                level: Some(LevelFilter::TRACE),
+                file: Some(PathBuf::from("wire.log")),
+            }
+        );
+        assert_eq!(config.log.targets["morpheus::core::storage"].file, None);
+        assert_eq!(config.queue.policy, BackpressurePolicy::Disconnect);
+        assert_eq!(config.queue.capacity, 50);
+        assert!(config.compression.enabled);
+        assert_eq!(config.compression.min_bytes, 4096);
+        assert_eq!(config.history.retention, 200);
+        assert_eq!(config.cluster_config().unwrap().node_id, "a");
+        assert!(config.policies().unwrap().is_read_only("announcements"));
+        assert_eq!(
+            config.topic_retention(),
+            HashMap::from([("announcements".to_string(), 10)])
+        );
+    }
+
+    #[test]
+    fn test_listeners_and_ws_path() {
+        let config: Config = toml::from_str(
+            r#"
+            ws_path = "/chat/socket/"
+
+            [[listeners]]
+            address = "0.0.0.0"
+            port = 443
+            tls = { cert = "cert.pem", key = "key.pem" }
+            "#,
+        )
+        .unwrap();
+
+        let listeners = config.listeners();
+        assert_eq!(listeners.len(), 2);
+        assert_eq!(listeners[0].addr(), "127.0.0.1:8080".parse().unwrap());
+        assert!(listeners[0].tls.is_none());
+        assert_eq!(listeners[1].addr(), "0.0.0.0:443".parse().unwrap());
+        assert!(listeners[1].tls.is_some());
+        assert_eq!(config.ws_path_segments().unwrap(), ["chat", "socket"]);
+
+        for ws_path in ["/", "/a//b", "/ws?x", "/admin/ws"] {
+            let config = Config {
+                ws_path: ws_path.to_string(),
+                ..Config::default()
+            };
+            assert!(config.ws_path_segments().is_err(), "{}", ws_path);
+        }
+        assert!(toml::from_str::<Config>("[[listeners]]\nport = 9000").is_err());
+    }
+
+    #[test]
+    fn test_empty_config_uses_defaults() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config, Config::default());
+        assert!(config.cluster_config().is_none());
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        assert!(toml::from_str::<Config>("prot = 9000").is_err());
+        assert!(toml::from_str::<Config>("[queue]\npolicy = \"block\"").is_err());
+        assert!(toml::from_str::<Config>("[log]\nrotation = \"weekly\"").is_err());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let vars = HashMap::from([
+            ("PORT", "7000"),
+            ("AUTH_TOKENS", "one, two"),
+            ("RATE_LIMIT", "2.5"),
+            ("LOG_LEVEL", "warn"),
+            ("LOG_FORMAT", "json"),
+            ("LOG_MAX_FILE_BYTES", "1048576"),
+            ("TRUSTED_PROXIES", "10.0.0.0/8, ::1"),
+            ("JWT_SECRET", "signing-secret"),
+            ("COMPRESSION", "false"),
+        ]);
+        let mut config = Config::default();
+        config
+            .apply_overrides(|key| vars.get(key).map(|v| v.to_string()))
+            .unwrap();
+        assert_eq!(config.port, 7000);
+        assert_eq!(config.auth.tokens, vec!["one", "two"]);
+        assert_eq!(config.rate_limit.unwrap().messages_per_second, 2.5);
+        assert_eq!(config.log.level, LevelFilter::WARN);
+        assert_eq!(config.log.format, LogFormat::Json);
+        assert_eq!(config.log.max_file_bytes, Some(1_048_576));
+        assert!(!config.compression.enabled);
+        let policies = config.policies().unwrap();
+        assert_eq!(policies.trusted_proxies.len(), 2);
+        assert!(policies.jwt.is_some());
+
+        let error = Config::default()
+            .apply_overrides(|key| (key == "PORT").then(|| "high".to_string()))
+            .unwrap_err();
+        assert_eq!(error, "Invalid value for MORPHEUS_PORT: 'high'");
+    }
+
+    #[test]
+    fn test_diff_separates_live_and_restart_settings() {
+        let old = Config::default();
+        let new: Config = toml::from_str(
+            r#"
+            port = 9000
+            motd = "Welcome"
+
+            [log]
+            level = "debug"
+
+            [topics.news]
+            retention = 5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            old.diff(&new),
+            ReloadSummary {
+                applied: vec!["MOTD", "log levels"],
+                needs_restart: vec!["bind address", "history retention"],
+            }
+        );
+        assert_eq!(new.diff(&new), ReloadSummary::default());
+
+        let quieter: Config =
+            toml::from_str("[log.targets.\"morpheus::ws\"]\nlevel = \"warn\"").unwrap();
+        let redirected: Config =
+            toml::from_str("[log.targets.\"morpheus::ws\"]\nlevel = \"warn\"\nfile = \"wire.log\"")
+                .unwrap();
+        assert_eq!(old.diff(&quieter).applied, vec!["log levels"]);
+        assert_eq!(quieter.diff(&redirected).needs_restart, vec!["log files"]);
+    }
+
+    #[tokio::test]
+    async fn test_reload_applies_live_settings() {
+        let path = std::env::temp_dir().join(format!("morpheus-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "motd = \"Hello\"\n[auth]\ntokens = [\"secret\"]\n").unwrap();
+        let audit_path = path.with_extension("audit.log");
+        let manager = ClientManager::new(Arc::new(crate::core::storage::InMemoryStorage::new()))
+            .with_audit_log(AuditLog::open(&audit_path).unwrap());
+        let manager = Arc::new(manager);
+        let reloader = Reloader::new(path.clone(), Config::default(), manager.clone());
+
+        let summary = reloader.reload().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(summary.applied, vec!["auth tokens", "MOTD"]);
+        assert_eq!(manager.motd().as_deref(), Some("Hello"));
+        assert!(!manager.policies().authorizes(None));
+        let audited = manager.audit().tail(1).unwrap();
+        std::fs::remove_file(&audit_path).unwrap();
+        assert_eq!(audited[0].action, "reload");
+        assert_eq!(
+            audited[0].result,
+            "Applied: auth tokens, MOTD; restart required for: none"
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use serde::Deserialize;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The longest a connection waits for its turn before it is refused as overloaded, which
+/// makes neo back off for longer instead of holding a socket open in the queue.
+pub const MAX_ADMISSION_WAIT: Duration = Duration::from_secs(10);
+
+/// Protection against reconnect storms after a restart, from the `[reconnect]` config
+/// section: clients are told to spread their reconnects over `jitter_secs`, and new
+/// connections are admitted at most `warmup_rate` per second for the first `warmup_secs`
+/// after startup.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReconnectConfig {
+    pub jitter_secs: u64,
+    /// Unpaced when unset.
+    pub warmup_rate: Option<f64>,
+    pub warmup_secs: u64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            jitter_secs: 10,
+            warmup_rate: Some(100.0),
+            warmup_secs: 60,
+        }
+    }
+}
+
+/// Admits new connections one at a time, evenly spaced, while the server is warming up.
+/// Each connection reserves the next free slot and waits for it, so a burst turns into a
+/// steady queue; connections that would wait longer than `MAX_ADMISSION_WAIT` are refused.
+#[derive(Debug)]
+pub struct AdmissionPacer {
+    /// The end of the warmup and the spacing between admissions; unpaced when absent.
+    pacing: Option<(Instant, Duration)>,
+    next_slot: Mutex<Instant>,
+}
+
+impl Default for AdmissionPacer {
+    fn default() -> Self {
+        Self {
+            pacing: None,
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl AdmissionPacer {
+    /// Starts a warmup that begins now.
+    pub fn new(config: &ReconnectConfig) -> Self {
+        let now = Instant::now();
+        let pacing = config
+            .warmup_rate
+            .filter(|rate| rate.is_finite() && *rate > 0.0 && config.warmup_secs > 0)
+            .map(|rate| {
+                let warmup_end = now + Duration::from_secs(config.warmup_secs);
+                (warmup_end, Duration::from_secs_f64(1.0 / rate))
+            });
+        Self {
+            pacing,
+            next_slot: Mutex::new(now),
+        }
+    }
+
+    /// How long a connection arriving now has to wait, reserving its slot, or `None` if
+    /// it would have to wait too long.
+    fn reserve(&self, now: Instant) -> Option<Duration> {
+        let Some((warmup_end, spacing)) = self.pacing else {
+            return Some(Duration::ZERO);
+        };
+        if now >= warmup_end {
+            return Some(Duration::ZERO);
+        }
+        let mut next_slot = self.next_slot.lock().unwrap();
+        let slot = (*next_slot).max(now);
+        let wait = slot - now;
+        if wait > MAX_ADMISSION_WAIT {
+            return None;
+        }
+        *next_slot = slot + spacing;
+        Some(wait)
+    }
+
+    /// Waits for a new connection's turn. Returns false if the connection should be
+    /// refused because the queue is too long.
+    pub async fn admit(&self) -> bool {
+        match self.reserve(Instant::now()) {
+            Some(Duration::ZERO) => true,
+            Some(wait) => {
+                tokio::time::sleep(wait).await;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connections_are_spaced_during_warmup_only() {
+        let pacer = AdmissionPacer::new(&ReconnectConfig {
+            jitter_secs: 0,
+            warmup_rate: Some(10.0),
+            warmup_secs: 60,
+        });
+        let now = Instant::now();
+        let waits: Vec<_> = (0..3).map(|_| pacer.reserve(now).unwrap()).collect();
+        assert_eq!(waits[0], Duration::ZERO);
+        assert_eq!(waits[1], Duration::from_millis(100));
+        assert_eq!(waits[2], Duration::from_millis(200));
+
+        // After the warmup nobody waits, however long the queue was.
+        assert_eq!(
+            pacer.reserve(now + Duration::from_secs(61)),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_connections_beyond_the_longest_wait_are_refused() {
+        let pacer = AdmissionPacer::new(&ReconnectConfig {
+            jitter_secs: 0,
+            warmup_rate: Some(1.0),
+            warmup_secs: 60,
+        });
+        let now = Instant::now();
+        let admitted = (0..20).filter(|_| pacer.reserve(now).is_some()).count();
+        assert_eq!(admitted as u64, MAX_ADMISSION_WAIT.as_secs() + 1);
+    }
+
+    #[test]
+    fn test_unpaced_without_rate() {
+        let pacer = AdmissionPacer::new(&ReconnectConfig {
+            warmup_rate: None,
+            ..ReconnectConfig::default()
+        });
+        let now = Instant::now();
+        assert!((0..1000).all(|_| pacer.reserve(now) == Some(Duration::ZERO)));
+    }
+}
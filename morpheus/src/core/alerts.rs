@@ -0,0 +1,430 @@
+use crate::{
+    cli::ui,
+    core::{
+        client_manager::ClientManager,
+        metrics::{Metric, Metrics},
+        msg::{Qos, ServerMessage},
+        provenance::{Origin, Provenance},
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, path::Path, str::FromStr, sync::Arc, time::Duration};
+use tokio::{task::JoinHandle, time::Instant};
+use uuid::Uuid;
+
+/// How often the rules are checked against fresh metrics.
+pub const EVALUATION_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How a metric is compared against a rule's threshold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Above,
+    AtLeast,
+    Below,
+    AtMost,
+}
+
+impl Comparison {
+    fn holds(self, value: usize, threshold: usize) -> bool {
+        match self {
+            Self::Above => value > threshold,
+            Self::AtLeast => value >= threshold,
+            Self::Below => value < threshold,
+            Self::AtMost => value <= threshold,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            Self::Above => ">",
+            Self::AtLeast => ">=",
+            Self::Below => "<",
+            Self::AtMost => "<=",
+        };
+        f.write_str(op)
+    }
+}
+
+/// When a rule triggers, written as `<metric> <op> <threshold> [for <duration>]`,
+/// e.g. `pending_acks > 1000 for 5m`. Durations take an `s`, `m` or `h` suffix.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Condition {
+    pub metric: Metric,
+    pub comparison: Comparison,
+    pub threshold: usize,
+    /// How long the comparison must hold before the rule fires.
+    pub duration: Duration,
+}
+
+impl Condition {
+    fn holds(&self, metrics: &Metrics) -> bool {
+        self.comparison
+            .holds(metrics.get(self.metric), self.threshold)
+    }
+}
+
+const CONDITION_SYNTAX: &str = "expected '<metric> <op> <threshold> [for <duration>]'";
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        let (metric, op, threshold, duration) = match parts.as_slice() {
+            [metric, op, threshold] => (metric, op, threshold, None),
+            [metric, op, threshold, "for", duration] => (metric, op, threshold, Some(duration)),
+            _ => return Err(format!("Invalid condition '{}' ({})", s, CONDITION_SYNTAX)),
+        };
+        let comparison = match *op {
+            ">" => Comparison::Above,
+            ">=" => Comparison::AtLeast,
+            "<" => Comparison::Below,
+            "<=" => Comparison::AtMost,
+            _ => return Err(format!("Unknown comparison '{}'", op)),
+        };
+        Ok(Self {
+            metric: metric.parse()?,
+            comparison,
+            threshold: threshold
+                .parse()
+                .map_err(|_| format!("Invalid threshold '{}'", threshold))?,
+            duration: duration.map_or(Ok(Duration::ZERO), |d| parse_duration(d))?,
+        })
+    }
+}
+
+impl TryFrom<String> for Condition {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.metric, self.comparison, self.threshold)?;
+        if !self.duration.is_zero() {
+            write!(f, " for {}", format_duration(self.duration))?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration '{}' (expected e.g. 30s, 5m or 1h)", s);
+    let split = s.len().checked_sub(1).ok_or_else(invalid)?;
+    let (amount, unit) = s.split_at(split);
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+    let seconds = match unit {
+        "s" => amount,
+        "m" => amount * 60,
+        "h" => amount * 3600,
+        _ => return Err(invalid()),
+    };
+    Ok(Duration::from_secs(seconds))
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        s if s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
+/// What happens when a rule fires.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AlertAction {
+    /// Print a warning on the server CLI.
+    Cli,
+    /// POST the alert as JSON to a URL.
+    Webhook { url: String },
+    /// Publish the alert as a message to a topic, e.g. an admin topic.
+    Topic { topic: String },
+}
+
+fn default_actions() -> Vec<AlertAction> {
+    vec![AlertAction::Cli]
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct AlertRule {
+    pub name: String,
+    pub when: Condition,
+    /// Defaults to a CLI warning.
+    #[serde(default = "default_actions")]
+    pub actions: Vec<AlertAction>,
+}
+
+/// The contents of an alert rules file.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct AlertConfig {
+    pub rules: Vec<AlertRule>,
+}
+
+impl AlertConfig {
+    /// Reads rules from a JSON file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&text).map_err(|e| format!("Invalid alert rules: {}", e))
+    }
+}
+
+/// A rule that fired, as reported to its actions.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Alert {
+    pub rule: String,
+    pub condition: String,
+    pub value: usize,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Alert '{}': {} (currently {})",
+            self.rule, self.condition, self.value
+        )
+    }
+}
+
+struct RuleState {
+    rule: AlertRule,
+    /// When the condition started holding, if it currently does.
+    breached_since: Option<Instant>,
+    fired: bool,
+}
+
+/// Evaluates alerting rules over server metrics. Each rule fires once when its
+/// condition has held for the configured duration, and re-arms once it clears.
+pub struct AlertEngine {
+    rules: Vec<RuleState>,
+}
+
+impl AlertEngine {
+    pub fn new(config: AlertConfig) -> Self {
+        let rules = config
+            .rules
+            .into_iter()
+            .map(|rule| RuleState {
+                rule,
+                breached_since: None,
+                fired: false,
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// Checks every rule against `metrics`, returning the rules that fire now.
+    pub fn evaluate(&mut self, metrics: &Metrics, now: Instant) -> Vec<(&AlertRule, Alert)> {
+        let mut fired = Vec::new();
+        for state in &mut self.rules {
+            if !state.rule.when.holds(metrics) {
+                state.breached_since = None;
+                state.fired = false;
+                continue;
+            }
+            let since = *state.breached_since.get_or_insert(now);
+            if !state.fired && now.duration_since(since) >= state.rule.when.duration {
+                state.fired = true;
+                let alert = Alert {
+                    rule: state.rule.name.clone(),
+                    condition: state.rule.when.to_string(),
+                    value: metrics.get(state.rule.when.metric),
+                };
+                fired.push((&state.rule, alert));
+            }
+        }
+        fired
+    }
+
+    /// Evaluates the rules every `EVALUATION_INTERVAL` and runs the actions of those that fire.
+    pub fn spawn(mut self, client_manager: Arc<ClientManager>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(EVALUATION_INTERVAL);
+            loop {
+                tick.tick().await;
+                let metrics = client_manager.metrics();
+                for (rule, alert) in self.evaluate(&metrics, Instant::now()) {
+                    for action in &rule.actions {
+                        run_action(action, &alert, &client_manager).await;
+                    }
+                }
+            }
+        })
+    }
+}
+
+async fn run_action(action: &AlertAction, alert: &Alert, client_manager: &ClientManager) {
+    match action {
+        AlertAction::Cli => ui::print_warning(&alert.to_string()),
+        AlertAction::Webhook { url } => {
+            // Don't let a slow endpoint hold up the other rules.
+            let url = url.clone();
+            let alert = alert.clone();
+            tokio::spawn(async move {
+                if let Err(e) = post_webhook(&url, &alert).await {
+                    eprintln!("Alert webhook {} failed: {}", url, e);
+                }
+            });
+        }
+        AlertAction::Topic { topic } => {
+            let message = ServerMessage::Topic {
+                id: Uuid::new_v4(),
+                topic: topic.clone(),
+                seq: 0,
+                sender: "Morpheus".to_string(),
+                content: alert.to_string(),
+                headers: BTreeMap::new(),
+                qos: Qos::FireAndForget,
+                reply_to: None,
+                retained: false,
+            };
+            client_manager
+                .broadcast_to_topic_with_provenance(
+                    topic,
+                    message,
+                    None,
+                    Provenance::new(Origin::Alert(alert.rule.clone())),
+                )
+                .await;
+        }
+    }
+}
+
+async fn post_webhook(url: &str, alert: &Alert) -> Result<(), String> {
+    let body = serde_json::to_string(alert).map_err(|e| e.to_string())?;
+    let request = hyper::Request::post(url)
+        .header("content-type", "application/json")
+        .body(hyper::Body::from(body))
+        .map_err(|e| e.to_string())?;
+    let response = hyper::Client::new()
+        .request(request)
+        .await
+        .map_err(|e| e.to_string())?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("server responded with {}", response.status()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(name: &str, when: &str) -> AlertRule {
+        AlertRule {
+            name: name.to_string(),
+            when: when.parse().unwrap(),
+            actions: default_actions(),
+        }
+    }
+
+    #[test]
+    fn test_parse_condition() {
+        let condition: Condition = "pending_acks > 1000 for 5m".parse().unwrap();
+        assert_eq!(
+            condition,
+            Condition {
+                metric: Metric::PendingAcks,
+                comparison: Comparison::Above,
+                threshold: 1000,
+                duration: Duration::from_secs(300),
+            }
+        );
+        assert_eq!(condition.to_string(), "pending_acks > 1000 for 5m");
+        assert_eq!(
+            "connected_clients < 1"
+                .parse::<Condition>()
+                .unwrap()
+                .duration,
+            Duration::ZERO
+        );
+        assert!("pending_acks > lots".parse::<Condition>().is_err());
+        assert!("pending_acks ~ 1".parse::<Condition>().is_err());
+        assert!("pending_acks > 1 for 5d".parse::<Condition>().is_err());
+        assert!("cpu > 1".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn test_config_deserializes_actions() {
+        let config: AlertConfig = serde_json::from_str(
+            r#"{"rules": [
+                {"name": "backlog", "when": "pending_acks > 1000 for 5m",
+                 "actions": [{"type": "webhook", "url": "http://localhost/hook"},
+                             {"type": "topic", "topic": "admin"}]},
+                {"name": "empty", "when": "connected_clients < 1"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.rules[0].actions,
+            vec![
+                AlertAction::Webhook {
+                    url: "http://localhost/hook".to_string()
+                },
+                AlertAction::Topic {
+                    topic: "admin".to_string()
+                },
+            ]
+        );
+        assert_eq!(config.rules[1].actions, vec![AlertAction::Cli]);
+    }
+
+    #[test]
+    fn test_rule_fires_after_duration_and_rearms() {
+        let mut engine = AlertEngine::new(AlertConfig {
+            rules: vec![rule("backlog", "pending_acks > 10 for 1m")],
+        });
+        let busy = Metrics {
+            pending_acks: 11,
+            ..Metrics::default()
+        };
+        let start = Instant::now();
+
+        assert!(engine.evaluate(&busy, start).is_empty());
+        assert!(engine
+            .evaluate(&busy, start + Duration::from_secs(30))
+            .is_empty());
+        let fired = engine.evaluate(&busy, start + Duration::from_secs(60));
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].1.value, 11);
+        // Fires once per breach.
+        assert!(engine
+            .evaluate(&busy, start + Duration::from_secs(90))
+            .is_empty());
+
+        assert!(engine
+            .evaluate(&Metrics::default(), start + Duration::from_secs(100))
+            .is_empty());
+        assert!(engine
+            .evaluate(&busy, start + Duration::from_secs(110))
+            .is_empty());
+        assert_eq!(
+            engine
+                .evaluate(&busy, start + Duration::from_secs(170))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_rule_without_duration_fires_immediately() {
+        let mut engine = AlertEngine::new(AlertConfig {
+            rules: vec![rule("empty", "connected_clients < 1")],
+        });
+        let fired = engine.evaluate(&Metrics::default(), Instant::now());
+        assert_eq!(
+            fired[0].1.to_string(),
+            "Alert 'empty': connected_clients < 1 (currently 0)"
+        );
+    }
+}
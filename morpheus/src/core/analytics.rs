@@ -0,0 +1,353 @@
+use crate::core::client_manager::ClientManager;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+
+/// The upper bounds of the size histogram buckets in bytes; larger messages fall into a
+/// final open-ended bucket.
+pub const SIZE_BUCKETS: [usize; 7] = [64, 256, 1024, 4096, 16384, 65536, 262144];
+/// How long one slot of the rolling window covers.
+pub const SLOT_LENGTH: Duration = Duration::from_secs(60);
+/// How many slots the rolling window keeps, i.e. the last hour.
+pub const WINDOW_SLOTS: usize = 60;
+/// How many messages may wait for the analytics task; messages beyond this are not
+/// sampled, so a burst costs publishers nothing.
+const MAX_PENDING_SAMPLES: usize = 10_000;
+/// How often the analytics task folds new samples into the window.
+const FOLD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The shape of a message's content.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ContentKind {
+    /// A JSON object or array.
+    Json,
+    Url,
+    Numeric,
+    Text,
+}
+
+impl ContentKind {
+    pub fn of(content: &str) -> Self {
+        let trimmed = content.trim();
+        if (trimmed.starts_with('{') || trimmed.starts_with('['))
+            && serde_json::from_str::<serde_json::Value>(trimmed).is_ok()
+        {
+            Self::Json
+        } else if !trimmed.contains(char::is_whitespace)
+            && (trimmed.starts_with("http://") || trimmed.starts_with("https://"))
+        {
+            Self::Url
+        } else if !trimmed.is_empty() && trimmed.parse::<f64>().is_ok() {
+            Self::Numeric
+        } else {
+            Self::Text
+        }
+    }
+}
+
+impl fmt::Display for ContentKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Json => "json",
+            Self::Url => "url",
+            Self::Numeric => "numeric",
+            Self::Text => "text",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Message sizes counted into the `SIZE_BUCKETS`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SizeHistogram {
+    counts: [u64; SIZE_BUCKETS.len() + 1],
+}
+
+impl SizeHistogram {
+    pub fn record(&mut self, bytes: usize) {
+        let bucket = SIZE_BUCKETS
+            .iter()
+            .position(|bound| bytes <= *bound)
+            .unwrap_or(SIZE_BUCKETS.len());
+        self.counts[bucket] += 1;
+    }
+
+    pub fn merge(&mut self, other: &SizeHistogram) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts) {
+            *count += other;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The number of messages per bucket, in the order of `SIZE_BUCKETS`.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// The upper bound of the bucket holding the `percentile`th smallest message, or
+    /// `None` if nothing was recorded or it is in the open-ended bucket.
+    pub fn percentile(&self, percentile: f64) -> Option<usize> {
+        let total = self.count();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((percentile / 100.0) * total as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return SIZE_BUCKETS.get(bucket).copied();
+            }
+        }
+        None
+    }
+}
+
+/// The sizes and kinds of content sampled from one topic, or from all of them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ContentProfile {
+    pub sizes: SizeHistogram,
+    pub kinds: BTreeMap<ContentKind, u64>,
+}
+
+impl ContentProfile {
+    fn record(&mut self, bytes: usize, kind: ContentKind) {
+        self.sizes.record(bytes);
+        *self.kinds.entry(kind).or_default() += 1;
+    }
+
+    fn merge(&mut self, other: &ContentProfile) {
+        self.sizes.merge(&other.sizes);
+        for (kind, count) in &other.kinds {
+            *self.kinds.entry(*kind).or_default() += count;
+        }
+    }
+
+    /// The kind most of the sampled messages have.
+    pub fn dominant_kind(&self) -> Option<ContentKind> {
+        self.kinds
+            .iter()
+            .max_by_key(|(_, count)| **count)
+            .map(|(kind, _)| *kind)
+    }
+}
+
+/// What the analytics task saw over its rolling window, shown by `/stats analytics`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AnalyticsReport {
+    /// How much time the samples cover, up to the full window.
+    pub covered: Duration,
+    pub overall: ContentProfile,
+    pub topics: BTreeMap<String, ContentProfile>,
+}
+
+impl AnalyticsReport {
+    /// The `percentile`th message size across all topics as a metric value: 0 before
+    /// anything was sampled and one more than the largest bucket bound for messages
+    /// beyond it.
+    pub fn size_metric(&self, percentile: f64) -> usize {
+        match self.overall.sizes.percentile(percentile) {
+            Some(bound) => bound,
+            None if self.overall.sizes.count() == 0 => 0,
+            None => SIZE_BUCKETS[SIZE_BUCKETS.len() - 1] + 1,
+        }
+    }
+}
+
+/// A topic message waiting to be sampled.
+#[derive(Clone, Debug)]
+struct Sample {
+    topic: String,
+    content: String,
+}
+
+/// Collects samples of published content for the analytics task and holds its latest
+/// report. Recording only queues the message, so publishers never wait for analysis.
+#[derive(Debug, Default)]
+pub struct ContentAnalytics {
+    pending: Mutex<VecDeque<Sample>>,
+    report: RwLock<Arc<AnalyticsReport>>,
+}
+
+impl ContentAnalytics {
+    /// Queues a published message for sampling, dropping it if the task is behind.
+    pub fn record(&self, topic: &str, content: &str) {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.len() < MAX_PENDING_SAMPLES {
+            pending.push_back(Sample {
+                topic: topic.to_string(),
+                content: content.to_string(),
+            });
+        }
+    }
+
+    /// The report published by the analytics task, empty until it first runs.
+    pub fn report(&self) -> Arc<AnalyticsReport> {
+        self.report.read().unwrap().clone()
+    }
+
+    fn take_pending(&self) -> Vec<Sample> {
+        self.pending.lock().unwrap().drain(..).collect()
+    }
+
+    fn publish(&self, report: AnalyticsReport) {
+        *self.report.write().unwrap() = Arc::new(report);
+    }
+}
+
+/// The per-topic profiles of one slot of the rolling window.
+struct Slot {
+    started: Instant,
+    topics: BTreeMap<String, ContentProfile>,
+}
+
+/// Per-topic content profiles over the last `WINDOW_SLOTS` slots.
+struct RollingWindow {
+    slots: VecDeque<Slot>,
+}
+
+impl RollingWindow {
+    fn new() -> Self {
+        Self {
+            slots: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self, now: Instant, sample: &Sample) {
+        let fresh = self
+            .slots
+            .back()
+            .is_some_and(|slot| now.duration_since(slot.started) < SLOT_LENGTH);
+        if !fresh {
+            self.slots.push_back(Slot {
+                started: now,
+                topics: BTreeMap::new(),
+            });
+        }
+        let slot = self.slots.back_mut().unwrap();
+        slot.topics
+            .entry(sample.topic.clone())
+            .or_default()
+            .record(sample.content.len(), ContentKind::of(&sample.content));
+    }
+
+    /// Drops the slots that have left the window.
+    fn expire(&mut self, now: Instant) {
+        let window = SLOT_LENGTH * WINDOW_SLOTS as u32;
+        while self
+            .slots
+            .front()
+            .is_some_and(|slot| now.duration_since(slot.started) >= window)
+        {
+            self.slots.pop_front();
+        }
+    }
+
+    fn report(&self, now: Instant) -> AnalyticsReport {
+        let mut report = AnalyticsReport {
+            covered: self
+                .slots
+                .front()
+                .map_or(Duration::ZERO, |slot| now.duration_since(slot.started)),
+            ..AnalyticsReport::default()
+        };
+        for slot in &self.slots {
+            for (topic, profile) in &slot.topics {
+                report.overall.merge(profile);
+                report
+                    .topics
+                    .entry(topic.clone())
+                    .or_default()
+                    .merge(profile);
+            }
+        }
+        report
+    }
+}
+
+/// Folds the messages published since the last run into the rolling window every second
+/// and publishes a fresh report.
+pub fn spawn(client_manager: Arc<ClientManager>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut window = RollingWindow::new();
+        let mut tick = tokio::time::interval(FOLD_INTERVAL);
+        loop {
+            tick.tick().await;
+            let analytics = client_manager.content_analytics();
+            let now = Instant::now();
+            for sample in analytics.take_pending() {
+                window.record(now, &sample);
+            }
+            window.expire(now);
+            analytics.publish(window.report(now));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(topic: &str, content: &str) -> Sample {
+        Sample {
+            topic: topic.to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_content_kind() {
+        assert_eq!(ContentKind::of(r#"{"order": 1}"#), ContentKind::Json);
+        assert_eq!(ContentKind::of("[1, 2]"), ContentKind::Json);
+        assert_eq!(ContentKind::of("{not json"), ContentKind::Text);
+        assert_eq!(ContentKind::of("https://example.com/a"), ContentKind::Url);
+        assert_eq!(ContentKind::of(" 42.5 "), ContentKind::Numeric);
+        assert_eq!(ContentKind::of("hello there"), ContentKind::Text);
+    }
+
+    #[test]
+    fn test_histogram_percentiles() {
+        let mut sizes = SizeHistogram::default();
+        assert_eq!(sizes.percentile(50.0), None);
+        for bytes in [10, 20, 30, 100, 5000] {
+            sizes.record(bytes);
+        }
+        assert_eq!(sizes.count(), 5);
+        assert_eq!(sizes.percentile(50.0), Some(64));
+        assert_eq!(sizes.percentile(80.0), Some(256));
+        assert_eq!(sizes.percentile(100.0), Some(16384));
+        sizes.record(1_000_000);
+        assert_eq!(sizes.percentile(100.0), None);
+    }
+
+    #[test]
+    fn test_window_rolls_over() {
+        let mut window = RollingWindow::new();
+        let start = Instant::now();
+        window.record(start, &sample("orders", r#"{"id": 1}"#));
+        window.record(start + SLOT_LENGTH, &sample("chat", "hi"));
+        window.record(start + SLOT_LENGTH, &sample("chat", "hello"));
+
+        let report = window.report(start + SLOT_LENGTH);
+        assert_eq!(report.overall.sizes.count(), 3);
+        assert_eq!(
+            report.topics["chat"].dominant_kind(),
+            Some(ContentKind::Text)
+        );
+        assert_eq!(report.topics["orders"].kinds[&ContentKind::Json], 1);
+
+        // The first slot leaves the window an hour after it started.
+        let later = start + SLOT_LENGTH * WINDOW_SLOTS as u32;
+        window.expire(later);
+        let report = window.report(later);
+        assert!(!report.topics.contains_key("orders"));
+        assert_eq!(report.overall.sizes.count(), 2);
+    }
+}
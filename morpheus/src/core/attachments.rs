@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// The bytes each binary chunk frame starts with: the attachment ID and the chunk index.
+pub const CHUNK_HEADER_LEN: usize = 20;
+
+/// Encodes a chunk of an attachment as the binary frame it is sent in.
+pub fn encode_chunk(id: Uuid, index: u32, data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(CHUNK_HEADER_LEN + data.len());
+    frame.extend_from_slice(id.as_bytes());
+    frame.extend_from_slice(&index.to_be_bytes());
+    frame.extend_from_slice(data);
+    frame
+}
+
+/// Splits a binary chunk frame into the attachment ID, the chunk index and its bytes, or
+/// returns `None` if it is too short to be one.
+pub fn decode_chunk(frame: &[u8]) -> Option<(Uuid, u32, &[u8])> {
+    if frame.len() < CHUNK_HEADER_LEN {
+        return None;
+    }
+    let (header, data) = frame.split_at(CHUNK_HEADER_LEN);
+    let id = Uuid::from_slice(&header[..16]).ok()?;
+    let index = u32::from_be_bytes(header[16..].try_into().ok()?);
+    Some((id, index, data))
+}
+
+/// Why a chunk or completion was not accepted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChunkError {
+    /// The ID names no attachment the connection has open.
+    Unknown(Uuid),
+    /// The attachment broke the rules and was dropped; its topic must be told.
+    Aborted { topic: String, reason: String },
+}
+
+#[derive(Debug)]
+struct Upload {
+    topic: String,
+    size: u64,
+    received: u64,
+    next_index: u32,
+}
+
+/// The attachments a single connection is sending, which must arrive in order and
+/// within the size they were announced with.
+#[derive(Debug, Default)]
+pub struct Uploads {
+    open: HashMap<Uuid, Upload>,
+}
+
+impl Uploads {
+    /// Opens an attachment of `size` bytes for `topic`, refused if it is larger than
+    /// `max` bytes or its ID is already in use.
+    pub fn start(&mut self, id: Uuid, topic: String, size: u64, max: usize) -> Result<(), String> {
+        if max == 0 {
+            return Err("Attachments are disabled on this server".to_string());
+        }
+        if size > max as u64 {
+            return Err(format!(
+                "Attachment too large: {} bytes (limit {} bytes)",
+                size, max
+            ));
+        }
+        if self.open.contains_key(&id) {
+            return Err(format!("Attachment {} is already being sent", id));
+        }
+        self.open.insert(
+            id,
+            Upload {
+                topic,
+                size,
+                received: 0,
+                next_index: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Accepts chunk `index` of `len` bytes and returns the topic to relay it to.
+    pub fn chunk(&mut self, id: Uuid, index: u32, len: usize) -> Result<&str, ChunkError> {
+        let upload = self.open.get_mut(&id).ok_or(ChunkError::Unknown(id))?;
+        let reason = if index != upload.next_index {
+            format!(
+                "chunk {} arrived when chunk {} was expected",
+                index, upload.next_index
+            )
+        } else if upload.received + len as u64 > upload.size {
+            format!("more than the announced {} bytes were sent", upload.size)
+        } else {
+            upload.received += len as u64;
+            upload.next_index += 1;
+            return Ok(&self.open[&id].topic);
+        };
+        Err(self.abort(id, reason))
+    }
+
+    /// Closes an attachment all of whose bytes arrived and returns its topic.
+    pub fn complete(&mut self, id: Uuid) -> Result<String, ChunkError> {
+        let upload = self.open.get(&id).ok_or(ChunkError::Unknown(id))?;
+        if upload.received < upload.size {
+            let reason = format!(
+                "completed after {} of {} bytes",
+                upload.received, upload.size
+            );
+            return Err(self.abort(id, reason));
+        }
+        Ok(self
+            .open
+            .remove(&id)
+            .map(|upload| upload.topic)
+            .unwrap_or_default())
+    }
+
+    /// Drops every open attachment, e.g. when the connection closes, returning their IDs
+    /// and topics.
+    pub fn abort_all(&mut self) -> Vec<(Uuid, String)> {
+        self.open
+            .drain()
+            .map(|(id, upload)| (id, upload.topic))
+            .collect()
+    }
+
+    fn abort(&mut self, id: Uuid, reason: String) -> ChunkError {
+        let topic = self.open.remove(&id).map(|upload| upload.topic);
+        ChunkError::Aborted {
+            topic: topic.unwrap_or_default(),
+            reason,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_frames_round_trip() {
+        let id = Uuid::new_v4();
+        let frame = encode_chunk(id, 7, b"bytes");
+        assert_eq!(frame.len(), CHUNK_HEADER_LEN + 5);
+        assert_eq!(decode_chunk(&frame), Some((id, 7, &b"bytes"[..])));
+        assert_eq!(decode_chunk(&frame[..CHUNK_HEADER_LEN - 1]), None);
+    }
+
+    #[test]
+    fn test_uploads_must_arrive_in_order_and_within_size() {
+        let mut uploads = Uploads::default();
+        let id = Uuid::new_v4();
+        assert!(uploads.start(id, "files".to_string(), 100, 10).is_err());
+        uploads.start(id, "files".to_string(), 6, 10).unwrap();
+        assert!(uploads.start(id, "files".to_string(), 6, 10).is_err());
+
+        assert_eq!(uploads.chunk(id, 0, 4), Ok("files"));
+        assert!(matches!(
+            uploads.chunk(id, 2, 2),
+            Err(ChunkError::Aborted { topic, .. }) if topic == "files"
+        ));
+        assert_eq!(uploads.chunk(id, 1, 2), Err(ChunkError::Unknown(id)));
+
+        uploads.start(id, "files".to_string(), 6, 10).unwrap();
+        assert_eq!(uploads.chunk(id, 0, 6), Ok("files"));
+        assert!(matches!(
+            uploads.chunk(id, 1, 1),
+            Err(ChunkError::Aborted { .. })
+        ));
+
+        uploads.start(id, "files".to_string(), 6, 10).unwrap();
+        assert!(matches!(
+            uploads.complete(id),
+            Err(ChunkError::Aborted { .. })
+        ));
+        uploads.start(id, "files".to_string(), 6, 10).unwrap();
+        uploads.chunk(id, 0, 6).unwrap();
+        assert_eq!(uploads.complete(id), Ok("files".to_string()));
+        assert!(uploads.abort_all().is_empty());
+    }
+}
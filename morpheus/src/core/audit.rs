@@ -0,0 +1,200 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tracing::warn;
+
+/// Whether an operator action did what was asked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Succeeded,
+    Failed,
+}
+
+/// One operator action, written to the audit log as a line of JSON.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    /// The command, e.g. `kick` or `reload`.
+    pub action: String,
+    /// What the action was applied to.
+    pub detail: String,
+    pub outcome: Outcome,
+    /// What came of it: who was reached, or why it failed.
+    pub result: String,
+}
+
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = match self.outcome {
+            Outcome::Succeeded => "ok",
+            Outcome::Failed => "FAILED",
+        };
+        write!(
+            f,
+            "{} {} {} [{}] {}",
+            self.timestamp.to_rfc3339(),
+            self.action,
+            self.detail,
+            outcome,
+            self.result
+        )
+    }
+}
+
+/// Every administrative action taken on the server, appended to a file that is never
+/// rewritten or rotated, unlike the message log.
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    path: Option<PathBuf>,
+    file: Mutex<Option<File>>,
+}
+
+impl AuditLog {
+    /// Opens the audit log at `path` for appending, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open audit log {}: {}", path.display(), e))?;
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            file: Mutex::new(Some(file)),
+        })
+    }
+
+    /// Records `action` on `detail`; `result` describes what it achieved or why it failed.
+    /// The action is not undone if it cannot be recorded, so that is only logged.
+    pub fn record(&self, action: &str, detail: &str, result: Result<String, String>) {
+        let (outcome, result) = match result {
+            Ok(result) => (Outcome::Succeeded, result),
+            Err(reason) => (Outcome::Failed, reason),
+        };
+        let entry = AuditEntry {
+            timestamp: Utc::now(),
+            action: action.to_string(),
+            detail: detail.to_string(),
+            outcome,
+            result,
+        };
+        let mut file = self.file.lock().unwrap();
+        let Some(file) = file.as_mut() else {
+            return;
+        };
+        let written = serde_json::to_string(&entry)
+            .map_err(std::io::Error::from)
+            .and_then(|line| writeln!(file, "{}", line))
+            .and_then(|_| file.flush());
+        if let Err(e) = written {
+            warn!("Failed to record {} in the audit log: {}", action, e);
+        }
+    }
+
+    /// The last `count` entries, oldest first.
+    pub fn tail(&self, count: usize) -> Result<Vec<AuditEntry>, String> {
+        let Some(path) = &self.path else {
+            return Ok(Vec::new());
+        };
+        let file =
+            File::open(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut entries = VecDeque::with_capacity(count);
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            let entry = serde_json::from_str(&line)
+                .map_err(|e| format!("Invalid audit log entry in {}: {}", path.display(), e))?;
+            if entries.len() == count {
+                entries.pop_front();
+            }
+            if count > 0 {
+                entries.push_back(entry);
+            }
+        }
+        Ok(entries.into())
+    }
+
+    /// Replaces `needle` with `replacement` in every entry of the audit log at `path`,
+    /// keeping the entries themselves. This is the one time the log is rewritten, to
+    /// honour an erasure request; the server must not be running. Returns how many
+    /// entries changed.
+    pub fn redact(path: &Path, needle: &str, replacement: &str) -> Result<usize, String> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        let mut redacted = 0;
+        let mut lines = Vec::new();
+        for line in text.lines() {
+            let mut entry: AuditEntry = serde_json::from_str(line)
+                .map_err(|e| format!("Invalid audit log entry in {}: {}", path.display(), e))?;
+            if entry.detail.contains(needle) || entry.result.contains(needle) {
+                entry.detail = entry.detail.replace(needle, replacement);
+                entry.result = entry.result.replace(needle, replacement);
+                redacted += 1;
+            }
+            lines.push(serde_json::to_string(&entry).map_err(|e| e.to_string())?);
+        }
+        if redacted > 0 {
+            let temp = path.with_extension("tmp");
+            std::fs::write(&temp, lines.join("\n") + "\n")
+                .and_then(|_| std::fs::rename(&temp, path))
+                .map_err(|e| format!("Failed to save {}: {}", path.display(), e))?;
+        }
+        Ok(redacted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_are_appended_across_restarts() {
+        let path =
+            std::env::temp_dir().join(format!("morpheus-audit-{}.log", uuid::Uuid::new_v4()));
+        let audit = AuditLog::open(&path).unwrap();
+        audit.record("kick", "c1", Ok("Kicked".to_string()));
+        audit.record("ban", "10.0.0.1", Err("Disk full".to_string()));
+        drop(audit);
+
+        let audit = AuditLog::open(&path).unwrap();
+        audit.record("reload", "morpheus.toml", Ok("Nothing changed".to_string()));
+        let entries = audit.tail(2).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].action, "ban");
+        assert_eq!(entries[0].outcome, Outcome::Failed);
+        assert_eq!(entries[0].result, "Disk full");
+        assert_eq!(entries[1].action, "reload");
+        assert_eq!(entries[1].outcome, Outcome::Succeeded);
+        assert_eq!(audit.tail(10).unwrap().len(), 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_redaction_keeps_entries() {
+        let path =
+            std::env::temp_dir().join(format!("morpheus-audit-{}.log", uuid::Uuid::new_v4()));
+        let audit = AuditLog::open(&path).unwrap();
+        audit.record("kick", "c1", Ok("Kicked c1".to_string()));
+        audit.record("ban", "c2", Ok("Banned".to_string()));
+        drop(audit);
+
+        assert_eq!(AuditLog::redact(&path, "c1", "[forgotten]").unwrap(), 1);
+        let audit = AuditLog::open(&path).unwrap();
+        let entries = audit.tail(10).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].action, "kick");
+        assert_eq!(entries[0].detail, "[forgotten]");
+        assert_eq!(entries[0].result, "Kicked [forgotten]");
+        assert_eq!(entries[1].detail, "c2");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
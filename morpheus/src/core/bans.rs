@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    fmt,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::RwLock,
+};
+use uuid::Uuid;
+
+/// Someone the operator banned: a client ID or every connection from an address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum BanTarget {
+    Client(Uuid),
+    Ip(IpAddr),
+}
+
+impl FromStr for BanTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(client_id) = Uuid::parse_str(s) {
+            Ok(BanTarget::Client(client_id))
+        } else if let Ok(ip) = s.parse() {
+            Ok(BanTarget::Ip(ip))
+        } else {
+            Err(format!("Not a client ID or IP address: {}", s))
+        }
+    }
+}
+
+impl TryFrom<String> for BanTarget {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<BanTarget> for String {
+    fn from(target: BanTarget) -> Self {
+        target.to_string()
+    }
+}
+
+impl fmt::Display for BanTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BanTarget::Client(client_id) => write!(f, "{}", client_id),
+            BanTarget::Ip(ip) => write!(f, "{}", ip),
+        }
+    }
+}
+
+/// The banned clients and addresses, saved as a JSON array after every change so
+/// bans survive a restart.
+#[derive(Debug, Default)]
+pub struct BanList {
+    path: Option<PathBuf>,
+    targets: RwLock<BTreeSet<BanTarget>>,
+}
+
+impl BanList {
+    /// Reads the ban list saved at `path`, starting empty if the file does not exist yet.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let targets = match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| format!("Invalid ban list {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            targets: RwLock::new(targets),
+        })
+    }
+
+    pub fn is_banned(&self, target: &BanTarget) -> bool {
+        self.targets.read().unwrap().contains(target)
+    }
+
+    /// Adds a ban. Returns false if the target was already banned.
+    pub fn ban(&self, target: BanTarget) -> Result<bool, String> {
+        let mut targets = self.targets.write().unwrap();
+        if !targets.insert(target) {
+            return Ok(false);
+        }
+        self.save(&targets)?;
+        Ok(true)
+    }
+
+    /// Lifts a ban. Returns false if the target was not banned.
+    pub fn unban(&self, target: &BanTarget) -> Result<bool, String> {
+        let mut targets = self.targets.write().unwrap();
+        if !targets.remove(target) {
+            return Ok(false);
+        }
+        self.save(&targets)?;
+        Ok(true)
+    }
+
+    pub fn list(&self) -> Vec<BanTarget> {
+        self.targets.read().unwrap().iter().copied().collect()
+    }
+
+    /// Writes the list to a temporary file first so a crash never leaves it half written.
+    fn save(&self, targets: &BTreeSet<BanTarget>) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let text = serde_json::to_string_pretty(targets).map_err(|e| e.to_string())?;
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, text)
+            .and_then(|_| std::fs::rename(&temp, path))
+            .map_err(|e| format!("Failed to save {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ban_target() {
+        let client_id = Uuid::new_v4();
+        assert_eq!(
+            client_id.to_string().parse(),
+            Ok(BanTarget::Client(client_id))
+        );
+        assert_eq!(
+            "10.0.0.1".parse(),
+            Ok(BanTarget::Ip("10.0.0.1".parse().unwrap()))
+        );
+        assert!("nobody".parse::<BanTarget>().is_err());
+    }
+
+    #[test]
+    fn test_bans_survive_reload() {
+        let path = std::env::temp_dir().join(format!("morpheus-bans-{}.json", Uuid::new_v4()));
+        let client_id = Uuid::new_v4();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let bans = BanList::load(&path).unwrap();
+        assert!(bans.list().is_empty());
+        assert_eq!(bans.ban(BanTarget::Client(client_id)), Ok(true));
+        assert_eq!(bans.ban(BanTarget::Ip(ip)), Ok(true));
+        assert_eq!(bans.ban(BanTarget::Ip(ip)), Ok(false));
+
+        let reloaded = BanList::load(&path).unwrap();
+        assert!(reloaded.is_banned(&BanTarget::Client(client_id)));
+        assert!(reloaded.is_banned(&BanTarget::Ip(ip)));
+        assert!(!reloaded.is_banned(&BanTarget::Client(Uuid::new_v4())));
+        assert_eq!(reloaded.unban(&BanTarget::Ip(ip)), Ok(true));
+        assert_eq!(reloaded.unban(&BanTarget::Ip(ip)), Ok(false));
+
+        assert_eq!(
+            BanList::load(&path).unwrap().list(),
+            vec![BanTarget::Client(client_id)]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+use crate::{
+    cli::ui,
+    core::{
+        client_manager::ClientManager,
+        msg::{self, ClientMessage, Qos, ServerMessage},
+    },
+};
+use futures_util::{SinkExt, StreamExt};
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{net::TcpStream, task::JoinHandle};
+use tokio_tungstenite::{
+    connect_async_tls_with_config, tungstenite::Message, Connector, MaybeTlsStream, WebSocketStream,
+};
+use uuid::Uuid;
+
+/// The topic the canary publishes its probes to.
+pub const CANARY_TOPIC: &str = "$canary";
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// How often the canary probes the server and how long a probe may take.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CanaryConfig {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for CanaryConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// The outcome of the most recent probes, exported through `ClientManager::metrics`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CanaryStatus {
+    /// The round trip time of the last successful probe.
+    pub latency: Option<Duration>,
+    /// How many probes in a row have failed.
+    pub consecutive_failures: usize,
+}
+
+/// The URL at which this server's own clients connect, for a server bound to `addr` that
+/// serves the client WebSocket at `ws_path`.
+pub fn loopback_url(addr: SocketAddr, tls: bool, ws_path: &str, token: Option<&str>) -> String {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    let scheme = if tls { "wss" } else { "ws" };
+    let mut url = format!(
+        "{}://{}/{}",
+        scheme,
+        SocketAddr::new(ip, addr.port()),
+        ws_path.trim_matches('/')
+    );
+    if let Some(token) = token {
+        url.push_str("?token=");
+        url.push_str(token);
+    }
+    url
+}
+
+/// A synthetic client that publishes a probe through the server's WebSocket endpoint and
+/// times how long it takes to come back on a second connection.
+pub struct Canary {
+    url: String,
+    config: CanaryConfig,
+    /// The publishing and subscribed connections, kept open between probes.
+    links: Option<(Socket, Socket)>,
+}
+
+impl Canary {
+    pub fn new(url: String, config: CanaryConfig) -> Self {
+        Self {
+            url,
+            config,
+            links: None,
+        }
+    }
+
+    /// Sends one probe, returning its round trip time. Connections are reopened on the
+    /// next probe after a failure.
+    pub async fn probe(&mut self) -> Result<Duration, String> {
+        let result = tokio::time::timeout(self.config.timeout, self.round_trip())
+            .await
+            .unwrap_or_else(|_| Err("timed out".to_string()));
+        if result.is_err() {
+            self.links = None;
+        }
+        result
+    }
+
+    /// Probes the server every interval, recording the results in the client manager
+    /// and warning when probes start failing.
+    pub fn spawn(mut self, client_manager: Arc<ClientManager>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let interval = self.config.interval;
+            let mut tick =
+                tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+            loop {
+                tick.tick().await;
+                let mut status = client_manager.canary_status();
+                match self.probe().await {
+                    Ok(latency) => {
+                        if status.consecutive_failures > 0 {
+                            ui::print_system_message(&format!(
+                                "Canary recovered after {} failed probe(s).",
+                                status.consecutive_failures
+                            ));
+                        }
+                        status = CanaryStatus {
+                            latency: Some(latency),
+                            consecutive_failures: 0,
+                        };
+                    }
+                    Err(e) => {
+                        status.consecutive_failures += 1;
+                        if status.consecutive_failures == 1 {
+                            ui::print_warning(&format!("Canary probe failed: {}", e));
+                        }
+                    }
+                }
+                client_manager.set_canary_status(status);
+            }
+        })
+    }
+
+    async fn round_trip(&mut self) -> Result<Duration, String> {
+        let links = match self.links.take() {
+            Some(links) => links,
+            None => self.connect().await?,
+        };
+        let (publisher, subscriber) = self.links.insert(links);
+
+        let probe_id = Uuid::new_v4().to_string();
+        let started = Instant::now();
+        let probe = ClientMessage::Message {
+            topic: CANARY_TOPIC.to_string(),
+            content: probe_id.clone(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            id: None,
+            reply_to: None,
+            retain: false,
+        };
+        send(publisher, &probe).await?;
+        loop {
+            tokio::select! {
+                // Reading the publisher lets its pings be answered and surfaces rejections.
+                frame = publisher.next() => {
+                    if let ServerMessage::Error { message } = read(frame)? {
+                        return Err(message);
+                    }
+                }
+                frame = subscriber.next() => match read(frame)? {
+                    ServerMessage::Topic { id, content, .. } if content == probe_id => {
+                        send(subscriber, &ClientMessage::MessageReceived { msg_id: id }).await?;
+                        return Ok(started.elapsed());
+                    }
+                    ServerMessage::LimitExceeded { .. } | ServerMessage::Kicked { .. } => {
+                        return Err("the server refused the canary".to_string());
+                    }
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    /// Opens both connections. The subscriber uses a session so that the server's
+    /// `Welcome` confirms the subscription before the first probe is sent.
+    async fn connect(&self) -> Result<(Socket, Socket), String> {
+        let publisher = self.open().await?;
+        let mut subscriber = self.open().await?;
+        let subscribe = ClientMessage::ConnectSession {
+            topic: CANARY_TOPIC.to_string(),
+            session_id: None,
+        };
+        send(&mut subscriber, &subscribe).await?;
+        loop {
+            match read(subscriber.next().await)? {
+                ServerMessage::Welcome { .. } => return Ok((publisher, subscriber)),
+                ServerMessage::LimitExceeded { .. } | ServerMessage::Kicked { .. } => {
+                    return Err("the server refused the canary".to_string());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    async fn open(&self) -> Result<Socket, String> {
+        // The server's certificate is issued for its public name, not the loopback address.
+        let tls = native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let connector = Some(Connector::NativeTls(tls));
+        let (socket, _) = connect_async_tls_with_config(self.url.as_str(), None, false, connector)
+            .await
+            .map_err(|e| format!("failed to connect: {}", e))?;
+        Ok(socket)
+    }
+}
+
+async fn send(socket: &mut Socket, message: &ClientMessage) -> Result<(), String> {
+    let text = msg::encode(message).map_err(|e| e.to_string())?;
+    socket
+        .send(Message::Text(text))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Decodes a frame, treating anything but a server message as `Unknown`.
+fn read(
+    frame: Option<Result<Message, tokio_tungstenite::tungstenite::Error>>,
+) -> Result<ServerMessage, String> {
+    match frame {
+        Some(Ok(Message::Text(text))) => Ok(msg::decode(&text).unwrap_or(ServerMessage::Unknown)),
+        Some(Ok(Message::Close(_))) | None => Err("connection closed".to_string()),
+        Some(Ok(_)) => Ok(ServerMessage::Unknown),
+        Some(Err(e)) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loopback_url() {
+        assert_eq!(
+            loopback_url("0.0.0.0:8080".parse().unwrap(), false, "/ws", None),
+            "ws://127.0.0.1:8080/ws"
+        );
+        assert_eq!(
+            loopback_url("[::]:443".parse().unwrap(), true, "/ws", Some("secret")),
+            "wss://[::1]:443/ws?token=secret"
+        );
+        assert_eq!(
+            loopback_url(
+                "10.0.0.5:9000".parse().unwrap(),
+                false,
+                "chat/socket/",
+                None
+            ),
+            "ws://10.0.0.5:9000/chat/socket"
+        );
+    }
+}
@@ -1,49 +1,379 @@
-use crate::{
-    cli::ui,
-    core::{
-        msg::ServerMessage,
-        storage::{Client, Storage},
+use crate::core::{
+    admission::{AdmissionPacer, ReconnectConfig},
+    analytics::ContentAnalytics,
+    audit::AuditLog,
+    bans::{BanList, BanTarget},
+    canary::CanaryStatus,
+    cluster::Cluster,
+    compression::{CompressionConfig, CompressionHandle},
+    consumer_groups::{ConsumerGroups, GroupOffsets, MAX_FETCH_BATCH},
+    fanout::{self, Delivered, Recipient},
+    forwards::Forwards,
+    history::{HistoryHooks, HistoryStore, InMemoryHistory, StoredMessage},
+    hooks::MessageHooks,
+    identities::{ConnectionHistory, Whois},
+    latency::BroadcastLatency,
+    membership::Membership,
+    metrics::Metrics,
+    msg::Limit,
+    msg::{CloseCode, Outgoing, ServerMessage, TopicSummary, NAME_HEADER},
+    policy::{Delivery, LimitExceeded, Policies, Role},
+    polls::{Poll, Polls},
+    provenance::{Origin, Provenance},
+    qos::{QosTracker, Unacked},
+    queue::{self, QueueConfig},
+    receipt::{AckRegistry, BroadcastReceipt, Readers},
+    requests::{PendingRequests, REQUEST_TIMEOUT},
+    stats::{ServerCounters, ServerStats, TopicCounters, TopicStats},
+    storage::{Client, CloseHandle, Session, Storage},
+    verbs::Verbs,
+    work_queue::{InFlight, WorkQueue, MAX_DELIVERY_ATTEMPTS},
+};
+use chrono::Utc;
+use dashmap::DashMap;
+use futures_util::{stream::SplitSink, Sink, SinkExt};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
     },
+    time::{Duration, Instant},
 };
-use futures_util::{stream::SplitSink, SinkExt};
-use std::sync::Arc;
-use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 use warp::ws::{Message, WebSocket};
 
+/// How long a disconnected client's session can be resumed.
+pub const SESSION_TTL: Duration = Duration::from_secs(300);
+
+/// The reason sent to banned clients when they are disconnected.
+pub const BANNED_REASON: &str = "You are banned from this server";
+
+/// How often a draining server checks whether its last client has left.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Whether the server is draining and how many clients it still has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DrainStatus {
+    pub draining: bool,
+    pub clients: usize,
+}
+
+/// Server-side WebSocket keepalive settings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Heartbeat {
+    /// How often a ping is sent to every client.
+    pub interval: Duration,
+    /// How long after a missed ping a silent client is considered dead.
+    pub timeout: Duration,
+}
+
+impl Heartbeat {
+    /// How long a connection may go without any incoming frame before it is reaped.
+    pub fn idle_deadline(&self) -> Duration {
+        self.interval + self.timeout
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
 /// A manager for clients that uses a generic storage backend.
 pub struct ClientManager {
     storage: Arc<dyn Storage>,
+    history: Arc<dyn HistoryStore>,
+    history_hooks: HistoryHooks,
+    message_hooks: MessageHooks,
+    verbs: Verbs,
+    heartbeat: Heartbeat,
+    queue_config: QueueConfig,
+    cluster: Option<Arc<Cluster>>,
+    policies: RwLock<Arc<Policies>>,
+    motd: RwLock<Option<String>>,
+    acks: AckRegistry,
+    readers: Readers,
+    /// The name each client last signed a message with, in its `NAME_HEADER`.
+    nicknames: DashMap<Uuid, String>,
+    bans: BanList,
+    connection_history: ConnectionHistory,
+    audit: AuditLog,
+    membership: Membership,
+    canary: RwLock<CanaryStatus>,
+    topic_stats: TopicCounters,
+    counters: ServerCounters,
+    content_analytics: ContentAnalytics,
+    broadcast_latency: BroadcastLatency,
+    work: WorkQueue,
+    qos: QosTracker,
+    consumer_groups: ConsumerGroups,
+    polls: Polls,
+    forwards: Forwards,
+    requests: PendingRequests,
+    admission: AdmissionPacer,
+    /// The window clients are told to spread their reconnects over.
+    reconnect_jitter: Duration,
+    compression: CompressionConfig,
+    /// Set once `/drain` was used; new WebSocket connections are refused from then on.
+    draining: AtomicBool,
 }
 
 impl ClientManager {
     /// Creates a new `ClientManager` with the given storage backend.
     pub fn new(storage: Arc<dyn Storage>) -> Self {
-        Self { storage }
+        Self::with_history(storage, Arc::new(InMemoryHistory::default()))
+    }
+
+    /// Creates a new `ClientManager` with the given storage and history backends.
+    pub fn with_history(storage: Arc<dyn Storage>, history: Arc<dyn HistoryStore>) -> Self {
+        Self {
+            storage,
+            history,
+            history_hooks: HistoryHooks::default(),
+            message_hooks: MessageHooks::default(),
+            verbs: Verbs::default(),
+            heartbeat: Heartbeat::default(),
+            queue_config: QueueConfig::default(),
+            cluster: None,
+            policies: RwLock::default(),
+            motd: RwLock::default(),
+            acks: AckRegistry::default(),
+            readers: Readers::default(),
+            nicknames: DashMap::new(),
+            bans: BanList::default(),
+            connection_history: ConnectionHistory::default(),
+            audit: AuditLog::default(),
+            membership: Membership::default(),
+            canary: RwLock::default(),
+            topic_stats: TopicCounters::default(),
+            counters: ServerCounters::default(),
+            content_analytics: ContentAnalytics::default(),
+            broadcast_latency: BroadcastLatency::default(),
+            work: WorkQueue::default(),
+            qos: QosTracker::default(),
+            consumer_groups: ConsumerGroups::default(),
+            polls: Polls::default(),
+            forwards: Forwards::default(),
+            requests: PendingRequests::default(),
+            admission: AdmissionPacer::default(),
+            reconnect_jitter: Duration::ZERO,
+            compression: CompressionConfig::default(),
+            draining: AtomicBool::new(false),
+        }
+    }
+
+    /// Makes this manager a cluster node, forwarding local topic messages to its peers.
+    pub fn with_cluster(mut self, cluster: Arc<Cluster>) -> Self {
+        self.cluster = Some(cluster);
+        self
+    }
+
+    pub fn cluster(&self) -> Option<Arc<Cluster>> {
+        self.cluster.clone()
+    }
+
+    /// Replaces the outgoing queue size and backpressure policy used for new connections.
+    pub fn with_queue_config(mut self, queue_config: QueueConfig) -> Self {
+        self.queue_config = queue_config;
+        self
+    }
+
+    /// Replaces the keepalive settings used for new connections.
+    pub fn with_heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    pub fn heartbeat(&self) -> Heartbeat {
+        self.heartbeat
+    }
+
+    /// Advertises a reconnect jitter window to clients and paces new connections for a
+    /// while, starting now.
+    pub fn with_reconnect(mut self, config: ReconnectConfig) -> Self {
+        self.admission = AdmissionPacer::new(&config);
+        self.reconnect_jitter = Duration::from_secs(config.jitter_secs);
+        self
+    }
+
+    /// Replaces the compression settings offered to clients that negotiate it.
+    pub fn with_compression(mut self, compression: CompressionConfig) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn compression(&self) -> CompressionConfig {
+        self.compression
+    }
+
+    /// Where new connections wait for their turn while the server warms up.
+    pub fn admission(&self) -> &AdmissionPacer {
+        &self.admission
+    }
+
+    /// Replaces the authentication, rate limiting and topic rules enforced on clients.
+    pub fn with_policies(self, policies: Policies) -> Self {
+        self.set_policies(policies);
+        self
+    }
+
+    /// The rules currently in force. Connections pick up changes on their next message.
+    pub fn policies(&self) -> Arc<Policies> {
+        self.policies.read().unwrap().clone()
+    }
+
+    /// Swaps the rules at runtime, e.g. on a config reload.
+    pub fn set_policies(&self, policies: Policies) {
+        *self.policies.write().unwrap() = Arc::new(policies);
+    }
+
+    /// The message of the day sent to clients when they subscribe.
+    pub fn motd(&self) -> Option<String> {
+        self.motd.read().unwrap().clone()
+    }
+
+    pub fn set_motd(&self, motd: Option<String>) {
+        *self.motd.write().unwrap() = motd;
+    }
+
+    /// The results of the canary's recent probes, if it is running.
+    pub fn canary_status(&self) -> CanaryStatus {
+        *self.canary.read().unwrap()
+    }
+
+    pub fn set_canary_status(&self, status: CanaryStatus) {
+        *self.canary.write().unwrap() = status;
+    }
+
+    /// Replaces the in-memory ban list, typically with one loaded from disk.
+    pub fn with_bans(mut self, bans: BanList) -> Self {
+        self.bans = bans;
+        self
+    }
+
+    pub fn bans(&self) -> &BanList {
+        &self.bans
+    }
+
+    /// Replaces the in-memory connection history, typically with one loaded from disk.
+    pub fn with_connection_history(mut self, connection_history: ConnectionHistory) -> Self {
+        self.connection_history = connection_history;
+        self
+    }
+
+    /// Records operator actions in `audit` instead of discarding them.
+    pub fn with_audit_log(mut self, audit: AuditLog) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Where operator actions are recorded.
+    pub fn audit(&self) -> &AuditLog {
+        &self.audit
+    }
+
+    /// Topic membership for admin tools, with the changes to it as they happen.
+    pub fn membership(&self) -> &Membership {
+        &self.membership
+    }
+
+    /// What is known about a session identity, or `None` if it never connected.
+    pub fn whois(&self, identity: &Uuid) -> Option<Whois> {
+        let record = self.connection_history.get(identity)?;
+        Some(Whois {
+            identity: *identity,
+            record,
+            online: self.storage.get_client(identity).is_some(),
+        })
     }
 
-    /// Registers a new client, returning their unique ID.
-    pub fn add_client(&self, mut sender: SplitSink<WebSocket, Message>) -> Uuid {
+    /// Bans a client ID or address and kicks the connected clients it matches.
+    /// Returns the number of clients kicked.
+    pub fn ban(&self, target: BanTarget) -> Result<usize, String> {
+        self.bans.ban(target)?;
+        let banned: Vec<_> = self
+            .storage
+            .get_all_clients()
+            .into_iter()
+            .filter(|client| match target {
+                BanTarget::Client(client_id) => client.id == client_id,
+                BanTarget::Ip(ip) => client.ip == Some(ip),
+            })
+            .collect();
+        for client in &banned {
+            self.kick_banned(&client.id);
+        }
+        Ok(banned.len())
+    }
+
+    /// The hook chain notified whenever a message is stored in or deleted from history.
+    pub fn history_hooks(&self) -> &HistoryHooks {
+        &self.history_hooks
+    }
+
+    /// The hook chain every client message passes through before it is broadcast.
+    pub fn message_hooks(&self) -> &MessageHooks {
+        &self.message_hooks
+    }
+
+    /// The custom message types clients may send, with their handlers.
+    pub fn verbs(&self) -> &Verbs {
+        &self.verbs
+    }
+
+    /// Registers a new client, returning their unique ID and the handle that fires when
+    /// the server closes the connection. If the server is at its connection limit, the
+    /// client is sent a `LimitExceeded` error and disconnected as overloaded instead.
+    pub fn add_client(
+        &self,
+        mut sender: SplitSink<WebSocket, Message>,
+        ip: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) -> Result<(Uuid, CloseHandle, CompressionHandle), LimitExceeded> {
+        if let Err(exceeded) = self.check_connection_limit() {
+            tokio::spawn(async move {
+                forward(&mut sender, &Outgoing::new(exceeded.into()), None).await;
+                close_with(&mut sender, CloseCode::Overloaded).await;
+            });
+            return Err(exceeded);
+        }
         let client_id = Uuid::new_v4();
-        let (tx, mut rx) = mpsc::channel::<ServerMessage>(100);
+        let (tx, mut rx) = queue::channel(self.queue_config);
+        let closer = CloseHandle::default();
+        let compression = CompressionHandle::default();
+        let ping_interval = self.heartbeat.interval;
+        let min_bytes = self.compression.min_bytes;
 
-        // This task forwards messages from the manager to the client's WebSocket connection.
+        // This task forwards messages from the manager to the client's WebSocket connection
+        // and pings it periodically so dead connections can be detected.
+        let closed = closer.clone();
+        let negotiated = compression.clone();
+        let compress = move || negotiated.is_enabled().then_some(min_bytes);
         tokio::spawn(async move {
+            let mut ping = tokio::time::interval_at(
+                tokio::time::Instant::now() + ping_interval,
+                ping_interval,
+            );
             loop {
                 tokio::select! {
-                    message = rx.recv() => {
+                    _ = ping.tick() => {
+                        if sender.send(Message::ping(Vec::new())).await.is_err() {
+                            break;
+                        }
+                    }
+                    message = rx.recv_shared() => {
                         match message {
                             Some(message) => {
-                                crate::log::middleware::log_outgoing(&message);
-                                let msg_str = match serde_json::to_string(&message) {
-                                    Ok(s) => s,
-                                    Err(e) => {
-                                        eprintln!("Failed to serialize message: {}", e);
-                                        continue;
-                                    }
-                                };
-
-                                if sender.send(Message::text(msg_str)).await.is_err() {
+                                if !forward(&mut sender, &message, compress()).await {
                                     // The client has disconnected.
                                     break;
                                 }
@@ -54,6 +384,22 @@ impl ClientManager {
                             }
                         }
                     }
+                    _ = closed.closed() => {
+                        // Flush what is already queued, such as the reason for a kick.
+                        while let Ok(message) = rx.try_recv_shared() {
+                            if !forward(&mut sender, &message, compress()).await {
+                                break;
+                            }
+                        }
+                        break;
+                    }
+                }
+            }
+            // Let the client know we are done, and why if the server ended the connection.
+            match closed.code() {
+                Some(code) => close_with(&mut sender, code).await,
+                None => {
+                    let _ = sender.close().await;
                 }
             }
         });
@@ -62,233 +408,2341 @@ impl ClientManager {
             id: client_id,
             topic: None,
             sender: tx,
+            session_id: None,
+            ip,
+            user_agent,
+            protocol_version: None,
+            connected_at: Utc::now(),
+            accepted_terms: HashMap::new(),
+            role: None,
+            closer: closer.clone(),
         };
 
         self.storage.add_client(new_client);
-        client_id
+        Ok((client_id, closer, compression))
     }
 
-    /// Unregisters a client.
-    pub fn remove_client(&self, client_id: &Uuid) {
-        self.storage.remove_client(client_id);
-        println!("Client {} disconnected.", client_id);
+    /// Registers a client that lives inside the server, such as a simulated one.
+    /// Messages for it are delivered to the returned receiver instead of a WebSocket.
+    pub fn add_internal_client(&self) -> (Uuid, queue::QueueReceiver) {
+        self.add_queue_client(None)
     }
 
-    /// Subscribes a client to a specific topic.
-    pub fn subscribe_client_to_topic(&self, client_id: &Uuid, topic: String) {
-        self.storage.subscribe_client_to_topic(client_id, topic);
+    /// Registers a pseudo-client for a connection that cannot carry a WebSocket, such as
+    /// a Server-Sent Events stream. Messages for it are delivered to the returned receiver.
+    /// Like a WebSocket client it counts towards the connection limit.
+    pub fn add_pseudo_client(
+        &self,
+        ip: Option<IpAddr>,
+    ) -> Result<(Uuid, queue::QueueReceiver), LimitExceeded> {
+        self.check_connection_limit()?;
+        self.counters.connection();
+        Ok(self.add_queue_client(ip))
     }
 
-    /// Sends a message to all clients in a specific topic, with an optional exclusion.
-    pub async fn broadcast_to_topic(
-        &self,
-        topic_name: &str,
-        message: ServerMessage,
-        exclude_id: Option<Uuid>,
-    ) {
-        let clients = self.storage.get_clients_in_topic(topic_name);
-        for client in clients {
-            if exclude_id != Some(client.id) {
-                self.send_message_to_client(&client.id, message.clone())
-                    .await;
-            }
-        }
+    fn add_queue_client(&self, ip: Option<IpAddr>) -> (Uuid, queue::QueueReceiver) {
+        let client_id = Uuid::new_v4();
+        let (tx, rx) = queue::channel(self.queue_config);
+        let client = Client {
+            id: client_id,
+            topic: None,
+            sender: tx,
+            session_id: None,
+            ip,
+            user_agent: None,
+            protocol_version: None,
+            connected_at: Utc::now(),
+            accepted_terms: HashMap::new(),
+            role: None,
+            closer: CloseHandle::default(),
+        };
+        self.storage.add_client(client);
+        (client_id, rx)
     }
 
-    /// Sends a message to all connected clients.
-    pub async fn broadcast_global(&self, message: ServerMessage) {
-        let clients = self.storage.get_all_clients();
-        for client in clients {
-            self.send_message_to_client(&client.id, message.clone())
-                .await;
+    fn check_connection_limit(&self) -> Result<(), LimitExceeded> {
+        let max_connections = self.policies().limits.max_connections;
+        match max_connections.filter(|max| self.storage.client_count() >= *max) {
+            Some(max) => Err(LimitExceeded {
+                limit: Limit::Connections,
+                max,
+            }),
+            None => Ok(()),
         }
     }
 
-    /// Sends a private message to a single client.
-    pub async fn send_private_message(&self, client_id: Uuid, message: ServerMessage) {
-        self.send_message_to_client(&client_id, message).await;
+    /// Unregisters a client, keeping its session around for resumption if it has one.
+    pub fn remove_client(&self, client_id: &Uuid) {
+        self.consumer_groups.leave(client_id);
+        self.qos.forget_client(client_id);
+        self.requests.forget_requester(client_id);
+        self.nicknames.remove(client_id);
+        if let Some(client) = self.storage.remove_client(client_id) {
+            if let Some(topic) = &client.topic {
+                self.membership.left(client.id, topic);
+            }
+            let pending_acks = self.storage.take_pending_acks(client_id);
+            if let Some(session_id) = client.session_id {
+                self.record_disconnect(client_id);
+                self.storage.save_session(Session {
+                    session_id,
+                    client_id: client.id,
+                    topic: client.topic,
+                    pending_acks,
+                    accepted_terms: client.accepted_terms,
+                    detached_at: Instant::now(),
+                });
+            }
+        }
+        info!(%client_id, "Client disconnected");
     }
 
-    /// Helper to send a message to a client.
-    async fn send_message_to_client(&self, client_id: &Uuid, message: ServerMessage) {
-        if let Some(client) = self.storage.get_client(client_id) {
-            if client.sender.send(message).await.is_err() {}
-        }
+    /// Sends a client a final `Kicked` message and closes its connection.
+    /// Unlike a dropped connection, the client's session cannot be resumed.
+    /// Returns false if no such client is connected.
+    pub fn kick_client(&self, client_id: &Uuid, reason: Option<String>) -> bool {
+        self.disconnect(client_id, reason, CloseCode::Kicked)
     }
 
-    // // Getter methods for server-side CLI
-    // pub fn get_client(&self, client_id: &Uuid) -> Option<Client> {
-    //     self.storage.get_client(client_id)
-    // }
+    /// Kicks a client that is banned, closing its connection with `CloseCode::Banned`.
+    pub fn kick_banned(&self, client_id: &Uuid) -> bool {
+        self.disconnect(
+            client_id,
+            Some(BANNED_REASON.to_string()),
+            CloseCode::Banned,
+        )
+    }
 
-    pub fn get_clients_by_topic(&self, topic: &str) -> Vec<Client> {
-        self.storage.get_clients_in_topic(topic)
+    /// Closes every client's connection with `code`, e.g. when the server shuts down.
+    /// Returns how many connections were closed.
+    pub fn close_all(&self, code: CloseCode) -> usize {
+        let clients = self.storage.get_all_clients();
+        for client in &clients {
+            client.closer.close(code);
+        }
+        clients.len()
     }
 
-    pub fn get_all_clients(&self) -> Vec<Client> {
-        self.storage.get_all_clients()
+    /// Stops accepting WebSocket connections and closes every connection as draining, so
+    /// clients reconnect through the load balancer to another node. Once the last client
+    /// has left, the console and the audit log are told. Returns how many connections
+    /// were closed, or `None` if the server was already draining.
+    pub fn drain(self: &Arc<Self>) -> Option<usize> {
+        if self.draining.swap(true, Ordering::Relaxed) {
+            return None;
+        }
+        let closed = self.close_all(CloseCode::Draining);
+        self.audit.record(
+            "drain",
+            "server",
+            Ok(format!("Closed {} connection(s)", closed)),
+        );
+        let manager = self.clone();
+        tokio::spawn(async move {
+            while manager.storage.client_count() > 0 {
+                tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+            }
+            manager
+                .audit
+                .record("drained", "server", Ok("No clients left".to_string()));
+            info!("Drained: the last client has disconnected");
+        });
+        Some(closed)
     }
 
-    pub fn get_all_topics(&self) -> Vec<String> {
-        self.storage.get_all_topics()
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
     }
 
-    /// Handles a message acknowledgment from a client.
-    pub async fn handle_message_acknowledgment(&self, client_id: Uuid, msg_id: Uuid) {
-        crate::log::middleware::log_ack(&client_id, &msg_id);
-        ui::print_system_message(&format!(
-            "Message {} acknowledged by client {}.",
-            msg_id, client_id
-        ));
-        // Optionally, you could send a ServerMessage::MessageAcknowledged back to the client
-        // or to other interested parties here.
+    pub fn drain_status(&self) -> DrainStatus {
+        DrainStatus {
+            draining: self.is_draining(),
+            clients: self.storage.client_count(),
+        }
     }
-}
 
-#[cfg(test)]
-impl ClientManager {
-    pub fn add_test_client(&self) -> (Uuid, mpsc::Receiver<ServerMessage>) {
-        let client_id = Uuid::new_v4();
-        let (tx, rx) = mpsc::channel(100);
-        let client = Client {
-            id: client_id,
-            topic: None,
-            sender: tx,
+    fn disconnect(&self, client_id: &Uuid, reason: Option<String>, code: CloseCode) -> bool {
+        let Some(client) = self.storage.remove_client(client_id) else {
+            return false;
         };
-        self.storage.add_client(client);
-        (client_id, rx)
+        self.storage.take_pending_acks(client_id);
+        self.consumer_groups.leave(client_id);
+        self.qos.forget_client(client_id);
+        self.requests.forget_requester(client_id);
+        self.nicknames.remove(client_id);
+        if let Some(topic) = &client.topic {
+            self.membership.left(client.id, topic);
+        }
+        if client.session_id.is_some() {
+            self.record_disconnect(client_id);
+        }
+        let _ = client.sender.send(ServerMessage::Kicked { reason });
+        client.closer.close(code);
+        true
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::core::storage::InMemoryStorage;
-    use tokio::sync::mpsc::Receiver;
+    /// Completes a `ConnectSession` handshake, returning the client's (possibly resumed) ID.
+    ///
+    /// If `session_id` names a detached session that has not expired, the connection takes
+    /// over that session's client ID, topic subscription, and unacknowledged messages.
+    /// Otherwise a new session is started for the connection.
+    pub async fn connect_session(
+        &self,
+        client_id: Uuid,
+        topic: String,
+        session_id: Option<Uuid>,
+    ) -> Uuid {
+        let resumed = session_id.and_then(|session_id| self.resume_session(client_id, session_id));
+        let (client_id, session_id, pending_acks) = match resumed {
+            Some(session) => (
+                session.client_id,
+                session.session_id,
+                Some(session.pending_acks),
+            ),
+            None => {
+                let session_id = Uuid::new_v4();
+                if let Some(mut client) = self.storage.get_client(&client_id) {
+                    client.session_id = Some(session_id);
+                    self.storage.add_client(client);
+                }
+                (client_id, session_id, None)
+            }
+        };
 
-    // Helper to create a mock client and return its ID and receiver
-    fn setup_mock_client(manager: &ClientManager) -> (Uuid, Receiver<ServerMessage>) {
-        manager.add_test_client()
-    }
+        let ip = self
+            .storage
+            .get_client(&client_id)
+            .and_then(|client| client.ip);
+        if let Err(e) = self.connection_history.connected(client_id, ip) {
+            warn!("{}", e);
+        }
 
-    fn create_manager() -> ClientManager {
-        let storage = Arc::new(InMemoryStorage::new());
-        ClientManager::new(storage)
+        let welcome = ServerMessage::Welcome {
+            client_id,
+            session_id,
+            resumed: pending_acks.is_some(),
+            reconnect_jitter_ms: self.reconnect_jitter.as_millis() as u64,
+        };
+        self.send_message_to_client(&client_id, welcome).await;
+        self.join_topic(&client_id, topic).await;
+        if let Some(client) = self.storage.get_client(&client_id) {
+            for message in pending_acks.unwrap_or_default() {
+                // Tracked again, so a resent QoS message that is lost is resent once more.
+                let msg_id = qos_id(&message.message);
+                if self.enqueue(&client, message.clone()) {
+                    if let Some(msg_id) = msg_id {
+                        self.track_unacked(client_id, msg_id, &message);
+                    }
+                }
+            }
+        }
+        client_id
     }
 
-    #[tokio::test]
-    async fn test_remove_client() {
-        let manager = create_manager();
-        let (client_id, _rx) = setup_mock_client(&manager);
-        let topic = "general".to_string();
-        manager.subscribe_client_to_topic(&client_id, topic.clone());
+    fn record_disconnect(&self, client_id: &Uuid) {
+        if let Err(e) = self.connection_history.disconnected(client_id) {
+            warn!("{}", e);
+        }
+    }
 
-        assert_eq!(manager.get_all_clients().len(), 1);
-        assert_eq!(manager.get_clients_by_topic(&topic).len(), 1);
+    /// Moves the connection registered as `client_id` onto a detached session.
+    fn resume_session(&self, client_id: Uuid, session_id: Uuid) -> Option<Session> {
+        let session = self.storage.take_session(&session_id)?;
+        if session.detached_at.elapsed() > SESSION_TTL
+            || self.storage.get_client(&session.client_id).is_some()
+        {
+            return None;
+        }
+        let mut client = self.storage.remove_client(&client_id)?;
+        if let Some(topic) = client.topic.take() {
+            self.membership.left(client_id, &topic);
+        }
+        client.id = session.client_id;
+        client.session_id = Some(session.session_id);
+        client.accepted_terms = session.accepted_terms.clone();
+        self.storage.add_client(client);
+        if let Some(topic) = &session.topic {
+            self.subscribe_ignoring_limits(&session.client_id, topic.clone());
+        }
+        Some(session)
+    }
 
-        manager.remove_client(&client_id);
+    /// Subscribes a client to a specific topic, unless the topic is full.
+    pub fn subscribe_client_to_topic(
+        &self,
+        client_id: &Uuid,
+        topic: String,
+    ) -> Result<(), LimitExceeded> {
+        if let Some(max) = self.policies().limits.max_clients_per_topic {
+            let others = self
+                .storage
+                .get_clients_in_topic(&topic)
+                .iter()
+                .filter(|client| client.id != *client_id)
+                .count();
+            if others >= max {
+                return Err(LimitExceeded {
+                    limit: Limit::TopicClients,
+                    max,
+                });
+            }
+        }
+        self.subscribe_ignoring_limits(client_id, topic);
+        Ok(())
+    }
 
-        assert!(manager.get_all_clients().is_empty());
-        assert!(manager.get_clients_by_topic(&topic).is_empty());
+    /// Subscribes a client on the operator's behalf, e.g. when moving it between topics.
+    pub fn subscribe_ignoring_limits(&self, client_id: &Uuid, topic: String) {
+        let client = self.storage.get_client(client_id);
+        self.topic_stats.joined(&topic);
+        self.storage
+            .subscribe_client_to_topic(client_id, topic.clone());
+        if let Some(client) = client {
+            self.membership
+                .moved(client.id, client.topic.as_deref(), &topic);
+        }
     }
 
-    #[tokio::test]
-    async fn test_send_private_message() {
-        let manager = create_manager();
-        let (client_id, mut rx) = setup_mock_client(&manager);
+    /// Subscribes a client to `topic`, unless the topic has rules the client has not
+    /// accepted yet. The client is then sent the rules in a `TopicInfo` and subscribed
+    /// once it accepts them. Returns whether the client was subscribed.
+    pub async fn join_topic(&self, client_id: &Uuid, topic: String) -> bool {
+        let policies = self.policies();
+        if let Some((rules, version)) = policies.rules(&topic) {
+            let accepted = self
+                .storage
+                .get_client(client_id)
+                .is_some_and(|client| client.accepted_terms.get(&topic) == Some(&version));
+            if !accepted {
+                let info = ServerMessage::TopicInfo {
+                    topic,
+                    rules: rules.to_string(),
+                    version,
+                };
+                self.send_message_to_client(client_id, info).await;
+                return false;
+            }
+        }
+        match self.subscribe_client_to_topic(client_id, topic.clone()) {
+            Ok(()) => {
+                self.send_retained(client_id, &topic);
+                true
+            }
+            Err(exceeded) => {
+                self.send_message_to_client(client_id, exceeded.into())
+                    .await;
+                false
+            }
+        }
+    }
 
-        let msg = ServerMessage::Private {
-            id: Uuid::new_v4(),
-            content: "Hello".to_string(),
+    /// Keeps a topic message as its topic's retained message, or clears the retained
+    /// message if the content is empty. New subscribers get it with `retained` set and
+    /// sequence number 0, since it is not sent in order with the topic's other messages.
+    pub fn retain(&self, message: &ServerMessage) {
+        let ServerMessage::Topic { topic, content, .. } = message else {
+            return;
         };
-
-        manager.send_private_message(client_id, msg.clone()).await;
-
-        let received = rx.recv().await.unwrap();
-        assert_eq!(
-            serde_json::to_string(&received).unwrap(),
-            serde_json::to_string(&msg).unwrap()
-        );
+        if content.is_empty() {
+            self.storage.set_retained(topic, None);
+            return;
+        }
+        let mut message = message.clone();
+        if let ServerMessage::Topic { seq, retained, .. } = &mut message {
+            *seq = 0;
+            *retained = true;
+        }
+        self.storage.set_retained(topic, Some(message));
     }
 
-    #[tokio::test]
-    async fn test_broadcast_global() {
-        let manager = create_manager();
-        let (_client1_id, mut rx1) = setup_mock_client(&manager);
-        let (_client2_id, mut rx2) = setup_mock_client(&manager);
+    /// The message a topic sends to new subscribers, if it has one.
+    pub fn retained(&self, topic: &str) -> Option<ServerMessage> {
+        self.storage.get_retained(topic)
+    }
 
-        let msg = ServerMessage::Global {
-            id: Uuid::new_v4(),
-            content: "Global message".to_string(),
-        };
+    /// Every topic that has had a message, with the last sequence number it handed out.
+    pub fn topic_seqs(&self) -> Vec<(String, u64)> {
+        self.storage.topic_seqs()
+    }
 
-        manager.broadcast_global(msg.clone()).await;
+    pub fn restore_topic_seq(&self, topic: &str, seq: u64) {
+        self.storage.restore_topic_seq(topic, seq)
+    }
 
-        let received1 = rx1.recv().await.unwrap();
-        let received2 = rx2.recv().await.unwrap();
+    /// Sends a client that just subscribed to `topic` the topic's retained message, if
+    /// it has one.
+    pub fn send_retained(&self, client_id: &Uuid, topic: &str) {
+        let retained = self.storage.get_retained(topic);
+        if let (Some(message), Some(client)) = (retained, self.storage.get_client(client_id)) {
+            self.enqueue(&client, Arc::new(Outgoing::new(message)));
+        }
+    }
 
-        assert_eq!(
-            serde_json::to_string(&received1).unwrap(),
-            serde_json::to_string(&msg).unwrap()
-        );
-        assert_eq!(
-            serde_json::to_string(&received2).unwrap(),
-            serde_json::to_string(&msg).unwrap()
-        );
+    /// Records the protocol version a client's frames are in.
+    pub fn set_protocol_version(&self, client_id: &Uuid, version: u32) {
+        if let Some(mut client) = self.storage.get_client(client_id) {
+            client.protocol_version = Some(version);
+            self.storage.add_client(client);
+        }
     }
 
-    #[tokio::test]
-    async fn test_broadcast_to_topic() {
-        let manager = create_manager();
-        let topic1 = "topic1".to_string();
-        let topic2 = "topic2".to_string();
+    /// Records the role the token a client connected with assigned it.
+    pub fn set_role(&self, client_id: &Uuid, role: Role) {
+        if let Some(mut client) = self.storage.get_client(client_id) {
+            client.role = Some(role);
+            self.storage.add_client(client);
+        }
+    }
 
-        let (client1_id, mut rx1) = setup_mock_client(&manager);
-        manager.subscribe_client_to_topic(&client1_id, topic1.clone());
+    /// Records that a client accepted the rules of `topic` and subscribes it.
+    /// Returns false if `version` is not the current version of the rules.
+    pub async fn accept_terms(&self, client_id: &Uuid, topic: String, version: u32) -> bool {
+        if self.policies().rules(&topic).map(|(_, current)| current) != Some(version) {
+            return false;
+        }
+        let Some(mut client) = self.storage.get_client(client_id) else {
+            return false;
+        };
+        client.accepted_terms.insert(topic.clone(), version);
+        self.storage.add_client(client);
+        info!(%client_id, %topic, version, "Client accepted the rules of a topic");
+        self.join_topic(client_id, topic).await
+    }
 
-        let (client2_id, mut rx2) = setup_mock_client(&manager);
-        manager.subscribe_client_to_topic(&client2_id, topic1.clone());
+    /// Sends a message to all clients in a specific topic, with an optional exclusion.
+    /// The message is recorded in history as originating from the operator.
+    pub async fn broadcast_to_topic(
+        &self,
+        topic_name: &str,
+        message: ServerMessage,
+        exclude_id: Option<Uuid>,
+    ) -> BroadcastReceipt {
+        self.broadcast_to_topic_with_provenance(
+            topic_name,
+            message,
+            exclude_id,
+            Provenance::new(Origin::Operator),
+        )
+        .await
+    }
+
+    /// Like `broadcast_to_topic`, recording the given provenance chain in history. The
+    /// forwarding rules from the topic then publish their copies of the message.
+    pub async fn broadcast_to_topic_with_provenance(
+        &self,
+        topic_name: &str,
+        message: ServerMessage,
+        exclude_id: Option<Uuid>,
+        provenance: Provenance,
+    ) -> BroadcastReceipt {
+        let forwarded = self.forwards.forward(&message);
+        let receipt = self
+            .publish(topic_name, message, exclude_id, provenance.clone())
+            .await;
+        for (rule, copy) in forwarded {
+            let mut provenance = provenance.clone();
+            provenance.record_transform(
+                "forward",
+                &format!("from topic '{}' by rule {}", rule.spec.from, rule.id),
+            );
+            self.publish(&rule.spec.to, copy, None, provenance).await;
+        }
+        receipt
+    }
+
+    /// Sends a topic message to the cluster peers and delivers it on this node.
+    async fn publish(
+        &self,
+        topic_name: &str,
+        message: ServerMessage,
+        exclude_id: Option<Uuid>,
+        provenance: Provenance,
+    ) -> BroadcastReceipt {
+        if let Some(cluster) = &self.cluster {
+            cluster.publish(&message);
+        }
+        self.deliver_to_topic(topic_name, message, exclude_id, provenance)
+            .await
+    }
+
+    /// Relays a frame from `sender`, such as an attachment chunk or a key announcement, to
+    /// the other subscribers of `topic` on this node. It is neither forwarded to cluster
+    /// peers nor kept in history.
+    pub async fn relay_to_topic(
+        &self,
+        topic_name: &str,
+        message: ServerMessage,
+        sender: Uuid,
+    ) -> BroadcastReceipt {
+        self.deliver_to_topic(
+            topic_name,
+            message,
+            Some(sender),
+            Provenance::new(Origin::Client(sender)),
+        )
+        .await
+    }
+
+    /// Delivers a topic message to the clients connected to this node only, giving it
+    /// the next sequence number of the topic on this node.
+    pub(crate) async fn deliver_to_topic(
+        &self,
+        topic_name: &str,
+        mut message: ServerMessage,
+        exclude_id: Option<Uuid>,
+        provenance: Provenance,
+    ) -> BroadcastReceipt {
+        if let ServerMessage::Topic { seq, .. } = &mut message {
+            *seq = self.storage.next_topic_seq(topic_name);
+        }
+        let started = Instant::now();
+        let delivery = self.policies().delivery(topic_name);
+        let receipt = match message.id() {
+            Some(msg_id) if delivery != Delivery::Broadcast => {
+                self.dispatch(msg_id, topic_name, &message, exclude_id, HashSet::new(), 0)
+                    .await
+            }
+            _ => {
+                let recipients = self.storage.topic_recipients(topic_name);
+                // Shared by the queues and the QoS redelivery, so it is serialized once.
+                let shared = Arc::new(Outgoing::new(message.clone()));
+                let receipt = self
+                    .deliver(recipients.clone(), exclude_id, shared.clone())
+                    .await;
+                self.track_qos(&recipients, exclude_id, shared, &receipt);
+                receipt
+            }
+        };
+        if let ServerMessage::Topic { content, .. } = &message {
+            self.broadcast_latency
+                .record(receipt.targeted, content.len(), started.elapsed());
+            self.topic_stats
+                .message(topic_name, content.len(), receipt.enqueued());
+            self.counters.message_routed();
+            self.content_analytics.record(topic_name, content);
+        }
+        self.record_history(&message, provenance).await;
+        receipt
+    }
+
+    /// Waits for every subscriber a QoS message was enqueued for to acknowledge it.
+    fn track_qos(
+        &self,
+        recipients: &[Recipient],
+        exclude_id: Option<Uuid>,
+        shared: Arc<Outgoing>,
+        receipt: &BroadcastReceipt,
+    ) {
+        let Some(msg_id) = qos_id(&shared.message) else {
+            return;
+        };
+        let delivered = recipients
+            .iter()
+            .filter(|r| exclude_id != Some(r.id) && !receipt.failed.contains(&r.id));
+        for recipient in delivered {
+            self.track_unacked(recipient.id, msg_id, &shared);
+        }
+    }
+
+    fn track_unacked(&self, client_id: Uuid, msg_id: Uuid, shared: &Arc<Outgoing>) {
+        self.qos.track(
+            client_id,
+            msg_id,
+            Unacked {
+                message: shared.clone(),
+                attempts: 1,
+                sent_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Sends QoS messages that a subscriber did not acknowledge within `timeout` to it
+    /// again. Returns how many were given up on after `MAX_DELIVERY_ATTEMPTS`.
+    pub fn redeliver_unacked(&self, timeout: Duration) -> usize {
+        let mut dropped = 0;
+        for (client_id, msg_id, unacked) in self.qos.take_expired(timeout) {
+            let Some(client) = self.storage.get_client(&client_id) else {
+                continue;
+            };
+            if unacked.attempts >= MAX_DELIVERY_ATTEMPTS {
+                dropped += 1;
+                continue;
+            }
+            if client.sender.send_shared(unacked.message.clone()).is_ok() {
+                self.qos.track(
+                    client_id,
+                    msg_id,
+                    Unacked {
+                        attempts: unacked.attempts + 1,
+                        sent_at: Instant::now(),
+                        ..unacked
+                    },
+                );
+            }
+        }
+        dropped
+    }
+
+    /// How many QoS messages are waiting for a subscriber's acknowledgment.
+    pub fn unacked_qos_count(&self) -> usize {
+        self.qos.len()
+    }
+
+    /// Whether an exactly-once message with this ID was already broadcast.
+    pub fn is_duplicate(&self, msg_id: &Uuid) -> bool {
+        self.qos.is_duplicate(msg_id)
+    }
+
+    /// Remembers that an exactly-once message was broadcast, so retries are not.
+    pub fn remember_published(&self, msg_id: Uuid) {
+        self.qos.remember(msg_id);
+    }
+
+    /// The topic a client is subscribed to, if it is connected and subscribed.
+    pub fn client_topic(&self, client_id: &Uuid) -> Option<String> {
+        self.storage.get_client(client_id)?.topic
+    }
+
+    /// The rules that forward messages from one topic to another.
+    pub fn forwards(&self) -> &Forwards {
+        &self.forwards
+    }
+
+    /// The polls that are still open.
+    pub fn polls(&self) -> &Polls {
+        &self.polls
+    }
+
+    /// Opens a poll and announces it to the subscribers of its topic on this node.
+    /// Polls are not shared with cluster peers, since their votes are counted locally.
+    pub async fn open_poll(&self, poll: Poll) -> BroadcastReceipt {
+        let announcement = poll.announcement();
+        let topic = poll.topic.clone();
+        self.polls.open(poll);
+        self.deliver_to_topic(
+            &topic,
+            announcement,
+            None,
+            Provenance::new(Origin::Operator),
+        )
+        .await
+    }
+
+    /// Records a client's vote in a poll of the topic it is subscribed to.
+    pub fn vote(&self, client_id: &Uuid, poll_id: &Uuid, option: usize) -> Result<(), String> {
+        let topic = self.client_topic(client_id);
+        self.polls
+            .vote(poll_id, *client_id, topic.as_deref(), option)
+    }
+
+    /// Closes the polls whose deadline has passed and sends their results to the
+    /// subscribers of their topics. Returns the closed polls.
+    pub async fn close_expired_polls(&self) -> Vec<Poll> {
+        let closed = self.polls.take_expired();
+        for poll in &closed {
+            let provenance = Provenance::new(Origin::Operator);
+            self.deliver_to_topic(&poll.topic, poll.results(), None, provenance)
+                .await;
+        }
+        closed
+    }
+
+    /// Sends a client's request to the other subscribers of `topic` on this node, waiting
+    /// for one of them to respond under the same correlation ID.
+    pub async fn send_request(
+        &self,
+        requester: Uuid,
+        topic: &str,
+        correlation_id: Uuid,
+        content: String,
+    ) -> Result<(), String> {
+        let responders = self
+            .get_clients_by_topic(topic)
+            .iter()
+            .filter(|client| client.id != requester)
+            .count();
+        if responders == 0 {
+            return Err(format!(
+                "Topic '{}' has no other subscriber to respond",
+                topic
+            ));
+        }
+        self.requests.open(
+            correlation_id,
+            requester,
+            topic.to_string(),
+            REQUEST_TIMEOUT,
+        )?;
+        let message = ServerMessage::Request {
+            topic: topic.to_string(),
+            correlation_id,
+            sender: requester.to_string(),
+            content,
+        };
+        self.relay_to_topic(topic, message, requester).await;
+        Ok(())
+    }
+
+    /// Routes a response back to the client whose request it answers. The responder must
+    /// be subscribed to the topic the request was sent to.
+    pub async fn respond(
+        &self,
+        responder: &Uuid,
+        correlation_id: Uuid,
+        content: String,
+    ) -> Result<(), String> {
+        let topic = self.client_topic(responder);
+        let requester = self.requests.take(&correlation_id, topic.as_deref())?;
+        let response = ServerMessage::Response {
+            correlation_id,
+            content,
+        };
+        self.send_private_message(requester, response).await;
+        Ok(())
+    }
+
+    /// How many requests are waiting for a response.
+    pub fn pending_request_count(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// Tells the requesters whose requests got no response in time. Returns how many
+    /// requests expired.
+    pub async fn expire_requests(&self) -> usize {
+        let expired = self.requests.take_expired();
+        for (correlation_id, pending) in &expired {
+            let error = ServerMessage::Error {
+                message: format!(
+                    "Request {} to topic '{}' got no response",
+                    correlation_id, pending.topic
+                ),
+            };
+            self.send_private_message(pending.requester, error).await;
+        }
+        expired.len()
+    }
+
+    /// Hands a queue topic's message to one subscriber, preferring those it was not
+    /// offered to yet, and tracks it until it is acknowledged. Nothing is tracked if
+    /// the topic has no subscribers.
+    async fn dispatch(
+        &self,
+        msg_id: Uuid,
+        topic: &str,
+        message: &ServerMessage,
+        exclude: Option<Uuid>,
+        mut tried: HashSet<Uuid>,
+        attempts: usize,
+    ) -> BroadcastReceipt {
+        let candidates: Vec<_> = self
+            .storage
+            .get_clients_in_topic(topic)
+            .into_iter()
+            .filter(|client| exclude != Some(client.id))
+            .collect();
+        let untried: Vec<_> = candidates
+            .iter()
+            .filter(|client| !tried.contains(&client.id))
+            .cloned()
+            .collect();
+        let pool = if untried.is_empty() {
+            &candidates
+        } else {
+            &untried
+        };
+        let delivery = self.policies().delivery(topic);
+        let Some(client) = self.work.pick(topic, delivery, pool).cloned() else {
+            return BroadcastReceipt::new(Some(msg_id), None);
+        };
+        tried.insert(client.id);
+        // Track before sending so that an early acknowledgment is not missed.
+        self.work.track(
+            msg_id,
+            InFlight {
+                topic: topic.to_string(),
+                message: message.clone(),
+                exclude,
+                assignee: client.id,
+                tried,
+                attempts: attempts + 1,
+                sent_at: Instant::now(),
+            },
+        );
+        self.deliver(
+            Arc::new([Recipient::from(&client)]),
+            None,
+            Arc::new(Outgoing::new(message.clone())),
+        )
+        .await
+    }
+
+    /// Gives queue messages that were not acknowledged within `timeout`, or whose
+    /// subscriber left, to another subscriber. Returns how many were dropped instead
+    /// because they ran out of attempts or nobody is subscribed.
+    pub async fn redeliver_expired(&self, timeout: Duration) -> usize {
+        let expired = self.work.take_expired(timeout, |client_id| {
+            self.storage.get_client(client_id).is_some()
+        });
+        let mut dropped = 0;
+        for (msg_id, in_flight) in expired {
+            if in_flight.attempts >= MAX_DELIVERY_ATTEMPTS {
+                dropped += 1;
+                continue;
+            }
+            let receipt = self
+                .dispatch(
+                    msg_id,
+                    &in_flight.topic,
+                    &in_flight.message,
+                    in_flight.exclude,
+                    in_flight.tried,
+                    in_flight.attempts,
+                )
+                .await;
+            if receipt.targeted == 0 {
+                dropped += 1;
+            }
+        }
+        dropped
+    }
+
+    /// How many queue messages are waiting for an acknowledgment.
+    pub fn in_flight_count(&self) -> usize {
+        self.work.len()
+    }
+
+    /// Looks up a message that is still retained in history.
+    pub fn get_history_message(&self, msg_id: &Uuid) -> Option<StoredMessage> {
+        self.history.get(msg_id)
+    }
+
+    /// Returns up to `limit` of the most recent messages in a topic, oldest first.
+    pub fn get_topic_history(&self, topic: &str, limit: usize) -> Vec<StoredMessage> {
+        self.history.recent(topic, limit)
+    }
+
+    /// Publishes messages from history again, in the order given, with their original
+    /// topic, sender, content and headers but new IDs and sequence numbers. Their
+    /// provenance records the replay. Returns the receipt of each.
+    pub async fn replay(&self, messages: &[StoredMessage]) -> Vec<BroadcastReceipt> {
+        let mut receipts = Vec::new();
+        for stored in messages {
+            let mut message = stored.to_server_message();
+            if let ServerMessage::Topic { id, .. } = &mut message {
+                *id = Uuid::new_v4();
+            }
+            let mut provenance = stored.provenance.clone();
+            provenance.record_transform("replay", &format!("of message {}", stored.id));
+            receipts.push(
+                self.broadcast_to_topic_with_provenance(&stored.topic, message, None, provenance)
+                    .await,
+            );
+        }
+        receipts
+    }
+
+    /// Every message in history, topic by topic, oldest first within each topic.
+    pub fn get_all_history(&self) -> Vec<StoredMessage> {
+        let mut topics = self.history.topics();
+        topics.sort();
+        topics
+            .iter()
+            .flat_map(|topic| self.history.recent(topic, usize::MAX))
+            .collect()
+    }
+
+    /// Hands a member of consumer group `group` the next batch of up to `max` messages
+    /// from the history of the topic it is subscribed to. Returns the topic and batch,
+    /// or `None` if the client is not subscribed to a topic.
+    pub fn fetch_for_group(
+        &self,
+        client_id: &Uuid,
+        group: &str,
+        max: usize,
+    ) -> Option<(String, Vec<StoredMessage>)> {
+        let topic = self.storage.get_client(client_id)?.topic?;
+        let max = max.min(MAX_FETCH_BATCH);
+        let batch = self
+            .consumer_groups
+            .fetch(&topic, group, *client_id, |seq| {
+                self.history.after(&topic, seq, max)
+            });
+        Some((topic, batch))
+    }
+
+    /// Up to `MAX_FETCH_BATCH` of the retained messages of `topic` from sequence number
+    /// `from_seq` on, for a subscriber that missed them. Returns `None` if the client is
+    /// not subscribed to `topic`.
+    pub fn missed_messages(
+        &self,
+        client_id: &Uuid,
+        topic: &str,
+        from_seq: u64,
+    ) -> Option<Vec<StoredMessage>> {
+        let subscribed = self.storage.get_client(client_id)?.topic?;
+        if subscribed != topic {
+            return None;
+        }
+        Some(
+            self.history
+                .after(topic, from_seq.saturating_sub(1), MAX_FETCH_BATCH),
+        )
+    }
+
+    /// Commits a consumer group's offset in the topic the client is subscribed to.
+    /// Returns the topic and the group's committed offset, or `None` if the client is not
+    /// subscribed to a topic.
+    pub fn commit_group_offset(
+        &self,
+        client_id: &Uuid,
+        group: &str,
+        offset: u64,
+    ) -> Option<(String, u64)> {
+        let topic = self.storage.get_client(client_id)?.topic?;
+        let committed = self.consumer_groups.commit(&topic, group, offset);
+        Some((topic, committed))
+    }
+
+    /// The offsets and members of a consumer group, if any member has used it.
+    pub fn consumer_group(&self, topic: &str, group: &str) -> Option<GroupOffsets> {
+        self.consumer_groups.get(topic, group)
+    }
+
+    /// Deletes a message from history, notifying the history hooks.
+    pub async fn delete_from_history(&self, msg_id: &Uuid) -> Option<StoredMessage> {
+        let removed = self.history.remove(msg_id)?;
+        self.readers.forget(msg_id);
+        self.history_hooks.message_deleted(&removed).await;
+        Some(removed)
+    }
+
+    /// Stores a topic message in history and runs the hooks for it and any evicted messages.
+    async fn record_history(&self, message: &ServerMessage, provenance: Provenance) {
+        if let ServerMessage::Topic {
+            id,
+            topic,
+            seq,
+            sender,
+            content,
+            headers,
+            reply_to,
+            ..
+        } = message
+        {
+            let stored = StoredMessage {
+                id: *id,
+                topic: topic.clone(),
+                seq: *seq,
+                sender: sender.clone(),
+                content: content.clone(),
+                headers: headers.clone(),
+                reply_to: *reply_to,
+                timestamp: Utc::now(),
+                provenance,
+            };
+            let evicted = self.history.append(stored.clone());
+            self.history_hooks.message_stored(&stored).await;
+            for message in &evicted {
+                self.readers.forget(&message.id);
+                self.history_hooks.message_deleted(message).await;
+            }
+        }
+    }
+
+    /// Sends a message to all connected clients.
+    pub async fn broadcast_global(&self, message: ServerMessage) -> BroadcastReceipt {
+        self.counters.message_routed();
+        let recipients = self
+            .storage
+            .get_all_clients()
+            .iter()
+            .map(Recipient::from)
+            .collect();
+        self.deliver(recipients, None, Arc::new(Outgoing::new(message)))
+            .await
+    }
+
+    /// Sends a message to each of `clients`. Acknowledgments are tracked from before the
+    /// first send so that none arriving early are missed.
+    /// Every recipient gets the same copy, serialized once by the first one to send it.
+    async fn deliver(
+        &self,
+        recipients: Arc<[Recipient]>,
+        exclude_id: Option<Uuid>,
+        shared: Arc<Outgoing>,
+    ) -> BroadcastReceipt {
+        let msg_id = shared.message.id();
+        let acks = msg_id.map(|id| {
+            let targets = recipients.iter().map(|r| r.id);
+            self.acks
+                .watch(id, targets.filter(|id| exclude_id != Some(*id)))
+        });
+        let mut receipt = BroadcastReceipt::new(msg_id, acks);
+        let readers = match (&shared.message, msg_id) {
+            // Messages published by clients carry the client's ID as their sender.
+            (ServerMessage::Topic { sender, .. }, Some(msg_id))
+                if Uuid::parse_str(sender).is_ok() =>
+            {
+                Some(msg_id)
+            }
+            _ => None,
+        };
+        let outcomes = fanout::fan_out(self.storage.clone(), recipients, exclude_id, shared).await;
+        let mut enqueued = Vec::new();
+        for (client_id, delivered) in outcomes {
+            let ok = self.settle(&client_id, delivered);
+            if ok {
+                enqueued.push(client_id);
+            }
+            receipt.record(client_id, ok);
+        }
+        if let Some(msg_id) = readers {
+            self.readers.expect(msg_id, enqueued);
+        }
+        debug!(
+            msg_id = ?receipt.msg_id,
+            targeted = receipt.targeted,
+            failed = receipt.failed.len(),
+            "broadcast delivered"
+        );
+        receipt
+    }
+
+    /// Sends a private message to a single client.
+    pub async fn send_private_message(&self, client_id: Uuid, message: ServerMessage) {
+        self.send_message_to_client(&client_id, message).await;
+    }
+
+    /// Helper to send a message to a client. Returns whether the message was enqueued.
+    async fn send_message_to_client(&self, client_id: &Uuid, message: ServerMessage) -> bool {
+        let Some(client) = self.storage.get_client(client_id) else {
+            return false;
+        };
+        if matches!(
+            message,
+            ServerMessage::Error { .. } | ServerMessage::LimitExceeded { .. }
+        ) {
+            self.counters.error();
+        }
+        self.enqueue(&client, Arc::new(Outgoing::new(message)))
+    }
+
+    /// Queues a message for a client, applying its backpressure policy.
+    /// Returns whether the message was enqueued.
+    fn enqueue(&self, client: &Client, message: Arc<Outgoing>) -> bool {
+        let delivered = fanout::enqueue(self.storage.as_ref(), &Recipient::from(client), message);
+        self.settle(&client.id, delivered)
+    }
+
+    /// Removes a client whose queue overflowed under the disconnect policy. Returns whether
+    /// the message was enqueued.
+    fn settle(&self, client_id: &Uuid, delivered: Delivered) -> bool {
+        if delivered == Delivered::Overloaded {
+            warn!(%client_id, "Client is too slow, disconnecting");
+            self.remove_client(client_id);
+        }
+        delivered == Delivered::Enqueued
+    }
+
+    // // Getter methods for server-side CLI
+    // pub fn get_client(&self, client_id: &Uuid) -> Option<Client> {
+    //     self.storage.get_client(client_id)
+    // }
+
+    pub fn get_clients_by_topic(&self, topic: &str) -> Vec<Client> {
+        self.storage.get_clients_in_topic(topic)
+    }
+
+    pub fn get_all_clients(&self) -> Vec<Client> {
+        self.storage.get_all_clients()
+    }
+
+    pub fn get_all_topics(&self) -> Vec<String> {
+        self.storage.get_all_topics()
+    }
+
+    /// The topics with subscribers on this node and their client counts, by name.
+    pub fn topic_summaries(&self) -> Vec<TopicSummary> {
+        let mut topics: Vec<_> = self
+            .storage
+            .get_all_topics()
+            .into_iter()
+            .map(|name| TopicSummary {
+                clients: self.storage.get_clients_in_topic(&name).len(),
+                name,
+            })
+            .collect();
+        topics.sort_by(|a, b| a.name.cmp(&b.name));
+        topics
+    }
+
+    /// The traffic counters of a topic together with its current subscriber count.
+    pub fn topic_stats(&self, topic: &str) -> TopicStats {
+        TopicStats {
+            clients: self.storage.get_clients_in_topic(topic).len(),
+            ..self.topic_stats.get(topic)
+        }
+    }
+
+    /// The counters shared by the client manager and the WebSocket handler.
+    pub fn counters(&self) -> &ServerCounters {
+        &self.counters
+    }
+
+    /// The server-wide totals since the server started.
+    pub fn server_stats(&self) -> ServerStats {
+        self.counters.snapshot(self.storage.client_count())
+    }
+
+    /// The stats of every topic that has seen traffic or has subscribers, by name.
+    pub fn all_topic_stats(&self) -> Vec<(String, TopicStats)> {
+        let mut topics = self.topic_stats.topics();
+        topics.extend(self.storage.get_all_topics());
+        topics.sort();
+        topics.dedup();
+        topics
+            .into_iter()
+            .map(|topic| {
+                let stats = self.topic_stats(&topic);
+                (topic, stats)
+            })
+            .collect()
+    }
+
+    /// The samples of published content and the analytics task's latest report.
+    pub fn content_analytics(&self) -> &ContentAnalytics {
+        &self.content_analytics
+    }
+
+    /// How long topic broadcasts took by subscriber count and content size.
+    pub fn broadcast_latency(&self) -> &BroadcastLatency {
+        &self.broadcast_latency
+    }
+
+    /// Takes a snapshot of the server's current load.
+    pub fn metrics(&self) -> Metrics {
+        let clients = self.storage.get_all_clients();
+        let canary = self.canary_status();
+        let analytics = self.content_analytics.report();
+        let latency = self.broadcast_latency.report();
+        Metrics {
+            connected_clients: clients.len(),
+            active_topics: self.storage.get_all_topics().len(),
+            pending_acks: self.storage.pending_ack_count(),
+            queued_messages: clients.iter().map(|client| client.sender.len()).sum(),
+            canary_latency_ms: canary
+                .latency
+                .map_or(0, |latency| latency.as_millis() as usize),
+            canary_failures: canary.consecutive_failures,
+            message_bytes_p50: analytics.size_metric(50.0),
+            message_bytes_p95: analytics.size_metric(95.0),
+            broadcast_latency_us_p50: latency.latency_metric(50.0),
+            broadcast_latency_us_p95: latency.latency_metric(95.0),
+        }
+    }
+
+    /// Moves every subscriber and the history of topic `from` into topic `into`.
+    /// Returns the number of clients moved.
+    pub async fn merge_topics(&self, from: &str, into: &str) -> usize {
+        let clients = self.storage.get_clients_in_topic(from);
+        for client in &clients {
+            self.move_client(client, from, into).await;
+        }
+        let mut next_seq = || self.storage.next_topic_seq(into);
+        for message in self.history.merge_topics(from, into, &mut next_seq) {
+            self.history_hooks.message_deleted(&message).await;
+        }
+        clients.len()
+    }
+
+    /// Moves the subscribers of `source` that `filter` matches into `destination`: by
+    /// ID, nickname, address, user agent or role. Returns the number of clients moved.
+    pub async fn split_topic(&self, source: &str, destination: &str, filter: &Regex) -> usize {
+        let clients: Vec<_> = self
+            .storage
+            .get_clients_in_topic(source)
+            .into_iter()
+            .filter(|client| self.matches(filter, client))
+            .collect();
+        for client in &clients {
+            self.move_client(client, source, destination).await;
+        }
+        clients.len()
+    }
+
+    /// Records the name a client signed a message with, if it signed it.
+    pub fn record_nickname(&self, client_id: Uuid, headers: &BTreeMap<String, String>) {
+        if let Some(name) = headers.get(NAME_HEADER) {
+            self.nicknames.insert(client_id, name.clone());
+        }
+    }
+
+    /// The name a client last signed a message with.
+    pub fn nickname(&self, client_id: &Uuid) -> Option<String> {
+        self.nicknames.get(client_id).map(|name| name.clone())
+    }
+
+    /// Whether `filter` matches the client's ID, its nickname or what it connected with:
+    /// its address, user agent or role.
+    fn matches(&self, filter: &Regex, client: &Client) -> bool {
+        [
+            Some(client.id.to_string()),
+            self.nickname(&client.id),
+            client.ip.map(|ip| ip.to_string()),
+            client.user_agent.clone(),
+            client.role.map(|role| role.to_string()),
+        ]
+        .into_iter()
+        .flatten()
+        .any(|field| filter.is_match(&field))
+    }
+
+    /// Resubscribes a client to another topic and tells it about the move.
+    async fn move_client(&self, client: &Client, from: &str, to: &str) {
+        self.subscribe_ignoring_limits(&client.id, to.to_string());
+        let notice = ServerMessage::TopicMoved {
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+        self.send_message_to_client(&client.id, notice).await;
+    }
+
+    /// Handles a message acknowledgment from a client.
+    pub async fn handle_message_acknowledgment(&self, client_id: Uuid, msg_id: Uuid) {
+        crate::log::middleware::log_ack(&client_id, &msg_id);
+        self.counters.ack_received();
+        self.storage.remove_pending_ack(&client_id, &msg_id);
+        self.acks.acknowledge(&msg_id, client_id);
+        self.work.acknowledge(&msg_id, client_id);
+        self.qos.acknowledge(client_id, msg_id);
+        // Tell the sender who has seen their message. Senders are found through history,
+        // so there are no receipts for messages the topic no longer retains, and only
+        // clients the message was delivered to can send one, once.
+        let read = self.readers.acknowledge(&msg_id, &client_id);
+        let Some(message) = self.get_history_message(&msg_id) else {
+            info!(%msg_id, %client_id, "Client acknowledged a message");
+            return;
+        };
+        let topic = &message.topic;
+        match message.provenance.origin() {
+            Some(Origin::Client(_)) if !read => {
+                debug!(%msg_id, %client_id, %topic, "ignored an acknowledgment from a non-recipient");
+            }
+            Some(Origin::Client(sender)) => {
+                let receipt = ServerMessage::MessageAcknowledged { msg_id, client_id };
+                self.send_message_to_client(sender, receipt).await;
+            }
+            _ => info!(%msg_id, %client_id, %topic, "Client acknowledged a message"),
+        }
+    }
+}
+
+/// Writes a message to a client's WebSocket, compressed if the client negotiated
+/// compression and the frame is at least `compress` bytes long. Returns false once the
+/// connection is gone.
+async fn forward(
+    sender: &mut SplitSink<WebSocket, Message>,
+    message: &Outgoing,
+    compress: Option<usize>,
+) -> bool {
+    if let ServerMessage::AttachmentChunk { frame } = &message.message {
+        return sender.send(Message::binary(frame.to_vec())).await.is_ok();
+    }
+    let Some(frame) = message.frame() else {
+        return true;
+    };
+    crate::log::middleware::log_outgoing(frame);
+    let frame = compress
+        .and_then(|min_bytes| message.compressed_frame(min_bytes))
+        .unwrap_or(frame);
+    // warp takes ownership of the text, so this copy is the only per-recipient allocation.
+    sender.send(Message::text(frame)).await.is_ok()
+}
+
+/// The ID of a topic message that is sent again until it is acknowledged.
+fn qos_id(message: &ServerMessage) -> Option<Uuid> {
+    match message {
+        ServerMessage::Topic { id, qos, .. } if !qos.is_fire_and_forget() => Some(*id),
+        _ => None,
+    }
+}
+
+/// Sends a close frame carrying `code` and closes the connection.
+pub(crate) async fn close_with<S>(sink: &mut S, code: CloseCode)
+where
+    S: Sink<Message> + Unpin,
+{
+    let _ = sink
+        .send(Message::close_with(code.code(), code.reason()))
+        .await;
+    let _ = sink.close().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::msg::Qos;
+    use crate::core::queue::{BackpressurePolicy, QueueReceiver as Receiver};
+    use crate::core::storage::InMemoryStorage;
+    use std::collections::BTreeMap;
+
+    // Helper to create a mock client and return its ID and receiver
+    fn setup_mock_client(manager: &ClientManager) -> (Uuid, Receiver) {
+        manager.add_internal_client()
+    }
+
+    fn create_manager() -> ClientManager {
+        let storage = Arc::new(InMemoryStorage::new());
+        ClientManager::new(storage)
+    }
+
+    #[tokio::test]
+    async fn test_remove_client() {
+        let manager = create_manager();
+        let (client_id, _rx) = setup_mock_client(&manager);
+        let topic = "general".to_string();
+        manager
+            .subscribe_client_to_topic(&client_id, topic.clone())
+            .unwrap();
+
+        assert_eq!(manager.get_all_clients().len(), 1);
+        assert_eq!(manager.get_clients_by_topic(&topic).len(), 1);
+
+        manager.remove_client(&client_id);
+
+        assert!(manager.get_all_clients().is_empty());
+        assert!(manager.get_clients_by_topic(&topic).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_send_private_message() {
+        let manager = create_manager();
+        let (client_id, mut rx) = setup_mock_client(&manager);
+
+        let msg = ServerMessage::Private {
+            id: Uuid::new_v4(),
+            content: "Hello".to_string(),
+        };
+
+        manager.send_private_message(client_id, msg.clone()).await;
+
+        let received = rx.recv().await.unwrap();
+        assert_eq!(
+            serde_json::to_string(&received).unwrap(),
+            serde_json::to_string(&msg).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_global() {
+        let manager = create_manager();
+        let (_client1_id, mut rx1) = setup_mock_client(&manager);
+        let (_client2_id, mut rx2) = setup_mock_client(&manager);
+
+        let msg = ServerMessage::Global {
+            id: Uuid::new_v4(),
+            content: "Global message".to_string(),
+        };
+
+        manager.broadcast_global(msg.clone()).await;
+
+        let received1 = rx1.recv().await.unwrap();
+        let received2 = rx2.recv().await.unwrap();
+
+        assert_eq!(
+            serde_json::to_string(&received1).unwrap(),
+            serde_json::to_string(&msg).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_string(&received2).unwrap(),
+            serde_json::to_string(&msg).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_to_topic() {
+        let manager = create_manager();
+        let topic1 = "topic1".to_string();
+        let topic2 = "topic2".to_string();
+
+        let (client1_id, mut rx1) = setup_mock_client(&manager);
+        manager
+            .subscribe_client_to_topic(&client1_id, topic1.clone())
+            .unwrap();
+
+        let (client2_id, mut rx2) = setup_mock_client(&manager);
+        manager
+            .subscribe_client_to_topic(&client2_id, topic1.clone())
+            .unwrap();
+
+        let (client3_id, mut rx3) = setup_mock_client(&manager);
+        manager
+            .subscribe_client_to_topic(&client3_id, topic2.clone())
+            .unwrap();
+
+        let msg = ServerMessage::Topic {
+            id: Uuid::new_v4(),
+            topic: topic1.clone(),
+            seq: 0,
+            sender: "Morpheus".to_string(),
+            content: "A message for topic1".to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            reply_to: None,
+            retained: false,
+        };
+
+        manager.broadcast_to_topic(&topic1, msg.clone(), None).await;
+
+        assert!(rx1.recv().await.is_some());
+        assert!(rx2.recv().await.is_some());
+        assert!(rx3.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_broadcasts_follow_subscription_changes() {
+        let manager = create_manager();
+        let (mover_id, mut mover_rx) = setup_mock_client(&manager);
+        manager
+            .subscribe_client_to_topic(&mover_id, "topic1".to_string())
+            .unwrap();
+        let notice = |topic: &str| ServerMessage::Topic {
+            id: Uuid::new_v4(),
+            topic: topic.to_string(),
+            seq: 0,
+            sender: "Morpheus".to_string(),
+            content: "notice".to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            reply_to: None,
+            retained: false,
+        };
+        manager
+            .broadcast_to_topic("topic1", notice("topic1"), None)
+            .await;
+        assert!(mover_rx.try_recv().is_ok());
+
+        // The topic's recipients are kept between broadcasts, so moving must refresh them.
+        manager
+            .subscribe_client_to_topic(&mover_id, "topic2".to_string())
+            .unwrap();
+        let receipt = manager
+            .broadcast_to_topic("topic1", notice("topic1"), None)
+            .await;
+        assert_eq!(receipt.targeted, 0);
+        assert!(mover_rx.try_recv().is_err());
+        manager
+            .broadcast_to_topic("topic2", notice("topic2"), None)
+            .await;
+        assert!(mover_rx.try_recv().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_topics_are_forgotten_when_their_last_subscriber_leaves() {
+        let manager = create_manager();
+        let (first_id, _first_rx) = setup_mock_client(&manager);
+        let (second_id, _second_rx) = setup_mock_client(&manager);
+        for id in [first_id, second_id] {
+            manager
+                .subscribe_client_to_topic(&id, "topic1".to_string())
+                .unwrap();
+        }
+
+        manager
+            .subscribe_client_to_topic(&first_id, "topic2".to_string())
+            .unwrap();
+        let mut topics = manager.get_all_topics();
+        topics.sort();
+        assert_eq!(topics, ["topic1", "topic2"]);
+
+        manager.remove_client(&second_id);
+        assert_eq!(manager.get_all_topics(), ["topic2"]);
+        manager.remove_client(&first_id);
+        assert!(manager.get_all_topics().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_broadcasts_reach_many_subscribers_with_bounded_latency() {
+        use crate::core::history::HistoryHook;
+        use async_trait::async_trait;
+
+        // A hook as slow as a stalled search index must not hold up delivery.
+        struct SlowHook;
+
+        #[async_trait]
+        impl HistoryHook for SlowHook {
+            async fn on_message_stored(&self, _message: &StoredMessage) {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+        }
+
+        let manager = Arc::new(create_manager());
+        manager.history_hooks().register(Arc::new(SlowHook));
+        let mut receivers: Vec<_> = (0..3 * fanout::SHARD_SIZE + 1)
+            .map(|_| {
+                let (client_id, rx) = setup_mock_client(&manager);
+                manager
+                    .subscribe_client_to_topic(&client_id, "crowd".to_string())
+                    .unwrap();
+                rx
+            })
+            .collect();
+        let message = ServerMessage::Topic {
+            id: Uuid::new_v4(),
+            topic: "crowd".to_string(),
+            seq: 0,
+            sender: "Morpheus".to_string(),
+            content: "everyone".to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            reply_to: None,
+            retained: false,
+        };
+
+        let started = Instant::now();
+        let broadcast = tokio::spawn({
+            let manager = manager.clone();
+            async move { manager.broadcast_to_topic("crowd", message, None).await }
+        });
+        for rx in &mut receivers {
+            tokio::time::timeout(Duration::from_secs(1), rx.recv())
+                .await
+                .expect("a subscriber waited too long")
+                .unwrap();
+        }
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(!broadcast.is_finished());
+        broadcast.abort();
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_to_topic_with_exclusion() {
+        let manager = create_manager();
+        let topic1 = "topic1".to_string();
+
+        let (client1_id, mut rx1) = setup_mock_client(&manager);
+        manager
+            .subscribe_client_to_topic(&client1_id, topic1.clone())
+            .unwrap();
 
-        let (client3_id, mut rx3) = setup_mock_client(&manager);
-        manager.subscribe_client_to_topic(&client3_id, topic2.clone());
+        let (client2_id, mut rx2) = setup_mock_client(&manager);
+        manager
+            .subscribe_client_to_topic(&client2_id, topic1.clone())
+            .unwrap();
 
         let msg = ServerMessage::Topic {
             id: Uuid::new_v4(),
             topic: topic1.clone(),
+            seq: 0,
+            sender: client1_id.to_string(),
+            content: "A message from client1".to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            reply_to: None,
+            retained: false,
+        };
+
+        manager
+            .broadcast_to_topic(&topic1, msg.clone(), Some(client1_id))
+            .await;
+
+        assert!(rx1.try_recv().is_err());
+        assert!(rx2.recv().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_to_topic_records_history() {
+        use crate::core::history::HistoryHook;
+        use async_trait::async_trait;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Default)]
+        struct CountingHook(AtomicUsize);
+
+        #[async_trait]
+        impl HistoryHook for CountingHook {
+            async fn on_message_stored(&self, _message: &StoredMessage) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let manager = create_manager();
+        let hook = Arc::new(CountingHook::default());
+        manager.history_hooks().register(hook.clone());
+
+        let msg_id = Uuid::new_v4();
+        let msg = ServerMessage::Topic {
+            id: msg_id,
+            topic: "topic1".to_string(),
+            seq: 0,
             sender: "Morpheus".to_string(),
-            content: "A message for topic1".to_string(),
+            content: "Remember".to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            reply_to: None,
+            retained: false,
         };
+        manager.broadcast_to_topic("topic1", msg, None).await;
 
-        manager.broadcast_to_topic(&topic1, msg.clone(), None).await;
+        let history = manager.get_topic_history("topic1", 10);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].id, msg_id);
+        assert_eq!(hook.0.load(Ordering::SeqCst), 1);
+
+        assert!(manager.delete_from_history(&msg_id).await.is_some());
+        assert!(manager.get_topic_history("topic1", 10).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_replay_republishes_messages_from_history() {
+        let manager = create_manager();
+        for content in ["one", "two", "three"] {
+            let msg = ServerMessage::Topic {
+                id: Uuid::new_v4(),
+                topic: "topic1".to_string(),
+                seq: 0,
+                sender: "alice".to_string(),
+                content: content.to_string(),
+                headers: BTreeMap::new(),
+                qos: Qos::FireAndForget,
+                reply_to: None,
+                retained: false,
+            };
+            manager.broadcast_to_topic("topic1", msg, None).await;
+        }
+        let originals = manager.get_topic_history("topic1", 2);
+        let (client_id, mut rx) = setup_mock_client(&manager);
+        manager
+            .subscribe_client_to_topic(&client_id, "topic1".to_string())
+            .unwrap();
+
+        let receipts = manager.replay(&originals).await;
+        assert_eq!(receipts.len(), 2);
+        for (original, expected_seq) in originals.iter().zip([4, 5]) {
+            match rx.recv().await.unwrap() {
+                ServerMessage::Topic {
+                    id,
+                    seq,
+                    sender,
+                    content,
+                    ..
+                } => {
+                    assert_ne!(id, original.id);
+                    assert_eq!(seq, expected_seq);
+                    assert_eq!(sender, "alice");
+                    assert_eq!(content, original.content);
+                    let replayed = manager.get_history_message(&id).unwrap();
+                    assert_eq!(replayed.provenance.entries().len(), 2);
+                }
+                other => panic!("Unexpected message {:?}", other),
+            }
+        }
+        assert_eq!(manager.get_topic_history("topic1", 10).len(), 5);
+        assert!(manager.replay(&[]).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_only_recipients_send_read_receipts() {
+        let manager = create_manager();
+        let (publisher, mut publisher_rx) = setup_mock_client(&manager);
+        let (reader, _reader_rx) = setup_mock_client(&manager);
+        let (stranger, _stranger_rx) = setup_mock_client(&manager);
+        for client_id in [publisher, reader] {
+            manager
+                .subscribe_client_to_topic(&client_id, "topic1".to_string())
+                .unwrap();
+        }
+        let msg_id = Uuid::new_v4();
+        let msg = ServerMessage::Topic {
+            id: msg_id,
+            topic: "topic1".to_string(),
+            seq: 0,
+            sender: publisher.to_string(),
+            content: "Seen it?".to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            reply_to: None,
+            retained: false,
+        };
+        manager
+            .broadcast_to_topic_with_provenance(
+                "topic1",
+                msg,
+                Some(publisher),
+                Provenance::new(Origin::Client(publisher)),
+            )
+            .await;
+
+        manager
+            .handle_message_acknowledgment(stranger, msg_id)
+            .await;
+        assert!(publisher_rx.try_recv().is_err());
+        manager.handle_message_acknowledgment(reader, msg_id).await;
+        match publisher_rx.try_recv() {
+            Ok(ServerMessage::MessageAcknowledged {
+                msg_id: id,
+                client_id,
+            }) => {
+                assert_eq!(id, msg_id);
+                assert_eq!(client_id, reader);
+            }
+            other => panic!("Unexpected message {:?}", other),
+        }
+        manager.handle_message_acknowledgment(reader, msg_id).await;
+        assert!(publisher_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connect_session_resumes_identity_and_pending_acks() {
+        let manager = create_manager();
+        let (old_id, mut old_rx) = setup_mock_client(&manager);
+        manager
+            .connect_session(old_id, "topic1".to_string(), None)
+            .await;
+        let session_id = match old_rx.recv().await.unwrap() {
+            ServerMessage::Welcome {
+                session_id,
+                resumed,
+                ..
+            } => {
+                assert!(!resumed);
+                session_id
+            }
+            other => panic!("Expected Welcome, got {:?}", other),
+        };
+
+        let msg_id = Uuid::new_v4();
+        let msg = ServerMessage::Private {
+            id: msg_id,
+            content: "Unacknowledged".to_string(),
+        };
+        manager.send_private_message(old_id, msg).await;
+        manager.remove_client(&old_id);
+
+        let (new_id, mut new_rx) = setup_mock_client(&manager);
+        let resumed_id = manager
+            .connect_session(new_id, "topic1".to_string(), Some(session_id))
+            .await;
+
+        assert_eq!(resumed_id, old_id);
+        assert!(manager.get_all_clients().iter().all(|c| c.id != new_id));
+        assert_eq!(manager.get_clients_by_topic("topic1").len(), 1);
+        assert!(matches!(
+            new_rx.recv().await.unwrap(),
+            ServerMessage::Welcome { resumed: true, .. }
+        ));
+        assert_eq!(new_rx.recv().await.unwrap().id(), Some(msg_id));
+
+        // Both connections count towards the identity's history; the new ID never had one.
+        let whois = manager.whois(&old_id).unwrap();
+        assert_eq!(whois.record.sessions, 2);
+        assert!(whois.online);
+        assert!(manager.whois(&new_id).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_resumed_qos_messages_are_redelivered() {
+        let manager = create_manager();
+        let (old_id, mut old_rx) = setup_mock_client(&manager);
+        manager
+            .connect_session(old_id, "news".to_string(), None)
+            .await;
+        let Some(ServerMessage::Welcome { session_id, .. }) = old_rx.recv().await else {
+            panic!("Expected Welcome");
+        };
+        let msg_id = Uuid::new_v4();
+        let message = ServerMessage::Topic {
+            id: msg_id,
+            topic: "news".to_string(),
+            seq: 0,
+            sender: "Morpheus".to_string(),
+            content: "hello".to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::AtLeastOnce,
+            reply_to: None,
+            retained: false,
+        };
+        manager.broadcast_to_topic("news", message, None).await;
+        manager.remove_client(&old_id);
+        assert_eq!(manager.unacked_qos_count(), 0);
+
+        let (new_id, mut new_rx) = setup_mock_client(&manager);
+        manager
+            .connect_session(new_id, "news".to_string(), Some(session_id))
+            .await;
+        assert_eq!(manager.unacked_qos_count(), 1);
+
+        // The resent message is lost on the way, so it is sent again.
+        while new_rx.try_recv().is_ok() {}
+        assert_eq!(manager.redeliver_unacked(Duration::ZERO), 0);
+        assert_eq!(new_rx.recv().await.and_then(|m| m.id()), Some(msg_id));
+    }
+
+    #[tokio::test]
+    async fn test_connect_session_with_unknown_session_starts_new_one() {
+        let manager = create_manager();
+        let (client_id, mut rx) = setup_mock_client(&manager);
+        let resumed_id = manager
+            .connect_session(client_id, "topic1".to_string(), Some(Uuid::new_v4()))
+            .await;
+
+        assert_eq!(resumed_id, client_id);
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            ServerMessage::Welcome { resumed: false, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_slow_client_is_disconnected() {
+        let storage = Arc::new(InMemoryStorage::new());
+        let manager = ClientManager::new(storage).with_queue_config(QueueConfig {
+            capacity: 1,
+            policy: BackpressurePolicy::Disconnect,
+        });
+        let (slow_id, _slow_rx) = setup_mock_client(&manager);
+        let (fast_id, mut fast_rx) = setup_mock_client(&manager);
+
+        for n in 0..2 {
+            let msg = ServerMessage::Global {
+                id: Uuid::new_v4(),
+                content: n.to_string(),
+            };
+            manager.broadcast_global(msg).await;
+            assert!(fast_rx.recv().await.is_some());
+        }
+
+        let remaining: Vec<_> = manager.get_all_clients().iter().map(|c| c.id).collect();
+        assert_eq!(remaining, vec![fast_id]);
+        assert!(!remaining.contains(&slow_id));
+    }
+
+    #[tokio::test]
+    async fn test_merge_and_split_topics() {
+        let manager = create_manager();
+        let (client1_id, mut rx1) = setup_mock_client(&manager);
+        manager
+            .subscribe_client_to_topic(&client1_id, "a".to_string())
+            .unwrap();
+        let (client2_id, _rx2) = setup_mock_client(&manager);
+        manager
+            .subscribe_client_to_topic(&client2_id, "a".to_string())
+            .unwrap();
+
+        assert_eq!(manager.merge_topics("a", "b").await, 2);
+        assert!(manager.get_clients_by_topic("a").is_empty());
+        assert_eq!(manager.get_clients_by_topic("b").len(), 2);
+        assert!(matches!(
+            rx1.recv().await.unwrap(),
+            ServerMessage::TopicMoved { from, to } if from == "a" && to == "b"
+        ));
+
+        let filter = Regex::new(&format!("^{}$", client1_id)).unwrap();
+        assert_eq!(manager.split_topic("b", "c", &filter).await, 1);
+        let moved: Vec<_> = manager
+            .get_clients_by_topic("c")
+            .into_iter()
+            .map(|c| c.id)
+            .collect();
+        assert_eq!(moved, vec![client1_id]);
+        assert_eq!(manager.get_clients_by_topic("b").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_split_topic_by_nickname() {
+        let manager = create_manager();
+        let (alice, _rx1) = setup_mock_client(&manager);
+        let (bob, _rx2) = setup_mock_client(&manager);
+        for id in [alice, bob] {
+            manager
+                .subscribe_client_to_topic(&id, "a".to_string())
+                .unwrap();
+        }
+        let signed = |name: &str| BTreeMap::from([(NAME_HEADER.to_string(), name.to_string())]);
+        manager.record_nickname(alice, &signed("alice"));
+        manager.record_nickname(bob, &signed("bob"));
+        manager.record_nickname(bob, &BTreeMap::new());
+
+        let filter = Regex::new("^alice$").unwrap();
+        assert_eq!(manager.split_topic("a", "b", &filter).await, 1);
+        let moved: Vec<_> = manager
+            .get_clients_by_topic("b")
+            .into_iter()
+            .map(|c| c.id)
+            .collect();
+        assert_eq!(moved, vec![alice]);
+        assert_eq!(manager.nickname(&bob).as_deref(), Some("bob"));
+
+        manager.remove_client(&alice);
+        assert_eq!(manager.nickname(&alice), None);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_snapshot() {
+        let manager = create_manager();
+        let (client_id, _rx) = setup_mock_client(&manager);
+        manager
+            .subscribe_client_to_topic(&client_id, "news".to_string())
+            .unwrap();
+        manager
+            .connect_session(client_id, "news".to_string(), None)
+            .await;
+        manager
+            .broadcast_to_topic(
+                "news",
+                ServerMessage::Topic {
+                    id: Uuid::new_v4(),
+                    topic: "news".to_string(),
+                    seq: 0,
+                    sender: "Morpheus".to_string(),
+                    content: "hello".to_string(),
+                    headers: BTreeMap::new(),
+                    qos: Qos::FireAndForget,
+                    reply_to: None,
+                    retained: false,
+                },
+                None,
+            )
+            .await;
+
+        let metrics = manager.metrics();
+        assert_eq!(metrics.connected_clients, 1);
+        assert_eq!(metrics.active_topics, 1);
+        assert_eq!(metrics.pending_acks, 1);
+        // The welcome message and the topic message.
+        assert_eq!(metrics.queued_messages, 2);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_receipt_tracks_acks() {
+        let manager = create_manager();
+        let (client1_id, _rx1) = setup_mock_client(&manager);
+        let (client2_id, _rx2) = setup_mock_client(&manager);
+        let (client3_id, rx3) = setup_mock_client(&manager);
+        drop(rx3);
+
+        let msg_id = Uuid::new_v4();
+        let receipt = manager
+            .broadcast_global(ServerMessage::Global {
+                id: msg_id,
+                content: "Global message".to_string(),
+            })
+            .await;
+        assert_eq!(receipt.msg_id, Some(msg_id));
+        assert_eq!(receipt.targeted, 3);
+        assert_eq!(receipt.failed, vec![client3_id]);
+        assert_eq!(receipt.enqueued(), 2);
+
+        manager
+            .handle_message_acknowledgment(client1_id, msg_id)
+            .await;
+        let outcome = receipt.wait_for_acks(Duration::from_millis(50)).await;
+        assert_eq!(outcome.acked, vec![client1_id]);
+        assert_eq!(outcome.missing, vec![client2_id]);
+        assert!(!outcome.is_complete());
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_receipt_resolves_when_all_acked() {
+        let manager = Arc::new(create_manager());
+        let (client1_id, _rx1) = setup_mock_client(&manager);
+        let (client2_id, _rx2) = setup_mock_client(&manager);
+        manager
+            .subscribe_client_to_topic(&client1_id, "news".to_string())
+            .unwrap();
+        manager
+            .subscribe_client_to_topic(&client2_id, "news".to_string())
+            .unwrap();
+
+        let msg_id = Uuid::new_v4();
+        let receipt = manager
+            .broadcast_to_topic(
+                "news",
+                ServerMessage::Topic {
+                    id: msg_id,
+                    topic: "news".to_string(),
+                    seq: 0,
+                    sender: "Morpheus".to_string(),
+                    content: "hello".to_string(),
+                    headers: BTreeMap::new(),
+                    qos: Qos::FireAndForget,
+                    reply_to: None,
+                    retained: false,
+                },
+                None,
+            )
+            .await;
+        let acker = manager.clone();
+        tokio::spawn(async move {
+            for client_id in [client1_id, client2_id] {
+                acker.handle_message_acknowledgment(client_id, msg_id).await;
+            }
+        });
+
+        let outcome = receipt.wait_for_acks(Duration::from_secs(5)).await;
+        assert!(outcome.is_complete());
+        assert_eq!(outcome.acked.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_qos_messages_are_redelivered_until_acked() {
+        let manager = create_manager();
+        let (client1_id, mut rx1) = setup_mock_client(&manager);
+        let (client2_id, mut rx2) = setup_mock_client(&manager);
+        for client_id in [client1_id, client2_id] {
+            manager
+                .subscribe_client_to_topic(&client_id, "news".to_string())
+                .unwrap();
+        }
 
+        let msg_id = Uuid::new_v4();
+        manager
+            .broadcast_to_topic(
+                "news",
+                ServerMessage::Topic {
+                    id: msg_id,
+                    topic: "news".to_string(),
+                    seq: 0,
+                    sender: "Morpheus".to_string(),
+                    content: "hello".to_string(),
+                    headers: BTreeMap::new(),
+                    qos: Qos::AtLeastOnce,
+                    reply_to: None,
+                    retained: false,
+                },
+                None,
+            )
+            .await;
         assert!(rx1.recv().await.is_some());
         assert!(rx2.recv().await.is_some());
-        assert!(rx3.try_recv().is_err());
+        assert_eq!(manager.unacked_qos_count(), 2);
+
+        manager
+            .handle_message_acknowledgment(client1_id, msg_id)
+            .await;
+        assert_eq!(manager.redeliver_unacked(Duration::ZERO), 0);
+        assert!(rx1.try_recv().is_err());
+        assert_eq!(rx2.recv().await.and_then(|m| m.id()), Some(msg_id));
+
+        // Every redelivery counts as another attempt; the last one is given up on.
+        for _ in 2..MAX_DELIVERY_ATTEMPTS {
+            manager.redeliver_unacked(Duration::ZERO);
+        }
+        assert_eq!(manager.redeliver_unacked(Duration::ZERO), 1);
+        assert_eq!(manager.unacked_qos_count(), 0);
     }
 
     #[tokio::test]
-    async fn test_broadcast_to_topic_with_exclusion() {
+    async fn test_kick_client() {
         let manager = create_manager();
-        let topic1 = "topic1".to_string();
+        let (client_id, mut rx) = setup_mock_client(&manager);
+        let closer = manager.storage.get_client(&client_id).unwrap().closer;
+        manager.record_nickname(
+            client_id,
+            &BTreeMap::from([(NAME_HEADER.to_string(), "spammer".to_string())]),
+        );
 
-        let (client1_id, mut rx1) = setup_mock_client(&manager);
-        manager.subscribe_client_to_topic(&client1_id, topic1.clone());
+        assert!(manager.kick_client(&client_id, Some("spam".to_string())));
+        assert_eq!(closer.code(), Some(CloseCode::Kicked));
+        assert!(manager.get_all_clients().is_empty());
+        assert_eq!(manager.nickname(&client_id), None);
+        match rx.recv().await {
+            Some(ServerMessage::Kicked { reason }) => assert_eq!(reason.as_deref(), Some("spam")),
+            other => panic!("expected a kick notice, got {:?}", other),
+        }
+        assert!(rx.recv().await.is_none());
+        assert!(!manager.kick_client(&client_id, None));
+    }
+
+    #[tokio::test]
+    async fn test_topic_rules_must_be_accepted() {
+        use crate::core::policy::TopicPolicy;
+
+        let manager = create_manager().with_policies(Policies {
+            topics: HashMap::from([(
+                "dojo".to_string(),
+                TopicPolicy {
+                    rules: Some("No agents.".to_string()),
+                    rules_version: 2,
+                    ..TopicPolicy::default()
+                },
+            )]),
+            ..Policies::default()
+        });
+        let (client_id, mut rx) = setup_mock_client(&manager);
+
+        assert!(!manager.join_topic(&client_id, "dojo".to_string()).await);
+        assert!(manager.get_clients_by_topic("dojo").is_empty());
+        match rx.recv().await {
+            Some(ServerMessage::TopicInfo { rules, version, .. }) => {
+                assert_eq!(rules, "No agents.");
+                assert_eq!(version, 2);
+            }
+            other => panic!("expected the topic rules, got {:?}", other),
+        }
+
+        assert!(
+            !manager
+                .accept_terms(&client_id, "dojo".to_string(), 1)
+                .await
+        );
+        assert!(
+            manager
+                .accept_terms(&client_id, "dojo".to_string(), 2)
+                .await
+        );
+        assert_eq!(manager.get_clients_by_topic("dojo").len(), 1);
+        assert!(manager.join_topic(&client_id, "dojo".to_string()).await);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_topic_client_limit() {
+        use crate::core::policy::Limits;
 
+        let manager = create_manager().with_policies(Policies {
+            limits: Limits {
+                max_clients_per_topic: Some(1),
+                ..Limits::default()
+            },
+            ..Policies::default()
+        });
+        let (client1_id, _rx1) = setup_mock_client(&manager);
         let (client2_id, mut rx2) = setup_mock_client(&manager);
-        manager.subscribe_client_to_topic(&client2_id, topic1.clone());
+
+        manager
+            .subscribe_client_to_topic(&client1_id, "news".to_string())
+            .unwrap();
+        // Subscribing again does not count the client twice.
+        manager
+            .subscribe_client_to_topic(&client1_id, "news".to_string())
+            .unwrap();
+        assert_eq!(
+            manager.subscribe_client_to_topic(&client2_id, "news".to_string()),
+            Err(LimitExceeded {
+                limit: Limit::TopicClients,
+                max: 1
+            })
+        );
+
+        assert!(!manager.join_topic(&client2_id, "news".to_string()).await);
+        assert!(matches!(
+            rx2.recv().await,
+            Some(ServerMessage::LimitExceeded {
+                limit: Limit::TopicClients,
+                max: 1
+            })
+        ));
+        assert_eq!(manager.get_clients_by_topic("news").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_queue_topic_delivers_to_one_subscriber() {
+        use crate::core::policy::TopicPolicy;
+
+        let manager = create_manager().with_policies(Policies {
+            topics: HashMap::from([(
+                "jobs".to_string(),
+                TopicPolicy {
+                    delivery: Delivery::RoundRobin,
+                    ..TopicPolicy::default()
+                },
+            )]),
+            ..Policies::default()
+        });
+        let (worker1, mut rx1) = setup_mock_client(&manager);
+        let (worker2, mut rx2) = setup_mock_client(&manager);
+        for worker in [worker1, worker2] {
+            manager
+                .subscribe_client_to_topic(&worker, "jobs".to_string())
+                .unwrap();
+        }
+
+        let job = |content: &str| ServerMessage::Topic {
+            id: Uuid::new_v4(),
+            topic: "jobs".to_string(),
+            seq: 0,
+            sender: "Morpheus".to_string(),
+            content: content.to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            reply_to: None,
+            retained: false,
+        };
+        let first = manager.broadcast_to_topic("jobs", job("one"), None).await;
+        let second = manager.broadcast_to_topic("jobs", job("two"), None).await;
+        assert_eq!((first.targeted, second.targeted), (1, 1));
+        assert!(
+            matches!(rx1.recv().await, Some(ServerMessage::Topic { content, .. }) if content == "one")
+        );
+        assert!(
+            matches!(rx2.recv().await, Some(ServerMessage::Topic { content, .. }) if content == "two")
+        );
+        assert!(rx1.try_recv().is_err());
+        assert_eq!(manager.in_flight_count(), 2);
+
+        // The first job is acknowledged; the second goes to the other worker on timeout.
+        manager
+            .handle_message_acknowledgment(worker1, first.msg_id.unwrap())
+            .await;
+        assert_eq!(manager.redeliver_expired(Duration::ZERO).await, 0);
+        match rx1.recv().await {
+            Some(ServerMessage::Topic { id, content, .. }) => {
+                assert_eq!(content, "two");
+                manager.handle_message_acknowledgment(worker1, id).await;
+            }
+            other => panic!("expected a redelivered job, got {:?}", other),
+        }
+        assert_eq!(manager.in_flight_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_queue_message_dropped_after_max_attempts() {
+        use crate::core::policy::TopicPolicy;
+
+        let manager = create_manager().with_policies(Policies {
+            topics: HashMap::from([(
+                "jobs".to_string(),
+                TopicPolicy {
+                    delivery: Delivery::LeastLoaded,
+                    ..TopicPolicy::default()
+                },
+            )]),
+            ..Policies::default()
+        });
+        let (worker, _rx) = setup_mock_client(&manager);
+        manager
+            .subscribe_client_to_topic(&worker, "jobs".to_string())
+            .unwrap();
+        let job = ServerMessage::Topic {
+            id: Uuid::new_v4(),
+            topic: "jobs".to_string(),
+            seq: 0,
+            sender: "Morpheus".to_string(),
+            content: "never acked".to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            reply_to: None,
+            retained: false,
+        };
+        manager.broadcast_to_topic("jobs", job, None).await;
+
+        for _ in 1..MAX_DELIVERY_ATTEMPTS {
+            assert_eq!(manager.redeliver_expired(Duration::ZERO).await, 0);
+        }
+        assert_eq!(manager.redeliver_expired(Duration::ZERO).await, 1);
+        assert_eq!(manager.in_flight_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_topic_stats() {
+        let manager = create_manager();
+        let (client1_id, _rx1) = setup_mock_client(&manager);
+        let (client2_id, _rx2) = setup_mock_client(&manager);
+        manager
+            .subscribe_client_to_topic(&client1_id, "news".to_string())
+            .unwrap();
+        manager
+            .subscribe_client_to_topic(&client2_id, "news".to_string())
+            .unwrap();
 
         let msg = ServerMessage::Topic {
             id: Uuid::new_v4(),
-            topic: topic1.clone(),
+            topic: "news".to_string(),
+            seq: 0,
             sender: client1_id.to_string(),
-            content: "A message from client1".to_string(),
+            content: "hello".to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            reply_to: None,
+            retained: false,
         };
+        manager
+            .broadcast_to_topic("news", msg, Some(client1_id))
+            .await;
+
+        let stats = manager.topic_stats("news");
+        assert_eq!(stats.messages, 1);
+        assert_eq!(stats.bytes_in, 5);
+        assert_eq!(stats.bytes_out, 5);
+        assert_eq!(stats.clients, 2);
+        assert!(stats.last_activity.is_some());
+        assert_eq!(manager.topic_stats("empty").clients, 0);
+        assert_eq!(
+            manager
+                .all_topic_stats()
+                .into_iter()
+                .map(|(topic, _)| topic)
+                .collect::<Vec<_>>(),
+            vec!["news"]
+        );
+    }
 
+    #[tokio::test]
+    async fn test_server_stats_count_routed_messages_acks_and_errors() {
+        let manager = create_manager();
+        let (client_id, _rx) = setup_mock_client(&manager);
+        let global = ServerMessage::Global {
+            id: Uuid::new_v4(),
+            content: "hello".to_string(),
+        };
+        manager.broadcast_global(global).await;
         manager
-            .broadcast_to_topic(&topic1, msg.clone(), Some(client1_id))
+            .handle_message_acknowledgment(client_id, Uuid::new_v4())
             .await;
+        let error = ServerMessage::Error {
+            message: "no".to_string(),
+        };
+        manager.send_private_message(client_id, error).await;
 
-        assert!(rx1.try_recv().is_err());
-        assert!(rx2.recv().await.is_some());
+        let stats = manager.server_stats();
+        assert_eq!(stats.clients, 1);
+        assert_eq!(
+            (stats.messages_routed, stats.acks_received, stats.errors),
+            (1, 1, 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_membership_changes_are_published() {
+        use crate::core::membership::Change;
+
+        let manager = create_manager();
+        let (client1_id, _rx1) = setup_mock_client(&manager);
+        let (client2_id, _rx2) = setup_mock_client(&manager);
+        let (_, mut events) = manager.membership().subscribe(None).unwrap();
+
+        manager
+            .subscribe_client_to_topic(&client1_id, "news".to_string())
+            .unwrap();
+        manager
+            .subscribe_client_to_topic(&client2_id, "news".to_string())
+            .unwrap();
+        manager.merge_topics("news", "archive").await;
+        manager.kick_client(&client1_id, None);
+        manager.remove_client(&client2_id);
+
+        let mut changes = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            changes.push((event.topic, event.change, event.count));
+        }
+        let expected = [
+            ("news", Change::Joined, 1),
+            ("news", Change::Joined, 2),
+            ("news", Change::Left, 1),
+            ("archive", Change::Joined, 1),
+            ("news", Change::Left, 0),
+            ("archive", Change::Joined, 2),
+            ("archive", Change::Left, 1),
+            ("archive", Change::Left, 0),
+        ];
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(topic, change, count)| (topic.to_string(), change, count))
+            .collect();
+        assert_eq!(changes, expected);
+        let snapshot = manager.membership().snapshot();
+        assert_eq!(snapshot.sequence, 8);
+        assert!(snapshot.topics.is_empty());
     }
 }
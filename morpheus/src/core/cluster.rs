@@ -0,0 +1,202 @@
+use crate::core::{
+    client_manager::ClientManager,
+    msg::ServerMessage,
+    provenance::{Origin, Provenance},
+};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::mpsc;
+use tokio_tungstenite::{connect_async, tungstenite::Message as PeerMessage};
+use warp::ws::WebSocket;
+
+/// How many broadcasts may be buffered per peer while its link is down.
+const LINK_BUFFER: usize = 1000;
+/// The delay after the first failed connection attempt to a peer.
+const LINK_RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+/// The maximum delay between connection attempts to a peer.
+const LINK_RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Messages exchanged between morpheus nodes over the `/cluster` WebSocket.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum ClusterMessage {
+    /// The first message on every link, identifying the connecting node.
+    Hello {
+        node_id: String,
+        secret: Option<String>,
+    },
+    /// A topic message published on `origin_node`.
+    TopicBroadcast {
+        origin_node: String,
+        message: ServerMessage,
+    },
+}
+
+/// Settings for running this server as a node in a cluster.
+#[derive(Clone, Debug, Default)]
+pub struct ClusterConfig {
+    /// A name unique within the cluster.
+    pub node_id: String,
+    /// The `/cluster` WebSocket URLs of every other node.
+    pub peers: Vec<String>,
+    /// A shared secret peers must present; `None` accepts any peer.
+    pub secret: Option<String>,
+}
+
+/// A node in a full mesh of morpheus servers. Every node dials every other node and
+/// forwards topic messages published locally; messages received from peers are only
+/// delivered to local clients, so they are never forwarded twice.
+pub struct Cluster {
+    node_id: String,
+    secret: Option<String>,
+    links: Vec<mpsc::Sender<String>>,
+}
+
+impl Cluster {
+    /// Starts a link task for every configured peer.
+    pub fn start(config: ClusterConfig) -> Arc<Self> {
+        let hello = serde_json::to_string(&ClusterMessage::Hello {
+            node_id: config.node_id.clone(),
+            secret: config.secret.clone(),
+        })
+        .expect("Failed to serialize cluster hello");
+
+        let links = config
+            .peers
+            .into_iter()
+            .map(|peer| {
+                let (tx, rx) = mpsc::channel(LINK_BUFFER);
+                tokio::spawn(run_link(peer, hello.clone(), rx));
+                tx
+            })
+            .collect();
+
+        Arc::new(Self {
+            node_id: config.node_id,
+            secret: config.secret,
+            links,
+        })
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Forwards a locally published topic message to every peer.
+    pub fn publish(&self, message: &ServerMessage) {
+        let broadcast = ClusterMessage::TopicBroadcast {
+            origin_node: self.node_id.clone(),
+            message: message.clone(),
+        };
+        let text = match serde_json::to_string(&broadcast) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("Failed to serialize cluster broadcast: {}", e);
+                return;
+            }
+        };
+        for link in &self.links {
+            // A full buffer means the peer has been unreachable for a while; drop.
+            let _ = link.try_send(text.clone());
+        }
+    }
+
+    fn accepts(&self, secret: &Option<String>) -> bool {
+        self.secret.is_none() || self.secret == *secret
+    }
+}
+
+/// Keeps an outbound link to `peer` alive, sending every queued broadcast over it.
+async fn run_link(peer: String, hello: String, mut rx: mpsc::Receiver<String>) {
+    let mut delay = LINK_RETRY_BASE_DELAY;
+    loop {
+        match connect_async(peer.as_str()).await {
+            Ok((ws, _)) => {
+                println!("Cluster link to {} established.", peer);
+                delay = LINK_RETRY_BASE_DELAY;
+                let (mut write, mut read) = ws.split();
+                if write.send(PeerMessage::Text(hello.clone())).await.is_ok() {
+                    loop {
+                        tokio::select! {
+                            text = rx.recv() => match text {
+                                Some(text) => {
+                                    if write.send(PeerMessage::Text(text)).await.is_err() {
+                                        break;
+                                    }
+                                }
+                                None => return, // The cluster was dropped.
+                            },
+                            // Reading keeps pongs flowing and tells us when the peer goes away.
+                            frame = read.next() => {
+                                if !matches!(frame, Some(Ok(_))) {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                eprintln!("Cluster link to {} lost.", peer);
+            }
+            Err(e) => eprintln!("Failed to connect to cluster peer {}: {}", peer, e),
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(LINK_RETRY_MAX_DELAY);
+    }
+}
+
+/// Handles an inbound link from another node, delivering its broadcasts locally.
+pub async fn peer_connected(ws: WebSocket, client_manager: Arc<ClientManager>) {
+    let Some(cluster) = client_manager.cluster() else {
+        return;
+    };
+    let (_, mut read) = ws.split();
+
+    let peer_id = match next_message(&mut read).await {
+        Some(ClusterMessage::Hello { node_id, secret }) if cluster.accepts(&secret) => node_id,
+        _ => {
+            eprintln!("Rejected cluster peer with a missing or invalid hello.");
+            return;
+        }
+    };
+    println!("Cluster peer {} connected.", peer_id);
+
+    while let Some(message) = next_message(&mut read).await {
+        if let ClusterMessage::TopicBroadcast {
+            origin_node,
+            message,
+        } = message
+        {
+            if origin_node == cluster.node_id() {
+                continue;
+            }
+            if let ServerMessage::Topic { topic, .. } = &message {
+                let topic = topic.clone();
+                let provenance =
+                    Provenance::new(Origin::Bridge(format!("cluster:{}", origin_node)));
+                client_manager
+                    .deliver_to_topic(&topic, message, None, provenance)
+                    .await;
+            }
+        }
+    }
+    println!("Cluster peer {} disconnected.", peer_id);
+}
+
+/// Reads the next cluster message, skipping frames that cannot be decoded.
+async fn next_message(
+    read: &mut futures_util::stream::SplitStream<WebSocket>,
+) -> Option<ClusterMessage> {
+    while let Some(Ok(frame)) = read.next().await {
+        if frame.is_close() {
+            return None;
+        }
+        if let Ok(text) = frame.to_str() {
+            match serde_json::from_str(text) {
+                Ok(message) => return Some(message),
+                Err(e) => eprintln!("Invalid cluster message: {}", e),
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,104 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use serde::Deserialize;
+use std::{
+    io::{Read, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// Frames shorter than this are sent as they are unless configured otherwise; deflate
+/// and base64 would not make them much smaller.
+pub const DEFAULT_MIN_COMPRESS_BYTES: usize = 1024;
+
+/// Compression of frames for clients that negotiate it, from the `[compression]` config
+/// section: frames of at least `min_bytes` are sent as `Compressed` frames once a client
+/// asked for them with `Negotiate`. Clients are told it is off when it is disabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    pub min_bytes: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_bytes: DEFAULT_MIN_COMPRESS_BYTES,
+        }
+    }
+}
+
+/// Whether a connection negotiated compression, shared by the task reading its frames,
+/// which negotiates it, and the task writing to it.
+#[derive(Clone, Debug, Default)]
+pub struct CompressionHandle {
+    enabled: Arc<AtomicBool>,
+}
+
+impl CompressionHandle {
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+}
+
+/// The payload of a `Compressed` frame carrying `frame`: the frame deflated, in base64.
+pub fn deflate(frame: &str) -> String {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    // Writing to a Vec cannot fail.
+    encoder.write_all(frame.as_bytes()).unwrap();
+    STANDARD.encode(encoder.finish().unwrap())
+}
+
+/// The frame inside the payload of a `Compressed` frame. Fails if the payload is not
+/// deflated base64 text or inflates to more than `max` bytes, which is not read.
+pub fn inflate(data: &str, max: usize) -> Result<String, String> {
+    let bytes = STANDARD
+        .decode(data)
+        .map_err(|e| format!("Invalid compressed frame: {}", e))?;
+    let mut frame = String::new();
+    DeflateDecoder::new(bytes.as_slice())
+        .take(max as u64 + 1)
+        .read_to_string(&mut frame)
+        .map_err(|e| format!("Invalid compressed frame: {}", e))?;
+    if frame.len() > max {
+        return Err(format!(
+            "Compressed frame too large: more than {} bytes inflated",
+            max
+        ));
+    }
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deflate_round_trip() {
+        let frame = r#"{"v":1,"type":"Message","payload":{"content":"hello"}}"#.repeat(100);
+        let data = deflate(&frame);
+        assert!(data.len() < frame.len() / 4);
+        assert_eq!(inflate(&data, frame.len()).unwrap(), frame);
+    }
+
+    #[test]
+    fn test_inflate_stops_at_the_limit() {
+        let data = deflate(&"x".repeat(1_000_000));
+        let error = inflate(&data, 64 * 1024).unwrap_err();
+        assert!(error.contains("too large"), "{}", error);
+    }
+
+    #[test]
+    fn test_inflate_rejects_garbage() {
+        assert!(inflate("not base64!", 1024).is_err());
+        assert!(inflate(&STANDARD.encode(b"not deflated"), 1024).is_err());
+    }
+}
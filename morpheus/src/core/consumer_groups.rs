@@ -0,0 +1,155 @@
+use crate::core::history::StoredMessage;
+use dashmap::DashMap;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// The most messages a single fetch returns, whatever the client asks for.
+pub const MAX_FETCH_BATCH: usize = 1000;
+
+/// The shared progress of a named consumer group through a topic's history.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GroupOffsets {
+    /// The sequence number of the last message handed to a member.
+    pub position: u64,
+    /// The sequence number up to which the members have processed the topic.
+    pub committed: u64,
+    /// The clients that fetched from the group since they connected.
+    pub members: HashSet<Uuid>,
+}
+
+/// Named consumer groups reading topic history by sequence number. The members of a
+/// group share its offsets, so each fetch hands out the next batch no member was given
+/// yet. When a member leaves, the group rewinds to its committed offset so that the
+/// batches it did not commit are fetched again.
+#[derive(Debug, Default)]
+pub(crate) struct ConsumerGroups {
+    groups: DashMap<(String, String), GroupOffsets>,
+}
+
+impl ConsumerGroups {
+    /// Hands `client_id` the batch `read` returns for the group's position and moves
+    /// the position past it. The group is locked while `read` runs, so concurrent
+    /// fetches from members never get the same messages.
+    pub fn fetch(
+        &self,
+        topic: &str,
+        group: &str,
+        client_id: Uuid,
+        read: impl FnOnce(u64) -> Vec<StoredMessage>,
+    ) -> Vec<StoredMessage> {
+        let mut offsets = self
+            .groups
+            .entry((topic.to_string(), group.to_string()))
+            .or_default();
+        offsets.members.insert(client_id);
+        let batch = read(offsets.position);
+        if let Some(last) = batch.last() {
+            offsets.position = last.seq;
+        }
+        batch
+    }
+
+    /// Records that the group processed the topic up to `seq`. Commits never move the
+    /// offset backwards. Returns the group's committed offset.
+    pub fn commit(&self, topic: &str, group: &str, seq: u64) -> u64 {
+        let mut offsets = self
+            .groups
+            .entry((topic.to_string(), group.to_string()))
+            .or_default();
+        offsets.committed = offsets.committed.max(seq);
+        offsets.position = offsets.position.max(offsets.committed);
+        offsets.committed
+    }
+
+    /// Removes a disconnected client from its groups, rewinding each of them to its
+    /// committed offset.
+    pub fn leave(&self, client_id: &Uuid) {
+        for mut offsets in self.groups.iter_mut() {
+            if offsets.members.remove(client_id) {
+                offsets.position = offsets.committed;
+            }
+        }
+    }
+
+    pub fn get(&self, topic: &str, group: &str) -> Option<GroupOffsets> {
+        self.groups
+            .get(&(topic.to_string(), group.to_string()))
+            .map(|offsets| offsets.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        history::{HistoryStore, InMemoryHistory},
+        provenance::{Origin, Provenance},
+    };
+    use chrono::Utc;
+    use std::collections::BTreeMap;
+
+    fn history(count: usize) -> InMemoryHistory {
+        let history = InMemoryHistory::default();
+        for n in 0..count {
+            history.append(StoredMessage {
+                id: Uuid::new_v4(),
+                topic: "orders".to_string(),
+                seq: n as u64 + 1,
+                sender: "Morpheus".to_string(),
+                content: format!("order {}", n + 1),
+                headers: BTreeMap::new(),
+                reply_to: None,
+                timestamp: Utc::now(),
+                provenance: Provenance::new(Origin::Operator),
+            });
+        }
+        history
+    }
+
+    fn seqs(batch: &[StoredMessage]) -> Vec<u64> {
+        batch.iter().map(|m| m.seq).collect()
+    }
+
+    #[test]
+    fn test_members_share_the_group_position() {
+        let history = history(5);
+        let groups = ConsumerGroups::default();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let fetch = |group: &str, client_id| {
+            groups.fetch("orders", group, client_id, |seq| {
+                history.after("orders", seq, 2)
+            })
+        };
+
+        assert_eq!(seqs(&fetch("billing", first)), vec![1, 2]);
+        assert_eq!(seqs(&fetch("billing", second)), vec![3, 4]);
+        // Another group reads the topic independently.
+        assert_eq!(seqs(&fetch("audit", second)), vec![1, 2]);
+        assert_eq!(seqs(&fetch("billing", first)), vec![5]);
+        assert!(fetch("billing", first).is_empty());
+    }
+
+    #[test]
+    fn test_leaving_rewinds_to_the_committed_offset() {
+        let history = history(4);
+        let groups = ConsumerGroups::default();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let fetch = |client_id| {
+            groups.fetch("orders", "billing", client_id, |seq| {
+                history.after("orders", seq, 2)
+            })
+        };
+
+        fetch(first);
+        assert_eq!(groups.commit("orders", "billing", 2), 2);
+        assert_eq!(seqs(&fetch(first)), vec![3, 4]);
+        assert_eq!(groups.commit("orders", "billing", 1), 2);
+
+        // The uncommitted batch goes to the remaining member.
+        groups.leave(&first);
+        assert_eq!(seqs(&fetch(second)), vec![3, 4]);
+        let offsets = groups.get("orders", "billing").unwrap();
+        assert_eq!((offsets.position, offsets.committed), (4, 2));
+        assert_eq!(offsets.members, HashSet::from([second]));
+    }
+}
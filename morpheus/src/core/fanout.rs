@@ -0,0 +1,201 @@
+use crate::core::{
+    msg::{CloseCode, Outgoing},
+    queue::{BackpressurePolicy, QueueSender, SendError},
+    storage::{Client, CloseHandle, Storage},
+};
+use futures_util::future::join_all;
+use std::sync::Arc;
+use tracing::error;
+use uuid::Uuid;
+
+/// Broadcasts to more recipients than this are split into shards of this size, each
+/// enqueued by its own task.
+pub const SHARD_SIZE: usize = 1024;
+
+/// What a broadcast needs of a subscriber: where to queue its messages. Storage keeps one
+/// shared list of these per topic, so a broadcast neither looks up nor copies its clients.
+#[derive(Clone, Debug)]
+pub struct Recipient {
+    pub id: Uuid,
+    pub sender: QueueSender,
+    pub closer: CloseHandle,
+    /// Whether the client has a durable session, which keeps its unacknowledged messages.
+    pub has_session: bool,
+}
+
+impl From<&Client> for Recipient {
+    fn from(client: &Client) -> Self {
+        Self {
+            id: client.id,
+            sender: client.sender.clone(),
+            closer: client.closer.clone(),
+            has_session: client.session_id.is_some(),
+        }
+    }
+}
+
+/// What became of a message queued for one recipient.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delivered {
+    Enqueued,
+    /// The queue is closed, or full and its policy drops new messages.
+    Dropped,
+    /// The queue is full and its policy disconnects slow clients; the connection was
+    /// closed and the client must be removed.
+    Overloaded,
+}
+
+/// Queues a message for one recipient, applying its backpressure policy.
+pub fn enqueue(storage: &dyn Storage, recipient: &Recipient, message: Arc<Outgoing>) -> Delivered {
+    if recipient.has_session && message.message.id().is_some() {
+        storage.add_pending_ack(&recipient.id, message.clone());
+    }
+    send(recipient, message)
+}
+
+fn send(recipient: &Recipient, message: Arc<Outgoing>) -> Delivered {
+    match recipient.sender.send_shared(message) {
+        Ok(()) => Delivered::Enqueued,
+        Err(SendError::Full) if recipient.sender.policy() == BackpressurePolicy::Disconnect => {
+            recipient.closer.close(CloseCode::Overloaded);
+            recipient.sender.close();
+            Delivered::Overloaded
+        }
+        Err(_) => Delivered::Dropped,
+    }
+}
+
+/// Queues a message for every recipient but `exclude`, returning what became of it for
+/// each. Up to `SHARD_SIZE` recipients are served in place; larger fan-outs are split into
+/// shards enqueued concurrently, spreading them over the runtime's workers, so a shard that
+/// is slow to lock its queues holds up no other. It returns once every shard is done, so
+/// consecutive broadcasts reach each queue in order.
+pub async fn fan_out(
+    storage: Arc<dyn Storage>,
+    recipients: Arc<[Recipient]>,
+    exclude: Option<Uuid>,
+    message: Arc<Outgoing>,
+) -> Vec<(Uuid, Delivered)> {
+    if recipients.len() <= SHARD_SIZE {
+        return enqueue_all(storage.as_ref(), &recipients, exclude, &message);
+    }
+    let shards: Vec<_> = (0..recipients.len())
+        .step_by(SHARD_SIZE)
+        .map(|start| {
+            let (storage, recipients, message) =
+                (storage.clone(), recipients.clone(), message.clone());
+            tokio::spawn(async move {
+                let end = (start + SHARD_SIZE).min(recipients.len());
+                enqueue_all(storage.as_ref(), &recipients[start..end], exclude, &message)
+            })
+        })
+        .collect();
+    let mut outcomes = Vec::with_capacity(recipients.len());
+    for (index, shard) in join_all(shards).await.into_iter().enumerate() {
+        match shard {
+            Ok(shard) => outcomes.extend(shard),
+            Err(e) => error!(
+                msg_id = ?message.message.id(),
+                first_recipient = index * SHARD_SIZE,
+                error = %e,
+                "Broadcast shard failed"
+            ),
+        }
+    }
+    outcomes
+}
+
+fn enqueue_all(
+    storage: &dyn Storage,
+    recipients: &[Recipient],
+    exclude: Option<Uuid>,
+    message: &Arc<Outgoing>,
+) -> Vec<(Uuid, Delivered)> {
+    let recipients: Vec<&Recipient> = recipients
+        .iter()
+        .filter(|recipient| exclude != Some(recipient.id))
+        .collect();
+    // Recorded for the whole shard at once, so a backend writing to disk does it once.
+    if message.message.id().is_some() {
+        let sessions: Vec<Uuid> = recipients
+            .iter()
+            .filter(|recipient| recipient.has_session)
+            .map(|recipient| recipient.id)
+            .collect();
+        if !sessions.is_empty() {
+            storage.add_pending_acks(&sessions, message.clone());
+        }
+    }
+    recipients
+        .into_iter()
+        .map(|recipient| (recipient.id, send(recipient, message.clone())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::{
+            msg::ServerMessage,
+            queue::{QueueConfig, QueueReceiver},
+            storage::InMemoryStorage,
+        },
+        testing::client_record,
+    };
+
+    fn recipients(count: usize, config: QueueConfig) -> (Vec<Recipient>, Vec<QueueReceiver>) {
+        (0..count)
+            .map(|_| {
+                let (client, receiver) = client_record(Uuid::new_v4(), config);
+                (Recipient::from(&client), receiver)
+            })
+            .unzip()
+    }
+
+    fn message() -> Arc<Outgoing> {
+        Arc::new(Outgoing::new(ServerMessage::Error {
+            message: "hello".to_string(),
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_large_fan_outs_reach_every_recipient_once() {
+        let storage: Arc<dyn Storage> = Arc::new(InMemoryStorage::new());
+        let (recipients, mut receivers) = recipients(2 * SHARD_SIZE + 1, QueueConfig::default());
+        let excluded = recipients[SHARD_SIZE].id;
+
+        let outcomes = fan_out(storage, recipients.into(), Some(excluded), message()).await;
+
+        assert_eq!(outcomes.len(), 2 * SHARD_SIZE);
+        assert!(outcomes
+            .iter()
+            .all(|(id, delivered)| *id != excluded && *delivered == Delivered::Enqueued));
+        let queued = receivers
+            .iter_mut()
+            .filter_map(|receiver| receiver.try_recv_shared().ok())
+            .count();
+        assert_eq!(queued, 2 * SHARD_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_full_queues_apply_their_policy() {
+        let storage: Arc<dyn Storage> = Arc::new(InMemoryStorage::new());
+        let config = |policy| QueueConfig {
+            capacity: 1,
+            policy,
+        };
+        let (mut slow, _receivers) = recipients(1, config(BackpressurePolicy::Disconnect));
+        let (dropping, _more) = recipients(1, config(BackpressurePolicy::DropNewest));
+        slow.extend(dropping);
+        let recipients: Arc<[Recipient]> = slow.into();
+
+        fan_out(storage.clone(), recipients.clone(), None, message()).await;
+        let outcomes = fan_out(storage, recipients.clone(), None, message()).await;
+
+        assert_eq!(outcomes[0].1, Delivered::Overloaded);
+        assert!(recipients[0].closer.is_closed());
+        assert_eq!(outcomes[1].1, Delivered::Dropped);
+        assert!(!recipients[1].closer.is_closed());
+    }
+}
@@ -0,0 +1,343 @@
+use crate::core::{
+    client_manager::ClientManager,
+    history::{HistoryHook, StoredMessage},
+    mirror::{
+        mirrored, passed_through, request, BACKLOG, LOOP_REJECTION, RETRY_BASE_DELAY,
+        RETRY_MAX_DELAY, SENDER_HEADER,
+    },
+    msg::{self, ClientMessage, CloseCode, Qos, ServerMessage},
+    provenance::{Origin, Provenance},
+};
+use async_trait::async_trait;
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+/// A server this one is federated with, from a `[[federation]]` config section.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FederationConfig {
+    /// The remote server's client WebSocket URL, e.g. `wss://other-host/ws`.
+    pub url: String,
+    /// The topics relayed in both directions.
+    pub topics: Vec<String>,
+    /// The token the remote server expects from its clients.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+impl FederationConfig {
+    /// The bridge that messages relayed from the remote server are recorded as coming from.
+    pub fn bridge(&self) -> String {
+        format!("federation:{}", self.url)
+    }
+}
+
+/// Links this server with independently operated morpheus servers. Each federated topic
+/// gets its own link, connecting to the remote server as a client subscribed to the topic:
+/// messages published here are published there, and messages published there are
+/// republished here. The remote server only has to accept the link's token, so a pair of
+/// servers is linked from one side.
+///
+/// Relayed messages carry the mirror `VIA_HEADER`, so a message never passes through the
+/// same server twice, however the servers are linked.
+pub struct Federation {
+    /// The name this server adds to `VIA_HEADER`.
+    instance_id: String,
+    links: Vec<Link>,
+}
+
+struct Link {
+    topic: String,
+    url: String,
+    bridge: String,
+    outbox: mpsc::Sender<String>,
+}
+
+impl Federation {
+    /// Starts a link task per federated topic of every server. Messages received over the
+    /// links are republished through `client_manager`.
+    pub fn start(
+        configs: &[FederationConfig],
+        instance_id: String,
+        client_manager: Arc<ClientManager>,
+    ) -> Self {
+        let links = configs
+            .iter()
+            .flat_map(|config| config.topics.iter().map(move |topic| (config, topic)))
+            .map(|(config, topic)| {
+                let (outbox, rx) = mpsc::channel(BACKLOG);
+                tokio::spawn(run_link(
+                    config.clone(),
+                    topic.clone(),
+                    instance_id.clone(),
+                    client_manager.clone(),
+                    rx,
+                ));
+                Link {
+                    topic: topic.clone(),
+                    url: config.url.clone(),
+                    bridge: config.bridge(),
+                    outbox,
+                }
+            })
+            .collect();
+        Self { instance_id, links }
+    }
+}
+
+#[async_trait]
+impl HistoryHook for Federation {
+    async fn on_message_stored(&self, message: &StoredMessage) {
+        // Messages are not sent back over the link they came from.
+        let mut links = self
+            .links
+            .iter()
+            .filter(|link| link.topic == message.topic)
+            .filter(|link| {
+                !matches!(
+                    message.provenance.origin(),
+                    Some(Origin::Bridge(name)) if *name == link.bridge
+                )
+            })
+            .peekable();
+        if links.peek().is_none() {
+            return;
+        }
+        let Some(relayed) = mirrored(message, &self.instance_id) else {
+            return;
+        };
+        let text = match msg::encode(&relayed) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("Failed to serialize federated message: {}", e);
+                return;
+            }
+        };
+        for link in links {
+            if let Err(mpsc::error::TrySendError::Full(_)) = link.outbox.try_send(text.clone()) {
+                eprintln!(
+                    "Federation of topic '{}' with {} is behind, dropping message {}",
+                    link.topic, link.url, message.id
+                );
+            }
+        }
+    }
+}
+
+/// The local copy of a message the remote server sent over a link, or `None` if it must
+/// not be republished: it is not of the link's topic, it is a retained message sent on
+/// subscribing, or it already passed through this server.
+fn republished(
+    message: ServerMessage,
+    link_topic: &str,
+    instance_id: &str,
+) -> Option<ServerMessage> {
+    let ServerMessage::Topic {
+        id,
+        topic,
+        sender,
+        content,
+        mut headers,
+        reply_to,
+        retained,
+        ..
+    } = message
+    else {
+        return None;
+    };
+    if retained || topic != link_topic || passed_through(&headers, instance_id) {
+        return None;
+    }
+    headers
+        .entry(SENDER_HEADER.to_string())
+        .or_insert_with(|| sender.clone());
+    Some(ServerMessage::Topic {
+        id,
+        topic,
+        seq: 0,
+        sender,
+        content,
+        headers,
+        qos: Qos::FireAndForget,
+        reply_to,
+        retained: false,
+    })
+}
+
+/// Keeps a client connection to the remote server open: subscribes to `topic`, publishes
+/// every queued message and republishes what the remote server sends.
+/// Reconnects with backoff until the remote server refuses us for good with an
+/// authentication, ban or kick close code.
+async fn run_link(
+    config: FederationConfig,
+    topic: String,
+    instance_id: String,
+    client_manager: Arc<ClientManager>,
+    mut rx: mpsc::Receiver<String>,
+) {
+    let provenance = Provenance::new(Origin::Bridge(config.bridge()));
+    let mut delay = RETRY_BASE_DELAY;
+    loop {
+        let request = match request(&config.url, config.token.as_deref()) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!(
+                    "Cannot federate topic '{}' with {}: {}",
+                    topic, config.url, e
+                );
+                return;
+            }
+        };
+        match connect_async(request).await {
+            Ok((ws, _)) => {
+                println!("Federating topic '{}' with {}.", topic, config.url);
+                delay = RETRY_BASE_DELAY;
+                let (mut write, mut read) = ws.split();
+                let connect = ClientMessage::Connect {
+                    topic: topic.clone(),
+                };
+                let subscribed = match msg::encode(&connect) {
+                    Ok(text) => write.send(Message::Text(text)).await.is_ok(),
+                    Err(_) => false,
+                };
+                let close_code = loop {
+                    if !subscribed {
+                        break None;
+                    }
+                    tokio::select! {
+                        text = rx.recv() => {
+                            let Some(text) = text else {
+                                return; // The federation was dropped.
+                            };
+                            if write.send(Message::Text(text)).await.is_err() {
+                                break None;
+                            }
+                        }
+                        frame = read.next() => match frame {
+                            Some(Ok(Message::Text(text))) => {
+                                let Ok(message) = msg::decode(&text) else {
+                                    continue;
+                                };
+                                match &message {
+                                    ServerMessage::Topic { id, qos, .. }
+                                        if !qos.is_fire_and_forget() =>
+                                    {
+                                        // Stops the remote server from redelivering it.
+                                        let ack = ClientMessage::MessageReceived { msg_id: *id };
+                                        if let Ok(text) = msg::encode(&ack) {
+                                            let _ = write.send(Message::Text(text)).await;
+                                        }
+                                    }
+                                    ServerMessage::TopicInfo { topic, .. } => {
+                                        eprintln!(
+                                            "{} requires accepting the rules of topic '{}'; it is not federated.",
+                                            config.url, topic
+                                        );
+                                        continue;
+                                    }
+                                    ServerMessage::Error { message }
+                                        if !message.ends_with(LOOP_REJECTION) =>
+                                    {
+                                        eprintln!(
+                                            "{} rejected a federated message: {}",
+                                            config.url, message
+                                        );
+                                        continue;
+                                    }
+                                    _ => {}
+                                }
+                                if let Some(copy) = republished(message, &topic, &instance_id) {
+                                    client_manager
+                                        .broadcast_to_topic_with_provenance(
+                                            &topic,
+                                            copy,
+                                            None,
+                                            provenance.clone(),
+                                        )
+                                        .await;
+                                }
+                            }
+                            Some(Ok(Message::Close(frame))) => {
+                                break frame.map(|frame| u16::from(frame.code));
+                            }
+                            Some(Ok(_)) => {}
+                            Some(Err(_)) | None => break None,
+                        }
+                    }
+                };
+                let refused = [CloseCode::AuthFailed, CloseCode::Banned, CloseCode::Kicked];
+                if let Some(code) =
+                    close_code.filter(|code| refused.iter().any(|c| c.code() == *code))
+                {
+                    eprintln!(
+                        "{} refused the federation of topic '{}' (close code {}). Not reconnecting.",
+                        config.url, topic, code
+                    );
+                    return;
+                }
+                eprintln!(
+                    "Federation link for topic '{}' to {} lost.",
+                    topic, config.url
+                );
+            }
+            Err(e) => eprintln!(
+                "Failed to connect federation of topic '{}' to {}: {}",
+                topic, config.url, e
+            ),
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(RETRY_MAX_DELAY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::mirror::VIA_HEADER;
+    use std::collections::BTreeMap;
+    use uuid::Uuid;
+
+    fn remote(topic: &str, headers: &[(&str, &str)], retained: bool) -> ServerMessage {
+        ServerMessage::Topic {
+            id: Uuid::new_v4(),
+            topic: topic.to_string(),
+            seq: 7,
+            sender: "Bob".to_string(),
+            content: "disk full".to_string(),
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<BTreeMap<_, _>>(),
+            qos: Qos::AtLeastOnce,
+            reply_to: None,
+            retained,
+        }
+    }
+
+    #[test]
+    fn test_remote_messages_of_federated_topics_are_republished() {
+        let message = remote("alerts", &[], false);
+        let id = message.id();
+        match republished(message, "alerts", "a") {
+            Some(ServerMessage::Topic {
+                id: copy_id,
+                headers,
+                qos,
+                ..
+            }) => {
+                assert_eq!(Some(copy_id), id);
+                assert_eq!(headers[SENDER_HEADER], "Bob");
+                assert_eq!(qos, Qos::FireAndForget);
+            }
+            other => panic!("Unexpected message {:?}", other),
+        }
+
+        assert!(republished(remote("general", &[], false), "alerts", "a").is_none());
+        assert!(republished(remote("alerts", &[], true), "alerts", "a").is_none());
+        let looped = remote("alerts", &[(VIA_HEADER, "a,b")], false);
+        assert!(republished(looped, "alerts", "a").is_none());
+    }
+}
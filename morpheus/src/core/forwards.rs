@@ -0,0 +1,254 @@
+use crate::core::msg::ServerMessage;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{fmt, sync::RwLock};
+use uuid::Uuid;
+
+/// The header a forwarded message carries, naming the topic it was published to.
+pub const FORWARDED_FROM_HEADER: &str = "forwarded-from";
+
+/// What a forwarding rule does, as the operator gives it on the console or to the admin API.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForwardSpec {
+    pub from: String,
+    pub to: String,
+    /// Only messages whose content matches this regex are forwarded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+    /// Rewrites the content of forwarded messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<Transform>,
+}
+
+impl fmt::Display for ForwardSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' to '{}'", self.from, self.to)?;
+        if let Some(filter) = &self.filter {
+            write!(f, " matching {}", filter)?;
+        }
+        if let Some(transform) = &self.transform {
+            write!(
+                f,
+                " replacing {} with {}",
+                transform.pattern, transform.replacement
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces every match of `pattern` with `replacement`, which may refer to the pattern's
+/// groups as `$1` or `$name`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Transform {
+    pub pattern: String,
+    pub replacement: String,
+}
+
+/// A forwarding rule with its regexes compiled.
+#[derive(Clone, Debug, Serialize)]
+pub struct ForwardRule {
+    pub id: Uuid,
+    #[serde(flatten)]
+    pub spec: ForwardSpec,
+    #[serde(skip)]
+    filter: Option<Regex>,
+    #[serde(skip)]
+    transform: Option<Regex>,
+}
+
+impl ForwardRule {
+    pub fn new(spec: ForwardSpec) -> Result<Self, String> {
+        if spec.from.is_empty() || spec.to.is_empty() {
+            return Err("A forwarding rule needs a source and a destination topic".to_string());
+        }
+        if spec.from == spec.to {
+            return Err(format!(
+                "Topic '{}' cannot be forwarded to itself",
+                spec.from
+            ));
+        }
+        let filter = spec
+            .filter
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| format!("Invalid filter: {}", e))?;
+        let transform = spec
+            .transform
+            .as_ref()
+            .map(|transform| Regex::new(&transform.pattern))
+            .transpose()
+            .map_err(|e| format!("Invalid transform: {}", e))?;
+        Ok(Self {
+            id: Uuid::new_v4(),
+            spec,
+            filter,
+            transform,
+        })
+    }
+
+    /// The content to forward, or `None` if the filter leaves the message out.
+    pub fn apply(&self, content: &str) -> Option<String> {
+        if self
+            .filter
+            .as_ref()
+            .is_some_and(|filter| !filter.is_match(content))
+        {
+            return None;
+        }
+        Some(match (&self.transform, &self.spec.transform) {
+            (Some(pattern), Some(transform)) => pattern
+                .replace_all(content, transform.replacement.as_str())
+                .into_owned(),
+            _ => content.to_string(),
+        })
+    }
+}
+
+/// The forwarding rules, in the order they were added. A message published to a topic is
+/// forwarded by every rule from that topic; forwarded messages are not forwarded again,
+/// so rules cannot loop.
+#[derive(Debug, Default)]
+pub struct Forwards {
+    rules: RwLock<Vec<ForwardRule>>,
+}
+
+impl Forwards {
+    pub fn add(&self, rule: ForwardRule) {
+        self.rules.write().unwrap().push(rule);
+    }
+
+    /// Removes a rule. Returns whether it existed.
+    pub fn remove(&self, id: &Uuid) -> bool {
+        let mut rules = self.rules.write().unwrap();
+        let before = rules.len();
+        rules.retain(|rule| rule.id != *id);
+        rules.len() != before
+    }
+
+    pub fn list(&self) -> Vec<ForwardRule> {
+        self.rules.read().unwrap().clone()
+    }
+
+    /// The copies of a topic message the rules from its topic forward, with the rule that
+    /// made each. Each copy gets a new ID and the `forwarded-from` header.
+    pub(crate) fn forward(&self, message: &ServerMessage) -> Vec<(ForwardRule, ServerMessage)> {
+        let ServerMessage::Topic {
+            topic,
+            sender,
+            content,
+            headers,
+            qos,
+            ..
+        } = message
+        else {
+            return Vec::new();
+        };
+        let rules = self.rules.read().unwrap();
+        rules
+            .iter()
+            .filter(|rule| rule.spec.from == *topic)
+            .filter_map(|rule| {
+                let content = rule.apply(content)?;
+                let mut headers = headers.clone();
+                headers.insert(FORWARDED_FROM_HEADER.to_string(), topic.clone());
+                let copy = ServerMessage::Topic {
+                    id: Uuid::new_v4(),
+                    topic: rule.spec.to.clone(),
+                    seq: 0,
+                    sender: sender.clone(),
+                    content,
+                    headers,
+                    qos: *qos,
+                    reply_to: None,
+                    retained: false,
+                };
+                Some((rule.clone(), copy))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::msg::Qos;
+    use std::collections::BTreeMap;
+
+    fn spec(filter: Option<&str>, transform: Option<(&str, &str)>) -> ForwardSpec {
+        ForwardSpec {
+            from: "alerts".to_string(),
+            to: "ops".to_string(),
+            filter: filter.map(str::to_string),
+            transform: transform.map(|(pattern, replacement)| Transform {
+                pattern: pattern.to_string(),
+                replacement: replacement.to_string(),
+            }),
+        }
+    }
+
+    fn published(topic: &str, content: &str) -> ServerMessage {
+        ServerMessage::Topic {
+            id: Uuid::new_v4(),
+            topic: topic.to_string(),
+            seq: 1,
+            sender: "sensor".to_string(),
+            content: content.to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            reply_to: None,
+            retained: false,
+        }
+    }
+
+    #[test]
+    fn test_rules_filter_and_transform() {
+        let rule =
+            ForwardRule::new(spec(Some("^CRIT"), Some((r"CRIT (\w+)", "critical: $1")))).unwrap();
+        assert_eq!(rule.apply("CRIT disk"), Some("critical: disk".to_string()));
+        assert_eq!(rule.apply("INFO disk"), None);
+
+        assert!(ForwardRule::new(spec(Some("("), None)).is_err());
+        assert!(ForwardRule::new(spec(None, Some(("(", "")))).is_err());
+        let mut looped = spec(None, None);
+        looped.to = looped.from.clone();
+        assert!(ForwardRule::new(looped).is_err());
+    }
+
+    #[test]
+    fn test_only_messages_of_the_source_topic_are_forwarded() {
+        let forwards = Forwards::default();
+        let rule = ForwardRule::new(spec(None, None)).unwrap();
+        let id = rule.id;
+        forwards.add(rule);
+
+        let copies = forwards.forward(&published("alerts", "CRIT disk"));
+        assert_eq!(copies.len(), 1);
+        match &copies[0].1 {
+            ServerMessage::Topic {
+                topic,
+                content,
+                headers,
+                ..
+            } => {
+                assert_eq!(topic, "ops");
+                assert_eq!(content, "CRIT disk");
+                assert_eq!(
+                    headers.get(FORWARDED_FROM_HEADER).map(String::as_str),
+                    Some("alerts")
+                );
+            }
+            other => panic!("Unexpected message {:?}", other),
+        }
+        assert!(forwards.forward(&published("ops", "hi")).is_empty());
+
+        assert!(forwards.remove(&id));
+        assert!(!forwards.remove(&id));
+        assert!(forwards
+            .forward(&published("alerts", "CRIT disk"))
+            .is_empty());
+    }
+}
@@ -0,0 +1,379 @@
+use crate::core::{
+    msg::{Qos, ServerMessage},
+    provenance::Provenance,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, RwLock},
+};
+use uuid::Uuid;
+
+/// The default number of messages retained per topic.
+pub const DEFAULT_RETENTION: usize = 1000;
+
+/// A topic message recorded in the history store.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredMessage {
+    pub id: Uuid,
+    pub topic: String,
+    /// The message's position in its topic, as it was delivered to subscribers.
+    /// Sequence numbers start at 1 and only ever grow, so consumers can resume after one.
+    pub seq: u64,
+    pub sender: String,
+    pub content: String,
+    pub headers: BTreeMap<String, String>,
+    /// The message this one replies to, if it is a reply.
+    pub reply_to: Option<Uuid>,
+    pub timestamp: DateTime<Utc>,
+    pub provenance: Provenance,
+}
+
+impl StoredMessage {
+    /// The message as it was delivered to the topic's subscribers.
+    pub fn to_server_message(&self) -> ServerMessage {
+        ServerMessage::Topic {
+            id: self.id,
+            topic: self.topic.clone(),
+            seq: self.seq,
+            sender: self.sender.clone(),
+            content: self.content.clone(),
+            headers: self.headers.clone(),
+            qos: Qos::FireAndForget,
+            reply_to: self.reply_to,
+            retained: false,
+        }
+    }
+}
+
+/// A trait defining the contract for the topic message history (retention buffer).
+pub trait HistoryStore: Send + Sync {
+    /// Appends a message, which already carries its sequence number.
+    /// Returns any messages evicted by the retention policy.
+    fn append(&self, message: StoredMessage) -> Vec<StoredMessage>;
+    fn get(&self, msg_id: &Uuid) -> Option<StoredMessage>;
+    /// Returns up to `limit` of the most recent messages in a topic, oldest first.
+    fn recent(&self, topic: &str, limit: usize) -> Vec<StoredMessage>;
+    /// Returns up to `limit` of the messages in a topic with a sequence number after
+    /// `seq`, oldest first.
+    fn after(&self, topic: &str, seq: u64, limit: usize) -> Vec<StoredMessage>;
+    fn remove(&self, msg_id: &Uuid) -> Option<StoredMessage>;
+    /// The topics that have messages in history.
+    fn topics(&self) -> Vec<String>;
+    /// Moves every message of topic `from` into topic `into`, keeping chronological order.
+    /// The moved messages are renumbered in `into` by calling `next_seq` for each.
+    /// Returns any messages evicted by the retention policy.
+    fn merge_topics(
+        &self,
+        from: &str,
+        into: &str,
+        next_seq: &mut dyn FnMut() -> u64,
+    ) -> Vec<StoredMessage>;
+}
+
+/// An in-memory history store keeping the last `retention` messages of every topic.
+pub struct InMemoryHistory {
+    retention: usize,
+    topic_retention: HashMap<String, usize>,
+    topics: DashMap<String, VecDeque<StoredMessage>>,
+}
+
+impl InMemoryHistory {
+    pub fn new(retention: usize) -> Self {
+        Self {
+            retention,
+            topic_retention: HashMap::new(),
+            topics: DashMap::new(),
+        }
+    }
+
+    /// Overrides the retention for individual topics.
+    pub fn with_topic_retention(mut self, topic_retention: HashMap<String, usize>) -> Self {
+        self.topic_retention = topic_retention;
+        self
+    }
+
+    fn retention_for(&self, topic: &str) -> usize {
+        self.topic_retention
+            .get(topic)
+            .copied()
+            .unwrap_or(self.retention)
+    }
+}
+
+impl Default for InMemoryHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_RETENTION)
+    }
+}
+
+impl HistoryStore for InMemoryHistory {
+    fn append(&self, message: StoredMessage) -> Vec<StoredMessage> {
+        let retention = self.retention_for(&message.topic);
+        let mut messages = self.topics.entry(message.topic.clone()).or_default();
+        messages.push_back(message);
+        let excess = messages.len().saturating_sub(retention);
+        messages.drain(..excess).collect()
+    }
+
+    fn get(&self, msg_id: &Uuid) -> Option<StoredMessage> {
+        self.topics
+            .iter()
+            .find_map(|entry| entry.value().iter().find(|m| m.id == *msg_id).cloned())
+    }
+
+    fn recent(&self, topic: &str, limit: usize) -> Vec<StoredMessage> {
+        self.topics
+            .get(topic)
+            .map(|messages| {
+                let skip = messages.len().saturating_sub(limit);
+                messages.iter().skip(skip).cloned().collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn after(&self, topic: &str, seq: u64, limit: usize) -> Vec<StoredMessage> {
+        let Some(messages) = self.topics.get(topic) else {
+            return Vec::new();
+        };
+        let mut after: Vec<_> = messages.iter().filter(|m| m.seq > seq).cloned().collect();
+        after.sort_by_key(|m| m.seq);
+        after.truncate(limit);
+        after
+    }
+
+    fn topics(&self) -> Vec<String> {
+        self.topics
+            .iter()
+            .filter(|entry| !entry.value().is_empty())
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    fn remove(&self, msg_id: &Uuid) -> Option<StoredMessage> {
+        self.topics.iter_mut().find_map(|mut entry| {
+            let messages = entry.value_mut();
+            let index = messages.iter().position(|m| m.id == *msg_id)?;
+            messages.remove(index)
+        })
+    }
+
+    fn merge_topics(
+        &self,
+        from: &str,
+        into: &str,
+        next_seq: &mut dyn FnMut() -> u64,
+    ) -> Vec<StoredMessage> {
+        let Some((_, moved)) = self.topics.remove(from) else {
+            return Vec::new();
+        };
+        let retention = self.retention_for(into);
+        let mut messages = self.topics.entry(into.to_string()).or_default();
+        messages.extend(moved.into_iter().map(|mut message| {
+            message.topic = into.to_string();
+            message.seq = next_seq();
+            message
+        }));
+        messages.make_contiguous().sort_by_key(|m| m.timestamp);
+        let excess = messages.len().saturating_sub(retention);
+        messages.drain(..excess).collect()
+    }
+}
+
+/// A hook notified about history changes, e.g. to feed an external search index.
+#[async_trait]
+pub trait HistoryHook: Send + Sync {
+    async fn on_message_stored(&self, _message: &StoredMessage) {}
+    async fn on_message_deleted(&self, _message: &StoredMessage) {}
+}
+
+/// An ordered chain of history hooks that can be extended at runtime.
+#[derive(Default)]
+pub struct HistoryHooks {
+    hooks: RwLock<Vec<Arc<dyn HistoryHook>>>,
+}
+
+impl HistoryHooks {
+    /// Appends a hook to the end of the chain.
+    pub fn register(&self, hook: Arc<dyn HistoryHook>) {
+        self.hooks.write().unwrap().push(hook);
+    }
+
+    pub fn len(&self) -> usize {
+        self.hooks.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub async fn message_stored(&self, message: &StoredMessage) {
+        for hook in self.snapshot() {
+            hook.on_message_stored(message).await;
+        }
+    }
+
+    pub async fn message_deleted(&self, message: &StoredMessage) {
+        for hook in self.snapshot() {
+            hook.on_message_deleted(message).await;
+        }
+    }
+
+    // Clone the chain so no lock is held across an await point.
+    fn snapshot(&self) -> Vec<Arc<dyn HistoryHook>> {
+        self.hooks.read().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::provenance::Origin;
+    use std::sync::Mutex;
+
+    fn message(topic: &str, content: &str) -> StoredMessage {
+        StoredMessage {
+            id: Uuid::new_v4(),
+            topic: topic.to_string(),
+            seq: 0,
+            sender: "Morpheus".to_string(),
+            content: content.to_string(),
+            headers: BTreeMap::new(),
+            reply_to: None,
+            timestamp: Utc::now(),
+            provenance: Provenance::new(Origin::Operator),
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingHook {
+        events: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl HistoryHook for RecordingHook {
+        async fn on_message_stored(&self, message: &StoredMessage) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("stored:{}", message.content));
+        }
+
+        async fn on_message_deleted(&self, message: &StoredMessage) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("deleted:{}", message.content));
+        }
+    }
+
+    #[test]
+    fn test_topic_retention_overrides_default() {
+        let history =
+            InMemoryHistory::new(2).with_topic_retention(HashMap::from([("short".to_string(), 1)]));
+        history.append(message("short", "one"));
+        assert_eq!(history.append(message("short", "two")).len(), 1);
+        history.append(message("long", "one"));
+        assert!(history.append(message("long", "two")).is_empty());
+    }
+
+    #[test]
+    fn test_retention_evicts_oldest() {
+        let history = InMemoryHistory::new(2);
+        assert!(history.append(message("t", "one")).is_empty());
+        assert!(history.append(message("t", "two")).is_empty());
+        let evicted = history.append(message("t", "three"));
+
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].content, "one");
+        let recent: Vec<_> = history
+            .recent("t", 10)
+            .into_iter()
+            .map(|m| m.content)
+            .collect();
+        assert_eq!(recent, vec!["two", "three"]);
+    }
+
+    #[test]
+    fn test_after_skips_evicted_messages() {
+        let history = InMemoryHistory::new(2);
+        for (seq, content) in [(1, "one"), (2, "two"), (3, "three")] {
+            history.append(StoredMessage {
+                seq,
+                ..message("t", content)
+            });
+        }
+
+        let after: Vec<_> = history
+            .after("t", 0, 10)
+            .into_iter()
+            .map(|m| (m.seq, m.content))
+            .collect();
+        assert_eq!(
+            after,
+            vec![(2, "two".to_string()), (3, "three".to_string())]
+        );
+        assert_eq!(history.after("t", 2, 10)[0].content, "three");
+        assert_eq!(history.after("t", 0, 1).len(), 1);
+        assert!(history.after("t", 3, 10).is_empty());
+        assert!(history.after("other", 0, 10).is_empty());
+    }
+
+    #[test]
+    fn test_get_and_remove() {
+        let history = InMemoryHistory::default();
+        let msg = message("t", "hello");
+        history.append(msg.clone());
+
+        assert_eq!(history.get(&msg.id), Some(msg.clone()));
+        assert_eq!(history.remove(&msg.id), Some(msg.clone()));
+        assert!(history.get(&msg.id).is_none());
+    }
+
+    #[test]
+    fn test_merge_topics_interleaves_by_timestamp() {
+        let history = InMemoryHistory::new(3);
+        let start = Utc::now();
+        for (n, (topic, content)) in [("a", "a1"), ("b", "b1"), ("a", "a2"), ("b", "b2")]
+            .into_iter()
+            .enumerate()
+        {
+            let mut msg = message(topic, content);
+            msg.timestamp = start + chrono::Duration::milliseconds(n as i64);
+            history.append(msg);
+        }
+
+        let mut seq = 2;
+        let evicted = history.merge_topics("a", "b", &mut || {
+            seq += 1;
+            seq
+        });
+
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].content, "a1");
+        assert!(history.recent("a", 10).is_empty());
+        let merged: Vec<_> = history.recent("b", 10).into_iter().collect();
+        assert!(merged.iter().all(|m| m.topic == "b"));
+        let contents: Vec<_> = merged.into_iter().map(|m| m.content).collect();
+        assert_eq!(contents, vec!["b1", "a2", "b2"]);
+    }
+
+    #[tokio::test]
+    async fn test_hooks_run_in_registration_order() {
+        let hooks = HistoryHooks::default();
+        let first = Arc::new(RecordingHook::default());
+        let second = Arc::new(RecordingHook::default());
+        hooks.register(first.clone());
+        hooks.register(second.clone());
+
+        let msg = message("t", "hello");
+        hooks.message_stored(&msg).await;
+        hooks.message_deleted(&msg).await;
+
+        let expected = vec!["stored:hello".to_string(), "deleted:hello".to_string()];
+        assert_eq!(*first.events.lock().unwrap(), expected);
+        assert_eq!(*second.events.lock().unwrap(), expected);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::core::provenance::Provenance;
+use async_trait::async_trait;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+use uuid::Uuid;
+
+/// A message a client published, on its way to the topic's subscribers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingMessage {
+    client_id: Uuid,
+    topic: String,
+    /// The content that will be delivered; hooks may rewrite it.
+    pub content: String,
+    /// The custom headers the client attached; hooks may add or change them.
+    pub headers: BTreeMap<String, String>,
+    /// Hooks may record what they did to the message here.
+    pub provenance: Provenance,
+}
+
+impl PendingMessage {
+    pub fn new(client_id: Uuid, topic: String, content: String, provenance: Provenance) -> Self {
+        Self {
+            client_id,
+            topic,
+            content,
+            headers: BTreeMap::new(),
+            provenance,
+        }
+    }
+
+    /// Attaches the headers the client sent with the message.
+    pub fn with_headers(mut self, headers: BTreeMap<String, String>) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// The client that published the message.
+    pub fn client_id(&self) -> Uuid {
+        self.client_id
+    }
+
+    /// The topic the message is published to. Hooks cannot move a message to another
+    /// topic, since its policies were already checked.
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+}
+
+/// A hook run on every client message before it is broadcast, e.g. for moderation,
+/// enrichment or auditing.
+#[async_trait]
+pub trait MessageHook: Send + Sync {
+    /// The stage name recorded in the provenance of messages the hook rewrites.
+    fn name(&self) -> &str;
+
+    /// Inspects the message and may rewrite or annotate it. Returning an error rejects
+    /// the message; the error is sent back to the client that published it.
+    async fn on_message(&self, message: &mut PendingMessage) -> Result<(), String>;
+}
+
+/// An ordered chain of message hooks that can be extended at runtime.
+#[derive(Default)]
+pub struct MessageHooks {
+    hooks: RwLock<Vec<Arc<dyn MessageHook>>>,
+}
+
+impl MessageHooks {
+    /// Appends a hook to the end of the chain.
+    pub fn register(&self, hook: Arc<dyn MessageHook>) {
+        self.hooks.write().unwrap().push(hook);
+    }
+
+    pub fn len(&self) -> usize {
+        self.hooks.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Runs the hooks in registration order, stopping at the first that rejects the
+    /// message. A rewrite of the content is recorded in the provenance chain.
+    pub async fn run(&self, message: &mut PendingMessage) -> Result<(), String> {
+        for hook in self.snapshot() {
+            let before = message.content.clone();
+            hook.on_message(message).await?;
+            if message.content != before {
+                message
+                    .provenance
+                    .record_transform(hook.name(), "rewrote the content");
+            }
+        }
+        Ok(())
+    }
+
+    // Clone the chain so no lock is held across an await point.
+    fn snapshot(&self) -> Vec<Arc<dyn MessageHook>> {
+        self.hooks.read().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::provenance::{Origin, ProvenanceStep};
+
+    struct Shout;
+
+    #[async_trait]
+    impl MessageHook for Shout {
+        fn name(&self) -> &str {
+            "shout"
+        }
+
+        async fn on_message(&self, message: &mut PendingMessage) -> Result<(), String> {
+            message.content = message.content.to_uppercase();
+            Ok(())
+        }
+    }
+
+    struct BlockWord(&'static str);
+
+    #[async_trait]
+    impl MessageHook for BlockWord {
+        fn name(&self) -> &str {
+            "block-word"
+        }
+
+        async fn on_message(&self, message: &mut PendingMessage) -> Result<(), String> {
+            if message.content.contains(self.0) {
+                return Err(format!("'{}' is not allowed", self.0));
+            }
+            Ok(())
+        }
+    }
+
+    fn pending(content: &str) -> PendingMessage {
+        let client_id = Uuid::new_v4();
+        PendingMessage::new(
+            client_id,
+            "general".to_string(),
+            content.to_string(),
+            Provenance::new(Origin::Client(client_id)),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_hooks_rewrite_in_order() {
+        let hooks = MessageHooks::default();
+        hooks.register(Arc::new(Shout));
+        hooks.register(Arc::new(BlockWord("spam")));
+
+        let mut message = pending("hello");
+        hooks.run(&mut message).await.unwrap();
+        assert_eq!(message.content, "HELLO");
+        let steps: Vec<_> = message
+            .provenance
+            .entries()
+            .iter()
+            .map(|entry| entry.step.clone())
+            .collect();
+        assert_eq!(
+            steps[1],
+            ProvenanceStep::Transform {
+                stage: "shout".to_string(),
+                description: "rewrote the content".to_string(),
+            }
+        );
+
+        // Later hooks see the content as rewritten by earlier ones.
+        let mut message = pending("spam");
+        assert!(hooks.run(&mut message).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rejection_stops_the_chain() {
+        let hooks = MessageHooks::default();
+        hooks.register(Arc::new(BlockWord("spam")));
+        hooks.register(Arc::new(Shout));
+
+        let mut message = pending("buy spam");
+        assert_eq!(
+            hooks.run(&mut message).await,
+            Err("'spam' is not allowed".to_string())
+        );
+        assert_eq!(message.content, "buy spam");
+        assert_eq!(message.provenance.entries().len(), 1);
+    }
+}
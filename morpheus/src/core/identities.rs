@@ -0,0 +1,150 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+use uuid::Uuid;
+
+/// When a durable identity was online, as recorded by `ConnectionHistory`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityRecord {
+    pub first_seen: DateTime<Utc>,
+    /// When the identity last connected or disconnected.
+    pub last_seen: DateTime<Utc>,
+    /// How many times the identity connected, counting resumed sessions.
+    pub sessions: u64,
+    /// The address of its latest connection, unknown for internal clients.
+    pub last_ip: Option<IpAddr>,
+}
+
+/// The answer to `/whois` and `GET /whois/<identity>`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Whois {
+    pub identity: Uuid,
+    #[serde(flatten)]
+    pub record: IdentityRecord,
+    /// Whether the identity is connected right now.
+    pub online: bool,
+}
+
+/// The connection history of every durable identity, i.e. the client ID of a client that
+/// connected with a session, which it keeps when it resumes the session. Saved as JSON
+/// after every change so the history survives a restart.
+#[derive(Debug, Default)]
+pub struct ConnectionHistory {
+    path: Option<PathBuf>,
+    records: RwLock<BTreeMap<Uuid, IdentityRecord>>,
+}
+
+impl ConnectionHistory {
+    /// Reads the history saved at `path`, starting empty if the file does not exist yet.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let records = match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| format!("Invalid connection history {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            records: RwLock::new(records),
+        })
+    }
+
+    /// Records that `identity` connected, or resumed its session, from `ip`.
+    pub fn connected(&self, identity: Uuid, ip: Option<IpAddr>) -> Result<(), String> {
+        let now = Utc::now();
+        let mut records = self.records.write().unwrap();
+        let record = records.entry(identity).or_insert(IdentityRecord {
+            first_seen: now,
+            last_seen: now,
+            sessions: 0,
+            last_ip: None,
+        });
+        record.last_seen = now;
+        record.sessions += 1;
+        record.last_ip = ip;
+        self.save(&records)
+    }
+
+    /// Records that `identity` went offline. Identities never seen connecting are ignored.
+    pub fn disconnected(&self, identity: &Uuid) -> Result<(), String> {
+        let mut records = self.records.write().unwrap();
+        let Some(record) = records.get_mut(identity) else {
+            return Ok(());
+        };
+        record.last_seen = Utc::now();
+        self.save(&records)
+    }
+
+    pub fn get(&self, identity: &Uuid) -> Option<IdentityRecord> {
+        self.records.read().unwrap().get(identity).cloned()
+    }
+
+    /// Deletes everything recorded about `identity`, returning the removed record.
+    pub fn forget(&self, identity: &Uuid) -> Result<Option<IdentityRecord>, String> {
+        let mut records = self.records.write().unwrap();
+        let Some(record) = records.remove(identity) else {
+            return Ok(None);
+        };
+        self.save(&records)?;
+        Ok(Some(record))
+    }
+
+    /// Writes the history to a temporary file first so a crash never leaves it half written.
+    fn save(&self, records: &BTreeMap<Uuid, IdentityRecord>) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let text = serde_json::to_string_pretty(records).map_err(|e| e.to_string())?;
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, text)
+            .and_then(|_| std::fs::rename(&temp, path))
+            .map_err(|e| format!("Failed to save {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_survives_reload() {
+        let path =
+            std::env::temp_dir().join(format!("morpheus-connections-{}.json", Uuid::new_v4()));
+        let identity = Uuid::new_v4();
+        let history = ConnectionHistory::load(&path).unwrap();
+        assert!(history.get(&identity).is_none());
+
+        history
+            .connected(identity, Some("10.0.0.1".parse().unwrap()))
+            .unwrap();
+        let first = history.get(&identity).unwrap();
+        history
+            .connected(identity, Some("10.0.0.2".parse().unwrap()))
+            .unwrap();
+        history.disconnected(&identity).unwrap();
+        history.disconnected(&Uuid::new_v4()).unwrap();
+
+        let record = ConnectionHistory::load(&path)
+            .unwrap()
+            .get(&identity)
+            .unwrap();
+        assert_eq!(record.first_seen, first.first_seen);
+        assert!(record.last_seen >= first.last_seen);
+        assert_eq!(record.sessions, 2);
+        assert_eq!(record.last_ip, Some("10.0.0.2".parse().unwrap()));
+
+        let history = ConnectionHistory::load(&path).unwrap();
+        assert_eq!(history.forget(&identity).unwrap().unwrap().sessions, 2);
+        assert!(history.forget(&identity).unwrap().is_none());
+        assert!(ConnectionHistory::load(&path)
+            .unwrap()
+            .get(&identity)
+            .is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
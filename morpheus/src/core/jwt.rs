@@ -0,0 +1,185 @@
+use super::policy::Permissions;
+use jsonwebtoken::{decode, errors::ErrorKind, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use std::{fmt, path::PathBuf};
+
+/// How the JSON Web Tokens clients connect with are verified.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JwtConfig {
+    /// The algorithm tokens must be signed with; any other is refused.
+    pub algorithm: Algorithm,
+    /// The shared secret of the `HS*` algorithms.
+    pub secret: Option<String>,
+    /// A PEM file with the public key of the `RS*`, `PS*`, `ES*` and `EdDSA` algorithms.
+    pub public_key: Option<PathBuf>,
+    /// The `iss` claim tokens must carry, if any.
+    pub issuer: Option<String>,
+    /// The `aud` claim tokens must carry, if any.
+    pub audience: Option<String>,
+}
+
+/// Verifies tokens against a [`JwtConfig`] and reads the permissions from their claims.
+#[derive(Clone)]
+pub struct Jwt {
+    config: JwtConfig,
+    key: DecodingKey,
+    validation: Validation,
+}
+
+impl Jwt {
+    /// Loads the key of `config`, reading the public key file if it names one.
+    pub fn new(config: &JwtConfig) -> Result<Self, String> {
+        let algorithm = config.algorithm;
+        let key = match (algorithm, &config.secret, &config.public_key) {
+            (Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512, Some(secret), None) => {
+                if secret.is_empty() {
+                    return Err("The JWT secret is empty".to_string());
+                }
+                DecodingKey::from_secret(secret.as_bytes())
+            }
+            (Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512, _, _) => {
+                return Err(format!(
+                    "JWT algorithm {:?} needs a secret and no public key",
+                    algorithm
+                ));
+            }
+            (_, None, Some(path)) => {
+                let pem = std::fs::read(path).map_err(|e| {
+                    format!("Failed to read JWT public key {}: {}", path.display(), e)
+                })?;
+                let key = match algorithm {
+                    Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(&pem),
+                    Algorithm::EdDSA => DecodingKey::from_ed_pem(&pem),
+                    _ => DecodingKey::from_rsa_pem(&pem),
+                };
+                key.map_err(|e| format!("Invalid JWT public key {}: {}", path.display(), e))?
+            }
+            (_, _, _) => {
+                return Err(format!(
+                    "JWT algorithm {:?} needs a public key and no secret",
+                    algorithm
+                ));
+            }
+        };
+        let mut validation = Validation::new(algorithm);
+        let mut required = vec!["exp"];
+        if let Some(issuer) = &config.issuer {
+            validation.set_issuer(&[issuer]);
+            required.push("iss");
+        }
+        match &config.audience {
+            Some(audience) => {
+                validation.set_audience(&[audience]);
+                required.push("aud");
+            }
+            None => validation.validate_aud = false,
+        }
+        validation.set_required_spec_claims(&required);
+        Ok(Self {
+            config: config.clone(),
+            key,
+            validation,
+        })
+    }
+
+    /// The permissions `token` grants, or why it is not accepted.
+    pub fn verify(&self, token: &str) -> Result<Permissions, String> {
+        decode::<Permissions>(token, &self.key, &self.validation)
+            .map(|data| data.claims)
+            .map_err(|e| match e.kind() {
+                ErrorKind::ExpiredSignature => "The token has expired".to_string(),
+                ErrorKind::InvalidSignature => "The token's signature is invalid".to_string(),
+                _ => format!("Invalid token: {}", e),
+            })
+    }
+}
+
+impl fmt::Debug for Jwt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Leaves the key out.
+        f.debug_struct("Jwt")
+            .field("algorithm", &self.config.algorithm)
+            .field("issuer", &self.config.issuer)
+            .field("audience", &self.config.audience)
+            .finish()
+    }
+}
+
+impl PartialEq for Jwt {
+    fn eq(&self, other: &Self) -> bool {
+        self.config == other.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, get_current_timestamp, EncodingKey, Header};
+    use serde_json::json;
+
+    fn sign(claims: serde_json::Value, secret: &str) -> String {
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_claims_become_permissions() {
+        let jwt = Jwt::new(&JwtConfig {
+            secret: Some("secret".to_string()),
+            issuer: Some("auth.example.com".to_string()),
+            ..JwtConfig::default()
+        })
+        .unwrap();
+        let exp = get_current_timestamp() + 60;
+
+        let token = sign(
+            json!({"exp": exp, "iss": "auth.example.com", "topics": ["ops"], "publish": false}),
+            "secret",
+        );
+        let permissions = jwt.verify(&token).unwrap();
+        assert!(permissions.may_subscribe("ops"));
+        assert!(!permissions.may_subscribe("general"));
+        assert!(!permissions.may_publish("ops"));
+        assert!(!permissions.admin);
+
+        let token = sign(json!({"exp": exp, "iss": "auth.example.com"}), "secret");
+        assert!(jwt.verify(&token).unwrap().may_publish("general"));
+
+        let forged = sign(json!({"exp": exp, "iss": "auth.example.com"}), "guess");
+        assert!(jwt.verify(&forged).is_err());
+        let expired = sign(
+            json!({"exp": exp - 3600, "iss": "auth.example.com"}),
+            "secret",
+        );
+        assert_eq!(
+            jwt.verify(&expired),
+            Err("The token has expired".to_string())
+        );
+        let foreign = sign(json!({"exp": exp, "iss": "elsewhere"}), "secret");
+        assert!(jwt.verify(&foreign).is_err());
+        let forever = sign(json!({"iss": "auth.example.com"}), "secret");
+        assert!(jwt.verify(&forever).is_err());
+    }
+
+    #[test]
+    fn test_keys_must_match_the_algorithm() {
+        assert!(Jwt::new(&JwtConfig::default()).is_err());
+        assert!(Jwt::new(&JwtConfig {
+            algorithm: Algorithm::RS256,
+            secret: Some("secret".to_string()),
+            ..JwtConfig::default()
+        })
+        .is_err());
+        assert!(Jwt::new(&JwtConfig {
+            algorithm: Algorithm::RS256,
+            public_key: Some("/nonexistent/key.pem".into()),
+            ..JwtConfig::default()
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use crate::core::analytics::SIZE_BUCKETS;
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// The upper bounds of the subscriber count buckets; larger topics fall into a final
+/// open-ended bucket.
+pub const SUBSCRIBER_BUCKETS: [usize; 5] = [1, 10, 100, 1000, 10000];
+/// The upper bounds of the latency histogram buckets in microseconds; slower broadcasts
+/// fall into a final open-ended bucket.
+pub const LATENCY_BUCKETS_US: [u64; 9] = [50, 100, 250, 500, 1000, 2500, 5000, 10000, 50000];
+
+const SUBSCRIBER_SLOTS: usize = SUBSCRIBER_BUCKETS.len() + 1;
+const SIZE_SLOTS: usize = SIZE_BUCKETS.len() + 1;
+const LATENCY_SLOTS: usize = LATENCY_BUCKETS_US.len() + 1;
+
+/// The index of the bucket `value` falls into, given the buckets' upper bounds.
+fn bucket<T: PartialOrd>(bounds: &[T], value: T) -> usize {
+    bounds
+        .iter()
+        .position(|bound| value <= *bound)
+        .unwrap_or(bounds.len())
+}
+
+/// Broadcast latencies counted into the `LATENCY_BUCKETS_US`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    counts: [u64; LATENCY_SLOTS],
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        self.counts[bucket(&LATENCY_BUCKETS_US, latency.as_micros() as u64)] += 1;
+    }
+
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts) {
+            *count += other;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The upper bound in microseconds of the bucket holding the `percentile`th fastest
+    /// broadcast, or `None` if nothing was recorded or it is in the open-ended bucket.
+    pub fn percentile(&self, percentile: f64) -> Option<u64> {
+        let total = self.count();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((percentile / 100.0) * total as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return LATENCY_BUCKETS_US.get(bucket).copied();
+            }
+        }
+        None
+    }
+}
+
+/// The broadcasts of one subscriber count bucket and content size bucket, each given by
+/// its index into `SUBSCRIBER_BUCKETS` and `SIZE_BUCKETS`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cell {
+    pub subscribers: usize,
+    pub size: usize,
+}
+
+/// Broadcast latencies since the server started, shown by `/stats latency`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyReport {
+    pub overall: LatencyHistogram,
+    /// The cells that saw at least one broadcast.
+    pub cells: BTreeMap<Cell, LatencyHistogram>,
+}
+
+impl LatencyReport {
+    /// The `percentile`th broadcast latency in microseconds as a metric value: 0 before
+    /// anything was recorded and one more than the largest bucket bound for broadcasts
+    /// beyond it.
+    pub fn latency_metric(&self, percentile: f64) -> usize {
+        match self.overall.percentile(percentile) {
+            Some(bound) => bound as usize,
+            None if self.overall.count() == 0 => 0,
+            None => LATENCY_BUCKETS_US[LATENCY_BUCKETS_US.len() - 1] as usize + 1,
+        }
+    }
+}
+
+/// Counts how long topic broadcasts take to reach every subscriber's queue, by how many
+/// subscribers the topic has and how large the content is. Recording only bumps an
+/// atomic counter, so it costs the broadcast nothing measurable.
+#[derive(Debug)]
+pub struct BroadcastLatency {
+    /// One counter per subscriber bucket, size bucket and latency bucket, in that order.
+    counts: Vec<AtomicU64>,
+}
+
+impl Default for BroadcastLatency {
+    fn default() -> Self {
+        Self {
+            counts: (0..SUBSCRIBER_SLOTS * SIZE_SLOTS * LATENCY_SLOTS)
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+        }
+    }
+}
+
+impl BroadcastLatency {
+    /// Records a broadcast of `bytes` content bytes to `subscribers` clients.
+    pub fn record(&self, subscribers: usize, bytes: usize, latency: Duration) {
+        let cell =
+            bucket(&SUBSCRIBER_BUCKETS, subscribers) * SIZE_SLOTS + bucket(&SIZE_BUCKETS, bytes);
+        let index = cell * LATENCY_SLOTS + bucket(&LATENCY_BUCKETS_US, latency.as_micros() as u64);
+        self.counts[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The histograms of every cell that saw a broadcast, and their sum.
+    pub fn report(&self) -> LatencyReport {
+        let mut report = LatencyReport::default();
+        for (cell, counts) in self.counts.chunks(LATENCY_SLOTS).enumerate() {
+            let mut histogram = LatencyHistogram::default();
+            for (count, counter) in histogram.counts.iter_mut().zip(counts) {
+                *count = counter.load(Ordering::Relaxed);
+            }
+            if histogram.count() == 0 {
+                continue;
+            }
+            report.overall.merge(&histogram);
+            let cell = Cell {
+                subscribers: cell / SIZE_SLOTS,
+                size: cell % SIZE_SLOTS,
+            };
+            report.cells.insert(cell, histogram);
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_broadcasts_are_bucketed_by_subscribers_and_size() {
+        let latency = BroadcastLatency::default();
+        latency.record(1, 10, Duration::from_micros(40));
+        latency.record(1, 20, Duration::from_micros(400));
+        latency.record(500, 5000, Duration::from_millis(3));
+        latency.record(50_000, 1_000_000, Duration::from_secs(1));
+
+        let report = latency.report();
+        assert_eq!(report.overall.count(), 4);
+        assert_eq!(report.cells.len(), 3);
+        let small = &report.cells[&Cell {
+            subscribers: 0,
+            size: 0,
+        }];
+        assert_eq!(small.count(), 2);
+        assert_eq!(small.percentile(50.0), Some(50));
+        assert_eq!(small.percentile(100.0), Some(500));
+        let medium = &report.cells[&Cell {
+            subscribers: 3,
+            size: 4,
+        }];
+        assert_eq!(medium.percentile(50.0), Some(5000));
+        let huge = &report.cells[&Cell {
+            subscribers: SUBSCRIBER_BUCKETS.len(),
+            size: SIZE_BUCKETS.len(),
+        }];
+        assert_eq!(huge.percentile(50.0), None);
+    }
+
+    #[test]
+    fn test_latency_metric() {
+        let latency = BroadcastLatency::default();
+        assert_eq!(latency.report().latency_metric(95.0), 0);
+        latency.record(3, 100, Duration::from_micros(80));
+        assert_eq!(latency.report().latency_metric(50.0), 100);
+        latency.record(3, 100, Duration::from_secs(1));
+        assert_eq!(latency.report().latency_metric(95.0), 50001);
+    }
+}
@@ -0,0 +1,216 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::Mutex,
+};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// How many recent events are kept for admin tools that reconnect and resume.
+pub const REPLAY_CAPACITY: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Joined,
+    Left,
+}
+
+/// One change to a topic's subscribers, as sent on the admin event stream.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MembershipEvent {
+    /// Counts every change on this node; the event that brings a snapshot's `sequence`
+    /// up by one is the first to apply on top of it.
+    pub sequence: u64,
+    pub topic: String,
+    pub change: Change,
+    pub client_id: Uuid,
+    /// The topic's subscribers after the change.
+    pub count: usize,
+}
+
+/// The subscribers of every topic as of `sequence`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MembershipSnapshot {
+    pub sequence: u64,
+    pub topics: BTreeMap<String, BTreeSet<Uuid>>,
+}
+
+/// Returned when an admin tool asks to resume after events that are no longer kept, or
+/// from a sequence number this node never reached (e.g. one from before a restart); it
+/// has to start over from a snapshot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stale {
+    /// The oldest sequence number that can still be resumed after.
+    pub oldest: u64,
+}
+
+#[derive(Default)]
+struct State {
+    snapshot: MembershipSnapshot,
+    recent: VecDeque<MembershipEvent>,
+}
+
+/// Topic membership as seen by admin tools: a versioned snapshot and the stream of
+/// changes to it. Both are updated under one lock so no change is missed or seen twice
+/// between a snapshot and the events that follow it.
+pub struct Membership {
+    state: Mutex<State>,
+    events: broadcast::Sender<MembershipEvent>,
+}
+
+impl Default for Membership {
+    fn default() -> Self {
+        Self {
+            state: Mutex::default(),
+            events: broadcast::channel(REPLAY_CAPACITY).0,
+        }
+    }
+}
+
+impl Membership {
+    /// Records a client moving from `from`, if it was subscribed anywhere, to `to`.
+    pub(crate) fn moved(&self, client_id: Uuid, from: Option<&str>, to: &str) {
+        if from == Some(to) {
+            return;
+        }
+        if let Some(from) = from {
+            self.change(client_id, from, Change::Left);
+        }
+        self.change(client_id, to, Change::Joined);
+    }
+
+    /// Records a subscribed client disconnecting.
+    pub(crate) fn left(&self, client_id: Uuid, topic: &str) {
+        self.change(client_id, topic, Change::Left);
+    }
+
+    fn change(&self, client_id: Uuid, topic: &str, change: Change) {
+        let mut state = self.state.lock().unwrap();
+        let snapshot = &mut state.snapshot;
+        let count = match change {
+            Change::Joined => {
+                let members = snapshot.topics.entry(topic.to_string()).or_default();
+                if !members.insert(client_id) {
+                    return;
+                }
+                members.len()
+            }
+            Change::Left => {
+                let Some(members) = snapshot.topics.get_mut(topic) else {
+                    return;
+                };
+                if !members.remove(&client_id) {
+                    return;
+                }
+                let count = members.len();
+                if count == 0 {
+                    snapshot.topics.remove(topic);
+                }
+                count
+            }
+        };
+        snapshot.sequence += 1;
+        let event = MembershipEvent {
+            sequence: snapshot.sequence,
+            topic: topic.to_string(),
+            change,
+            client_id,
+            count,
+        };
+        if state.recent.len() == REPLAY_CAPACITY {
+            state.recent.pop_front();
+        }
+        state.recent.push_back(event.clone());
+        // Nobody listening is not an error.
+        let _ = self.events.send(event);
+    }
+
+    pub fn snapshot(&self) -> MembershipSnapshot {
+        self.state.lock().unwrap().snapshot.clone()
+    }
+
+    /// Subscribes to changes after sequence number `since`, returning the ones that
+    /// already happened followed by a receiver for the rest. Without `since` only new
+    /// changes are delivered.
+    pub fn subscribe(
+        &self,
+        since: Option<u64>,
+    ) -> Result<(Vec<MembershipEvent>, broadcast::Receiver<MembershipEvent>), Stale> {
+        let state = self.state.lock().unwrap();
+        let missed = match since {
+            None => Vec::new(),
+            Some(since) => {
+                let oldest = state
+                    .recent
+                    .front()
+                    .map_or(state.snapshot.sequence, |event| event.sequence - 1);
+                if since < oldest || since > state.snapshot.sequence {
+                    return Err(Stale { oldest });
+                }
+                state
+                    .recent
+                    .iter()
+                    .filter(|event| event.sequence > since)
+                    .cloned()
+                    .collect()
+            }
+        };
+        Ok((missed, self.events.subscribe()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes_are_sequenced_and_replayed() {
+        let membership = Membership::default();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        membership.moved(a, None, "news");
+        membership.moved(b, None, "news");
+        let snapshot = membership.snapshot();
+        assert_eq!(snapshot.sequence, 2);
+        assert_eq!(snapshot.topics["news"], BTreeSet::from([a, b]));
+
+        // Re-subscribing to the same topic is not a change.
+        membership.moved(a, Some("news"), "news");
+        membership.moved(a, Some("news"), "sports");
+        membership.left(b, "news");
+        membership.left(b, "news");
+
+        let (missed, _) = membership.subscribe(Some(snapshot.sequence)).unwrap();
+        let changes: Vec<_> = missed
+            .iter()
+            .map(|e| (e.sequence, e.topic.as_str(), e.change, e.count))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (3, "news", Change::Left, 1),
+                (4, "sports", Change::Joined, 1),
+                (5, "news", Change::Left, 0),
+            ]
+        );
+        assert!(!membership.snapshot().topics.contains_key("news"));
+        assert_eq!(
+            membership.subscribe(Some(6)).unwrap_err(),
+            Stale { oldest: 0 }
+        );
+    }
+
+    #[test]
+    fn test_resuming_after_evicted_events_is_stale() {
+        let membership = Membership::default();
+        for _ in 0..=REPLAY_CAPACITY {
+            membership.moved(Uuid::new_v4(), None, "news");
+        }
+        assert_eq!(
+            membership.subscribe(Some(0)).unwrap_err(),
+            Stale { oldest: 1 }
+        );
+        let (missed, _) = membership.subscribe(Some(1)).unwrap();
+        assert_eq!(missed.len(), REPLAY_CAPACITY);
+    }
+}
@@ -0,0 +1,102 @@
+use std::{fmt, str::FromStr};
+
+/// A point-in-time snapshot of server load, taken with `ClientManager::metrics`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub connected_clients: usize,
+    pub active_topics: usize,
+    /// Messages sent to session clients that have not been acknowledged yet.
+    pub pending_acks: usize,
+    /// Messages waiting in client queues to be written to their sockets.
+    pub queued_messages: usize,
+    /// Round trip time of the canary's last successful probe, 0 before the first one.
+    pub canary_latency_ms: usize,
+    /// How many canary probes in a row have failed.
+    pub canary_failures: usize,
+    /// The median size of recently published messages, as a size bucket bound in bytes.
+    pub message_bytes_p50: usize,
+    /// The 95th percentile size of recently published messages, as a size bucket bound.
+    pub message_bytes_p95: usize,
+    /// The median time topic broadcasts took to reach every subscriber's queue, as a
+    /// latency bucket bound in microseconds.
+    pub broadcast_latency_us_p50: usize,
+    /// The 95th percentile broadcast time, as a latency bucket bound in microseconds.
+    pub broadcast_latency_us_p95: usize,
+}
+
+impl Metrics {
+    pub fn get(&self, metric: Metric) -> usize {
+        match metric {
+            Metric::ConnectedClients => self.connected_clients,
+            Metric::ActiveTopics => self.active_topics,
+            Metric::PendingAcks => self.pending_acks,
+            Metric::QueuedMessages => self.queued_messages,
+            Metric::CanaryLatencyMs => self.canary_latency_ms,
+            Metric::CanaryFailures => self.canary_failures,
+            Metric::MessageBytesP50 => self.message_bytes_p50,
+            Metric::MessageBytesP95 => self.message_bytes_p95,
+            Metric::BroadcastLatencyUsP50 => self.broadcast_latency_us_p50,
+            Metric::BroadcastLatencyUsP95 => self.broadcast_latency_us_p95,
+        }
+    }
+}
+
+const METRIC_NAMES: &str = "connected_clients, active_topics, pending_acks, queued_messages, \
+    canary_latency_ms, canary_failures, message_bytes_p50, message_bytes_p95, \
+    broadcast_latency_us_p50 or broadcast_latency_us_p95";
+
+/// The name of a single value in `Metrics`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    ConnectedClients,
+    ActiveTopics,
+    PendingAcks,
+    QueuedMessages,
+    CanaryLatencyMs,
+    CanaryFailures,
+    MessageBytesP50,
+    MessageBytesP95,
+    BroadcastLatencyUsP50,
+    BroadcastLatencyUsP95,
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "connected_clients" => Ok(Self::ConnectedClients),
+            "active_topics" => Ok(Self::ActiveTopics),
+            "pending_acks" => Ok(Self::PendingAcks),
+            "queued_messages" => Ok(Self::QueuedMessages),
+            "canary_latency_ms" => Ok(Self::CanaryLatencyMs),
+            "canary_failures" => Ok(Self::CanaryFailures),
+            "message_bytes_p50" => Ok(Self::MessageBytesP50),
+            "message_bytes_p95" => Ok(Self::MessageBytesP95),
+            "broadcast_latency_us_p50" => Ok(Self::BroadcastLatencyUsP50),
+            "broadcast_latency_us_p95" => Ok(Self::BroadcastLatencyUsP95),
+            _ => Err(format!(
+                "Unknown metric '{}' (expected {})",
+                s, METRIC_NAMES
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::ConnectedClients => "connected_clients",
+            Self::ActiveTopics => "active_topics",
+            Self::PendingAcks => "pending_acks",
+            Self::QueuedMessages => "queued_messages",
+            Self::CanaryLatencyMs => "canary_latency_ms",
+            Self::CanaryFailures => "canary_failures",
+            Self::MessageBytesP50 => "message_bytes_p50",
+            Self::MessageBytesP95 => "message_bytes_p95",
+            Self::BroadcastLatencyUsP50 => "broadcast_latency_us_p50",
+            Self::BroadcastLatencyUsP95 => "broadcast_latency_us_p95",
+        };
+        f.write_str(name)
+    }
+}
@@ -0,0 +1,330 @@
+use crate::core::{
+    history::{HistoryHook, StoredMessage},
+    hooks::{MessageHook, PendingMessage},
+    msg::{self, ClientMessage, CloseCode, Qos, ServerMessage},
+    provenance::Origin,
+};
+use async_trait::async_trait;
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::{collections::BTreeMap, time::Duration};
+use tokio::sync::mpsc;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{
+        client::IntoClientRequest, handshake::client::Request, http::HeaderValue, Message,
+    },
+};
+
+/// The header listing the instances a mirrored message went through, comma separated.
+/// An instance never mirrors a message that already passed through it, so mirrors
+/// pointing at each other do not loop.
+pub const VIA_HEADER: &str = "mirror-via";
+/// The header carrying the sender on the instance the message was first published on.
+pub const SENDER_HEADER: &str = "mirror-sender";
+
+/// The reason mirrored messages coming back to an instance they passed through are
+/// rejected with; links do not report these rejections.
+pub(crate) const LOOP_REJECTION: &str = "already mirrored through this instance";
+
+/// How many messages may be buffered per mirror while its link is down.
+pub(crate) const BACKLOG: usize = 1000;
+/// The delay after the first failed connection attempt to a remote instance.
+pub(crate) const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+/// The maximum delay between connection attempts to a remote instance.
+pub(crate) const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// A topic republished on another morpheus instance, from a `[[mirrors]]` config section.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MirrorConfig {
+    pub topic: String,
+    /// The remote instance's client WebSocket URL, e.g. `wss://other-host/ws`.
+    pub url: String,
+    /// The token the remote instance expects from its clients.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// Republishes the messages of mirrored topics on remote instances, connecting to each
+/// as an ordinary client. Unlike clustering this is one-way: nothing published on the
+/// remote instance comes back, unless it mirrors the topic here itself.
+pub struct MirrorForwarder {
+    /// The name this instance adds to `VIA_HEADER`.
+    instance_id: String,
+    mirrors: Vec<Mirror>,
+}
+
+struct Mirror {
+    topic: String,
+    url: String,
+    backlog: mpsc::Sender<String>,
+}
+
+impl MirrorForwarder {
+    /// Starts a link task per mirror.
+    pub fn start(configs: &[MirrorConfig], instance_id: String) -> Self {
+        let mirrors = configs
+            .iter()
+            .map(|config| {
+                let (backlog, rx) = mpsc::channel(BACKLOG);
+                tokio::spawn(run_link(config.clone(), rx));
+                Mirror {
+                    topic: config.topic.clone(),
+                    url: config.url.clone(),
+                    backlog,
+                }
+            })
+            .collect();
+        Self {
+            instance_id,
+            mirrors,
+        }
+    }
+}
+
+#[async_trait]
+impl HistoryHook for MirrorForwarder {
+    async fn on_message_stored(&self, message: &StoredMessage) {
+        let mut mirrors = self
+            .mirrors
+            .iter()
+            .filter(|mirror| mirror.topic == message.topic)
+            .peekable();
+        if mirrors.peek().is_none() {
+            return;
+        }
+        let Some(mirrored) = mirrored(message, &self.instance_id) else {
+            return;
+        };
+        let text = match msg::encode(&mirrored) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("Failed to serialize mirrored message: {}", e);
+                return;
+            }
+        };
+        for mirror in mirrors {
+            if let Err(mpsc::error::TrySendError::Full(_)) = mirror.backlog.try_send(text.clone()) {
+                eprintln!(
+                    "Mirror of topic '{}' to {} is behind, dropping message {}",
+                    mirror.topic, mirror.url, message.id
+                );
+            }
+        }
+    }
+}
+
+/// Rejects mirrored messages that come back to an instance they already passed through,
+/// which happens when two instances mirror a topic to each other. Registered as a
+/// message hook next to the `MirrorForwarder`.
+pub struct LoopGuard {
+    instance_id: String,
+}
+
+impl LoopGuard {
+    pub fn new(instance_id: String) -> Self {
+        Self { instance_id }
+    }
+}
+
+#[async_trait]
+impl MessageHook for LoopGuard {
+    fn name(&self) -> &str {
+        "mirror-loop-guard"
+    }
+
+    async fn on_message(&self, message: &mut PendingMessage) -> Result<(), String> {
+        if passed_through(&message.headers, &self.instance_id) {
+            return Err(LOOP_REJECTION.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Whether a message with these headers was already mirrored by `instance_id`.
+pub(crate) fn passed_through(headers: &BTreeMap<String, String>, instance_id: &str) -> bool {
+    headers
+        .get(VIA_HEADER)
+        .is_some_and(|via| via.split(',').any(|id| id == instance_id))
+}
+
+/// The message to publish on a remote instance for `message`, or `None` if it must not
+/// be mirrored: it already passed through this instance, or it was forwarded by a
+/// cluster peer, which mirrors it itself.
+pub(crate) fn mirrored(message: &StoredMessage, instance_id: &str) -> Option<ClientMessage> {
+    if matches!(
+        message.provenance.origin(),
+        Some(Origin::Bridge(name)) if name.starts_with("cluster:")
+    ) {
+        return None;
+    }
+    if passed_through(&message.headers, instance_id) {
+        return None;
+    }
+    let mut headers = message.headers.clone();
+    let via = match headers.get(VIA_HEADER) {
+        Some(via) => format!("{},{}", via, instance_id),
+        None => instance_id.to_string(),
+    };
+    headers.insert(VIA_HEADER.to_string(), via);
+    headers
+        .entry(SENDER_HEADER.to_string())
+        .or_insert_with(|| message.sender.clone());
+    Some(ClientMessage::Message {
+        topic: message.topic.clone(),
+        content: message.content.clone(),
+        headers,
+        qos: Qos::FireAndForget,
+        id: Some(message.id),
+        reply_to: message.reply_to,
+        retain: false,
+    })
+}
+
+/// The handshake request for the remote instance, carrying the token if there is one.
+pub(crate) fn request(url: &str, token: Option<&str>) -> Result<Request, String> {
+    let mut request = url
+        .into_client_request()
+        .map_err(|e| format!("invalid URL {}: {}", url, e))?;
+    if let Some(token) = token {
+        let value = HeaderValue::from_str(&format!("Bearer {}", token))
+            .map_err(|_| "the token is not a valid header value".to_string())?;
+        request.headers_mut().insert("authorization", value);
+    }
+    Ok(request)
+}
+
+/// Keeps a client connection to the remote instance open, publishing every queued
+/// message over it. Reconnects with backoff until the remote instance refuses us for
+/// good with an authentication, ban or kick close code.
+async fn run_link(config: MirrorConfig, mut rx: mpsc::Receiver<String>) {
+    let mut delay = RETRY_BASE_DELAY;
+    loop {
+        let request = match request(&config.url, config.token.as_deref()) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("Cannot mirror topic '{}': {}", config.topic, e);
+                return;
+            }
+        };
+        match connect_async(request).await {
+            Ok((ws, _)) => {
+                println!("Mirroring topic '{}' to {}.", config.topic, config.url);
+                delay = RETRY_BASE_DELAY;
+                let (mut write, mut read) = ws.split();
+                let close_code = loop {
+                    tokio::select! {
+                        text = rx.recv() => {
+                            let Some(text) = text else {
+                                return; // The forwarder was dropped.
+                            };
+                            if write.send(Message::Text(text)).await.is_err() {
+                                break None;
+                            }
+                        }
+                        // Reading keeps pongs flowing and surfaces rejections.
+                        frame = read.next() => match frame {
+                            Some(Ok(Message::Text(text))) => {
+                                match msg::decode(&text) {
+                                    Ok(ServerMessage::Error { message })
+                                        if !message.ends_with(LOOP_REJECTION) =>
+                                    {
+                                        eprintln!(
+                                            "{} rejected a message mirrored from topic '{}': {}",
+                                            config.url, config.topic, message
+                                        );
+                                    }
+                                    _ => {}
+                                }
+                            }
+                            Some(Ok(Message::Close(frame))) => {
+                                break frame.map(|frame| u16::from(frame.code));
+                            }
+                            Some(Ok(_)) => {}
+                            Some(Err(_)) | None => break None,
+                        }
+                    }
+                };
+                let refused = [CloseCode::AuthFailed, CloseCode::Banned, CloseCode::Kicked];
+                if let Some(code) =
+                    close_code.filter(|code| refused.iter().any(|c| c.code() == *code))
+                {
+                    eprintln!(
+                        "{} refused the mirror of topic '{}' (close code {}). Not reconnecting.",
+                        config.url, config.topic, code
+                    );
+                    return;
+                }
+                eprintln!("Mirror link to {} lost.", config.url);
+            }
+            Err(e) => eprintln!(
+                "Failed to connect mirror of topic '{}' to {}: {}",
+                config.topic, config.url, e
+            ),
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(RETRY_MAX_DELAY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::provenance::Provenance;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn stored(headers: &[(&str, &str)], origin: Origin) -> StoredMessage {
+        StoredMessage {
+            id: Uuid::new_v4(),
+            topic: "alerts".to_string(),
+            seq: 1,
+            sender: "Alice".to_string(),
+            content: "disk full".to_string(),
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            reply_to: None,
+            timestamp: Utc::now(),
+            provenance: Provenance::new(origin),
+        }
+    }
+
+    fn headers_of(message: Option<ClientMessage>) -> BTreeMap<String, String> {
+        match message {
+            Some(ClientMessage::Message { headers, .. }) => headers,
+            other => panic!("Unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_mirrored_messages_are_tagged_with_their_path() {
+        let message = stored(&[("trace-id", "abc")], Origin::Operator);
+        let headers = headers_of(mirrored(&message, "a"));
+        assert_eq!(headers[VIA_HEADER], "a");
+        assert_eq!(headers[SENDER_HEADER], "Alice");
+        assert_eq!(headers["trace-id"], "abc");
+
+        // The next hop keeps the original sender and extends the path.
+        let message = stored(
+            &[(VIA_HEADER, "a"), (SENDER_HEADER, "Alice")],
+            Origin::Operator,
+        );
+        let headers = headers_of(mirrored(&message, "b"));
+        assert_eq!(headers[VIA_HEADER], "a,b");
+        assert_eq!(headers[SENDER_HEADER], "Alice");
+    }
+
+    #[test]
+    fn test_messages_are_not_mirrored_twice() {
+        let looped = stored(&[(VIA_HEADER, "a,b")], Origin::Operator);
+        assert!(mirrored(&looped, "a").is_none());
+        assert!(mirrored(&looped, "b").is_none());
+        assert!(mirrored(&looped, "c").is_some());
+
+        let from_peer = stored(&[], Origin::Bridge("cluster:b".to_string()));
+        assert!(mirrored(&from_peer, "a").is_none());
+    }
+}
@@ -1,4 +1,42 @@
+pub mod admission;
+pub mod alerts;
+pub mod analytics;
+pub mod attachments;
+pub mod audit;
+pub mod bans;
+pub mod canary;
 pub mod client_manager;
+pub mod cluster;
+pub mod compression;
+pub mod consumer_groups;
+pub mod fanout;
+pub mod federation;
+pub mod forwards;
+pub mod history;
+pub mod hooks;
+pub mod identities;
+pub mod jwt;
+pub mod latency;
+pub mod membership;
+pub mod metrics;
+pub mod mirror;
 pub mod msg;
+pub mod policy;
+pub mod polls;
+pub mod provenance;
+pub mod qos;
+pub mod query;
+pub mod queue;
+pub mod receipt;
+pub mod requests;
+pub mod scheduler;
 pub mod server;
+pub mod simulator;
+pub mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod stats;
 pub mod storage;
+pub mod verbs;
+pub mod webhooks;
+pub mod work_queue;
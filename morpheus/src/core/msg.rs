@@ -1,14 +1,239 @@
-use serde::{Deserialize, Serialize};
+use crate::core::compression;
+use serde::{
+    de::{self, value::MapAccessDeserializer, DeserializeOwned, DeserializeSeed, IntoDeserializer},
+    Deserialize, Serialize,
+};
+use serde_json::{value::RawValue, Map, Value};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    sync::{Arc, OnceLock},
+};
 use uuid::Uuid;
 
+/// The protocol version this build speaks.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The message header neo signs messages with its user's name in.
+pub const NAME_HEADER: &str = "name";
+
+/// The wire format of every frame: `{ "v": 1, "type": ..., "payload": {...}, "meta": {...} }`.
+///
+/// Decoding is lenient so that peers on different versions can talk: unknown fields in a
+/// payload are ignored, an unknown `type` decodes to the `Unknown` variant, `meta` may carry
+/// anything and frames without `v` are read as version 1.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Envelope<T> {
+    #[serde(default = "default_version")]
+    pub v: u32,
+    #[serde(flatten)]
+    pub message: T,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub meta: Map<String, Value>,
+}
+
+fn default_version() -> u32 {
+    1
+}
+
+impl<T> Envelope<T> {
+    pub fn new(message: T) -> Self {
+        Self {
+            v: PROTOCOL_VERSION,
+            message,
+            meta: Map::new(),
+        }
+    }
+}
+
+/// Wraps a message in an envelope and serializes it.
+pub fn encode<T: Serialize>(message: &T) -> serde_json::Result<String> {
+    serde_json::to_string(&Envelope::new(message))
+}
+
+/// A frame whose message has not been matched against a message type yet. The payload
+/// is borrowed from the frame's text, so nothing is copied before the message is built.
+/// `v` and `meta` are not needed to decode and are skipped.
+#[derive(Deserialize)]
+struct RawMessage<'a> {
+    #[serde(rename = "type", borrow)]
+    kind: Cow<'a, str>,
+    #[serde(default, borrow)]
+    payload: Option<&'a RawValue>,
+}
+
+/// Parses a frame, returning the message inside its envelope.
+pub fn decode<T: DeserializeOwned>(text: &str) -> serde_json::Result<T> {
+    let raw: RawMessage = serde_json::from_str(text)?;
+    T::deserialize(MapAccessDeserializer::new(Tagged::new(&raw, true))).or_else(|error| {
+        // `#[serde(other)]` only matches a bare tag, so retry without the payload
+        // to map types we don't know to `Unknown`; known types keep their error.
+        T::deserialize(MapAccessDeserializer::new(Tagged::new(&raw, false))).map_err(|_| error)
+    })
+}
+
+/// Reads the type and payload of a frame without matching them against a message type,
+/// for types the protocol does not define, such as custom verbs. The payload is `Null`
+/// when the frame has none.
+pub fn decode_untyped(text: &str) -> serde_json::Result<(String, Value)> {
+    let raw: RawMessage = serde_json::from_str(text)?;
+    let payload = match raw.payload {
+        Some(payload) => serde_json::from_str(payload.get())?,
+        None => Value::Null,
+    };
+    Ok((raw.kind.into_owned(), payload))
+}
+
+/// The type of a frame, read without its payload, or `None` if the frame is not a JSON
+/// object with a type.
+pub fn kind(text: &str) -> Option<Cow<'_, str>> {
+    serde_json::from_str::<RawMessage>(text)
+        .ok()
+        .map(|raw| raw.kind)
+}
+
+/// The protocol version of a frame, read like `Envelope` reads it, or `None` if the frame
+/// is not a JSON object.
+pub fn version(text: &str) -> Option<u32> {
+    #[derive(Deserialize)]
+    struct Version {
+        #[serde(default = "default_version")]
+        v: u32,
+    }
+    serde_json::from_str::<Version>(text)
+        .ok()
+        .map(|frame| frame.v)
+}
+
+/// Presents a `RawMessage` as the `{"type": ..., "payload": ...}` map that message enums
+/// are tagged with, without building that map.
+struct Tagged<'r, 'a> {
+    raw: &'r RawMessage<'a>,
+    with_payload: bool,
+    /// The next entry: 0 is `type`, 1 is `payload`.
+    entry: u8,
+}
+
+impl<'r, 'a> Tagged<'r, 'a> {
+    fn new(raw: &'r RawMessage<'a>, with_payload: bool) -> Self {
+        Self {
+            raw,
+            with_payload,
+            entry: 0,
+        }
+    }
+}
+
+impl<'de> de::MapAccess<'de> for Tagged<'_, 'de> {
+    type Error = serde_json::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> serde_json::Result<Option<K::Value>> {
+        let key = match (self.entry, self.raw.payload) {
+            (0, _) => "type",
+            (1, Some(_)) if self.with_payload => "payload",
+            _ => return Ok(None),
+        };
+        seed.deserialize(key.into_deserializer()).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> serde_json::Result<V::Value> {
+        self.entry += 1;
+        match (self.entry, self.raw.payload) {
+            (1, _) => seed.deserialize(self.raw.kind.as_ref().into_deserializer()),
+            (_, Some(payload)) => seed.deserialize(payload),
+            (_, None) => Err(de::Error::custom("no payload")),
+        }
+    }
+}
+
+/// A message on its way to one or more clients. It is serialized the first time a
+/// WebSocket needs it, and that frame is shared by every queue it was sent to.
+#[derive(Debug)]
+pub struct Outgoing {
+    pub message: ServerMessage,
+    frame: OnceLock<Option<String>>,
+    /// The frame as a `Compressed` frame, or `None` if that is not smaller.
+    compressed: OnceLock<Option<String>>,
+}
+
+impl Outgoing {
+    pub fn new(message: ServerMessage) -> Self {
+        Self {
+            message,
+            frame: OnceLock::new(),
+            compressed: OnceLock::new(),
+        }
+    }
+
+    /// The message as a `Compressed` frame for clients that negotiated compression, or
+    /// `None` if its frame is shorter than `min_bytes` or would not get any shorter.
+    pub fn compressed_frame(&self, min_bytes: usize) -> Option<&str> {
+        let frame = self.frame().filter(|frame| frame.len() >= min_bytes)?;
+        self.compressed
+            .get_or_init(|| {
+                let data = compression::deflate(frame);
+                encode(&ServerMessage::Compressed { data })
+                    .ok()
+                    .filter(|compressed| compressed.len() < frame.len())
+            })
+            .as_deref()
+    }
+
+    /// The message as sent on the wire, or `None` if it cannot be serialized.
+    pub fn frame(&self) -> Option<&str> {
+        self.frame
+            .get_or_init(|| match encode(&self.message) {
+                Ok(frame) => Some(frame),
+                Err(e) => {
+                    eprintln!("Failed to serialize message: {}", e);
+                    None
+                }
+            })
+            .as_deref()
+    }
+}
+
 /// Messages sent from the client to the server.
 #[derive(Serialize, Deserialize, Debug)]
-#[serde(tag = "type")]
+#[serde(tag = "type", content = "payload")]
 pub enum ClientMessage {
     /// Initial message to connect and subscribe to a topic.
     Connect { topic: String },
+    /// Connects with a durable session, resuming `session_id` if it is still known.
+    ConnectSession {
+        topic: String,
+        session_id: Option<Uuid>,
+    },
     /// A message sent to a topic.
-    Message { topic: String, content: String },
+    Message {
+        topic: String,
+        content: String,
+        /// Custom metadata such as correlation IDs or routing hints, passed on to
+        /// subscribers, history and webhooks untouched.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        headers: BTreeMap<String, String>,
+        /// The delivery guarantee the sender asks for.
+        #[serde(default, skip_serializing_if = "Qos::is_fire_and_forget")]
+        qos: Qos,
+        /// An ID chosen by the sender, required above `FireAndForget` so that the server
+        /// can confirm the message and recognize retries. Subscribers get the message
+        /// under this ID.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<Uuid>,
+        /// The ID of the topic message this one replies to, if it is a reply.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reply_to: Option<Uuid>,
+        /// Keep this message as the topic's retained message, sent to every client that
+        /// subscribes from now on. Retaining an empty message clears it.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        retain: bool,
+    },
     /// A private reply to a message from Morpheus.
     ReplyToMorpheus {
         /// The ID of the message being replied to.
@@ -17,11 +242,95 @@ pub enum ClientMessage {
     },
     /// Acknowledgment that a message was received by the client.
     MessageReceived { msg_id: Uuid },
+    /// Agreement to the rules of a topic, answering `TopicInfo`.
+    AcceptTerms { topic: String, version: u32 },
+    /// Fetches up to `max` messages of the subscribed topic's history for a consumer
+    /// group, continuing where the group's members left off. Answered with `Fetched`.
+    Fetch { group: String, max: usize },
+    /// Records that a consumer group processed its topic up to sequence number `offset`.
+    CommitOffset { group: String, offset: u64 },
+    /// Asks for the retained messages of `topic`, which the client must be subscribed to,
+    /// from sequence number `from_seq` on. They are sent again as `Topic` messages; an
+    /// `Error` reports those no longer retained.
+    RequestMissed { topic: String, from_seq: u64 },
+    /// Catches up a client resuming from the background in one message: acknowledges the
+    /// messages in `acked`, then is answered like `RequestMissed`.
+    Resync {
+        topic: String,
+        from_seq: u64,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        acked: Vec<Uuid>,
+    },
+    /// Opens a poll in the client's topic, closing after `duration_secs` or the server's
+    /// default. Subscribers get it as `Poll`.
+    CreatePoll {
+        question: String,
+        options: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        duration_secs: Option<u64>,
+    },
+    /// Votes for an option of an open poll in the client's topic, by its index counted
+    /// from 0. Voting again replaces the earlier vote. Answered with `Voted`.
+    Vote { poll_id: Uuid, option: usize },
+    /// Asks which topics have subscribers. Answered with `TopicList`.
+    ListTopics,
+    /// Announces a file of `size` bytes for `topic`, which the client must be subscribed
+    /// to. Its content follows in binary frames, each the attachment ID, the chunk's
+    /// index counted from 0 as a big-endian u32 and the chunk's bytes.
+    AttachmentStart {
+        id: Uuid,
+        topic: String,
+        name: String,
+        content_type: String,
+        size: u64,
+    },
+    /// Ends an attachment once all its chunks were sent. Answered with `Published`.
+    AttachmentComplete { id: Uuid },
+    /// Announces the client's public key for end-to-end encryption to the other
+    /// subscribers of `topic`, which the client must be subscribed to.
+    PublicKey { topic: String, key: String },
+    /// Asks the server to answer with a `Pong` carrying the same `nonce`, to measure the
+    /// round trip.
+    Ping { nonce: u64 },
+    /// Presents a token as the first message, for clients that cannot set headers or
+    /// query parameters on the upgrade. The server closes the connection if the token is
+    /// not accepted.
+    Authenticate { token: String },
+    /// Disconnects another client, as `/kick` does on the server console. Only clients
+    /// with the admin role may send it.
+    Kick {
+        client_id: Uuid,
+        reason: Option<String>,
+    },
+    /// Bans another client by its ID, as `/ban` does on the server console. Only clients
+    /// with the admin role may send it.
+    Ban { client_id: Uuid },
+    /// Asks the other subscribers of `topic`, which the client must be subscribed to, to
+    /// respond under `correlation_id`. They get it as `Request`; the first `Respond` is
+    /// sent back as `Response`, or an `Error` if none comes in time.
+    Request {
+        topic: String,
+        correlation_id: Uuid,
+        content: String,
+    },
+    /// Answers a `Request` of the client's topic by its correlation ID.
+    Respond {
+        correlation_id: Uuid,
+        content: String,
+    },
+    /// Asks the server to send large frames as `Compressed` frames from now on; it
+    /// answers with `Negotiated`.
+    Negotiate { compress: bool },
+    /// Another frame, deflated and in base64, for servers that agreed to compression.
+    Compressed { data: String },
+    /// A message type introduced by a newer client.
+    #[serde(other)]
+    Unknown,
 }
 
 /// Messages sent from the server to the client.
 #[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(tag = "type")]
+#[serde(tag = "type", content = "payload")]
 pub enum ServerMessage {
     /// A global message from Morpheus to all clients.
     Global { id: Uuid, content: String },
@@ -29,16 +338,312 @@ pub enum ServerMessage {
     Topic {
         id: Uuid,
         topic: String,
+        /// The message's position in its topic, counted per topic from 1 by the server
+        /// that delivers it. A client that sees a number skipped can ask for the missing
+        /// messages with `RequestMissed`; its own messages are not sent back to it, so
+        /// their numbers are skipped too.
+        #[serde(default)]
+        seq: u64,
         /// The sender of the message.
         sender: String,
         content: String,
+        /// The custom headers the sender attached.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        headers: BTreeMap<String, String>,
+        /// The delivery guarantee the sender asked for. Above `FireAndForget` the message
+        /// is sent again until it is acknowledged.
+        #[serde(default, skip_serializing_if = "Qos::is_fire_and_forget")]
+        qos: Qos,
+        /// The ID of the topic message this one replies to, if it is a reply.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reply_to: Option<Uuid>,
+        /// Whether this is the topic's retained message, sent on subscribing rather than
+        /// as it was published. It carries sequence number 0.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        retained: bool,
     },
     /// A private message from Morpheus.
     Private { id: Uuid, content: String },
     /// Confirmation that a private message was delivered.
     MessageDelivered { msg_id: Uuid },
-    /// Acknowledgment that a message was received by a client.
+    /// A read receipt, sent to the client that published `msg_id` when `client_id`
+    /// acknowledged it.
     MessageAcknowledged { msg_id: Uuid, client_id: Uuid },
     /// An error message from the server.
     Error { message: String },
+    /// The client was moved to another topic by the operator.
+    TopicMoved { from: String, to: String },
+    /// The message of the day, sent after subscribing when the server has one.
+    Motd { content: String },
+    /// The rules of a topic, which the client must accept with `AcceptTerms` before it is
+    /// subscribed.
+    TopicInfo {
+        topic: String,
+        rules: String,
+        version: u32,
+    },
+    /// The server refused the connection or subscription because it is full.
+    LimitExceeded { limit: Limit, max: usize },
+    /// The operator removed the client; the server closes the connection after this.
+    Kicked { reason: Option<String> },
+    /// Reply to `ConnectSession` carrying the client's identity and session.
+    Welcome {
+        client_id: Uuid,
+        session_id: Uuid,
+        /// Whether a previous session was resumed.
+        resumed: bool,
+        /// How long a client should spread its reconnect over, waiting a random part of
+        /// it, when the server goes away, so that clients do not all return at once.
+        #[serde(default)]
+        reconnect_jitter_ms: u64,
+    },
+    /// A batch of topic history for a consumer group, oldest first. Empty when the group
+    /// has caught up.
+    Fetched {
+        topic: String,
+        group: String,
+        messages: Vec<FetchedMessage>,
+    },
+    /// Confirmation of `CommitOffset`, carrying the group's committed offset.
+    OffsetCommitted {
+        topic: String,
+        group: String,
+        offset: u64,
+    },
+    /// Confirms that a message sent with a QoS above `FireAndForget` was accepted and
+    /// broadcast. A retry of an exactly-once message is confirmed again but not broadcast.
+    Published { id: Uuid },
+    /// A poll was opened in the topic; vote on it with `Vote`.
+    Poll {
+        id: Uuid,
+        topic: String,
+        question: String,
+        options: Vec<String>,
+        /// The client ID of the subscriber who asked, or "Morpheus".
+        creator: String,
+        closes_in_secs: u64,
+    },
+    /// A poll closed; `votes` holds the number of votes for each option, in order.
+    PollResults {
+        id: Uuid,
+        topic: String,
+        question: String,
+        options: Vec<String>,
+        votes: Vec<usize>,
+    },
+    /// Confirms a `Vote`.
+    Voted { poll_id: Uuid, option: usize },
+    /// The topics that have subscribers on the server, answering `ListTopics`.
+    TopicList { topics: Vec<TopicSummary> },
+    /// A subscriber started sending a file to the topic. Its chunks follow as binary
+    /// frames, relayed as the sender sent them, until `AttachmentComplete` or
+    /// `AttachmentAborted`.
+    AttachmentStart {
+        id: Uuid,
+        topic: String,
+        /// The client ID of the subscriber sending the file.
+        sender: String,
+        name: String,
+        content_type: String,
+        size: u64,
+    },
+    /// Every chunk of the attachment was relayed.
+    AttachmentComplete { id: Uuid },
+    /// The attachment will not be completed, e.g. because its sender disconnected or
+    /// went over the size limit; drop the chunks received so far.
+    AttachmentAborted { id: Uuid, reason: String },
+    /// A subscriber announced its public key for end-to-end encryption. The server relays
+    /// the key, like encrypted content, without looking at it.
+    PublicKey {
+        topic: String,
+        /// The client ID of the subscriber the key belongs to.
+        sender: String,
+        key: String,
+    },
+    /// Answers a `Ping` with its `nonce`.
+    Pong { nonce: u64 },
+    /// A subscriber asks the topic for a response; answer it with `Respond` and the same
+    /// `correlation_id`.
+    Request {
+        topic: String,
+        correlation_id: Uuid,
+        /// The client ID of the subscriber asking.
+        sender: String,
+        content: String,
+    },
+    /// The response to a `Request` the client sent.
+    Response {
+        correlation_id: Uuid,
+        content: String,
+    },
+    /// Answers `Negotiate`: whether frames of the server's minimum size and up come as
+    /// `Compressed` frames, and whether the client may send them.
+    Negotiated { compress: bool },
+    /// Another frame, deflated and in base64.
+    Compressed { data: String },
+    /// A chunk of an attachment, relayed to subscribers as the binary frame its sender
+    /// sent. Never encoded as JSON.
+    #[serde(skip)]
+    AttachmentChunk { frame: Arc<[u8]> },
+    /// A message type introduced by a newer server.
+    #[serde(other)]
+    Unknown,
+}
+
+/// A topic message handed to a consumer group.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FetchedMessage {
+    /// The message's sequence number in its topic, to commit once it is processed.
+    pub seq: u64,
+    pub id: Uuid,
+    pub sender: String,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// A topic and how many clients are subscribed to it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TopicSummary {
+    pub name: String,
+    pub clients: usize,
+}
+
+/// How hard the server tries to get a topic message to every subscriber.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Qos {
+    /// Sent once; lost if the subscriber's connection drops before it arrives.
+    #[default]
+    FireAndForget,
+    /// Confirmed to the sender and sent to subscribers again until they acknowledge it,
+    /// so it may arrive more than once.
+    AtLeastOnce,
+    /// Like `AtLeastOnce`, but a sender's retry is not broadcast again and subscribers
+    /// drop redeliveries they already saw by the message ID.
+    ExactlyOnce,
+}
+
+impl Qos {
+    pub fn is_fire_and_forget(&self) -> bool {
+        *self == Qos::FireAndForget
+    }
+}
+
+/// A capacity limit the server enforces.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Limit {
+    /// Concurrent connections to the server.
+    Connections,
+    /// Clients subscribed to a single topic.
+    TopicClients,
+}
+
+/// Why the server closed a connection, sent as the code of the WebSocket close frame so
+/// clients can decide whether and how soon to reconnect. The codes are taken from the
+/// 4000-4999 range that RFC 6455 leaves to applications.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseCode {
+    /// The connection presented a missing or invalid token. Reconnecting will not help.
+    AuthFailed = 4001,
+    /// The client sent a frame that is not part of the protocol, such as a binary frame
+    /// that is not an attachment chunk.
+    ProtocolError = 4002,
+    /// The client or its address is banned. Reconnecting will not help.
+    Banned = 4003,
+    /// An operator disconnected the client. Reconnecting would just bring it back.
+    Kicked = 4004,
+    /// The server is shutting down. Reconnecting may reach it again or another node.
+    Draining = 4005,
+    /// The client stopped answering the server's pings.
+    Idle = 4008,
+    /// The server is full or the client could not keep up with its messages. Clients
+    /// should wait longer than usual before reconnecting.
+    Overloaded = 4013,
+}
+
+impl CloseCode {
+    /// The close code as sent on the wire.
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+
+    /// The reason sent along with the close code.
+    pub fn reason(self) -> &'static str {
+        match self {
+            CloseCode::AuthFailed => "authentication failed",
+            CloseCode::ProtocolError => "protocol error",
+            CloseCode::Banned => "banned",
+            CloseCode::Kicked => "kicked",
+            CloseCode::Draining => "server shutting down",
+            CloseCode::Idle => "idle",
+            CloseCode::Overloaded => "overloaded",
+        }
+    }
+}
+
+impl ServerMessage {
+    /// Returns the ID of messages that clients are expected to acknowledge.
+    pub fn id(&self) -> Option<Uuid> {
+        match self {
+            ServerMessage::Global { id, .. }
+            | ServerMessage::Topic { id, .. }
+            | ServerMessage::Private { id, .. } => Some(*id),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_wraps_message_in_envelope() {
+        let id = Uuid::nil();
+        let text = encode(&ServerMessage::Private {
+            id,
+            content: "hi".to_string(),
+        })
+        .unwrap();
+        let value: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "v": PROTOCOL_VERSION,
+                "type": "Private",
+                "payload": { "id": id, "content": "hi" }
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_frames_from_newer_clients() {
+        // A future message type is reported as unknown instead of failing.
+        let unknown = r#"{"v":2,"type":"Typing","payload":{"topic":"general"}}"#;
+        assert!(matches!(
+            decode::<ClientMessage>(unknown).unwrap(),
+            ClientMessage::Unknown
+        ));
+
+        // New payload fields and metadata are ignored.
+        let extended = r#"{"v":2,"type":"Connect","payload":{"topic":"general","priority":1},
+            "meta":{"trace":"abc"}}"#;
+        assert!(matches!(
+            decode::<ClientMessage>(extended).unwrap(),
+            ClientMessage::Connect { topic } if topic == "general"
+        ));
+    }
+
+    #[test]
+    fn test_decode_frame_without_version() {
+        let frame = r#"{"type":"Connect","payload":{"topic":"general"}}"#;
+        let envelope: Envelope<ClientMessage> = serde_json::from_str(frame).unwrap();
+        assert_eq!(envelope.v, 1);
+        assert!(matches!(envelope.message, ClientMessage::Connect { .. }));
+        assert_eq!(version(frame), Some(1));
+        assert_eq!(version(r#"{"v":2,"type":"Typing"}"#), Some(2));
+        assert_eq!(version("not json"), None);
+    }
 }
@@ -0,0 +1,408 @@
+use crate::{
+    core::{
+        jwt::Jwt,
+        msg::{Limit, ServerMessage},
+    },
+    proxy::Cidr,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, time::Instant};
+
+/// The burst size used when a rate limit does not set one.
+pub const DEFAULT_BURST: u32 = 10;
+
+/// How many messages a single connection may publish.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// The sustained number of messages allowed per second.
+    pub messages_per_second: f64,
+    /// How many messages may be sent in a burst above the sustained rate.
+    #[serde(default = "default_burst")]
+    pub burst: u32,
+}
+
+fn default_burst() -> u32 {
+    DEFAULT_BURST
+}
+
+/// The largest text frame a client may send unless configured otherwise.
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 64 * 1024;
+/// The largest attachment a client may send unless configured otherwise.
+pub const DEFAULT_MAX_ATTACHMENT_BYTES: usize = 4 * 1024 * 1024;
+
+/// Caps on how many clients the server accepts, unlimited when absent, and on how
+/// large their messages and attachments may be.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_connections: Option<usize>,
+    pub max_clients_per_topic: Option<usize>,
+    pub max_message_bytes: usize,
+    /// The largest file a client may send in chunks; 0 disables attachments.
+    pub max_attachment_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            max_clients_per_topic: None,
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            max_attachment_bytes: DEFAULT_MAX_ATTACHMENT_BYTES,
+        }
+    }
+}
+
+impl Limits {
+    /// The largest frame the WebSocket layer reads at all. Oversized messages up to this
+    /// size are refused with an error; anything larger closes the connection without
+    /// being buffered.
+    pub fn frame_ceiling(&self) -> usize {
+        self.max_message_bytes.saturating_mul(4)
+    }
+}
+
+/// A client was refused because a `Limits` cap was reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub limit: Limit,
+    pub max: usize,
+}
+
+impl From<LimitExceeded> for ServerMessage {
+    fn from(exceeded: LimitExceeded) -> Self {
+        ServerMessage::LimitExceeded {
+            limit: exceeded.limit,
+            max: exceeded.max,
+        }
+    }
+}
+
+/// Settings that apply to a single topic.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TopicPolicy {
+    /// Only the operator and clients with the admin role may publish; messages from other
+    /// clients are rejected.
+    pub read_only: bool,
+    /// How many messages of this topic are kept in history, overriding the default.
+    pub retention: Option<usize>,
+    /// Rules clients must accept before they are subscribed.
+    pub rules: Option<String>,
+    /// Bump to make clients accept changed rules again.
+    pub rules_version: u32,
+    /// Whether messages go to every subscriber or to just one, as in a work queue.
+    pub delivery: Delivery,
+}
+
+/// How a topic's messages are distributed among its subscribers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Delivery {
+    /// Every subscriber gets every message.
+    #[default]
+    Broadcast,
+    /// Each message goes to one subscriber, taking turns.
+    RoundRobin,
+    /// Each message goes to one subscriber chosen at random.
+    Random,
+    /// Each message goes to the subscriber with the fewest unacknowledged messages.
+    LeastLoaded,
+}
+
+/// What a connection is for, assigned to a token in `[auth.roles]` or by a JSON Web
+/// Token's `role` claim. Connections without a role may publish and subscribe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// May only publish.
+    Publisher,
+    /// May only subscribe.
+    Subscriber,
+    /// May do anything, including publishing to read-only topics and sending the
+    /// management messages `Kick` and `Ban`.
+    Admin,
+}
+
+impl Role {
+    pub fn may_publish(self) -> bool {
+        self != Role::Subscriber
+    }
+
+    pub fn may_subscribe(self) -> bool {
+        self != Role::Publisher
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Publisher => "publisher",
+            Role::Subscriber => "subscriber",
+            Role::Admin => "admin",
+        })
+    }
+}
+
+/// What a client may do, as granted by the token it connected with. A JSON Web Token's
+/// claims are read as these fields: `topics` lists the topics it may use, every topic when
+/// absent, and a trailing `*` matches any topic starting with what precedes it.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Permissions {
+    pub topics: Option<Vec<String>>,
+    pub publish: bool,
+    pub subscribe: bool,
+    /// Whether the `/admin` and `/whois` endpoints may be used.
+    pub admin: bool,
+    /// Narrows `publish` and `subscribe` further; the admin role implies `admin`.
+    pub role: Option<Role>,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self {
+            topics: None,
+            publish: true,
+            subscribe: true,
+            admin: false,
+            role: None,
+        }
+    }
+}
+
+impl From<Role> for Permissions {
+    fn from(role: Role) -> Self {
+        Self {
+            admin: role == Role::Admin,
+            role: Some(role),
+            ..Self::default()
+        }
+    }
+}
+
+impl Permissions {
+    /// The permissions of a static token, or of everyone when authentication is disabled.
+    pub fn all() -> Self {
+        Self {
+            admin: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn may_publish(&self, topic: &str) -> bool {
+        self.publish && self.role.is_none_or(Role::may_publish) && self.covers(topic)
+    }
+
+    pub fn may_subscribe(&self, topic: &str) -> bool {
+        self.subscribe && self.role.is_none_or(Role::may_subscribe) && self.covers(topic)
+    }
+
+    /// Whether the admin endpoints may be used.
+    pub fn is_admin(&self) -> bool {
+        self.admin || self.role == Some(Role::Admin)
+    }
+
+    /// Whether the connection has the admin role, which lets it manage other clients.
+    pub fn has_admin_role(&self) -> bool {
+        self.role == Some(Role::Admin)
+    }
+
+    fn covers(&self, topic: &str) -> bool {
+        self.topics.as_ref().is_none_or(|topics| {
+            topics
+                .iter()
+                .any(|allowed| match allowed.strip_suffix('*') {
+                    Some(prefix) => topic.starts_with(prefix),
+                    None => allowed == topic,
+                })
+        })
+    }
+}
+
+/// The access rules the server enforces on client connections.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Policies {
+    /// Tokens accepted on the WebSocket upgrade, granting every permission; authentication
+    /// is disabled when there are none, no `auth_roles` and no `jwt`.
+    pub auth_tokens: Vec<String>,
+    /// Tokens accepted on the WebSocket upgrade, granting the permissions of their role.
+    pub auth_roles: HashMap<String, Role>,
+    /// Verifies JSON Web Tokens, whose claims grant the permissions.
+    pub jwt: Option<Jwt>,
+    pub rate_limit: Option<RateLimit>,
+    pub limits: Limits,
+    pub topics: HashMap<String, TopicPolicy>,
+    /// Proxies whose `X-Forwarded-For` is believed, so limits and bans apply to the
+    /// clients behind them.
+    pub trusted_proxies: Vec<Cidr>,
+}
+
+impl Policies {
+    /// Whether connections must present a token.
+    pub fn requires_auth(&self) -> bool {
+        !self.auth_tokens.is_empty() || !self.auth_roles.is_empty() || self.jwt.is_some()
+    }
+
+    /// What a connection presenting `token` may do, or why it may not connect.
+    pub fn permissions(&self, token: Option<&str>) -> Result<Permissions, String> {
+        if !self.requires_auth() {
+            return Ok(Permissions::all());
+        }
+        let token = token.ok_or_else(|| "No token was presented".to_string())?;
+        if let Some(role) = self.auth_roles.get(token) {
+            return Ok((*role).into());
+        }
+        if self.auth_tokens.iter().any(|t| t == token) {
+            return Ok(Permissions::all());
+        }
+        match &self.jwt {
+            Some(jwt) => jwt.verify(token),
+            None => Err("The token is not accepted".to_string()),
+        }
+    }
+
+    /// Whether a connection presenting `token` may connect.
+    pub fn authorizes(&self, token: Option<&str>) -> bool {
+        self.permissions(token).is_ok()
+    }
+
+    /// Whether a request presenting `token` may use the admin endpoints.
+    pub fn authorizes_admin(&self, token: Option<&str>) -> bool {
+        self.permissions(token)
+            .is_ok_and(|permissions| permissions.is_admin())
+    }
+
+    /// The rules of `topic` and their version, if it has any.
+    pub fn rules(&self, topic: &str) -> Option<(&str, u32)> {
+        let policy = self.topics.get(topic)?;
+        Some((policy.rules.as_deref()?, policy.rules_version))
+    }
+
+    pub fn delivery(&self, topic: &str) -> Delivery {
+        self.topics
+            .get(topic)
+            .map_or(Delivery::Broadcast, |policy| policy.delivery)
+    }
+
+    pub fn is_read_only(&self, topic: &str) -> bool {
+        self.topics
+            .get(topic)
+            .is_some_and(|policy| policy.read_only)
+    }
+}
+
+/// A token bucket enforcing a `RateLimit` on one connection.
+#[derive(Debug)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: f64::from(limit.burst.max(1)),
+            refilled_at: Instant::now(),
+        }
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Takes a token if one is available at `now`.
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.messages_per_second)
+            .min(f64::from(self.limit.burst.max(1)));
+        self.refilled_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_token_bucket_allows_burst_then_refills() {
+        let mut bucket = TokenBucket::new(RateLimit {
+            messages_per_second: 2.0,
+            burst: 3,
+        });
+        let start = Instant::now();
+        assert!((0..3).all(|_| bucket.try_acquire(start)));
+        assert!(!bucket.try_acquire(start));
+        assert!(bucket.try_acquire(start + Duration::from_millis(500)));
+        assert!(!bucket.try_acquire(start + Duration::from_millis(600)));
+    }
+
+    #[test]
+    fn test_policies() {
+        let policies = Policies {
+            auth_tokens: vec!["secret".to_string()],
+            topics: HashMap::from([(
+                "news".to_string(),
+                TopicPolicy {
+                    read_only: true,
+                    ..TopicPolicy::default()
+                },
+            )]),
+            ..Policies::default()
+        };
+        assert!(policies.authorizes(Some("secret")));
+        assert!(!policies.authorizes(Some("guess")));
+        assert!(!policies.authorizes(None));
+        assert!(Policies::default().authorizes(None));
+        assert!(policies.authorizes_admin(Some("secret")));
+        assert!(Policies::default().authorizes_admin(None));
+        assert!(policies.is_read_only("news"));
+        assert!(!policies.is_read_only("general"));
+    }
+
+    #[test]
+    fn test_permissions_cover_listed_topics() {
+        let permissions = Permissions {
+            topics: Some(vec!["ops".to_string(), "team-*".to_string()]),
+            publish: false,
+            ..Permissions::default()
+        };
+        assert!(permissions.may_subscribe("ops"));
+        assert!(permissions.may_subscribe("team-blue"));
+        assert!(!permissions.may_subscribe("general"));
+        assert!(!permissions.may_publish("ops"));
+        assert!(Permissions::all().may_publish("general"));
+    }
+
+    #[test]
+    fn test_roles_narrow_permissions() {
+        let policies = Policies {
+            auth_roles: HashMap::from([
+                ("pub".to_string(), Role::Publisher),
+                ("sub".to_string(), Role::Subscriber),
+                ("ops".to_string(), Role::Admin),
+            ]),
+            ..Policies::default()
+        };
+        let publisher = policies.permissions(Some("pub")).unwrap();
+        assert!(publisher.may_publish("general") && !publisher.may_subscribe("general"));
+        let subscriber = policies.permissions(Some("sub")).unwrap();
+        assert!(!subscriber.may_publish("general") && subscriber.may_subscribe("general"));
+        assert!(policies.permissions(Some("ops")).unwrap().has_admin_role());
+        assert!(policies.authorizes_admin(Some("ops")));
+        assert!(!policies.authorizes_admin(Some("pub")));
+        assert!(!policies.authorizes(None));
+    }
+}
@@ -0,0 +1,256 @@
+use crate::{
+    cli::ui,
+    core::{client_manager::ClientManager, msg::ServerMessage},
+};
+use dashmap::DashMap;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// How long a poll stays open when its creator does not say.
+pub const DEFAULT_POLL_DURATION: Duration = Duration::from_secs(300);
+/// The longest a poll may stay open.
+pub const MAX_POLL_DURATION: Duration = Duration::from_secs(24 * 3600);
+/// How many options a poll may have.
+pub const MAX_POLL_OPTIONS: usize = 10;
+/// How often polls are checked for their deadline.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A question put to a topic's subscribers, open for votes until its deadline.
+#[derive(Clone, Debug)]
+pub struct Poll {
+    pub id: Uuid,
+    pub topic: String,
+    pub question: String,
+    pub options: Vec<String>,
+    /// Who asked: a client ID, or the server for operator polls.
+    pub creator: String,
+    pub duration: Duration,
+    pub closes_at: Instant,
+    /// The option index each voter chose; voting again replaces the earlier vote.
+    votes: HashMap<Uuid, usize>,
+}
+
+impl Poll {
+    pub fn new(
+        topic: String,
+        question: String,
+        options: Vec<String>,
+        creator: String,
+        duration: Duration,
+    ) -> Result<Self, String> {
+        if question.trim().is_empty() {
+            return Err("A poll needs a question".to_string());
+        }
+        if !(2..=MAX_POLL_OPTIONS).contains(&options.len()) {
+            return Err(format!(
+                "A poll needs between 2 and {} options",
+                MAX_POLL_OPTIONS
+            ));
+        }
+        if duration.is_zero() || duration > MAX_POLL_DURATION {
+            return Err(format!(
+                "A poll must close within {} hours",
+                MAX_POLL_DURATION.as_secs() / 3600
+            ));
+        }
+        Ok(Self {
+            id: Uuid::new_v4(),
+            topic,
+            question,
+            options,
+            creator,
+            duration,
+            closes_at: Instant::now() + duration,
+            votes: HashMap::new(),
+        })
+    }
+
+    /// The number of votes for each option, in option order.
+    pub fn tally(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.options.len()];
+        for option in self.votes.values() {
+            counts[*option] += 1;
+        }
+        counts
+    }
+
+    /// The message that opens the poll in its topic.
+    pub fn announcement(&self) -> ServerMessage {
+        ServerMessage::Poll {
+            id: self.id,
+            topic: self.topic.clone(),
+            question: self.question.clone(),
+            options: self.options.clone(),
+            creator: self.creator.clone(),
+            closes_in_secs: self.duration.as_secs(),
+        }
+    }
+
+    /// The message that closes the poll with its final tally.
+    pub fn results(&self) -> ServerMessage {
+        ServerMessage::PollResults {
+            id: self.id,
+            topic: self.topic.clone(),
+            question: self.question.clone(),
+            options: self.options.clone(),
+            votes: self.tally(),
+        }
+    }
+}
+
+/// The polls that are still open, keyed by ID.
+#[derive(Debug, Default)]
+pub struct Polls {
+    polls: DashMap<Uuid, Poll>,
+}
+
+impl Polls {
+    pub fn open(&self, poll: Poll) {
+        self.polls.insert(poll.id, poll);
+    }
+
+    /// Records a vote by a subscriber of `topic`, the topic the voter is in.
+    pub fn vote(
+        &self,
+        poll_id: &Uuid,
+        voter: Uuid,
+        topic: Option<&str>,
+        option: usize,
+    ) -> Result<(), String> {
+        let mut poll = self
+            .polls
+            .get_mut(poll_id)
+            .ok_or_else(|| format!("Poll {} is not open", poll_id))?;
+        if topic != Some(poll.topic.as_str()) {
+            return Err(format!(
+                "Poll {} belongs to topic '{}', which you are not in",
+                poll_id, poll.topic
+            ));
+        }
+        if option >= poll.options.len() {
+            return Err(format!("Poll {} has no option {}", poll_id, option + 1));
+        }
+        poll.votes.insert(voter, option);
+        Ok(())
+    }
+
+    /// Removes and returns the polls whose deadline has passed.
+    pub fn take_expired(&self) -> Vec<Poll> {
+        let now = Instant::now();
+        let expired: Vec<Uuid> = self
+            .polls
+            .iter()
+            .filter(|poll| poll.closes_at <= now)
+            .map(|poll| poll.id)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|id| self.polls.remove(&id))
+            .map(|(_, poll)| poll)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.polls.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Closes polls once their deadline passes, announcing the results in their topics.
+pub fn spawn_closer(client_manager: Arc<ClientManager>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            tick.tick().await;
+            for poll in client_manager.close_expired_polls().await {
+                ui::print_system_message(&format!(
+                    "Poll {} in topic '{}' closed with {} vote(s).",
+                    poll.id,
+                    poll.topic,
+                    poll.tally().iter().sum::<usize>()
+                ));
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poll(duration: Duration) -> Poll {
+        Poll::new(
+            "general".to_string(),
+            "Lunch?".to_string(),
+            vec!["Pizza".to_string(), "Sushi".to_string()],
+            "Morpheus".to_string(),
+            duration,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_new_poll_is_validated() {
+        let options = |n: usize| (0..n).map(|n| n.to_string()).collect::<Vec<_>>();
+        let new = |question: &str, options, duration| {
+            Poll::new(
+                "t".to_string(),
+                question.to_string(),
+                options,
+                "c".to_string(),
+                duration,
+            )
+        };
+        assert!(new("Q?", options(2), DEFAULT_POLL_DURATION).is_ok());
+        assert!(new(" ", options(2), DEFAULT_POLL_DURATION).is_err());
+        assert!(new("Q?", options(1), DEFAULT_POLL_DURATION).is_err());
+        assert!(new("Q?", options(MAX_POLL_OPTIONS + 1), DEFAULT_POLL_DURATION).is_err());
+        assert!(new("Q?", options(2), Duration::ZERO).is_err());
+        assert!(new("Q?", options(2), MAX_POLL_DURATION * 2).is_err());
+    }
+
+    #[test]
+    fn test_votes_are_checked_and_replaced() {
+        let polls = Polls::default();
+        let poll = poll(DEFAULT_POLL_DURATION);
+        let id = poll.id;
+        polls.open(poll);
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(polls.vote(&id, alice, Some("general"), 0).is_ok());
+        assert!(polls.vote(&id, bob, Some("general"), 0).is_ok());
+        assert!(polls.vote(&id, bob, Some("general"), 1).is_ok());
+        assert!(polls.vote(&id, bob, Some("general"), 2).is_err());
+        assert!(polls.vote(&id, bob, Some("random"), 0).is_err());
+        assert!(polls.vote(&id, bob, None, 0).is_err());
+        assert!(polls
+            .vote(&Uuid::new_v4(), bob, Some("general"), 0)
+            .is_err());
+
+        assert!(polls.take_expired().is_empty());
+        assert_eq!(polls.polls.get(&id).unwrap().tally(), vec![1, 1]);
+    }
+
+    #[test]
+    fn test_take_expired_closes_polls_past_their_deadline() {
+        let polls = Polls::default();
+        let mut expired = poll(DEFAULT_POLL_DURATION);
+        expired.closes_at = Instant::now() - Duration::from_secs(1);
+        let expired_id = expired.id;
+        polls.open(expired);
+        polls.open(poll(DEFAULT_POLL_DURATION));
+
+        let closed = polls.take_expired();
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].id, expired_id);
+        assert_eq!(polls.len(), 1);
+    }
+}
@@ -0,0 +1,106 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use uuid::Uuid;
+
+/// Where a message entered the server.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Origin {
+    /// Published by a connected client.
+    Client(Uuid),
+    /// Sent by the operator from the server CLI.
+    Operator,
+    /// Forwarded from another topic or server by the named bridge.
+    Bridge(String),
+    /// Submitted through the REST API.
+    Rest,
+    /// Delivered by the scheduler.
+    Scheduler,
+    /// Published by a simulated client started with `/simulate`.
+    Simulated(Uuid),
+    /// Raised by the named alerting rule.
+    Alert(String),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Client(id) => write!(f, "client {}", id),
+            Origin::Operator => write!(f, "operator"),
+            Origin::Bridge(name) => write!(f, "bridge '{}'", name),
+            Origin::Rest => write!(f, "REST API"),
+            Origin::Scheduler => write!(f, "scheduler"),
+            Origin::Simulated(id) => write!(f, "simulated client {}", id),
+            Origin::Alert(rule) => write!(f, "alert rule '{}'", rule),
+        }
+    }
+}
+
+/// A single step in a message's provenance chain.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ProvenanceStep {
+    /// The message was created.
+    Origin(Origin),
+    /// A stage (e.g. a filter) rewrote or annotated the message.
+    Transform { stage: String, description: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProvenanceEntry {
+    pub step: ProvenanceStep,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// The chain of steps a message went through before it was delivered.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    entries: Vec<ProvenanceEntry>,
+}
+
+impl Provenance {
+    /// Starts a new chain at the given origin.
+    pub fn new(origin: Origin) -> Self {
+        let mut provenance = Self::default();
+        provenance.push(ProvenanceStep::Origin(origin));
+        provenance
+    }
+
+    /// Records that `stage` transformed the message.
+    pub fn record_transform(&mut self, stage: &str, description: &str) {
+        self.push(ProvenanceStep::Transform {
+            stage: stage.to_string(),
+            description: description.to_string(),
+        });
+    }
+
+    /// Returns the origin the chain started from.
+    pub fn origin(&self) -> Option<&Origin> {
+        self.entries.iter().find_map(|entry| match &entry.step {
+            ProvenanceStep::Origin(origin) => Some(origin),
+            _ => None,
+        })
+    }
+
+    pub fn entries(&self) -> &[ProvenanceEntry] {
+        &self.entries
+    }
+
+    fn push(&mut self, step: ProvenanceStep) {
+        self.entries.push(ProvenanceEntry {
+            step,
+            timestamp: Utc::now(),
+        });
+    }
+}
+
+impl fmt::Display for ProvenanceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = self.timestamp.format("%Y-%m-%d %H:%M:%S%.3f");
+        match &self.step {
+            ProvenanceStep::Origin(origin) => write!(f, "{} origin: {}", time, origin),
+            ProvenanceStep::Transform { stage, description } => {
+                write!(f, "{} transform by {}: {}", time, stage, description)
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{
+    cli::ui,
+    core::{client_manager::ClientManager, msg::Outgoing},
+};
+use dashmap::DashMap;
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// How long a subscriber has to acknowledge a QoS message before it is sent again.
+pub const QOS_REDELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many exactly-once message IDs are remembered to recognize a sender's retries.
+const DEDUP_CAPACITY: usize = 10_000;
+/// How often unacknowledged QoS messages are checked.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A QoS message sent to one subscriber and waiting for its acknowledgment.
+#[derive(Clone, Debug)]
+pub(crate) struct Unacked {
+    pub message: Arc<Outgoing>,
+    /// How many times the message was sent to the subscriber.
+    pub attempts: usize,
+    pub sent_at: Instant,
+}
+
+/// The IDs of the most recent exactly-once messages, oldest first.
+#[derive(Debug, Default)]
+struct Seen {
+    ids: HashSet<Uuid>,
+    order: VecDeque<Uuid>,
+}
+
+/// Tracks topic messages sent with a QoS above fire-and-forget: which subscribers still
+/// owe an acknowledgment, and which exactly-once messages were already broadcast.
+#[derive(Debug, Default)]
+pub(crate) struct QosTracker {
+    /// Keyed by subscriber and message ID.
+    unacked: DashMap<(Uuid, Uuid), Unacked>,
+    seen: Mutex<Seen>,
+}
+
+impl QosTracker {
+    pub fn track(&self, client_id: Uuid, msg_id: Uuid, unacked: Unacked) {
+        self.unacked.insert((client_id, msg_id), unacked);
+    }
+
+    pub fn acknowledge(&self, client_id: Uuid, msg_id: Uuid) {
+        self.unacked.remove(&(client_id, msg_id));
+    }
+
+    /// Stops tracking everything sent to a client that disconnected. A resumed session
+    /// gets its unacknowledged messages from its pending acks instead.
+    pub fn forget_client(&self, client_id: &Uuid) {
+        self.unacked.retain(|(client, _), _| client != client_id);
+    }
+
+    /// Takes the messages that were not acknowledged within `timeout`, with their
+    /// subscriber and ID.
+    pub fn take_expired(&self, timeout: Duration) -> Vec<(Uuid, Uuid, Unacked)> {
+        let expired: Vec<(Uuid, Uuid)> = self
+            .unacked
+            .iter()
+            .filter(|entry| entry.sent_at.elapsed() >= timeout)
+            .map(|entry| *entry.key())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|key| self.unacked.remove(&key))
+            .map(|((client_id, msg_id), unacked)| (client_id, msg_id, unacked))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.unacked.len()
+    }
+
+    /// Whether an exactly-once message with this ID was already broadcast.
+    pub fn is_duplicate(&self, msg_id: &Uuid) -> bool {
+        self.seen.lock().unwrap().ids.contains(msg_id)
+    }
+
+    /// Remembers that an exactly-once message was broadcast, forgetting the oldest one
+    /// once `DEDUP_CAPACITY` are remembered.
+    pub fn remember(&self, msg_id: Uuid) {
+        let mut seen = self.seen.lock().unwrap();
+        if !seen.ids.insert(msg_id) {
+            return;
+        }
+        seen.order.push_back(msg_id);
+        if seen.order.len() > DEDUP_CAPACITY {
+            if let Some(oldest) = seen.order.pop_front() {
+                seen.ids.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Sends unacknowledged QoS messages to their subscribers again every second.
+pub fn spawn_redelivery(client_manager: Arc<ClientManager>, timeout: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            tick.tick().await;
+            let dropped = client_manager.redeliver_unacked(timeout);
+            if dropped > 0 {
+                ui::print_warning(&format!(
+                    "Gave up on {} QoS message(s) that a subscriber never acknowledged.",
+                    dropped
+                ));
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::msg::ServerMessage;
+
+    fn unacked(sent_at: Instant) -> Unacked {
+        Unacked {
+            message: Arc::new(Outgoing::new(ServerMessage::Error {
+                message: "test".to_string(),
+            })),
+            attempts: 1,
+            sent_at,
+        }
+    }
+
+    #[test]
+    fn test_take_expired_skips_acknowledged_and_recent() {
+        let tracker = QosTracker::default();
+        let (client, old, acked, recent) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        let long_ago = Instant::now() - Duration::from_secs(60);
+        tracker.track(client, old, unacked(long_ago));
+        tracker.track(client, acked, unacked(long_ago));
+        tracker.track(client, recent, unacked(Instant::now()));
+        tracker.acknowledge(client, acked);
+
+        let expired = tracker.take_expired(Duration::from_secs(10));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].1, old);
+        assert_eq!(tracker.len(), 1);
+
+        tracker.forget_client(&client);
+        assert_eq!(tracker.len(), 0);
+    }
+
+    #[test]
+    fn test_dedup_window_forgets_oldest() {
+        let tracker = QosTracker::default();
+        let first = Uuid::new_v4();
+        tracker.remember(first);
+        assert!(tracker.is_duplicate(&first));
+        for _ in 0..DEDUP_CAPACITY {
+            tracker.remember(Uuid::new_v4());
+        }
+        assert!(!tracker.is_duplicate(&first));
+    }
+}
@@ -0,0 +1,724 @@
+use crate::core::{
+    alerts::parse_duration, client_manager::ClientManager, history::StoredMessage, storage::Client,
+};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use std::{cmp::Ordering, fmt, str::FromStr, time::Duration};
+
+/// What a query reads from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// The connected clients.
+    Clients,
+    /// The messages in history.
+    Messages,
+}
+
+impl Source {
+    /// The columns of this source, with the kind of value each holds.
+    fn columns(self) -> &'static [(&'static str, Kind)] {
+        match self {
+            Source::Clients => &[
+                ("id", Kind::Text),
+                ("topic", Kind::Text),
+                ("session_id", Kind::Text),
+                ("ip", Kind::Text),
+                ("user_agent", Kind::Text),
+                ("protocol_version", Kind::Number),
+                ("role", Kind::Text),
+                ("queued", Kind::Number),
+                ("connected_at", Kind::Time),
+            ],
+            Source::Messages => &[
+                ("id", Kind::Text),
+                ("topic", Kind::Text),
+                ("seq", Kind::Number),
+                ("sender", Kind::Text),
+                ("content", Kind::Text),
+                ("origin", Kind::Text),
+                ("timestamp", Kind::Time),
+            ],
+        }
+    }
+
+    /// Looks up a column by name.
+    fn column(self, column: &str) -> Result<(&'static str, Kind), String> {
+        self.columns()
+            .iter()
+            .find(|(name, _)| *name == column)
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<_> = self.columns().iter().map(|(name, _)| *name).collect();
+                format!(
+                    "Unknown column '{}' (expected one of {})",
+                    column,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Text,
+    Number,
+    Time,
+}
+
+/// A value in a query result; `Null` where a client has no topic, session or address.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Text(String),
+    Number(u64),
+    Time(DateTime<Utc>),
+    Null,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Text(text) => f.write_str(text),
+            Value::Number(number) => write!(f, "{}", number),
+            Value::Time(time) => write!(f, "{}", time.format("%Y-%m-%dT%H:%M:%SZ")),
+            Value::Null => f.write_str("-"),
+        }
+    }
+}
+
+impl Value {
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::Text(text) => text.clone().into(),
+            Value::Number(number) => (*number).into(),
+            Value::Time(time) => time.to_rfc3339().into(),
+            Value::Null => serde_json::Value::Null,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Op::Eq => ordering.is_eq(),
+            Op::Ne => ordering.is_ne(),
+            Op::Lt => ordering.is_lt(),
+            Op::Le => ordering.is_le(),
+            Op::Gt => ordering.is_gt(),
+            Op::Ge => ordering.is_ge(),
+        }
+    }
+}
+
+/// What a column is compared with, already checked against the column's kind.
+#[derive(Clone, Debug)]
+enum Operand {
+    Text(String),
+    Number(u64),
+    Time(DateTime<Utc>),
+    /// A time relative to when the query runs, from `ago('10m')`.
+    Ago(Duration),
+    /// A `LIKE` pattern, compiled from `%` and `_` wildcards.
+    Pattern(Regex),
+}
+
+impl PartialEq for Operand {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Operand::Pattern(a), Operand::Pattern(b)) => a.as_str() == b.as_str(),
+            (Operand::Text(a), Operand::Text(b)) => a == b,
+            (Operand::Number(a), Operand::Number(b)) => a == b,
+            (Operand::Time(a), Operand::Time(b)) => a == b,
+            (Operand::Ago(a), Operand::Ago(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Condition {
+    column: &'static str,
+    op: Op,
+    operand: Operand,
+}
+
+impl Condition {
+    /// Whether `value` satisfies the condition. Like in SQL, nothing is true of `Null`.
+    fn holds(&self, value: &Value, now: DateTime<Utc>) -> bool {
+        match (value, &self.operand) {
+            (Value::Text(text), Operand::Pattern(pattern)) => pattern.is_match(text),
+            (Value::Text(text), Operand::Text(operand)) => {
+                self.op.holds(text.as_str().cmp(operand.as_str()))
+            }
+            (Value::Number(number), Operand::Number(operand)) => self.op.holds(number.cmp(operand)),
+            (Value::Time(time), Operand::Time(operand)) => self.op.holds(time.cmp(operand)),
+            (Value::Time(time), Operand::Ago(ago)) => {
+                let operand = now - chrono::Duration::from_std(*ago).unwrap_or_default();
+                self.op.holds(time.cmp(&operand))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// An ad-hoc question about the server's state, in a small SQL dialect:
+///
+/// ```text
+/// SELECT <* | column, ...> FROM <clients | messages>
+///     [WHERE <column> <op> <value> [AND ...]]
+///     [ORDER BY <column> [ASC | DESC]] [LIMIT <n>]
+/// ```
+///
+/// The operators are `=`, `!=`, `<`, `<=`, `>`, `>=` and `LIKE` with `%` and `_`
+/// wildcards. Values are `'quoted text'`, whole numbers, and for time columns either a
+/// quoted RFC 3339 timestamp or `ago('10m')`. Keywords are case-insensitive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    source: Source,
+    /// The selected columns, all of them for `*`.
+    columns: Vec<&'static str>,
+    conditions: Vec<Condition>,
+    order_by: Option<(&'static str, bool)>,
+    limit: Option<usize>,
+}
+
+/// The rows a query produced, ready to print as a table or JSON.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResultSet {
+    pub columns: Vec<&'static str>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl Query {
+    /// Runs the query against the current clients or history.
+    pub fn run(&self, client_manager: &ClientManager) -> ResultSet {
+        let rows = match self.source {
+            Source::Clients => client_manager
+                .get_all_clients()
+                .iter()
+                .map(client_row)
+                .collect(),
+            Source::Messages => client_manager
+                .get_all_history()
+                .iter()
+                .map(message_row)
+                .collect(),
+        };
+        self.select(rows, Utc::now())
+    }
+
+    /// Filters, sorts and projects full rows of the source.
+    fn select(&self, rows: Vec<Vec<Value>>, now: DateTime<Utc>) -> ResultSet {
+        let index = |column: &str| {
+            self.source
+                .columns()
+                .iter()
+                .position(|(name, _)| *name == column)
+                .expect("columns are checked when the query is parsed")
+        };
+        let mut rows: Vec<_> = rows
+            .into_iter()
+            .filter(|row| {
+                self.conditions
+                    .iter()
+                    .all(|condition| condition.holds(&row[index(condition.column)], now))
+            })
+            .collect();
+        if let Some((column, descending)) = self.order_by {
+            let i = index(column);
+            rows.sort_by(|a, b| {
+                let ordering = compare(&a[i], &b[i]);
+                if descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            });
+        }
+        if let Some(limit) = self.limit {
+            rows.truncate(limit);
+        }
+        let selected: Vec<_> = self.columns.iter().map(|column| index(column)).collect();
+        ResultSet {
+            columns: self.columns.clone(),
+            rows: rows
+                .into_iter()
+                .map(|row| selected.iter().map(|i| row[*i].clone()).collect())
+                .collect(),
+        }
+    }
+}
+
+/// Orders values for `ORDER BY`, with `Null` last.
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Text(a), Value::Text(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => a.cmp(b),
+        (Value::Time(a), Value::Time(b)) => a.cmp(b),
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Greater,
+        (_, Value::Null) => Ordering::Less,
+        _ => Ordering::Equal,
+    }
+}
+
+fn optional(value: Option<impl ToString>) -> Value {
+    value.map_or(Value::Null, |value| Value::Text(value.to_string()))
+}
+
+/// A client as a row, in the order of `Source::Clients.columns()`.
+fn client_row(client: &Client) -> Vec<Value> {
+    vec![
+        Value::Text(client.id.to_string()),
+        optional(client.topic.as_ref()),
+        optional(client.session_id),
+        optional(client.ip),
+        optional(client.user_agent.as_ref()),
+        client
+            .protocol_version
+            .map_or(Value::Null, |version| Value::Number(version.into())),
+        optional(client.role),
+        Value::Number(client.sender.len() as u64),
+        Value::Time(client.connected_at),
+    ]
+}
+
+/// A message as a row, in the order of `Source::Messages.columns()`.
+fn message_row(message: &StoredMessage) -> Vec<Value> {
+    vec![
+        Value::Text(message.id.to_string()),
+        Value::Text(message.topic.clone()),
+        Value::Number(message.seq),
+        Value::Text(message.sender.clone()),
+        Value::Text(message.content.clone()),
+        optional(message.provenance.origin()),
+        Value::Time(message.timestamp),
+    ]
+}
+
+impl ResultSet {
+    /// The rows as an aligned text table with a header.
+    pub fn to_table(&self) -> String {
+        let cells: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| row.iter().map(Value::to_string).collect())
+            .collect();
+        let widths: Vec<_> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                cells
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .fold(column.len(), usize::max)
+            })
+            .collect();
+        let line = |values: Vec<&str>| {
+            let padded: Vec<_> = values
+                .iter()
+                .zip(&widths)
+                .map(|(value, width)| format!("{:<width$}", value, width = width))
+                .collect();
+            padded.join(" | ").trim_end().to_string()
+        };
+        let mut table = vec![line(self.columns.clone())];
+        let rule: Vec<_> = widths.iter().map(|width| "-".repeat(*width)).collect();
+        table.push(rule.join("-+-"));
+        for row in &cells {
+            table.push(line(row.iter().map(String::as_str).collect()));
+        }
+        table.join("\n")
+    }
+
+    /// The rows as a JSON array of objects keyed by column.
+    pub fn to_json(&self) -> String {
+        let rows: Vec<serde_json::Value> = self
+            .rows
+            .iter()
+            .map(|row| {
+                let object = self
+                    .columns
+                    .iter()
+                    .zip(row)
+                    .map(|(column, value)| (column.to_string(), value.to_json()))
+                    .collect();
+                serde_json::Value::Object(object)
+            })
+            .collect();
+        serde_json::to_string_pretty(&rows).expect("query results are valid JSON")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Number(u64),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => f.write_str(word),
+            Token::Text(text) => write!(f, "'{}'", text),
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Symbol(symbol) => f.write_str(symbol),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    const SYMBOLS: [&str; 11] = ["!=", "<>", "<=", ">=", "=", "<", ">", ",", "*", "(", ")"];
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c == '\'' {
+            // A quoted string, where '' stands for a single quote.
+            let mut text = String::new();
+            let mut chars = rest.char_indices().skip(1).peekable();
+            let end = loop {
+                match chars.next() {
+                    Some((_, '\'')) if matches!(chars.peek(), Some((_, '\''))) => {
+                        text.push('\'');
+                        chars.next();
+                    }
+                    Some((i, '\'')) => break i + 1,
+                    Some((_, c)) => text.push(c),
+                    None => return Err("Unterminated string in query".to_string()),
+                }
+            };
+            tokens.push(Token::Text(text));
+            rest = &rest[end..];
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            tokens.push(Token::Symbol(if *symbol == "<>" { "!=" } else { symbol }));
+            rest = &rest[symbol.len()..];
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            tokens.push(match word.parse() {
+                Ok(number) => Token::Number(number),
+                Err(_) => Token::Word(word.to_string()),
+            });
+            rest = &rest[end..];
+        } else {
+            return Err(format!("Unexpected '{}' in query", c));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Walks the tokens of a query.
+struct Parser {
+    tokens: std::vec::IntoIter<Token>,
+    peeked: Option<Token>,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        self.peeked.take().or_else(|| self.tokens.next())
+    }
+
+    fn peek(&mut self) -> Option<&Token> {
+        if self.peeked.is_none() {
+            self.peeked = self.tokens.next();
+        }
+        self.peeked.as_ref()
+    }
+
+    /// Consumes the next token if it is the keyword `keyword`.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found =
+            matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword));
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), String> {
+        if self.keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.unexpected(keyword))
+        }
+    }
+
+    fn symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol);
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    fn word(&mut self, what: &str) -> Result<String, String> {
+        match self.peek() {
+            Some(Token::Word(_)) => match self.next() {
+                Some(Token::Word(word)) => Ok(word.to_lowercase()),
+                _ => unreachable!(),
+            },
+            _ => Err(self.unexpected(what)),
+        }
+    }
+
+    fn unexpected(&mut self, expected: &str) -> String {
+        match self.peek() {
+            Some(token) => format!("Expected {} but found {}", expected, token),
+            None => format!("Expected {} at the end of the query", expected),
+        }
+    }
+}
+
+impl FromStr for Query {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?.into_iter(),
+            peeked: None,
+        };
+        parser.expect_keyword("select")?;
+        let mut names = Vec::new();
+        if !parser.symbol("*") {
+            loop {
+                names.push(parser.word("a column")?);
+                if !parser.symbol(",") {
+                    break;
+                }
+            }
+        }
+        parser.expect_keyword("from")?;
+        let source = match parser.word("clients or messages")?.as_str() {
+            "clients" => Source::Clients,
+            "messages" => Source::Messages,
+            other => {
+                return Err(format!(
+                    "Unknown source '{}' (expected clients or messages)",
+                    other
+                ))
+            }
+        };
+        let columns = if names.is_empty() {
+            source.columns().iter().map(|(name, _)| *name).collect()
+        } else {
+            names
+                .iter()
+                .map(|name| source.column(name).map(|(column, _)| column))
+                .collect::<Result<_, _>>()?
+        };
+
+        let mut conditions = Vec::new();
+        if parser.keyword("where") {
+            loop {
+                conditions.push(parse_condition(&mut parser, source)?);
+                if !parser.keyword("and") {
+                    break;
+                }
+            }
+        }
+        let mut order_by = None;
+        if parser.keyword("order") {
+            parser.expect_keyword("by")?;
+            let (name, _) = source.column(&parser.word("a column")?)?;
+            let descending = parser.keyword("desc");
+            if !descending {
+                parser.keyword("asc");
+            }
+            order_by = Some((name, descending));
+        }
+        let mut limit = None;
+        if parser.keyword("limit") {
+            match parser.next() {
+                Some(Token::Number(n)) => limit = Some(n as usize),
+                _ => return Err("Expected a number after LIMIT".to_string()),
+            }
+        }
+        if parser.peek().is_some() {
+            return Err(parser.unexpected("the end of the query"));
+        }
+        Ok(Self {
+            source,
+            columns,
+            conditions,
+            order_by,
+            limit,
+        })
+    }
+}
+
+fn parse_condition(parser: &mut Parser, source: Source) -> Result<Condition, String> {
+    let (column, kind) = source.column(&parser.word("a column")?)?;
+    if parser.keyword("like") {
+        let Some(Token::Text(pattern)) = parser.next() else {
+            return Err("Expected a quoted pattern after LIKE".to_string());
+        };
+        if kind != Kind::Text {
+            return Err(format!("LIKE only works on text, not '{}'", column));
+        }
+        return Ok(Condition {
+            column,
+            op: Op::Eq,
+            operand: Operand::Pattern(like_pattern(&pattern)),
+        });
+    }
+    let op = match parser.next() {
+        Some(Token::Symbol("=")) => Op::Eq,
+        Some(Token::Symbol("!=")) => Op::Ne,
+        Some(Token::Symbol("<")) => Op::Lt,
+        Some(Token::Symbol("<=")) => Op::Le,
+        Some(Token::Symbol(">")) => Op::Gt,
+        Some(Token::Symbol(">=")) => Op::Ge,
+        _ => return Err(format!("Expected a comparison after '{}'", column)),
+    };
+    let operand = match (kind, parser.next()) {
+        (Kind::Text, Some(Token::Text(text))) => Operand::Text(text),
+        (Kind::Number, Some(Token::Number(number))) => Operand::Number(number),
+        (Kind::Time, Some(Token::Text(text))) => DateTime::parse_from_rfc3339(&text)
+            .map(|time| Operand::Time(time.with_timezone(&Utc)))
+            .map_err(|_| format!("Invalid timestamp '{}' (expected RFC 3339)", text))?,
+        (Kind::Time, Some(Token::Word(word))) if word.eq_ignore_ascii_case("ago") => {
+            let duration = match (parser.next(), parser.next(), parser.next()) {
+                (
+                    Some(Token::Symbol("(")),
+                    Some(Token::Text(duration)),
+                    Some(Token::Symbol(")")),
+                ) => parse_duration(&duration)?,
+                _ => return Err("Expected ago('<duration>'), e.g. ago('10m')".to_string()),
+            };
+            Operand::Ago(duration)
+        }
+        (Kind::Text, _) => return Err(format!("'{}' must be compared with quoted text", column)),
+        (Kind::Number, _) => return Err(format!("'{}' must be compared with a number", column)),
+        (Kind::Time, _) => {
+            return Err(format!(
+                "'{}' must be compared with a quoted timestamp or ago('<duration>')",
+                column
+            ))
+        }
+    };
+    Ok(Condition {
+        column,
+        op,
+        operand,
+    })
+}
+
+/// Compiles a `LIKE` pattern, where `%` matches any text and `_` any one character.
+fn like_pattern(pattern: &str) -> Regex {
+    let mut regex = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '%' => regex.push_str(".*"),
+            '_' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex).expect("escaped LIKE patterns are valid regexes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    fn messages(now: DateTime<Utc>) -> Vec<Vec<Value>> {
+        let row = |seq: u64, topic: &str, sender: &str, minutes_ago: i64| {
+            vec![
+                text(&format!("id-{}", seq)),
+                text(topic),
+                Value::Number(seq),
+                text(sender),
+                text("hello"),
+                text("operator"),
+                Value::Time(now - chrono::Duration::minutes(minutes_ago)),
+            ]
+        };
+        vec![
+            row(1, "ops", "Alice", 30),
+            row(2, "ops", "Bob", 5),
+            row(3, "general", "Alice", 1),
+        ]
+    }
+
+    fn run(query: &str) -> ResultSet {
+        let now = Utc::now();
+        query.parse::<Query>().unwrap().select(messages(now), now)
+    }
+
+    #[test]
+    fn test_filters_sorts_and_projects() {
+        let result = run("SELECT seq, sender FROM messages WHERE topic = 'ops' ORDER BY seq DESC");
+        assert_eq!(result.columns, vec!["seq", "sender"]);
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Number(2), text("Bob")],
+                vec![Value::Number(1), text("Alice")],
+            ]
+        );
+
+        let result =
+            run("select seq from messages where timestamp > ago('10m') and sender like 'A%'");
+        assert_eq!(result.rows, vec![vec![Value::Number(3)]]);
+
+        let result = run("SELECT * FROM messages WHERE seq >= 2 LIMIT 1");
+        assert_eq!(result.columns.len(), Source::Messages.columns().len());
+        assert_eq!(result.rows.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_queries_are_explained() {
+        let error = |query: &str| query.parse::<Query>().unwrap_err();
+        assert!(error("SELECT * FROM users").contains("Unknown source"));
+        assert!(error("SELECT name FROM clients").contains("Unknown column 'name'"));
+        assert!(error("SELECT * FROM clients WHERE queued = 'x'").contains("a number"));
+        assert!(
+            error("SELECT * FROM clients WHERE connected_at > 'yesterday'").contains("RFC 3339")
+        );
+        assert!(error("SELECT * FROM messages WHERE seq LIKE '1%'").contains("LIKE"));
+        assert!(error("SELECT * FROM messages WHERE topic = 'ops").contains("Unterminated"));
+        assert!(error("SELECT * FROM messages LIMIT 5 extra").contains("end of the query"));
+    }
+
+    #[test]
+    fn test_results_render_as_table_and_json() {
+        let result = ResultSet {
+            columns: vec!["topic", "queued"],
+            rows: vec![
+                vec![text("general"), Value::Number(3)],
+                vec![Value::Null, Value::Number(0)],
+            ],
+        };
+        assert_eq!(
+            result.to_table(),
+            "topic   | queued\n--------+-------\ngeneral | 3\n-       | 0"
+        );
+        let json: serde_json::Value = serde_json::from_str(&result.to_json()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {"topic": "general", "queued": 3},
+                {"topic": null, "queued": 0}
+            ])
+        );
+    }
+}
@@ -0,0 +1,345 @@
+use crate::core::msg::{Outgoing, ServerMessage};
+use serde::Deserialize;
+use std::{
+    collections::VecDeque,
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::sync::Notify;
+
+/// What to do when a client's outgoing queue is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum BackpressurePolicy {
+    /// Evict the oldest queued message to make room for the new one.
+    #[default]
+    DropOldest,
+    /// Discard the new message.
+    DropNewest,
+    /// Disconnect the slow client.
+    Disconnect,
+}
+
+impl FromStr for BackpressurePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(Self::DropOldest),
+            "drop-newest" => Ok(Self::DropNewest),
+            "disconnect" => Ok(Self::Disconnect),
+            _ => Err(format!(
+                "Unknown backpressure policy '{}' (expected drop-oldest, drop-newest or disconnect)",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for BackpressurePolicy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for BackpressurePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::DropOldest => "drop-oldest",
+            Self::DropNewest => "drop-newest",
+            Self::Disconnect => "disconnect",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The size and overflow behaviour of every client's outgoing queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueConfig {
+    pub capacity: usize,
+    pub policy: BackpressurePolicy,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 100,
+            policy: BackpressurePolicy::default(),
+        }
+    }
+}
+
+/// Why a message was not enqueued.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SendError {
+    /// The receiving side is gone or the queue was closed.
+    Closed,
+    /// The queue is full and the policy does not allow evicting older messages.
+    Full,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+}
+
+#[derive(Debug)]
+struct Shared {
+    messages: Mutex<VecDeque<Arc<Outgoing>>>,
+    notify: Notify,
+    config: QueueConfig,
+    senders: AtomicUsize,
+    closed: AtomicBool,
+}
+
+/// Creates a bounded outgoing queue that applies `config.policy` when full.
+pub fn channel(config: QueueConfig) -> (QueueSender, QueueReceiver) {
+    let shared = Arc::new(Shared {
+        messages: Mutex::new(VecDeque::with_capacity(config.capacity)),
+        notify: Notify::new(),
+        config,
+        senders: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+    });
+    (
+        QueueSender {
+            shared: shared.clone(),
+        },
+        QueueReceiver { shared },
+    )
+}
+
+/// The sending half of a client's outgoing queue. Sending never waits.
+#[derive(Debug)]
+pub struct QueueSender {
+    shared: Arc<Shared>,
+}
+
+impl QueueSender {
+    /// Enqueues a message, applying the backpressure policy if the queue is full.
+    pub fn send(&self, message: ServerMessage) -> Result<(), SendError> {
+        self.send_shared(Arc::new(Outgoing::new(message)))
+    }
+
+    /// Enqueues a message that is also sent to other queues, so it is neither copied
+    /// nor serialized again for this one.
+    pub fn send_shared(&self, message: Arc<Outgoing>) -> Result<(), SendError> {
+        if self.is_closed() {
+            return Err(SendError::Closed);
+        }
+        let mut messages = self.shared.messages.lock().unwrap();
+        if messages.len() >= self.shared.config.capacity {
+            match self.shared.config.policy {
+                BackpressurePolicy::DropOldest => {
+                    messages.pop_front();
+                }
+                BackpressurePolicy::DropNewest | BackpressurePolicy::Disconnect => {
+                    return Err(SendError::Full);
+                }
+            }
+        }
+        messages.push_back(message);
+        drop(messages);
+        self.shared.notify.notify_one();
+        Ok(())
+    }
+
+    /// The number of messages waiting to be written to the client.
+    pub fn len(&self) -> usize {
+        self.shared.messages.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn policy(&self) -> BackpressurePolicy {
+        self.shared.config.policy
+    }
+
+    /// Closes the queue, discarding anything not yet delivered.
+    pub fn close(&self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+        self.shared.messages.lock().unwrap().clear();
+        self.shared.notify.notify_one();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::SeqCst)
+    }
+}
+
+impl Clone for QueueSender {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::SeqCst);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for QueueSender {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shared.notify.notify_one();
+        }
+    }
+}
+
+/// The receiving half of a client's outgoing queue.
+#[derive(Debug)]
+pub struct QueueReceiver {
+    shared: Arc<Shared>,
+}
+
+impl QueueReceiver {
+    /// Waits for the next message. Returns `None` once the queue is closed,
+    /// or when every sender is gone and the queue has been drained.
+    pub async fn recv(&mut self) -> Option<ServerMessage> {
+        self.recv_shared().await.map(unshare)
+    }
+
+    /// Like `recv`, without copying a message that was also sent to other queues.
+    pub async fn recv_shared(&mut self) -> Option<Arc<Outgoing>> {
+        loop {
+            match self.try_recv_shared() {
+                Ok(message) => return Some(message),
+                Err(TryRecvError::Closed) => return None,
+                Err(TryRecvError::Empty) => self.shared.notify.notified().await,
+            }
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Result<ServerMessage, TryRecvError> {
+        self.try_recv_shared().map(unshare)
+    }
+
+    pub fn try_recv_shared(&mut self) -> Result<Arc<Outgoing>, TryRecvError> {
+        if self.shared.closed.load(Ordering::SeqCst) {
+            return Err(TryRecvError::Closed);
+        }
+        match self.shared.messages.lock().unwrap().pop_front() {
+            Some(message) => Ok(message),
+            None if self.shared.senders.load(Ordering::SeqCst) == 0 => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+/// Takes the message out of `outgoing`, copying it only if other queues still hold it.
+fn unshare(outgoing: Arc<Outgoing>) -> ServerMessage {
+    Arc::try_unwrap(outgoing).map_or_else(|shared| shared.message.clone(), |own| own.message)
+}
+
+impl Drop for QueueReceiver {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(n: usize) -> ServerMessage {
+        ServerMessage::Error {
+            message: n.to_string(),
+        }
+    }
+
+    fn content(message: ServerMessage) -> String {
+        match message {
+            ServerMessage::Error { message } => message,
+            other => panic!("Unexpected message {:?}", other),
+        }
+    }
+
+    fn config(policy: BackpressurePolicy) -> QueueConfig {
+        QueueConfig {
+            capacity: 2,
+            policy,
+        }
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let (tx, mut rx) = channel(config(BackpressurePolicy::DropOldest));
+        for n in 0..3 {
+            assert_eq!(tx.send(error(n)), Ok(()));
+        }
+        assert_eq!(tx.len(), 2);
+        assert_eq!(content(rx.try_recv().unwrap()), "1");
+        assert_eq!(content(rx.try_recv().unwrap()), "2");
+    }
+
+    #[test]
+    fn test_drop_newest_and_disconnect_reject_when_full() {
+        for policy in [
+            BackpressurePolicy::DropNewest,
+            BackpressurePolicy::Disconnect,
+        ] {
+            let (tx, mut rx) = channel(config(policy));
+            assert_eq!(tx.send(error(0)), Ok(()));
+            assert_eq!(tx.send(error(1)), Ok(()));
+            assert_eq!(tx.send(error(2)), Err(SendError::Full));
+            assert_eq!(content(rx.try_recv().unwrap()), "0");
+            assert_eq!(content(rx.try_recv().unwrap()), "1");
+            assert!(matches!(rx.try_recv(), Err(TryRecvError::Empty)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recv_ends_when_senders_are_dropped() {
+        let (tx, mut rx) = channel(QueueConfig::default());
+        tx.send(error(0)).unwrap();
+        drop(tx);
+        assert_eq!(content(rx.recv().await.unwrap()), "0");
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_close_wakes_receiver() {
+        let (tx, mut rx) = channel(QueueConfig::default());
+        let waiter = tokio::spawn(async move { rx.recv().await });
+        tokio::task::yield_now().await;
+        tx.close();
+        assert!(waiter.await.unwrap().is_none());
+        assert_eq!(tx.send(error(0)), Err(SendError::Closed));
+    }
+
+    #[test]
+    fn test_shared_message_is_serialized_once() {
+        let (tx1, mut rx1) = channel(QueueConfig::default());
+        let (tx2, mut rx2) = channel(QueueConfig::default());
+        let message = Arc::new(Outgoing::new(error(7)));
+        tx1.send_shared(message.clone()).unwrap();
+        tx2.send_shared(message.clone()).unwrap();
+        drop(message);
+
+        let first = rx1.try_recv_shared().unwrap();
+        let frame = first.frame().unwrap();
+        let second = rx2.try_recv_shared().unwrap();
+        assert_eq!(second.frame().unwrap().as_ptr(), frame.as_ptr());
+        assert!(frame.contains("\"7\""));
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        for policy in [
+            BackpressurePolicy::DropOldest,
+            BackpressurePolicy::DropNewest,
+            BackpressurePolicy::Disconnect,
+        ] {
+            assert_eq!(policy.to_string().parse(), Ok(policy));
+        }
+        assert!("block".parse::<BackpressurePolicy>().is_err());
+    }
+}
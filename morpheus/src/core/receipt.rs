@@ -0,0 +1,184 @@
+use dashmap::DashMap;
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+/// The outcome of a broadcast, returned by `ClientManager::broadcast_to_topic`
+/// and `ClientManager::broadcast_global`.
+#[derive(Debug)]
+pub struct BroadcastReceipt {
+    /// The ID of the message, if it is one clients acknowledge.
+    pub msg_id: Option<Uuid>,
+    /// How many clients the message was addressed to.
+    pub targeted: usize,
+    /// Clients whose queue rejected the message.
+    pub failed: Vec<Uuid>,
+    acks: Option<AckWatch>,
+}
+
+impl BroadcastReceipt {
+    pub(crate) fn new(msg_id: Option<Uuid>, acks: Option<AckWatch>) -> Self {
+        Self {
+            msg_id,
+            targeted: 0,
+            failed: Vec::new(),
+            acks,
+        }
+    }
+
+    /// Records the result of enqueueing the message for one client.
+    pub(crate) fn record(&mut self, client_id: Uuid, enqueued: bool) {
+        self.targeted += 1;
+        if !enqueued {
+            self.failed.push(client_id);
+            if let Some(acks) = &self.acks {
+                acks.forget(&client_id);
+            }
+        }
+    }
+
+    /// How many clients the message was enqueued for.
+    pub fn enqueued(&self) -> usize {
+        self.targeted - self.failed.len()
+    }
+
+    /// Waits until every client the message was enqueued for has acknowledged it,
+    /// or until `timeout` elapses.
+    pub async fn wait_for_acks(self, timeout: Duration) -> AckOutcome {
+        match self.acks {
+            Some(acks) => acks.wait(timeout).await,
+            None => AckOutcome::default(),
+        }
+    }
+}
+
+/// Which clients acknowledged a broadcast in time.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct AckOutcome {
+    pub acked: Vec<Uuid>,
+    pub missing: Vec<Uuid>,
+}
+
+impl AckOutcome {
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+#[derive(Debug, Default)]
+struct AckProgress {
+    pending: HashSet<Uuid>,
+    acked: Vec<Uuid>,
+}
+
+#[derive(Debug, Default)]
+struct AckState {
+    progress: Mutex<AckProgress>,
+    notify: Notify,
+}
+
+/// Acknowledgments being awaited, keyed by message ID.
+#[derive(Debug, Default)]
+pub(crate) struct AckRegistry {
+    waiting: Arc<DashMap<Uuid, Arc<AckState>>>,
+}
+
+impl AckRegistry {
+    /// Starts collecting acknowledgments for `msg_id` from `clients`.
+    /// Collection stops when the returned watch is dropped.
+    pub(crate) fn watch(&self, msg_id: Uuid, clients: impl IntoIterator<Item = Uuid>) -> AckWatch {
+        let state = Arc::new(AckState::default());
+        state.progress.lock().unwrap().pending.extend(clients);
+        self.waiting.insert(msg_id, state.clone());
+        AckWatch {
+            msg_id,
+            state,
+            waiting: self.waiting.clone(),
+        }
+    }
+
+    pub(crate) fn acknowledge(&self, msg_id: &Uuid, client_id: Uuid) {
+        if let Some(state) = self.waiting.get(msg_id) {
+            let mut progress = state.progress.lock().unwrap();
+            if progress.pending.remove(&client_id) {
+                progress.acked.push(client_id);
+                state.notify.notify_one();
+            }
+        }
+    }
+}
+
+/// The clients each message published by a client was delivered to that have not
+/// acknowledged it yet. Only they may send the publisher a read receipt.
+#[derive(Debug, Default)]
+pub(crate) struct Readers {
+    pending: DashMap<Uuid, HashSet<Uuid>>,
+}
+
+impl Readers {
+    pub(crate) fn expect(&self, msg_id: Uuid, clients: impl IntoIterator<Item = Uuid>) {
+        self.pending.entry(msg_id).or_default().extend(clients);
+    }
+
+    /// Returns whether `client_id` was delivered the message and had not acknowledged it.
+    pub(crate) fn acknowledge(&self, msg_id: &Uuid, client_id: &Uuid) -> bool {
+        let Some(mut readers) = self.pending.get_mut(msg_id) else {
+            return false;
+        };
+        let read = readers.remove(client_id);
+        let done = readers.is_empty();
+        drop(readers);
+        if done {
+            self.pending
+                .remove_if(msg_id, |_, readers| readers.is_empty());
+        }
+        read
+    }
+
+    /// Stops expecting acknowledgments for a message, e.g. once it left history.
+    pub(crate) fn forget(&self, msg_id: &Uuid) {
+        self.pending.remove(msg_id);
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct AckWatch {
+    msg_id: Uuid,
+    state: Arc<AckState>,
+    waiting: Arc<DashMap<Uuid, Arc<AckState>>>,
+}
+
+impl AckWatch {
+    fn forget(&self, client_id: &Uuid) {
+        self.state
+            .progress
+            .lock()
+            .unwrap()
+            .pending
+            .remove(client_id);
+    }
+
+    async fn wait(self, timeout: Duration) -> AckOutcome {
+        let _ = tokio::time::timeout(timeout, async {
+            while !self.state.progress.lock().unwrap().pending.is_empty() {
+                self.state.notify.notified().await;
+            }
+        })
+        .await;
+        let progress = self.state.progress.lock().unwrap();
+        AckOutcome {
+            acked: progress.acked.clone(),
+            missing: progress.pending.iter().copied().collect(),
+        }
+    }
+}
+
+impl Drop for AckWatch {
+    fn drop(&mut self) {
+        self.waiting.remove(&self.msg_id);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::{cli::ui, core::client_manager::ClientManager};
+use dashmap::{mapref::entry::Entry, DashMap};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// How long a request waits for a response before its requester is told none came.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often pending requests are checked for their deadline.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A request sent to a topic's subscribers and waiting for one of them to respond.
+#[derive(Clone, Debug)]
+pub(crate) struct PendingRequest {
+    pub requester: Uuid,
+    pub topic: String,
+    pub deadline: Instant,
+}
+
+/// Routes responses back to the clients whose requests they answer, by correlation ID.
+/// Only the first response to a request is routed; later ones are refused.
+#[derive(Debug, Default)]
+pub(crate) struct PendingRequests {
+    pending: DashMap<Uuid, PendingRequest>,
+}
+
+impl PendingRequests {
+    /// Waits for a response to `correlation_id` until `timeout` passes. Refuses an ID that
+    /// is already waiting.
+    pub fn open(
+        &self,
+        correlation_id: Uuid,
+        requester: Uuid,
+        topic: String,
+        timeout: Duration,
+    ) -> Result<(), String> {
+        match self.pending.entry(correlation_id) {
+            Entry::Occupied(_) => Err(format!(
+                "Request {} is already waiting for a response",
+                correlation_id
+            )),
+            Entry::Vacant(entry) => {
+                entry.insert(PendingRequest {
+                    requester,
+                    topic,
+                    deadline: Instant::now() + timeout,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Takes the request a response from a subscriber of `topic` answers. A request to
+    /// another topic stays pending.
+    pub fn take(&self, correlation_id: &Uuid, topic: Option<&str>) -> Result<Uuid, String> {
+        self.pending
+            .remove_if(correlation_id, |_, pending| {
+                topic == Some(pending.topic.as_str())
+            })
+            .map(|(_, pending)| pending.requester)
+            .ok_or_else(|| format!("No request {} is waiting for a response", correlation_id))
+    }
+
+    /// Takes the requests whose deadline has passed, with their correlation ID.
+    pub fn take_expired(&self) -> Vec<(Uuid, PendingRequest)> {
+        let now = Instant::now();
+        let expired: Vec<Uuid> = self
+            .pending
+            .iter()
+            .filter(|entry| entry.deadline <= now)
+            .map(|entry| *entry.key())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|id| self.pending.remove(&id))
+            .collect()
+    }
+
+    /// Drops the requests of a client that disconnected; responses to them are refused.
+    pub fn forget_requester(&self, client_id: &Uuid) {
+        self.pending
+            .retain(|_, pending| pending.requester != *client_id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Tells requesters when nobody responded to their request in time.
+pub fn spawn_expirer(client_manager: Arc<ClientManager>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            tick.tick().await;
+            let expired = client_manager.expire_requests().await;
+            if expired > 0 {
+                ui::print_warning(&format!(
+                    "{} request(s) got no response within {} seconds.",
+                    expired,
+                    REQUEST_TIMEOUT.as_secs()
+                ));
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_responses_are_routed_once_from_the_topic() {
+        let requests = PendingRequests::default();
+        let (id, requester) = (Uuid::new_v4(), Uuid::new_v4());
+        requests
+            .open(id, requester, "rpc".to_string(), REQUEST_TIMEOUT)
+            .unwrap();
+        assert!(requests
+            .open(id, Uuid::new_v4(), "rpc".to_string(), REQUEST_TIMEOUT)
+            .is_err());
+
+        assert!(requests.take(&id, Some("general")).is_err());
+        assert!(requests.take(&id, None).is_err());
+        assert_eq!(requests.take(&id, Some("rpc")), Ok(requester));
+        assert!(requests.take(&id, Some("rpc")).is_err());
+    }
+
+    #[test]
+    fn test_expired_and_forgotten_requests_are_dropped() {
+        let requests = PendingRequests::default();
+        let (old, recent, gone) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let requester = Uuid::new_v4();
+        requests
+            .open(old, requester, "rpc".to_string(), Duration::ZERO)
+            .unwrap();
+        requests
+            .open(recent, requester, "rpc".to_string(), REQUEST_TIMEOUT)
+            .unwrap();
+        requests
+            .open(gone, Uuid::new_v4(), "rpc".to_string(), REQUEST_TIMEOUT)
+            .unwrap();
+
+        let expired = requests.take_expired();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0, old);
+
+        requests.forget_requester(&requester);
+        assert_eq!(requests.len(), 1);
+        assert!(requests.take(&gone, Some("rpc")).is_ok());
+    }
+}
@@ -0,0 +1,493 @@
+use crate::core::{
+    alerts::parse_duration,
+    client_manager::ClientManager,
+    msg::{Qos, ServerMessage},
+    provenance::{Origin, Provenance},
+};
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, DurationRound, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// How far ahead a cron expression is searched for its next match; expressions that
+/// never match, such as `0 0 31 2 *`, give up after this many days.
+const CRON_HORIZON_DAYS: i64 = 366 * 4;
+
+/// The allowed values of one cron field, as a bit per value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct CronField {
+    values: u64,
+    /// Whether the field was `*`, which matters for how day of month and day of week combine.
+    any: bool,
+}
+
+impl CronField {
+    fn parse(field: &str, min: u32, max: u32) -> Result<Self, String> {
+        let mut values = 0u64;
+        for item in field.split(',') {
+            let (range, step) = match item.split_once('/') {
+                Some((range, step)) => match step.parse::<u32>() {
+                    Ok(step) if step > 0 => (range, step),
+                    _ => return Err(format!("Invalid step '{}'", step)),
+                },
+                None => (item, 1),
+            };
+            let (start, end) = match range {
+                "*" => (min, max),
+                _ => {
+                    let (start, end) = range.split_once('-').unwrap_or((range, range));
+                    let parse = |value: &str| {
+                        value
+                            .parse::<u32>()
+                            .ok()
+                            .filter(|value| (min..=max).contains(value))
+                            .ok_or_else(|| {
+                                format!("Invalid value '{}' (expected {}-{})", value, min, max)
+                            })
+                    };
+                    // `a/n` means every n-th value from a onwards.
+                    let end = if step > 1 && start == end {
+                        max.to_string()
+                    } else {
+                        end.to_string()
+                    };
+                    (parse(start)?, parse(&end)?)
+                }
+            };
+            if start > end {
+                return Err(format!("Invalid range '{}'", range));
+            }
+            for value in (start..=end).step_by(step as usize) {
+                values |= 1 << value;
+            }
+        }
+        Ok(Self {
+            values,
+            any: field == "*",
+        })
+    }
+
+    fn matches(&self, value: u32) -> bool {
+        self.values & (1 << value) != 0
+    }
+}
+
+/// A standard five-field cron expression (minute, hour, day of month, month, day of week),
+/// evaluated in UTC. Fields take `*`, values, `a-b` ranges, `/n` steps and comma lists;
+/// day of week runs from 0 (Sunday) to 7 (Sunday again).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CronSchedule {
+    source: String,
+    minutes: CronField,
+    hours: CronField,
+    days: CronField,
+    months: CronField,
+    weekdays: CronField,
+}
+
+impl CronSchedule {
+    /// The first matching minute strictly after `after`.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let minute = ChronoDuration::minutes(1);
+        let mut time = after.duration_trunc(minute).ok()? + minute;
+        let horizon = after + ChronoDuration::days(CRON_HORIZON_DAYS);
+        while time < horizon {
+            if !self.months.matches(time.month()) || !self.day_matches(time) {
+                time = time.duration_trunc(ChronoDuration::days(1)).ok()? + ChronoDuration::days(1);
+            } else if !self.hours.matches(time.hour()) {
+                time =
+                    time.duration_trunc(ChronoDuration::hours(1)).ok()? + ChronoDuration::hours(1);
+            } else if !self.minutes.matches(time.minute()) {
+                time += minute;
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+
+    /// Like cron, a restricted day of month and day of week match when either one does.
+    fn day_matches(&self, time: DateTime<Utc>) -> bool {
+        let day = self.days.matches(time.day());
+        let weekday = time.weekday().num_days_from_sunday();
+        let weekday = self.weekdays.matches(weekday) || (weekday == 0 && self.weekdays.matches(7));
+        match (self.days.any, self.weekdays.any) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields.as_slice() else {
+            return Err(format!(
+                "Invalid cron expression '{}' (expected minute hour day month weekday)",
+                s
+            ));
+        };
+        let invalid = |name: &str, e: String| format!("Invalid cron {} field: {}", name, e);
+        Ok(Self {
+            source: fields.join(" "),
+            minutes: CronField::parse(minutes, 0, 59).map_err(|e| invalid("minute", e))?,
+            hours: CronField::parse(hours, 0, 23).map_err(|e| invalid("hour", e))?,
+            days: CronField::parse(days, 1, 31).map_err(|e| invalid("day", e))?,
+            months: CronField::parse(months, 1, 12).map_err(|e| invalid("month", e))?,
+            weekdays: CronField::parse(weekdays, 0, 7).map_err(|e| invalid("weekday", e))?,
+        })
+    }
+}
+
+/// When a scheduled message is delivered. Serialized as it is written in `/schedule`,
+/// a delay such as `90s` or a cron expression.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Schedule {
+    /// Once, this long after it was scheduled.
+    Delay(Duration),
+    /// Every time the cron expression matches, until cancelled.
+    Cron(CronSchedule),
+}
+
+impl Schedule {
+    /// Parses a delay such as `30s`, `5m` or `1h`, or a cron expression.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if s.split_whitespace().count() > 1 {
+            s.parse().map(Schedule::Cron)
+        } else {
+            parse_duration(s).map(Schedule::Delay)
+        }
+    }
+
+    fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Delay(delay) => Some(after + ChronoDuration::from_std(*delay).ok()?),
+            Schedule::Cron(cron) => cron.next_after(after),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Delay(delay) => write!(f, "once after {}s", delay.as_secs()),
+            Schedule::Cron(cron) => write!(f, "cron '{}'", cron.source),
+        }
+    }
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Schedule::parse(&s)
+    }
+}
+
+impl From<Schedule> for String {
+    fn from(schedule: Schedule) -> Self {
+        match schedule {
+            Schedule::Delay(delay) => format!("{}s", delay.as_secs()),
+            Schedule::Cron(cron) => cron.source,
+        }
+    }
+}
+
+/// A message waiting in the scheduler, as shown by `/schedule list`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScheduledMessage {
+    pub id: Uuid,
+    pub schedule: Schedule,
+    pub topic: String,
+    pub content: String,
+    pub next_run: DateTime<Utc>,
+}
+
+struct Job {
+    message: ScheduledMessage,
+    task: JoinHandle<()>,
+}
+
+/// Delivers operator announcements to topics at a later time, once or on a cron schedule.
+/// Each scheduled message has its own task sleeping until it is due; messages are sent
+/// as the server with a scheduler provenance. Schedules live in memory; `/snapshot save`
+/// writes them out and `/snapshot load` brings them back.
+pub struct Scheduler {
+    client_manager: Arc<ClientManager>,
+    jobs: Arc<Mutex<HashMap<Uuid, Job>>>,
+}
+
+impl Scheduler {
+    pub fn new(client_manager: Arc<ClientManager>) -> Self {
+        Self {
+            client_manager,
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Schedules `content` for `topic`, returning the scheduled message.
+    pub fn schedule(
+        &self,
+        schedule: Schedule,
+        topic: String,
+        content: String,
+    ) -> Result<ScheduledMessage, String> {
+        let next_run = schedule
+            .next_after(Utc::now())
+            .ok_or_else(|| format!("The schedule {} never fires", schedule))?;
+        let message = ScheduledMessage {
+            id: Uuid::new_v4(),
+            schedule,
+            topic,
+            content,
+            next_run,
+        };
+        self.spawn(&mut self.jobs.lock().unwrap(), message.clone());
+        Ok(message)
+    }
+
+    /// Takes over a message scheduled elsewhere, such as by the server a snapshot was
+    /// saved on, keeping its ID. A one-off message that fell due in the meantime is
+    /// delivered right away; a cron schedule carries on from its next match. Returns
+    /// false when the message is already scheduled here.
+    pub fn restore(&self, mut message: ScheduledMessage) -> bool {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.contains_key(&message.id) {
+            return false;
+        }
+        let now = Utc::now();
+        if let Schedule::Cron(cron) = &message.schedule {
+            if message.next_run < now {
+                match cron.next_after(now) {
+                    Some(next_run) => message.next_run = next_run,
+                    None => return false,
+                }
+            }
+        }
+        self.spawn(&mut jobs, message);
+        true
+    }
+
+    /// Starts the task of a scheduled message. The caller holds the lock on `jobs`, so
+    /// the task can't finish before its job is inserted.
+    fn spawn(&self, jobs: &mut HashMap<Uuid, Job>, message: ScheduledMessage) {
+        let task = tokio::spawn(run(
+            self.client_manager.clone(),
+            self.jobs.clone(),
+            message.clone(),
+        ));
+        jobs.insert(message.id, Job { message, task });
+    }
+
+    /// The scheduled messages, the next one due first.
+    pub fn list(&self) -> Vec<ScheduledMessage> {
+        let mut messages: Vec<_> = self
+            .jobs
+            .lock()
+            .unwrap()
+            .values()
+            .map(|job| job.message.clone())
+            .collect();
+        messages.sort_by_key(|message| message.next_run);
+        messages
+    }
+
+    /// Cancels a scheduled message, returning whether it was still scheduled.
+    pub fn cancel(&self, id: &Uuid) -> bool {
+        match self.jobs.lock().unwrap().remove(id) {
+            Some(job) => {
+                job.task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Sleeps until each run of a scheduled message and delivers it, removing the job once
+/// its schedule has no more runs.
+async fn run(
+    client_manager: Arc<ClientManager>,
+    jobs: Arc<Mutex<HashMap<Uuid, Job>>>,
+    mut message: ScheduledMessage,
+) {
+    loop {
+        let wait = (message.next_run - Utc::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+        let topic_message = ServerMessage::Topic {
+            id: Uuid::new_v4(),
+            topic: message.topic.clone(),
+            seq: 0,
+            sender: "Morpheus".to_string(),
+            content: message.content.clone(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            reply_to: None,
+            retained: false,
+        };
+        client_manager
+            .broadcast_to_topic_with_provenance(
+                &message.topic,
+                topic_message,
+                None,
+                Provenance::new(Origin::Scheduler),
+            )
+            .await;
+
+        let next_run = match &message.schedule {
+            Schedule::Delay(_) => None,
+            Schedule::Cron(cron) => cron.next_after(message.next_run.max(Utc::now())),
+        };
+        let mut jobs = jobs.lock().unwrap();
+        match next_run {
+            Some(next_run) => {
+                message.next_run = next_run;
+                if let Some(job) = jobs.get_mut(&message.id) {
+                    job.message.next_run = next_run;
+                }
+            }
+            None => {
+                jobs.remove(&message.id);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::storage::InMemoryStorage;
+    use chrono::TimeZone;
+
+    fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap()
+    }
+
+    #[test]
+    fn test_cron_next_after() {
+        let every_5: CronSchedule = "*/5 * * * *".parse().unwrap();
+        assert_eq!(
+            every_5.next_after(at(2024, 1, 1, 10, 2)),
+            Some(at(2024, 1, 1, 10, 5))
+        );
+        assert_eq!(
+            every_5.next_after(at(2024, 1, 1, 10, 5)),
+            Some(at(2024, 1, 1, 10, 10))
+        );
+
+        // 2024-01-01 was a Monday.
+        let weekday_mornings: CronSchedule = "30 9 * * 1-5".parse().unwrap();
+        assert_eq!(
+            weekday_mornings.next_after(at(2024, 1, 5, 10, 0)),
+            Some(at(2024, 1, 8, 9, 30))
+        );
+
+        let leap_day: CronSchedule = "0 0 29 2 *".parse().unwrap();
+        assert_eq!(
+            leap_day.next_after(at(2024, 3, 1, 0, 0)),
+            Some(at(2028, 2, 29, 0, 0))
+        );
+        let never: CronSchedule = "0 0 31 2 *".parse().unwrap();
+        assert_eq!(never.next_after(at(2024, 1, 1, 0, 0)), None);
+    }
+
+    #[test]
+    fn test_cron_day_of_month_or_week() {
+        // The 15th or any Sunday; 2024-01-07 was a Sunday.
+        let cron: CronSchedule = "0 12 15 * 7".parse().unwrap();
+        assert_eq!(
+            cron.next_after(at(2024, 1, 1, 0, 0)),
+            Some(at(2024, 1, 7, 12, 0))
+        );
+        assert_eq!(
+            cron.next_after(at(2024, 1, 14, 12, 0)),
+            Some(at(2024, 1, 15, 12, 0))
+        );
+    }
+
+    #[test]
+    fn test_parse_schedule() {
+        assert_eq!(
+            Schedule::parse("90s").unwrap(),
+            Schedule::Delay(Duration::from_secs(90))
+        );
+        assert!(matches!(
+            Schedule::parse("0 9 * * 1").unwrap(),
+            Schedule::Cron(_)
+        ));
+        assert!(Schedule::parse("soon").is_err());
+        assert!(Schedule::parse("60 * * * *").is_err());
+        assert!(Schedule::parse("* * * *").is_err());
+        assert!(Schedule::parse("*/0 * * * *").is_err());
+    }
+
+    #[test]
+    fn test_schedule_serializes_as_written() {
+        for written in ["90s", "0 9 * * 1-5"] {
+            let schedule = Schedule::parse(written).unwrap();
+            let json = serde_json::to_string(&schedule).unwrap();
+            assert_eq!(json, format!("\"{}\"", written));
+            assert_eq!(serde_json::from_str::<Schedule>(&json).unwrap(), schedule);
+        }
+        assert!(serde_json::from_str::<Schedule>("\"soon\"").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_delayed_message_is_delivered_once_and_cancel_stops_cron() {
+        let manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+        let (observer, mut rx) = manager.add_internal_client();
+        manager
+            .subscribe_client_to_topic(&observer, "news".to_string())
+            .unwrap();
+        let scheduler = Scheduler::new(manager.clone());
+
+        let cron = scheduler
+            .schedule(
+                Schedule::parse("0 0 1 1 *").unwrap(),
+                "news".to_string(),
+                "Happy new year".to_string(),
+            )
+            .unwrap();
+        scheduler
+            .schedule(
+                Schedule::Delay(Duration::from_millis(20)),
+                "news".to_string(),
+                "Maintenance in 5 minutes".to_string(),
+            )
+            .unwrap();
+        assert_eq!(scheduler.list().len(), 2);
+
+        let message = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        match message {
+            ServerMessage::Topic {
+                sender, content, ..
+            } => {
+                assert_eq!(sender, "Morpheus");
+                assert_eq!(content, "Maintenance in 5 minutes");
+            }
+            other => panic!("Unexpected message {:?}", other),
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let remaining = scheduler.list();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].id, cron.id);
+
+        assert!(scheduler.cancel(&cron.id));
+        assert!(!scheduler.cancel(&cron.id));
+        assert!(scheduler.list().is_empty());
+    }
+}
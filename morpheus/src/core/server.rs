@@ -1,65 +1,503 @@
 use crate::{
-    cli::{commands, ui},
-    core::{client_manager::ClientManager, msg::ServerMessage},
+    cli::{commands, completion::CommandHelper, ui},
+    config::Reloader,
+    core::{
+        analytics::SIZE_BUCKETS,
+        bans::BanTarget,
+        client_manager::ClientManager,
+        forwards::{ForwardRule, ForwardSpec},
+        latency::{LATENCY_BUCKETS_US, SUBSCRIBER_BUCKETS},
+        msg::{CloseCode, Qos, ServerMessage},
+        polls::Poll,
+        query::Query,
+        receipt::BroadcastReceipt,
+        scheduler::{Schedule, Scheduler},
+        simulator::{self, Simulator},
+        snapshot::Snapshot,
+    },
+    log::middleware,
 };
-use std::sync::Arc;
-use tokio::io::{self, AsyncBufReadExt, BufReader};
+use regex::Regex;
+use rustyline::{error::ReadlineError, history::DefaultHistory, Editor};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::mpsc;
 use uuid::Uuid;
 
+/// How long `/exit` waits for clients to be told the server is shutting down.
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(200);
+
 /// The main server structure that handles CLI commands.
 pub struct Server {
     client_manager: Arc<ClientManager>,
+    simulator: Simulator,
+    scheduler: Scheduler,
+    reloader: Option<Arc<Reloader>>,
+    history_file: Option<PathBuf>,
 }
 
 impl Server {
     pub fn new(client_manager: Arc<ClientManager>) -> Self {
-        Self { client_manager }
+        Self {
+            simulator: Simulator::new(client_manager.clone()),
+            scheduler: Scheduler::new(client_manager.clone()),
+            client_manager,
+            reloader: None,
+            history_file: None,
+        }
+    }
+
+    /// Enables `/reload` for a server started from a config file.
+    pub fn with_reloader(mut self, reloader: Arc<Reloader>) -> Self {
+        self.reloader = Some(reloader);
+        self
+    }
+
+    /// Keeps the command history of the CLI in `path` across restarts.
+    pub fn with_history_file(mut self, path: PathBuf) -> Self {
+        self.history_file = Some(path);
+        self
     }
 
-    /// Runs the main CLI loop for the server.
+    /// Runs the main CLI loop for the server. Lines are read with arrow-key history,
+    /// Ctrl-R search and Tab completion of topics and client IDs; Ctrl-C shuts the server
+    /// down like `/exit`, and Ctrl-D closes the console while the server keeps running.
     pub async fn run_cli(&self) {
-        ui::print_prompt();
-        let mut stdin = BufReader::new(io::stdin());
-        let mut line = String::new();
+        ui::use_editor_prompt();
+        let (lines, mut received) = mpsc::channel(1);
+        let (handled, next) = std::sync::mpsc::channel();
+        let helper = CommandHelper::new(self.client_manager.clone());
+        let history_file = self.history_file.clone();
+        std::thread::spawn(move || read_lines(helper, history_file, lines, next));
 
-        loop {
-            line.clear();
-            if stdin.read_line(&mut line).await.is_err() {
-                ui::print_error("Could not read from stdin.");
+        while let Some(line) = received.recv().await {
+            let command = match line {
+                Ok(line) => commands::parse_command(line.trim()),
+                Err(ReadlineError::Interrupted) => commands::Command::Exit,
+                Err(ReadlineError::Eof) => {
+                    ui::print_system_message("Console closed; the server keeps running.");
+                    std::future::pending::<()>().await;
+                    return;
+                }
+                Err(e) => {
+                    ui::print_error(&format!("Could not read from stdin: {}", e));
+                    break;
+                }
+            };
+            self.handle_command(command).await;
+            if handled.send(()).is_err() {
                 break;
             }
+        }
+    }
 
-            match commands::parse_command(line.trim()) {
-                commands::Command::Help => {
-                    let help_text = r#"Morpheus Server Commands:
+    /// Closes every connection as draining and exits the process, recording it in the
+    /// audit log.
+    pub async fn shutdown(&self) {
+        self.audit("exit", "server", Ok("Shutting down".to_string()));
+        ui::print_system_message("Shutting down...");
+        self.client_manager.close_all(CloseCode::Draining);
+        // Give the connections a moment to send their close frames.
+        tokio::time::sleep(SHUTDOWN_GRACE).await;
+        std::process::exit(0);
+    }
+
+    /// Runs one command line, e.g. one typed into the TUI's command box.
+    pub async fn execute(&self, line: &str) {
+        self.handle_command(commands::parse_command(line.trim()))
+            .await;
+    }
+
+    async fn handle_command(&self, command: commands::Command) {
+        match command {
+            commands::Command::Help => {
+                let help_text = r#"Morpheus Server Commands:
 /h, /hellp                      - List all commands
 /l, /list     all               - List all connected clients
 /l, /list     topics            - List all active topics
+/l, /list     bans              - List banned clients and addresses
 /l, /list     <topic>           - List clients in a specific topic
 /g, /global   <msg>             - Send a message to all clients
+/g, /global   --file <path>     - Send a file's content to all clients
 /t, /topic    <topic> <msg>     - Send a message to a topic
+/t, /topic    <topic> --file <path>
+                        - Send a file's content to a topic
+/t, /topic    merge <a> <b>     - Move all subscribers and history of a into b
+/t, /topic    split <src> <dst> --filter <regex>
+                        - Move subscribers of src matching by ID, nickname,
+                          address, user agent or role into dst
 /p, /private  <client_id> <msg> - Send a private message
+/k, /kick     <client_id> [why] - Disconnect a client
+/ban          <client_id|ip>    - Ban a client or address and kick it
+/unban        <client_id|ip>    - Lift a ban
+/w, /whois    <identity>        - Show a session identity's connection history
+/i, /inspect  <msg_id>          - Show a message's history and provenance
+/audit        tail [n]          - Show the last n operator actions (default 20)
+/replay       <topic> [n] [--broadcast]
+                        - Show the last n messages in a topic (default 20), and publish
+                          them again with --broadcast
+/snapshot     save <path>       - Save topics, retained messages, bans and schedules
+/snapshot     load <path>       - Add the state saved in a snapshot to the server's
+/stats        [topic]           - Show message, byte and client counts per topic
+/stats        server            - Show uptime, connections, messages, acks and errors
+/stats        analytics         - Show content sizes and kinds over the last hour
+/stats        latency           - Show broadcast latency by subscriber count and size
+/q, /query    [--json] "SELECT <cols|*> FROM clients|messages [WHERE ...] [ORDER BY ...] [LIMIT n]"
+                        - Query clients or history, e.g. WHERE topic = 'ops' AND
+                          connected_at > ago('10m')
+/s, /simulate <topic> <n> [rate] - Start n simulated clients publishing rate msgs/sec
+/s, /simulate stop              - Stop all simulated clients
+/schedule     <delay> <topic> <msg>
+                        - Send a message to a topic after e.g. 30s, 5m or 1h
+/schedule     "<cron>" <topic> <msg>
+                        - Send a message whenever a cron expression matches (UTC)
+/poll         <topic> "<question>" <option>... [--for 5m]
+                        - Open a poll in a topic and announce the results when it closes
+/schedule     list              - List scheduled messages
+/schedule     cancel <id>       - Cancel a scheduled message
+/forward      <from> <to> [--filter <regex>] [--replace <regex> <replacement>]
+                        - Forward messages published to one topic to another
+/forward      list              - List forwarding rules
+/forward      remove <id>       - Remove a forwarding rule
+/loglevel     [level]           - Show or change the log level until the next reload
+/reload                         - Re-read the config file
+/drain                          - Refuse new connections and move clients to other nodes
 /e, /exit                       - Shutdown the server"#;
-                    ui::print_system_message(help_text);
-                }
-                commands::Command::List(scope) => self.handle_list_command(scope),
-                commands::Command::Global(content) => self.handle_global_command(content).await,
-                commands::Command::Topic { topic, content } => {
-                    self.handle_topic_command(topic, content).await
-                }
-                commands::Command::Private { client_id, content } => {
-                    self.handle_private_command(client_id, content).await
-                }
-                commands::Command::Exit => {
-                    ui::print_system_message("Shutting down...");
-                    std::process::exit(0);
+                ui::print_system_message(help_text);
+            }
+            commands::Command::List(scope) => self.handle_list_command(scope),
+            commands::Command::Global(content) => self.handle_global_command(content).await,
+            commands::Command::Topic { topic, content } => {
+                self.handle_topic_command(topic, content).await
+            }
+            commands::Command::GlobalFile(path) => match self.read_announcement(&path) {
+                Ok(content) => self.handle_global_command(content).await,
+                Err(e) => ui::print_error(&e),
+            },
+            commands::Command::TopicFile { topic, path } => match self.read_announcement(&path) {
+                Ok(content) => self.handle_topic_command(topic, content).await,
+                Err(e) => ui::print_error(&e),
+            },
+            commands::Command::MergeTopics { from, into } => {
+                self.handle_merge_command(from, into).await
+            }
+            commands::Command::SplitTopic {
+                source,
+                destination,
+                filter,
+            } => self.handle_split_command(source, destination, filter).await,
+            commands::Command::Private { client_id, content } => {
+                self.handle_private_command(client_id, content).await
+            }
+            commands::Command::Kick { client_id, reason } => {
+                self.handle_kick_command(client_id, reason)
+            }
+            commands::Command::Ban(target) => self.handle_ban_command(target),
+            commands::Command::Unban(target) => self.handle_unban_command(target),
+            commands::Command::Whois(identity) => self.handle_whois_command(identity),
+            commands::Command::Inspect(msg_id) => self.handle_inspect_command(msg_id),
+            commands::Command::Stats(topic) => self.handle_stats_command(topic),
+            commands::Command::ServerStats => self.handle_server_stats_command(),
+            commands::Command::Analytics => self.handle_analytics_command(),
+            commands::Command::Latency => self.handle_latency_command(),
+            commands::Command::Query { query, json } => self.handle_query_command(&query, json),
+            commands::Command::Simulate { topic, count, rate } => {
+                self.handle_simulate_command(topic, count, rate)
+            }
+            commands::Command::LogLevel(None) => {
+                ui::print_system_message(&format!("Log level: {}", middleware::log_level()))
+            }
+            commands::Command::LogLevel(Some(level)) => {
+                middleware::set_log_level(level);
+                self.audit("loglevel", &level.to_string(), Ok("Set".to_string()));
+                ui::print_confirmation(&format!("Log level set to {}.", level));
+            }
+            commands::Command::AuditTail(count) => self.handle_audit_tail_command(count),
+            commands::Command::Replay {
+                topic,
+                count,
+                broadcast,
+            } => self.handle_replay_command(&topic, count, broadcast).await,
+            commands::Command::SnapshotSave(path) => self.handle_snapshot_save_command(&path),
+            commands::Command::SnapshotLoad(path) => self.handle_snapshot_load_command(&path),
+            commands::Command::Reload => match &self.reloader {
+                Some(reloader) => report_reload(reloader),
+                None => {
+                    ui::print_error("No config file to reload; start the server with --config.")
                 }
-                commands::Command::Unknown(err) if !err.is_empty() => ui::print_error(&err),
-                _ => ui::print_prompt(),
+            },
+            commands::Command::Schedule {
+                schedule,
+                topic,
+                content,
+            } => self.handle_schedule_command(schedule, topic, content),
+            commands::Command::Poll {
+                topic,
+                question,
+                options,
+                duration,
+            } => {
+                self.handle_poll_command(topic, question, options, duration)
+                    .await
+            }
+            commands::Command::Forward(spec) => self.handle_forward_command(spec),
+            commands::Command::ForwardList => self.handle_forward_list_command(),
+            commands::Command::ForwardRemove(id) => self.handle_forward_remove_command(id),
+            commands::Command::ScheduleList => self.handle_schedule_list_command(),
+            commands::Command::ScheduleCancel(id) => self.handle_schedule_cancel_command(id),
+            commands::Command::SimulateStop => {
+                let stopped = self.simulator.stop();
+                let result = format!("Stopped {} simulated client(s)", stopped);
+                self.audit("simulate stop", "all", Ok(result.clone()));
+                ui::print_confirmation(&format!("{}.", result));
+            }
+            commands::Command::Drain => self.handle_drain_command(),
+            commands::Command::Exit => self.shutdown().await,
+            commands::Command::Unknown(err) if !err.is_empty() => ui::print_error(&err),
+            _ => ui::print_prompt(),
+        }
+    }
+
+    /// Records an operator action in the audit log.
+    fn audit(&self, action: &str, detail: &str, result: Result<String, String>) {
+        self.client_manager.audit().record(action, detail, result);
+    }
+
+    fn handle_drain_command(&self) {
+        match self.client_manager.drain() {
+            Some(closed) => ui::print_confirmation(&format!(
+                "Draining: new connections are refused and {} client(s) were told to move \
+                 to another node. Waiting for the last one to disconnect.",
+                closed
+            )),
+            None => ui::print_system_message(&format!(
+                "Already draining; {} client(s) left.",
+                self.client_manager.drain_status().clients
+            )),
+        }
+    }
+
+    fn handle_simulate_command(&self, topic: String, count: usize, rate: Option<f64>) {
+        let rate = rate.unwrap_or(simulator::DEFAULT_RATE);
+        self.simulator.spawn(&topic, count, rate);
+        let detail = format!("{} client(s) in '{}' at {} msg/s", count, topic, rate);
+        self.audit("simulate", &detail, Ok("Started".to_string()));
+        ui::print_confirmation(&format!(
+            "Started {} simulated client(s) in topic '{}' at {} msg/s each ({} running).",
+            count,
+            topic,
+            rate,
+            self.simulator.len()
+        ));
+    }
+
+    fn handle_schedule_command(&self, schedule: Schedule, topic: String, content: String) {
+        let detail = format!("{} to '{}': {}", schedule, topic, content);
+        match self.scheduler.schedule(schedule, topic, content) {
+            Ok(scheduled) => {
+                self.audit(
+                    "schedule",
+                    &detail,
+                    Ok(format!("Scheduled {}", scheduled.id)),
+                );
+                ui::print_confirmation(&format!(
+                    "Scheduled {} for topic '{}', next at {}.",
+                    scheduled.id,
+                    scheduled.topic,
+                    scheduled.next_run.format("%Y-%m-%d %H:%M:%S UTC")
+                ));
+            }
+            Err(e) => {
+                self.audit("schedule", &detail, Err(e.clone()));
+                ui::print_error(&e);
+            }
+        }
+    }
+
+    async fn handle_poll_command(
+        &self,
+        topic: String,
+        question: String,
+        options: Vec<String>,
+        duration: Duration,
+    ) {
+        let detail = format!("{}: {} [{}]", topic, question, options.join(", "));
+        match Poll::new(topic, question, options, "Morpheus".to_string(), duration) {
+            Ok(poll) => {
+                let (id, topic) = (poll.id, poll.topic.clone());
+                let receipt = self.client_manager.open_poll(poll).await;
+                self.audit("poll", &detail, Ok(delivery_result(&receipt)));
+                ui::print_confirmation(&format!(
+                    "Opened poll {} in topic '{}' for {}s.",
+                    id,
+                    topic,
+                    duration.as_secs()
+                ));
+            }
+            Err(e) => {
+                self.audit("poll", &detail, Err(e.clone()));
+                ui::print_error(&e);
             }
         }
     }
 
+    fn handle_schedule_list_command(&self) {
+        println!("\nScheduled messages:");
+        for scheduled in self.scheduler.list() {
+            println!(
+                "- {} next at {} ({}) to '{}': {}",
+                scheduled.id,
+                scheduled.next_run.format("%Y-%m-%d %H:%M:%S UTC"),
+                scheduled.schedule,
+                scheduled.topic,
+                scheduled.content
+            );
+        }
+        ui::print_prompt();
+    }
+
+    fn handle_schedule_cancel_command(&self, id: Uuid) {
+        if self.scheduler.cancel(&id) {
+            self.audit(
+                "schedule cancel",
+                &id.to_string(),
+                Ok("Cancelled".to_string()),
+            );
+            ui::print_confirmation(&format!("Cancelled scheduled message {}.", id));
+        } else {
+            let error = format!("No scheduled message {}", id);
+            self.audit("schedule cancel", &id.to_string(), Err(error.clone()));
+            ui::print_error(&format!("{}.", error));
+        }
+    }
+
+    async fn handle_replay_command(&self, topic: &str, count: usize, broadcast: bool) {
+        let messages = self.client_manager.get_topic_history(topic, count);
+        if messages.is_empty() {
+            ui::print_error(&format!("Topic '{}' has no messages in history.", topic));
+            return;
+        }
+        println!("\nLast {} message(s) in topic '{}':", messages.len(), topic);
+        for message in &messages {
+            println!(
+                "- #{} {} {} from {}: {}",
+                message.seq,
+                message.timestamp.format("%Y-%m-%d %H:%M:%S UTC"),
+                message.id,
+                message.sender,
+                preview(&message.content)
+            );
+        }
+        if !broadcast {
+            ui::print_prompt();
+            return;
+        }
+        // The messages shown are the ones replayed, even if more arrived meanwhile.
+        let receipts = self.client_manager.replay(&messages).await;
+        let targeted: usize = receipts.iter().map(|receipt| receipt.targeted).sum();
+        let failed: usize = receipts.iter().map(|receipt| receipt.failed.len()).sum();
+        let detail = format!("{}: {} message(s)", topic, receipts.len());
+        self.audit(
+            "replay",
+            &detail,
+            Ok(format!(
+                "Sent {} message(s) to {} client(s), {} failed",
+                receipts.len(),
+                targeted,
+                failed
+            )),
+        );
+        ui::print_confirmation(&format!(
+            "Replayed {} message(s) to topic '{}'.",
+            receipts.len(),
+            topic
+        ));
+    }
+
+    fn handle_snapshot_save_command(&self, path: &Path) {
+        let snapshot = Snapshot::take(&self.client_manager, &self.scheduler);
+        let summary = format!(
+            "{} topic(s), {} ban(s) and {} scheduled message(s)",
+            snapshot.topics.len(),
+            snapshot.bans.len(),
+            snapshot.scheduled.len()
+        );
+        let detail = path.display().to_string();
+        match snapshot.save(path) {
+            Ok(()) => {
+                self.audit("snapshot save", &detail, Ok(format!("Saved {}", summary)));
+                ui::print_confirmation(&format!("Saved {} to {}.", summary, detail));
+            }
+            Err(e) => {
+                self.audit("snapshot save", &detail, Err(e.clone()));
+                ui::print_error(&e);
+            }
+        }
+    }
+
+    fn handle_snapshot_load_command(&self, path: &Path) {
+        let detail = path.display().to_string();
+        let restored = Snapshot::load(path)
+            .and_then(|snapshot| snapshot.restore(&self.client_manager, &self.scheduler));
+        match restored {
+            Ok(restored) => {
+                let summary = format!(
+                    "{} topic(s), {} retained message(s), {} ban(s) and {} scheduled message(s)",
+                    restored.topics, restored.retained, restored.bans, restored.scheduled
+                );
+                self.audit(
+                    "snapshot load",
+                    &detail,
+                    Ok(format!("Restored {}", summary)),
+                );
+                ui::print_confirmation(&format!("Restored {} from {}.", summary, detail));
+            }
+            Err(e) => {
+                self.audit("snapshot load", &detail, Err(e.clone()));
+                ui::print_error(&e);
+            }
+        }
+    }
+
+    fn handle_forward_command(&self, spec: ForwardSpec) {
+        let detail = spec.to_string();
+        match ForwardRule::new(spec) {
+            Ok(rule) => {
+                let id = rule.id;
+                self.client_manager.forwards().add(rule);
+                self.audit("forward", &detail, Ok(format!("Added rule {}", id)));
+                ui::print_confirmation(&format!("Forwarding {} (rule {}).", detail, id));
+            }
+            Err(e) => {
+                self.audit("forward", &detail, Err(e.clone()));
+                ui::print_error(&e);
+            }
+        }
+    }
+
+    fn handle_forward_list_command(&self) {
+        println!("\nForwarding rules:");
+        for rule in self.client_manager.forwards().list() {
+            println!("- {} {}", rule.id, rule.spec);
+        }
+        ui::print_prompt();
+    }
+
+    fn handle_forward_remove_command(&self, id: Uuid) {
+        if self.client_manager.forwards().remove(&id) {
+            self.audit("forward remove", &id.to_string(), Ok("Removed".to_string()));
+            ui::print_confirmation(&format!("Removed forwarding rule {}.", id));
+        } else {
+            let error = format!("No forwarding rule {}", id);
+            self.audit("forward remove", &id.to_string(), Err(error.clone()));
+            ui::print_error(&format!("{}.", error));
+        }
+    }
+
     fn handle_list_command(&self, scope: commands::ListScope) {
         match scope {
             commands::ListScope::All => {
@@ -67,9 +505,24 @@ impl Server {
                 let clients = self.client_manager.get_all_clients();
                 for client in clients {
                     println!(
-                        "- {} (Topic: {})",
+                        "- {} (Topic: {}, Queued: {})",
                         client.id,
-                        client.topic.as_deref().unwrap_or("None")
+                        client.topic.as_deref().unwrap_or("None"),
+                        client.sender.len()
+                    );
+                    println!(
+                        "  from {}, connected {}, protocol {}, agent {}, role {}",
+                        client
+                            .ip
+                            .map_or("internal".to_string(), |ip| ip.to_string()),
+                        client.connected_at.format("%Y-%m-%d %H:%M:%S UTC"),
+                        client
+                            .protocol_version
+                            .map_or("unknown".to_string(), |v| format!("v{}", v)),
+                        client.user_agent.as_deref().unwrap_or("unknown"),
+                        client
+                            .role
+                            .map_or("none".to_string(), |role| role.to_string())
                     );
                 }
             }
@@ -82,7 +535,13 @@ impl Server {
             commands::ListScope::Topic(topic) => {
                 println!("\nClients in topic '{}':", topic);
                 for client in self.client_manager.get_clients_by_topic(&topic) {
-                    println!("- {}", client.id);
+                    println!("- {} (Queued: {})", client.id, client.sender.len());
+                }
+            }
+            commands::ListScope::Bans => {
+                println!("\nBanned clients and addresses:");
+                for target in self.client_manager.bans().list() {
+                    println!("- {}", target);
                 }
             }
         }
@@ -94,21 +553,87 @@ impl Server {
             id: Uuid::new_v4(),
             content: content.clone(),
         };
-        self.client_manager.broadcast_global(msg).await;
-        ui::print_confirmation(&format!("Global message sent: {}", content));
+        let receipt = self.client_manager.broadcast_global(msg).await;
+        self.audit("global", &content, Ok(delivery_result(&receipt)));
+        ui::print_confirmation(&format!("Global message sent: {}", preview(&content)));
     }
 
     async fn handle_topic_command(&self, topic: String, content: String) {
         let msg = ServerMessage::Topic {
             id: Uuid::new_v4(),
             topic: topic.clone(),
+            seq: 0,
             sender: "Morpheus".to_string(),
             content: content.clone(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            reply_to: None,
+            retained: false,
         };
-        self.client_manager
+        let receipt = self
+            .client_manager
             .broadcast_to_topic(&topic, msg, None)
             .await;
-        ui::print_confirmation(&format!("Message sent to topic '{}': {}", topic, content));
+        let detail = format!("{}: {}", topic, content);
+        self.audit("topic", &detail, Ok(delivery_result(&receipt)));
+        ui::print_confirmation(&format!(
+            "Message sent to topic '{}': {}",
+            topic,
+            preview(&content)
+        ));
+    }
+
+    /// Reads a message to send from `path`. Windows line endings become `\n` and the
+    /// final newline is dropped, so the lines arrive as written.
+    fn read_announcement(&self, path: &Path) -> Result<String, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let content = text.replace("\r\n", "\n");
+        let content = content.trim_end_matches('\n');
+        let max = self.client_manager.policies().limits.max_message_bytes;
+        if content.trim().is_empty() {
+            Err(format!("{} is empty.", path.display()))
+        } else if content.len() > max {
+            Err(format!(
+                "{} is {} bytes, over the message size limit of {} bytes.",
+                path.display(),
+                content.len(),
+                max
+            ))
+        } else {
+            Ok(content.to_string())
+        }
+    }
+
+    async fn handle_merge_command(&self, from: String, into: String) {
+        let moved = self.client_manager.merge_topics(&from, &into).await;
+        let detail = format!("{} into {}", from, into);
+        self.audit("merge", &detail, Ok(format!("{} client(s) moved", moved)));
+        ui::print_system_message(&format!(
+            "Merged topic '{}' into '{}' ({} client(s) moved).",
+            from, into, moved
+        ));
+    }
+
+    async fn handle_split_command(&self, source: String, destination: String, filter: String) {
+        let detail = format!("{} into {} matching {}", source, destination, filter);
+        let filter = match Regex::new(&filter) {
+            Ok(filter) => filter,
+            Err(e) => {
+                let error = format!("Invalid filter: {}", e);
+                self.audit("split", &detail, Err(error.clone()));
+                return ui::print_error(&error);
+            }
+        };
+        let moved = self
+            .client_manager
+            .split_topic(&source, &destination, &filter)
+            .await;
+        self.audit("split", &detail, Ok(format!("{} client(s) moved", moved)));
+        ui::print_system_message(&format!(
+            "Moved {} client(s) from '{}' to '{}'.",
+            moved, source, destination
+        ));
     }
 
     async fn handle_private_command(&self, client_id: Uuid, content: String) {
@@ -120,9 +645,354 @@ impl Server {
         self.client_manager
             .send_private_message(client_id, msg)
             .await;
+        let detail = format!("{}: {}", client_id, content);
+        self.audit("private", &detail, Ok(format!("Sent as {}", msg_id)));
         ui::print_confirmation(&format!(
             "Private message (id: {}) sent to {}: {}",
             msg_id, client_id, content
         ));
     }
+
+    fn handle_kick_command(&self, client_id: Uuid, reason: Option<String>) {
+        let detail = match &reason {
+            Some(reason) => format!("{} ({})", client_id, reason),
+            None => client_id.to_string(),
+        };
+        if self.client_manager.kick_client(&client_id, reason) {
+            self.audit("kick", &detail, Ok("Kicked".to_string()));
+            ui::print_confirmation(&format!("Kicked client {}.", client_id));
+        } else {
+            self.audit("kick", &detail, Err("Not connected".to_string()));
+            ui::print_error(&format!("No connected client {}.", client_id));
+        }
+    }
+
+    fn handle_ban_command(&self, target: BanTarget) {
+        let detail = target.to_string();
+        match self.client_manager.ban(target) {
+            Ok(kicked) => {
+                self.audit("ban", &detail, Ok(format!("{} client(s) kicked", kicked)));
+                ui::print_confirmation(&format!("Banned {} ({} client(s) kicked).", target, kicked))
+            }
+            Err(e) => {
+                self.audit("ban", &detail, Err(e.clone()));
+                ui::print_error(&e)
+            }
+        }
+    }
+
+    fn handle_unban_command(&self, target: BanTarget) {
+        let detail = target.to_string();
+        match self.client_manager.bans().unban(&target) {
+            Ok(true) => {
+                self.audit("unban", &detail, Ok("Unbanned".to_string()));
+                ui::print_confirmation(&format!("Unbanned {}.", target))
+            }
+            Ok(false) => {
+                self.audit("unban", &detail, Err("Not banned".to_string()));
+                ui::print_error(&format!("{} is not banned.", target))
+            }
+            Err(e) => {
+                self.audit("unban", &detail, Err(e.clone()));
+                ui::print_error(&e)
+            }
+        }
+    }
+
+    fn handle_audit_tail_command(&self, count: usize) {
+        match self.client_manager.audit().tail(count) {
+            Ok(entries) if entries.is_empty() => {
+                ui::print_system_message("No operator actions recorded yet.")
+            }
+            Ok(entries) => {
+                println!("\nLast {} operator action(s):", entries.len());
+                for entry in entries {
+                    println!("- {}", entry);
+                }
+                ui::print_prompt();
+            }
+            Err(e) => ui::print_error(&e),
+        }
+    }
+
+    fn handle_stats_command(&self, topic: Option<String>) {
+        let topics = match topic {
+            Some(topic) => {
+                let stats = self.client_manager.topic_stats(&topic);
+                vec![(topic, stats)]
+            }
+            None => self.client_manager.all_topic_stats(),
+        };
+        println!("\nTopic statistics:");
+        for (topic, stats) in topics {
+            let last_activity = stats
+                .last_activity
+                .map_or("never".to_string(), |at| at.to_rfc3339());
+            println!(
+                "- {}: {} message(s), {} B in, {} B out, {} client(s), last active {}",
+                topic,
+                stats.messages,
+                stats.bytes_in,
+                stats.bytes_out,
+                stats.clients,
+                last_activity
+            );
+        }
+        ui::print_prompt();
+    }
+
+    fn handle_server_stats_command(&self) {
+        let stats = self.client_manager.server_stats();
+        let secs = stats.uptime.as_secs();
+        println!("\nServer statistics:");
+        println!(
+            "- Uptime: {}h {}m {}s",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        );
+        println!("- Connections since start: {}", stats.connections);
+        println!("- Connected clients: {}", stats.clients);
+        println!("- Messages routed: {}", stats.messages_routed);
+        println!("- Acknowledgments received: {}", stats.acks_received);
+        println!("- Errors: {}", stats.errors);
+        ui::print_prompt();
+    }
+
+    fn handle_analytics_command(&self) {
+        let report = self.client_manager.content_analytics().report();
+        let total = report.overall.sizes.count();
+        if total == 0 {
+            ui::print_system_message("No messages sampled in the last hour.");
+            return;
+        }
+        println!(
+            "\nContent analytics ({} message(s) over the last {} min):",
+            total,
+            report.covered.as_secs() / 60
+        );
+        println!("Sizes:");
+        let mut lower = 0;
+        for (bound, count) in SIZE_BUCKETS.iter().zip(report.overall.sizes.counts()) {
+            println!("- {}-{} B: {}", lower, bound, count);
+            lower = bound + 1;
+        }
+        println!(
+            "- over {} B: {}",
+            lower - 1,
+            report.overall.sizes.counts()[SIZE_BUCKETS.len()]
+        );
+        println!("Kinds:");
+        for (kind, count) in &report.overall.kinds {
+            println!("- {}: {} ({:.0}%)", kind, count, share(*count, total));
+        }
+        println!("Topics:");
+        for (topic, profile) in &report.topics {
+            let count = profile.sizes.count();
+            println!(
+                "- {}: {} message(s) ({:.0}%), p50 {}, p95 {}, mostly {}",
+                topic,
+                count,
+                share(count, total),
+                size_bound(profile.sizes.percentile(50.0)),
+                size_bound(profile.sizes.percentile(95.0)),
+                profile
+                    .dominant_kind()
+                    .map_or("unknown".to_string(), |kind| kind.to_string())
+            );
+        }
+        ui::print_prompt();
+    }
+
+    fn handle_latency_command(&self) {
+        let report = self.client_manager.broadcast_latency().report();
+        let total = report.overall.count();
+        if total == 0 {
+            ui::print_system_message("No topic broadcasts since the server started.");
+            return;
+        }
+        println!(
+            "\nBroadcast latency ({} broadcast(s) since start): p50 {}, p95 {}",
+            total,
+            latency_bound(report.overall.percentile(50.0)),
+            latency_bound(report.overall.percentile(95.0))
+        );
+        println!("By subscribers and content size:");
+        for (cell, histogram) in &report.cells {
+            println!(
+                "- {}, {}: {} broadcast(s), p50 {}, p95 {}",
+                bucket_range(&SUBSCRIBER_BUCKETS, cell.subscribers, "subscriber(s)"),
+                bucket_range(&SIZE_BUCKETS, cell.size, "B"),
+                histogram.count(),
+                latency_bound(histogram.percentile(50.0)),
+                latency_bound(histogram.percentile(95.0))
+            );
+        }
+        ui::print_prompt();
+    }
+
+    fn handle_whois_command(&self, identity: Uuid) {
+        match self.client_manager.whois(&identity) {
+            Some(whois) => {
+                println!("\nIdentity {}:", whois.identity);
+                println!("- First seen: {}", whois.record.first_seen);
+                println!("- Last seen: {}", whois.record.last_seen);
+                println!("- Sessions: {}", whois.record.sessions);
+                match whois.record.last_ip {
+                    Some(ip) => println!("- Last IP: {}", ip),
+                    None => println!("- Last IP: unknown"),
+                }
+                println!("- Online: {}", if whois.online { "yes" } else { "no" });
+                ui::print_prompt();
+            }
+            None => ui::print_error(&format!("Identity {} has never connected.", identity)),
+        }
+    }
+
+    fn handle_query_command(&self, query: &Query, json: bool) {
+        let result = query.run(&self.client_manager);
+        if json {
+            println!("\n{}", result.to_json());
+        } else {
+            println!("\n{}", result.to_table());
+            println!("({} row(s))", result.rows.len());
+        }
+        ui::print_prompt();
+    }
+
+    fn handle_inspect_command(&self, msg_id: Uuid) {
+        match self.client_manager.get_history_message(&msg_id) {
+            Some(message) => {
+                println!("\nMessage {}:", message.id);
+                println!("- Topic: {}", message.topic);
+                println!("- Sender: {}", message.sender);
+                println!("- Stored at: {}", message.timestamp);
+                println!("- Content: {}", message.content);
+                println!("Provenance:");
+                for entry in message.provenance.entries() {
+                    println!("- {}", entry);
+                }
+                ui::print_prompt();
+            }
+            None => ui::print_error(&format!("Message {} is not in history.", msg_id)),
+        }
+    }
+}
+
+/// The first line of `content`, with how many lines it has if there are more.
+fn preview(content: &str) -> String {
+    let mut lines = content.lines();
+    let first = lines.next().unwrap_or_default();
+    match lines.count() {
+        0 => first.to_string(),
+        more => format!("{} … ({} lines)", first, more + 1),
+    }
+}
+
+/// Summarizes who an operator message reached, for the audit log.
+fn delivery_result(receipt: &BroadcastReceipt) -> String {
+    format!(
+        "Sent to {} client(s), {} failed",
+        receipt.targeted,
+        receipt.failed.len()
+    )
+}
+
+fn share(count: u64, total: u64) -> f64 {
+    count as f64 * 100.0 / total as f64
+}
+
+/// Describes a size percentile from `SizeHistogram::percentile`.
+fn size_bound(bound: Option<usize>) -> String {
+    match bound {
+        Some(bound) => format!("<= {} B", bound),
+        None => format!("> {} B", SIZE_BUCKETS[SIZE_BUCKETS.len() - 1]),
+    }
+}
+
+/// Describes a latency percentile from `LatencyHistogram::percentile`.
+fn latency_bound(bound: Option<u64>) -> String {
+    match bound {
+        Some(bound) => format!("<= {} us", bound),
+        None => format!("> {} us", LATENCY_BUCKETS_US[LATENCY_BUCKETS_US.len() - 1]),
+    }
+}
+
+/// Describes bucket `index` of a histogram with upper `bounds`, e.g. "11-100 B".
+fn bucket_range(bounds: &[usize], index: usize, unit: &str) -> String {
+    let lower = match index {
+        0 => 0,
+        _ => bounds[index - 1] + 1,
+    };
+    match bounds.get(index) {
+        Some(upper) => format!("{}-{} {}", lower, upper, unit),
+        None => format!("over {} {}", lower - 1, unit),
+    }
+}
+
+/// Reads operator commands from stdin with line editing until the console closes,
+/// handing each line to the CLI loop and prompting again once it was handled. Lines are
+/// saved to `history_file` as they are entered.
+fn read_lines(
+    helper: CommandHelper,
+    history_file: Option<PathBuf>,
+    lines: mpsc::Sender<Result<String, ReadlineError>>,
+    handled: std::sync::mpsc::Receiver<()>,
+) {
+    let mut editor: Editor<CommandHelper, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            let _ = lines.blocking_send(Err(e));
+            return;
+        }
+    };
+    editor.set_helper(Some(helper));
+    if let Some(path) = &history_file {
+        // Missing on the first run.
+        let _ = editor.load_history(path);
+    }
+    loop {
+        let line = editor.readline(ui::PROMPT);
+        if let Ok(line) = &line {
+            if !line.trim().is_empty() {
+                let _ = editor.add_history_entry(line.as_str());
+                if let Some(path) = &history_file {
+                    if let Err(e) = editor.save_history(path) {
+                        ui::print_error(&format!(
+                            "Could not save the command history to {}: {}",
+                            path.display(),
+                            e
+                        ));
+                    }
+                }
+            }
+        }
+        let last = line.is_err();
+        if lines.blocking_send(line).is_err() || last || handled.recv().is_err() {
+            return;
+        }
+    }
+}
+
+/// Reloads the config and prints what changed; shared by `/reload` and SIGHUP.
+pub fn report_reload(reloader: &Reloader) {
+    match reloader.reload() {
+        Ok(summary) => {
+            let mut report = format!("Reloaded {}.", reloader.path().display());
+            if summary.applied.is_empty() && summary.needs_restart.is_empty() {
+                report.push_str(" Nothing changed.");
+            }
+            if !summary.applied.is_empty() {
+                report.push_str(&format!("\nApplied: {}", summary.applied.join(", ")));
+            }
+            if !summary.needs_restart.is_empty() {
+                report.push_str(&format!(
+                    "\nRestart required for: {}",
+                    summary.needs_restart.join(", ")
+                ));
+            }
+            ui::print_system_message(&report);
+        }
+        Err(e) => ui::print_error(&format!("Reload failed, keeping current settings: {}", e)),
+    }
 }
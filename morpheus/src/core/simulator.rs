@@ -0,0 +1,191 @@
+use crate::core::{
+    client_manager::ClientManager,
+    msg::{Qos, ServerMessage},
+    provenance::{Origin, Provenance},
+};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// Messages per second each simulated client publishes when no rate is given.
+pub const DEFAULT_RATE: f64 = 1.0;
+
+const LOREM: &[&str] = &[
+    "lorem",
+    "ipsum",
+    "dolor",
+    "sit",
+    "amet",
+    "consectetur",
+    "adipiscing",
+    "elit",
+    "sed",
+    "do",
+    "eiusmod",
+    "tempor",
+    "incididunt",
+    "ut",
+    "labore",
+    "et",
+    "dolore",
+    "magna",
+    "aliqua",
+];
+
+struct SimulatedClient {
+    id: Uuid,
+    task: JoinHandle<()>,
+}
+
+/// Runs synthetic clients inside the server so demos don't need external load tools.
+/// Simulated clients are registered like real ones: they show up in `/list`,
+/// receive topic traffic and publish lorem-ipsum messages at a fixed rate.
+pub struct Simulator {
+    client_manager: Arc<ClientManager>,
+    clients: Mutex<Vec<SimulatedClient>>,
+}
+
+impl Simulator {
+    pub fn new(client_manager: Arc<ClientManager>) -> Self {
+        Self {
+            client_manager,
+            clients: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Starts `count` clients in `topic`, each publishing `rate` messages per second.
+    /// Returns the IDs of the new clients.
+    pub fn spawn(&self, topic: &str, count: usize, rate: f64) -> Vec<Uuid> {
+        let period = Duration::from_secs_f64(1.0 / rate);
+        let mut clients = self.clients.lock().unwrap();
+        (0..count)
+            .map(|_| {
+                let (id, mut rx) = self.client_manager.add_internal_client();
+                self.client_manager
+                    .subscribe_ignoring_limits(&id, topic.to_string());
+
+                let client_manager = self.client_manager.clone();
+                let topic = topic.to_string();
+                let task = tokio::spawn(async move {
+                    let mut publish =
+                        tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+                    let mut sequence = id.as_u128() as usize;
+                    loop {
+                        tokio::select! {
+                            _ = publish.tick() => {
+                                sequence = sequence.wrapping_add(1);
+                                let message = ServerMessage::Topic {
+                                    id: Uuid::new_v4(),
+                                    topic: topic.clone(),
+                                    seq: 0,
+                                    sender: id.to_string(),
+                                    content: lorem_sentence(sequence),
+                                    headers: BTreeMap::new(),
+                                    qos: Qos::FireAndForget,
+                                    reply_to: None,
+                                    retained: false,
+                                };
+                                client_manager
+                                    .broadcast_to_topic_with_provenance(
+                                        &topic,
+                                        message,
+                                        Some(id),
+                                        Provenance::new(Origin::Simulated(id)),
+                                    )
+                                    .await;
+                            }
+                            // Drain incoming traffic so the queue never fills up.
+                            message = rx.recv() => {
+                                if message.is_none() {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                });
+
+                clients.push(SimulatedClient { id, task });
+                id
+            })
+            .collect()
+    }
+
+    /// Stops and unregisters every simulated client, returning how many were running.
+    pub fn stop(&self) -> usize {
+        let clients: Vec<_> = self.clients.lock().unwrap().drain(..).collect();
+        for client in &clients {
+            client.task.abort();
+            self.client_manager.remove_client(&client.id);
+        }
+        clients.len()
+    }
+
+    /// The number of simulated clients currently running.
+    pub fn len(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Builds a short lorem-ipsum sentence; different sequence numbers give different sentences.
+fn lorem_sentence(sequence: usize) -> String {
+    let length = 4 + sequence % 7;
+    let words: Vec<&str> = (0..length)
+        .map(|n| LOREM[sequence.wrapping_mul(7).wrapping_add(n * 3) % LOREM.len()])
+        .collect();
+    let mut sentence = words.join(" ");
+    sentence[..1].make_ascii_uppercase();
+    sentence.push('.');
+    sentence
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::storage::InMemoryStorage;
+
+    #[test]
+    fn test_lorem_sentence() {
+        let sentence = lorem_sentence(3);
+        assert!(sentence.chars().next().unwrap().is_uppercase());
+        assert!(sentence.ends_with('.'));
+        assert_ne!(sentence, lorem_sentence(4));
+    }
+
+    #[tokio::test]
+    async fn test_spawn_publishes_and_stop_removes_clients() {
+        let manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+        let (observer, mut rx) = manager.add_internal_client();
+        manager
+            .subscribe_client_to_topic(&observer, "demo".to_string())
+            .unwrap();
+
+        let simulator = Simulator::new(manager.clone());
+        let ids = simulator.spawn("demo", 2, 50.0);
+        assert_eq!(simulator.len(), 2);
+        assert_eq!(manager.get_clients_by_topic("demo").len(), 3);
+
+        let message = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        match message {
+            ServerMessage::Topic { topic, sender, .. } => {
+                assert_eq!(topic, "demo");
+                assert!(ids.iter().any(|id| id.to_string() == sender));
+            }
+            other => panic!("Unexpected message {:?}", other),
+        }
+
+        assert_eq!(simulator.stop(), 2);
+        assert!(simulator.is_empty());
+        assert_eq!(manager.get_clients_by_topic("demo").len(), 1);
+    }
+}
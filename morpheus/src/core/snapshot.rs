@@ -0,0 +1,283 @@
+use crate::core::{
+    bans::BanTarget,
+    client_manager::ClientManager,
+    msg::ServerMessage,
+    scheduler::{ScheduledMessage, Scheduler},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+/// The version of the snapshot format this server writes and reads.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// The server state that outlives connections, written to a JSON file by `/snapshot save`
+/// and brought back by `/snapshot load`, on the same server or on one with another storage
+/// backend. Clients, sessions and history are not part of it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub taken_at: DateTime<Utc>,
+    pub topics: Vec<TopicSnapshot>,
+    pub bans: Vec<BanTarget>,
+    pub scheduled: Vec<ScheduledMessage>,
+}
+
+/// A topic in a snapshot: the last sequence number it handed out and its retained message.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TopicSnapshot {
+    pub name: String,
+    pub seq: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retained: Option<ServerMessage>,
+}
+
+/// What loading a snapshot added to the server.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Restored {
+    pub topics: usize,
+    pub retained: usize,
+    pub bans: usize,
+    pub scheduled: usize,
+}
+
+impl Snapshot {
+    /// Captures every topic that has had a message or has subscribers, the bans and the
+    /// scheduled messages.
+    pub fn take(client_manager: &ClientManager, scheduler: &Scheduler) -> Self {
+        let mut seqs: BTreeMap<String, u64> = client_manager.topic_seqs().into_iter().collect();
+        for topic in client_manager.get_all_topics() {
+            seqs.entry(topic).or_default();
+        }
+        let topics = seqs
+            .into_iter()
+            .map(|(name, seq)| TopicSnapshot {
+                retained: client_manager.retained(&name),
+                name,
+                seq,
+            })
+            .collect();
+        Self {
+            version: SNAPSHOT_VERSION,
+            taken_at: Utc::now(),
+            topics,
+            bans: client_manager.bans().list(),
+            scheduled: scheduler.list(),
+        }
+    }
+
+    /// Writes the snapshot to a temporary file first so a crash never leaves it half written.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, text)
+            .and_then(|_| std::fs::rename(&temp, path))
+            .map_err(|e| format!("Failed to save {}: {}", path.display(), e))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let snapshot: Self = serde_json::from_str(&text)
+            .map_err(|e| format!("Invalid snapshot {}: {}", path.display(), e))?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(format!(
+                "Unsupported snapshot version {} in {} (expected {})",
+                snapshot.version,
+                path.display(),
+                SNAPSHOT_VERSION
+            ));
+        }
+        Ok(snapshot)
+    }
+
+    /// Adds the snapshot's state to the server's without removing or overwriting anything:
+    /// sequence numbers only move forward, a topic that has a retained message keeps it,
+    /// and bans and scheduled messages already in place are left alone. Restored bans
+    /// kick the clients they match.
+    pub fn restore(
+        self,
+        client_manager: &ClientManager,
+        scheduler: &Scheduler,
+    ) -> Result<Restored, String> {
+        let mut restored = Restored::default();
+        for topic in self.topics {
+            client_manager.restore_topic_seq(&topic.name, topic.seq);
+            restored.topics += 1;
+            if let Some(message) = topic.retained {
+                if client_manager.retained(&topic.name).is_none() {
+                    client_manager.retain(&message);
+                    restored.retained += 1;
+                }
+            }
+        }
+        for target in self.bans {
+            if !client_manager.bans().is_banned(&target) {
+                client_manager.ban(target)?;
+                restored.bans += 1;
+            }
+        }
+        for message in self.scheduled {
+            if scheduler.restore(message) {
+                restored.scheduled += 1;
+            }
+        }
+        Ok(restored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{msg::Qos, scheduler::Schedule, storage::InMemoryStorage};
+    use std::{path::PathBuf, sync::Arc, time::Duration};
+    use uuid::Uuid;
+
+    fn manager() -> Arc<ClientManager> {
+        Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())))
+    }
+
+    fn news(content: &str) -> ServerMessage {
+        ServerMessage::Topic {
+            id: Uuid::new_v4(),
+            topic: "news".to_string(),
+            seq: 0,
+            sender: "Morpheus".to_string(),
+            content: content.to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            reply_to: None,
+            retained: false,
+        }
+    }
+
+    fn snapshot_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("morpheus-snapshot-{}-{}", name, Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_round_trip() {
+        let source = manager();
+        let source_scheduler = Scheduler::new(source.clone());
+        for n in 0..3 {
+            source
+                .broadcast_to_topic("news", news(&format!("update {}", n)), None)
+                .await;
+        }
+        source.retain(&news("Read the rules"));
+        let banned: BanTarget = "10.0.0.7".parse().unwrap();
+        source.ban(banned).unwrap();
+        let cron = source_scheduler
+            .schedule(
+                Schedule::parse("0 9 * * 1").unwrap(),
+                "news".to_string(),
+                "Weekly digest".to_string(),
+            )
+            .unwrap();
+        source_scheduler
+            .schedule(
+                Schedule::Delay(Duration::from_secs(3600)),
+                "news".to_string(),
+                "Maintenance".to_string(),
+            )
+            .unwrap();
+
+        let dir = snapshot_dir("round-trip");
+        let path = dir.join("snapshot.json");
+        Snapshot::take(&source, &source_scheduler)
+            .save(&path)
+            .unwrap();
+
+        let target = manager();
+        let target_scheduler = Scheduler::new(target.clone());
+        let restored = Snapshot::load(&path)
+            .unwrap()
+            .restore(&target, &target_scheduler)
+            .unwrap();
+        assert_eq!(
+            restored,
+            Restored {
+                topics: 1,
+                retained: 1,
+                bans: 1,
+                scheduled: 2
+            }
+        );
+        assert_eq!(target.topic_seqs(), vec![("news".to_string(), 3)]);
+        match target.retained("news") {
+            Some(ServerMessage::Topic {
+                content, retained, ..
+            }) => {
+                assert_eq!(content, "Read the rules");
+                assert!(retained);
+            }
+            other => panic!("Unexpected retained message {:?}", other),
+        }
+        assert!(target.bans().is_banned(&banned));
+        let scheduled = target_scheduler.list();
+        assert_eq!(scheduled.len(), 2);
+        assert!(scheduled
+            .iter()
+            .any(|message| message.id == cron.id && message.schedule == cron.schedule));
+
+        // Loading the same snapshot again adds nothing.
+        let restored = Snapshot::load(&path)
+            .unwrap()
+            .restore(&target, &target_scheduler)
+            .unwrap();
+        assert_eq!(
+            restored,
+            Restored {
+                topics: 1,
+                ..Restored::default()
+            }
+        );
+        assert_eq!(target_scheduler.list().len(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restore_never_moves_a_topic_back() {
+        let source = manager();
+        source.broadcast_to_topic("news", news("old"), None).await;
+        let snapshot = Snapshot::take(&source, &Scheduler::new(source.clone()));
+
+        let target = manager();
+        for n in 0..5 {
+            target
+                .broadcast_to_topic("news", news(&format!("new {}", n)), None)
+                .await;
+        }
+        target.retain(&news("Current rules"));
+        snapshot
+            .restore(&target, &Scheduler::new(target.clone()))
+            .unwrap();
+        assert_eq!(target.topic_seqs(), vec![("news".to_string(), 5)]);
+        assert!(matches!(
+            target.retained("news"),
+            Some(ServerMessage::Topic { content, .. }) if content == "Current rules"
+        ));
+    }
+
+    #[test]
+    fn test_load_rejects_other_versions() {
+        let dir = snapshot_dir("version");
+        let path = dir.join("snapshot.json");
+        std::fs::write(
+            &path,
+            r#"{"version":2,"taken_at":"2024-01-01T00:00:00Z","topics":[],"bans":[],"scheduled":[]}"#,
+        )
+        .unwrap();
+        let error = Snapshot::load(&path).unwrap_err();
+        assert!(
+            error.contains("Unsupported snapshot version 2"),
+            "{}",
+            error
+        );
+        assert!(Snapshot::load(&dir.join("missing.json")).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,1104 @@
+//! Storage and history backends that keep what a restart would otherwise lose in a SQLite
+//! database, behind the `sqlite` feature.
+//!
+//! Connections cannot outlive the process, so [`SqliteStorage`] keeps connected clients in
+//! memory like [`InMemoryStorage`] and writes through what is worth keeping: the sessions of
+//! clients that asked for one, with their subscription and unacknowledged messages, and each
+//! topic's retained message and sequence number. A session whose client was connected when
+//! the server stopped can be resumed once it is back, as if the client had just dropped.
+//! [`SqliteHistory`] keeps the message history, serving reads from its copy in memory.
+//!
+//! Writes are queued for the [`Database`]'s own thread, which commits whatever queued up
+//! meanwhile in one transaction, so broadcasting never waits for the disk.
+
+use crate::{
+    core::{
+        client_manager::SESSION_TTL,
+        fanout::Recipient,
+        history::{HistoryStore, InMemoryHistory, StoredMessage},
+        msg::{Outgoing, ServerMessage},
+        storage::{Client, InMemoryStorage, Session, Storage, MAX_PENDING_ACKS},
+    },
+    forget::DatabaseErasure,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use dashmap::DashMap;
+use rusqlite::{params, types::Type, Connection, OptionalExtension, Row};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    str::FromStr,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+use uuid::Uuid;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS sessions (
+        session_id TEXT PRIMARY KEY,
+        client_id TEXT NOT NULL,
+        topic TEXT,
+        accepted_terms TEXT NOT NULL,
+        -- Milliseconds since the epoch; NULL while the client is connected.
+        detached_at INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS pending_acks (
+        client_id TEXT NOT NULL,
+        msg_id TEXT NOT NULL,
+        message TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS pending_acks_by_client ON pending_acks (client_id);
+    CREATE TABLE IF NOT EXISTS retained (
+        topic TEXT PRIMARY KEY,
+        message TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS topic_seqs (
+        topic TEXT PRIMARY KEY,
+        seq INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS history (
+        id TEXT PRIMARY KEY,
+        topic TEXT NOT NULL,
+        seq INTEGER NOT NULL,
+        sender TEXT NOT NULL,
+        content TEXT NOT NULL,
+        headers TEXT NOT NULL,
+        reply_to TEXT,
+        -- RFC 3339 with nanoseconds, so it sorts as text.
+        timestamp TEXT NOT NULL,
+        provenance TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS history_by_topic ON history (topic, timestamp);
+";
+
+const HISTORY_COLUMNS: &str =
+    "id, topic, seq, sender, content, headers, reply_to, timestamp, provenance";
+
+/// The most queued writes committed in one transaction.
+const MAX_BATCH: usize = 1024;
+
+type Job = Box<dyn FnOnce(&mut Connection) + Send>;
+
+/// A SQLite database shared by the storage and history backends. Its connection belongs
+/// to a thread of its own, which runs queued jobs in order.
+#[derive(Clone)]
+pub struct Database {
+    writer: Arc<Writer>,
+}
+
+struct Writer {
+    jobs: Option<Sender<Job>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Writer {
+    /// Waits for the queued writes, so a database opened again sees them.
+    fn drop(&mut self) {
+        self.jobs.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Database {
+    /// Opens the database at `path`, creating it and its tables if needed.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        Self::init(Connection::open(path))
+            .map_err(|e| format!("Failed to open database {}: {}", path.display(), e))
+    }
+
+    /// A database that lives as long as the process, e.g. for tests.
+    pub fn open_in_memory() -> Result<Self, String> {
+        Self::init(Connection::open_in_memory())
+            .map_err(|e| format!("Failed to open in-memory database: {}", e))
+    }
+
+    fn init(conn: rusqlite::Result<Connection>) -> Result<Self, String> {
+        let prepared = conn.and_then(|conn| {
+            // Every message written through costs an append to the log, not a sync.
+            conn.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))?;
+            conn.execute_batch("PRAGMA synchronous = NORMAL;")?;
+            conn.execute_batch(SCHEMA)?;
+            Ok(conn)
+        });
+        let conn = prepared.map_err(|e| e.to_string())?;
+        let (jobs, queued) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("morpheus-db".to_string())
+            .spawn(move || write_queued(conn, queued))
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            writer: Arc::new(Writer {
+                jobs: Some(jobs),
+                thread: Some(thread),
+            }),
+        })
+    }
+
+    /// Queues a write, which runs after every job queued before it.
+    fn write(&self, job: impl FnOnce(&mut Connection) + Send + 'static) {
+        let queued = self
+            .writer
+            .jobs
+            .as_ref()
+            .is_some_and(|jobs| jobs.send(Box::new(job)).is_ok());
+        if !queued {
+            warn!("The database thread has stopped; a write is lost");
+        }
+    }
+
+    /// Runs `query` once every write queued before it is done and waits for its result.
+    /// Only for what cannot be answered from memory, as this blocks the caller.
+    fn read<T: Send + 'static>(
+        &self,
+        query: impl FnOnce(&mut Connection) -> T + Send + 'static,
+    ) -> T {
+        let (result, answer) = mpsc::sync_channel(1);
+        self.write(move |conn| {
+            let _ = result.send(query(conn));
+        });
+        answer.recv().expect("the database thread has stopped")
+    }
+
+    /// Deletes the sessions and unacknowledged messages of a durable identity and puts
+    /// `redacted` in place of it as the sender of its messages in history. Their
+    /// provenance keeps the nil UUID instead, so it still parses.
+    pub fn forget(&self, identity: &Uuid, redacted: &str) -> Result<DatabaseErasure, String> {
+        let (identity, redacted) = (identity.to_string(), redacted.to_string());
+        self.read(move |conn| {
+            Ok(DatabaseErasure {
+                sessions: conn.execute("DELETE FROM sessions WHERE client_id = ?1", [&identity])?,
+                pending_acks: conn
+                    .execute("DELETE FROM pending_acks WHERE client_id = ?1", [&identity])?,
+                messages: conn.execute(
+                    "UPDATE history SET sender = ?2, provenance = REPLACE(provenance, ?1, ?3)
+                     WHERE sender = ?1 OR provenance LIKE '%' || ?1 || '%'",
+                    params![identity, redacted, Uuid::nil().to_string()],
+                )?,
+            })
+        })
+        .map_err(|e: rusqlite::Error| {
+            format!("Failed to erase the identity from the database: {}", e)
+        })
+    }
+}
+
+/// Runs the queued jobs until every `Database` handle is gone, committing those that
+/// queued up while the previous batch was written in one transaction.
+fn write_queued(mut conn: Connection, queued: Receiver<Job>) {
+    while let Ok(job) = queued.recv() {
+        let batch: Vec<Job> = std::iter::once(job)
+            .chain(queued.try_iter().take(MAX_BATCH - 1))
+            .collect();
+        let in_transaction =
+            log_failure("begin a transaction", conn.execute_batch("BEGIN")).is_some();
+        for job in batch {
+            job(&mut conn);
+        }
+        if in_transaction && log_failure("commit", conn.execute_batch("COMMIT")).is_none() {
+            let _ = conn.execute_batch("ROLLBACK");
+        }
+    }
+}
+
+/// Logs a write the database failed. What is in memory stays right; the write is only
+/// missing after a restart.
+fn log_failure<T>(what: &str, result: rusqlite::Result<T>) -> Option<T> {
+    result
+        .map_err(|e| warn!("Failed to {} in the database: {}", what, e))
+        .ok()
+}
+
+fn now_millis() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+fn conversion_error(
+    column: usize,
+    e: impl std::error::Error + Send + Sync + 'static,
+) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(column, Type::Text, Box::new(e))
+}
+
+/// Reads a text column holding a UUID or another value parsed from text.
+fn parsed<T>(row: &Row, column: usize) -> rusqlite::Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    row.get::<_, String>(column)?
+        .parse()
+        .map_err(|e| conversion_error(column, e))
+}
+
+/// Reads a text column holding JSON.
+fn json<T: DeserializeOwned>(row: &Row, column: usize) -> rusqlite::Result<T> {
+    serde_json::from_str(&row.get::<_, String>(column)?).map_err(|e| conversion_error(column, e))
+}
+
+fn to_json(value: &impl serde::Serialize) -> rusqlite::Result<String> {
+    serde_json::to_string(value).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+}
+
+/// What a restarted server takes over from the database.
+struct Loaded {
+    retained: Vec<(String, ServerMessage)>,
+    topic_seqs: Vec<(String, u64)>,
+}
+
+/// A storage backend that keeps sessions, subscriptions, retained messages and topic
+/// sequence numbers in a [`Database`] across restarts.
+pub struct SqliteStorage {
+    live: InMemoryStorage,
+    db: Database,
+    /// The last sequence number handed out per topic, as written to the database.
+    topic_seqs: DashMap<String, u64>,
+}
+
+impl SqliteStorage {
+    /// Loads what the database kept. Sessions whose client was connected when the server
+    /// stopped count as detached from now on; those detached for longer than
+    /// `SESSION_TTL` are forgotten.
+    pub fn new(db: &Database) -> Result<Self, String> {
+        let loaded = db
+            .read(|conn| Self::load(conn))
+            .map_err(|e| format!("Failed to load the database: {}", e))?;
+        let live = InMemoryStorage::new();
+        for (topic, message) in loaded.retained {
+            live.set_retained(&topic, Some(message));
+        }
+        Ok(Self {
+            live,
+            db: db.clone(),
+            topic_seqs: loaded.topic_seqs.into_iter().collect(),
+        })
+    }
+
+    fn load(conn: &Connection) -> rusqlite::Result<Loaded> {
+        let now = now_millis();
+        conn.execute(
+            "UPDATE sessions SET detached_at = ?1 WHERE detached_at IS NULL",
+            [now],
+        )?;
+        conn.execute(
+            "DELETE FROM sessions WHERE detached_at < ?1",
+            [now - SESSION_TTL.as_millis() as i64],
+        )?;
+        conn.execute(
+            "DELETE FROM pending_acks WHERE client_id NOT IN (SELECT client_id FROM sessions)",
+            [],
+        )?;
+
+        let mut retained = conn.prepare("SELECT topic, message FROM retained")?;
+        let retained = retained
+            .query_map([], |row| Ok((row.get(0)?, json(row, 1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        let mut seqs = conn.prepare("SELECT topic, seq FROM topic_seqs")?;
+        let topic_seqs = seqs
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(Loaded {
+            retained,
+            topic_seqs,
+        })
+    }
+
+    /// The session of a connected client, if it asked for one.
+    fn session_of(&self, client_id: &Uuid) -> Option<Uuid> {
+        self.live
+            .get_client(client_id)
+            .and_then(|client| client.session_id)
+    }
+
+    /// Records the session of a connected client, so it can be resumed after a restart.
+    fn attach(&self, client: &Client, session_id: Uuid) {
+        let (client_id, topic) = (client.id.to_string(), client.topic.clone());
+        let Some(accepted_terms) = log_failure("record a session", to_json(&client.accepted_terms))
+        else {
+            return;
+        };
+        self.db.write(move |conn| {
+            let written = conn.execute(
+                "INSERT INTO sessions (session_id, client_id, topic, accepted_terms, detached_at)
+                 VALUES (?1, ?2, ?3, ?4, NULL)
+                 ON CONFLICT (session_id) DO UPDATE SET client_id = excluded.client_id,
+                     topic = excluded.topic, accepted_terms = excluded.accepted_terms,
+                     detached_at = NULL",
+                params![session_id.to_string(), client_id, topic, accepted_terms],
+            );
+            log_failure("record a session", written);
+        });
+    }
+
+    fn write_session(conn: &mut Connection, session: &Session) -> rusqlite::Result<()> {
+        let tx = conn.savepoint()?;
+        let client_id = session.client_id.to_string();
+        tx.execute(
+            "INSERT OR REPLACE INTO sessions
+             (session_id, client_id, topic, accepted_terms, detached_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                session.session_id.to_string(),
+                client_id,
+                session.topic,
+                to_json(&session.accepted_terms)?,
+                now_millis(),
+            ],
+        )?;
+        tx.execute(
+            "DELETE FROM pending_acks WHERE client_id = ?1",
+            [&client_id],
+        )?;
+        for pending in &session.pending_acks {
+            insert_pending_ack(&tx, &client_id, &pending.message)?;
+        }
+        tx.commit()
+    }
+
+    /// Takes a detached session out of the database, as it was saved before a restart.
+    fn read_session(conn: &mut Connection, session_id: &Uuid) -> rusqlite::Result<Option<Session>> {
+        let tx = conn.savepoint()?;
+        let session = tx
+            .query_row(
+                "SELECT client_id, topic, accepted_terms, detached_at FROM sessions
+                 WHERE session_id = ?1 AND detached_at IS NOT NULL",
+                [session_id.to_string()],
+                |row| {
+                    Ok((
+                        parsed::<Uuid>(row, 0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        json::<HashMap<String, u32>>(row, 2)?,
+                        row.get::<_, i64>(3)?,
+                    ))
+                },
+            )
+            .optional()?;
+        tx.execute(
+            "DELETE FROM sessions WHERE session_id = ?1",
+            [session_id.to_string()],
+        )?;
+        let Some((client_id, topic, accepted_terms, detached_at)) = session else {
+            tx.commit()?;
+            return Ok(None);
+        };
+        let pending_acks = {
+            let mut statement =
+                tx.prepare("SELECT message FROM pending_acks WHERE client_id = ?1 ORDER BY rowid")?;
+            let rows = statement
+                .query_map([client_id.to_string()], |row| json::<ServerMessage>(row, 0))?;
+            rows.map(|row| row.map(|message| Arc::new(Outgoing::new(message))))
+                .collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        tx.execute(
+            "DELETE FROM pending_acks WHERE client_id = ?1",
+            [client_id.to_string()],
+        )?;
+        tx.commit()?;
+
+        // Instants do not survive a restart; the session is as old as the wall clock says.
+        let age = Duration::from_millis((now_millis() - detached_at).max(0) as u64);
+        Ok(Instant::now().checked_sub(age).map(|detached_at| Session {
+            session_id: *session_id,
+            client_id,
+            topic,
+            pending_acks,
+            accepted_terms,
+            detached_at,
+        }))
+    }
+
+    fn write_topic_seq(&self, topic: &str, seq: u64) {
+        let topic = topic.to_string();
+        self.db.write(move |conn| {
+            let written = conn.execute(
+                "INSERT OR REPLACE INTO topic_seqs (topic, seq) VALUES (?1, ?2)",
+                params![topic, seq],
+            );
+            log_failure("record a sequence number", written);
+        });
+    }
+}
+
+fn insert_pending_ack(
+    conn: &Connection,
+    client_id: &str,
+    message: &ServerMessage,
+) -> rusqlite::Result<()> {
+    let Some(msg_id) = message.id() else {
+        return Ok(());
+    };
+    conn.execute(
+        "INSERT INTO pending_acks (client_id, msg_id, message) VALUES (?1, ?2, ?3)",
+        params![client_id, msg_id.to_string(), to_json(message)?],
+    )?;
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    fn add_client(&self, client: Client) {
+        if let Some(session_id) = client.session_id {
+            self.attach(&client, session_id);
+        }
+        self.live.add_client(client)
+    }
+
+    fn remove_client(&self, client_id: &Uuid) -> Option<Client> {
+        let client = self.live.remove_client(client_id)?;
+        if let Some(session_id) = client.session_id {
+            // Saved again as detached if the session can be resumed.
+            self.db.write(move |conn| {
+                let written = conn.execute(
+                    "DELETE FROM sessions WHERE session_id = ?1 AND detached_at IS NULL",
+                    [session_id.to_string()],
+                );
+                log_failure("forget a session", written);
+            });
+        }
+        Some(client)
+    }
+
+    fn get_client(&self, client_id: &Uuid) -> Option<Client> {
+        self.live.get_client(client_id)
+    }
+
+    fn get_all_clients(&self) -> Vec<Client> {
+        self.live.get_all_clients()
+    }
+
+    fn client_count(&self) -> usize {
+        self.live.client_count()
+    }
+
+    fn subscribe_client_to_topic(&self, client_id: &Uuid, topic: String) {
+        self.live
+            .subscribe_client_to_topic(client_id, topic.clone());
+        if let Some(session_id) = self.session_of(client_id) {
+            self.db.write(move |conn| {
+                let written = conn.execute(
+                    "UPDATE sessions SET topic = ?1 WHERE session_id = ?2",
+                    params![topic, session_id.to_string()],
+                );
+                log_failure("record a subscription", written);
+            });
+        }
+    }
+
+    fn get_clients_in_topic(&self, topic: &str) -> Vec<Client> {
+        self.live.get_clients_in_topic(topic)
+    }
+
+    fn topic_recipients(&self, topic: &str) -> Arc<[Recipient]> {
+        self.live.topic_recipients(topic)
+    }
+
+    fn get_all_topics(&self) -> Vec<String> {
+        self.live.get_all_topics()
+    }
+
+    fn add_pending_ack(&self, client_id: &Uuid, message: Arc<Outgoing>) {
+        self.add_pending_acks(&[*client_id], message)
+    }
+
+    fn add_pending_acks(&self, client_ids: &[Uuid], message: Arc<Outgoing>) {
+        // Which clients' oldest message makes way for this one, as it does in memory.
+        let mut written = Vec::new();
+        for client_id in client_ids {
+            if self.session_of(client_id).is_some() {
+                let full = self.live.pending_ack_len(client_id) >= MAX_PENDING_ACKS;
+                written.push((client_id.to_string(), full));
+            }
+            self.live.add_pending_ack(client_id, message.clone());
+        }
+        if written.is_empty() {
+            return;
+        }
+        self.db.write(move |conn| {
+            for (client_id, full) in written {
+                let recorded =
+                    insert_pending_ack(conn, &client_id, &message.message).and_then(|()| {
+                        if full {
+                            conn.execute(
+                                "DELETE FROM pending_acks WHERE rowid =
+                                 (SELECT MIN(rowid) FROM pending_acks WHERE client_id = ?1)",
+                                [&client_id],
+                            )?;
+                        }
+                        Ok(())
+                    });
+                log_failure("record an unacknowledged message", recorded);
+            }
+        });
+    }
+
+    fn remove_pending_ack(&self, client_id: &Uuid, msg_id: &Uuid) -> bool {
+        let removed = self.live.remove_pending_ack(client_id, msg_id);
+        if removed {
+            let (client_id, msg_id) = (client_id.to_string(), msg_id.to_string());
+            self.db.write(move |conn| {
+                let written = conn.execute(
+                    "DELETE FROM pending_acks WHERE client_id = ?1 AND msg_id = ?2",
+                    [client_id, msg_id],
+                );
+                log_failure("forget an acknowledged message", written);
+            });
+        }
+        removed
+    }
+
+    fn take_pending_acks(&self, client_id: &Uuid) -> Vec<Arc<Outgoing>> {
+        let id = client_id.to_string();
+        self.db.write(move |conn| {
+            let written = conn.execute("DELETE FROM pending_acks WHERE client_id = ?1", [id]);
+            log_failure("forget unacknowledged messages", written);
+        });
+        self.live.take_pending_acks(client_id)
+    }
+
+    fn pending_ack_count(&self) -> usize {
+        self.live.pending_ack_count()
+    }
+
+    fn save_session(&self, session: Session) {
+        let saved = session.clone();
+        self.db.write(move |conn| {
+            log_failure("save a session", Self::write_session(conn, &saved));
+        });
+        self.live.save_session(session)
+    }
+
+    fn take_session(&self, session_id: &Uuid) -> Option<Session> {
+        // The database has the session too, unless it was saved before a restart.
+        let id = *session_id;
+        let stored = self
+            .db
+            .read(move |conn| log_failure("load a session", Self::read_session(conn, &id)))
+            .flatten();
+        self.live.take_session(session_id).or(stored)
+    }
+
+    fn next_topic_seq(&self, topic: &str) -> u64 {
+        let seq = {
+            let mut seq = self.topic_seqs.entry(topic.to_string()).or_default();
+            *seq += 1;
+            *seq
+        };
+        self.write_topic_seq(topic, seq);
+        seq
+    }
+
+    fn topic_seqs(&self) -> Vec<(String, u64)> {
+        self.topic_seqs
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
+
+    fn restore_topic_seq(&self, topic: &str, seq: u64) {
+        let seq = {
+            let mut current = self.topic_seqs.entry(topic.to_string()).or_default();
+            *current = (*current).max(seq);
+            *current
+        };
+        self.write_topic_seq(topic, seq);
+    }
+
+    fn set_retained(&self, topic: &str, message: Option<ServerMessage>) {
+        let (name, json) = (topic.to_string(), message.as_ref().map(to_json));
+        self.db.write(move |conn| {
+            let written = match json {
+                Some(message) => message.and_then(|message| {
+                    conn.execute(
+                        "INSERT OR REPLACE INTO retained (topic, message) VALUES (?1, ?2)",
+                        params![name, message],
+                    )
+                }),
+                None => conn.execute("DELETE FROM retained WHERE topic = ?1", [name]),
+            };
+            log_failure("record a retained message", written);
+        });
+        self.live.set_retained(topic, message)
+    }
+
+    fn get_retained(&self, topic: &str) -> Option<ServerMessage> {
+        self.live.get_retained(topic)
+    }
+}
+
+/// A history store keeping the last `retention` messages of every topic in a
+/// [`Database`], and in memory like [`InMemoryHistory`] to answer reads.
+pub struct SqliteHistory {
+    live: InMemoryHistory,
+    db: Database,
+    retention: usize,
+}
+
+impl SqliteHistory {
+    pub fn new(db: &Database, retention: usize) -> Self {
+        let history = Self {
+            live: InMemoryHistory::new(retention),
+            db: db.clone(),
+            retention,
+        };
+        history.load();
+        history
+    }
+
+    /// Overrides the retention for individual topics.
+    pub fn with_topic_retention(self, topic_retention: HashMap<String, usize>) -> Self {
+        // Loaded again, as the default retention may have left out messages a topic keeps.
+        let history = Self {
+            live: InMemoryHistory::new(self.retention).with_topic_retention(topic_retention),
+            ..self
+        };
+        history.load();
+        history
+    }
+
+    /// Copies the messages the database kept into memory. Those beyond their topic's
+    /// retention are deleted with the topic's next eviction.
+    fn load(&self) {
+        let loaded = self.db.read(|conn| {
+            select(
+                conn,
+                &format!(
+                    "SELECT {} FROM history ORDER BY timestamp, rowid",
+                    HISTORY_COLUMNS
+                ),
+                [],
+            )
+        });
+        for message in log_failure("read messages", loaded).unwrap_or_default() {
+            self.live.append(message);
+        }
+    }
+
+    /// Deletes evicted messages, with any older ones of their `topics` left over from a
+    /// larger retention.
+    fn delete_evicted(&self, topics: &[&str], evicted: &[StoredMessage]) {
+        let Some(newest) = evicted.iter().map(|message| message.timestamp).max() else {
+            return;
+        };
+        let topics: Vec<String> = topics.iter().map(|topic| topic.to_string()).collect();
+        let newest = newest.to_rfc3339_opts(SecondsFormat::Nanos, true);
+        let ids: Vec<String> = evicted
+            .iter()
+            .map(|message| message.id.to_string())
+            .collect();
+        self.db.write(move |conn| {
+            let deleted = topics.iter().try_for_each(|topic| {
+                conn.execute(
+                    "DELETE FROM history WHERE topic = ?1 AND timestamp < ?2",
+                    params![topic, newest],
+                )
+                .map(|_| ())
+            });
+            let deleted = deleted.and_then(|()| {
+                ids.iter().try_for_each(|id| {
+                    conn.execute("DELETE FROM history WHERE id = ?1", [id])
+                        .map(|_| ())
+                })
+            });
+            log_failure("evict messages", deleted);
+        });
+    }
+}
+
+fn stored_message(row: &Row) -> rusqlite::Result<StoredMessage> {
+    let timestamp: String = row.get(7)?;
+    Ok(StoredMessage {
+        id: parsed(row, 0)?,
+        topic: row.get(1)?,
+        seq: row.get(2)?,
+        sender: row.get(3)?,
+        content: row.get(4)?,
+        headers: json(row, 5)?,
+        reply_to: row
+            .get::<_, Option<String>>(6)?
+            .map(|id| id.parse().map_err(|e| conversion_error(6, e)))
+            .transpose()?,
+        timestamp: DateTime::parse_from_rfc3339(&timestamp)
+            .map_err(|e| conversion_error(7, e))?
+            .with_timezone(&Utc),
+        provenance: json(row, 8)?,
+    })
+}
+
+fn insert_message(conn: &Connection, message: &StoredMessage) -> rusqlite::Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT OR REPLACE INTO history ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            HISTORY_COLUMNS
+        ),
+        params![
+            message.id.to_string(),
+            message.topic,
+            message.seq,
+            message.sender,
+            message.content,
+            to_json(&message.headers)?,
+            message.reply_to.map(|id| id.to_string()),
+            message
+                .timestamp
+                .to_rfc3339_opts(SecondsFormat::Nanos, true),
+            to_json(&message.provenance)?,
+        ],
+    )?;
+    Ok(())
+}
+
+fn select(
+    conn: &Connection,
+    sql: &str,
+    params: impl rusqlite::Params,
+) -> rusqlite::Result<Vec<StoredMessage>> {
+    let mut statement = conn.prepare(sql)?;
+    let rows = statement.query_map(params, stored_message)?;
+    rows.collect()
+}
+
+impl HistoryStore for SqliteHistory {
+    fn append(&self, message: StoredMessage) -> Vec<StoredMessage> {
+        let row = message.clone();
+        self.db.write(move |conn| {
+            log_failure("record a message", insert_message(conn, &row));
+        });
+        let topic = message.topic.clone();
+        let evicted = self.live.append(message);
+        self.delete_evicted(&[&topic], &evicted);
+        evicted
+    }
+
+    fn get(&self, msg_id: &Uuid) -> Option<StoredMessage> {
+        self.live.get(msg_id)
+    }
+
+    fn recent(&self, topic: &str, limit: usize) -> Vec<StoredMessage> {
+        self.live.recent(topic, limit)
+    }
+
+    fn after(&self, topic: &str, seq: u64, limit: usize) -> Vec<StoredMessage> {
+        self.live.after(topic, seq, limit)
+    }
+
+    fn remove(&self, msg_id: &Uuid) -> Option<StoredMessage> {
+        let message = self.live.remove(msg_id)?;
+        let id = msg_id.to_string();
+        self.db.write(move |conn| {
+            let written = conn.execute("DELETE FROM history WHERE id = ?1", [id]);
+            log_failure("delete a message", written);
+        });
+        Some(message)
+    }
+
+    fn topics(&self) -> Vec<String> {
+        self.live.topics()
+    }
+
+    fn merge_topics(
+        &self,
+        from: &str,
+        into: &str,
+        next_seq: &mut dyn FnMut() -> u64,
+    ) -> Vec<StoredMessage> {
+        let moved: HashSet<Uuid> = self
+            .live
+            .recent(from, usize::MAX)
+            .iter()
+            .map(|message| message.id)
+            .collect();
+        let evicted = self.live.merge_topics(from, into, next_seq);
+        let renumbered: Vec<(String, u64)> = self
+            .live
+            .recent(into, usize::MAX)
+            .into_iter()
+            .filter(|message| moved.contains(&message.id))
+            .map(|message| (message.id.to_string(), message.seq))
+            .collect();
+        let topic = into.to_string();
+        self.db.write(move |conn| {
+            let written = renumbered.into_iter().try_for_each(|(id, seq)| {
+                conn.execute(
+                    "UPDATE history SET topic = ?1, seq = ?2 WHERE id = ?3",
+                    params![topic, seq, id],
+                )
+                .map(|_| ())
+            });
+            log_failure("merge topics", written);
+        });
+        // Evicted messages of `from` still have their old topic in the database.
+        self.delete_evicted(&[from, into], &evicted);
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::{
+            client_manager::ClientManager,
+            msg::Qos,
+            provenance::{Origin, Provenance},
+            queue::QueueConfig,
+        },
+        testing::client_record,
+    };
+    use std::{collections::BTreeMap, path::PathBuf};
+
+    /// A database path in a directory of its own, which also takes the write-ahead log.
+    fn temp_db() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("morpheus-db-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("morpheus.db")
+    }
+
+    fn client(session_id: Option<Uuid>) -> Client {
+        let (client, _receiver) = client_record(Uuid::new_v4(), QueueConfig::default());
+        Client {
+            session_id,
+            accepted_terms: HashMap::from([("news".to_string(), 2)]),
+            ..client
+        }
+    }
+
+    fn topic_message(topic: &str, content: &str) -> ServerMessage {
+        ServerMessage::Topic {
+            id: Uuid::new_v4(),
+            topic: topic.to_string(),
+            seq: 1,
+            sender: "Morpheus".to_string(),
+            content: content.to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::AtLeastOnce,
+            reply_to: None,
+            retained: false,
+        }
+    }
+
+    fn stored(topic: &str, content: &str) -> StoredMessage {
+        StoredMessage {
+            id: Uuid::new_v4(),
+            topic: topic.to_string(),
+            seq: 0,
+            sender: "Morpheus".to_string(),
+            content: content.to_string(),
+            headers: BTreeMap::from([("lang".to_string(), "en".to_string())]),
+            reply_to: Some(Uuid::new_v4()),
+            timestamp: Utc::now(),
+            provenance: Provenance::new(Origin::Operator),
+        }
+    }
+
+    #[test]
+    fn test_connected_sessions_can_be_resumed_after_a_restart() {
+        let path = temp_db();
+        let session_id = Uuid::new_v4();
+        let (client_id, message) = {
+            let storage = SqliteStorage::new(&Database::open(&path).unwrap()).unwrap();
+            let client = client(Some(session_id));
+            let client_id = client.id;
+            storage.add_client(client);
+            storage.subscribe_client_to_topic(&client_id, "news".to_string());
+            let message = topic_message("news", "unacknowledged");
+            storage.add_pending_ack(&client_id, Arc::new(Outgoing::new(message.clone())));
+            let acked = topic_message("news", "acknowledged");
+            storage.add_pending_ack(&client_id, Arc::new(Outgoing::new(acked.clone())));
+            storage.remove_pending_ack(&client_id, &acked.id().unwrap());
+            // The server stops without the client disconnecting.
+            (client_id, message)
+        };
+
+        let storage = SqliteStorage::new(&Database::open(&path).unwrap()).unwrap();
+        let session = storage.take_session(&session_id).unwrap();
+        assert_eq!(session.client_id, client_id);
+        assert_eq!(session.topic.as_deref(), Some("news"));
+        assert_eq!(session.accepted_terms.get("news"), Some(&2));
+        assert_eq!(session.pending_acks.len(), 1);
+        assert_eq!(session.pending_acks[0].message.id(), message.id());
+        assert!(session.detached_at.elapsed() < SESSION_TTL);
+        assert!(storage.take_session(&session_id).is_none());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_unacknowledged_messages_of_a_broadcast_are_kept_up_to_the_limit() {
+        let path = temp_db();
+        let sessions = [Uuid::new_v4(), Uuid::new_v4()];
+        let messages: Vec<_> = (0..=MAX_PENDING_ACKS)
+            .map(|i| topic_message("news", &i.to_string()))
+            .collect();
+        {
+            let storage = SqliteStorage::new(&Database::open(&path).unwrap()).unwrap();
+            let clients: Vec<_> = sessions
+                .iter()
+                .map(|session_id| {
+                    let client = client(Some(*session_id));
+                    let client_id = client.id;
+                    storage.add_client(client);
+                    client_id
+                })
+                .collect();
+            for message in &messages {
+                storage.add_pending_acks(&clients, Arc::new(Outgoing::new(message.clone())));
+            }
+        }
+
+        let storage = SqliteStorage::new(&Database::open(&path).unwrap()).unwrap();
+        for session_id in sessions {
+            let pending = storage.take_session(&session_id).unwrap().pending_acks;
+            assert_eq!(pending.len(), MAX_PENDING_ACKS);
+            assert_eq!(pending[0].message.id(), messages[1].id());
+        }
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_a_restarted_server_resumes_sessions_and_history() {
+        let path = temp_db();
+        let manager = |db: &Database| {
+            let storage = Arc::new(SqliteStorage::new(db).unwrap());
+            ClientManager::with_history(storage, Arc::new(SqliteHistory::new(db, 10)))
+        };
+        let (client_id, session_id, message) = {
+            let manager = manager(&Database::open(&path).unwrap());
+            let (client_id, mut rx) = manager.add_internal_client();
+            manager
+                .connect_session(client_id, "news".to_string(), None)
+                .await;
+            let Some(ServerMessage::Welcome { session_id, .. }) = rx.recv().await else {
+                panic!("Expected Welcome");
+            };
+            let message = topic_message("news", "before the restart");
+            manager
+                .broadcast_to_topic("news", message.clone(), None)
+                .await;
+            (client_id, session_id, message)
+        };
+
+        let manager = manager(&Database::open(&path).unwrap());
+        let (new_id, mut rx) = manager.add_internal_client();
+        let resumed = manager
+            .connect_session(new_id, "news".to_string(), Some(session_id))
+            .await;
+        assert_eq!(resumed, client_id);
+        assert!(matches!(
+            rx.recv().await,
+            Some(ServerMessage::Welcome { resumed: true, .. })
+        ));
+        assert_eq!(rx.recv().await.and_then(|m| m.id()), message.id());
+        let history = manager.get_topic_history("news", 10);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].content, "before the restart");
+        assert_eq!(history[0].seq, 1);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_kicked_clients_leave_no_session_behind() {
+        let path = temp_db();
+        let session_id = Uuid::new_v4();
+        {
+            let storage = SqliteStorage::new(&Database::open(&path).unwrap()).unwrap();
+            let client = client(Some(session_id));
+            let client_id = client.id;
+            storage.add_client(client);
+            storage.remove_client(&client_id);
+            storage.take_pending_acks(&client_id);
+        }
+
+        let storage = SqliteStorage::new(&Database::open(&path).unwrap()).unwrap();
+        assert!(storage.take_session(&session_id).is_none());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_retained_messages_and_sequence_numbers_survive_a_restart() {
+        let path = temp_db();
+        let retained = topic_message("news", "latest");
+        {
+            let storage = SqliteStorage::new(&Database::open(&path).unwrap()).unwrap();
+            storage.set_retained("news", Some(retained.clone()));
+            storage.set_retained("sports", Some(topic_message("sports", "gone")));
+            storage.set_retained("sports", None);
+            assert_eq!(storage.next_topic_seq("news"), 1);
+            assert_eq!(storage.next_topic_seq("news"), 2);
+            storage.restore_topic_seq("alerts", 40);
+            storage.restore_topic_seq("news", 1);
+        }
+
+        let storage = SqliteStorage::new(&Database::open(&path).unwrap()).unwrap();
+        assert_eq!(
+            storage.get_retained("news").and_then(|m| m.id()),
+            retained.id()
+        );
+        assert!(storage.get_retained("sports").is_none());
+        assert_eq!(storage.next_topic_seq("news"), 3);
+        assert_eq!(storage.next_topic_seq("sports"), 1);
+        assert_eq!(storage.next_topic_seq("alerts"), 41);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_history_survives_a_restart_within_its_retention() {
+        let path = temp_db();
+        let (first, second, third) = (
+            stored("news", "first"),
+            stored("news", "second"),
+            stored("news", "third"),
+        );
+        {
+            let history = SqliteHistory::new(&Database::open(&path).unwrap(), 2);
+            assert!(history.append(first.clone()).is_empty());
+            assert!(history.append(second.clone()).is_empty());
+            assert_eq!(history.append(third.clone()), vec![first.clone()]);
+        }
+
+        let history = SqliteHistory::new(&Database::open(&path).unwrap(), 2);
+        assert_eq!(
+            history.recent("news", 10),
+            vec![second.clone(), third.clone()]
+        );
+        assert_eq!(history.get(&third.id), Some(third.clone()));
+        assert!(history.get(&first.id).is_none());
+        assert_eq!(history.topics(), vec!["news".to_string()]);
+        assert_eq!(history.remove(&second.id), Some(second));
+        assert_eq!(history.recent("news", 10), vec![third]);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_merging_topics_renumbers_and_evicts() {
+        let db = Database::open_in_memory().unwrap();
+        let history = SqliteHistory::new(&db, 10)
+            .with_topic_retention(HashMap::from([("all".to_string(), 2)]));
+        let manager = ClientManager::with_history(
+            Arc::new(SqliteStorage::new(&db).unwrap()),
+            Arc::new(history),
+        );
+        manager
+            .broadcast_to_topic("all", topic_message("all", "a"), None)
+            .await;
+        for content in ["b", "c", "d"] {
+            manager
+                .broadcast_to_topic("old", topic_message("old", content), None)
+                .await;
+        }
+
+        manager.merge_topics("old", "all").await;
+        let merged = manager.get_topic_history("all", 10);
+        let contents: Vec<_> = merged.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["c", "d"]);
+        let seqs: Vec<_> = merged.iter().map(|m| m.seq).collect();
+        assert_eq!(seqs, [3, 4]);
+        assert!(manager.get_topic_history("old", 10).is_empty());
+    }
+}
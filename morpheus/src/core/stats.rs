@@ -0,0 +1,175 @@
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Traffic through a topic since the server started, shown by `/stats`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TopicStats {
+    /// Messages published to the topic.
+    pub messages: u64,
+    /// Content bytes published to the topic.
+    pub bytes_in: u64,
+    /// Content bytes handed to subscribers, counted once per recipient.
+    pub bytes_out: u64,
+    /// Clients subscribed to the topic on this node right now.
+    pub clients: usize,
+    /// When a message was last published or a client last joined.
+    pub last_activity: Option<DateTime<Utc>>,
+}
+
+/// The per-topic counters kept by the client manager.
+#[derive(Debug, Default)]
+pub(crate) struct TopicCounters {
+    topics: DashMap<String, TopicStats>,
+}
+
+impl TopicCounters {
+    /// Counts a message of `bytes` content bytes delivered to `recipients` clients.
+    pub fn message(&self, topic: &str, bytes: usize, recipients: usize) {
+        let mut stats = self.topics.entry(topic.to_string()).or_default();
+        stats.messages += 1;
+        stats.bytes_in += bytes as u64;
+        stats.bytes_out += (bytes * recipients) as u64;
+        stats.last_activity = Some(Utc::now());
+    }
+
+    /// Records a client joining the topic.
+    pub fn joined(&self, topic: &str) {
+        self.topics
+            .entry(topic.to_string())
+            .or_default()
+            .last_activity = Some(Utc::now());
+    }
+
+    /// The counters of a topic, without its client count.
+    pub fn get(&self, topic: &str) -> TopicStats {
+        self.topics
+            .get(topic)
+            .map(|stats| stats.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn topics(&self) -> Vec<String> {
+        self.topics
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+}
+
+/// Totals since the server started, shown by `/stats server`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ServerStats {
+    pub uptime: Duration,
+    /// WebSocket and Server-Sent Events connections accepted.
+    pub connections: u64,
+    /// Clients connected right now.
+    pub clients: usize,
+    /// Topic messages delivered on this node and global broadcasts.
+    pub messages_routed: u64,
+    /// Acknowledgments clients sent for messages they received.
+    pub acks_received: u64,
+    /// Errors reported to clients and connections that ended in an error.
+    pub errors: u64,
+}
+
+/// The server-wide counters, bumped by the client manager and the WebSocket handler.
+#[derive(Debug)]
+pub struct ServerCounters {
+    started: Instant,
+    connections: AtomicU64,
+    messages_routed: AtomicU64,
+    acks_received: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl Default for ServerCounters {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            connections: AtomicU64::default(),
+            messages_routed: AtomicU64::default(),
+            acks_received: AtomicU64::default(),
+            errors: AtomicU64::default(),
+        }
+    }
+}
+
+impl ServerCounters {
+    pub fn connection(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn message_routed(&self) {
+        self.messages_routed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn ack_received(&self) {
+        self.acks_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The counters as of now, with `clients` as the connected client count.
+    pub fn snapshot(&self, clients: usize) -> ServerStats {
+        ServerStats {
+            uptime: self.started.elapsed(),
+            connections: self.connections.load(Ordering::Relaxed),
+            clients,
+            messages_routed: self.messages_routed.load(Ordering::Relaxed),
+            acks_received: self.acks_received.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters_accumulate_per_topic() {
+        let counters = TopicCounters::default();
+        counters.joined("general");
+        assert_eq!(counters.get("general").messages, 0);
+        assert!(counters.get("general").last_activity.is_some());
+
+        counters.message("general", 10, 3);
+        counters.message("general", 5, 0);
+        counters.message("news", 7, 1);
+
+        let general = counters.get("general");
+        assert_eq!(
+            (general.messages, general.bytes_in, general.bytes_out),
+            (2, 15, 30)
+        );
+        assert_eq!(counters.get("news").bytes_out, 7);
+        assert_eq!(counters.get("unknown"), TopicStats::default());
+    }
+
+    #[test]
+    fn test_server_counters_snapshot() {
+        let counters = ServerCounters::default();
+        counters.connection();
+        counters.connection();
+        counters.message_routed();
+        counters.ack_received();
+        counters.error();
+        let stats = counters.snapshot(1);
+        assert_eq!(
+            (
+                stats.connections,
+                stats.clients,
+                stats.messages_routed,
+                stats.acks_received,
+                stats.errors
+            ),
+            (2, 1, 1, 1, 1)
+        );
+    }
+}
@@ -1,34 +1,205 @@
-use crate::core::msg::ServerMessage;
+use crate::core::{
+    fanout::Recipient,
+    msg::{CloseCode, Outgoing, ServerMessage},
+    policy::Role,
+    queue::QueueSender,
+};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
-use tokio::sync::mpsc;
+use indexmap::IndexSet;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::{Arc, OnceLock},
+    time::Instant,
+};
+use tokio::sync::Notify;
+use tracing::debug;
 use uuid::Uuid;
 
+/// The maximum number of unacknowledged messages kept per client.
+pub const MAX_PENDING_ACKS: usize = 100;
+
 /// Represents a connected client's data stored on the server.
 #[derive(Clone, Debug)]
 pub struct Client {
     pub id: Uuid,
     pub topic: Option<String>,
-    pub sender: mpsc::Sender<ServerMessage>,
+    pub sender: QueueSender,
+    /// The durable session this client belongs to, if it requested one.
+    pub session_id: Option<Uuid>,
+    /// The address the client connected from, unknown for internal clients.
+    pub ip: Option<IpAddr>,
+    /// The `User-Agent` header of the client's WebSocket handshake, if it sent one.
+    pub user_agent: Option<String>,
+    /// The protocol version of the client's frames, known once it sent one.
+    pub protocol_version: Option<u32>,
+    pub connected_at: DateTime<Utc>,
+    /// The version of each topic's rules the client accepted.
+    pub accepted_terms: HashMap<String, u32>,
+    /// Ends the client's connection when the server drops it, e.g. on a kick.
+    pub closer: CloseHandle,
+    /// The role its token assigned, if any.
+    pub role: Option<Role>,
+}
+
+/// What is known about a connected client, as `GET /admin/clients` lists it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientInfo {
+    pub id: Uuid,
+    pub topic: Option<String>,
+    pub session_id: Option<Uuid>,
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    pub protocol_version: Option<u32>,
+    pub role: Option<Role>,
+    pub connected_at: DateTime<Utc>,
+    /// Messages waiting to be sent to the client.
+    pub queued: usize,
+}
+
+impl From<&Client> for ClientInfo {
+    fn from(client: &Client) -> Self {
+        Self {
+            id: client.id,
+            topic: client.topic.clone(),
+            session_id: client.session_id,
+            ip: client.ip,
+            user_agent: client.user_agent.clone(),
+            protocol_version: client.protocol_version,
+            role: client.role,
+            connected_at: client.connected_at,
+            queued: client.sender.len(),
+        }
+    }
+}
+
+/// Lets the server end a connection from outside the task that owns it.
+#[derive(Clone, Debug, Default)]
+pub struct CloseHandle {
+    inner: Arc<CloseState>,
+}
+
+#[derive(Debug, Default)]
+struct CloseState {
+    /// Why the connection is closed; only the first reason given is kept.
+    code: OnceLock<CloseCode>,
+    notify: Notify,
+}
+
+impl CloseHandle {
+    pub fn close(&self, code: CloseCode) {
+        let _ = self.inner.code.set(code);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.code.get().is_some()
+    }
+
+    /// The close code to send the client, once `close` has been called.
+    pub fn code(&self) -> Option<CloseCode> {
+        self.inner.code.get().copied()
+    }
+
+    /// Completes once `close` has been called.
+    pub async fn closed(&self) {
+        loop {
+            let notified = self.inner.notify.notified();
+            if self.is_closed() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// The state of a disconnected client that can be resumed on reconnect.
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub session_id: Uuid,
+    pub client_id: Uuid,
+    pub topic: Option<String>,
+    /// Messages that were sent but never acknowledged by the client.
+    pub pending_acks: Vec<Arc<Outgoing>>,
+    pub accepted_terms: HashMap<String, u32>,
+    pub detached_at: Instant,
 }
 
 /// A trait defining the contract for storing client and topic information.
 /// This allows for different storage backends (e.g., in-memory, Redis).
 #[async_trait]
 pub trait Storage: Send + Sync {
+    /// Stores a client, replacing any record with its ID. The client is then a member of
+    /// the topic its record names, and of no other.
     fn add_client(&self, client: Client);
     fn remove_client(&self, client_id: &Uuid) -> Option<Client>;
     fn get_client(&self, client_id: &Uuid) -> Option<Client>;
     fn get_all_clients(&self) -> Vec<Client>;
+    fn client_count(&self) -> usize;
     fn subscribe_client_to_topic(&self, client_id: &Uuid, topic: String);
     fn get_clients_in_topic(&self, topic: &str) -> Vec<Client>;
+    /// The queues of a topic's subscribers, one list shared by every broadcast to the
+    /// topic until its subscribers change.
+    fn topic_recipients(&self, topic: &str) -> Arc<[Recipient]>;
     fn get_all_topics(&self) -> Vec<String>;
+    fn add_pending_ack(&self, client_id: &Uuid, message: Arc<Outgoing>);
+    /// Like `add_pending_ack` for each of `client_ids`, e.g. the recipients of a broadcast,
+    /// which a backend writing to disk may record at once.
+    fn add_pending_acks(&self, client_ids: &[Uuid], message: Arc<Outgoing>) {
+        for client_id in client_ids {
+            self.add_pending_ack(client_id, message.clone());
+        }
+    }
+    fn remove_pending_ack(&self, client_id: &Uuid, msg_id: &Uuid) -> bool;
+    fn take_pending_acks(&self, client_id: &Uuid) -> Vec<Arc<Outgoing>>;
+    /// The number of unacknowledged messages across all connected clients.
+    fn pending_ack_count(&self) -> usize;
+    fn save_session(&self, session: Session);
+    fn take_session(&self, session_id: &Uuid) -> Option<Session>;
+    /// Hands out the next sequence number of a topic's messages, starting at 1.
+    fn next_topic_seq(&self, topic: &str) -> u64;
+    /// The last sequence number handed out for every topic that has had a message.
+    fn topic_seqs(&self) -> Vec<(String, u64)>;
+    /// Carries a topic's sequence numbers on from `seq`, as after a restart, unless the
+    /// topic is already past it.
+    fn restore_topic_seq(&self, topic: &str, seq: u64);
+    /// Keeps `message` as the one a topic sends to new subscribers, replacing the previous
+    /// one, or forgets the topic's retained message when `message` is `None`.
+    fn set_retained(&self, topic: &str, message: Option<ServerMessage>);
+    fn get_retained(&self, topic: &str) -> Option<ServerMessage>;
+}
+
+/// The subscribers of a topic, in the order they subscribed until one leaves.
+#[derive(Default)]
+struct TopicMembers {
+    /// Indexed, so leaving a large topic does not scan its members.
+    ids: IndexSet<Uuid>,
+    /// Built by the first broadcast after the subscribers changed.
+    recipients: Option<Arc<[Recipient]>>,
+    /// Counts changes to the subscribers, so a list built from an older set is not kept.
+    generation: u64,
+}
+
+impl TopicMembers {
+    /// Forgets the recipients built for the previous subscribers.
+    fn changed(&mut self) {
+        self.recipients = None;
+        self.generation += 1;
+    }
 }
 
 /// An in-memory storage implementation using DashMap for concurrent access.
 pub struct InMemoryStorage {
     clients: DashMap<Uuid, Client>,
-    topics: DashMap<String, Vec<Uuid>>,
+    topics: DashMap<String, TopicMembers>,
+    pending_acks: DashMap<Uuid, VecDeque<Arc<Outgoing>>>,
+    sessions: DashMap<Uuid, Session>,
+    /// The last sequence number handed out per topic.
+    topic_seqs: DashMap<String, u64>,
+    retained: DashMap<String, ServerMessage>,
 }
 
 impl InMemoryStorage {
@@ -36,8 +207,37 @@ impl InMemoryStorage {
         Self {
             clients: DashMap::new(),
             topics: DashMap::new(),
+            pending_acks: DashMap::new(),
+            sessions: DashMap::new(),
+            topic_seqs: DashMap::new(),
+            retained: DashMap::new(),
         }
     }
+
+    /// Adds a client to a topic's members.
+    fn join_topic(&self, topic: String, client_id: &Uuid) {
+        let mut members = self.topics.entry(topic).or_default();
+        members.ids.insert(*client_id);
+        members.changed();
+    }
+
+    /// The number of unacknowledged messages kept for a client.
+    #[cfg(feature = "sqlite")]
+    pub(crate) fn pending_ack_len(&self, client_id: &Uuid) -> usize {
+        self.pending_acks
+            .get(client_id)
+            .map_or(0, |pending| pending.len())
+    }
+
+    /// Takes a client out of a topic, forgetting the topic once nobody is left in it.
+    fn leave_topic(&self, topic: &str, client_id: &Uuid) {
+        if let Some(mut members) = self.topics.get_mut(topic) {
+            members.ids.swap_remove(client_id);
+            members.changed();
+        }
+        self.topics
+            .remove_if(topic, |_, members| members.ids.is_empty());
+    }
 }
 
 impl Default for InMemoryStorage {
@@ -49,16 +249,33 @@ impl Default for InMemoryStorage {
 #[async_trait]
 impl Storage for InMemoryStorage {
     fn add_client(&self, client: Client) {
-        self.clients.insert(client.id, client);
+        debug!(client_id = %client.id, "client stored");
+        let (client_id, topic) = (client.id, client.topic.clone());
+        let old_topic = self
+            .clients
+            .insert(client_id, client)
+            .and_then(|old| old.topic);
+        if topic != old_topic {
+            // A record saved from an outdated copy names another topic; membership
+            // follows the record.
+            if let Some(old_topic) = &old_topic {
+                self.leave_topic(old_topic, &client_id);
+            }
+            if let Some(topic) = topic {
+                self.join_topic(topic, &client_id);
+            }
+        } else if let Some(mut members) = topic.and_then(|topic| self.topics.get_mut(&topic)) {
+            // The client's queue or session may have changed.
+            members.changed();
+        }
     }
 
     fn remove_client(&self, client_id: &Uuid) -> Option<Client> {
         if let Some((_, client)) = self.clients.remove(client_id) {
             if let Some(topic_name) = &client.topic {
-                if let Some(mut topic_clients) = self.topics.get_mut(topic_name) {
-                    topic_clients.retain(|id| id != client_id);
-                }
+                self.leave_topic(topic_name, client_id);
             }
+            debug!(%client_id, "client removed");
             Some(client)
         } else {
             None
@@ -73,37 +290,137 @@ impl Storage for InMemoryStorage {
         self.clients.iter().map(|c| c.value().clone()).collect()
     }
 
+    fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
     fn subscribe_client_to_topic(&self, client_id: &Uuid, topic: String) {
         if let Some(mut client) = self.clients.get_mut(client_id) {
             // Remove from old topic if it exists
             if let Some(old_topic) = client.topic.take() {
-                if let Some(mut clients) = self.topics.get_mut(&old_topic) {
-                    clients.retain(|id| id != client_id);
-                }
+                self.leave_topic(&old_topic, client_id);
             }
             // Add to new topic
+            debug!(%client_id, %topic, "client subscribed");
             client.topic = Some(topic.clone());
-            self.topics.entry(topic).or_default().push(*client_id);
+            self.join_topic(topic, client_id);
         }
     }
 
     fn get_clients_in_topic(&self, topic: &str) -> Vec<Client> {
+        // Subscribing locks a client before its topics, so the topic's guard is dropped
+        // before any client is looked up.
+        let ids: Vec<Uuid> = match self.topics.get(topic) {
+            Some(members) => members.ids.iter().copied().collect(),
+            None => return Vec::new(),
+        };
+        ids.iter()
+            .filter_map(|id| self.clients.get(id).map(|c| c.value().clone()))
+            .collect()
+    }
+
+    fn topic_recipients(&self, topic: &str) -> Arc<[Recipient]> {
+        let (ids, generation): (Vec<Uuid>, u64) = match self.topics.get(topic) {
+            Some(members) => match &members.recipients {
+                Some(recipients) => return recipients.clone(),
+                None => (members.ids.iter().copied().collect(), members.generation),
+            },
+            None => return Arc::new([]),
+        };
+        // As in `get_clients_in_topic`, clients are looked up without holding the topic.
+        let recipients: Arc<[Recipient]> = ids
+            .iter()
+            .filter_map(|id| self.clients.get(id).map(|c| Recipient::from(c.value())))
+            .collect();
+        if let Some(mut members) = self.topics.get_mut(topic) {
+            if members.generation == generation {
+                members.recipients = Some(recipients.clone());
+            }
+        }
+        recipients
+    }
+
+    fn get_all_topics(&self) -> Vec<String> {
+        // Topics are forgotten once their last subscriber leaves.
         self.topics
-            .get(topic)
-            .map(|client_ids| {
-                client_ids
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    fn add_pending_ack(&self, client_id: &Uuid, message: Arc<Outgoing>) {
+        let mut pending = self.pending_acks.entry(*client_id).or_default();
+        pending.push_back(message);
+        if pending.len() > MAX_PENDING_ACKS {
+            pending.pop_front();
+        }
+    }
+
+    fn remove_pending_ack(&self, client_id: &Uuid, msg_id: &Uuid) -> bool {
+        self.pending_acks
+            .get_mut(client_id)
+            .and_then(|mut pending| {
+                let index = pending
                     .iter()
-                    .filter_map(|id| self.clients.get(id).map(|c| c.value().clone()))
-                    .collect()
+                    .position(|m| m.message.id() == Some(*msg_id))?;
+                pending.remove(index)
             })
+            .is_some()
+    }
+
+    fn take_pending_acks(&self, client_id: &Uuid) -> Vec<Arc<Outgoing>> {
+        self.pending_acks
+            .remove(client_id)
+            .map(|(_, pending)| pending.into())
             .unwrap_or_default()
     }
 
-    fn get_all_topics(&self) -> Vec<String> {
-        self.topics
+    fn pending_ack_count(&self) -> usize {
+        self.pending_acks
             .iter()
-            .filter(|entry| !entry.value().is_empty())
-            .map(|entry| entry.key().clone())
+            .map(|entry| entry.value().len())
+            .sum()
+    }
+
+    fn save_session(&self, session: Session) {
+        debug!(session_id = %session.session_id, "session saved");
+        self.sessions.insert(session.session_id, session);
+    }
+
+    fn take_session(&self, session_id: &Uuid) -> Option<Session> {
+        self.sessions.remove(session_id).map(|(_, session)| session)
+    }
+
+    fn next_topic_seq(&self, topic: &str) -> u64 {
+        let mut seq = self.topic_seqs.entry(topic.to_string()).or_default();
+        *seq += 1;
+        *seq
+    }
+
+    fn topic_seqs(&self) -> Vec<(String, u64)> {
+        self.topic_seqs
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
             .collect()
     }
+
+    fn restore_topic_seq(&self, topic: &str, seq: u64) {
+        let mut current = self.topic_seqs.entry(topic.to_string()).or_default();
+        *current = (*current).max(seq);
+    }
+
+    fn set_retained(&self, topic: &str, message: Option<ServerMessage>) {
+        match message {
+            Some(message) => {
+                self.retained.insert(topic.to_string(), message);
+            }
+            None => {
+                self.retained.remove(topic);
+            }
+        }
+    }
+
+    fn get_retained(&self, topic: &str) -> Option<ServerMessage> {
+        self.retained.get(topic).map(|m| m.value().clone())
+    }
 }
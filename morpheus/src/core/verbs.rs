@@ -0,0 +1,121 @@
+use crate::core::client_manager::ClientManager;
+use async_trait::async_trait;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+use uuid::Uuid;
+
+/// The prefix every custom message type must start with, so that custom verbs never
+/// collide with message types a later protocol version adds.
+pub const CUSTOM_VERB_PREFIX: &str = "X-";
+
+/// The client that sent a custom verb.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientHandle {
+    id: Uuid,
+    topic: Option<String>,
+}
+
+impl ClientHandle {
+    pub fn new(id: Uuid, topic: Option<String>) -> Self {
+        Self { id, topic }
+    }
+
+    /// The client's ID, e.g. to answer it with `ClientManager::send_private_message`.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// The topic the client is subscribed to, if any.
+    pub fn topic(&self) -> Option<&str> {
+        self.topic.as_deref()
+    }
+}
+
+/// Handles an application-specific message type such as `{"type": "X-MyApp", ...}`,
+/// which the server would otherwise reject as unsupported.
+#[async_trait]
+pub trait VerbHandler: Send + Sync {
+    /// Handles one message of the type the handler was registered for. `payload` is
+    /// `Null` when the frame had none. Returning an error sends it back to the client.
+    async fn handle(
+        &self,
+        client: &ClientHandle,
+        payload: Value,
+        client_manager: &Arc<ClientManager>,
+    ) -> Result<(), String>;
+}
+
+/// The custom verbs registered by the program embedding the server, by message type.
+#[derive(Default)]
+pub struct Verbs {
+    handlers: RwLock<HashMap<String, Arc<dyn VerbHandler>>>,
+}
+
+impl Verbs {
+    /// Routes messages of type `verb` to `handler`. The type must start with `X-`, and
+    /// each type can only have one handler.
+    pub fn register(&self, verb: &str, handler: Arc<dyn VerbHandler>) -> Result<(), String> {
+        if !verb.starts_with(CUSTOM_VERB_PREFIX) || verb.len() == CUSTOM_VERB_PREFIX.len() {
+            return Err(format!(
+                "Custom message type '{}' must start with '{}'",
+                verb, CUSTOM_VERB_PREFIX
+            ));
+        }
+        let mut handlers = self.handlers.write().unwrap();
+        if handlers.contains_key(verb) {
+            return Err(format!(
+                "Custom message type '{}' is already registered",
+                verb
+            ));
+        }
+        handlers.insert(verb.to_string(), handler);
+        Ok(())
+    }
+
+    /// The handler for messages of type `verb`, if one is registered.
+    pub fn get(&self, verb: &str) -> Option<Arc<dyn VerbHandler>> {
+        self.handlers.read().unwrap().get(verb).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.handlers.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Noop;
+
+    #[async_trait]
+    impl VerbHandler for Noop {
+        async fn handle(
+            &self,
+            _client: &ClientHandle,
+            _payload: Value,
+            _client_manager: &Arc<ClientManager>,
+        ) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_only_prefixed_verbs_register_once() {
+        let verbs = Verbs::default();
+        assert!(verbs.register("X-MyApp", Arc::new(Noop)).is_ok());
+        assert!(verbs.register("X-MyApp", Arc::new(Noop)).is_err());
+        assert!(verbs.register("Message", Arc::new(Noop)).is_err());
+        assert!(verbs.register("X-", Arc::new(Noop)).is_err());
+        assert_eq!(verbs.len(), 1);
+        assert!(verbs.get("X-MyApp").is_some());
+        assert!(verbs.get("X-Other").is_none());
+    }
+}
@@ -0,0 +1,503 @@
+use crate::core::{
+    history::{HistoryHook, StoredMessage},
+    msg,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use regex::Regex;
+use serde::Deserialize;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+use tracing::{info, warn};
+
+/// How many messages may wait for a slow webhook before new ones are dropped.
+const BACKLOG: usize = 1000;
+/// The delay before the first retry; doubled after every failed attempt.
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+/// An endpoint that topic messages are POSTed to, from a `[[webhooks]]` config section.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Regexes matched against the topic name; every topic is forwarded when empty.
+    #[serde(default)]
+    pub topics: Vec<String>,
+    /// How many times a failed delivery is retried before the message is given up on.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// A body template with `{{field}}` placeholders; the JSON envelope is sent when unset.
+    #[serde(default)]
+    pub template: Option<String>,
+    /// The `content-type` header of the POSTs.
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    /// A file that deliveries are appended to, one JSON object per line, once they
+    /// ran out of retries.
+    #[serde(default)]
+    pub dead_letter: Option<PathBuf>,
+}
+
+fn default_content_type() -> String {
+    "application/json".to_string()
+}
+
+/// One piece of a parsed body template.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    /// A placeholder, and whether its value is inserted raw (`{{{field}}}`) rather than
+    /// escaped for a JSON string (`{{field}}`).
+    Field(Field, bool),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Field {
+    Id,
+    Topic,
+    Seq,
+    Sender,
+    Content,
+    Timestamp,
+    Header(String),
+}
+
+/// A Handlebars-style body template such as `{"text": "{{sender}}: {{content}}"}`.
+///
+/// The placeholders are `id`, `topic`, `seq`, `sender`, `content`, `timestamp` and
+/// `headers.<name>` (empty when the message lacks the header). Values in double braces are
+/// escaped so they can sit inside a JSON string; triple braces insert them unchanged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            let raw = rest[start..].starts_with("{{{");
+            let (open, close) = if raw { (3, "}}}") } else { (2, "}}") };
+            let after = &rest[start + open..];
+            let end = after.find(close).ok_or_else(|| {
+                format!(
+                    "Unclosed placeholder at byte {}",
+                    source.len() - rest.len() + start
+                )
+            })?;
+            let field = match after[..end].trim() {
+                "id" => Field::Id,
+                "topic" => Field::Topic,
+                "seq" => Field::Seq,
+                "sender" => Field::Sender,
+                "content" => Field::Content,
+                "timestamp" => Field::Timestamp,
+                name => match name.strip_prefix("headers.") {
+                    Some(header) if !header.is_empty() => Field::Header(header.to_string()),
+                    _ => return Err(format!("Unknown placeholder '{}'", name)),
+                },
+            };
+            segments.push(Segment::Field(field, raw));
+            rest = &after[end + close.len()..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+        Ok(Self { segments })
+    }
+
+    pub fn render(&self, message: &StoredMessage) -> String {
+        let mut body = String::new();
+        for segment in &self.segments {
+            let (field, raw) = match segment {
+                Segment::Literal(text) => {
+                    body.push_str(text);
+                    continue;
+                }
+                Segment::Field(field, raw) => (field, *raw),
+            };
+            let value = match field {
+                Field::Id => message.id.to_string(),
+                Field::Topic => message.topic.clone(),
+                Field::Seq => message.seq.to_string(),
+                Field::Sender => message.sender.clone(),
+                Field::Content => message.content.clone(),
+                Field::Timestamp => message.timestamp.to_rfc3339(),
+                Field::Header(name) => message.headers.get(name).cloned().unwrap_or_default(),
+            };
+            if raw {
+                body.push_str(&value);
+            } else {
+                // Encoding a string always succeeds; the surrounding quotes are dropped.
+                let quoted = serde_json::to_string(&value).unwrap_or_default();
+                body.push_str(&quoted[1..quoted.len() - 1]);
+            }
+        }
+        body
+    }
+}
+
+/// A message waiting to be POSTed to one webhook.
+struct Delivery {
+    msg_id: String,
+    topic: String,
+    body: String,
+}
+
+/// Where a webhook's deliveries go and what they look like.
+struct Endpoint {
+    url: String,
+    content_type: String,
+    max_retries: u32,
+    dead_letter: Option<PathBuf>,
+}
+
+struct Webhook {
+    url: String,
+    topics: Vec<Regex>,
+    template: Option<Template>,
+    backlog: mpsc::Sender<Delivery>,
+}
+
+impl Webhook {
+    fn matches(&self, topic: &str) -> bool {
+        self.topics.is_empty() || self.topics.iter().any(|filter| filter.is_match(topic))
+    }
+}
+
+/// A history hook that POSTs every stored topic message to the webhooks whose filters
+/// match, as the same JSON envelope clients receive or rendered from the webhook's
+/// template. Each webhook has its own delivery task, so a slow endpoint delays neither
+/// publishers nor the other webhooks. Every attempt is logged under this module's log
+/// target, and deliveries that ran out of retries go to the webhook's dead-letter file.
+pub struct WebhookForwarder {
+    webhooks: Vec<Webhook>,
+}
+
+impl WebhookForwarder {
+    /// Compiles the topic filters and templates and starts a delivery task per webhook.
+    pub fn start(configs: &[WebhookConfig]) -> Result<Self, String> {
+        let mut webhooks = Vec::new();
+        for config in configs {
+            let topics = config
+                .topics
+                .iter()
+                .map(|filter| Regex::new(filter))
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("Invalid topic filter for webhook {}: {}", config.url, e))?;
+            let template = config
+                .template
+                .as_deref()
+                .map(Template::parse)
+                .transpose()
+                .map_err(|e| format!("Invalid template for webhook {}: {}", config.url, e))?;
+            let (backlog, rx) = mpsc::channel(BACKLOG);
+            let endpoint = Endpoint {
+                url: config.url.clone(),
+                content_type: config.content_type.clone(),
+                max_retries: config.max_retries,
+                dead_letter: config.dead_letter.clone(),
+            };
+            tokio::spawn(deliver_all(endpoint, rx));
+            webhooks.push(Webhook {
+                url: config.url.clone(),
+                topics,
+                template,
+                backlog,
+            });
+        }
+        Ok(Self { webhooks })
+    }
+}
+
+#[async_trait]
+impl HistoryHook for WebhookForwarder {
+    async fn on_message_stored(&self, message: &StoredMessage) {
+        let webhooks: Vec<_> = self
+            .webhooks
+            .iter()
+            .filter(|webhook| webhook.matches(&message.topic))
+            .collect();
+        if webhooks.is_empty() {
+            return;
+        }
+        let mut envelope = None;
+        for webhook in webhooks {
+            let body = match &webhook.template {
+                Some(template) => template.render(message),
+                None => match envelope
+                    .get_or_insert_with(|| msg::encode(&message.to_server_message()))
+                {
+                    Ok(body) => body.clone(),
+                    Err(e) => {
+                        warn!(msg_id = %message.id, "failed to encode webhook body: {}", e);
+                        continue;
+                    }
+                },
+            };
+            let delivery = Delivery {
+                msg_id: message.id.to_string(),
+                topic: message.topic.clone(),
+                body,
+            };
+            if webhook.backlog.try_send(delivery).is_err() {
+                warn!(
+                    url = %webhook.url,
+                    msg_id = %message.id,
+                    "webhook backlog full, message dropped"
+                );
+            }
+        }
+    }
+}
+
+/// Delivers a webhook's messages in order, retrying each with exponential backoff and
+/// dead-lettering it once the retries are used up.
+async fn deliver_all(endpoint: Endpoint, mut backlog: mpsc::Receiver<Delivery>) {
+    let url = &endpoint.url;
+    while let Some(delivery) = backlog.recv().await {
+        let msg_id = &delivery.msg_id;
+        let mut delay = RETRY_BASE_DELAY;
+        for attempt in 1..=endpoint.max_retries + 1 {
+            match post(url, &endpoint.content_type, delivery.body.clone()).await {
+                Ok(()) => {
+                    info!(%url, %msg_id, attempt, "webhook delivered");
+                    break;
+                }
+                Err(e) if attempt > endpoint.max_retries => {
+                    warn!(%url, %msg_id, attempt, "webhook delivery failed, giving up: {}", e);
+                    if let Some(path) = &endpoint.dead_letter {
+                        if let Err(e) = dead_letter(path, url, &delivery, &e).await {
+                            warn!(%url, %msg_id, "failed to dead-letter webhook delivery: {}", e);
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!(%url, %msg_id, attempt, "webhook delivery failed, retrying: {}", e);
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+            }
+        }
+    }
+}
+
+/// Appends a failed delivery to a dead-letter file so it can be inspected or replayed.
+async fn dead_letter(
+    path: &Path,
+    url: &str,
+    delivery: &Delivery,
+    error: &str,
+) -> std::io::Result<()> {
+    let record = serde_json::json!({
+        "url": url,
+        "msg_id": delivery.msg_id,
+        "topic": delivery.topic,
+        "body": delivery.body,
+        "error": error,
+        "failed_at": Utc::now(),
+    });
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(format!("{}\n", record).as_bytes()).await
+}
+
+async fn post(url: &str, content_type: &str, body: String) -> Result<(), String> {
+    let request = hyper::Request::post(url)
+        .header("content-type", content_type)
+        .body(hyper::Body::from(body))
+        .map_err(|e| e.to_string())?;
+    let response = hyper::Client::new()
+        .request(request)
+        .await
+        .map_err(|e| e.to_string())?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("server responded with {}", response.status()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        msg::ServerMessage,
+        provenance::{Origin, Provenance},
+    };
+    use chrono::Utc;
+    use std::collections::BTreeMap;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use uuid::Uuid;
+    use warp::{http::StatusCode, Filter};
+
+    fn stored(topic: &str, content: &str) -> StoredMessage {
+        StoredMessage {
+            id: Uuid::new_v4(),
+            topic: topic.to_string(),
+            seq: 1,
+            sender: "Morpheus".to_string(),
+            content: content.to_string(),
+            headers: BTreeMap::new(),
+            reply_to: None,
+            timestamp: Utc::now(),
+            provenance: Provenance::new(Origin::Operator),
+        }
+    }
+
+    #[test]
+    fn test_parse_webhook_config() {
+        let config: WebhookConfig =
+            toml::from_str("url = \"http://localhost/hook\"\ntopics = [\"^ops-\"]").unwrap();
+        assert_eq!(config.topics, vec!["^ops-"]);
+        assert_eq!(config.max_retries, 3);
+    }
+
+    #[test]
+    fn test_template_renders_escaped_and_raw_fields() {
+        let mut message = stored("ops-alerts", "disk \"sda\" full");
+        message
+            .headers
+            .insert("host".to_string(), "db1".to_string());
+        let template = Template::parse(
+            r#"{"text": "[{{topic}}] {{ content }} on {{headers.host}}{{headers.missing}}", "seq": {{{seq}}}}"#,
+        )
+        .unwrap();
+        let body: serde_json::Value = serde_json::from_str(&template.render(&message)).unwrap();
+        assert_eq!(body["text"], r#"[ops-alerts] disk "sda" full on db1"#);
+        assert_eq!(body["seq"], 1);
+    }
+
+    #[test]
+    fn test_template_rejects_unknown_and_unclosed_placeholders() {
+        assert!(Template::parse("{{body}}").is_err());
+        assert!(Template::parse("{{headers.}}").is_err());
+        assert!(Template::parse("{{content").is_err());
+        assert_eq!(
+            Template::parse("no placeholders").unwrap().segments.len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_filter_is_rejected() {
+        let config = WebhookConfig {
+            url: "http://localhost/hook".to_string(),
+            topics: vec!["(".to_string()],
+            max_retries: 0,
+            template: None,
+            content_type: default_content_type(),
+            dead_letter: None,
+        };
+        assert!(WebhookForwarder::start(&[config]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_matching_messages_are_posted_with_retries() {
+        // The endpoint fails every first attempt so each message needs one retry.
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let (bodies_tx, mut bodies) = mpsc::unbounded_channel();
+        let route = warp::post().and(warp::body::bytes()).map({
+            let attempts = attempts.clone();
+            move |body: warp::hyper::body::Bytes| {
+                if attempts.fetch_add(1, Ordering::SeqCst).is_multiple_of(2) {
+                    return StatusCode::INTERNAL_SERVER_ERROR;
+                }
+                bodies_tx.send(body).unwrap();
+                StatusCode::OK
+            }
+        });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let forwarder = WebhookForwarder::start(&[WebhookConfig {
+            url: format!("http://{}/hook", addr),
+            topics: vec!["^ops-".to_string()],
+            max_retries: 1,
+            template: None,
+            content_type: default_content_type(),
+            dead_letter: None,
+        }])
+        .unwrap();
+        forwarder
+            .on_message_stored(&stored("general", "skipped"))
+            .await;
+        let message = stored("ops-alerts", "disk full");
+        forwarder.on_message_stored(&message).await;
+
+        let body = tokio::time::timeout(Duration::from_secs(5), bodies.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        match msg::decode::<ServerMessage>(std::str::from_utf8(&body).unwrap()).unwrap() {
+            ServerMessage::Topic { id, content, .. } => {
+                assert_eq!(id, message.id);
+                assert_eq!(content, "disk full");
+            }
+            other => panic!("unexpected body {:?}", other),
+        }
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_deliveries_are_dead_lettered_with_the_template() {
+        let route = warp::post()
+            .and(warp::header::<String>("content-type"))
+            .map(|content_type: String| {
+                assert_eq!(content_type, "text/plain");
+                StatusCode::SERVICE_UNAVAILABLE
+            });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let path =
+            std::env::temp_dir().join(format!("morpheus-dead-letter-{}.jsonl", Uuid::new_v4()));
+
+        let forwarder = WebhookForwarder::start(&[WebhookConfig {
+            url: format!("http://{}/hook", addr),
+            topics: Vec::new(),
+            max_retries: 0,
+            template: Some("{{sender}}: {{content}}".to_string()),
+            content_type: "text/plain".to_string(),
+            dead_letter: Some(path.clone()),
+        }])
+        .unwrap();
+        let message = stored("general", "hello");
+        forwarder.on_message_stored(&message).await;
+
+        let record = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Ok(contents) = tokio::fs::read_to_string(&path).await {
+                    if contents.ends_with('\n') {
+                        return contents;
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let _ = std::fs::remove_file(&path);
+        let record: serde_json::Value = serde_json::from_str(record.trim()).unwrap();
+        assert_eq!(record["msg_id"], message.id.to_string());
+        assert_eq!(record["topic"], "general");
+        assert_eq!(record["body"], "Morpheus: hello");
+        assert!(record["error"].as_str().unwrap().contains("503"));
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{
+    cli::ui,
+    core::{client_manager::ClientManager, msg::ServerMessage, policy::Delivery, storage::Client},
+};
+use dashmap::DashMap;
+use rand::Rng;
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// How long a queue topic's subscriber has to acknowledge a message before it is given
+/// to another subscriber.
+pub const REDELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+/// How many times a queue message is sent before it is dropped.
+pub const MAX_DELIVERY_ATTEMPTS: usize = 5;
+/// How often unacknowledged queue messages are checked.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A queue message handed to one subscriber and waiting for its acknowledgment.
+#[derive(Clone, Debug)]
+pub(crate) struct InFlight {
+    pub topic: String,
+    pub message: ServerMessage,
+    /// The publisher, who never receives its own message.
+    pub exclude: Option<Uuid>,
+    pub assignee: Uuid,
+    /// Every subscriber the message was offered to so far.
+    pub tried: HashSet<Uuid>,
+    /// How many times the message was sent, including redeliveries to the same client.
+    pub attempts: usize,
+    pub sent_at: Instant,
+}
+
+/// Picks the subscriber of a queue topic that gets each message and tracks the
+/// messages that have not been acknowledged yet.
+#[derive(Debug, Default)]
+pub(crate) struct WorkQueue {
+    in_flight: DashMap<Uuid, InFlight>,
+    /// The next round-robin position per topic.
+    cursors: DashMap<String, usize>,
+}
+
+impl WorkQueue {
+    /// Chooses one of `candidates` according to `delivery`.
+    pub fn pick<'a>(
+        &self,
+        topic: &str,
+        delivery: Delivery,
+        candidates: &'a [Client],
+    ) -> Option<&'a Client> {
+        if candidates.is_empty() {
+            return None;
+        }
+        let index = match delivery {
+            Delivery::Broadcast | Delivery::RoundRobin => {
+                let mut cursor = self.cursors.entry(topic.to_string()).or_default();
+                let index = *cursor % candidates.len();
+                *cursor = cursor.wrapping_add(1);
+                index
+            }
+            Delivery::Random => rand::thread_rng().gen_range(0..candidates.len()),
+            Delivery::LeastLoaded => (0..candidates.len())
+                .min_by_key(|&i| {
+                    let client = &candidates[i];
+                    (self.assigned_to(&client.id), client.sender.len())
+                })
+                .unwrap_or(0),
+        };
+        candidates.get(index)
+    }
+
+    pub fn track(&self, msg_id: Uuid, in_flight: InFlight) {
+        self.in_flight.insert(msg_id, in_flight);
+    }
+
+    /// Stops tracking a message once a subscriber it was offered to acknowledges it.
+    pub fn acknowledge(&self, msg_id: &Uuid, client_id: Uuid) {
+        self.in_flight
+            .remove_if(msg_id, |_, in_flight| in_flight.tried.contains(&client_id));
+    }
+
+    /// Takes the messages whose assignee did not acknowledge them within `timeout` or is
+    /// no longer connected according to `connected`.
+    pub fn take_expired(
+        &self,
+        timeout: Duration,
+        connected: impl Fn(&Uuid) -> bool,
+    ) -> Vec<(Uuid, InFlight)> {
+        let expired: Vec<Uuid> = self
+            .in_flight
+            .iter()
+            .filter(|entry| entry.sent_at.elapsed() >= timeout || !connected(&entry.assignee))
+            .map(|entry| *entry.key())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|msg_id| self.in_flight.remove(&msg_id))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    fn assigned_to(&self, client_id: &Uuid) -> usize {
+        self.in_flight
+            .iter()
+            .filter(|entry| entry.assignee == *client_id)
+            .count()
+    }
+}
+
+/// Gives unacknowledged queue messages to other subscribers every second.
+pub fn spawn_redelivery(client_manager: Arc<ClientManager>, timeout: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            tick.tick().await;
+            let dropped = client_manager.redeliver_expired(timeout).await;
+            if dropped > 0 {
+                ui::print_warning(&format!(
+                    "Dropped {} queue message(s) that no subscriber acknowledged.",
+                    dropped
+                ));
+            }
+        }
+    })
+}
@@ -0,0 +1,433 @@
+use crate::{
+    config::Config,
+    core::{
+        audit::AuditLog,
+        bans::{BanList, BanTarget},
+        identities::ConnectionHistory,
+    },
+};
+use std::{
+    fmt,
+    net::{SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+/// What replaces a forgotten identity in the records that are kept.
+pub const REDACTED: &str = "[forgotten]";
+
+/// What was erased from the SQLite database the config names.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DatabaseErasure {
+    /// How many sessions of the identity were deleted.
+    pub sessions: usize,
+    /// How many of its unacknowledged messages were deleted.
+    pub pending_acks: usize,
+    /// How many messages in history it was redacted from as the sender.
+    pub messages: usize,
+}
+
+/// What `morpheus forget` erased, printed as the erasure report.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ErasureReport {
+    pub identity: Uuid,
+    /// The session count of the removed connection record, if there was one.
+    pub sessions: Option<u64>,
+    /// How many audit entries named the identity. They are kept with the identity
+    /// redacted, so the operator actions still count.
+    pub audit_entries: usize,
+    /// How many entries were removed from each log file that mentioned the identity.
+    pub log_entries: Vec<(PathBuf, usize)>,
+    /// What was erased from the database, if one is configured. Without one, history
+    /// is held in memory only and was cleared when the server stopped.
+    pub database: Option<DatabaseErasure>,
+    /// Whether the identity is on the ban list, which is left as it is.
+    pub banned: bool,
+    pub errors: Vec<String>,
+}
+
+impl ErasureReport {
+    fn new(identity: Uuid) -> Self {
+        Self {
+            identity,
+            sessions: None,
+            audit_entries: 0,
+            log_entries: Vec::new(),
+            database: None,
+            banned: false,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// A summary for the audit log that does not name the identity.
+    fn summary(&self) -> String {
+        let mut summary = format!(
+            "Removed {} connection record(s) and {} log entr(ies), redacted {} audit entr(ies)",
+            usize::from(self.sessions.is_some()),
+            self.log_entries.iter().map(|(_, n)| n).sum::<usize>(),
+            self.audit_entries
+        );
+        if let Some(database) = &self.database {
+            summary.push_str(&format!(
+                "; removed {} session(s) and {} unacknowledged message(s) from the database, \
+                 redacted {} message(s) in its history",
+                database.sessions, database.pending_acks, database.messages
+            ));
+        }
+        summary
+    }
+}
+
+impl fmt::Display for ErasureReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Erasure report for identity {}:", self.identity)?;
+        match self.sessions {
+            Some(sessions) => writeln!(
+                f,
+                "- Connection history: removed the record of {} session(s)",
+                sessions
+            )?,
+            None => writeln!(f, "- Connection history: no record")?,
+        }
+        writeln!(
+            f,
+            "- Audit log: redacted {} entr(ies) naming the identity",
+            self.audit_entries
+        )?;
+        if self.log_entries.is_empty() {
+            writeln!(f, "- Logs: no entries")?;
+        }
+        for (path, count) in &self.log_entries {
+            writeln!(
+                f,
+                "- Logs: removed {} entr(ies) from {}",
+                count,
+                path.display()
+            )?;
+        }
+        match &self.database {
+            Some(database) => {
+                writeln!(
+                    f,
+                    "- Database: removed {} session(s) and {} unacknowledged message(s)",
+                    database.sessions, database.pending_acks
+                )?;
+                writeln!(
+                    f,
+                    "- Topic history: redacted the sender of {} message(s)",
+                    database.messages
+                )?;
+            }
+            None => writeln!(
+                f,
+                "- Topic history: held in memory only, cleared when the server stopped"
+            )?,
+        }
+        if self.banned {
+            writeln!(
+                f,
+                "- Bans: the identity is banned; the ban is kept until lifted with /unban"
+            )?;
+        }
+        for error in &self.errors {
+            writeln!(f, "- ERROR: {}", error)?;
+        }
+        if self.succeeded() {
+            write!(f, "Erasure complete.")
+        } else {
+            write!(
+                f,
+                "Erasure incomplete, run it again once the errors are fixed."
+            )
+        }
+    }
+}
+
+/// Erases what the server persisted about a durable identity: its connection record,
+/// every log entry mentioning it, including rotated files, its name in the audit log
+/// and, with a database configured, its sessions and its name in history. The server must be stopped, since it would write its copy of the connection
+/// history back; nothing is changed if it still holds the port.
+pub fn run(config: &Config, identity: Uuid) -> ErasureReport {
+    let mut report = ErasureReport::new(identity);
+    let addr = SocketAddr::new(config.address, config.port);
+    if let Err(e) = TcpListener::bind(addr) {
+        report.errors.push(format!(
+            "Cannot bind {} ({}); stop the server before erasing its data",
+            addr, e
+        ));
+        return report;
+    }
+    let needle = identity.to_string();
+
+    match ConnectionHistory::load(&config.connection_history)
+        .and_then(|history| history.forget(&identity))
+    {
+        Ok(record) => report.sessions = record.map(|record| record.sessions),
+        Err(e) => report.errors.push(e),
+    }
+    match AuditLog::redact(&config.audit_log, &needle, REDACTED) {
+        Ok(count) => report.audit_entries = count,
+        Err(e) => report.errors.push(e),
+    }
+    match log_files(&config.log.directory) {
+        Ok(files) => {
+            for path in files {
+                match erase_log_entries(&path, &needle) {
+                    Ok(0) => {}
+                    Ok(count) => report.log_entries.push((path, count)),
+                    Err(e) => report.errors.push(e),
+                }
+            }
+        }
+        Err(e) => report.errors.push(e),
+    }
+    if let Some(path) = &config.database {
+        match erase_database(path, &identity) {
+            Ok(database) => report.database = Some(database),
+            Err(e) => report.errors.push(e),
+        }
+    }
+    match BanList::load(&config.ban_list) {
+        Ok(bans) => report.banned = bans.is_banned(&BanTarget::Client(identity)),
+        Err(e) => report.errors.push(e),
+    }
+
+    let result = if report.succeeded() {
+        Ok(report.summary())
+    } else {
+        Err(report.summary())
+    };
+    match AuditLog::open(&config.audit_log) {
+        Ok(audit) => audit.record("forget", REDACTED, result),
+        Err(e) => report.errors.push(e),
+    }
+    report
+}
+
+#[cfg(feature = "sqlite")]
+fn erase_database(path: &Path, identity: &Uuid) -> Result<DatabaseErasure, String> {
+    crate::core::sqlite::Database::open(path)?.forget(identity, REDACTED)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn erase_database(path: &Path, _identity: &Uuid) -> Result<DatabaseErasure, String> {
+    Err(format!(
+        "Cannot erase the identity from {}: the server was built without the sqlite feature",
+        path.display()
+    ))
+}
+
+/// Every file in the log directory: the current and rotated message logs, module logs
+/// and access logs.
+fn log_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to list {}: {}", dir.display(), e)),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("Failed to list {}: {}", dir.display(), e))?
+            .path();
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Whether `line` starts a log entry rather than continuing the previous one, as the
+/// wire log does with the frame it logs: text entries start with their date, JSON
+/// entries are objects with a `timestamp`.
+fn starts_entry(line: &str) -> bool {
+    let date = line.as_bytes().get(..10).is_some_and(|date| {
+        date.iter().enumerate().all(|(i, c)| match i {
+            4 | 7 => *c == b'-',
+            _ => c.is_ascii_digit(),
+        })
+    });
+    date || serde_json::from_str::<serde_json::Value>(line)
+        .is_ok_and(|entry| entry.get("timestamp").is_some())
+}
+
+/// Removes every entry of the log file at `path` that mentions `needle`, together with
+/// its continuation lines. Returns how many entries were removed.
+fn erase_log_entries(path: &Path, needle: &str) -> Result<usize, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut entries: Vec<Vec<&str>> = Vec::new();
+    for line in text.lines() {
+        match entries.last_mut() {
+            Some(entry) if !starts_entry(line) => entry.push(line),
+            _ => entries.push(vec![line]),
+        }
+    }
+    let before = entries.len();
+    entries.retain(|entry| !entry.iter().any(|line| line.contains(needle)));
+    let removed = before - entries.len();
+    if removed > 0 {
+        let mut kept: String = entries.concat().join("\n");
+        if !kept.is_empty() {
+            kept.push('\n');
+        }
+        let temp = path.with_extension("erasing");
+        std::fs::write(&temp, kept)
+            .and_then(|_| std::fs::rename(&temp, path))
+            .map_err(|e| format!("Failed to save {}: {}", path.display(), e))?;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_are_removed_with_their_continuation_lines() {
+        let path = std::env::temp_dir().join(format!("morpheus-forget-{}.log", Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            "2026-10-16T10:00:00.000000Z  INFO morpheus::ws::wire: INCOMING from [abc]:\n\
+             {\"v\":1,\"type\":\"Message\",\"payload\":{\"content\":\"secret\"}}\n\
+             2026-10-16T10:00:01.000000Z  INFO morpheus::ws::wire: INCOMING from [def]:\n\
+             {\"v\":1,\"type\":\"Message\",\"payload\":{\"content\":\"public\"}}\n\
+             {\"level\":\"INFO\",\"message\":\"ACK from [abc]\",\"timestamp\":\"2026-10-16\"}\n",
+        )
+        .unwrap();
+
+        assert_eq!(erase_log_entries(&path, "abc").unwrap(), 2);
+        let kept = std::fs::read_to_string(&path).unwrap();
+        assert!(
+            !kept.contains("secret") && !kept.contains("abc"),
+            "{}",
+            kept
+        );
+        assert_eq!(kept.lines().count(), 2);
+        assert_eq!(erase_log_entries(&path, "abc").unwrap(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_forget_erases_persisted_records() {
+        let dir = std::env::temp_dir().join(format!("morpheus-forget-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("logs")).unwrap();
+        let mut config = Config {
+            port: 0,
+            ban_list: dir.join("bans.json"),
+            connection_history: dir.join("connections.json"),
+            audit_log: dir.join("audit.log"),
+            ..Config::default()
+        };
+        config.log.directory = dir.join("logs");
+        let identity = Uuid::new_v4();
+        ConnectionHistory::load(&config.connection_history)
+            .unwrap()
+            .connected(identity, None)
+            .unwrap();
+        let audit = AuditLog::open(&config.audit_log).unwrap();
+        audit.record("kick", &identity.to_string(), Ok("Kicked".to_string()));
+        drop(audit);
+        std::fs::write(
+            dir.join("logs").join("access.log.2026-10-16"),
+            format!("10.0.0.1 GET /whois/{} 200 0.100ms\n", identity),
+        )
+        .unwrap();
+
+        let report = run(&config, identity);
+        assert!(report.succeeded(), "{}", report);
+        assert_eq!(report.sessions, Some(1));
+        assert_eq!(report.audit_entries, 1);
+        assert_eq!(report.log_entries.len(), 1);
+        let audit = std::fs::read_to_string(&config.audit_log).unwrap();
+        assert!(!audit.contains(&identity.to_string()));
+        // The kick and the erasure itself are both still on record.
+        assert_eq!(audit.lines().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_forget_erases_the_identity_from_the_database() {
+        use crate::core::{
+            history::{HistoryStore, StoredMessage},
+            msg::{Outgoing, Qos, ServerMessage},
+            provenance::{Origin, Provenance},
+            queue::QueueConfig,
+            sqlite::{Database, SqliteHistory, SqliteStorage},
+            storage::Storage,
+        };
+        use std::{collections::BTreeMap, sync::Arc};
+
+        let dir = std::env::temp_dir().join(format!("morpheus-forget-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = Config {
+            port: 0,
+            ban_list: dir.join("bans.json"),
+            connection_history: dir.join("connections.json"),
+            audit_log: dir.join("audit.log"),
+            database: Some(dir.join("morpheus.db")),
+            ..Config::default()
+        };
+        let identity = Uuid::new_v4();
+        let message = StoredMessage {
+            id: Uuid::new_v4(),
+            topic: "news".to_string(),
+            seq: 1,
+            sender: identity.to_string(),
+            content: "hello".to_string(),
+            headers: BTreeMap::new(),
+            reply_to: None,
+            timestamp: chrono::Utc::now(),
+            provenance: Provenance::new(Origin::Client(identity)),
+        };
+        {
+            let db = Database::open(config.database.as_ref().unwrap()).unwrap();
+            let storage = SqliteStorage::new(&db).unwrap();
+            let (client, _receiver) =
+                crate::testing::client_record(identity, QueueConfig::default());
+            storage.add_client(crate::core::storage::Client {
+                session_id: Some(Uuid::new_v4()),
+                ..client
+            });
+            storage.subscribe_client_to_topic(&identity, "news".to_string());
+            let pending = ServerMessage::Topic {
+                id: Uuid::new_v4(),
+                topic: "news".to_string(),
+                seq: 2,
+                sender: "Morpheus".to_string(),
+                content: "unacknowledged".to_string(),
+                headers: BTreeMap::new(),
+                qos: Qos::AtLeastOnce,
+                reply_to: None,
+                retained: false,
+            };
+            storage.add_pending_ack(&identity, Arc::new(Outgoing::new(pending)));
+            SqliteHistory::new(&db, 10).append(message.clone());
+        }
+
+        let report = run(&config, identity);
+        assert!(report.succeeded(), "{}", report);
+        assert_eq!(
+            report.database,
+            Some(DatabaseErasure {
+                sessions: 1,
+                pending_acks: 1,
+                messages: 1,
+            })
+        );
+        let db = Database::open(config.database.as_ref().unwrap()).unwrap();
+        let history = SqliteHistory::new(&db, 10);
+        let kept = history.get(&message.id).unwrap();
+        assert_eq!(kept.sender, REDACTED);
+        assert!(!format!("{:?}", kept.provenance).contains(&identity.to_string()));
+        assert_eq!(kept.content, "hello");
+        drop((history, db));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,340 @@
+use crate::{
+    core::{client_manager::ClientManager, cluster::peer_connected, msg::CloseCode},
+    proxy,
+    ws::{
+        admin::{self, ForwardRequest},
+        handler::{authenticated_client_connected, client_connected, refuse, request_token},
+        sse,
+    },
+};
+use std::{collections::HashMap, net::IpAddr, sync::Arc};
+use tracing::info;
+use uuid::Uuid;
+use warp::{
+    http::{HeaderMap, StatusCode},
+    Filter, Rejection, Reply,
+};
+
+/// Every WebSocket and REST endpoint of the server, served by `client_manager`, with the
+/// client WebSocket at `/ws`.
+///
+/// The server binary serves these at the root; an existing warp application can mount
+/// them under its own prefix, middleware and TLS termination instead, e.g.
+/// `warp::path("chat").and(morpheus::http::filters(client_manager))`. The access log is
+/// left to the caller (see [`crate::log::access::access_log`]), and so is the PROXY
+/// protocol (see [`crate::proxy::serve`]).
+pub fn filters(
+    client_manager: Arc<ClientManager>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    filters_with_ws_path(client_manager, &["ws".to_string()])
+}
+
+/// Like [`filters`], with the client WebSocket at the path made of `ws_path`'s segments
+/// instead (see [`crate::config::Config::ws_path_segments`]).
+pub fn filters_with_ws_path(
+    client_manager: Arc<ClientManager>,
+    ws_path: &[String],
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    ws(client_manager.clone(), ws_path)
+        .or(sse(client_manager.clone()))
+        .or(whois(client_manager.clone()))
+        .or(membership(client_manager.clone()))
+        .or(admin_clients(client_manager.clone()))
+        .or(admin_drain(client_manager.clone()))
+        .or(admin_events(client_manager.clone()))
+        .or(admin_forwards(client_manager.clone()))
+        .or(cluster(client_manager))
+}
+
+/// `GET /ws`, or the segments of `path`: the client WebSocket.
+pub fn ws(
+    client_manager: Arc<ClientManager>,
+    path: &[String],
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    path.iter()
+        .fold(warp::any().boxed(), |filter, segment| {
+            filter.and(warp::path(segment.clone())).boxed()
+        })
+        .and(warp::ws())
+        .and(with_client_manager(client_manager.clone()))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::header::optional::<String>("user-agent"))
+        .and(client_ip(client_manager))
+        .map(
+            |ws: warp::ws::Ws,
+             manager: Arc<ClientManager>,
+             query: HashMap<String, String>,
+             authorization: Option<String>,
+             user_agent: Option<String>,
+             ip: Option<IpAddr>| {
+                // Without a token, the client may still authenticate with its first message.
+                let permissions = match request_token(&query, authorization.as_deref()) {
+                    Some(token) => match manager.policies().permissions(Some(token)) {
+                        Ok(permissions) => Some(permissions),
+                        Err(reason) => {
+                            info!(?ip, "Refused a connection: {}", reason);
+                            // Browsers cannot read the status of a failed handshake, so
+                            // the refusal is sent as a close code instead.
+                            return ws
+                                .on_upgrade(|socket| refuse(socket, CloseCode::AuthFailed))
+                                .into_response();
+                        }
+                    },
+                    None => None,
+                };
+                // A load balancer sends the client to another node when it retries.
+                if manager.is_draining() {
+                    return ws
+                        .on_upgrade(|socket| refuse(socket, CloseCode::Draining))
+                        .into_response();
+                }
+                let ceiling = manager.policies().limits.frame_ceiling();
+                ws.max_message_size(ceiling)
+                    .max_frame_size(ceiling)
+                    .on_upgrade(move |socket| async move {
+                        match permissions {
+                            Some(permissions) => {
+                                authenticated_client_connected(
+                                    socket,
+                                    manager,
+                                    ip,
+                                    user_agent,
+                                    permissions,
+                                )
+                                .await
+                            }
+                            None => client_connected(socket, manager, ip, user_agent).await,
+                        }
+                    })
+                    .into_response()
+            },
+        )
+}
+
+/// `GET /sse/<topic>`: a topic as Server-Sent Events.
+pub fn sse(
+    client_manager: Arc<ClientManager>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("sse" / String)
+        .and(warp::get())
+        .and(with_client_manager(client_manager.clone()))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(client_ip(client_manager))
+        .map(
+            |topic: String,
+             manager: Arc<ClientManager>,
+             query: HashMap<String, String>,
+             authorization: Option<String>,
+             ip: Option<IpAddr>| {
+                let token = request_token(&query, authorization.as_deref());
+                match manager.policies().permissions(token) {
+                    Ok(permissions) if permissions.may_subscribe(&topic) => {}
+                    Ok(_) => return StatusCode::FORBIDDEN.into_response(),
+                    Err(_) => return StatusCode::UNAUTHORIZED.into_response(),
+                }
+                sse::subscribe(manager, topic, ip)
+            },
+        )
+}
+
+/// `GET /whois/<identity>`: the connection history of a durable identity.
+pub fn whois(
+    client_manager: Arc<ClientManager>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("whois" / Uuid)
+        .and(warp::get())
+        .and(with_client_manager(client_manager))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("authorization"))
+        .map(
+            |identity: Uuid,
+             manager: Arc<ClientManager>,
+             query: HashMap<String, String>,
+             authorization: Option<String>| {
+                let token = request_token(&query, authorization.as_deref());
+                if !manager.policies().authorizes_admin(token) {
+                    return StatusCode::UNAUTHORIZED.into_response();
+                }
+                match manager.whois(&identity) {
+                    Some(whois) => warp::reply::json(&whois).into_response(),
+                    None => StatusCode::NOT_FOUND.into_response(),
+                }
+            },
+        )
+}
+
+/// `GET /admin/clients`: the connected clients with their connection metadata.
+pub fn admin_clients(
+    client_manager: Arc<ClientManager>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("admin" / "clients")
+        .and(warp::get())
+        .and(with_client_manager(client_manager))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("authorization"))
+        .map(
+            |manager: Arc<ClientManager>,
+             query: HashMap<String, String>,
+             authorization: Option<String>| {
+                let token = request_token(&query, authorization.as_deref());
+                if !manager.policies().authorizes_admin(token) {
+                    return StatusCode::UNAUTHORIZED.into_response();
+                }
+                admin::clients(manager)
+            },
+        )
+}
+
+/// `GET /admin/drain`: whether the server is draining and how many clients are left.
+/// `POST /admin/drain`: starts draining, like `/drain` on the console.
+pub fn admin_drain(
+    client_manager: Arc<ClientManager>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("admin" / "drain")
+        .and(
+            warp::get()
+                .map(|| false)
+                .or(warp::post().map(|| true))
+                .unify(),
+        )
+        .and(with_client_manager(client_manager))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("authorization"))
+        .map(
+            |start: bool,
+             manager: Arc<ClientManager>,
+             query: HashMap<String, String>,
+             authorization: Option<String>| {
+                let token = request_token(&query, authorization.as_deref());
+                if !manager.policies().authorizes_admin(token) {
+                    return StatusCode::UNAUTHORIZED.into_response();
+                }
+                admin::drain(manager, start)
+            },
+        )
+}
+
+/// How large a forwarding rule sent to `POST /admin/forwards` may be.
+const MAX_FORWARD_BODY_BYTES: u64 = 16 * 1024;
+
+/// `GET /admin/forwards`: the forwarding rules.
+/// `POST /admin/forwards`: adds a rule, like `/forward` on the console.
+/// `DELETE /admin/forwards/{id}`: removes a rule.
+pub fn admin_forwards(
+    client_manager: Arc<ClientManager>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let list = warp::path!("admin" / "forwards")
+        .and(warp::get())
+        .map(|| ForwardRequest::List);
+    let add = warp::path!("admin" / "forwards")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_FORWARD_BODY_BYTES))
+        .and(warp::body::bytes())
+        .map(ForwardRequest::Add);
+    let remove = warp::path!("admin" / "forwards" / Uuid)
+        .and(warp::delete())
+        .map(ForwardRequest::Remove);
+    list.or(add)
+        .unify()
+        .or(remove)
+        .unify()
+        .and(with_client_manager(client_manager))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("authorization"))
+        .map(
+            |request: ForwardRequest,
+             manager: Arc<ClientManager>,
+             query: HashMap<String, String>,
+             authorization: Option<String>| {
+                let token = request_token(&query, authorization.as_deref());
+                if !manager.policies().authorizes_admin(token) {
+                    return StatusCode::UNAUTHORIZED.into_response();
+                }
+                admin::forwards(manager, request)
+            },
+        )
+}
+
+/// `GET /admin/membership`: a snapshot of the topics and their subscribers.
+pub fn membership(
+    client_manager: Arc<ClientManager>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("admin" / "membership")
+        .and(warp::get())
+        .and(with_client_manager(client_manager))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::header::optional::<String>("if-none-match"))
+        .map(
+            |manager: Arc<ClientManager>,
+             query: HashMap<String, String>,
+             authorization: Option<String>,
+             if_none_match: Option<String>| {
+                let token = request_token(&query, authorization.as_deref());
+                if !manager.policies().authorizes_admin(token) {
+                    return StatusCode::UNAUTHORIZED.into_response();
+                }
+                admin::membership_snapshot(manager, if_none_match)
+            },
+        )
+}
+
+/// `GET /admin/events`: membership changes as Server-Sent Events.
+pub fn admin_events(
+    client_manager: Arc<ClientManager>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("admin" / "events")
+        .and(warp::get())
+        .and(with_client_manager(client_manager))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::header::optional::<u64>("last-event-id"))
+        .map(
+            |manager: Arc<ClientManager>,
+             query: HashMap<String, String>,
+             authorization: Option<String>,
+             last_event_id: Option<u64>| {
+                let token = request_token(&query, authorization.as_deref());
+                if !manager.policies().authorizes_admin(token) {
+                    return StatusCode::UNAUTHORIZED.into_response();
+                }
+                let since = match query.get("since").map(|since| since.parse()) {
+                    Some(Ok(since)) => Some(since),
+                    Some(Err(_)) => return StatusCode::BAD_REQUEST.into_response(),
+                    None => last_event_id,
+                };
+                admin::membership_events(manager, since)
+            },
+        )
+}
+
+/// `GET /cluster`: the WebSocket cluster peers connect to.
+pub fn cluster(
+    client_manager: Arc<ClientManager>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("cluster")
+        .and(warp::ws())
+        .and(with_client_manager(client_manager))
+        .map(|ws: warp::ws::Ws, manager| {
+            ws.on_upgrade(move |socket| peer_connected(socket, manager))
+        })
+}
+
+/// The address of the client, behind the proxies the policies trust.
+fn client_ip(
+    client_manager: Arc<ClientManager>,
+) -> impl Filter<Extract = (Option<IpAddr>,), Error = std::convert::Infallible> + Clone {
+    warp::addr::remote()
+        .and(warp::header::headers_cloned())
+        .map(move |peer, headers: HeaderMap| {
+            proxy::client_ip(peer, &headers, &client_manager.policies().trusted_proxies)
+        })
+}
+
+fn with_client_manager(
+    client_manager: Arc<ClientManager>,
+) -> impl Filter<Extract = (Arc<ClientManager>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || client_manager.clone())
+}
@@ -1,4 +1,11 @@
+pub mod check;
 pub mod cli;
+pub mod config;
 pub mod core;
+pub mod forget;
+pub mod http;
 pub mod log;
+pub mod proxy;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod ws;
@@ -0,0 +1,68 @@
+use crate::{core::client_manager::ClientManager, proxy};
+use std::{net::IpAddr, sync::Arc, time::Duration};
+use tracing::info;
+
+/// The tracing target used for access log records. Records with this target are
+/// written to the access log file instead of the message log.
+pub const ACCESS_LOG_TARGET: &str = "morpheus::access";
+
+/// The file name prefix of the daily-rotated access log.
+pub const ACCESS_LOG_FILE: &str = "access.log";
+
+/// Returns a warp logging filter that records every HTTP request
+/// (including WebSocket upgrades) to the access log, from the client behind the proxies
+/// `client_manager` trusts.
+pub fn access_log(
+    client_manager: Arc<ClientManager>,
+) -> warp::log::Log<impl Fn(warp::log::Info) + Clone> {
+    warp::log::custom(move |info| {
+        let trusted = &client_manager.policies().trusted_proxies;
+        let line = format_access_line(
+            proxy::client_ip(info.remote_addr(), info.request_headers(), trusted),
+            info.method().as_str(),
+            info.path(),
+            info.status().as_u16(),
+            info.elapsed(),
+        );
+        info!(target: ACCESS_LOG_TARGET, "{}", line);
+    })
+}
+
+/// Formats a single access log line: `<source ip> <method> <path> <status> <latency>`.
+pub fn format_access_line(
+    source: Option<IpAddr>,
+    method: &str,
+    path: &str,
+    status: u16,
+    elapsed: Duration,
+) -> String {
+    let source = source
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "-".to_string());
+    format!(
+        "{} {} {} {} {:.3}ms",
+        source,
+        method,
+        path,
+        status,
+        elapsed.as_secs_f64() * 1000.0
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_access_line() {
+        let ip: IpAddr = "10.0.0.7".parse().unwrap();
+        assert_eq!(
+            format_access_line(Some(ip), "GET", "/ws", 101, Duration::from_micros(1500)),
+            "10.0.0.7 GET /ws 101 1.500ms"
+        );
+        assert_eq!(
+            format_access_line(None, "GET", "/", 404, Duration::ZERO),
+            "- GET / 404 0.000ms"
+        );
+    }
+}
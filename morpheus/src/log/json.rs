@@ -0,0 +1,233 @@
+use chrono::Local;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{fmt, str::FromStr};
+use tracing::{
+    field::{Field, Visit},
+    span::Record,
+    Event, Subscriber,
+};
+use tracing_subscriber::{
+    field::RecordFields,
+    fmt::{format::Writer, FmtContext, FormatEvent, FormatFields, FormattedFields},
+    registry::LookupSpan,
+};
+
+/// How log files are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, for log shippers.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "Unknown log format '{}' (expected text or json)",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for LogFormat {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Writes every event as a JSON object with its `timestamp`, `level`, `target`,
+/// `message`, the names of the spans it happened in, if any, and its other fields. The
+/// fields of those spans, such as the `client_id` of a connection, are among the event's
+/// fields when the layer formats span fields with [`JsonSpanFields`].
+pub struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let meta = event.metadata();
+        let mut fields = JsonFields::default();
+        event.record(&mut fields);
+
+        // The event's own fields win over its spans', and inner spans over outer ones.
+        let mut all_fields = Map::new();
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                let span_fields = extensions
+                    .get::<FormattedFields<N>>()
+                    .and_then(|formatted| serde_json::from_str::<Map<_, _>>(formatted).ok());
+                all_fields.extend(span_fields.unwrap_or_default());
+            }
+        }
+        all_fields.extend(fields.fields);
+
+        let mut line = Map::new();
+        line.insert("timestamp".into(), Local::now().to_rfc3339().into());
+        line.insert("level".into(), meta.level().as_str().into());
+        line.insert("target".into(), meta.target().into());
+        if let Some(message) = fields.message {
+            line.insert("message".into(), message.into());
+        }
+        if let Some(scope) = ctx.event_scope() {
+            let spans: Vec<Value> = scope.from_root().map(|span| span.name().into()).collect();
+            line.insert("spans".into(), spans.into());
+        }
+        if !all_fields.is_empty() {
+            line.insert("fields".into(), all_fields.into());
+        }
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+/// Formats the fields of spans as a JSON object, for [`JsonFormat`] to add to the events
+/// in them. Fields recorded after a span was created join the object.
+pub struct JsonSpanFields;
+
+impl<'writer> FormatFields<'writer> for JsonSpanFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut visitor = JsonFields::default();
+        fields.record(&mut visitor);
+        write!(writer, "{}", Value::Object(visitor.fields))
+    }
+
+    fn add_fields(
+        &self,
+        current: &'writer mut FormattedFields<Self>,
+        fields: &Record<'_>,
+    ) -> fmt::Result {
+        let mut merged: Map<String, Value> =
+            serde_json::from_str(&current.fields).unwrap_or_default();
+        let mut visitor = JsonFields::default();
+        fields.record(&mut visitor);
+        merged.extend(visitor.fields);
+        current.fields = Value::Object(merged).to_string();
+        Ok(())
+    }
+}
+
+/// Collects an event's fields as JSON values, keeping `message` apart.
+#[derive(Default)]
+struct JsonFields {
+    message: Option<String>,
+    fields: Map<String, Value>,
+}
+
+impl JsonFields {
+    fn insert(&mut self, field: &Field, value: Value) {
+        self.fields.insert(field.name().to_string(), value);
+    }
+}
+
+impl Visit for JsonFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_string());
+        } else {
+            self.insert(field, value.into());
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::tests::Buffer;
+
+    #[test]
+    fn test_events_are_json_lines() {
+        let buffer = Buffer::default();
+        let subscriber = tracing_subscriber::fmt()
+            .event_format(JsonFormat)
+            .with_writer(buffer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("delivery");
+            let _entered = span.enter();
+            tracing::warn!(target: "morpheus::test", client = "c1", attempts = 3, "Slow {}", "client");
+        });
+
+        let output = buffer.contents();
+        let line: Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["target"], "morpheus::test");
+        assert_eq!(line["message"], "Slow client");
+        assert_eq!(line["spans"], serde_json::json!(["delivery"]));
+        assert_eq!(
+            line["fields"],
+            serde_json::json!({"client": "c1", "attempts": 3})
+        );
+        assert!(line["timestamp"].is_string());
+    }
+
+    #[test]
+    fn test_span_fields_are_event_fields() {
+        let buffer = Buffer::default();
+        let subscriber = tracing_subscriber::fmt()
+            .fmt_fields(JsonSpanFields)
+            .event_format(JsonFormat)
+            .with_writer(buffer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!(
+                "connection",
+                client_id = tracing::field::Empty,
+                topic = "lobby"
+            );
+            span.record("client_id", "c1");
+            let _entered = span.enter();
+            tracing::info!(topic = "news", "Subscribed");
+        });
+
+        let output = buffer.contents();
+        let line: Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(line["spans"], serde_json::json!(["connection"]));
+        assert_eq!(
+            line["fields"],
+            serde_json::json!({"client_id": "c1", "topic": "news"})
+        );
+    }
+}
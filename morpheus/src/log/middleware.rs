@@ -1,43 +1,317 @@
-use crate::core::msg::{ClientMessage, ServerMessage};
-use chrono::Local;
-use tracing::info;
-
-pub fn init_file_logger() {
-    let log_dir = "logs";
-    if !std::path::Path::new(log_dir).exists() {
-        std::fs::create_dir(log_dir).expect("Failed to create log directory");
-    }
-    let now = Local::now();
-    let log_filename = format!("morpheus-{}.log", now.format("%Y-%m-%d-%H-%M-%S"));
-    let log_path = std::path::Path::new(log_dir).join(log_filename);
-    let log_file = std::fs::File::create(log_path).expect("Failed to create log file");
-
-    tracing_subscriber::fmt()
-        .with_writer(log_file)
-        .with_ansi(false) // ANSI codes are not useful in a file
+use crate::{
+    config::{LogConfig, LogTarget},
+    core::msg::ClientMessage,
+    log::{
+        access::{ACCESS_LOG_FILE, ACCESS_LOG_TARGET},
+        json::{JsonFormat, JsonSpanFields, LogFormat},
+        recent::{self, RecentLines},
+        rotation::RotatingFile,
+    },
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
+};
+use tracing::{info, level_filters::LevelFilter, trace, Level, Metadata, Subscriber};
+use tracing_appender::rolling::RollingFileAppender;
+use tracing_subscriber::{
+    filter::filter_fn, fmt::MakeWriter, layer::SubscriberExt, registry::LookupSpan,
+    util::SubscriberInitExt, Layer,
+};
+
+/// The message log is written to `<prefix>-<time the file was started>.log`.
+pub const MESSAGE_LOG_PREFIX: &str = "morpheus";
+
+/// The target of the wire logs: every message received from and sent to a client.
+pub const WIRE_LOG_TARGET: &str = "morpheus::ws::wire";
+
+/// The levels and destinations of the log; changeable at runtime.
+static FILTERS: RwLock<Filters> = RwLock::new(Filters {
+    level: LevelFilter::INFO,
+    targets: Vec::new(),
+});
+
+struct Filters {
+    level: LevelFilter,
+    /// Per-module overrides, most specific module first.
+    targets: Vec<(String, LogTarget)>,
+}
+
+impl Filters {
+    /// The level and file (`None` for the message log) that events of `target` go to.
+    fn route(&self, target: &str) -> (LevelFilter, Option<&Path>) {
+        let rule = self.targets.iter().find(|(module, _)| {
+            target
+                .strip_prefix(module.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+        });
+        match rule {
+            Some((_, rule)) => (rule.level.unwrap_or(self.level), rule.file.as_deref()),
+            None => (self.level, None),
+        }
+    }
+}
+
+/// Changes the log levels without restarting, e.g. on a config reload. Files opened at
+/// startup keep receiving their modules' events; a module routed to any other file
+/// falls back to the message log until the server is restarted.
+pub fn set_log_filters(config: &LogConfig) {
+    let mut targets: Vec<_> = config
+        .targets
+        .iter()
+        .map(|(module, target)| (module.clone(), target.clone()))
+        .collect();
+    targets.sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+    *FILTERS.write().unwrap() = Filters {
+        level: config.level,
+        targets,
+    };
+}
+
+/// Changes the level of every module without an override of its own until the next
+/// config reload, e.g. from `/loglevel`.
+pub fn set_log_level(level: LevelFilter) {
+    FILTERS.write().unwrap().level = level;
+}
+
+/// The level of every module without an override of its own.
+pub fn log_level() -> LevelFilter {
+    FILTERS.read().unwrap().level
+}
+
+/// Whether an event passes the levels, wherever it is written to.
+fn enabled(meta: &Metadata) -> bool {
+    if meta.target() == ACCESS_LOG_TARGET {
+        return false;
+    }
+    let (level, _) = FILTERS.read().unwrap().route(meta.target());
+    *meta.level() <= level
+}
+
+/// Whether an event belongs in `file`, given the files that were opened at startup.
+fn routed_to(meta: &Metadata, file: Option<&Path>, opened: &[PathBuf]) -> bool {
+    if meta.target() == ACCESS_LOG_TARGET {
+        return false;
+    }
+    let filters = FILTERS.read().unwrap();
+    let (level, destination) = filters.route(meta.target());
+    let destination = destination.filter(|path| opened.iter().any(|f| f == path));
+    *meta.level() <= level && destination == file
+}
+
+/// Formats events as configured: plain text without ANSI codes, which are not useful in
+/// a file, or JSON lines.
+fn formatted<S, W>(writer: W, format: LogFormat) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(false);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer
+            .fmt_fields(JsonSpanFields)
+            .event_format(JsonFormat)
+            .boxed(),
+    }
+}
+
+fn file_layer<S>(
+    file: RotatingFile,
+    format: LogFormat,
+    route: Option<PathBuf>,
+    opened: Vec<PathBuf>,
+) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    formatted(Mutex::new(file), format).with_filter(filter_fn(move |meta| {
+        routed_to(meta, route.as_deref(), &opened)
+    }))
+}
+
+/// Sets up the message log, a file for every module routed elsewhere, standard output if
+/// enabled, the recent lines the TUI shows while it captures them, and, unless disabled,
+/// the daily-rotated access log. Every log is rotated and pruned according to
+/// the config's rotation policy, except that the access log always rotates daily.
+pub fn init_file_logger(config: &LogConfig) {
+    let log_dir = &config.directory;
+    if !log_dir.exists() {
+        std::fs::create_dir_all(log_dir).expect("Failed to create log directory");
+    }
+    let policy = config.rotation_policy();
+    let log_file = RotatingFile::timestamped(log_dir, MESSAGE_LOG_PREFIX, policy)
+        .expect("Failed to create log file");
+
+    set_log_filters(config);
+    let opened: Vec<PathBuf> = config
+        .target_files()
+        .into_iter()
+        .map(Path::to_path_buf)
+        .collect();
+    let mut file_layers: Vec<_> = opened
+        .iter()
+        .map(|path| {
+            let file =
+                RotatingFile::fixed(log_dir, path, policy).expect("Failed to create log file");
+            file_layer(file, config.format, Some(path.clone()), opened.clone())
+        })
+        .collect();
+    // An empty `Vec` of layers would switch every other layer off as well.
+    file_layers.push(file_layer(log_file, config.format, None, opened));
+    // Standard output mirrors every file but the access log.
+    let stdout_layer = config.stdout.then(|| {
+        let layer = match config.format {
+            LogFormat::Text => tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stdout)
+                .boxed(),
+            LogFormat::Json => formatted(std::io::stdout, LogFormat::Json),
+        };
+        layer.with_filter(filter_fn(enabled))
+    });
+    // Mirrors standard output for the TUI, which hides it.
+    let recent_layer = tracing_subscriber::fmt::layer()
+        .with_writer(RecentLines)
+        .with_ansi(false)
+        .with_filter(filter_fn(|meta| recent::is_capturing() && enabled(meta)));
+    let access_layer = config.access_log.then(|| {
+        let mut access_file = RollingFileAppender::builder()
+            .rotation(tracing_appender::rolling::Rotation::DAILY)
+            .filename_prefix(ACCESS_LOG_FILE);
+        if let Some(max_files) = policy.max_files {
+            access_file = access_file.max_log_files(max_files);
+        }
+        let access_file = access_file
+            .build(log_dir)
+            .expect("Failed to create access log");
+        let layer = match config.format {
+            LogFormat::Text => tracing_subscriber::fmt::layer()
+                .with_writer(access_file)
+                .with_ansi(false)
+                .with_target(false)
+                .with_level(false)
+                .boxed(),
+            LogFormat::Json => formatted(access_file, LogFormat::Json),
+        };
+        layer.with_filter(filter_fn(|meta| meta.target() == ACCESS_LOG_TARGET))
+    });
+
+    tracing_subscriber::registry()
+        .with(file_layers)
+        .with(stdout_layer)
+        .with(recent_layer)
+        .with(access_layer)
         .init();
 }
 
+/// Logs a message as it is received. Frames carry message content, so they are only
+/// logged at TRACE and content does not end up in the log files at the default level.
 pub fn log_incoming(client_id: &uuid::Uuid, msg: &ClientMessage) {
+    // Skip serializing every message when nobody reads the wire log.
+    if !tracing::enabled!(target: WIRE_LOG_TARGET, Level::TRACE) {
+        return;
+    }
     let msg_json = serde_json::to_string(msg).unwrap_or_else(|_| "Failed to serialize".to_string());
-    info!
-        (target: "morpheus::log",
+    trace!
+        (target: WIRE_LOG_TARGET,
         "INCOMING from [{}]:\n{}",
         client_id,
         msg_json
     );
 }
 
-pub fn log_outgoing(msg: &ServerMessage) {
-    let msg_json = serde_json::to_string(msg).unwrap_or_else(|_| "Failed to serialize".to_string());
-    info!(target: "morpheus::log", "OUTGOING:\n{}", msg_json);
+/// Logs a frame as it is sent, at TRACE like incoming frames; it is already encoded, so
+/// this costs nothing extra.
+pub fn log_outgoing(frame: &str) {
+    trace!(target: WIRE_LOG_TARGET, "OUTGOING:\n{}", frame);
 }
 
 pub fn log_ack(client_id: &uuid::Uuid, msg_id: &uuid::Uuid) {
     info!
-        (target: "morpheus::log",
+        (target: WIRE_LOG_TARGET,
         "ACK from [{}]: msg_id={}",
         client_id,
         msg_id
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::tests::Buffer;
+
+    #[test]
+    fn test_wire_log_keeps_content_out_above_trace() {
+        let client_id = uuid::Uuid::new_v4();
+        let msg_id = uuid::Uuid::new_v4();
+        let wire_log = |level| {
+            let buffer = Buffer::default();
+            let subscriber = tracing_subscriber::fmt()
+                .with_max_level(level)
+                .with_writer(buffer.clone())
+                .finish();
+            tracing::subscriber::with_default(subscriber, || {
+                log_incoming(
+                    &client_id,
+                    &ClientMessage::ReplyToMorpheus {
+                        original_msg_id: msg_id,
+                        content: "incoming secret".to_string(),
+                    },
+                );
+                log_outgoing(r#"{"type":"Global","payload":{"content":"outgoing secret"}}"#);
+                log_ack(&client_id, &msg_id);
+            });
+            buffer.contents()
+        };
+
+        let info = wire_log(Level::INFO);
+        assert!(!info.contains("secret"), "{}", info);
+        assert!(info.contains(WIRE_LOG_TARGET) && info.contains(&msg_id.to_string()));
+        let trace = wire_log(Level::TRACE);
+        assert!(
+            trace.contains("incoming secret") && trace.contains("outgoing secret"),
+            "{}",
+            trace
+        );
+    }
+
+    #[test]
+    fn test_most_specific_target_wins() {
+        let target = |level, file: Option<&str>| LogTarget {
+            level,
+            file: file.map(PathBuf::from),
+        };
+        let filters = Filters {
+            level: LevelFilter::INFO,
+            targets: vec![
+                (
+                    "morpheus::ws::wire".to_string(),
+                    target(Some(LevelFilter::DEBUG), Some("wire.log")),
+                ),
+                ("morpheus::ws".to_string(), target(None, Some("ws.log"))),
+                (
+                    "morpheus::core".to_string(),
+                    target(Some(LevelFilter::WARN), None),
+                ),
+            ],
+        };
+
+        assert_eq!(
+            filters.route(WIRE_LOG_TARGET),
+            (LevelFilter::DEBUG, Some(Path::new("wire.log")))
+        );
+        assert_eq!(
+            filters.route("morpheus::ws::handler"),
+            (LevelFilter::INFO, Some(Path::new("ws.log")))
+        );
+        assert_eq!(
+            filters.route("morpheus::core::storage"),
+            (LevelFilter::WARN, None)
+        );
+        // A prefix only matches at a module boundary.
+        assert_eq!(filters.route("morpheus::corex"), (LevelFilter::INFO, None));
+        assert_eq!(filters.route("morpheus"), (LevelFilter::INFO, None));
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// How many lines are kept for the TUI to show.
+pub const RECENT_CAPACITY: usize = 500;
+
+/// Whether log lines and console output are being kept, i.e. whether the TUI is shown.
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+
+/// The most recent log lines and console output, oldest first.
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Starts or stops keeping log lines.
+pub fn capture(enabled: bool) {
+    CAPTURING.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_capturing() -> bool {
+    CAPTURING.load(Ordering::Relaxed)
+}
+
+/// Keeps a line, dropping the oldest beyond [`RECENT_CAPACITY`]. Blank lines are skipped.
+pub fn push(line: &str) {
+    let line = line.trim_end();
+    if line.is_empty() {
+        return;
+    }
+    let mut recent = RECENT.lock().unwrap();
+    if recent.len() == RECENT_CAPACITY {
+        recent.pop_front();
+    }
+    recent.push_back(line.to_string());
+}
+
+/// The last `count` lines, oldest first.
+pub fn last(count: usize) -> Vec<String> {
+    let recent = RECENT.lock().unwrap();
+    recent
+        .iter()
+        .skip(recent.len().saturating_sub(count))
+        .cloned()
+        .collect()
+}
+
+/// The writer of the log layer that keeps events as recent lines.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RecentLines;
+
+impl<'a> MakeWriter<'a> for RecentLines {
+    type Writer = EventWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        EventWriter::default()
+    }
+}
+
+/// Collects a formatted event and keeps its lines once dropped.
+#[derive(Debug, Default)]
+pub struct EventWriter(Vec<u8>);
+
+impl io::Write for EventWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for EventWriter {
+    fn drop(&mut self) {
+        String::from_utf8_lossy(&self.0).lines().for_each(push);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_keeps_the_most_recent_lines() {
+        for i in 0..RECENT_CAPACITY + 2 {
+            push(&format!("line {}", i));
+        }
+        push("  ");
+        let mut writer = RecentLines.make_writer();
+        writer.write_all(b"first\nsecond\n").unwrap();
+        drop(writer);
+
+        assert_eq!(
+            last(3),
+            [
+                format!("line {}", RECENT_CAPACITY + 1),
+                "first".into(),
+                "second".into()
+            ]
+        );
+        assert_eq!(last(usize::MAX).len(), RECENT_CAPACITY);
+    }
+}
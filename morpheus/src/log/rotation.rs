@@ -0,0 +1,326 @@
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// How often a log starts a new file regardless of its size.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Rotation {
+    /// Only the size limit, if any, starts a new file.
+    #[default]
+    Never,
+    Hourly,
+    Daily,
+}
+
+impl Rotation {
+    /// The period `time` falls in; a new file is started when it changes.
+    fn period(&self, time: DateTime<Local>) -> Option<String> {
+        match self {
+            Self::Never => None,
+            Self::Hourly => Some(time.format("%Y-%m-%d-%H").to_string()),
+            Self::Daily => Some(time.format("%Y-%m-%d").to_string()),
+        }
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(Self::Never),
+            "hourly" => Ok(Self::Hourly),
+            "daily" => Ok(Self::Daily),
+            _ => Err(format!(
+                "Unknown log rotation '{}' (expected never, hourly or daily)",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Rotation {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// When a log starts a new file and how many of its files are kept.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RotationPolicy {
+    pub rotation: Rotation,
+    /// Start a new file before one would grow beyond this many bytes.
+    pub max_file_bytes: Option<u64>,
+    /// How many files the log keeps, the one being written included; older ones are
+    /// deleted when a new file is started.
+    pub max_files: Option<usize>,
+}
+
+/// How the files of a log are named.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Naming {
+    /// Every file is named `<prefix>-<time it was started>.log`, like the message log.
+    Timestamped { prefix: String },
+    /// The log is always written to `path`; a full file is renamed to
+    /// `<stem>-<time it was rotated>.<extension>` next to it.
+    Fixed { path: PathBuf },
+}
+
+/// A log file that moves on to a new file according to a `RotationPolicy`.
+pub struct RotatingFile {
+    directory: PathBuf,
+    naming: Naming,
+    policy: RotationPolicy,
+    file: File,
+    /// The path `file` was opened at, which for `Fixed` naming is always the same.
+    path: PathBuf,
+    written: u64,
+    period: Option<String>,
+}
+
+impl RotatingFile {
+    /// A log that starts a new `<prefix>-<time>.log` file in `directory` for every run.
+    pub fn timestamped(directory: &Path, prefix: &str, policy: RotationPolicy) -> io::Result<Self> {
+        let naming = Naming::Timestamped {
+            prefix: prefix.to_string(),
+        };
+        Self::open(directory, naming, policy)
+    }
+
+    /// A log that keeps appending to `directory/file` across runs.
+    pub fn fixed(directory: &Path, file: &Path, policy: RotationPolicy) -> io::Result<Self> {
+        let naming = Naming::Fixed {
+            path: directory.join(file),
+        };
+        Self::open(directory, naming, policy)
+    }
+
+    fn open(directory: &Path, naming: Naming, policy: RotationPolicy) -> io::Result<Self> {
+        let now = Local::now();
+        let path = current_path(&naming, directory, now);
+        let file = append(&path)?;
+        let written = file.metadata()?.len();
+        let log = Self {
+            directory: directory.to_path_buf(),
+            naming,
+            policy,
+            file,
+            path,
+            written,
+            period: policy.rotation.period(now),
+        };
+        log.prune()?;
+        Ok(log)
+    }
+
+    /// Whether writing `len` more bytes at `now` should go to a new file.
+    fn due(&self, len: usize, now: DateTime<Local>) -> bool {
+        let full = self
+            .policy
+            .max_file_bytes
+            .is_some_and(|max| self.written > 0 && self.written + len as u64 > max);
+        full || self.policy.rotation.period(now) != self.period
+    }
+
+    fn rotate(&mut self, now: DateTime<Local>) -> io::Result<()> {
+        self.file.flush()?;
+        if let Naming::Fixed { path } = &self.naming {
+            std::fs::rename(path, archive_path(path, now))?;
+        }
+        self.path = current_path(&self.naming, &self.directory, now);
+        self.file = append(&self.path)?;
+        self.written = self.file.metadata()?.len();
+        self.period = self.policy.rotation.period(now);
+        self.prune()
+    }
+
+    /// Deletes the oldest files of this log beyond `max_files`.
+    fn prune(&self) -> io::Result<()> {
+        let Some(max_files) = self.policy.max_files else {
+            return Ok(());
+        };
+        let mut files = self.files()?;
+        // Oldest first; names carry the time, which breaks ties in modification times.
+        files.sort_by_cached_key(|path| {
+            let modified = path.metadata().and_then(|meta| meta.modified()).ok();
+            (modified, path.clone())
+        });
+        let excess = files.len().saturating_sub(max_files);
+        let old = files.into_iter().filter(|path| *path != self.path);
+        for path in old.take(excess) {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Every file of this log in its directory, the current one included.
+    fn files(&self) -> io::Result<Vec<PathBuf>> {
+        let (prefix, extension) = match &self.naming {
+            Naming::Timestamped { prefix } => (format!("{}-", prefix), "log".to_string()),
+            Naming::Fixed { path } => (format!("{}-", stem(path)), extension(path)),
+        };
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if (name.starts_with(&prefix) && extension_of(name) == extension) || path == self.path {
+                files.push(path);
+            }
+        }
+        Ok(files)
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let now = Local::now();
+        if self.due(buf.len(), now) {
+            self.rotate(now)?;
+        }
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn current_path(naming: &Naming, directory: &Path, now: DateTime<Local>) -> PathBuf {
+    match naming {
+        Naming::Timestamped { prefix } => {
+            directory.join(format!("{}-{}.log", prefix, timestamp(now)))
+        }
+        Naming::Fixed { path } => path.clone(),
+    }
+}
+
+fn archive_path(path: &Path, now: DateTime<Local>) -> PathBuf {
+    let name = match extension(path).as_str() {
+        "" => format!("{}-{}", stem(path), timestamp(now)),
+        extension => format!("{}-{}.{}", stem(path), timestamp(now), extension),
+    };
+    path.with_file_name(name)
+}
+
+/// Sorts in the order files were started; milliseconds keep quickly rotating files apart.
+fn timestamp(time: DateTime<Local>) -> String {
+    time.format("%Y-%m-%d-%H-%M-%S%.3f").to_string()
+}
+
+fn stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn extension_of(name: &str) -> String {
+    extension(Path::new(name))
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("morpheus-logs-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_size_rotation_keeps_newest_files() {
+        let dir = temp_dir();
+        std::fs::write(dir.join("other.txt"), "not a log").unwrap();
+        let policy = RotationPolicy {
+            max_file_bytes: Some(10),
+            max_files: Some(2),
+            ..RotationPolicy::default()
+        };
+        let mut log = RotatingFile::timestamped(&dir, "morpheus", policy).unwrap();
+        for line in ["first\n", "second\n", "third\n"] {
+            log.write_all(line.as_bytes()).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+
+        let names = names(&dir);
+        assert_eq!(names.len(), 3, "{:?}", names);
+        assert_eq!(names[2], "other.txt");
+        let contents: Vec<_> = names[..2]
+            .iter()
+            .map(|name| std::fs::read_to_string(dir.join(name)).unwrap())
+            .collect();
+        assert_eq!(contents, ["second\n", "third\n"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fixed_file_is_archived_on_rotation() {
+        let dir = temp_dir();
+        let policy = RotationPolicy {
+            max_file_bytes: Some(4),
+            ..RotationPolicy::default()
+        };
+        let mut log = RotatingFile::fixed(&dir, Path::new("wire.log"), policy).unwrap();
+        log.write_all(b"one\n").unwrap();
+        log.write_all(b"two\n").unwrap();
+
+        let names = names(&dir);
+        assert_eq!(names.len(), 2);
+        assert!(names[0].starts_with("wire-") && names[0].ends_with(".log"));
+        assert_eq!(
+            std::fs::read_to_string(dir.join(&names[0])).unwrap(),
+            "one\n"
+        );
+        assert_eq!(names[1], "wire.log");
+        assert_eq!(
+            std::fs::read_to_string(dir.join("wire.log")).unwrap(),
+            "two\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_time_rotation_starts_a_new_period() {
+        let dir = temp_dir();
+        let policy = RotationPolicy {
+            rotation: Rotation::Hourly,
+            ..RotationPolicy::default()
+        };
+        let log = RotatingFile::timestamped(&dir, "morpheus", policy).unwrap();
+        let now = Local::now();
+        assert!(!log.due(1, now));
+        assert!(log.due(1, now + chrono::Duration::hours(1)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
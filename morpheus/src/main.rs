@@ -1,71 +1,567 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use morpheus::{
-    core::{client_manager::ClientManager, server::Server, storage::InMemoryStorage},
-    ws::handler::client_connected,
+    check::{self, Report},
+    config::{Config, Reloader},
+    core::{
+        alerts::{AlertConfig, AlertEngine},
+        analytics,
+        audit::AuditLog,
+        bans::BanList,
+        canary::{loopback_url, Canary},
+        client_manager::ClientManager,
+        cluster::Cluster,
+        federation::Federation,
+        history::{HistoryStore, InMemoryHistory},
+        identities::ConnectionHistory,
+        mirror::{LoopGuard, MirrorForwarder},
+        polls,
+        qos::{self, QOS_REDELIVERY_TIMEOUT},
+        queue::BackpressurePolicy,
+        requests,
+        server::{report_reload, Server},
+        storage::{InMemoryStorage, Storage},
+        webhooks::WebhookForwarder,
+        work_queue::{spawn_redelivery, REDELIVERY_TIMEOUT},
+    },
+    forget, http,
+    log::access::access_log,
+    proxy,
 };
 use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
     sync::Arc,
 };
-use tracing::info;
+use tracing::{info, level_filters::LevelFilter};
+use uuid::Uuid;
 use warp::Filter;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// IP address to bind to
-    #[arg(short, long, default_value_t = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)))]
-    address: IpAddr,
+    /// TOML config file; flags given on the command line take precedence
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
 
-    /// Port to listen on
-    #[arg(short, long, default_value_t = 8080)]
-    port: u16,
+    /// IP address to bind to [default: 127.0.0.1]
+    #[arg(short, long, global = true)]
+    address: Option<IpAddr>,
+
+    /// Port to listen on [default: 8080]
+    #[arg(short, long, global = true)]
+    port: Option<u16>,
+
+    /// Path clients open their WebSocket at [default: /ws]
+    #[arg(long, global = true)]
+    ws_path: Option<String>,
+
+    /// Maximum number of outgoing messages queued per client [default: 100]
+    #[arg(long, global = true)]
+    queue_capacity: Option<usize>,
+
+    /// What to do when a client's queue is full: drop-oldest, drop-newest or disconnect [default: drop-oldest]
+    #[arg(long, global = true)]
+    backpressure: Option<BackpressurePolicy>,
+
+    /// Name of this node in a cluster (defaults to a random ID when peers are given)
+    #[arg(long, global = true)]
+    node_id: Option<String>,
+
+    /// The /cluster WebSocket URL of another node; repeat for every peer
+    #[arg(long = "peer", global = true)]
+    peers: Vec<String>,
+
+    /// Shared secret that cluster peers must present
+    #[arg(long, global = true)]
+    cluster_secret: Option<String>,
+
+    /// JSON file with alerting rules evaluated over server metrics
+    #[arg(long, global = true)]
+    alert_rules: Option<PathBuf>,
+
+    /// Where banned client IDs and addresses are saved [default: bans.json]
+    #[arg(long, global = true)]
+    ban_list: Option<PathBuf>,
+
+    /// Where the connection history of session identities is saved [default: connections.json]
+    #[arg(long, global = true)]
+    connection_history: Option<PathBuf>,
+
+    /// Where operator actions are recorded [default: audit.log]
+    #[arg(long, global = true)]
+    audit_log: Option<PathBuf>,
+
+    /// Where the commands typed into the CLI are kept [default: cli_history.txt]
+    #[arg(long, global = true)]
+    cli_history: Option<PathBuf>,
+
+    /// Keep sessions, subscriptions, retained messages and history in this SQLite database
+    #[arg(long, global = true)]
+    database: Option<PathBuf>,
+
+    /// Probe the server end to end through a loopback connection every SECS seconds
+    #[arg(long, value_name = "SECS", global = true)]
+    canary_interval: Option<u64>,
+
+    /// The most verbose level logged: off, error, warn, info, debug or trace [default: info]
+    #[arg(long, global = true)]
+    log_level: Option<LevelFilter>,
+
+    /// Also write the log to standard output
+    #[arg(long, global = true)]
+    log_stdout: bool,
+
+    /// Show live panels of topics, clients and throughput instead of the command line
+    #[cfg(unix)]
+    #[arg(long, global = true, conflicts_with = "no_cli")]
+    tui: bool,
+
+    /// Run without the command line, e.g. under systemd or in a container. The server is
+    /// controlled through the admin API and signals, and SIGINT or SIGTERM shut it down
+    #[arg(long, visible_alias = "daemon", global = true)]
+    no_cli: bool,
+
+    #[command(subcommand)]
+    mode: Option<Mode>,
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+enum Mode {
+    /// Run the server; the default. Runs the same checks as `check` first
+    Serve,
+    /// Validate the config, storage files, TLS material, access rules and port, then exit
+    Check,
+    /// Erase what was persisted about a durable identity and print an erasure report.
+    /// Run it while the server is stopped
+    Forget {
+        /// The identity, i.e. the client ID of a session client
+        #[arg(long)]
+        identity: Uuid,
+    },
+}
+
+impl Args {
+    /// Reads the config file, if any, with environment overrides applied.
+    fn file_config(&self) -> Result<Config, String> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    /// Applies the flags given on the command line, which win over everything else.
+    fn apply_flags(&self, config: &mut Config) {
+        if let Some(address) = self.address {
+            config.address = address;
+        }
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        if let Some(ws_path) = &self.ws_path {
+            config.ws_path = ws_path.clone();
+        }
+        if let Some(capacity) = self.queue_capacity {
+            config.queue.capacity = capacity;
+        }
+        if let Some(policy) = self.backpressure {
+            config.queue.policy = policy;
+        }
+        if self.node_id.is_some() {
+            config.cluster.node_id = self.node_id.clone();
+        }
+        if !self.peers.is_empty() {
+            config.cluster.peers = self.peers.clone();
+        }
+        if self.cluster_secret.is_some() {
+            config.cluster.secret = self.cluster_secret.clone();
+        }
+        if self.alert_rules.is_some() {
+            config.alert_rules = self.alert_rules.clone();
+        }
+        if let Some(ban_list) = &self.ban_list {
+            config.ban_list = ban_list.clone();
+        }
+        if let Some(connection_history) = &self.connection_history {
+            config.connection_history = connection_history.clone();
+        }
+        if let Some(audit_log) = &self.audit_log {
+            config.audit_log = audit_log.clone();
+        }
+        if let Some(cli_history) = &self.cli_history {
+            config.cli_history = cli_history.clone();
+        }
+        if self.database.is_some() {
+            config.database = self.database.clone();
+        }
+        if let Some(level) = self.log_level {
+            config.log.level = level;
+        }
+        if self.log_stdout {
+            config.log.stdout = true;
+        }
+        if let Some(interval) = self.canary_interval {
+            config
+                .canary
+                .get_or_insert_with(Default::default)
+                .interval_secs = interval;
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    morpheus::log::middleware::init_file_logger();
-    info!("Logger initialized, starting server...");
     let args = Args::parse();
-    let addr = SocketAddr::new(args.address, args.port);
+    if args.no_cli {
+        morpheus::cli::ui::hide_prompt();
+    }
+    let file_config = match args.file_config() {
+        Ok(config) => config,
+        Err(e) if args.mode == Some(Mode::Check) => exit_with_report(&Report::unreadable(e)),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut config = file_config.clone();
+    args.apply_flags(&mut config);
+    if let Some(Mode::Forget { identity }) = &args.mode {
+        let report = forget::run(&config, *identity);
+        println!("{}", report);
+        std::process::exit(if report.succeeded() { 0 } else { 1 });
+    }
+    let report = check::run(&config);
+    if args.mode == Some(Mode::Check) || !report.passed() {
+        exit_with_report(&report);
+    }
+    for problem in report.problems() {
+        eprintln!("Warning: {}: {}", problem.check, problem.detail);
+    }
+    morpheus::log::middleware::init_file_logger(&config.log);
+    info!("Logger initialized, starting server...");
+    let addr = SocketAddr::new(config.address, config.port);
+    let ws_path = match config.ws_path_segments() {
+        Ok(ws_path) => ws_path,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    println!("Morpheus server starting on {}", addr);
+    for listener in config.listeners() {
+        let over = if listener.tls.is_some() {
+            " over TLS"
+        } else {
+            ""
+        };
+        println!("Morpheus server starting on {}{}", listener.addr(), over);
+    }
 
-    // The storage backend is created here and wrapped in an Arc.
-    let storage = Arc::new(InMemoryStorage::new());
+    let (storage, history) = match backends(&config) {
+        Ok(backends) => backends,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let bans = match BanList::load(&config.ban_list) {
+        Ok(bans) => bans,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let connection_history = match ConnectionHistory::load(&config.connection_history) {
+        Ok(connection_history) => connection_history,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let audit = match AuditLog::open(&config.audit_log) {
+        Ok(audit) => audit,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let policies = match config.policies() {
+        Ok(policies) => policies,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
     // The ClientManager is created with a dynamic reference to the storage.
-    let client_manager = Arc::new(ClientManager::new(storage));
+    let mut client_manager = ClientManager::with_history(storage, history)
+        .with_queue_config(config.queue)
+        .with_compression(config.compression)
+        .with_reconnect(config.reconnect)
+        .with_policies(policies)
+        .with_bans(bans)
+        .with_connection_history(connection_history)
+        .with_audit_log(audit);
+    client_manager.set_motd(config.motd.clone());
+    if let Some(cluster_config) = config.cluster_config() {
+        println!(
+            "Cluster node '{}' with {} peer(s)",
+            cluster_config.node_id,
+            cluster_config.peers.len()
+        );
+        client_manager = client_manager.with_cluster(Cluster::start(cluster_config));
+    }
+    if !config.webhooks.is_empty() {
+        match WebhookForwarder::start(&config.webhooks) {
+            Ok(forwarder) => client_manager.history_hooks().register(Arc::new(forwarder)),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        println!(
+            "Forwarding topic messages to {} webhook(s)",
+            config.webhooks.len()
+        );
+    }
+    // A cluster node tags the messages it mirrors or federates with its node ID.
+    let instance_id = client_manager
+        .cluster()
+        .map(|cluster| cluster.node_id().to_string())
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    if !config.mirrors.is_empty() || !config.federation.is_empty() {
+        let guard = LoopGuard::new(instance_id.clone());
+        client_manager.message_hooks().register(Arc::new(guard));
+    }
+    if !config.mirrors.is_empty() {
+        let forwarder = MirrorForwarder::start(&config.mirrors, instance_id.clone());
+        client_manager.history_hooks().register(Arc::new(forwarder));
+        println!("Mirroring {} topic(s)", config.mirrors.len());
+    }
+    let client_manager = Arc::new(client_manager);
+    if !config.federation.is_empty() {
+        let federation = Federation::start(&config.federation, instance_id, client_manager.clone());
+        client_manager
+            .history_hooks()
+            .register(Arc::new(federation));
+        println!("Federating with {} server(s)", config.federation.len());
+    }
+    spawn_redelivery(client_manager.clone(), REDELIVERY_TIMEOUT);
+    qos::spawn_redelivery(client_manager.clone(), QOS_REDELIVERY_TIMEOUT);
+    polls::spawn_closer(client_manager.clone());
+    requests::spawn_expirer(client_manager.clone());
+    analytics::spawn(client_manager.clone());
+
+    if let Some(path) = &config.alert_rules {
+        let alert_config = match AlertConfig::load(path) {
+            Ok(alert_config) => alert_config,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        println!("Loaded {} alert rule(s)", alert_config.rules.len());
+        AlertEngine::new(alert_config).spawn(client_manager.clone());
+    }
+
+    if let Some(canary_config) = config.canary_config() {
+        let token = config.auth.tokens.first().map(String::as_str);
+        let url = loopback_url(addr, config.tls.is_some(), &config.ws_path, token);
+        println!("Canary probing every {}s", canary_config.interval.as_secs());
+        Canary::new(url, canary_config).spawn(client_manager.clone());
+    }
 
-    let server = Server::new(client_manager.clone());
+    let mut server =
+        Server::new(client_manager.clone()).with_history_file(config.cli_history.clone());
+    if let Some(path) = args.config {
+        let reloader = Arc::new(Reloader::new(path, file_config, client_manager.clone()));
+        #[cfg(unix)]
+        tokio::spawn(reload_on_sighup(reloader.clone()));
+        server = server.with_reloader(reloader);
+    }
 
-    let ws_route = warp::path("ws")
-        .and(warp::ws())
-        .and(with_client_manager(client_manager.clone()))
-        .map(|ws: warp::ws::Ws, manager| {
-            ws.on_upgrade(move |socket| client_connected(socket, manager))
-        });
+    let routes = http::filters_with_ws_path(client_manager.clone(), &ws_path)
+        .with(access_log(client_manager.clone()));
 
-    // Start the warp server in a separate task.
-    let warp_server = tokio::spawn(warp::serve(ws_route).run(addr));
+    // Start a warp server for every listener, each in a separate task.
+    let proxy_protocol = config.proxy.proxy_protocol;
+    let listeners = config.listeners().into_iter().map(|listener| {
+        let server = warp::serve(routes.clone());
+        match &listener.tls {
+            // The check refuses TLS listeners with the PROXY protocol.
+            _ if proxy_protocol => {
+                let routes = routes.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = proxy::serve(listener.addr(), routes).await {
+                        eprintln!("Failed to listen on {}: {}", listener.addr(), e);
+                    }
+                })
+            }
+            Some(tls) => tokio::spawn(
+                server
+                    .tls()
+                    .cert_path(&tls.cert)
+                    .key_path(&tls.key)
+                    .run(listener.addr()),
+            ),
+            None => tokio::spawn(server.run(listener.addr())),
+        }
+    });
+    let warp_server = futures_util::future::select_all(listeners);
 
-    // Start the CLI in the main task.
-    let cli_server = tokio::spawn(async move {
-        server.run_cli().await;
+    // Start the CLI, or the TUI in its place, in the main task.
+    #[cfg(unix)]
+    let tui = args.tui;
+    #[cfg(not(unix))]
+    let tui = false;
+    let no_cli = args.no_cli;
+    if no_cli {
+        println!("Running without the command line; send SIGINT or SIGTERM to stop.");
+    }
+    // The line editor puts the terminal in raw mode while it waits for a line.
+    #[cfg(unix)]
+    let terminal = (!no_cli && !tui).then(terminal_settings).flatten();
+    let server = Arc::new(server);
+    let cli = server.clone();
+    let mut cli_server = tokio::spawn(async move {
+        if no_cli {
+            std::future::pending::<()>().await;
+        } else if tui {
+            run_tui(&cli, client_manager).await;
+        } else {
+            cli.run_cli().await;
+        }
     });
 
-    // Wait for either the server or the CLI to finish.
+    // Wait for the server or the CLI to finish, or for a signal to stop. Either of the
+    // last two closes the connections like `/exit` instead of dropping them mid-write.
     tokio::select! {
         _ = warp_server => {
             eprintln!("Warp server has concluded.");
+            return;
         },
-        _ = cli_server => {
+        _ = &mut cli_server => {
             eprintln!("CLI has concluded.");
         }
+        _ = shutdown_signal() => {
+            if tui {
+                // Give the terminal back before exiting.
+                #[cfg(unix)]
+                morpheus::cli::tui::quit();
+                let _ = cli_server.await;
+            }
+            #[cfg(unix)]
+            if let Some(terminal) = terminal {
+                restore_terminal(&terminal);
+                // Leave the prompt's line.
+                println!();
+            }
+        }
     }
+    server.shutdown().await;
 }
 
-fn with_client_manager(
-    client_manager: Arc<ClientManager>,
-) -> impl Filter<Extract = (Arc<ClientManager>,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || client_manager.clone())
+type Backends = (Arc<dyn Storage>, Arc<dyn HistoryStore>);
+
+/// The storage and history backends: in memory, or in the SQLite database the config names.
+fn backends(config: &Config) -> Result<Backends, String> {
+    let Some(path) = &config.database else {
+        let history = InMemoryHistory::new(config.history.retention)
+            .with_topic_retention(config.topic_retention());
+        return Ok((Arc::new(InMemoryStorage::new()), Arc::new(history)));
+    };
+    #[cfg(feature = "sqlite")]
+    {
+        use morpheus::core::sqlite::{Database, SqliteHistory, SqliteStorage};
+        let db = Database::open(path)?;
+        let history = SqliteHistory::new(&db, config.history.retention)
+            .with_topic_retention(config.topic_retention());
+        println!("Keeping state in {}", path.display());
+        Ok((Arc::new(SqliteStorage::new(&db)?), Arc::new(history)))
+    }
+    #[cfg(not(feature = "sqlite"))]
+    Err(format!(
+        "Cannot keep state in {}: the server was built without the sqlite feature",
+        path.display()
+    ))
+}
+
+/// Prints a self-check report and exits, with status 1 if any check failed.
+fn exit_with_report(report: &Report) -> ! {
+    println!("Morpheus self-check:\n{}", report);
+    std::process::exit(if report.passed() { 0 } else { 1 });
+}
+
+/// Runs the TUI, executing the commands typed into it, until the operator quits.
+#[cfg(unix)]
+async fn run_tui(server: &Server, client_manager: Arc<ClientManager>) {
+    let (commands, mut received) = tokio::sync::mpsc::channel(16);
+    let tui =
+        tokio::task::spawn_blocking(move || morpheus::cli::tui::run(client_manager, commands));
+    // Ends once the TUI quits and drops its end of the channel.
+    let execute = async {
+        while let Some(line) = received.recv().await {
+            server.execute(&line).await;
+        }
+    };
+    let (result, ()) = tokio::join!(tui, execute);
+    if let Ok(Err(e)) = result {
+        eprintln!("TUI failed: {}", e);
+    }
+}
+
+#[cfg(not(unix))]
+async fn run_tui(_server: &Server, _client_manager: Arc<ClientManager>) {}
+
+/// Waits for Ctrl-C, or on Unix for SIGTERM, which service managers stop the server with.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {},
+                    _ = terminate.recv() => {},
+                }
+                return;
+            }
+            Err(e) => eprintln!("Failed to listen for SIGTERM: {}", e),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        eprintln!("Failed to listen for Ctrl-C: {}", e);
+        std::future::pending::<()>().await;
+    }
+}
+
+/// The settings of the terminal on stdin, if it is one.
+#[cfg(unix)]
+fn terminal_settings() -> Option<libc::termios> {
+    let mut settings = std::mem::MaybeUninit::uninit();
+    (unsafe { libc::tcgetattr(libc::STDIN_FILENO, settings.as_mut_ptr()) } == 0)
+        .then(|| unsafe { settings.assume_init() })
+}
+
+#[cfg(unix)]
+fn restore_terminal(settings: &libc::termios) {
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, settings) };
+}
+
+/// Reloads the config file every time the process receives SIGHUP.
+#[cfg(unix)]
+async fn reload_on_sighup(reloader: Arc<Reloader>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            eprintln!("Failed to listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        report_reload(&reloader);
+    }
 }
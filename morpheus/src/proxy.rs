@@ -0,0 +1,407 @@
+use hyper::{
+    header::HeaderValue,
+    http::HeaderMap,
+    server::conn::Http,
+    service::{service_fn, Service},
+};
+use serde::Deserialize;
+use std::{
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream},
+};
+use warp::{Filter, Reply};
+
+/// The header a trusted proxy names the client and the proxies in between in.
+pub const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// How long a connection may take to send its PROXY protocol header.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The longest PROXY protocol version 1 line, `\r\n` included.
+const MAX_V1_HEADER: usize = 107;
+
+/// The first twelve bytes of a PROXY protocol version 2 header.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// A range of addresses such as `10.0.0.0/8`; a plain address is a range of one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, canonical(ip)) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                masked(u32::from(network).into(), 32, self.prefix)
+                    == masked(u32::from(ip).into(), 32, self.prefix)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                masked(network.into(), 128, self.prefix) == masked(ip.into(), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The top `prefix` of the `bits` bits of `address`.
+fn masked(address: u128, bits: u8, prefix: u8) -> u128 {
+    match bits - prefix {
+        0 => address,
+        host if host >= 128 => 0,
+        host => address >> host,
+    }
+}
+
+/// Compares IPv4 clients of a dual-stack socket as IPv4.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        ip => ip,
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid address range '{}'", s);
+        let (network, prefix) = match s.split_once('/') {
+            Some((network, prefix)) => (network, Some(prefix)),
+            None => (s, None),
+        };
+        let network = canonical(network.trim().parse().map_err(|_| invalid())?);
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().map_err(|_| invalid())?,
+            None => bits,
+        };
+        if prefix > bits {
+            return Err(invalid());
+        }
+        Ok(Self { network, prefix })
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// The address of the client behind `peer`, the address the connection came from.
+///
+/// Proxies append the address they received a request from to `X-Forwarded-For`, so the
+/// list is walked from the right for as long as the address is one of the `trusted`
+/// proxies; the first one that is not, or the leftmost, is the client. Without a `peer`
+/// the rightmost entry stands in for it: the server appends it for connections that
+/// announced their source with the PROXY protocol.
+pub fn client_ip(
+    peer: Option<SocketAddr>,
+    headers: &HeaderMap,
+    trusted: &[Cidr],
+) -> Option<IpAddr> {
+    let mut hops: Vec<IpAddr> = forwarded_for(headers);
+    hops.extend(peer.map(|peer| canonical(peer.ip())));
+    let mut hops = hops.into_iter().rev();
+    let mut client = hops.next()?;
+    for hop in hops {
+        if !trusted.iter().any(|cidr| cidr.contains(client)) {
+            break;
+        }
+        client = hop;
+    }
+    Some(client)
+}
+
+/// The addresses in every `X-Forwarded-For` header, left to right. Entries that are not
+/// an address end the list there, as nothing to their left can be told apart from a
+/// forgery.
+fn forwarded_for(headers: &HeaderMap) -> Vec<IpAddr> {
+    let entries: Vec<&str> = headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    let mut hops: Vec<IpAddr> = entries
+        .iter()
+        .rev()
+        .map_while(|entry| parse_hop(entry))
+        .collect();
+    hops.reverse();
+    hops
+}
+
+/// An address as proxies write it: bare, or with a port as `1.2.3.4:5` or `[::1]:5`.
+fn parse_hop(entry: &str) -> Option<IpAddr> {
+    entry
+        .parse::<IpAddr>()
+        .or_else(|_| entry.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+        .map(canonical)
+}
+
+/// Reads the PROXY protocol header, version 1 or 2, at the start of `stream`, leaving
+/// the stream at the first byte after it. Returns the source address the proxy announced,
+/// or `None` for a health check or a source that is not TCP over IP, which should be taken
+/// from the connection itself.
+pub async fn read_header(stream: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    let invalid = |why: &str| io::Error::new(io::ErrorKind::InvalidData, why.to_string());
+    let mut start = [0; 12];
+    stream.read_exact(&mut start).await?;
+    if start == V2_SIGNATURE {
+        let mut fixed = [0; 4];
+        stream.read_exact(&mut fixed).await?;
+        let mut addresses = vec![0; u16::from_be_bytes([fixed[2], fixed[3]]) as usize];
+        stream.read_exact(&mut addresses).await?;
+        return parse_v2(fixed[0], fixed[1], &addresses).ok_or_else(|| invalid("Bad v2 header"));
+    }
+    if !start.starts_with(b"PROXY ") {
+        return Err(invalid("Missing PROXY protocol header"));
+    }
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == MAX_V1_HEADER {
+            return Err(invalid("PROXY protocol header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line).map_err(|_| invalid("Bad v1 header"))?;
+    parse_v1(line).ok_or_else(|| invalid("Bad v1 header"))
+}
+
+/// Parses `PROXY TCP4 <source> <destination> <source port> <destination port>\r\n`.
+fn parse_v1(line: &str) -> Option<Option<SocketAddr>> {
+    let mut fields = line.strip_suffix("\r\n")?.split(' ').skip(1);
+    match fields.next()? {
+        "UNKNOWN" => Some(None),
+        "TCP4" | "TCP6" => {
+            let source: IpAddr = fields.next()?.parse().ok()?;
+            let _destination: IpAddr = fields.next()?.parse().ok()?;
+            let port = fields.next()?.parse().ok()?;
+            Some(Some(SocketAddr::new(source, port)))
+        }
+        _ => None,
+    }
+}
+
+/// Parses the rest of a version 2 header: the version and command byte, the address
+/// family and protocol byte, and the addresses.
+fn parse_v2(version_command: u8, family: u8, addresses: &[u8]) -> Option<Option<SocketAddr>> {
+    if version_command >> 4 != 2 {
+        return None;
+    }
+    match version_command & 0x0f {
+        // LOCAL: the proxy's own connection, e.g. a health check.
+        0 => return Some(None),
+        // PROXY
+        1 => {}
+        _ => return None,
+    }
+    match family {
+        // TCP over IPv4: source, destination, source port, destination port.
+        0x11 if addresses.len() >= 12 => {
+            let ip: [u8; 4] = addresses[..4].try_into().ok()?;
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Some(Some(SocketAddr::new(ip.into(), port)))
+        }
+        0x21 if addresses.len() >= 36 => {
+            let ip: [u8; 16] = addresses[..16].try_into().ok()?;
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Some(Some(SocketAddr::new(ip.into(), port)))
+        }
+        _ => Some(None),
+    }
+}
+
+/// Serves `filter` on `addr` to connections that start with a PROXY protocol header, as
+/// sent by HAProxy with `send-proxy` or nginx with `proxy_protocol on`. The source each
+/// announces is appended to `X-Forwarded-For` so [`client_ip`] finds it. Connections
+/// without a valid header are closed.
+pub async fn serve<F>(addr: SocketAddr, filter: F) -> io::Result<()>
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let listener = TcpListener::bind(addr).await?;
+    loop {
+        let (mut stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                tracing::warn!("Failed to accept a connection on {}: {}", addr, e);
+                continue;
+            }
+        };
+        let service = warp::service(filter.clone());
+        tokio::spawn(async move {
+            let source = match tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut stream)).await
+            {
+                Ok(Ok(source)) => source.unwrap_or(peer),
+                Ok(Err(e)) => {
+                    tracing::debug!("Closing a connection from {}: {}", peer, e);
+                    return;
+                }
+                Err(_) => {
+                    tracing::debug!("Closing a connection from {}: no PROXY header", peer);
+                    return;
+                }
+            };
+            let service = service_fn(move |mut request: hyper::Request<hyper::Body>| {
+                append_forwarded_for(request.headers_mut(), source.ip());
+                let mut service = service.clone();
+                async move { service.call(request).await }
+            });
+            if let Err(e) = Http::new()
+                .http1_only(true)
+                .serve_connection(stream, service)
+                .with_upgrades()
+                .await
+            {
+                tracing::debug!("Connection from {} failed: {}", source, e);
+            }
+        });
+    }
+}
+
+/// Appends `ip` to the request's `X-Forwarded-For`, merging any earlier headers into one
+/// so it ends up last.
+fn append_forwarded_for(headers: &mut HeaderMap, ip: IpAddr) {
+    let mut hops: Vec<String> = headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .map(String::from)
+        .collect();
+    hops.push(ip.to_string());
+    if let Ok(value) = HeaderValue::from_str(&hops.join(", ")) {
+        headers.insert(X_FORWARDED_FOR, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    fn cidrs(ranges: &[&str]) -> Vec<Cidr> {
+        ranges.iter().map(|range| range.parse().unwrap()).collect()
+    }
+
+    fn forwarded(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(X_FORWARDED_FOR, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_cidr_contains() {
+        let private: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(private.contains("10.200.3.4".parse().unwrap()));
+        assert!(!private.contains("11.0.0.1".parse().unwrap()));
+        assert!(private.contains("::ffff:10.0.0.1".parse().unwrap()));
+        let loopback: Cidr = "::1".parse().unwrap();
+        assert!(loopback.contains("::1".parse().unwrap()));
+        assert!(!loopback.contains("127.0.0.1".parse().unwrap()));
+        assert!("0.0.0.0/0"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains("8.8.8.8".parse().unwrap()));
+        for invalid in ["10.0.0.0/33", "nowhere", "10.0.0.0/x"] {
+            assert!(invalid.parse::<Cidr>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_client_ip_walks_trusted_proxies() {
+        let trusted = cidrs(&["10.0.0.0/8"]);
+        let proxy: SocketAddr = "10.0.0.2:40000".parse().unwrap();
+        let headers = forwarded("1.1.1.1, 203.0.113.9, 10.0.0.5");
+
+        // Only addresses added by trusted proxies are believed.
+        assert_eq!(
+            client_ip(Some(proxy), &headers, &trusted),
+            Some("203.0.113.9".parse().unwrap())
+        );
+        // A client connecting directly cannot forge the header.
+        let direct: SocketAddr = "198.51.100.7:5000".parse().unwrap();
+        assert_eq!(
+            client_ip(Some(direct), &headers, &trusted),
+            Some(direct.ip())
+        );
+        assert_eq!(
+            client_ip(Some(proxy), &forwarded("garbage, 10.0.0.9"), &trusted),
+            Some("10.0.0.9".parse().unwrap())
+        );
+        assert_eq!(
+            client_ip(Some(proxy), &HeaderMap::new(), &trusted),
+            Some(proxy.ip())
+        );
+        // Without a peer the rightmost entry, added by the server, is the peer.
+        assert_eq!(
+            client_ip(None, &forwarded("203.0.113.9"), &trusted),
+            Some("203.0.113.9".parse().unwrap())
+        );
+        assert_eq!(client_ip(None, &HeaderMap::new(), &trusted), None);
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        assert_eq!(
+            parse_v1("PROXY TCP4 203.0.113.9 10.0.0.1 51000 443\r\n"),
+            Some(Some("203.0.113.9:51000".parse().unwrap()))
+        );
+        assert_eq!(parse_v1("PROXY UNKNOWN\r\n"), Some(None));
+        assert_eq!(parse_v1("PROXY TCP4 nowhere 10.0.0.1 1 2\r\n"), None);
+
+        let mut v4 = vec![203, 0, 113, 9, 10, 0, 0, 1];
+        v4.extend(51000u16.to_be_bytes());
+        v4.extend(443u16.to_be_bytes());
+        assert_eq!(
+            parse_v2(0x21, 0x11, &v4),
+            Some(Some("203.0.113.9:51000".parse().unwrap()))
+        );
+        assert_eq!(parse_v2(0x20, 0x00, &[]), Some(None));
+        assert_eq!(parse_v2(0x11, 0x11, &v4), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_header_leaves_the_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+
+        client
+            .write_all(b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 80\r\nGET /")
+            .await
+            .unwrap();
+        assert_eq!(
+            read_header(&mut server).await.unwrap(),
+            Some("[2001:db8::1]:4000".parse().unwrap())
+        );
+        let mut rest = [0; 5];
+        server.read_exact(&mut rest).await.unwrap();
+        assert_eq!(&rest, b"GET /");
+
+        client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        assert!(read_header(&mut server).await.is_err());
+    }
+}
@@ -0,0 +1,242 @@
+//! Fault injection for tests of how the server copes with a flaky network and a lossy
+//! storage backend.
+//!
+//! [`ChaosStorage`] wraps a backend and loses some of its writes; [`ChaosClient`] stands
+//! between a subscriber's queue and the test, delaying and dropping deliveries and cutting
+//! the connection now and then. Every fault is rolled on a generator seeded from
+//! [`Faults::seed`], so under `tokio::time::pause` on a current-thread runtime a test
+//! replays the same faults on every run.
+//!
+//! ```no_run
+//! use morpheus::testing::chaos::{ChaosClient, Faults};
+//! # use morpheus::core::{client_manager::ClientManager, storage::InMemoryStorage};
+//! # use std::sync::Arc;
+//!
+//! # async fn example() {
+//! # let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+//! let faults = Faults { drop_rate: 0.3, disconnect_rate: 0.05, ..Faults::default() };
+//! let subscriber = ChaosClient::connect(&client_manager, &faults);
+//! subscriber.connect_session(&client_manager, "news", None).await;
+//! # }
+//! ```
+
+use crate::core::{
+    client_manager::ClientManager,
+    fanout::Recipient,
+    msg::{Outgoing, ServerMessage},
+    storage::{Client, Session, Storage},
+};
+use async_trait::async_trait;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// How often things go wrong. Rates are probabilities from 0 to 1; the default injects
+/// no faults at all.
+#[derive(Clone, Debug, Default)]
+pub struct Faults {
+    pub seed: u64,
+    /// The longest a delivery is held up; each waits a random part of it.
+    pub max_latency: Duration,
+    /// How often a delivery is lost on its way to the client.
+    pub drop_rate: f64,
+    /// How often a delivery cuts the client's connection instead of arriving.
+    pub disconnect_rate: f64,
+    /// How often the storage backend loses a pending ack or a session it was given.
+    pub lost_write_rate: f64,
+}
+
+/// The faults' random generator.
+#[derive(Clone)]
+struct Dice(Arc<Mutex<StdRng>>);
+
+impl Dice {
+    fn new(seed: u64) -> Self {
+        Self(Arc::new(Mutex::new(StdRng::seed_from_u64(seed))))
+    }
+
+    fn hits(&self, rate: f64) -> bool {
+        rate > 0.0 && self.0.lock().unwrap().gen_bool(rate.min(1.0))
+    }
+
+    fn latency(&self, max: Duration) -> Duration {
+        if max.is_zero() {
+            return Duration::ZERO;
+        }
+        max.mul_f64(self.0.lock().unwrap().gen())
+    }
+}
+
+/// A storage backend that loses some of the pending acks and sessions it is given, as a
+/// remote store that drops writes does. Everything else goes to the wrapped backend.
+pub struct ChaosStorage {
+    inner: Arc<dyn Storage>,
+    faults: Faults,
+    dice: Dice,
+}
+
+impl ChaosStorage {
+    pub fn new(inner: Arc<dyn Storage>, faults: &Faults) -> Self {
+        Self {
+            inner,
+            faults: faults.clone(),
+            dice: Dice::new(faults.seed),
+        }
+    }
+
+    fn loses_write(&self) -> bool {
+        self.dice.hits(self.faults.lost_write_rate)
+    }
+}
+
+#[async_trait]
+impl Storage for ChaosStorage {
+    fn add_client(&self, client: Client) {
+        self.inner.add_client(client)
+    }
+
+    fn remove_client(&self, client_id: &Uuid) -> Option<Client> {
+        self.inner.remove_client(client_id)
+    }
+
+    fn get_client(&self, client_id: &Uuid) -> Option<Client> {
+        self.inner.get_client(client_id)
+    }
+
+    fn get_all_clients(&self) -> Vec<Client> {
+        self.inner.get_all_clients()
+    }
+
+    fn client_count(&self) -> usize {
+        self.inner.client_count()
+    }
+
+    fn subscribe_client_to_topic(&self, client_id: &Uuid, topic: String) {
+        self.inner.subscribe_client_to_topic(client_id, topic)
+    }
+
+    fn get_clients_in_topic(&self, topic: &str) -> Vec<Client> {
+        self.inner.get_clients_in_topic(topic)
+    }
+
+    fn topic_recipients(&self, topic: &str) -> Arc<[Recipient]> {
+        self.inner.topic_recipients(topic)
+    }
+
+    fn get_all_topics(&self) -> Vec<String> {
+        self.inner.get_all_topics()
+    }
+
+    fn add_pending_ack(&self, client_id: &Uuid, message: Arc<Outgoing>) {
+        if !self.loses_write() {
+            self.inner.add_pending_ack(client_id, message)
+        }
+    }
+
+    fn remove_pending_ack(&self, client_id: &Uuid, msg_id: &Uuid) -> bool {
+        self.inner.remove_pending_ack(client_id, msg_id)
+    }
+
+    fn take_pending_acks(&self, client_id: &Uuid) -> Vec<Arc<Outgoing>> {
+        self.inner.take_pending_acks(client_id)
+    }
+
+    fn pending_ack_count(&self) -> usize {
+        self.inner.pending_ack_count()
+    }
+
+    fn save_session(&self, session: Session) {
+        if !self.loses_write() {
+            self.inner.save_session(session)
+        }
+    }
+
+    fn take_session(&self, session_id: &Uuid) -> Option<Session> {
+        self.inner.take_session(session_id)
+    }
+
+    fn next_topic_seq(&self, topic: &str) -> u64 {
+        self.inner.next_topic_seq(topic)
+    }
+
+    fn topic_seqs(&self) -> Vec<(String, u64)> {
+        self.inner.topic_seqs()
+    }
+
+    fn restore_topic_seq(&self, topic: &str, seq: u64) {
+        self.inner.restore_topic_seq(topic, seq)
+    }
+
+    fn set_retained(&self, topic: &str, message: Option<ServerMessage>) {
+        self.inner.set_retained(topic, message)
+    }
+
+    fn get_retained(&self, topic: &str) -> Option<ServerMessage> {
+        self.inner.get_retained(topic)
+    }
+}
+
+/// An internal client on a flaky connection. Its deliveries are delayed and some are
+/// lost; now and then one cuts the connection, which the server sees as the client going
+/// away, and the messages already through are the last `recv` returns.
+pub struct ChaosClient {
+    id: Arc<Mutex<Uuid>>,
+    messages: mpsc::UnboundedReceiver<ServerMessage>,
+}
+
+impl ChaosClient {
+    /// Registers the client with `client_manager`, in a task of the current runtime that
+    /// lasts as long as its connection.
+    pub fn connect(client_manager: &Arc<ClientManager>, faults: &Faults) -> Self {
+        let (client_id, mut queue) = client_manager.add_internal_client();
+        let id = Arc::new(Mutex::new(client_id));
+        let (tx, messages) = mpsc::unbounded_channel();
+        let dice = Dice::new(faults.seed);
+        let (client_manager, faults, current) =
+            (client_manager.clone(), faults.clone(), id.clone());
+        tokio::spawn(async move {
+            while let Some(message) = queue.recv().await {
+                if dice.hits(faults.disconnect_rate) {
+                    let client_id = *current.lock().unwrap();
+                    client_manager.remove_client(&client_id);
+                    return;
+                }
+                tokio::time::sleep(dice.latency(faults.max_latency)).await;
+                if !dice.hits(faults.drop_rate) && tx.send(message).is_err() {
+                    return;
+                }
+            }
+        });
+        Self { id, messages }
+    }
+
+    /// The client's ID, which changes when it resumes a session.
+    pub fn id(&self) -> Uuid {
+        *self.id.lock().unwrap()
+    }
+
+    /// Completes a `ConnectSession` handshake for the client, resuming `session_id` if
+    /// the server still has it.
+    pub async fn connect_session(
+        &self,
+        client_manager: &ClientManager,
+        topic: &str,
+        session_id: Option<Uuid>,
+    ) -> Uuid {
+        let client_id = client_manager
+            .connect_session(self.id(), topic.to_string(), session_id)
+            .await;
+        *self.id.lock().unwrap() = client_id;
+        client_id
+    }
+
+    /// The next message that made it through, or `None` once the connection was cut and
+    /// every earlier message was read.
+    pub async fn recv(&mut self) -> Option<ServerMessage> {
+        self.messages.recv().await
+    }
+}
@@ -0,0 +1,185 @@
+//! Helpers for integration tests against a morpheus server running in the test process,
+//! behind the `testing` feature.
+//!
+//! ```no_run
+//! use morpheus::{core::msg::ServerMessage, testing::TestHarness};
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let harness = TestHarness::start().await;
+//! let mut alice = harness.client("news").await?;
+//! let mut bob = harness.client("news").await?;
+//! alice.send_message("news", "hello").await?;
+//! assert!(matches!(bob.recv().await?, Some(ServerMessage::Topic { .. })));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`client_record`] makes the client records that storage tests and benchmarks put in
+//! storage directly, and [`chaos`] injects faults into storage and deliveries.
+
+pub mod chaos;
+
+use crate::{
+    core::{
+        client_manager::ClientManager,
+        msg::{self, ClientMessage, Qos, ServerMessage},
+        queue::{self, QueueConfig, QueueReceiver},
+        storage::{Client, CloseHandle, InMemoryStorage},
+    },
+    http,
+};
+use chrono::Utc;
+use futures_util::{SinkExt, StreamExt};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{protocol::Message, Error as WsError},
+    MaybeTlsStream, WebSocketStream,
+};
+use uuid::Uuid;
+
+/// The record of a client that connected with neo and has not subscribed yet, with the
+/// receiving end of its queue. Tests set the fields they care about on the copy they get.
+pub fn client_record(id: Uuid, queue: QueueConfig) -> (Client, QueueReceiver) {
+    let (sender, receiver) = queue::channel(queue);
+    let client = Client {
+        id,
+        topic: None,
+        sender,
+        session_id: None,
+        ip: None,
+        user_agent: Some("neo/0.1.0".to_string()),
+        protocol_version: Some(1),
+        connected_at: Utc::now(),
+        accepted_terms: HashMap::new(),
+        closer: CloseHandle::default(),
+        role: None,
+    };
+    (client, receiver)
+}
+
+/// A local port that was free when asked for.
+pub async fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// A server serving every endpoint of [`http::filters`] on a local port, in a task of the
+/// current runtime.
+pub struct TestHarness {
+    pub port: u16,
+    pub client_manager: Arc<ClientManager>,
+}
+
+impl TestHarness {
+    /// Starts a server with in-memory storage and default settings.
+    pub async fn start() -> Self {
+        let storage = Arc::new(InMemoryStorage::new());
+        Self::serve(Arc::new(ClientManager::new(storage))).await
+    }
+
+    /// Starts a server for a configured `client_manager`. It accepts connections as soon
+    /// as this returns.
+    pub async fn serve(client_manager: Arc<ClientManager>) -> Self {
+        let (addr, server) =
+            warp::serve(http::filters(client_manager.clone())).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        Self {
+            port: addr.port(),
+            client_manager,
+        }
+    }
+
+    /// The URL of the client WebSocket.
+    pub fn url(&self) -> String {
+        format!("ws://127.0.0.1:{}/ws", self.port)
+    }
+
+    /// Connects a client subscribed to `topic`.
+    pub async fn client(&self, topic: &str) -> Result<TestClient, WsError> {
+        TestClient::connect(self.port, topic).await
+    }
+}
+
+/// A client speaking the protocol over a raw WebSocket, so tests see every frame the
+/// server sends.
+pub struct TestClient {
+    /// The connection, for frames the helpers do not cover.
+    pub ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl TestClient {
+    /// Connects to the server on `port` and subscribes to `topic`.
+    pub async fn connect(port: u16, topic: &str) -> Result<Self, WsError> {
+        let url = format!("ws://127.0.0.1:{}/ws", port);
+        let (ws, _) = connect_async(&url).await?;
+        let mut client = Self { ws };
+        client
+            .send(&ClientMessage::Connect {
+                topic: topic.to_string(),
+            })
+            .await?;
+        Ok(client)
+    }
+
+    /// Publishes `content` to `topic`, fire and forget.
+    pub async fn send_message(&mut self, topic: &str, content: &str) -> Result<(), WsError> {
+        self.send(&ClientMessage::Message {
+            topic: topic.to_string(),
+            content: content.to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            id: None,
+            reply_to: None,
+            retain: false,
+        })
+        .await
+    }
+
+    pub async fn send(&mut self, message: &ClientMessage) -> Result<(), WsError> {
+        let text = msg::encode(message).expect("client messages always serialize");
+        self.ws.send(Message::Text(text)).await
+    }
+
+    /// The next message from the server, or `None` once the connection is closed. Pings,
+    /// pongs and binary frames, such as attachment chunks, are skipped.
+    ///
+    /// # Panics
+    ///
+    /// If the server sends text that does not decode as a message.
+    pub async fn recv(&mut self) -> Result<Option<ServerMessage>, WsError> {
+        while let Some(frame) = self.ws.next().await {
+            match frame? {
+                Message::Text(text) => match msg::decode(&text) {
+                    Ok(message) => return Ok(Some(message)),
+                    Err(e) => panic!("The server sent an undecodable frame {}: {}", text, e),
+                },
+                Message::Close(_) => return Ok(None),
+                Message::Ping(_) | Message::Pong(_) | Message::Binary(_) | Message::Frame(_) => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads frames until the server closes the connection and returns its close code.
+    pub async fn close_code(&mut self) -> Option<u16> {
+        while let Some(Ok(frame)) = self.ws.next().await {
+            if let Message::Close(frame) = frame {
+                return frame.map(|frame| frame.code.into());
+            }
+        }
+        None
+    }
+
+    pub async fn close(mut self) -> Result<(), WsError> {
+        self.ws.close(None).await
+    }
+}
@@ -0,0 +1,156 @@
+use crate::core::{
+    client_manager::ClientManager,
+    forwards::{ForwardRule, ForwardSpec},
+    membership::MembershipEvent,
+    storage::ClientInfo,
+};
+use futures_util::{stream, StreamExt};
+use std::{convert::Infallible, sync::Arc};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+use warp::{
+    http::{header, StatusCode},
+    hyper::body::Bytes,
+    reply::{self, Response},
+    sse::Event,
+    Reply,
+};
+
+/// The connected clients and where they connected from, longest connected first.
+pub fn clients(client_manager: Arc<ClientManager>) -> Response {
+    let mut clients: Vec<ClientInfo> = client_manager
+        .get_all_clients()
+        .iter()
+        .map(ClientInfo::from)
+        .collect();
+    clients.sort_by_key(|client| (client.connected_at, client.id));
+    reply::json(&clients).into_response()
+}
+
+/// Starts draining if `start` is set, then answers with the drain status: `202 Accepted`
+/// while clients are left and `200 OK` once none are.
+pub fn drain(client_manager: Arc<ClientManager>, start: bool) -> Response {
+    if start {
+        client_manager.drain();
+    }
+    let status = client_manager.drain_status();
+    let code = if status.draining && status.clients > 0 {
+        StatusCode::ACCEPTED
+    } else {
+        StatusCode::OK
+    };
+    reply::with_status(reply::json(&status), code).into_response()
+}
+
+/// What a request to `/admin/forwards` asks for.
+pub enum ForwardRequest {
+    List,
+    /// Add the rule in the JSON body, a [`ForwardSpec`].
+    Add(Bytes),
+    Remove(Uuid),
+}
+
+/// Lists, adds or removes forwarding rules. An added rule is answered with `201 Created`
+/// and its ID, a removed one with `204 No Content`.
+pub fn forwards(client_manager: Arc<ClientManager>, request: ForwardRequest) -> Response {
+    let forwards = client_manager.forwards();
+    match request {
+        ForwardRequest::List => reply::json(&forwards.list()).into_response(),
+        ForwardRequest::Add(body) => {
+            let spec = match serde_json::from_slice::<ForwardSpec>(&body) {
+                Ok(spec) => spec,
+                Err(e) => {
+                    let reason = format!("Invalid forwarding rule: {}", e);
+                    return reply::with_status(reason, StatusCode::BAD_REQUEST).into_response();
+                }
+            };
+            let detail = spec.to_string();
+            match ForwardRule::new(spec) {
+                Ok(rule) => {
+                    let created = reply::json(&rule);
+                    let result = Ok(format!("Added rule {}", rule.id));
+                    forwards.add(rule);
+                    client_manager.audit().record("forward", &detail, result);
+                    reply::with_status(created, StatusCode::CREATED).into_response()
+                }
+                Err(e) => {
+                    client_manager
+                        .audit()
+                        .record("forward", &detail, Err(e.clone()));
+                    reply::with_status(e, StatusCode::BAD_REQUEST).into_response()
+                }
+            }
+        }
+        ForwardRequest::Remove(id) => {
+            if !forwards.remove(&id) {
+                return StatusCode::NOT_FOUND.into_response();
+            }
+            client_manager.audit().record(
+                "forward remove",
+                &id.to_string(),
+                Ok("Removed".to_string()),
+            );
+            StatusCode::NO_CONTENT.into_response()
+        }
+    }
+}
+
+/// The topic membership snapshot, tagged with its sequence number as the `ETag` so a
+/// dashboard that already has it gets `304 Not Modified` instead.
+pub fn membership_snapshot(
+    client_manager: Arc<ClientManager>,
+    if_none_match: Option<String>,
+) -> Response {
+    let snapshot = client_manager.membership().snapshot();
+    let etag = format!("\"{}\"", snapshot.sequence);
+    let unchanged = if_none_match.is_some_and(|tags| {
+        tags.split(',')
+            .any(|tag| tag.trim() == etag || tag.trim() == "*")
+    });
+    let response = if unchanged {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        reply::json(&snapshot).into_response()
+    };
+    reply::with_header(response, header::ETAG, etag).into_response()
+}
+
+/// Streams membership changes as Server-Sent Events named `membership`, each with its
+/// sequence number as the event ID. Resuming after `since` (a snapshot's sequence or the
+/// `Last-Event-ID` of a dropped stream) first replays what was missed; if that is no
+/// longer kept the answer is `410 Gone` and the dashboard has to fetch a new snapshot.
+/// A stream that falls too far behind is closed for the same reason.
+pub fn membership_events(client_manager: Arc<ClientManager>, since: Option<u64>) -> Response {
+    let (missed, receiver) = match client_manager.membership().subscribe(since) {
+        Ok(subscription) => subscription,
+        Err(stale) => {
+            let reason = format!(
+                "Changes before sequence {} are no longer kept; fetch /admin/membership again",
+                stale.oldest
+            );
+            return reply::with_status(reason, StatusCode::GONE).into_response();
+        }
+    };
+    let live = stream::unfold(receiver, |mut receiver| async move {
+        match receiver.recv().await {
+            Ok(event) => Some((event, receiver)),
+            Err(broadcast::error::RecvError::Lagged(_) | broadcast::error::RecvError::Closed) => {
+                None
+            }
+        }
+    });
+    let events = stream::iter(missed)
+        .chain(live)
+        .filter_map(|event| async move { to_event(&event).map(Ok::<_, Infallible>) });
+    warp::sse::reply(warp::sse::keep_alive().stream(events)).into_response()
+}
+
+fn to_event(event: &MembershipEvent) -> Option<Event> {
+    let data = serde_json::to_string(event).ok()?;
+    Some(
+        Event::default()
+            .event("membership")
+            .id(event.sequence.to_string())
+            .data(data),
+    )
+}
@@ -1,83 +1,776 @@
 use crate::core::{
-    client_manager::ClientManager,
-    msg::{ClientMessage, ServerMessage},
+    attachments::{decode_chunk, ChunkError, Uploads},
+    bans::BanTarget,
+    client_manager::{close_with, ClientManager, BANNED_REASON},
+    compression::{self, CompressionHandle},
+    hooks::PendingMessage,
+    msg::{self, ClientMessage, CloseCode, FetchedMessage, Qos, ServerMessage},
+    policy::{Permissions, TokenBucket},
+    polls::{Poll, DEFAULT_POLL_DURATION},
+    provenance::{Origin, Provenance},
+    verbs::ClientHandle,
 };
-use futures_util::StreamExt;
-use std::sync::Arc;
+use futures_util::{SinkExt, StreamExt};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{field, info, trace, warn, Instrument, Span};
 use uuid::Uuid;
 use warp::ws::{Message, WebSocket};
 
-pub async fn client_connected(ws: WebSocket, client_manager: Arc<ClientManager>) {
+/// How long a client that connected without a token has to send `Authenticate`.
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The token a connecting client presented, either as a `token` query parameter
+/// or as an `Authorization: Bearer` header.
+pub fn request_token<'a>(
+    query: &'a HashMap<String, String>,
+    authorization: Option<&'a str>,
+) -> Option<&'a str> {
+    query
+        .get("token")
+        .map(String::as_str)
+        .or_else(|| authorization?.strip_prefix("Bearer "))
+}
+
+/// Completes the upgrade of a connection that may not connect, such as one without a
+/// valid token, and closes it with `code` so the client learns why.
+pub async fn refuse(mut ws: WebSocket, code: CloseCode) {
+    close_with(&mut ws, code).await;
+}
+
+/// Serves a client that presented no token on the upgrade. If the policies require one,
+/// its first message must be `Authenticate`.
+pub async fn client_connected(
+    ws: WebSocket,
+    client_manager: Arc<ClientManager>,
+    ip: Option<IpAddr>,
+    user_agent: Option<String>,
+) {
+    serve_client(ws, client_manager, ip, user_agent, None).await;
+}
+
+/// Serves a client whose token was accepted on the upgrade, granting it `permissions`.
+pub async fn authenticated_client_connected(
+    ws: WebSocket,
+    client_manager: Arc<ClientManager>,
+    ip: Option<IpAddr>,
+    user_agent: Option<String>,
+    permissions: Permissions,
+) {
+    serve_client(ws, client_manager, ip, user_agent, Some(permissions)).await;
+}
+
+/// Serves a connection in a `connection` span, which the events about it happen in. The
+/// span carries the client's `ip`, its `client_id` once it is registered and the `topic`
+/// it last subscribed to.
+async fn serve_client(
+    ws: WebSocket,
+    client_manager: Arc<ClientManager>,
+    ip: Option<IpAddr>,
+    user_agent: Option<String>,
+    permissions: Option<Permissions>,
+) {
+    let span = tracing::info_span!(
+        "connection",
+        ip = field::Empty,
+        client_id = field::Empty,
+        topic = field::Empty
+    );
+    if let Some(ip) = ip {
+        span.record("ip", field::display(ip));
+    }
+    serve_connection(ws, client_manager, ip, user_agent, permissions)
+        .instrument(span)
+        .await;
+}
+
+async fn serve_connection(
+    mut ws: WebSocket,
+    client_manager: Arc<ClientManager>,
+    ip: Option<IpAddr>,
+    user_agent: Option<String>,
+    permissions: Option<Permissions>,
+) {
+    if ip.is_some_and(|ip| client_manager.bans().is_banned(&BanTarget::Ip(ip))) {
+        info!("Refused a connection from a banned address");
+        let kicked = ServerMessage::Kicked {
+            reason: Some(BANNED_REASON.to_string()),
+        };
+        if let Ok(text) = msg::encode(&kicked) {
+            let _ = ws.send(Message::text(text)).await;
+        }
+        close_with(&mut ws, CloseCode::Banned).await;
+        return;
+    }
+    let permissions = match permissions {
+        Some(permissions) => permissions,
+        None => match authenticate(&mut ws, &client_manager).await {
+            Ok(permissions) => permissions,
+            Err(reason) => {
+                info!("Refused a connection: {}", reason);
+                close_with(&mut ws, CloseCode::AuthFailed).await;
+                return;
+            }
+        },
+    };
+    if !client_manager.admission().admit().await {
+        warn!("Refused a connection: too many clients are connecting at once");
+        close_with(&mut ws, CloseCode::Overloaded).await;
+        return;
+    }
     let (ws_sender, mut ws_receiver) = ws.split();
 
-    // Use an unbounded channel to handle messages from the client manager
-    let client_id = client_manager.add_client(ws_sender);
-    println!("Client {} connected.", client_id);
+    // The client manager queues the client's messages in a bounded queue; when it is full,
+    // the backpressure policy drops the oldest or newest message or disconnects the client.
+    let Ok((mut client_id, closer, compression)) =
+        client_manager.add_client(ws_sender, ip, user_agent)
+    else {
+        warn!("Refused a connection: the server is full");
+        return;
+    };
+    Span::current().record("client_id", field::display(client_id));
+    info!("Client connected");
+    if let Some(role) = permissions.role {
+        client_manager.set_role(&client_id, role);
+    }
+    client_manager.counters().connection();
+    let mut rate_limiter = client_manager.policies().rate_limit.map(TokenBucket::new);
+    let mut uploads = Uploads::default();
+    let mut version_known = false;
 
-    // This loop handles messages received from the client
-    while let Some(result) = ws_receiver.next().await {
+    // This loop handles messages received from the client. Any frame, including the
+    // pong replies to our pings, counts as a sign of life.
+    let idle_deadline = client_manager.heartbeat().idle_deadline();
+    loop {
+        let next = tokio::select! {
+            next = tokio::time::timeout(idle_deadline, ws_receiver.next()) => next,
+            _ = closer.closed() => {
+                info!("Client was disconnected by the server");
+                break;
+            }
+        };
+        let result = match next {
+            Ok(Some(result)) => result,
+            Ok(None) => break,
+            Err(_) => {
+                info!("Client stopped responding to pings");
+                closer.close(CloseCode::Idle);
+                break;
+            }
+        };
         let msg = match result {
             Ok(msg) => msg,
             Err(e) => {
-                eprintln!("Error receiving message from client {}: {}", client_id, e);
+                warn!("Error receiving a message: {}", e);
+                client_manager.counters().error();
                 break;
             }
         };
-        handle_message(&client_id, msg, &client_manager).await;
+        if msg.is_binary() && decode_chunk(msg.as_bytes()).is_none() {
+            warn!("Client sent a binary frame that is not an attachment chunk, closing");
+            client_manager.counters().error();
+            closer.close(CloseCode::ProtocolError);
+            break;
+        }
+        if !version_known {
+            if let Some(version) = msg.to_str().ok().and_then(msg::version) {
+                client_manager.set_protocol_version(&client_id, version);
+                version_known = true;
+            }
+        }
+        if let Some(resumed_id) = handle_message(
+            &client_id,
+            msg,
+            &client_manager,
+            &permissions,
+            &compression,
+            &mut rate_limiter,
+            &mut uploads,
+        )
+        .await
+        {
+            client_id = resumed_id;
+            Span::current().record("client_id", field::display(client_id));
+        }
     }
 
     // Client disconnected
+    for (id, topic) in uploads.abort_all() {
+        abort_attachment(
+            &client_id,
+            id,
+            &topic,
+            "sender disconnected",
+            &client_manager,
+        )
+        .await;
+    }
     client_manager.remove_client(&client_id);
 }
 
-async fn handle_message(client_id: &Uuid, msg: Message, client_manager: &Arc<ClientManager>) {
+/// Handles the frames of a client registered with `ClientManager::add_internal_client` in
+/// turn, the way a connection's frames are handled once it is authenticated with every
+/// permission. Returns the client's ID afterwards, which changes if a frame resumed a
+/// session. Binary frames that are not attachment chunks, which close a connection, are
+/// skipped. Fuzz targets drive the handler through this.
+pub async fn handle_frames(
+    mut client_id: Uuid,
+    frames: impl IntoIterator<Item = Message>,
+    client_manager: &Arc<ClientManager>,
+) -> Uuid {
+    let permissions = Permissions::all();
+    let compression = CompressionHandle::default();
+    let mut rate_limiter = client_manager.policies().rate_limit.map(TokenBucket::new);
+    let mut uploads = Uploads::default();
+    for msg in frames {
+        if msg.is_binary() && decode_chunk(msg.as_bytes()).is_none() {
+            continue;
+        }
+        if let Some(resumed_id) = handle_message(
+            &client_id,
+            msg,
+            client_manager,
+            &permissions,
+            &compression,
+            &mut rate_limiter,
+            &mut uploads,
+        )
+        .await
+        {
+            client_id = resumed_id;
+        }
+    }
+    for (id, topic) in uploads.abort_all() {
+        abort_attachment(
+            &client_id,
+            id,
+            &topic,
+            "sender disconnected",
+            client_manager,
+        )
+        .await;
+    }
+    client_id
+}
+
+/// Reads the `Authenticate` message a client without a token must start with, and
+/// returns the permissions its token grants. Clients need not send one while
+/// authentication is disabled.
+async fn authenticate(
+    ws: &mut WebSocket,
+    client_manager: &ClientManager,
+) -> Result<Permissions, String> {
+    let policies = client_manager.policies();
+    if !policies.requires_auth() {
+        return Ok(Permissions::all());
+    }
+    let first = match tokio::time::timeout(AUTH_TIMEOUT, ws.next()).await {
+        Ok(Some(Ok(msg))) => msg,
+        Ok(_) => return Err("it closed before authenticating".to_string()),
+        Err(_) => return Err("it did not authenticate in time".to_string()),
+    };
+    match first.to_str().map(msg::decode::<ClientMessage>) {
+        Ok(Ok(ClientMessage::Authenticate { token })) => policies.permissions(Some(&token)),
+        _ => Err("its first message was not authenticate".to_string()),
+    }
+}
+
+/// Handles a single frame from a client.
+/// Returns the client's new ID when the frame resumed a previous session.
+async fn handle_message(
+    client_id: &Uuid,
+    msg: Message,
+    client_manager: &Arc<ClientManager>,
+    permissions: &Permissions,
+    compression: &CompressionHandle,
+    rate_limiter: &mut Option<TokenBucket>,
+    uploads: &mut Uploads,
+) -> Option<Uuid> {
+    let max = client_manager.policies().limits.max_message_bytes;
+    if msg.as_bytes().len() > max {
+        let error_msg = ServerMessage::Error {
+            message: format!(
+                "Message too large: {} bytes (limit {} bytes)",
+                msg.as_bytes().len(),
+                max
+            ),
+        };
+        client_manager
+            .send_private_message(*client_id, error_msg)
+            .await;
+        return None;
+    }
+    if msg.is_binary() {
+        relay_chunk(client_id, msg.into_bytes(), client_manager, uploads).await;
+        return None;
+    }
     if let Ok(text) = msg.to_str() {
-        match serde_json::from_str::<ClientMessage>(text) {
+        match msg::decode::<ClientMessage>(text) {
             Ok(client_message) => {
                 crate::log::middleware::log_incoming(client_id, &client_message);
                 match client_message {
                     ClientMessage::Connect { topic } => {
-                        println!("Client {} subscribing to topic '{}'", client_id, topic);
-                        client_manager.subscribe_client_to_topic(client_id, topic);
-                    }
-                    ClientMessage::Message { topic, content } => {
-                        println!(
-                            "Client {} sent message to topic '{}'\n'{}'",
-                            client_id, topic, content
-                        );
+                        if let Some(error) = check_subscribe(&topic, permissions) {
+                            client_manager.send_private_message(*client_id, error).await;
+                            return None;
+                        }
+                        Span::current().record("topic", topic.as_str());
+                        info!(%topic, "Client subscribing to a topic");
+                        client_manager.join_topic(client_id, topic).await;
+                        send_motd(client_id, client_manager).await;
+                    }
+                    ClientMessage::ConnectSession { topic, session_id } => {
+                        if let Some(error) = check_subscribe(&topic, permissions) {
+                            client_manager.send_private_message(*client_id, error).await;
+                            return None;
+                        }
+                        Span::current().record("topic", topic.as_str());
+                        info!(%topic, "Client subscribing to a topic with a session");
+                        let resumed_id = client_manager
+                            .connect_session(*client_id, topic, session_id)
+                            .await;
+                        if client_manager
+                            .bans()
+                            .is_banned(&BanTarget::Client(resumed_id))
+                        {
+                            client_manager.kick_banned(&resumed_id);
+                            return Some(resumed_id);
+                        }
+                        send_motd(&resumed_id, client_manager).await;
+                        if resumed_id != *client_id {
+                            info!(%resumed_id, "Client resumed a session");
+                            return Some(resumed_id);
+                        }
+                    }
+                    ClientMessage::Message {
+                        topic,
+                        content,
+                        headers,
+                        qos,
+                        id,
+                        reply_to,
+                        retain,
+                    } => {
+                        let id = match (qos, id) {
+                            (Qos::FireAndForget, id) => id.unwrap_or_else(Uuid::new_v4),
+                            (_, Some(id)) => id,
+                            (_, None) => {
+                                let error_msg = ServerMessage::Error {
+                                    message: "Messages with a QoS above fire_and_forget need an id"
+                                        .to_string(),
+                                };
+                                client_manager
+                                    .send_private_message(*client_id, error_msg)
+                                    .await;
+                                return None;
+                            }
+                        };
+                        // Content stays out of the log above TRACE, so erasing a message
+                        // does not leave it behind in the log files.
+                        info!(%topic, msg_id = %id, bytes = content.len(), "Client sent a message");
+                        trace!(msg_id = %id, %content, "Message content");
+                        if qos == Qos::ExactlyOnce && client_manager.is_duplicate(&id) {
+                            // A retry of a message we already broadcast: confirm it again.
+                            client_manager
+                                .send_private_message(*client_id, ServerMessage::Published { id })
+                                .await;
+                            return None;
+                        }
+                        if let Some(error) =
+                            check_publish(&topic, client_manager, permissions, rate_limiter)
+                        {
+                            client_manager.send_private_message(*client_id, error).await;
+                            return None;
+                        }
+                        if let Some(error) = check_reply(&topic, reply_to, client_manager) {
+                            client_manager.send_private_message(*client_id, error).await;
+                            return None;
+                        }
+                        client_manager.record_nickname(*client_id, &headers);
+                        let mut pending = PendingMessage::new(
+                            *client_id,
+                            topic.clone(),
+                            content,
+                            Provenance::new(Origin::Client(*client_id)),
+                        )
+                        .with_headers(headers);
+                        if let Err(reason) = client_manager.message_hooks().run(&mut pending).await
+                        {
+                            let error_msg = ServerMessage::Error {
+                                message: format!("Message rejected: {}", reason),
+                            };
+                            client_manager
+                                .send_private_message(*client_id, error_msg)
+                                .await;
+                            return None;
+                        }
                         let message = ServerMessage::Topic {
-                            id: Uuid::new_v4(),
+                            id,
                             topic: topic.clone(),
+                            seq: 0,
                             sender: client_id.to_string(),
-                            content,
+                            content: pending.content,
+                            headers: pending.headers,
+                            qos,
+                            reply_to,
+                            retained: false,
                         };
+                        if retain {
+                            client_manager.retain(&message);
+                        }
                         // Broadcast to topic, excluding the sender
                         client_manager
-                            .broadcast_to_topic(&topic, message, Some(*client_id))
+                            .broadcast_to_topic_with_provenance(
+                                &topic,
+                                message,
+                                Some(*client_id),
+                                pending.provenance,
+                            )
                             .await;
+                        if qos == Qos::ExactlyOnce {
+                            client_manager.remember_published(id);
+                        }
+                        if !qos.is_fire_and_forget() {
+                            client_manager
+                                .send_private_message(*client_id, ServerMessage::Published { id })
+                                .await;
+                        }
                     }
                     ClientMessage::ReplyToMorpheus {
                         original_msg_id,
                         content,
                     } => {
-                        // For now, just print replies to the server console
-                        println!(
-                            "\n[REPLY to {} from {}]: {}",
-                            original_msg_id, client_id, content
-                        );
+                        // For now, replies to the server are only logged, their content
+                        // at TRACE like that of other messages.
+                        info!(%original_msg_id, bytes = content.len(), "Client replied to the server");
+                        trace!(%original_msg_id, %content, "Reply content");
                     }
                     ClientMessage::MessageReceived { msg_id } => {
                         client_manager
                             .handle_message_acknowledgment(*client_id, msg_id)
                             .await;
                     }
+                    ClientMessage::AcceptTerms { topic, version } => {
+                        if let Some(error) = check_subscribe(&topic, permissions) {
+                            client_manager.send_private_message(*client_id, error).await;
+                            return None;
+                        }
+                        if !client_manager
+                            .accept_terms(client_id, topic.clone(), version)
+                            .await
+                        {
+                            let error_msg = ServerMessage::Error {
+                                message: format!(
+                                    "Version {} of the rules of topic '{}' is not current",
+                                    version, topic
+                                ),
+                            };
+                            client_manager
+                                .send_private_message(*client_id, error_msg)
+                                .await;
+                        }
+                    }
+                    ClientMessage::Fetch { group, max } => {
+                        let reply = match client_manager.fetch_for_group(client_id, &group, max) {
+                            Some((topic, batch)) => ServerMessage::Fetched {
+                                topic,
+                                group,
+                                messages: batch
+                                    .into_iter()
+                                    .map(|stored| FetchedMessage {
+                                        seq: stored.seq,
+                                        id: stored.id,
+                                        sender: stored.sender,
+                                        content: stored.content,
+                                        headers: stored.headers,
+                                    })
+                                    .collect(),
+                            },
+                            None => not_subscribed(),
+                        };
+                        client_manager.send_private_message(*client_id, reply).await;
+                    }
+                    ClientMessage::CommitOffset { group, offset } => {
+                        let reply =
+                            match client_manager.commit_group_offset(client_id, &group, offset) {
+                                Some((topic, offset)) => ServerMessage::OffsetCommitted {
+                                    topic,
+                                    group,
+                                    offset,
+                                },
+                                None => not_subscribed(),
+                            };
+                        client_manager.send_private_message(*client_id, reply).await;
+                    }
+                    ClientMessage::RequestMissed { topic, from_seq } => {
+                        send_missed(client_id, &topic, from_seq, client_manager).await;
+                    }
+                    ClientMessage::Resync {
+                        topic,
+                        from_seq,
+                        acked,
+                    } => {
+                        for msg_id in acked {
+                            client_manager
+                                .handle_message_acknowledgment(*client_id, msg_id)
+                                .await;
+                        }
+                        send_missed(client_id, &topic, from_seq, client_manager).await;
+                    }
+                    ClientMessage::CreatePoll {
+                        question,
+                        options,
+                        duration_secs,
+                    } => {
+                        let duration =
+                            duration_secs.map_or(DEFAULT_POLL_DURATION, Duration::from_secs);
+                        let opened = create_poll(
+                            client_id,
+                            question,
+                            options,
+                            duration,
+                            client_manager,
+                            permissions,
+                            rate_limiter,
+                        )
+                        .await;
+                        if let Err(error) = opened {
+                            client_manager.send_private_message(*client_id, error).await;
+                        }
+                    }
+                    ClientMessage::Vote { poll_id, option } => {
+                        let reply = match client_manager.vote(client_id, &poll_id, option) {
+                            Ok(()) => ServerMessage::Voted { poll_id, option },
+                            Err(message) => ServerMessage::Error { message },
+                        };
+                        client_manager.send_private_message(*client_id, reply).await;
+                    }
+                    ClientMessage::ListTopics => {
+                        let topics = client_manager.topic_summaries();
+                        client_manager
+                            .send_private_message(*client_id, ServerMessage::TopicList { topics })
+                            .await;
+                    }
+                    ClientMessage::Negotiate { compress } => {
+                        let compress = compress && client_manager.compression().enabled;
+                        info!(compress, "Client negotiated compression");
+                        client_manager
+                            .send_private_message(
+                                *client_id,
+                                ServerMessage::Negotiated { compress },
+                            )
+                            .await;
+                        if compress {
+                            compression.enable();
+                        }
+                    }
+                    ClientMessage::Compressed { data } => {
+                        let frame = if client_manager.compression().enabled {
+                            compression::inflate(&data, max)
+                        } else {
+                            Err("Compression is disabled on this server".to_string())
+                        };
+                        let frame = frame.and_then(|frame| match msg::kind(&frame) {
+                            Some(kind) if kind == "Compressed" => {
+                                Err("Compressed frames cannot be nested".to_string())
+                            }
+                            _ => Ok(frame),
+                        });
+                        match frame {
+                            Ok(frame) => {
+                                return Box::pin(handle_message(
+                                    client_id,
+                                    Message::text(frame),
+                                    client_manager,
+                                    permissions,
+                                    compression,
+                                    rate_limiter,
+                                    uploads,
+                                ))
+                                .await;
+                            }
+                            Err(message) => {
+                                client_manager
+                                    .send_private_message(
+                                        *client_id,
+                                        ServerMessage::Error { message },
+                                    )
+                                    .await;
+                            }
+                        }
+                    }
+                    ClientMessage::Ping { nonce } => {
+                        client_manager
+                            .send_private_message(*client_id, ServerMessage::Pong { nonce })
+                            .await;
+                    }
+                    ClientMessage::AttachmentStart {
+                        id,
+                        topic,
+                        name,
+                        content_type,
+                        size,
+                    } => {
+                        let action = "sending it attachments";
+                        if let Some(error) =
+                            check_subscribed(client_id, &topic, action, client_manager)
+                        {
+                            client_manager.send_private_message(*client_id, error).await;
+                            return None;
+                        }
+                        if let Some(error) =
+                            check_publish(&topic, client_manager, permissions, rate_limiter)
+                        {
+                            client_manager.send_private_message(*client_id, error).await;
+                            return None;
+                        }
+                        let max = client_manager.policies().limits.max_attachment_bytes;
+                        if let Err(message) = uploads.start(id, topic.clone(), size, max) {
+                            client_manager
+                                .send_private_message(*client_id, ServerMessage::Error { message })
+                                .await;
+                            return None;
+                        }
+                        info!(%topic, %name, size, "Client is sending an attachment");
+                        let message = ServerMessage::AttachmentStart {
+                            id,
+                            topic: topic.clone(),
+                            sender: client_id.to_string(),
+                            name,
+                            content_type,
+                            size,
+                        };
+                        client_manager
+                            .relay_to_topic(&topic, message, *client_id)
+                            .await;
+                    }
+                    ClientMessage::AttachmentComplete { id } => match uploads.complete(id) {
+                        Ok(topic) => {
+                            let message = ServerMessage::AttachmentComplete { id };
+                            client_manager
+                                .relay_to_topic(&topic, message, *client_id)
+                                .await;
+                            client_manager
+                                .send_private_message(*client_id, ServerMessage::Published { id })
+                                .await;
+                        }
+                        Err(error) => {
+                            reject_attachment(client_id, id, error, client_manager).await;
+                        }
+                    },
+                    ClientMessage::PublicKey { topic, key } => {
+                        let action = "announcing a key in it";
+                        if let Some(error) =
+                            check_subscribed(client_id, &topic, action, client_manager)
+                        {
+                            client_manager.send_private_message(*client_id, error).await;
+                            return None;
+                        }
+                        let message = ServerMessage::PublicKey {
+                            topic: topic.clone(),
+                            sender: client_id.to_string(),
+                            key,
+                        };
+                        client_manager
+                            .relay_to_topic(&topic, message, *client_id)
+                            .await;
+                    }
+                    ClientMessage::Kick {
+                        client_id: target,
+                        reason,
+                    } => {
+                        let reply = manage(client_id, permissions, client_manager, |manager| {
+                            let detail = match &reason {
+                                Some(reason) => format!("{} ({})", target, reason),
+                                None => target.to_string(),
+                            };
+                            let result = if manager.kick_client(&target, reason) {
+                                Ok(format!("Kicked client {}", target))
+                            } else {
+                                Err(format!("No connected client {}", target))
+                            };
+                            ("kick", detail, result)
+                        });
+                        client_manager.send_private_message(*client_id, reply).await;
+                    }
+                    ClientMessage::Ban { client_id: target } => {
+                        let reply = manage(client_id, permissions, client_manager, |manager| {
+                            let target = BanTarget::Client(target);
+                            let result = manager.ban(target).map(|kicked| {
+                                format!("Banned {} ({} client(s) kicked)", target, kicked)
+                            });
+                            ("ban", target.to_string(), result)
+                        });
+                        client_manager.send_private_message(*client_id, reply).await;
+                    }
+                    ClientMessage::Request {
+                        topic,
+                        correlation_id,
+                        content,
+                    } => {
+                        let action = "sending it requests";
+                        let refused = check_subscribed(client_id, &topic, action, client_manager)
+                            .or_else(|| {
+                                check_publish(&topic, client_manager, permissions, rate_limiter)
+                            });
+                        if let Some(error) = refused {
+                            client_manager.send_private_message(*client_id, error).await;
+                            return None;
+                        }
+                        if let Err(message) = client_manager
+                            .send_request(*client_id, &topic, correlation_id, content)
+                            .await
+                        {
+                            client_manager
+                                .send_private_message(*client_id, ServerMessage::Error { message })
+                                .await;
+                        }
+                    }
+                    ClientMessage::Respond {
+                        correlation_id,
+                        content,
+                    } => {
+                        let refused = match client_manager.client_topic(client_id) {
+                            Some(topic) => {
+                                check_publish(&topic, client_manager, permissions, rate_limiter)
+                            }
+                            None => Some(ServerMessage::Error {
+                                message: "Subscribe to a topic before responding to requests"
+                                    .to_string(),
+                            }),
+                        };
+                        if let Some(error) = refused {
+                            client_manager.send_private_message(*client_id, error).await;
+                            return None;
+                        }
+                        if let Err(message) = client_manager
+                            .respond(client_id, correlation_id, content)
+                            .await
+                        {
+                            client_manager
+                                .send_private_message(*client_id, ServerMessage::Error { message })
+                                .await;
+                        }
+                    }
+                    ClientMessage::Authenticate { .. } => {
+                        let error_msg = ServerMessage::Error {
+                            message: "Authenticate only as the first message".to_string(),
+                        };
+                        client_manager
+                            .send_private_message(*client_id, error_msg)
+                            .await;
+                    }
+                    ClientMessage::Unknown => {
+                        handle_custom_verb(client_id, text, client_manager).await;
+                    }
                 }
             }
             Err(e) => {
-                eprintln!(
-                    "Error deserializing message from client {}: {}",
-                    client_id, e
-                );
+                warn!("Error deserializing a message: {}", e);
                 let error_msg = ServerMessage::Error {
                     message: "Invalid message format".to_string(),
                 };
@@ -87,4 +780,344 @@ async fn handle_message(client_id: &Uuid, msg: Message, client_manager: &Arc<Cli
             }
         }
     }
+    None
+}
+
+/// Routes a message of a type the protocol does not define to the handler the embedding
+/// program registered for it, if any.
+async fn handle_custom_verb(client_id: &Uuid, text: &str, client_manager: &Arc<ClientManager>) {
+    let handler = msg::decode_untyped(text)
+        .ok()
+        .and_then(|(verb, payload)| Some((client_manager.verbs().get(&verb)?, payload)));
+    let result = match handler {
+        Some((handler, payload)) => {
+            let client = ClientHandle::new(*client_id, client_manager.client_topic(client_id));
+            handler.handle(&client, payload, client_manager).await
+        }
+        None => Err("Unsupported message type".to_string()),
+    };
+    if let Err(message) = result {
+        let error_msg = ServerMessage::Error { message };
+        client_manager
+            .send_private_message(*client_id, error_msg)
+            .await;
+    }
+}
+
+/// Relays an attachment chunk to the topic its attachment was announced for, aborting
+/// the attachment if the chunk is out of order or beyond the announced size.
+async fn relay_chunk(
+    client_id: &Uuid,
+    frame: Vec<u8>,
+    client_manager: &ClientManager,
+    uploads: &mut Uploads,
+) {
+    let Some((id, index, len)) =
+        decode_chunk(&frame).map(|(id, index, data)| (id, index, data.len()))
+    else {
+        return;
+    };
+    match uploads.chunk(id, index, len) {
+        Ok(topic) => {
+            let topic = topic.to_string();
+            let message = ServerMessage::AttachmentChunk {
+                frame: frame.into(),
+            };
+            client_manager
+                .relay_to_topic(&topic, message, *client_id)
+                .await;
+        }
+        Err(error) => reject_attachment(client_id, id, error, client_manager).await,
+    }
+}
+
+/// Tells a client why its attachment chunk or completion was not accepted, and the
+/// topic that the attachment was aborted, if it was.
+async fn reject_attachment(
+    client_id: &Uuid,
+    id: Uuid,
+    error: ChunkError,
+    client_manager: &ClientManager,
+) {
+    let message = match error {
+        ChunkError::Unknown(id) => format!("Attachment {} was not started", id),
+        ChunkError::Aborted { topic, reason } => {
+            abort_attachment(client_id, id, &topic, &reason, client_manager).await;
+            format!("Attachment {} aborted: {}", id, reason)
+        }
+    };
+    client_manager
+        .send_private_message(*client_id, ServerMessage::Error { message })
+        .await;
+}
+
+/// Tells the other subscribers of `topic` to drop what they received of an attachment.
+async fn abort_attachment(
+    client_id: &Uuid,
+    id: Uuid,
+    topic: &str,
+    reason: &str,
+    client_manager: &ClientManager,
+) {
+    info!(attachment_id = %id, %client_id, %reason, "Attachment was aborted");
+    let message = ServerMessage::AttachmentAborted {
+        id,
+        reason: reason.to_string(),
+    };
+    client_manager
+        .relay_to_topic(topic, message, *client_id)
+        .await;
+}
+
+/// Sends the message of the day, if one is set, to a client that just subscribed.
+async fn send_motd(client_id: &Uuid, client_manager: &ClientManager) {
+    if let Some(content) = client_manager.motd() {
+        client_manager
+            .send_private_message(*client_id, ServerMessage::Motd { content })
+            .await;
+    }
+}
+
+/// Opens a poll from a client in the topic it is subscribed to, which it must be
+/// allowed to publish to. Returns the error to send back otherwise.
+async fn create_poll(
+    client_id: &Uuid,
+    question: String,
+    options: Vec<String>,
+    duration: Duration,
+    client_manager: &ClientManager,
+    permissions: &Permissions,
+    rate_limiter: &mut Option<TokenBucket>,
+) -> Result<(), ServerMessage> {
+    let topic = client_manager
+        .client_topic(client_id)
+        .ok_or_else(|| ServerMessage::Error {
+            message: "Subscribe to a topic before opening a poll".to_string(),
+        })?;
+    if let Some(error) = check_publish(&topic, client_manager, permissions, rate_limiter) {
+        return Err(error);
+    }
+    let poll = Poll::new(topic, question, options, client_id.to_string(), duration)
+        .map_err(|message| ServerMessage::Error { message })?;
+    info!(poll_id = %poll.id, topic = %poll.topic, question = %poll.question, "Client opened a poll");
+    client_manager.open_poll(poll).await;
+    Ok(())
+}
+
+/// Carries out a management message from a client with the admin role: `action` returns
+/// the audit log's action, detail and result. Returns the reply to the client, a private
+/// message with the result or an error.
+fn manage(
+    client_id: &Uuid,
+    permissions: &Permissions,
+    client_manager: &ClientManager,
+    action: impl FnOnce(&ClientManager) -> (&'static str, String, Result<String, String>),
+) -> ServerMessage {
+    if !permissions.has_admin_role() {
+        return ServerMessage::Error {
+            message: "Only clients with the admin role may manage other clients".to_string(),
+        };
+    }
+    let (name, detail, result) = action(client_manager);
+    info!(action = name, %detail, ?result, "Client ran a management action");
+    let detail = format!("{} (by client {})", detail, client_id);
+    client_manager.audit().record(name, &detail, result.clone());
+    match result {
+        Ok(content) => ServerMessage::Private {
+            id: Uuid::new_v4(),
+            content,
+        },
+        Err(message) => ServerMessage::Error { message },
+    }
+}
+
+/// Returns the error to send back if a client may not subscribe to `topic`.
+fn check_subscribe(topic: &str, permissions: &Permissions) -> Option<ServerMessage> {
+    (!permissions.may_subscribe(topic)).then(|| ServerMessage::Error {
+        message: format!("Not allowed to subscribe to topic '{}'", topic),
+    })
+}
+
+/// Returns the error to send back if a client may not publish to `topic` right now.
+fn check_publish(
+    topic: &str,
+    client_manager: &ClientManager,
+    permissions: &Permissions,
+    rate_limiter: &mut Option<TokenBucket>,
+) -> Option<ServerMessage> {
+    let policies = client_manager.policies();
+    // Follow rate limit changes made by a config reload.
+    if rate_limiter.as_ref().map(TokenBucket::limit) != policies.rate_limit {
+        *rate_limiter = policies.rate_limit.map(TokenBucket::new);
+    }
+    let message = if !permissions.may_publish(topic) {
+        format!("Not allowed to publish to topic '{}'", topic)
+    } else if policies.is_read_only(topic) && !permissions.has_admin_role() {
+        format!("Topic '{}' is read-only", topic)
+    } else if rate_limiter
+        .as_mut()
+        .is_some_and(|bucket| !bucket.try_acquire(Instant::now()))
+    {
+        "Rate limit exceeded, message dropped".to_string()
+    } else {
+        return None;
+    };
+    Some(ServerMessage::Error { message })
+}
+
+/// Returns the error to send back if a client is not subscribed to `topic`, which
+/// `action` needs.
+fn check_subscribed(
+    client_id: &Uuid,
+    topic: &str,
+    action: &str,
+    client_manager: &ClientManager,
+) -> Option<ServerMessage> {
+    (client_manager.client_topic(client_id).as_deref() != Some(topic)).then(|| {
+        ServerMessage::Error {
+            message: format!("Subscribe to topic '{}' before {}", topic, action),
+        }
+    })
+}
+
+/// Returns the error to send back if a message to `topic` replies to a message of
+/// another topic. Replies to messages no longer in history are let through.
+fn check_reply(
+    topic: &str,
+    reply_to: Option<Uuid>,
+    client_manager: &ClientManager,
+) -> Option<ServerMessage> {
+    let original = client_manager.get_history_message(&reply_to?)?;
+    (original.topic != topic).then(|| ServerMessage::Error {
+        message: format!(
+            "Message {} is not in topic '{}', replies must stay in its topic",
+            original.id, topic
+        ),
+    })
+}
+
+/// Sends a client the retained messages of `topic` it asked for again, preceded by an
+/// error naming those that are no longer retained.
+async fn send_missed(
+    client_id: &Uuid,
+    topic: &str,
+    from_seq: u64,
+    client_manager: &Arc<ClientManager>,
+) {
+    let Some(missed) = client_manager.missed_messages(client_id, topic, from_seq) else {
+        let error_msg = ServerMessage::Error {
+            message: format!(
+                "Subscribe to topic '{}' before requesting its missed messages",
+                topic
+            ),
+        };
+        client_manager
+            .send_private_message(*client_id, error_msg)
+            .await;
+        return;
+    };
+    let from_seq = from_seq.max(1);
+    if let Some(first) = missed.first().filter(|first| first.seq > from_seq) {
+        let error_msg = ServerMessage::Error {
+            message: format!(
+                "Messages {} to {} of topic '{}' are no longer retained",
+                from_seq,
+                first.seq - 1,
+                topic
+            ),
+        };
+        client_manager
+            .send_private_message(*client_id, error_msg)
+            .await;
+    }
+    for message in &missed {
+        client_manager
+            .send_private_message(*client_id, message.to_server_message())
+            .await;
+    }
+}
+
+/// The reply to consumer group requests from clients without a topic.
+fn not_subscribed() -> ServerMessage {
+    ServerMessage::Error {
+        message: "Subscribe to a topic before using consumer groups".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_token() {
+        let query = HashMap::from([("token".to_string(), "abc".to_string())]);
+        assert_eq!(request_token(&query, Some("Bearer xyz")), Some("abc"));
+        assert_eq!(
+            request_token(&HashMap::new(), Some("Bearer xyz")),
+            Some("xyz")
+        );
+        assert_eq!(request_token(&HashMap::new(), Some("Basic xyz")), None);
+        assert_eq!(request_token(&HashMap::new(), None), None);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_frames_leave_the_connection_usable() {
+        use crate::core::storage::InMemoryStorage;
+
+        let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+        let (client_id, _rx) = client_manager.add_internal_client();
+        let (observer_id, mut observer_rx) = client_manager.add_internal_client();
+        client_manager
+            .join_topic(&observer_id, "news".to_string())
+            .await;
+        let mut frames: Vec<Message> = [
+            "",
+            "{",
+            "null",
+            "[]",
+            "\"message\"",
+            r#"{"type":"Message"}"#,
+            r#"{"type":"Message","payload":{"topic":1,"content":null}}"#,
+            r#"{"type":"Connect","payload":{"topic":"news"},"v":4294967296}"#,
+            r#"{"type":"Connect","payload":{"topic":"news"},"meta":[]}"#,
+            r#"{"type":"MessageReceived","payload":{"msg_id":"not-a-uuid"}}"#,
+            r#"{"type":"AttachmentStart","payload":{"id":"00000000-0000-0000-0000-000000000007","topic":"news","name":"../..","content_type":"","size":18446744073709551615}}"#,
+            r#"{"type":"AttachmentComplete","payload":{"id":"00000000-0000-0000-0000-000000000000"}}"#,
+            r#"{"type":"no_such_type","payload":{"deeply":[[[[[[]]]]]]}}"#,
+            "\u{0}\u{feff}{\"type\":\"Connect\"}",
+        ]
+        .into_iter()
+        .map(Message::text)
+        .collect();
+        frames.push(Message::text("[".repeat(100_000)));
+        frames.push(Message::binary(vec![0xff; 64]));
+        frames.push(Message::binary(Vec::new()));
+        frames.push(Message::text(
+            r#"{"type":"Connect","payload":{"topic":"news"}}"#,
+        ));
+        frames.push(Message::text(
+            r#"{"type":"Message","payload":{"topic":"news","content":"still here"}}"#,
+        ));
+
+        let handled = tokio::time::timeout(
+            Duration::from_secs(5),
+            handle_frames(client_id, frames, &client_manager),
+        )
+        .await;
+
+        assert_eq!(handled.ok(), Some(client_id));
+        let delivered = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match observer_rx.recv().await {
+                    Some(ServerMessage::Topic { content, .. }) if content == "still here" => {
+                        break true
+                    }
+                    Some(_) => {}
+                    None => break false,
+                }
+            }
+        })
+        .await;
+        assert_eq!(delivered.ok(), Some(true));
+    }
 }
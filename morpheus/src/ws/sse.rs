@@ -0,0 +1,94 @@
+use crate::core::{
+    bans::BanTarget,
+    client_manager::{ClientManager, BANNED_REASON},
+    msg::{self, ServerMessage},
+    queue::QueueReceiver,
+};
+use futures_util::stream;
+use std::{convert::Infallible, net::IpAddr, sync::Arc};
+use tracing::info;
+use uuid::Uuid;
+use warp::{
+    http::StatusCode,
+    reply::{self, Response},
+    sse::Event,
+    Reply,
+};
+
+/// A read-only subscriber fed through a pseudo-client, unregistered once its stream ends.
+struct SseClient {
+    id: Uuid,
+    client_manager: Arc<ClientManager>,
+    receiver: QueueReceiver,
+}
+
+impl Drop for SseClient {
+    fn drop(&mut self) {
+        self.client_manager.remove_client(&self.id);
+    }
+}
+
+/// Subscribes a read-only client to `topic` and streams the topic's messages to it as
+/// Server-Sent Events, each carrying the same JSON envelope WebSocket clients receive.
+/// Messages are acknowledged as soon as they are written to the stream.
+pub fn subscribe(
+    client_manager: Arc<ClientManager>,
+    topic: String,
+    ip: Option<IpAddr>,
+) -> Response {
+    if ip.is_some_and(|ip| client_manager.bans().is_banned(&BanTarget::Ip(ip))) {
+        return refuse(StatusCode::FORBIDDEN, BANNED_REASON.to_string());
+    }
+    // Accepting rules needs a message from the client, which SSE cannot send.
+    if client_manager.policies().rules(&topic).is_some() {
+        return refuse(
+            StatusCode::FORBIDDEN,
+            format!(
+                "Topic '{}' has rules that must be accepted over a WebSocket",
+                topic
+            ),
+        );
+    }
+    let (id, receiver) = match client_manager.add_pseudo_client(ip) {
+        Ok(client) => client,
+        Err(exceeded) => {
+            return refuse(
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("The server is full (limit {})", exceeded.max),
+            )
+        }
+    };
+    let client = SseClient {
+        id,
+        client_manager: client_manager.clone(),
+        receiver,
+    };
+    if let Err(exceeded) = client_manager.subscribe_client_to_topic(&id, topic.clone()) {
+        return refuse(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("Topic '{}' is full (limit {})", topic, exceeded.max),
+        );
+    }
+    info!(client_id = %id, %topic, "SSE client subscribed");
+    client_manager.send_retained(&id, &topic);
+
+    let events = stream::unfold(Some(client), |client| async move {
+        let mut client = client?;
+        let message = client.receiver.recv().await?;
+        if let Some(msg_id) = message.id() {
+            client
+                .client_manager
+                .handle_message_acknowledgment(client.id, msg_id)
+                .await;
+        }
+        let event = Event::default().data(msg::encode(&message).ok()?);
+        // A kicked client gets the reason before its stream ends.
+        let next = (!matches!(message, ServerMessage::Kicked { .. })).then_some(client);
+        Some((Ok::<_, Infallible>(event), next))
+    });
+    warp::sse::reply(warp::sse::keep_alive().stream(events)).into_response()
+}
+
+fn refuse(status: StatusCode, reason: String) -> Response {
+    reply::with_status(reason, status).into_response()
+}
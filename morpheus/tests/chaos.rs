@@ -0,0 +1,252 @@
+//! Reconnection, redelivery and cleanup under injected faults. Time is paused, so the
+//! redelivery sweeps and delivery latencies pass instantly, and the faults are seeded, so
+//! every run sees the same ones.
+
+use morpheus::{
+    core::{
+        client_manager::ClientManager,
+        msg::{Qos, ServerMessage},
+        qos,
+        storage::{InMemoryStorage, Storage},
+    },
+    testing::chaos::{ChaosClient, ChaosStorage, Faults},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::time::timeout;
+use uuid::Uuid;
+
+/// Longer than any test here needs to get a message through, in paused time.
+const STALLED_AFTER: Duration = Duration::from_secs(600);
+
+fn manager(storage: Arc<dyn Storage>) -> Arc<ClientManager> {
+    Arc::new(ClientManager::new(storage))
+}
+
+fn news(n: u128) -> ServerMessage {
+    ServerMessage::Topic {
+        id: Uuid::from_u128(n),
+        topic: "news".to_string(),
+        seq: 0,
+        sender: "Morpheus".to_string(),
+        content: format!("message {}", n),
+        headers: BTreeMap::new(),
+        qos: Qos::AtLeastOnce,
+        reply_to: None,
+        retained: false,
+    }
+}
+
+fn is_connected(client_manager: &ClientManager, client_id: Uuid) -> bool {
+    client_manager
+        .get_all_clients()
+        .iter()
+        .any(|client| client.id == client_id)
+}
+
+/// A subscriber to "news" with a session, which acknowledges every message it gets and
+/// resumes its session whenever its connection is cut.
+struct Subscriber {
+    client: ChaosClient,
+    faults: Faults,
+    session_id: Uuid,
+    received: BTreeSet<Uuid>,
+    reconnects: u64,
+}
+
+impl Subscriber {
+    async fn connect(client_manager: &Arc<ClientManager>, faults: Faults) -> Self {
+        let client = ChaosClient::connect(client_manager, &faults);
+        let client_id = client.connect_session(client_manager, "news", None).await;
+        let session_id = client_manager
+            .get_all_clients()
+            .into_iter()
+            .find(|client| client.id == client_id)
+            .and_then(|client| client.session_id)
+            .expect("the client has a session");
+        Self {
+            client,
+            faults,
+            session_id,
+            received: BTreeSet::new(),
+            reconnects: 0,
+        }
+    }
+
+    /// Waits for the next message that makes it through, reconnecting as often as it
+    /// takes.
+    async fn next(&mut self, client_manager: &Arc<ClientManager>) {
+        let message = timeout(STALLED_AFTER, self.client.recv())
+            .await
+            .expect("no message got through");
+        match message {
+            Some(ServerMessage::Topic { id, .. }) => {
+                client_manager
+                    .handle_message_acknowledgment(self.client.id(), id)
+                    .await;
+                self.received.insert(id);
+            }
+            Some(_) => {}
+            None => self.reconnect(client_manager).await,
+        }
+    }
+
+    /// Reads what got through before the connection was cut, if it was, and resumes the
+    /// session on a new one.
+    async fn ensure_connected(&mut self, client_manager: &Arc<ClientManager>) {
+        while !is_connected(client_manager, self.client.id()) {
+            self.next(client_manager).await;
+        }
+    }
+
+    async fn reconnect(&mut self, client_manager: &Arc<ClientManager>) {
+        let id = self.client.id();
+        self.reconnects += 1;
+        let faults = Faults {
+            seed: self.faults.seed + self.reconnects,
+            ..self.faults.clone()
+        };
+        self.client = ChaosClient::connect(client_manager, &faults);
+        let resumed = self
+            .client
+            .connect_session(client_manager, "news", Some(self.session_id))
+            .await;
+        assert_eq!(resumed, id, "the session was not resumed");
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_at_least_once_messages_reach_a_lossy_subscriber() {
+    let client_manager = manager(Arc::new(InMemoryStorage::new()));
+    let faults = Faults {
+        seed: 7,
+        max_latency: Duration::from_millis(300),
+        drop_rate: 0.4,
+        ..Faults::default()
+    };
+    let mut subscriber = Subscriber::connect(&client_manager, faults).await;
+    let redelivery = qos::spawn_redelivery(client_manager.clone(), Duration::ZERO);
+
+    // One message at a time, so the faults fall on the same deliveries every run.
+    for n in 0..20 {
+        client_manager
+            .broadcast_to_topic("news", news(n), None)
+            .await;
+        while !subscriber.received.contains(&Uuid::from_u128(n)) {
+            subscriber.next(&client_manager).await;
+        }
+    }
+
+    assert_eq!(subscriber.received.len(), 20);
+    assert_eq!(client_manager.unacked_qos_count(), 0);
+    redelivery.abort();
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_sessions_resume_with_their_messages_after_random_disconnects() {
+    let client_manager = manager(Arc::new(InMemoryStorage::new()));
+    let faults = Faults {
+        seed: 11,
+        max_latency: Duration::from_millis(300),
+        drop_rate: 0.2,
+        disconnect_rate: 0.2,
+        ..Faults::default()
+    };
+    let mut subscriber = Subscriber::connect(&client_manager, faults).await;
+    let redelivery = qos::spawn_redelivery(client_manager.clone(), Duration::ZERO);
+
+    for n in 0..20 {
+        // A message published between connections is not the session's to receive.
+        subscriber.ensure_connected(&client_manager).await;
+        client_manager
+            .broadcast_to_topic("news", news(n), None)
+            .await;
+        while !subscriber.received.contains(&Uuid::from_u128(n)) {
+            subscriber.next(&client_manager).await;
+        }
+    }
+
+    assert!(subscriber.reconnects > 0);
+    assert_eq!(subscriber.received.len(), 20);
+    redelivery.abort();
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_random_disconnects_leave_nothing_behind() {
+    let faults = Faults {
+        seed: 3,
+        max_latency: Duration::from_millis(100),
+        drop_rate: 0.2,
+        disconnect_rate: 0.1,
+        lost_write_rate: 0.3,
+    };
+    let storage = Arc::new(ChaosStorage::new(Arc::new(InMemoryStorage::new()), &faults));
+    let client_manager = manager(storage.clone());
+    let mut clients = Vec::new();
+    for seed in 0..8 {
+        let faults = Faults {
+            seed,
+            ..faults.clone()
+        };
+        let client = ChaosClient::connect(&client_manager, &faults);
+        client.connect_session(&client_manager, "news", None).await;
+        clients.push(client);
+    }
+    let redelivery = qos::spawn_redelivery(client_manager.clone(), Duration::ZERO);
+
+    // Publish until every connection has been cut.
+    let mut n = 0;
+    while !client_manager.get_all_clients().is_empty() {
+        assert!(n < 1000, "some connections were never cut");
+        client_manager
+            .broadcast_to_topic("news", news(n), None)
+            .await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        n += 1;
+    }
+
+    assert!(client_manager.get_all_topics().is_empty());
+    assert_eq!(client_manager.unacked_qos_count(), 0);
+    assert_eq!(storage.pending_ack_count(), 0);
+    for mut client in clients {
+        while timeout(STALLED_AFTER, client.recv())
+            .await
+            .expect("the connection was not closed")
+            .is_some()
+        {}
+    }
+    redelivery.abort();
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_a_lost_session_starts_afresh() {
+    let faults = Faults {
+        lost_write_rate: 1.0,
+        ..Faults::default()
+    };
+    let storage = Arc::new(ChaosStorage::new(Arc::new(InMemoryStorage::new()), &faults));
+    let client_manager = manager(storage);
+    let subscriber = Subscriber::connect(&client_manager, Faults::default()).await;
+    let id = subscriber.client.id();
+    client_manager.remove_client(&id);
+
+    let mut client = ChaosClient::connect(&client_manager, &Faults::default());
+    let client_id = client
+        .connect_session(&client_manager, "news", Some(subscriber.session_id))
+        .await;
+    assert_ne!(client_id, id);
+    match client.recv().await {
+        Some(ServerMessage::Welcome {
+            resumed,
+            session_id,
+            ..
+        }) => {
+            assert!(!resumed);
+            assert_ne!(session_id, subscriber.session_id);
+        }
+        other => panic!("expected a Welcome, got {:?}", other),
+    }
+}
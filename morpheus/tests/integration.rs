@@ -3,115 +3,26 @@
 
 use anyhow::Result;
 use futures_util::{SinkExt, StreamExt};
-use morpheus::core::{
-    client_manager::ClientManager,
-    msg::{ClientMessage, ServerMessage},
-    storage::InMemoryStorage,
+use morpheus::{
+    core::{
+        client_manager::ClientManager,
+        compression::{self, CompressionConfig},
+        msg::{self, ClientMessage, CloseCode, Qos, ServerMessage},
+        storage::InMemoryStorage,
+    },
+    testing::{free_port, TestClient, TestHarness},
 };
-use std::{sync::Arc, time::Duration};
-use tokio::net::TcpListener;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
 use tokio::net::TcpStream;
 use tokio::sync::OnceCell;
-use tokio_tungstenite::{
-    connect_async,
-    tungstenite::{protocol::Message, Error as WsError},
-    MaybeTlsStream, WebSocketStream,
-};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream};
 use uuid::Uuid;
 use warp::Filter;
 
-struct TestHarness {
-    port: u16,
-    client_manager: Arc<ClientManager>,
-}
-
 static HARNESS: OnceCell<TestHarness> = OnceCell::const_new();
 
-async fn find_free_port() -> u16 {
-    TcpListener::bind("127.0.0.1:0")
-        .await
-        .unwrap()
-        .local_addr()
-        .unwrap()
-        .port()
-}
-
 async fn setup_server() -> &'static TestHarness {
-    HARNESS
-        .get_or_init(|| async {
-            let port = find_free_port().await;
-            let addr = format!("127.0.0.1:{}", port);
-
-            let storage = Arc::new(InMemoryStorage::new());
-            let client_manager = Arc::new(ClientManager::new(storage));
-            let server_client_manager = client_manager.clone();
-
-            tokio::spawn(async move {
-                let ws_route = warp::path("ws")
-                    .and(warp::ws())
-                    .and(warp::any().map(move || server_client_manager.clone()))
-                    .map(|ws: warp::ws::Ws, manager| {
-                        ws.on_upgrade(move |socket| {
-                            morpheus::ws::handler::client_connected(socket, manager)
-                        })
-                    });
-
-                warp::serve(ws_route)
-                    .run(addr.parse::<std::net::SocketAddr>().unwrap())
-                    .await;
-            });
-
-            tokio::time::sleep(Duration::from_millis(100)).await;
-
-            TestHarness {
-                port,
-                client_manager,
-            }
-        })
-        .await
-}
-
-struct TestClient {
-    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
-}
-
-impl TestClient {
-    async fn new(port: u16, topic: &str) -> Result<Self> {
-        let url = format!("ws://127.0.0.1:{}/ws", port);
-        let (mut ws, _) = connect_async(&url).await?;
-
-        let connect_msg = ClientMessage::Connect {
-            topic: topic.to_string(),
-        };
-        let connect_msg_str = serde_json::to_string(&connect_msg)?;
-        ws.send(Message::Text(connect_msg_str)).await?;
-
-        Ok(Self { ws })
-    }
-
-    async fn send_message(&mut self, topic: &str, content: &str) -> Result<()> {
-        let msg = ClientMessage::Message {
-            topic: topic.to_string(),
-            content: content.to_string(),
-        };
-        let msg_str = serde_json::to_string(&msg)?;
-        self.ws.send(Message::Text(msg_str)).await?;
-        Ok(())
-    }
-
-    async fn recv(&mut self) -> Result<Option<ServerMessage>, WsError> {
-        let msg = self.ws.next().await;
-        match msg {
-            Some(Ok(Message::Text(text))) => Ok(Some(serde_json::from_str(&text).unwrap())),
-            Some(Err(e)) => Err(e),
-            _ => Ok(None),
-        }
-    }
-
-    async fn close(mut self) -> Result<()> {
-        self.ws.close(None).await?;
-        Ok(())
-    }
+    HARNESS.get_or_init(TestHarness::start).await
 }
 
 #[tokio::test]
@@ -136,8 +47,8 @@ async fn all_integration_tests() -> Result<()> {
 async fn test_topic_messaging(harness: &TestHarness) -> Result<()> {
     let topic = &format!("cats-{}", Uuid::new_v4());
 
-    let mut client1 = TestClient::new(harness.port, topic).await?;
-    let mut client2 = TestClient::new(harness.port, topic).await?;
+    let mut client1 = TestClient::connect(harness.port, topic).await?;
+    let mut client2 = TestClient::connect(harness.port, topic).await?;
 
     for _ in 0..10 {
         if harness.client_manager.get_clients_by_topic(topic).len() == 2 {
@@ -171,8 +82,8 @@ async fn test_topic_messaging(harness: &TestHarness) -> Result<()> {
 async fn test_global_message(harness: &TestHarness) -> Result<()> {
     let topic1 = &format!("topic1-{}", Uuid::new_v4());
     let topic2 = &format!("topic2-{}", Uuid::new_v4());
-    let mut client1 = TestClient::new(harness.port, topic1).await?;
-    let mut client2 = TestClient::new(harness.port, topic2).await?;
+    let mut client1 = TestClient::connect(harness.port, topic1).await?;
+    let mut client2 = TestClient::connect(harness.port, topic2).await?;
 
     for _ in 0..10 {
         if harness.client_manager.get_clients_by_topic(topic1).len() == 1
@@ -217,7 +128,7 @@ async fn test_global_message(harness: &TestHarness) -> Result<()> {
 
 async fn test_private_message(harness: &TestHarness) -> Result<()> {
     let topic = &format!("general-{}", Uuid::new_v4());
-    let mut client1 = TestClient::new(harness.port, topic).await?;
+    let mut client1 = TestClient::connect(harness.port, topic).await?;
 
     let mut client1_id = None;
     for _ in 0..10 {
@@ -231,7 +142,7 @@ async fn test_private_message(harness: &TestHarness) -> Result<()> {
     }
     let client1_id = client1_id.expect("Client 1 not found in time");
 
-    let mut client2 = TestClient::new(harness.port, topic).await?;
+    let mut client2 = TestClient::connect(harness.port, topic).await?;
 
     for _ in 0..10 {
         if harness.client_manager.get_clients_by_topic(topic).len() == 2 {
@@ -270,3 +181,1870 @@ async fn test_private_message(harness: &TestHarness) -> Result<()> {
     client2.close().await?;
     Ok(())
 }
+
+#[tokio::test]
+async fn test_unresponsive_client_is_reaped() -> Result<()> {
+    use morpheus::core::client_manager::Heartbeat;
+
+    let heartbeat = Heartbeat {
+        interval: Duration::from_millis(100),
+        timeout: Duration::from_millis(100),
+    };
+    let client_manager =
+        Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())).with_heartbeat(heartbeat));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    // This client keeps reading, so tungstenite answers the server's pings.
+    let topic = &format!("heartbeat-{}", Uuid::new_v4());
+    let mut responsive = TestClient::connect(port, topic).await?;
+    let reader = tokio::spawn(async move { while let Some(Ok(_)) = responsive.ws.next().await {} });
+
+    // This client never reads, so its pongs are never sent.
+    let _silent = TestClient::connect(port, topic).await?;
+
+    for _ in 0..10 {
+        if client_manager.get_clients_by_topic(topic).len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(client_manager.get_clients_by_topic(topic).len(), 2);
+
+    tokio::time::sleep(heartbeat.idle_deadline() * 4).await;
+    assert_eq!(
+        client_manager.get_clients_by_topic(topic).len(),
+        1,
+        "The silent client should have been reaped"
+    );
+
+    reader.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cluster_forwards_topic_messages() -> Result<()> {
+    use morpheus::core::cluster::{Cluster, ClusterConfig};
+
+    // Node B accepts peers; node A dials B, so A's topic messages reach B's clients.
+    let node_b = Arc::new(
+        ClientManager::new(Arc::new(InMemoryStorage::new())).with_cluster(Cluster::start(
+            ClusterConfig {
+                node_id: "b".to_string(),
+                peers: Vec::new(),
+                secret: Some("s3cret".to_string()),
+            },
+        )),
+    );
+    let port_b = TestHarness::serve(node_b.clone()).await.port;
+    let node_a = Arc::new(
+        ClientManager::new(Arc::new(InMemoryStorage::new())).with_cluster(Cluster::start(
+            ClusterConfig {
+                node_id: "a".to_string(),
+                peers: vec![format!("ws://127.0.0.1:{}/cluster", port_b)],
+                secret: Some("s3cret".to_string()),
+            },
+        )),
+    );
+    let port_a = TestHarness::serve(node_a.clone()).await.port;
+
+    let topic = &format!("cluster-{}", Uuid::new_v4());
+    let mut publisher = TestClient::connect(port_a, topic).await?;
+    let mut subscriber = TestClient::connect(port_b, topic).await?;
+    for _ in 0..20 {
+        if node_a.get_clients_by_topic(topic).len() == 1
+            && node_b.get_clients_by_topic(topic).len() == 1
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    // Give the cluster link time to come up.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    publisher.send_message(topic, "across nodes").await?;
+
+    let received = tokio::time::timeout(Duration::from_secs(2), subscriber.recv()).await??;
+    match received {
+        Some(ServerMessage::Topic { content, .. }) => assert_eq!(content, "across nodes"),
+        other => panic!("Unexpected message {:?}", other),
+    }
+
+    publisher.close().await?;
+    subscriber.close().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mirrors_republish_topics_without_looping() -> Result<()> {
+    use morpheus::core::mirror::{
+        LoopGuard, MirrorConfig, MirrorForwarder, SENDER_HEADER, VIA_HEADER,
+    };
+
+    // A and B mirror the same topic to each other; each message must cross only once.
+    let topic = &format!("mirror-{}", Uuid::new_v4());
+    let node_a = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let node_b = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port_a = TestHarness::serve(node_a.clone()).await.port;
+    let port_b = TestHarness::serve(node_b.clone()).await.port;
+    for (node, port, id) in [(&node_a, port_b, "a"), (&node_b, port_a, "b")] {
+        let mirror = MirrorConfig {
+            topic: topic.to_string(),
+            url: format!("ws://127.0.0.1:{}/ws", port),
+            token: None,
+        };
+        let forwarder = MirrorForwarder::start(&[mirror], id.to_string());
+        node.history_hooks().register(Arc::new(forwarder));
+        let guard = LoopGuard::new(id.to_string());
+        node.message_hooks().register(Arc::new(guard));
+    }
+
+    let mut publisher = TestClient::connect(port_a, topic).await?;
+    let mut subscriber = TestClient::connect(port_b, topic).await?;
+    // Give the mirror links time to come up.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    publisher.send_message(topic, "replicated").await?;
+
+    let received = tokio::time::timeout(Duration::from_secs(2), subscriber.recv()).await??;
+    let Some(ServerMessage::Topic {
+        content, headers, ..
+    }) = received
+    else {
+        panic!("Unexpected message {:?}", received);
+    };
+    assert_eq!(content, "replicated");
+    assert_eq!(headers[VIA_HEADER], "a");
+    assert!(headers.contains_key(SENDER_HEADER));
+
+    // A refuses it when B sends it back.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    for node in [&node_a, &node_b] {
+        let copies = node
+            .get_topic_history(topic, 10)
+            .into_iter()
+            .filter(|m| m.content == "replicated")
+            .count();
+        assert_eq!(copies, 1);
+    }
+
+    publisher.close().await?;
+    subscriber.close().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_federated_topics_are_relayed_both_ways() -> Result<()> {
+    use morpheus::core::{
+        federation::{Federation, FederationConfig},
+        mirror::{LoopGuard, VIA_HEADER},
+        provenance::Origin,
+    };
+
+    // Only A is configured; B accepts A's links as ordinary clients.
+    let topic = &format!("federated-{}", Uuid::new_v4());
+    let other_topic = format!("federated-{}", Uuid::new_v4());
+    let node_a = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let node_b = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port_a = TestHarness::serve(node_a.clone()).await.port;
+    let port_b = TestHarness::serve(node_b.clone()).await.port;
+    let link = FederationConfig {
+        url: format!("ws://127.0.0.1:{}/ws", port_b),
+        topics: vec![topic.to_string(), other_topic],
+        token: None,
+    };
+    let bridge = link.bridge();
+    let federation = Federation::start(&[link], "a".to_string(), node_a.clone());
+    node_a.history_hooks().register(Arc::new(federation));
+    node_a
+        .message_hooks()
+        .register(Arc::new(LoopGuard::new("a".to_string())));
+
+    let mut alice = TestClient::connect(port_a, topic).await?;
+    let mut bob = TestClient::connect(port_b, topic).await?;
+    // Give the link time to come up and subscribe.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    alice.send_message(topic, "from a").await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), bob.recv()).await??;
+    let Some(ServerMessage::Topic {
+        content, headers, ..
+    }) = received
+    else {
+        panic!("Unexpected message {:?}", received);
+    };
+    assert_eq!(content, "from a");
+    assert_eq!(headers[VIA_HEADER], "a");
+
+    bob.send_message(topic, "from b").await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), alice.recv()).await??;
+    let Some(ServerMessage::Topic { id, content, .. }) = received else {
+        panic!("Unexpected message {:?}", received);
+    };
+    assert_eq!(content, "from b");
+    let stored = node_a.get_history_message(&id).unwrap();
+    assert_eq!(stored.provenance.origin(), Some(&Origin::Bridge(bridge)));
+
+    // Neither message comes back to the server it was published on.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    for node in [&node_a, &node_b] {
+        for text in ["from a", "from b"] {
+            let copies = node
+                .get_topic_history(topic, 10)
+                .into_iter()
+                .filter(|m| m.content == text)
+                .count();
+            assert_eq!(copies, 1);
+        }
+    }
+
+    alice.close().await?;
+    bob.close().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_acknowledgments_are_forwarded_to_the_sender() -> Result<()> {
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let topic = &format!("receipts-{}", Uuid::new_v4());
+    let mut sender = TestClient::connect(port, topic).await?;
+    let mut reader = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    sender.send_message(topic, "did you see this?").await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), reader.recv()).await??;
+    let Some(ServerMessage::Topic { id, .. }) = received else {
+        panic!("Unexpected message {:?}", received);
+    };
+    reader
+        .send(&ClientMessage::MessageReceived { msg_id: id })
+        .await?;
+
+    let sender_id = client_manager.get_history_message(&id).unwrap().sender;
+    let reader_id = client_manager
+        .get_clients_by_topic(topic)
+        .into_iter()
+        .map(|client| client.id)
+        .find(|client_id| client_id.to_string() != sender_id)
+        .unwrap();
+    let receipt = tokio::time::timeout(Duration::from_secs(2), sender.recv()).await??;
+    match receipt {
+        Some(ServerMessage::MessageAcknowledged { msg_id, client_id }) => {
+            assert_eq!(msg_id, id);
+            assert_eq!(client_id, reader_id);
+        }
+        other => panic!("Unexpected message {:?}", other),
+    }
+
+    sender.close().await?;
+    reader.close().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_custom_verbs_reach_registered_handlers() -> Result<()> {
+    use async_trait::async_trait;
+    use morpheus::core::verbs::{ClientHandle, VerbHandler};
+
+    /// Answers `X-Echo` with the payload's `text`, privately.
+    struct Echo;
+
+    #[async_trait]
+    impl VerbHandler for Echo {
+        async fn handle(
+            &self,
+            client: &ClientHandle,
+            payload: serde_json::Value,
+            client_manager: &Arc<ClientManager>,
+        ) -> Result<(), String> {
+            let text = payload["text"].as_str().ok_or("text is missing")?;
+            let reply = ServerMessage::Private {
+                id: Uuid::new_v4(),
+                content: format!("{} (from topic {})", text, client.topic().unwrap_or("-")),
+            };
+            client_manager
+                .send_private_message(client.id(), reply)
+                .await;
+            Ok(())
+        }
+    }
+
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    client_manager
+        .verbs()
+        .register("X-Echo", Arc::new(Echo))
+        .unwrap();
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+    let mut client = TestClient::connect(port, "verbs").await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let frames = [
+        r#"{"v":1,"type":"X-Echo","payload":{"text":"hi"}}"#,
+        r#"{"v":1,"type":"X-Echo","payload":{}}"#,
+        r#"{"v":1,"type":"X-Unregistered","payload":{}}"#,
+    ];
+    for frame in frames {
+        client.ws.send(Message::Text(frame.to_string())).await?;
+    }
+    let mut replies = Vec::new();
+    for _ in 0..frames.len() {
+        replies.push(tokio::time::timeout(Duration::from_secs(2), client.recv()).await??);
+    }
+    assert!(matches!(
+        &replies[0],
+        Some(ServerMessage::Private { content, .. }) if content == "hi (from topic verbs)"
+    ));
+    assert!(matches!(
+        &replies[1],
+        Some(ServerMessage::Error { message }) if message == "text is missing"
+    ));
+    assert!(matches!(
+        &replies[2],
+        Some(ServerMessage::Error { message }) if message == "Unsupported message type"
+    ));
+
+    client.close().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_banned_address_is_kicked_and_refused() -> Result<()> {
+    use morpheus::core::bans::BanTarget;
+
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let topic = &format!("bans-{}", Uuid::new_v4());
+    let mut connected = TestClient::connect(port, topic).await?;
+    for _ in 0..10 {
+        if client_manager.get_clients_by_topic(topic).len() == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let kicked = client_manager
+        .ban(BanTarget::Ip("127.0.0.1".parse()?))
+        .map_err(anyhow::Error::msg)?;
+    assert_eq!(kicked, 1);
+    for client in [&mut connected, &mut TestClient::connect(port, topic).await?] {
+        let received = tokio::time::timeout(Duration::from_secs(2), client.recv()).await??;
+        assert!(
+            matches!(received, Some(ServerMessage::Kicked { .. })),
+            "Unexpected message {:?}",
+            received
+        );
+        let code = tokio::time::timeout(Duration::from_secs(2), client.close_code()).await?;
+        assert_eq!(code, Some(CloseCode::Banned.code()));
+    }
+    assert!(client_manager.get_all_clients().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_connection_limit_refuses_extra_clients() -> Result<()> {
+    use morpheus::core::{
+        msg::Limit,
+        policy::{Limits, Policies},
+    };
+
+    let client_manager = Arc::new(
+        ClientManager::new(Arc::new(InMemoryStorage::new())).with_policies(Policies {
+            limits: Limits {
+                max_connections: Some(1),
+                ..Limits::default()
+            },
+            ..Policies::default()
+        }),
+    );
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let topic = &format!("limits-{}", Uuid::new_v4());
+    let _first = TestClient::connect(port, topic).await?;
+    for _ in 0..10 {
+        if client_manager.get_clients_by_topic(topic).len() == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let mut refused = TestClient::connect(port, topic).await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), refused.recv()).await??;
+    assert!(
+        matches!(
+            received,
+            Some(ServerMessage::LimitExceeded {
+                limit: Limit::Connections,
+                max: 1
+            })
+        ),
+        "Unexpected message {:?}",
+        received
+    );
+    let code = tokio::time::timeout(Duration::from_secs(2), refused.close_code()).await?;
+    assert_eq!(code, Some(CloseCode::Overloaded.code()));
+    assert_eq!(client_manager.get_all_clients().len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_oversized_message_is_refused() -> Result<()> {
+    use morpheus::core::policy::{Limits, Policies};
+
+    let client_manager = Arc::new(
+        ClientManager::new(Arc::new(InMemoryStorage::new())).with_policies(Policies {
+            limits: Limits {
+                max_message_bytes: 256,
+                ..Limits::default()
+            },
+            ..Policies::default()
+        }),
+    );
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let topic = &format!("size-{}", Uuid::new_v4());
+    let mut sender = TestClient::connect(port, topic).await?;
+    let mut receiver = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Over the limit, but within the frame ceiling the WebSocket layer still reads.
+    sender.send_message(topic, &"x".repeat(512)).await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), sender.recv()).await??;
+    match received {
+        Some(ServerMessage::Error { message }) => {
+            assert!(message.starts_with("Message too large"), "{}", message)
+        }
+        other => panic!("Unexpected message {:?}", other),
+    }
+
+    // The connection stays usable for messages within the limit.
+    sender.send_message(topic, "small").await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), receiver.recv()).await??;
+    assert!(
+        matches!(&received, Some(ServerMessage::Topic { content, .. }) if content == "small"),
+        "Unexpected message {:?}",
+        received
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_attachments_are_relayed_in_chunks() -> Result<()> {
+    use morpheus::core::{
+        attachments::encode_chunk,
+        policy::{Limits, Policies},
+    };
+
+    let client_manager = Arc::new(
+        ClientManager::new(Arc::new(InMemoryStorage::new())).with_policies(Policies {
+            limits: Limits {
+                max_attachment_bytes: 16,
+                ..Limits::default()
+            },
+            ..Policies::default()
+        }),
+    );
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let topic = &format!("files-{}", Uuid::new_v4());
+    let mut sender = TestClient::connect(port, topic).await?;
+    let mut receiver = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let start = |id: Uuid, size: u64| ClientMessage::AttachmentStart {
+        id,
+        topic: topic.to_string(),
+        name: "red-pill.png".to_string(),
+        content_type: "image/png".to_string(),
+        size,
+    };
+    sender.send(&start(Uuid::new_v4(), 17)).await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), sender.recv()).await??;
+    assert!(
+        matches!(&received, Some(ServerMessage::Error { message }) if message.starts_with("Attachment too large")),
+        "Unexpected message {:?}",
+        received
+    );
+
+    let id = Uuid::new_v4();
+    sender.send(&start(id, 10)).await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), receiver.recv()).await??;
+    assert!(
+        matches!(&received, Some(ServerMessage::AttachmentStart { id: started, size: 10, .. }) if *started == id),
+        "Unexpected message {:?}",
+        received
+    );
+    let chunks = [encode_chunk(id, 0, b"012345"), encode_chunk(id, 1, b"6789")];
+    for chunk in &chunks {
+        sender.ws.send(Message::Binary(chunk.clone())).await?;
+        let received = tokio::time::timeout(Duration::from_secs(2), receiver.ws.next()).await?;
+        assert!(
+            matches!(&received, Some(Ok(Message::Binary(frame))) if frame == chunk),
+            "Unexpected frame {:?}",
+            received
+        );
+    }
+    sender
+        .send(&ClientMessage::AttachmentComplete { id })
+        .await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), receiver.recv()).await??;
+    assert!(
+        matches!(received, Some(ServerMessage::AttachmentComplete { id: completed }) if completed == id),
+        "Unexpected message {:?}",
+        received
+    );
+    let received = tokio::time::timeout(Duration::from_secs(2), sender.recv()).await??;
+    assert!(
+        matches!(received, Some(ServerMessage::Published { id: published }) if published == id),
+        "Unexpected message {:?}",
+        received
+    );
+
+    // An attachment left unfinished is aborted when its sender leaves.
+    let id = Uuid::new_v4();
+    sender.send(&start(id, 10)).await?;
+    tokio::time::timeout(Duration::from_secs(2), receiver.recv()).await??;
+    sender.close().await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), receiver.recv()).await??;
+    assert!(
+        matches!(&received, Some(ServerMessage::AttachmentAborted { id: aborted, reason }) if *aborted == id && reason == "sender disconnected"),
+        "Unexpected message {:?}",
+        received
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_public_keys_are_relayed_within_the_topic() -> Result<()> {
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let topic = &format!("e2e-{}", Uuid::new_v4());
+    let mut announcer = TestClient::connect(port, topic).await?;
+    let mut peer = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let announce = |topic: &str| ClientMessage::PublicKey {
+        topic: topic.to_string(),
+        key: "opaque-key".to_string(),
+    };
+    announcer.send(&announce(topic)).await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), peer.recv()).await??;
+    assert!(
+        matches!(&received, Some(ServerMessage::PublicKey { key, .. }) if key == "opaque-key"),
+        "Unexpected message {:?}",
+        received
+    );
+
+    announcer.send(&announce("elsewhere")).await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), announcer.recv()).await??;
+    assert!(
+        matches!(&received, Some(ServerMessage::Error { message }) if message.starts_with("Subscribe to topic 'elsewhere'")),
+        "Unexpected message {:?}",
+        received
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_message_hooks_rewrite_and_reject() -> Result<()> {
+    use async_trait::async_trait;
+    use morpheus::core::hooks::{MessageHook, PendingMessage};
+
+    struct Moderator;
+
+    #[async_trait]
+    impl MessageHook for Moderator {
+        fn name(&self) -> &str {
+            "moderator"
+        }
+
+        async fn on_message(&self, message: &mut PendingMessage) -> Result<(), String> {
+            if message.content.contains("spam") {
+                return Err("no spam".to_string());
+            }
+            message.content = message.content.replace("darn", "****");
+            Ok(())
+        }
+    }
+
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    client_manager.message_hooks().register(Arc::new(Moderator));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let topic = &format!("hooks-{}", Uuid::new_v4());
+    let mut sender = TestClient::connect(port, topic).await?;
+    let mut receiver = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    sender.send_message(topic, "buy spam").await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), sender.recv()).await??;
+    assert!(
+        matches!(&received, Some(ServerMessage::Error { message }) if message == "Message rejected: no spam"),
+        "Unexpected message {:?}",
+        received
+    );
+
+    sender.send_message(topic, "oh darn").await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), receiver.recv()).await??;
+    let Some(ServerMessage::Topic { id, content, .. }) = received else {
+        panic!("Unexpected message {:?}", received);
+    };
+    assert_eq!(content, "oh ****");
+    let stored = client_manager.get_history_message(&id).unwrap();
+    assert_eq!(stored.provenance.entries().len(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_headers_reach_subscribers_and_history() -> Result<()> {
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let topic = &format!("headers-{}", Uuid::new_v4());
+    let mut sender = TestClient::connect(port, topic).await?;
+    let mut receiver = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let headers = BTreeMap::from([("correlation-id".to_string(), "42".to_string())]);
+    sender
+        .send(&ClientMessage::Message {
+            topic: topic.to_string(),
+            content: "order placed".to_string(),
+            headers: headers.clone(),
+            qos: Qos::FireAndForget,
+            id: None,
+            reply_to: None,
+            retain: false,
+        })
+        .await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), receiver.recv()).await??;
+    let Some(ServerMessage::Topic {
+        id,
+        headers: received_headers,
+        ..
+    }) = received
+    else {
+        panic!("Unexpected message {:?}", received);
+    };
+    assert_eq!(received_headers, headers);
+    assert_eq!(
+        client_manager.get_history_message(&id).unwrap().headers,
+        headers
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replies_keep_their_thread_within_a_topic() -> Result<()> {
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let topic = &format!("threads-{}", Uuid::new_v4());
+    let mut sender = TestClient::connect(port, topic).await?;
+    let mut receiver = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    sender.send_message(topic, "lunch?").await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), receiver.recv()).await??;
+    let Some(ServerMessage::Topic { id: original, .. }) = received else {
+        panic!("Unexpected message {:?}", received);
+    };
+    let reply = |topic: &str| ClientMessage::Message {
+        topic: topic.to_string(),
+        content: "sure".to_string(),
+        headers: BTreeMap::new(),
+        qos: Qos::FireAndForget,
+        id: None,
+        reply_to: Some(original),
+        retain: false,
+    };
+    receiver.send(&reply(topic)).await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), sender.recv()).await??;
+    let Some(ServerMessage::Topic { id, reply_to, .. }) = received else {
+        panic!("Unexpected message {:?}", received);
+    };
+    assert_eq!(reply_to, Some(original));
+    assert_eq!(
+        client_manager.get_history_message(&id).unwrap().reply_to,
+        Some(original)
+    );
+
+    // A reply cannot pull a message into another topic's thread.
+    let other = &format!("threads-{}", Uuid::new_v4());
+    let mut outsider = TestClient::connect(port, other).await?;
+    outsider.send(&reply(other)).await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), outsider.recv()).await??;
+    assert!(
+        matches!(&received, Some(ServerMessage::Error { message }) if message.contains("replies must stay")),
+        "Unexpected message {:?}",
+        received
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_consumer_group_members_share_offsets() -> Result<()> {
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let topic = &format!("orders-{}", Uuid::new_v4());
+    let mut publisher = TestClient::connect(port, topic).await?;
+    for n in 1..=3 {
+        publisher
+            .send_message(topic, &format!("order {}", n))
+            .await?;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut first = TestClient::connect(port, topic).await?;
+    let mut second = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let fetch = ClientMessage::Fetch {
+        group: "billing".to_string(),
+        max: 2,
+    };
+    let mut fetched = Vec::new();
+    for consumer in [&mut first, &mut second] {
+        consumer.send(&fetch).await?;
+        let received = tokio::time::timeout(Duration::from_secs(2), consumer.recv()).await??;
+        let Some(ServerMessage::Fetched { messages, .. }) = received else {
+            panic!("Unexpected message {:?}", received);
+        };
+        fetched.push(messages.into_iter().map(|m| m.seq).collect::<Vec<_>>());
+    }
+    assert_eq!(fetched, vec![vec![1, 2], vec![3]]);
+
+    first
+        .send(&ClientMessage::CommitOffset {
+            group: "billing".to_string(),
+            offset: 2,
+        })
+        .await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), first.recv()).await??;
+    assert!(
+        matches!(
+            received,
+            Some(ServerMessage::OffsetCommitted { offset: 2, .. })
+        ),
+        "Unexpected message {:?}",
+        received
+    );
+
+    // The second member leaves without committing, so its batch is handed out again.
+    second.close().await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    first.send(&fetch).await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), first.recv()).await??;
+    let Some(ServerMessage::Fetched { messages, .. }) = received else {
+        panic!("Unexpected message {:?}", received);
+    };
+    assert_eq!(messages[0].content, "order 3");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_topic_sequence_numbers_and_missed_messages() -> Result<()> {
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let topic = &format!("seq-{}", Uuid::new_v4());
+    let mut sender = TestClient::connect(port, topic).await?;
+    let mut receiver = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut seqs = Vec::new();
+    for n in 1..=3 {
+        sender.send_message(topic, &format!("tick {}", n)).await?;
+        let received = tokio::time::timeout(Duration::from_secs(2), receiver.recv()).await??;
+        let Some(ServerMessage::Topic { seq, .. }) = received else {
+            panic!("Unexpected message {:?}", received);
+        };
+        seqs.push(seq);
+    }
+    assert_eq!(seqs, vec![1, 2, 3]);
+
+    receiver
+        .send(&ClientMessage::RequestMissed {
+            topic: topic.to_string(),
+            from_seq: 2,
+        })
+        .await?;
+    let mut missed = Vec::new();
+    for _ in 0..2 {
+        let received = tokio::time::timeout(Duration::from_secs(2), receiver.recv()).await??;
+        let Some(ServerMessage::Topic { seq, content, .. }) = received else {
+            panic!("Unexpected message {:?}", received);
+        };
+        missed.push((seq, content));
+    }
+    assert_eq!(
+        missed,
+        vec![(2, "tick 2".to_string()), (3, "tick 3".to_string())]
+    );
+
+    receiver
+        .send(&ClientMessage::RequestMissed {
+            topic: "elsewhere".to_string(),
+            from_seq: 1,
+        })
+        .await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), receiver.recv()).await??;
+    assert!(
+        matches!(received, Some(ServerMessage::Error { .. })),
+        "Unexpected message {:?}",
+        received
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_resync_acknowledges_and_replays_in_one_message() -> Result<()> {
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let topic = &format!("resync-{}", Uuid::new_v4());
+    let mut sender = TestClient::connect(port, topic).await?;
+    let mut sleeper = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    sender.send_message(topic, "before").await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), sleeper.recv()).await??;
+    let Some(ServerMessage::Topic { id, seq: 1, .. }) = received else {
+        panic!("Unexpected message {:?}", received);
+    };
+    // Sent while the sleeper is in the background and not reading.
+    sender.send_message(topic, "during").await?;
+
+    sleeper
+        .send(&ClientMessage::Resync {
+            topic: topic.to_string(),
+            from_seq: 2,
+            acked: vec![id],
+        })
+        .await?;
+    let receipt = tokio::time::timeout(Duration::from_secs(2), sender.recv()).await??;
+    assert!(
+        matches!(receipt, Some(ServerMessage::MessageAcknowledged { msg_id, .. }) if msg_id == id),
+        "Unexpected message {:?}",
+        receipt
+    );
+    // The live delivery and the replay both arrive; a client drops the one it has seen.
+    for _ in 0..2 {
+        let received = tokio::time::timeout(Duration::from_secs(2), sleeper.recv()).await??;
+        assert!(
+            matches!(&received, Some(ServerMessage::Topic { seq: 2, content, .. }) if content == "during"),
+            "Unexpected message {:?}",
+            received
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_exactly_once_retries_are_confirmed_but_not_rebroadcast() -> Result<()> {
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let topic = &format!("qos-{}", Uuid::new_v4());
+    let mut publisher = TestClient::connect(port, topic).await?;
+    let mut subscriber = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let id = Uuid::new_v4();
+    let message = ClientMessage::Message {
+        topic: topic.to_string(),
+        content: "exactly once".to_string(),
+        headers: BTreeMap::new(),
+        qos: Qos::ExactlyOnce,
+        id: Some(id),
+        reply_to: None,
+        retain: false,
+    };
+    for _ in 0..2 {
+        publisher.send(&message).await?;
+        let received = tokio::time::timeout(Duration::from_secs(2), publisher.recv()).await??;
+        assert!(
+            matches!(received, Some(ServerMessage::Published { id: confirmed }) if confirmed == id),
+            "Unexpected message {:?}",
+            received
+        );
+    }
+
+    let received = tokio::time::timeout(Duration::from_secs(2), subscriber.recv()).await??;
+    assert!(
+        matches!(received, Some(ServerMessage::Topic { id: got, qos: Qos::ExactlyOnce, .. }) if got == id),
+        "Unexpected message {:?}",
+        received
+    );
+    let again = tokio::time::timeout(Duration::from_millis(300), subscriber.recv()).await;
+    assert!(again.is_err(), "The retry was broadcast again: {:?}", again);
+
+    publisher
+        .send(&ClientMessage::Message {
+            topic: topic.to_string(),
+            content: "no id".to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::AtLeastOnce,
+            id: None,
+            reply_to: None,
+            retain: false,
+        })
+        .await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), publisher.recv()).await??;
+    assert!(
+        matches!(received, Some(ServerMessage::Error { .. })),
+        "Unexpected message {:?}",
+        received
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_poll_is_announced_voted_on_and_closed_with_results() -> Result<()> {
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+    morpheus::core::polls::spawn_closer(client_manager.clone());
+
+    let topic = &format!("poll-{}", Uuid::new_v4());
+    let mut creator = TestClient::connect(port, topic).await?;
+    let mut voter = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    creator
+        .send(&ClientMessage::CreatePoll {
+            question: "Red or blue pill?".to_string(),
+            options: vec!["Red".to_string(), "Blue".to_string()],
+            duration_secs: Some(1),
+        })
+        .await?;
+    let poll_id = match tokio::time::timeout(Duration::from_secs(2), voter.recv()).await?? {
+        Some(ServerMessage::Poll {
+            id,
+            question,
+            options,
+            closes_in_secs,
+            ..
+        }) => {
+            assert_eq!(question, "Red or blue pill?");
+            assert_eq!(options.len(), 2);
+            assert_eq!(closes_in_secs, 1);
+            id
+        }
+        other => panic!("Unexpected message {:?}", other),
+    };
+
+    for option in [0, 2] {
+        voter.send(&ClientMessage::Vote { poll_id, option }).await?;
+    }
+    let received = tokio::time::timeout(Duration::from_secs(2), voter.recv()).await??;
+    assert!(
+        matches!(received, Some(ServerMessage::Voted { option: 0, .. })),
+        "Unexpected message {:?}",
+        received
+    );
+    let received = tokio::time::timeout(Duration::from_secs(2), voter.recv()).await??;
+    assert!(
+        matches!(received, Some(ServerMessage::Error { .. })),
+        "Unexpected message {:?}",
+        received
+    );
+
+    match tokio::time::timeout(Duration::from_secs(3), voter.recv()).await?? {
+        Some(ServerMessage::PollResults { id, votes, .. }) => {
+            assert_eq!(id, poll_id);
+            assert_eq!(votes, vec![1, 0]);
+        }
+        other => panic!("Unexpected message {:?}", other),
+    }
+    assert!(client_manager.polls().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_responses_are_routed_back_to_the_requester() -> Result<()> {
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let topic = &format!("rpc-{}", Uuid::new_v4());
+    let mut requester = TestClient::connect(port, topic).await?;
+    let mut responder = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let correlation_id = Uuid::new_v4();
+    requester
+        .send(&ClientMessage::Request {
+            topic: topic.to_string(),
+            correlation_id,
+            content: "status?".to_string(),
+        })
+        .await?;
+    match tokio::time::timeout(Duration::from_secs(2), responder.recv()).await?? {
+        Some(ServerMessage::Request {
+            correlation_id: id,
+            content,
+            ..
+        }) => {
+            assert_eq!(id, correlation_id);
+            assert_eq!(content, "status?");
+        }
+        other => panic!("Unexpected message {:?}", other),
+    }
+
+    for content in ["ok", "late"] {
+        responder
+            .send(&ClientMessage::Respond {
+                correlation_id,
+                content: content.to_string(),
+            })
+            .await?;
+    }
+    match tokio::time::timeout(Duration::from_secs(2), requester.recv()).await?? {
+        Some(ServerMessage::Response {
+            correlation_id: id,
+            content,
+        }) => {
+            assert_eq!(id, correlation_id);
+            assert_eq!(content, "ok");
+        }
+        other => panic!("Unexpected message {:?}", other),
+    }
+    assert_eq!(client_manager.pending_request_count(), 0);
+    let received = tokio::time::timeout(Duration::from_secs(2), responder.recv()).await??;
+    assert!(
+        matches!(&received, Some(ServerMessage::Error { message }) if message.starts_with("No request")),
+        "Unexpected message {:?}",
+        received
+    );
+
+    let lonely = &format!("rpc-{}", Uuid::new_v4());
+    let mut alone = TestClient::connect(port, lonely).await?;
+    alone
+        .send(&ClientMessage::Request {
+            topic: lonely.to_string(),
+            correlation_id: Uuid::new_v4(),
+            content: "anyone?".to_string(),
+        })
+        .await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), alone.recv()).await??;
+    assert!(
+        matches!(&received, Some(ServerMessage::Error { message }) if message.contains("no other subscriber")),
+        "Unexpected message {:?}",
+        received
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_retained_message_greets_new_subscribers() -> Result<()> {
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let topic = &format!("sensors-{}", Uuid::new_v4());
+    let mut publisher = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let retained = |content: &str| ClientMessage::Message {
+        topic: topic.to_string(),
+        content: content.to_string(),
+        headers: BTreeMap::new(),
+        qos: Qos::FireAndForget,
+        id: None,
+        reply_to: None,
+        retain: true,
+    };
+    publisher.send(&retained("21.5")).await?;
+    publisher.send(&retained("22.0")).await?;
+    publisher.send_message(topic, "not retained").await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut late = TestClient::connect(port, topic).await?;
+    match tokio::time::timeout(Duration::from_secs(2), late.recv()).await?? {
+        Some(ServerMessage::Topic {
+            content,
+            seq,
+            retained,
+            ..
+        }) => {
+            assert_eq!(content, "22.0");
+            assert_eq!(seq, 0);
+            assert!(retained);
+        }
+        other => panic!("Unexpected message {:?}", other),
+    }
+
+    publisher.send(&retained("")).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut later = TestClient::connect(port, topic).await?;
+    later.send(&ClientMessage::Ping { nonce: 1 }).await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), later.recv()).await??;
+    assert!(
+        matches!(received, Some(ServerMessage::Pong { nonce: 1 })),
+        "Unexpected message {:?}",
+        received
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_list_topics_reports_client_counts() -> Result<()> {
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let (busy, quiet) = (
+        format!("busy-{}", Uuid::new_v4()),
+        format!("quiet-{}", Uuid::new_v4()),
+    );
+    let mut asker = TestClient::connect(port, &busy).await?;
+    let _other = TestClient::connect(port, &busy).await?;
+    let _loner = TestClient::connect(port, &quiet).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    asker.send(&ClientMessage::ListTopics).await?;
+    match tokio::time::timeout(Duration::from_secs(2), asker.recv()).await?? {
+        Some(ServerMessage::TopicList { topics }) => {
+            let names: Vec<_> = topics.iter().map(|topic| topic.name.as_str()).collect();
+            assert_eq!(names, vec![busy.as_str(), quiet.as_str()]);
+            assert_eq!(topics[0].clients, 2);
+            assert_eq!(topics[1].clients, 1);
+        }
+        other => panic!("Unexpected message {:?}", other),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ping_is_answered_with_pong() -> Result<()> {
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let mut client = TestClient::connect(port, &format!("ping-{}", Uuid::new_v4())).await?;
+    client.send(&ClientMessage::Ping { nonce: 42 }).await?;
+    match tokio::time::timeout(Duration::from_secs(2), client.recv()).await?? {
+        Some(ServerMessage::Pong { nonce }) => assert_eq!(nonce, 42),
+        other => panic!("Unexpected message {:?}", other),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_admin_clients_lists_connection_metadata() -> Result<()> {
+    use morpheus::core::storage::ClientInfo;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let topic = format!("meta-{}", Uuid::new_v4());
+    let mut request = format!("ws://127.0.0.1:{}/ws", port).into_client_request()?;
+    request
+        .headers_mut()
+        .insert("user-agent", "probe/1.0".parse()?);
+    let (mut ws, _) = connect_async(request).await?;
+    let connect = ClientMessage::Connect {
+        topic: topic.clone(),
+    };
+    ws.send(Message::Text(morpheus::core::msg::encode(&connect)?))
+        .await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let uri = format!("http://127.0.0.1:{}/admin/clients", port).parse()?;
+    let response = hyper::Client::new().get(uri).await?;
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    let clients: Vec<ClientInfo> = serde_json::from_slice(&body)?;
+    let client = clients
+        .iter()
+        .find(|client| client.topic.as_deref() == Some(topic.as_str()))
+        .expect("client not listed");
+    assert_eq!(client.user_agent.as_deref(), Some("probe/1.0"));
+    assert_eq!(client.ip, Some([127, 0, 0, 1].into()));
+    assert_eq!(
+        client.protocol_version,
+        Some(morpheus::core::msg::PROTOCOL_VERSION)
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_forwarding_rules_copy_messages_between_topics() -> Result<()> {
+    use hyper::{Body, Method, Request, StatusCode};
+
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+    let (alerts, ops) = (
+        format!("alerts-{}", Uuid::new_v4()),
+        format!("ops-{}", Uuid::new_v4()),
+    );
+
+    let rule = serde_json::json!({
+        "from": alerts,
+        "to": ops,
+        "filter": "^CRIT",
+        "transform": { "pattern": "^CRIT", "replacement": "critical:" },
+    });
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("http://127.0.0.1:{}/admin/forwards", port))
+        .body(Body::from(rule.to_string()))?;
+    let response = hyper::Client::new().request(request).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    let created: serde_json::Value = serde_json::from_slice(&body)?;
+    let id = created["id"]
+        .as_str()
+        .expect("rule without an ID")
+        .to_string();
+
+    let mut publisher = TestClient::connect(port, &alerts).await?;
+    let mut watcher = TestClient::connect(port, &ops).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    publisher.send_message(&alerts, "INFO all good").await?;
+    publisher.send_message(&alerts, "CRIT disk full").await?;
+    match tokio::time::timeout(Duration::from_secs(2), watcher.recv()).await?? {
+        Some(ServerMessage::Topic {
+            topic,
+            content,
+            headers,
+            ..
+        }) => {
+            assert_eq!(topic, ops);
+            assert_eq!(content, "critical: disk full");
+            assert_eq!(headers.get("forwarded-from"), Some(&alerts));
+        }
+        other => panic!("Unexpected message {:?}", other),
+    }
+
+    let request = Request::builder()
+        .method(Method::DELETE)
+        .uri(format!("http://127.0.0.1:{}/admin/forwards/{}", port, id))
+        .body(Body::empty())?;
+    let response = hyper::Client::new().request(request).await?;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(client_manager.forwards().list().is_empty());
+
+    publisher.send_message(&alerts, "CRIT again").await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    watcher.send(&ClientMessage::Ping { nonce: 2 }).await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), watcher.recv()).await??;
+    assert!(
+        matches!(received, Some(ServerMessage::Pong { nonce: 2 })),
+        "Unexpected message {:?}",
+        received
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_drain_moves_clients_and_refuses_new_ones() -> Result<()> {
+    use morpheus::core::client_manager::DrainStatus;
+
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = free_port().await;
+    tokio::spawn(
+        warp::serve(morpheus::http::filters(client_manager.clone())).run(([127, 0, 0, 1], port)),
+    );
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let topic = &format!("drain-{}", Uuid::new_v4());
+    let mut client = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let drain = || {
+        hyper::Request::post(format!("http://127.0.0.1:{}/admin/drain", port))
+            .body(hyper::Body::empty())
+    };
+    let response = hyper::Client::new().request(drain()?).await?;
+    assert_eq!(response.status(), 202);
+    let code = tokio::time::timeout(Duration::from_secs(2), client.close_code()).await?;
+    assert_eq!(code, Some(CloseCode::Draining.code()));
+
+    let mut refused = TestClient::connect(port, topic).await?;
+    let code = tokio::time::timeout(Duration::from_secs(2), refused.close_code()).await?;
+    assert_eq!(code, Some(CloseCode::Draining.code()));
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let uri = format!("http://127.0.0.1:{}/admin/drain", port).parse()?;
+    let response = hyper::Client::new().get(uri).await?;
+    assert_eq!(response.status(), 200);
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    let status: DrainStatus = serde_json::from_slice(&body)?;
+    assert_eq!(
+        status,
+        DrainStatus {
+            draining: true,
+            clients: 0
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_clients_behind_proxies_are_seen_by_their_address() -> Result<()> {
+    use morpheus::core::{bans::BanTarget, policy::Policies};
+    use tokio::io::AsyncWriteExt;
+    use tokio_tungstenite::{client_async, tungstenite::client::IntoClientRequest};
+
+    let client_manager = Arc::new(
+        ClientManager::new(Arc::new(InMemoryStorage::new())).with_policies(Policies {
+            trusted_proxies: vec!["127.0.0.0/8".parse().map_err(anyhow::Error::msg)?],
+            ..Policies::default()
+        }),
+    );
+    let port = free_port().await;
+    tokio::spawn(
+        warp::serve(morpheus::http::filters(client_manager.clone())).run(([127, 0, 0, 1], port)),
+    );
+    let proxied_port = free_port().await;
+    tokio::spawn(morpheus::proxy::serve(
+        ([127, 0, 0, 1], proxied_port).into(),
+        morpheus::http::filters(client_manager.clone()),
+    ));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let ip_of = |topic: &str| {
+        client_manager
+            .get_clients_by_topic(topic)
+            .first()
+            .and_then(|client| client.ip)
+    };
+
+    // Forwarded by a trusted proxy.
+    let forwarded_topic = &format!("forwarded-{}", Uuid::new_v4());
+    let mut request = format!("ws://127.0.0.1:{}/ws", port).into_client_request()?;
+    request
+        .headers_mut()
+        .insert("x-forwarded-for", "198.51.100.4, 127.0.0.2".parse()?);
+    let (ws, _) = connect_async(request).await?;
+    let mut forwarded = TestClient { ws };
+    forwarded
+        .send(&ClientMessage::Connect {
+            topic: forwarded_topic.clone(),
+        })
+        .await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(ip_of(forwarded_topic), Some("198.51.100.4".parse()?));
+
+    // Announced with the PROXY protocol.
+    let topic = &format!("proxied-{}", Uuid::new_v4());
+    let mut stream = TcpStream::connect(("127.0.0.1", proxied_port)).await?;
+    stream
+        .write_all(b"PROXY TCP4 203.0.113.9 127.0.0.1 51000 80\r\n")
+        .await?;
+    let url = format!("ws://127.0.0.1:{}/ws", proxied_port);
+    let (ws, _) = client_async(url, MaybeTlsStream::Plain(stream)).await?;
+    let mut proxied = TestClient { ws };
+    proxied
+        .send(&ClientMessage::Connect {
+            topic: topic.clone(),
+        })
+        .await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(ip_of(topic), Some("203.0.113.9".parse()?));
+
+    // Bans apply to the client, not to the proxy.
+    let kicked = client_manager
+        .ban(BanTarget::Ip("203.0.113.9".parse()?))
+        .map_err(anyhow::Error::msg)?;
+    assert_eq!(kicked, 1);
+    let code = tokio::time::timeout(Duration::from_secs(2), proxied.close_code()).await?;
+    assert_eq!(code, Some(CloseCode::Banned.code()));
+    assert_eq!(ip_of(forwarded_topic), Some("198.51.100.4".parse()?));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_jwt_claims_limit_what_clients_may_do() -> Result<()> {
+    use jsonwebtoken::{encode, get_current_timestamp, EncodingKey, Header};
+    use morpheus::core::{
+        jwt::{Jwt, JwtConfig},
+        policy::Policies,
+    };
+
+    let jwt = Jwt::new(&JwtConfig {
+        secret: Some("signing-secret".to_string()),
+        ..JwtConfig::default()
+    })
+    .map_err(anyhow::Error::msg)?;
+    let client_manager = Arc::new(
+        ClientManager::new(Arc::new(InMemoryStorage::new())).with_policies(Policies {
+            jwt: Some(jwt),
+            ..Policies::default()
+        }),
+    );
+    let port = free_port().await;
+    tokio::spawn(
+        warp::serve(morpheus::http::filters(client_manager.clone())).run(([127, 0, 0, 1], port)),
+    );
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let sign = |claims: serde_json::Value| {
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"signing-secret"),
+        )
+    };
+    let exp = get_current_timestamp() + 60;
+    let topic = &format!("jwt-{}", Uuid::new_v4());
+
+    // A reader of one topic, with the token as a query parameter.
+    let reader = sign(serde_json::json!({"exp": exp, "topics": [topic], "publish": false}))?;
+    let url = format!("ws://127.0.0.1:{}/ws?token={}", port, reader);
+    let (ws, _) = connect_async(&url).await?;
+    let mut client = TestClient { ws };
+    client
+        .send(&ClientMessage::Connect {
+            topic: "elsewhere".to_string(),
+        })
+        .await?;
+    assert!(matches!(
+        client.recv().await?,
+        Some(ServerMessage::Error { message }) if message.contains("subscribe")
+    ));
+    client
+        .send(&ClientMessage::Connect {
+            topic: topic.clone(),
+        })
+        .await?;
+    client.send_message(topic, "hello").await?;
+    assert!(matches!(
+        client.recv().await?,
+        Some(ServerMessage::Error { message }) if message.contains("publish")
+    ));
+
+    // A writer authenticating with its first message.
+    let writer = sign(serde_json::json!({"exp": exp, "topics": [topic]}))?;
+    let (ws, _) = connect_async(format!("ws://127.0.0.1:{}/ws", port)).await?;
+    let mut sender = TestClient { ws };
+    sender
+        .send(&ClientMessage::Authenticate { token: writer })
+        .await?;
+    sender
+        .send(&ClientMessage::Connect {
+            topic: topic.clone(),
+        })
+        .await?;
+    sender.send_message(topic, "from the writer").await?;
+    assert!(matches!(
+        client.recv().await?,
+        Some(ServerMessage::Topic { content, .. }) if content == "from the writer"
+    ));
+
+    // Expired tokens are refused, and only admins may use the admin endpoints.
+    let expired = sign(serde_json::json!({"exp": exp - 3600}))?;
+    let url = format!("ws://127.0.0.1:{}/ws?token={}", port, expired);
+    let (ws, _) = connect_async(&url).await?;
+    let code = tokio::time::timeout(Duration::from_secs(2), TestClient { ws }.close_code()).await?;
+    assert_eq!(code, Some(CloseCode::AuthFailed.code()));
+    let admin = sign(serde_json::json!({"exp": exp, "admin": true}))?;
+    for (token, status) in [(reader, 401), (admin, 200)] {
+        let uri = format!("http://127.0.0.1:{}/admin/clients?token={}", port, token);
+        let response = hyper::Client::new().get(uri.parse()?).await?;
+        assert_eq!(response.status(), status);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_accepting_terms_does_not_bypass_jwt_topics() -> Result<()> {
+    use jsonwebtoken::{encode, get_current_timestamp, EncodingKey, Header};
+    use morpheus::core::{
+        jwt::{Jwt, JwtConfig},
+        policy::{Policies, TopicPolicy},
+    };
+    use std::collections::HashMap;
+
+    let jwt = Jwt::new(&JwtConfig {
+        secret: Some("signing-secret".to_string()),
+        ..JwtConfig::default()
+    })
+    .map_err(anyhow::Error::msg)?;
+    let restricted = format!("terms-{}", Uuid::new_v4());
+    let client_manager = Arc::new(
+        ClientManager::new(Arc::new(InMemoryStorage::new())).with_policies(Policies {
+            jwt: Some(jwt),
+            topics: HashMap::from([(
+                restricted.clone(),
+                TopicPolicy {
+                    rules: Some("Be kind.".to_string()),
+                    rules_version: 1,
+                    ..TopicPolicy::default()
+                },
+            )]),
+            ..Policies::default()
+        }),
+    );
+    let port = free_port().await;
+    tokio::spawn(
+        warp::serve(morpheus::http::filters(client_manager.clone())).run(([127, 0, 0, 1], port)),
+    );
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let claims = serde_json::json!({
+        "exp": get_current_timestamp() + 60,
+        "topics": ["elsewhere"],
+    });
+    let token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(b"signing-secret"),
+    )?;
+    let url = format!("ws://127.0.0.1:{}/ws?token={}", port, token);
+    let (ws, _) = connect_async(&url).await?;
+    let mut client = TestClient { ws };
+    client
+        .send(&ClientMessage::AcceptTerms {
+            topic: restricted.clone(),
+            version: 1,
+        })
+        .await?;
+    assert!(matches!(
+        client.recv().await?,
+        Some(ServerMessage::Error { message }) if message.contains("subscribe")
+    ));
+    assert!(client_manager.get_clients_by_topic(&restricted).is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_roles_limit_clients_and_admins_manage_them() -> Result<()> {
+    use morpheus::core::policy::{Policies, Role, TopicPolicy};
+    use std::collections::HashMap;
+
+    let topic = format!("roles-{}", Uuid::new_v4());
+    let client_manager = Arc::new(
+        ClientManager::new(Arc::new(InMemoryStorage::new())).with_policies(Policies {
+            auth_roles: HashMap::from([
+                ("pub".to_string(), Role::Publisher),
+                ("sub".to_string(), Role::Subscriber),
+                ("ops".to_string(), Role::Admin),
+            ]),
+            topics: HashMap::from([(
+                topic.clone(),
+                TopicPolicy {
+                    read_only: true,
+                    ..TopicPolicy::default()
+                },
+            )]),
+            ..Policies::default()
+        }),
+    );
+    let port = free_port().await;
+    tokio::spawn(
+        warp::serve(morpheus::http::filters(client_manager.clone())).run(([127, 0, 0, 1], port)),
+    );
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let connect = |token: &str| {
+        let url = format!("ws://127.0.0.1:{}/ws?token={}", port, token);
+        async move {
+            Ok::<_, anyhow::Error>(TestClient {
+                ws: connect_async(url).await?.0,
+            })
+        }
+    };
+    let error = |message: Option<ServerMessage>| match message {
+        Some(ServerMessage::Error { message }) => message,
+        other => panic!("expected an error, got {:?}", other),
+    };
+
+    let mut subscriber = connect("sub").await?;
+    subscriber
+        .send(&ClientMessage::Connect {
+            topic: topic.clone(),
+        })
+        .await?;
+    subscriber.send_message(&topic, "hello").await?;
+    assert!(error(subscriber.recv().await?).contains("publish"));
+
+    let mut publisher = connect("pub").await?;
+    publisher
+        .send(&ClientMessage::Connect {
+            topic: topic.clone(),
+        })
+        .await?;
+    assert!(error(publisher.recv().await?).contains("subscribe"));
+
+    // Only the admin may publish to the read-only topic, and manage other clients.
+    let subscriber_client = &client_manager.get_clients_by_topic(&topic)[0];
+    assert_eq!(subscriber_client.role, Some(Role::Subscriber));
+    let subscriber_id = subscriber_client.id;
+    publisher
+        .send(&ClientMessage::Kick {
+            client_id: subscriber_id,
+            reason: None,
+        })
+        .await?;
+    assert!(error(publisher.recv().await?).contains("admin role"));
+
+    let mut admin = connect("ops").await?;
+    admin.send_message(&topic, "announcement").await?;
+    assert!(matches!(
+        subscriber.recv().await?,
+        Some(ServerMessage::Topic { content, .. }) if content == "announcement"
+    ));
+    admin
+        .send(&ClientMessage::Kick {
+            client_id: subscriber_id,
+            reason: Some("maintenance".to_string()),
+        })
+        .await?;
+    assert!(matches!(
+        admin.recv().await?,
+        Some(ServerMessage::Private { content, .. }) if content.contains("Kicked")
+    ));
+    let code = tokio::time::timeout(Duration::from_secs(2), subscriber.close_code()).await?;
+    assert_eq!(code, Some(CloseCode::Kicked.code()));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sse_subscriber_receives_topic_messages() -> Result<()> {
+    use hyper::body::HttpBody;
+
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+
+    let topic = &format!("sse-{}", Uuid::new_v4());
+    let uri = format!("http://127.0.0.1:{}/sse/{}", port, topic).parse()?;
+    let mut response = hyper::Client::new().get(uri).await?;
+    assert_eq!(response.status(), 200);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(client_manager.get_clients_by_topic(topic).len(), 1);
+
+    let mut publisher = TestClient::connect(port, topic).await?;
+    publisher.send_message(topic, "streamed").await?;
+    let mut body = String::new();
+    while !body.contains("\n\n") {
+        let chunk = tokio::time::timeout(Duration::from_secs(2), response.body_mut().data())
+            .await?
+            .expect("stream ended")?;
+        body.push_str(std::str::from_utf8(&chunk)?);
+    }
+    let data = body.trim().strip_prefix("data:").expect("not a data event");
+    let message: ServerMessage = serde_json::from_str(data)?;
+    assert!(
+        matches!(&message, ServerMessage::Topic { content, .. } if content == "streamed"),
+        "Unexpected message {:?}",
+        message
+    );
+
+    // Closing the stream unregisters the pseudo-client, leaving only the publisher.
+    drop(response);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(client_manager.get_clients_by_topic(topic).len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_admin_membership_snapshot_and_deltas() -> Result<()> {
+    use hyper::body::HttpBody;
+    use morpheus::core::membership::{Change, MembershipEvent, MembershipSnapshot};
+
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+    let topic = &format!("members-{}", Uuid::new_v4());
+    let _first = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // 1. The snapshot is versioned by its sequence number.
+    let http = hyper::Client::new();
+    let uri: hyper::Uri = format!("http://127.0.0.1:{}/admin/membership", port).parse()?;
+    let response = http.get(uri.clone()).await?;
+    let etag = response.headers()["etag"].to_str()?.to_string();
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    let snapshot: MembershipSnapshot = serde_json::from_slice(&body)?;
+    assert_eq!(snapshot.topics[topic].len(), 1);
+    assert_eq!(etag, format!("\"{}\"", snapshot.sequence));
+    let request = hyper::Request::get(uri)
+        .header("if-none-match", &etag)
+        .body(hyper::Body::empty())?;
+    assert_eq!(http.request(request).await?.status(), 304);
+
+    // 2. Changes after the snapshot arrive as deltas, missed ones first.
+    let second = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let request = hyper::Request::get(format!("http://127.0.0.1:{}/admin/events", port))
+        .header("last-event-id", snapshot.sequence.to_string())
+        .body(hyper::Body::empty())?;
+    let mut response = http.request(request).await?;
+    assert_eq!(response.status(), 200);
+    drop(second);
+
+    let mut body = String::new();
+    while body.matches("\n\n").count() < 2 {
+        let chunk = tokio::time::timeout(Duration::from_secs(2), response.body_mut().data())
+            .await?
+            .expect("stream ended")?;
+        body.push_str(std::str::from_utf8(&chunk)?);
+    }
+    let events: Vec<MembershipEvent> = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    let changes: Vec<_> = events
+        .iter()
+        .map(|event| {
+            (
+                event.sequence - snapshot.sequence,
+                event.change,
+                event.count,
+            )
+        })
+        .collect();
+    assert_eq!(changes, vec![(1, Change::Joined, 2), (2, Change::Left, 1)]);
+    assert!(body.contains(&format!("id:{}", snapshot.sequence + 1)));
+    Ok(())
+}
+
+/// Connects to `topic` and negotiates compression, returning the server's answer.
+async fn negotiate(port: u16, topic: &str) -> Result<(TestClient, bool)> {
+    let mut client = TestClient::connect(port, topic).await?;
+    client
+        .send(&ClientMessage::Negotiate { compress: true })
+        .await?;
+    match tokio::time::timeout(Duration::from_secs(2), client.recv()).await?? {
+        Some(ServerMessage::Negotiated { compress }) => Ok((client, compress)),
+        other => panic!("Expected Negotiated, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_compressed_round_trip() -> Result<()> {
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager).await.port;
+    let topic = &format!("deflate-{}", Uuid::new_v4());
+    let (mut sender, compress) = negotiate(port, topic).await?;
+    assert!(compress);
+    let (mut receiver, compress) = negotiate(port, topic).await?;
+    assert!(compress);
+    let mut plain = TestClient::connect(port, topic).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let content = "The quick brown fox jumps over the lazy dog. ".repeat(400);
+    let frame = msg::encode(&ClientMessage::Message {
+        topic: topic.to_string(),
+        content: content.clone(),
+        headers: BTreeMap::new(),
+        qos: Qos::FireAndForget,
+        id: None,
+        reply_to: None,
+        retain: false,
+    })?;
+    let data = compression::deflate(&frame);
+    assert!(data.len() < frame.len() / 4);
+    sender.send(&ClientMessage::Compressed { data }).await?;
+
+    // Subscribers that negotiated compression get the message deflated, the others as it is.
+    let received = tokio::time::timeout(Duration::from_secs(2), receiver.recv()).await??;
+    let Some(ServerMessage::Compressed { data }) = received else {
+        panic!("Expected a compressed frame, got {:?}", received);
+    };
+    let inflated = msg::decode(&compression::inflate(&data, 1024 * 1024).unwrap())?;
+    assert!(
+        matches!(&inflated, ServerMessage::Topic { content: c, .. } if *c == content),
+        "Unexpected message {:?}",
+        inflated
+    );
+    let received = tokio::time::timeout(Duration::from_secs(2), plain.recv()).await??;
+    assert!(
+        matches!(&received, Some(ServerMessage::Topic { content: c, .. }) if *c == content),
+        "Unexpected message {:?}",
+        received
+    );
+
+    // Small frames are not worth compressing.
+    sender.send_message(topic, "hello").await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), receiver.recv()).await??;
+    assert!(
+        matches!(&received, Some(ServerMessage::Topic { content, .. }) if content == "hello"),
+        "Unexpected message {:?}",
+        received
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_compression_can_be_disabled() -> Result<()> {
+    let client_manager =
+        ClientManager::new(Arc::new(InMemoryStorage::new())).with_compression(CompressionConfig {
+            enabled: false,
+            ..CompressionConfig::default()
+        });
+    let port = TestHarness::serve(Arc::new(client_manager)).await.port;
+    let topic = &format!("deflate-{}", Uuid::new_v4());
+    let (mut client, compress) = negotiate(port, topic).await?;
+    assert!(!compress);
+
+    let data = compression::deflate(&msg::encode(&ClientMessage::Ping { nonce: 1 })?);
+    client.send(&ClientMessage::Compressed { data }).await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), client.recv()).await??;
+    assert!(
+        matches!(&received, Some(ServerMessage::Error { message }) if message.contains("disabled")),
+        "Unexpected message {:?}",
+        received
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_canary_probe_round_trip() -> Result<()> {
+    use morpheus::core::{
+        bans::BanTarget,
+        canary::{loopback_url, Canary, CanaryConfig},
+    };
+
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = TestHarness::serve(client_manager.clone()).await.port;
+    let url = loopback_url(([0, 0, 0, 0], port).into(), false, "/ws", None);
+    let mut canary = Canary::new(url, CanaryConfig::default());
+
+    for _ in 0..2 {
+        let latency = canary.probe().await.map_err(anyhow::Error::msg)?;
+        assert!(latency < CanaryConfig::default().timeout);
+    }
+
+    client_manager
+        .ban(BanTarget::Ip("127.0.0.1".parse()?))
+        .map_err(anyhow::Error::msg)?;
+    assert!(canary.probe().await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_filters_mount_under_another_app() -> Result<()> {
+    let client_manager = Arc::new(ClientManager::new(Arc::new(InMemoryStorage::new())));
+    let port = free_port().await;
+    let routes = warp::path("health")
+        .map(|| "ok")
+        .or(warp::path("chat").and(morpheus::http::filters(client_manager)));
+    tokio::spawn(warp::serve(routes).run(([127, 0, 0, 1], port)));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let topic = &format!("embedded-{}", Uuid::new_v4());
+    let url = format!("ws://127.0.0.1:{}/chat/ws", port);
+    let mut clients = Vec::new();
+    for _ in 0..2 {
+        let (ws, _) = connect_async(&url).await?;
+        let mut client = TestClient { ws };
+        client
+            .send(&ClientMessage::Connect {
+                topic: topic.to_string(),
+            })
+            .await?;
+        clients.push(client);
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    clients[0]
+        .send_message(topic, "hello from the host app")
+        .await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), clients[1].recv()).await??;
+    assert!(
+        matches!(&received, Some(ServerMessage::Topic { content, .. }) if content == "hello from the host app"),
+        "Unexpected message {:?}",
+        received
+    );
+
+    // The root is left to the host app.
+    assert!(connect_async(format!("ws://127.0.0.1:{}/ws", port))
+        .await
+        .is_err());
+    Ok(())
+}
@@ -0,0 +1,218 @@
+//! Properties every `Storage` backend must keep, checked after each step of generated
+//! sequences of connects, subscriptions, unsubscriptions, removals, broadcasts and updates
+//! of client records.
+//!
+//! A backend is covered by a property test that runs the generated operations against it
+//! with `run`. proptest shrinks a failing sequence to a minimal one and saves it under
+//! `proptest-regressions/`, so it is tried first on the next run.
+
+use morpheus::{
+    core::{
+        fanout,
+        msg::{Outgoing, ServerMessage},
+        queue::{QueueConfig, QueueReceiver},
+        storage::{InMemoryStorage, Storage},
+    },
+    testing::client_record,
+};
+use proptest::{collection::vec, prelude::*};
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+};
+use uuid::Uuid;
+
+/// Sequences checked per backend.
+const CASES: u32 = 256;
+/// The most operations in a sequence.
+const STEPS: usize = 64;
+/// Few clients and topics, so operations keep meeting each other.
+const CLIENTS: usize = 6;
+const TOPICS: [&str; 3] = ["news", "sports", "weather"];
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    /// Stores a fresh record for the client, as a new connection does.
+    Connect(usize),
+    Remove(usize),
+    Subscribe(usize, usize),
+    /// Stores the client's record again without a topic.
+    Unsubscribe(usize),
+    /// Stores the client's record again with its topic set to the given one, as a
+    /// record copied before the client moved is saved after it moved.
+    Save(usize, usize),
+    Get(usize),
+    /// Queues a message for every recipient of the topic.
+    Broadcast(usize),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let (client, topic) = (0..CLIENTS, 0..TOPICS.len());
+    prop_oneof![
+        client.clone().prop_map(Op::Connect),
+        client.clone().prop_map(Op::Remove),
+        (client.clone(), topic.clone()).prop_map(|(c, t)| Op::Subscribe(c, t)),
+        client.clone().prop_map(Op::Unsubscribe),
+        (client.clone(), topic.clone()).prop_map(|(c, t)| Op::Save(c, t)),
+        client.prop_map(Op::Get),
+        topic.prop_map(Op::Broadcast),
+    ]
+}
+
+/// What a backend must hold: the topic of every stored client, and the receiving end of
+/// the queue of every client that connected.
+#[derive(Default)]
+struct Model {
+    topics: HashMap<Uuid, Option<String>>,
+    receivers: HashMap<Uuid, QueueReceiver>,
+}
+
+/// Applies `op` to both the backend and the model.
+fn apply(storage: &dyn Storage, model: &mut Model, op: Op) -> Result<(), String> {
+    let id = |client: usize| Uuid::from_u128(client as u128 + 1);
+    match op {
+        Op::Connect(client) => {
+            let (record, receiver) = client_record(id(client), QueueConfig::default());
+            storage.add_client(record);
+            model.topics.insert(id(client), None);
+            model.receivers.insert(id(client), receiver);
+        }
+        Op::Remove(client) => {
+            let removed = storage
+                .remove_client(&id(client))
+                .map(|client| client.topic);
+            let expected = model.topics.remove(&id(client));
+            if removed != expected {
+                return Err(format!("removed {:?}, expected {:?}", removed, expected));
+            }
+        }
+        Op::Subscribe(client, topic) => {
+            storage.subscribe_client_to_topic(&id(client), TOPICS[topic].to_string());
+            if let Some(current) = model.topics.get_mut(&id(client)) {
+                *current = Some(TOPICS[topic].to_string());
+            }
+        }
+        Op::Unsubscribe(client) => save(storage, model, id(client), None),
+        Op::Save(client, topic) => save(storage, model, id(client), Some(TOPICS[topic])),
+        Op::Get(client) => {
+            let got = storage.get_client(&id(client)).map(|client| client.topic);
+            let expected = model.topics.get(&id(client)).cloned();
+            if got != expected {
+                return Err(format!("got {:?}, expected {:?}", got, expected));
+            }
+        }
+        Op::Broadcast(topic) => {
+            let message = Arc::new(Outgoing::new(ServerMessage::Error {
+                message: "hello".to_string(),
+            }));
+            for recipient in storage.topic_recipients(TOPICS[topic]).iter() {
+                fanout::enqueue(storage, recipient, message.clone());
+            }
+            for (id, receiver) in &mut model.receivers {
+                let mut received = 0;
+                while receiver.try_recv_shared().is_ok() {
+                    received += 1;
+                }
+                let subscribed = model.topics.get(id).cloned().flatten();
+                let expected = usize::from(subscribed.as_deref() == Some(TOPICS[topic]));
+                if received != expected {
+                    return Err(format!(
+                        "client {} received {} of a broadcast to '{}', expected {}",
+                        id, received, TOPICS[topic], expected
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Stores the client's record again with `topic`, if the client is stored.
+fn save(storage: &dyn Storage, model: &mut Model, id: Uuid, topic: Option<&str>) {
+    if let Some(mut stored) = storage.get_client(&id) {
+        stored.topic = topic.map(str::to_string);
+        model.topics.insert(id, stored.topic.clone());
+        storage.add_client(stored);
+    }
+}
+
+/// Checks that the backend agrees with the model: every stored client is counted once,
+/// and topics list exactly their subscribers, with no member that left or was removed
+/// and no topic without members.
+fn check(storage: &dyn Storage, model: &Model) -> Result<(), String> {
+    let stored: BTreeSet<Uuid> = storage.get_all_clients().iter().map(|c| c.id).collect();
+    let expected: BTreeSet<Uuid> = model.topics.keys().copied().collect();
+    if storage.client_count() != model.topics.len() || stored != expected {
+        return Err(format!(
+            "{} clients stored as {:?}, expected {:?}",
+            storage.client_count(),
+            stored,
+            expected
+        ));
+    }
+
+    for topic in TOPICS {
+        let expected: BTreeSet<Uuid> = model
+            .topics
+            .iter()
+            .filter(|(_, current)| current.as_deref() == Some(topic))
+            .map(|(id, _)| *id)
+            .collect();
+        let clients: Vec<Uuid> = storage
+            .get_clients_in_topic(topic)
+            .iter()
+            .map(|client| client.id)
+            .collect();
+        let recipients: Vec<Uuid> = storage
+            .topic_recipients(topic)
+            .iter()
+            .map(|recipient| recipient.id)
+            .collect();
+        for (name, members) in [("clients", clients), ("recipients", recipients)] {
+            let unique: BTreeSet<Uuid> = members.iter().copied().collect();
+            if members.len() != unique.len() || unique != expected {
+                return Err(format!(
+                    "topic '{}' has {} {:?}, expected {:?}",
+                    topic, name, members, expected
+                ));
+            }
+        }
+    }
+
+    let topics: BTreeSet<String> = storage.get_all_topics().into_iter().collect();
+    let expected: BTreeSet<String> = model.topics.values().flatten().cloned().collect();
+    if topics != expected {
+        return Err(format!("topics {:?}, expected {:?}", topics, expected));
+    }
+    Ok(())
+}
+
+/// Runs `ops` against a fresh backend, checking the invariants after every step.
+fn run(storage: &dyn Storage, ops: &[Op]) -> Result<(), TestCaseError> {
+    let mut model = Model::default();
+    for (step, op) in ops.iter().enumerate() {
+        apply(storage, &mut model, *op)
+            .and_then(|()| check(storage, &model))
+            .map_err(|violation| {
+                TestCaseError::fail(format!("step {} ({:?}): {}", step, op, violation))
+            })?;
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+    #[test]
+    fn test_in_memory_storage_keeps_its_invariants(ops in vec(op(), 1..=STEPS)) {
+        run(&InMemoryStorage::new(), &ops)?;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_storage_keeps_its_invariants(ops in vec(op(), 1..=STEPS)) {
+        use morpheus::core::sqlite::{Database, SqliteStorage};
+        let db = Database::open_in_memory().unwrap();
+        run(&SqliteStorage::new(&db).unwrap(), &ops)?;
+    }
+}
@@ -0,0 +1,466 @@
+//! Golden tests for the wire format. Every message variant is encoded and compared with
+//! the frames recorded under `tests/golden/`, one per line, so a renamed field, variant or
+//! tag shows up as a failing line instead of as a broken deployed client. neo checks the
+//! same files against its copy of the protocol.
+//!
+//! After an intended protocol change, rewrite the files with
+//! `UPDATE_GOLDEN=1 cargo test --test wire_format` and review the diff. Other encodings,
+//! should they be added, get golden files of their own next to these.
+
+use morpheus::core::msg::{
+    self, ClientMessage, FetchedMessage, Limit, Qos, ServerMessage, TopicSummary,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{collections::BTreeMap, fmt::Debug, path::PathBuf};
+use uuid::Uuid;
+
+fn id(n: u128) -> Uuid {
+    Uuid::from_u128(n)
+}
+
+fn headers() -> BTreeMap<String, String> {
+    BTreeMap::from([("trace-id".to_string(), "abc123".to_string())])
+}
+
+/// The name of a client message's variant. The match has no wildcard so that adding a
+/// variant fails to compile until it is given a sample below.
+fn client_variant(message: &ClientMessage) -> &'static str {
+    match message {
+        ClientMessage::Connect { .. } => "Connect",
+        ClientMessage::ConnectSession { .. } => "ConnectSession",
+        ClientMessage::Message { .. } => "Message",
+        ClientMessage::ReplyToMorpheus { .. } => "ReplyToMorpheus",
+        ClientMessage::MessageReceived { .. } => "MessageReceived",
+        ClientMessage::AcceptTerms { .. } => "AcceptTerms",
+        ClientMessage::Fetch { .. } => "Fetch",
+        ClientMessage::CommitOffset { .. } => "CommitOffset",
+        ClientMessage::RequestMissed { .. } => "RequestMissed",
+        ClientMessage::Resync { .. } => "Resync",
+        ClientMessage::CreatePoll { .. } => "CreatePoll",
+        ClientMessage::Vote { .. } => "Vote",
+        ClientMessage::ListTopics => "ListTopics",
+        ClientMessage::AttachmentStart { .. } => "AttachmentStart",
+        ClientMessage::AttachmentComplete { .. } => "AttachmentComplete",
+        ClientMessage::PublicKey { .. } => "PublicKey",
+        ClientMessage::Ping { .. } => "Ping",
+        ClientMessage::Authenticate { .. } => "Authenticate",
+        ClientMessage::Kick { .. } => "Kick",
+        ClientMessage::Ban { .. } => "Ban",
+        ClientMessage::Request { .. } => "Request",
+        ClientMessage::Respond { .. } => "Respond",
+        ClientMessage::Negotiate { .. } => "Negotiate",
+        ClientMessage::Compressed { .. } => "Compressed",
+        // Only ever decoded, never sent.
+        ClientMessage::Unknown => "Unknown",
+    }
+}
+
+/// Like `client_variant`, for server messages.
+fn server_variant(message: &ServerMessage) -> &'static str {
+    match message {
+        ServerMessage::Global { .. } => "Global",
+        ServerMessage::Topic { .. } => "Topic",
+        ServerMessage::Private { .. } => "Private",
+        ServerMessage::MessageDelivered { .. } => "MessageDelivered",
+        ServerMessage::MessageAcknowledged { .. } => "MessageAcknowledged",
+        ServerMessage::Error { .. } => "Error",
+        ServerMessage::TopicMoved { .. } => "TopicMoved",
+        ServerMessage::Motd { .. } => "Motd",
+        ServerMessage::TopicInfo { .. } => "TopicInfo",
+        ServerMessage::LimitExceeded { .. } => "LimitExceeded",
+        ServerMessage::Kicked { .. } => "Kicked",
+        ServerMessage::Welcome { .. } => "Welcome",
+        ServerMessage::Fetched { .. } => "Fetched",
+        ServerMessage::OffsetCommitted { .. } => "OffsetCommitted",
+        ServerMessage::Published { .. } => "Published",
+        ServerMessage::Poll { .. } => "Poll",
+        ServerMessage::PollResults { .. } => "PollResults",
+        ServerMessage::Voted { .. } => "Voted",
+        ServerMessage::TopicList { .. } => "TopicList",
+        ServerMessage::AttachmentStart { .. } => "AttachmentStart",
+        ServerMessage::AttachmentComplete { .. } => "AttachmentComplete",
+        ServerMessage::AttachmentAborted { .. } => "AttachmentAborted",
+        ServerMessage::PublicKey { .. } => "PublicKey",
+        ServerMessage::Pong { .. } => "Pong",
+        ServerMessage::Request { .. } => "Request",
+        ServerMessage::Response { .. } => "Response",
+        ServerMessage::Negotiated { .. } => "Negotiated",
+        ServerMessage::Compressed { .. } => "Compressed",
+        // Relayed as a binary frame, never encoded.
+        ServerMessage::AttachmentChunk { .. } => "AttachmentChunk",
+        ServerMessage::Unknown => "Unknown",
+    }
+}
+
+/// One sample of every client message, with optional fields both left out and set.
+fn client_samples() -> Vec<ClientMessage> {
+    vec![
+        ClientMessage::Connect {
+            topic: "general".to_string(),
+        },
+        ClientMessage::ConnectSession {
+            topic: "general".to_string(),
+            session_id: None,
+        },
+        ClientMessage::ConnectSession {
+            topic: "general".to_string(),
+            session_id: Some(id(1)),
+        },
+        ClientMessage::Message {
+            topic: "general".to_string(),
+            content: "Hello".to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            id: None,
+            reply_to: None,
+            retain: false,
+        },
+        ClientMessage::Message {
+            topic: "general".to_string(),
+            content: "Hello".to_string(),
+            headers: headers(),
+            qos: Qos::ExactlyOnce,
+            id: Some(id(2)),
+            reply_to: Some(id(6)),
+            retain: false,
+        },
+        ClientMessage::Message {
+            topic: "sensors".to_string(),
+            content: "21.5".to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            id: None,
+            reply_to: None,
+            retain: true,
+        },
+        ClientMessage::ReplyToMorpheus {
+            original_msg_id: id(3),
+            content: "Thanks".to_string(),
+        },
+        ClientMessage::MessageReceived { msg_id: id(4) },
+        ClientMessage::AcceptTerms {
+            topic: "resistance".to_string(),
+            version: 2,
+        },
+        ClientMessage::Fetch {
+            group: "workers".to_string(),
+            max: 10,
+        },
+        ClientMessage::CommitOffset {
+            group: "workers".to_string(),
+            offset: 42,
+        },
+        ClientMessage::RequestMissed {
+            topic: "general".to_string(),
+            from_seq: 7,
+        },
+        ClientMessage::Resync {
+            topic: "general".to_string(),
+            from_seq: 7,
+            acked: Vec::new(),
+        },
+        ClientMessage::Resync {
+            topic: "general".to_string(),
+            from_seq: 7,
+            acked: vec![id(8)],
+        },
+        ClientMessage::CreatePoll {
+            question: "Red or blue?".to_string(),
+            options: vec!["Red".to_string(), "Blue".to_string()],
+            duration_secs: None,
+        },
+        ClientMessage::CreatePoll {
+            question: "Red or blue?".to_string(),
+            options: vec!["Red".to_string(), "Blue".to_string()],
+            duration_secs: Some(60),
+        },
+        ClientMessage::Vote {
+            poll_id: id(5),
+            option: 1,
+        },
+        ClientMessage::ListTopics,
+        ClientMessage::AttachmentStart {
+            id: id(7),
+            topic: "general".to_string(),
+            name: "red-pill.png".to_string(),
+            content_type: "image/png".to_string(),
+            size: 2048,
+        },
+        ClientMessage::AttachmentComplete { id: id(7) },
+        ClientMessage::PublicKey {
+            topic: "general".to_string(),
+            key: "bW9ycGhldXMta2V5LTMyLWJ5dGVzLWxvbmctLS0tLS0=".to_string(),
+        },
+        ClientMessage::Ping { nonce: 7 },
+        ClientMessage::Authenticate {
+            token: "eyJhbGciOiJIUzI1NiJ9.e30.signature".to_string(),
+        },
+        ClientMessage::Kick {
+            client_id: id(30),
+            reason: Some("spam".to_string()),
+        },
+        ClientMessage::Ban { client_id: id(31) },
+        ClientMessage::Request {
+            topic: "rpc".to_string(),
+            correlation_id: id(32),
+            content: "status?".to_string(),
+        },
+        ClientMessage::Respond {
+            correlation_id: id(32),
+            content: "ok".to_string(),
+        },
+        ClientMessage::Negotiate { compress: true },
+        ClientMessage::Compressed {
+            data: "q1bKL0pRslIqSs1JTSxOVaoFAA==".to_string(),
+        },
+    ]
+}
+
+/// One sample of every server message, with optional fields both left out and set.
+fn server_samples() -> Vec<ServerMessage> {
+    let topic = |headers, qos, reply_to| ServerMessage::Topic {
+        id: id(10),
+        topic: "general".to_string(),
+        seq: 3,
+        sender: "Morpheus".to_string(),
+        content: "Hello".to_string(),
+        headers,
+        qos,
+        reply_to,
+        retained: false,
+    };
+    vec![
+        ServerMessage::Global {
+            id: id(11),
+            content: "Hello everyone".to_string(),
+        },
+        topic(BTreeMap::new(), Qos::FireAndForget, None),
+        topic(headers(), Qos::AtLeastOnce, Some(id(21))),
+        ServerMessage::Topic {
+            id: id(33),
+            topic: "sensors".to_string(),
+            seq: 0,
+            sender: "neo".to_string(),
+            content: "21.5".to_string(),
+            headers: BTreeMap::new(),
+            qos: Qos::FireAndForget,
+            reply_to: None,
+            retained: true,
+        },
+        ServerMessage::Private {
+            id: id(12),
+            content: "Psst".to_string(),
+        },
+        ServerMessage::MessageDelivered { msg_id: id(13) },
+        ServerMessage::MessageAcknowledged {
+            msg_id: id(14),
+            client_id: id(15),
+        },
+        ServerMessage::Error {
+            message: "Oops".to_string(),
+        },
+        ServerMessage::TopicMoved {
+            from: "a".to_string(),
+            to: "b".to_string(),
+        },
+        ServerMessage::Motd {
+            content: "Welcome to the real world".to_string(),
+        },
+        ServerMessage::TopicInfo {
+            topic: "resistance".to_string(),
+            rules: "Be kind.".to_string(),
+            version: 2,
+        },
+        ServerMessage::LimitExceeded {
+            limit: Limit::Connections,
+            max: 100,
+        },
+        ServerMessage::LimitExceeded {
+            limit: Limit::TopicClients,
+            max: 10,
+        },
+        ServerMessage::Kicked { reason: None },
+        ServerMessage::Kicked {
+            reason: Some("Spam".to_string()),
+        },
+        ServerMessage::Welcome {
+            client_id: id(16),
+            session_id: id(17),
+            resumed: true,
+            reconnect_jitter_ms: 10_000,
+        },
+        ServerMessage::Fetched {
+            topic: "jobs".to_string(),
+            group: "workers".to_string(),
+            messages: vec![FetchedMessage {
+                seq: 1,
+                id: id(18),
+                sender: "Morpheus".to_string(),
+                content: "Job".to_string(),
+                headers: headers(),
+            }],
+        },
+        ServerMessage::OffsetCommitted {
+            topic: "jobs".to_string(),
+            group: "workers".to_string(),
+            offset: 1,
+        },
+        ServerMessage::Published { id: id(19) },
+        ServerMessage::Poll {
+            id: id(20),
+            topic: "general".to_string(),
+            question: "Red or blue?".to_string(),
+            options: vec!["Red".to_string(), "Blue".to_string()],
+            creator: "Morpheus".to_string(),
+            closes_in_secs: 300,
+        },
+        ServerMessage::PollResults {
+            id: id(20),
+            topic: "general".to_string(),
+            question: "Red or blue?".to_string(),
+            options: vec!["Red".to_string(), "Blue".to_string()],
+            votes: vec![2, 1],
+        },
+        ServerMessage::Voted {
+            poll_id: id(20),
+            option: 0,
+        },
+        ServerMessage::TopicList {
+            topics: vec![TopicSummary {
+                name: "general".to_string(),
+                clients: 3,
+            }],
+        },
+        ServerMessage::AttachmentStart {
+            id: id(22),
+            topic: "general".to_string(),
+            sender: "neo".to_string(),
+            name: "red-pill.png".to_string(),
+            content_type: "image/png".to_string(),
+            size: 2048,
+        },
+        ServerMessage::AttachmentComplete { id: id(22) },
+        ServerMessage::AttachmentAborted {
+            id: id(22),
+            reason: "sender disconnected".to_string(),
+        },
+        ServerMessage::PublicKey {
+            topic: "general".to_string(),
+            sender: "neo".to_string(),
+            key: "bW9ycGhldXMta2V5LTMyLWJ5dGVzLWxvbmctLS0tLS0=".to_string(),
+        },
+        ServerMessage::Pong { nonce: 7 },
+        ServerMessage::Request {
+            topic: "rpc".to_string(),
+            correlation_id: id(32),
+            sender: "neo".to_string(),
+            content: "status?".to_string(),
+        },
+        ServerMessage::Response {
+            correlation_id: id(32),
+            content: "ok".to_string(),
+        },
+        ServerMessage::Negotiated { compress: true },
+        ServerMessage::Compressed {
+            data: "q1bKL0pRslIqSs1JTSxOVaoFAA==".to_string(),
+        },
+    ]
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(name)
+}
+
+/// Compares the frames of `samples` with a golden file, line by line, or rewrites the file
+/// when `UPDATE_GOLDEN` is set. Every mismatch is reported, not just the first.
+fn check_golden<T>(name: &str, samples: &[T], variant: fn(&T) -> &'static str)
+where
+    T: Serialize + DeserializeOwned + Debug,
+{
+    let frames: Vec<String> = samples.iter().map(|s| msg::encode(s).unwrap()).collect();
+    let path = golden_path(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, frames.join("\n") + "\n").unwrap();
+        return;
+    }
+    let golden = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Cannot read {}: {}", path.display(), e));
+    let golden: Vec<&str> = golden.lines().collect();
+
+    let mut mismatches = Vec::new();
+    for (i, (sample, frame)) in samples.iter().zip(&frames).enumerate() {
+        match golden.get(i) {
+            Some(expected) if expected == frame => {}
+            Some(expected) => mismatches.push(format!(
+                "line {} ({}):\n  expected: {}\n  actual:   {}",
+                i + 1,
+                variant(sample),
+                expected,
+                frame
+            )),
+            None => mismatches.push(format!(
+                "line {} ({}) is missing:\n  actual:   {}",
+                i + 1,
+                variant(sample),
+                frame
+            )),
+        }
+    }
+    for (i, extra) in golden.iter().enumerate().skip(frames.len()) {
+        mismatches.push(format!(
+            "line {} has no sample:\n  expected: {}",
+            i + 1,
+            extra
+        ));
+    }
+    assert!(
+        mismatches.is_empty(),
+        "The wire format of {} changed. If this is intended, run \
+         `UPDATE_GOLDEN=1 cargo test --test wire_format` and review the diff.\n\n{}",
+        name,
+        mismatches.join("\n")
+    );
+
+    // The golden frames must also decode to the same messages.
+    for (expected, sample) in golden.iter().zip(samples) {
+        let decoded: T = msg::decode(expected).unwrap();
+        assert_eq!(
+            variant(&decoded),
+            variant(sample),
+            "{} decoded to {:?}",
+            expected,
+            decoded
+        );
+        assert_eq!(msg::encode(&decoded).unwrap(), *expected);
+    }
+}
+
+/// Checks that the samples cover every variant except `Unknown`.
+fn assert_covers<T>(samples: &[T], variant: fn(&T) -> &'static str, count: usize) {
+    let mut covered: Vec<_> = samples.iter().map(variant).collect();
+    covered.dedup();
+    assert!(!covered.contains(&"Unknown"));
+    assert_eq!(
+        covered.len(),
+        count,
+        "Every variant needs a sample: {:?}",
+        covered
+    );
+}
+
+#[test]
+fn test_client_messages_match_golden_frames() {
+    let samples = client_samples();
+    assert_covers(&samples, client_variant, 24);
+    check_golden("client_messages.jsonl", &samples, client_variant);
+}
+
+#[test]
+fn test_server_messages_match_golden_frames() {
+    let samples = server_samples();
+    assert_covers(&samples, server_variant, 28);
+    check_golden("server_messages.jsonl", &samples, server_variant);
+}
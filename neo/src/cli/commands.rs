@@ -1,3 +1,4 @@
+use std::path::PathBuf;
 use uuid::Uuid;
 
 /// Represents a command issued by the user.
@@ -7,6 +8,47 @@ pub enum Command {
     Message(String),
     /// Reply to a specific message.
     Reply { msg_id: Uuid, content: String },
+    /// Re-establish the connection to the server.
+    Reconnect,
+    /// Send the saved draft for the current topic.
+    SendDraft,
+    /// Discard the saved draft for the current topic.
+    ClearDraft,
+    /// Open a poll in the current topic, open for `duration_secs` or the server's default.
+    Poll {
+        question: String,
+        options: Vec<String>,
+        duration_secs: Option<u64>,
+    },
+    /// Vote for an option of a poll, counted from 0.
+    Vote { poll_id: Uuid, option: usize },
+    /// Ask the other subscribers of the current topic for a response.
+    Request(String),
+    /// Answer a request by its correlation ID.
+    Respond {
+        correlation_id: Uuid,
+        content: String,
+    },
+    /// Ask the server which topics have subscribers.
+    Topics,
+    /// Show the client's ID, topics, server, uptime and round-trip latency.
+    Whoami,
+    /// Join a topic and make it the current one.
+    Join(String),
+    /// Leave a joined topic.
+    Leave(String),
+    /// Make another joined topic the current one.
+    Switch(String),
+    /// Send a file to the current topic.
+    Attach(PathBuf),
+    /// Turn end-to-end encryption of sent messages on or off.
+    E2e(bool),
+    /// Replace the key pair used for end-to-end encryption.
+    Keygen,
+    /// Show the key fingerprints of this client and the topic's subscribers.
+    Keys,
+    /// Mark the subscriber key with this fingerprint as verified.
+    Verify(String),
     /// Show help message.
     Help,
     /// An unknown or invalid command.
@@ -40,6 +82,82 @@ pub fn parse_command(input: &str) -> Command {
             }
         }
         "/help" | "/h" => Command::Help,
+        "/reconnect" => Command::Reconnect,
+        "/topics" => Command::Topics,
+        "/whoami" => Command::Whoami,
+        "/join" | "/leave" | "/switch" => {
+            let (Some(topic), None) = (parts.next(), parts.next()) else {
+                return Command::Unknown(format!("Usage: {} <topic>", command));
+            };
+            match command {
+                "/join" => Command::Join(topic.to_string()),
+                "/leave" => Command::Leave(topic.to_string()),
+                _ => Command::Switch(topic.to_string()),
+            }
+        }
+        "/e2e" => match parts.next() {
+            Some("on") => Command::E2e(true),
+            Some("off") => Command::E2e(false),
+            _ => Command::Unknown("Usage: /e2e on|off".to_string()),
+        },
+        "/keygen" => Command::Keygen,
+        "/keys" => Command::Keys,
+        "/verify" => {
+            let fingerprint = parts.collect::<Vec<&str>>().join(" ");
+            if fingerprint.is_empty() {
+                Command::Unknown("Usage: /verify <fingerprint>".to_string())
+            } else {
+                Command::Verify(fingerprint)
+            }
+        }
+        "/attach" => {
+            let path = parts.collect::<Vec<&str>>().join(" ");
+            if path.is_empty() {
+                Command::Unknown("Usage: /attach <path>".to_string())
+            } else {
+                Command::Attach(PathBuf::from(path))
+            }
+        }
+        "/draft" => match parts.next() {
+            None => Command::SendDraft,
+            Some("clear") => Command::ClearDraft,
+            Some(_) => Command::Unknown("Usage: /draft [clear]".to_string()),
+        },
+        "/poll" => parse_poll(&parts.collect::<Vec<&str>>().join(" ")),
+        "/vote" => {
+            let usage = || Command::Unknown("Usage: /vote <poll_id> <option number>".to_string());
+            let (Some(poll_id), Some(option)) = (parts.next(), parts.next()) else {
+                return usage();
+            };
+            match (Uuid::parse_str(poll_id), option.trim().parse::<usize>()) {
+                (Ok(poll_id), Ok(option)) if option > 0 => Command::Vote {
+                    poll_id,
+                    option: option - 1,
+                },
+                _ => usage(),
+            }
+        }
+        "/request" => {
+            let content = parts.collect::<Vec<&str>>().join(" ");
+            if content.is_empty() {
+                Command::Unknown("Usage: /request <text>".to_string())
+            } else {
+                Command::Request(content)
+            }
+        }
+        "/respond" => {
+            let usage = || Command::Unknown("Usage: /respond <correlation_id> <text>".to_string());
+            let (Some(correlation_id), Some(content)) = (parts.next(), parts.next()) else {
+                return usage();
+            };
+            match Uuid::parse_str(correlation_id) {
+                Ok(correlation_id) => Command::Respond {
+                    correlation_id,
+                    content: content.to_string(),
+                },
+                Err(_) => usage(),
+            }
+        }
         "/msg" | "/m" => {
             let content = parts.collect::<Vec<&str>>().join(" ");
             if content.is_empty() {
@@ -52,6 +170,62 @@ pub fn parse_command(input: &str) -> Command {
     }
 }
 
+fn parse_poll(args: &str) -> Command {
+    let usage = || {
+        Command::Unknown(
+            "Usage: /poll \"<question>\" <option> <option>... [--for <30s|5m|1h>]".to_string(),
+        )
+    };
+    let Some(mut words) = split_quoted(args) else {
+        return usage();
+    };
+    let mut duration_secs = None;
+    if let Some(flag) = words.iter().position(|word| word == "--for") {
+        match words.get(flag + 1).and_then(|value| parse_seconds(value)) {
+            Some(seconds) => duration_secs = Some(seconds),
+            None => return usage(),
+        }
+        words.drain(flag..flag + 2);
+    }
+    let mut words = words.into_iter();
+    match words.next() {
+        Some(question) => Command::Poll {
+            question,
+            options: words.collect(),
+            duration_secs,
+        },
+        None => usage(),
+    }
+}
+
+/// Parses a duration such as `30s`, `5m` or `1h` into seconds.
+fn parse_seconds(value: &str) -> Option<u64> {
+    let (amount, unit) = value.split_at(value.len().checked_sub(1)?);
+    let amount: u64 = amount.parse().ok()?;
+    match unit {
+        "s" => Some(amount),
+        "m" => Some(amount * 60),
+        "h" => Some(amount * 3600),
+        _ => None,
+    }
+}
+
+/// Splits on whitespace, keeping double-quoted phrases together. Returns `None` if a
+/// quote is not closed.
+fn split_quoted(args: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut rest = args.trim_start();
+    while !rest.is_empty() {
+        let (word, after) = match rest.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"')?,
+            None => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+        };
+        words.push(word.to_string());
+        rest = after.trim_start();
+    }
+    Some(words)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -111,6 +285,129 @@ mod tests {
         assert_eq!(parse_command("/h"), Command::Help);
     }
 
+    #[test]
+    fn test_parse_reconnect_command() {
+        assert_eq!(parse_command("/reconnect"), Command::Reconnect);
+        assert_eq!(parse_command("/topics"), Command::Topics);
+        assert_eq!(parse_command("/whoami"), Command::Whoami);
+    }
+
+    #[test]
+    fn test_parse_topic_commands() {
+        assert_eq!(parse_command("/join ops"), Command::Join("ops".to_string()));
+        assert_eq!(
+            parse_command("/leave ops"),
+            Command::Leave("ops".to_string())
+        );
+        assert_eq!(
+            parse_command("/switch ops"),
+            Command::Switch("ops".to_string())
+        );
+        assert_eq!(
+            parse_command("/join"),
+            Command::Unknown("Usage: /join <topic>".to_string())
+        );
+        assert_eq!(
+            parse_command("/switch ops general"),
+            Command::Unknown("Usage: /switch <topic>".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_draft_command() {
+        assert_eq!(parse_command("/draft"), Command::SendDraft);
+        assert_eq!(parse_command("/draft clear"), Command::ClearDraft);
+        assert_eq!(
+            parse_command("/draft keep"),
+            Command::Unknown("Usage: /draft [clear]".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_attach_command() {
+        assert_eq!(
+            parse_command("/attach my notes.txt"),
+            Command::Attach(PathBuf::from("my notes.txt"))
+        );
+        assert_eq!(
+            parse_command("/attach"),
+            Command::Unknown("Usage: /attach <path>".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_e2e_commands() {
+        assert_eq!(parse_command("/e2e on"), Command::E2e(true));
+        assert_eq!(parse_command("/e2e off"), Command::E2e(false));
+        assert_eq!(
+            parse_command("/e2e"),
+            Command::Unknown("Usage: /e2e on|off".to_string())
+        );
+        assert_eq!(parse_command("/keys"), Command::Keys);
+        assert_eq!(parse_command("/keygen"), Command::Keygen);
+        assert_eq!(
+            parse_command("/verify AB:CD EF"),
+            Command::Verify("AB:CD EF".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_poll_and_vote() {
+        assert_eq!(
+            parse_command("/poll \"Red or blue?\" Red \"Light blue\" --for 2m"),
+            Command::Poll {
+                question: "Red or blue?".to_string(),
+                options: vec!["Red".to_string(), "Light blue".to_string()],
+                duration_secs: Some(120)
+            }
+        );
+        assert!(matches!(
+            parse_command("/poll \"Unclosed Red Blue"),
+            Command::Unknown(_)
+        ));
+        assert!(matches!(
+            parse_command("/poll Ready? Yes No --for soon"),
+            Command::Unknown(_)
+        ));
+
+        let poll_id = Uuid::new_v4();
+        assert_eq!(
+            parse_command(&format!("/vote {} 2", poll_id)),
+            Command::Vote { poll_id, option: 1 }
+        );
+        assert!(matches!(
+            parse_command(&format!("/vote {} 0", poll_id)),
+            Command::Unknown(_)
+        ));
+        assert!(matches!(parse_command("/vote"), Command::Unknown(_)));
+    }
+
+    #[test]
+    fn test_parse_request_and_respond() {
+        assert_eq!(
+            parse_command("/request disk usage?"),
+            Command::Request("disk usage?".to_string())
+        );
+        assert!(matches!(parse_command("/request"), Command::Unknown(_)));
+
+        let correlation_id = Uuid::new_v4();
+        assert_eq!(
+            parse_command(&format!("/respond {} 42% used", correlation_id)),
+            Command::Respond {
+                correlation_id,
+                content: "42% used".to_string()
+            }
+        );
+        assert!(matches!(
+            parse_command("/respond not-an-id ok"),
+            Command::Unknown(_)
+        ));
+        assert!(matches!(
+            parse_command(&format!("/respond {}", correlation_id)),
+            Command::Unknown(_)
+        ));
+    }
+
     #[test]
     fn test_parse_unknown_command() {
         let input = "/foo bar";
@@ -1,2 +1,5 @@
 pub mod commands;
+pub mod pipe;
+#[cfg(feature = "tui")]
+pub mod tui;
 pub mod ui;
@@ -0,0 +1,82 @@
+use crate::{
+    cli::ui,
+    core::{
+        client::Client,
+        events::Event,
+        msg::{self, ServerMessage},
+    },
+};
+use futures_util::StreamExt;
+use std::{collections::HashSet, time::Duration};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt},
+    time::Instant,
+};
+
+/// How long pipe mode waits for the server to confirm what was sent once its input ends.
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs `client` as part of a shell pipeline: publishes every line of `input` to the
+/// topic and writes every server message to `output` as a line of JSON. Returns at the
+/// end of `input`, once the server confirmed the messages sent with a QoS above
+/// fire-and-forget.
+pub async fn run<R, W>(
+    client: Client,
+    input: &mut R,
+    output: &mut W,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let (client, mut events) = client.spawn();
+    let mut unconfirmed = HashSet::new();
+    let mut input_buf = String::new();
+    // When to stop waiting for confirmations, once the input has ended.
+    let mut give_up = None;
+
+    while give_up.is_none() || !unconfirmed.is_empty() {
+        tokio::select! {
+            event = events.next() => match event {
+                Some(Event::Message(message)) => {
+                    if let ServerMessage::Published { id } = &message {
+                        unconfirmed.remove(id);
+                    }
+                    // Chunks have no JSON form; the attachment's start and end do.
+                    if !matches!(message, ServerMessage::AttachmentChunk { .. }) {
+                        let line = format!("{}\n", msg::encode(&message)?);
+                        output.write_all(line.as_bytes()).await?;
+                        output.flush().await?;
+                    }
+                }
+                Some(Event::Error(e)) => return Err(e.into()),
+                Some(Event::Disconnected { .. } | Event::Reconnected) => {}
+                None => return Ok(()),
+            },
+            result = input.read_line(&mut input_buf), if give_up.is_none() => {
+                if result? == 0 {
+                    give_up = Some(Instant::now() + CONFIRM_TIMEOUT);
+                } else {
+                    let content = input_buf.trim();
+                    if !content.is_empty() {
+                        unconfirmed.extend(client.publish(content).await?);
+                    }
+                    input_buf.clear();
+                }
+            }
+            _ = tokio::time::sleep_until(give_up.unwrap_or_else(Instant::now)),
+                if give_up.is_some() =>
+            {
+                ui::print_error(&format!(
+                    "The server did not confirm {} message(s).",
+                    unconfirmed.len()
+                ));
+                break;
+            }
+        }
+    }
+    client.close().await;
+    // Wait for the close frame to be sent.
+    while events.next().await.is_some() {}
+    Ok(())
+}
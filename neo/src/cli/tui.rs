@@ -0,0 +1,369 @@
+use crate::{
+    cli::ui::{self, Output},
+    core::client::Client,
+};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, Borders, Paragraph},
+    DefaultTerminal, Frame,
+};
+use std::{
+    collections::VecDeque,
+    io,
+    sync::mpsc::{self as std_mpsc, TryRecvError},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncWriteExt, BufReader},
+    sync::mpsc,
+};
+
+/// How long the screen waits for a key before showing new output.
+const TICK: Duration = Duration::from_millis(50);
+/// How many lines the message pane keeps.
+const SCROLLBACK: usize = 5000;
+
+/// What a key press asks the screen to do.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Action {
+    Continue,
+    Quit,
+    /// Hand a line typed into the input line to the client.
+    Send(String),
+}
+
+/// A line of the message pane.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct PaneLine {
+    text: String,
+    error: bool,
+}
+
+/// The message pane, its scroll position and the input line.
+#[derive(Debug, Default)]
+struct Screen {
+    lines: VecDeque<PaneLine>,
+    /// How many rows the pane is scrolled up from the bottom; it follows new output at 0.
+    scroll: usize,
+    /// Messages that arrived while scrolled up.
+    unread: usize,
+    /// The line the unread messages start at.
+    first_unread: Option<usize>,
+    /// Whether the message being received got a line while scrolled up.
+    unread_message: bool,
+    /// The width of the pane when it was last drawn, which lines are wrapped to.
+    width: usize,
+    /// The height of the pane when it was last drawn.
+    height: usize,
+    input: String,
+}
+
+impl Screen {
+    /// Adds output from the client. Blank lines are dropped and messages separated by one
+    /// instead.
+    fn add(&mut self, output: Output) {
+        match output {
+            Output::Line { text, error } => {
+                for text in text.lines().filter(|text| !text.trim().is_empty()) {
+                    self.push(PaneLine {
+                        text: text.to_string(),
+                        error,
+                    });
+                }
+            }
+            Output::End => {
+                if self.lines.back().is_some_and(|line| !line.text.is_empty()) {
+                    self.push(PaneLine {
+                        text: String::new(),
+                        error: false,
+                    });
+                }
+                if std::mem::take(&mut self.unread_message) {
+                    self.unread += 1;
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, line: PaneLine) {
+        if self.scroll > 0 {
+            // Keep the rows being read in place.
+            self.scroll += rows(&line.text, self.width);
+            if !line.text.is_empty() {
+                self.unread_message = true;
+                if self.first_unread.is_none() {
+                    self.first_unread = Some(self.lines.len());
+                    // The marker above it is a row too.
+                    self.scroll += 1;
+                }
+            }
+        }
+        self.lines.push_back(line);
+        if self.lines.len() > SCROLLBACK {
+            self.lines.pop_front();
+            self.first_unread = self.first_unread.map(|first| first.saturating_sub(1));
+        }
+    }
+
+    fn scroll_by(&mut self, delta: isize) {
+        self.scroll = self.scroll.saturating_add_signed(delta);
+        if self.scroll == 0 {
+            self.unread = 0;
+            self.first_unread = None;
+        }
+    }
+
+    fn on_key(&mut self, key: KeyEvent) -> Action {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        let page = self.height.saturating_sub(1).max(1) as isize;
+        match key.code {
+            KeyCode::Char('c' | 'd') if control => return Action::Quit,
+            KeyCode::Esc => return Action::Quit,
+            KeyCode::Char(c) if !control => self.input.push(c),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Enter => {
+                let line = std::mem::take(&mut self.input);
+                self.scroll_by(-(self.scroll as isize));
+                if !line.trim().is_empty() {
+                    return Action::Send(line);
+                }
+            }
+            KeyCode::Up => self.scroll_by(1),
+            KeyCode::Down => self.scroll_by(-1),
+            KeyCode::PageUp => self.scroll_by(page),
+            KeyCode::PageDown => self.scroll_by(-page),
+            KeyCode::Home => self.scroll_by(isize::MAX),
+            KeyCode::End => self.scroll_by(-(self.scroll as isize)),
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    /// The pane's rows: every line wrapped to `width`, with a marker where the unread
+    /// messages start.
+    fn rows(&self, width: usize) -> Vec<Line<'static>> {
+        let mut rows = Vec::new();
+        for (index, line) in self.lines.iter().enumerate() {
+            if self.first_unread == Some(index) {
+                rows.push(
+                    Line::from(format!("── {} unread ──", self.unread.max(1)))
+                        .style(Style::default().fg(Color::Yellow)),
+                );
+            }
+            let style = if line.error {
+                Style::default().fg(Color::Red)
+            } else {
+                Style::default()
+            };
+            rows.extend(wrap(&line.text, width).map(|row| Line::from(row).style(style)));
+        }
+        rows
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, pane, input] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(3),
+        ])
+        .areas(frame.area());
+
+        let mut status =
+            "neo  PgUp/PgDn or ↑/↓ scroll  End jump to the newest  Esc quit".to_string();
+        if self.unread > 0 {
+            status.push_str(&format!("  [{} unread ↓]", self.unread));
+        }
+        frame.render_widget(
+            Paragraph::new(status).style(Style::default().add_modifier(Modifier::REVERSED)),
+            header,
+        );
+
+        self.width = pane.width.max(1) as usize;
+        self.height = pane.height as usize;
+        let rows = self.rows(self.width);
+        self.scroll = self.scroll.min(rows.len().saturating_sub(self.height));
+        let end = rows.len() - self.scroll;
+        let start = end.saturating_sub(self.height);
+        frame.render_widget(Paragraph::new(rows[start..end].to_vec()), pane);
+
+        self.draw_input(frame, input);
+    }
+
+    fn draw_input(&self, frame: &mut Frame, area: Rect) {
+        // Keep the end of a long line in view.
+        let visible = area.width.saturating_sub(2) as usize;
+        let skip = self
+            .input
+            .chars()
+            .count()
+            .saturating_sub(visible.saturating_sub(1));
+        let shown: String = self.input.chars().skip(skip).collect();
+        let x = area.x + 1 + shown.chars().count() as u16;
+        frame.set_cursor_position((x, area.y + 1));
+        frame.render_widget(
+            Paragraph::new(shown).block(Block::default().borders(Borders::ALL).title(" > ")),
+            area,
+        );
+    }
+}
+
+/// How many rows `text` takes wrapped to `width`.
+fn rows(text: &str, width: usize) -> usize {
+    wrap(text, width).count()
+}
+
+/// Splits `text` into rows of at most `width` characters; an empty line is one row.
+fn wrap(text: &str, width: usize) -> impl Iterator<Item = String> + '_ {
+    let chars: Vec<char> = text.chars().collect();
+    let width = width.max(1);
+    let count = chars.len().div_ceil(width).max(1);
+    (0..count).map(move |row| {
+        chars
+            .iter()
+            .skip(row * width)
+            .take(width)
+            .collect::<String>()
+    })
+}
+
+/// Runs `client` with a scrollable message pane above a separate input line instead of
+/// printing to the console, until the client stops or the user quits with Esc.
+pub async fn run(client: &mut Client) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (output, received) = std_mpsc::channel();
+    let (lines, mut typed) = mpsc::unbounded_channel::<String>();
+    let (reader, mut writer) = tokio::io::duplex(4096);
+    // The client reads what is typed as if it were stdin, and stops at the end of it
+    // once the screen is closed.
+    tokio::spawn(async move {
+        while let Some(line) = typed.recv().await {
+            if writer
+                .write_all(format!("{}\n", line).as_bytes())
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    ui::redirect(output);
+    let screen = tokio::task::spawn_blocking(move || show(received, lines));
+    let result = client.run(&mut BufReader::new(reader)).await;
+    // Closes the screen if the client stopped on its own.
+    ui::restore();
+    let unsent = screen.await??;
+    client.save_unsent(&unsent);
+    result
+}
+
+/// Shows the screen until the user quits or the client stops, and returns what was left
+/// on the input line.
+fn show(
+    output: std_mpsc::Receiver<Output>,
+    lines: mpsc::UnboundedSender<String>,
+) -> io::Result<String> {
+    let mut terminal = ratatui::try_init()?;
+    let result = event_loop(&mut terminal, &output, &lines);
+    ratatui::try_restore()?;
+    result
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    output: &std_mpsc::Receiver<Output>,
+    lines: &mpsc::UnboundedSender<String>,
+) -> io::Result<String> {
+    let mut screen = Screen::default();
+    loop {
+        loop {
+            match output.try_recv() {
+                Ok(output) => screen.add(output),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(screen.input),
+            }
+        }
+        terminal.draw(|frame| screen.draw(frame))?;
+        if !event::poll(TICK)? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match screen.on_key(key) {
+                Action::Continue => {}
+                Action::Quit => return Ok(screen.input),
+                Action::Send(line) => {
+                    if lines.send(line).is_err() {
+                        return Ok(screen.input);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str) -> Output {
+        Output::Line {
+            text: text.to_string(),
+            error: false,
+        }
+    }
+
+    fn texts(screen: &Screen) -> Vec<String> {
+        screen.rows(80).iter().map(|row| row.to_string()).collect()
+    }
+
+    #[test]
+    fn test_wrap_splits_long_lines() {
+        assert_eq!(wrap("abcdefg", 3).collect::<Vec<_>>(), ["abc", "def", "g"]);
+        assert_eq!(wrap("", 3).collect::<Vec<_>>(), [""]);
+        assert_eq!(rows("abcdef", 3), 2);
+    }
+
+    #[test]
+    fn test_messages_arriving_while_scrolled_up_are_unread() {
+        let mut screen = Screen {
+            width: 80,
+            height: 2,
+            ..Screen::default()
+        };
+        screen.add(line("\n[TOPIC:ops] (from: a)\n\nfirst"));
+        screen.add(Output::End);
+        assert_eq!(texts(&screen), ["[TOPIC:ops] (from: a)", "first", ""]);
+
+        screen.scroll_by(1);
+        screen.add(line("[TOPIC:ops] (from: b)\nsecond"));
+        screen.add(Output::End);
+        assert_eq!(screen.unread, 1);
+        // The rows being read stay in place.
+        assert_eq!(screen.scroll, 5);
+        assert_eq!(
+            texts(&screen),
+            [
+                "[TOPIC:ops] (from: a)",
+                "first",
+                "",
+                "── 1 unread ──",
+                "[TOPIC:ops] (from: b)",
+                "second",
+                ""
+            ]
+        );
+
+        let enter = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+        screen.input = "hello".to_string();
+        assert_eq!(screen.on_key(enter), Action::Send("hello".to_string()));
+        assert_eq!((screen.scroll, screen.unread), (0, 0));
+        assert_eq!(screen.first_unread, None);
+    }
+}
@@ -1,56 +1,313 @@
-use crate::core::msg::ServerMessage;
-use std::io::{self, Write};
+use crate::core::msg::{Limit, ServerMessage};
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Mutex,
+    },
+};
 use uuid::Uuid;
 
+/// What is shown instead of printed while output is redirected, e.g. to the TUI.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Output {
+    /// A line, or several, that would have gone to stdout or, if `error`, stderr.
+    Line { text: String, error: bool },
+    /// The end of a message: where the prompt would have been printed.
+    End,
+}
+
+/// Where output goes instead of stdout and stderr; printed when absent.
+static REDIRECT: Mutex<Option<Sender<Output>>> = Mutex::new(None);
+
+/// Sends everything this module would print to `output` until [`restore`] is called.
+pub fn redirect(output: Sender<Output>) {
+    *REDIRECT.lock().unwrap() = Some(output);
+}
+
+/// Prints output again, dropping the redirect's sender.
+pub fn restore() {
+    REDIRECT.lock().unwrap().take();
+}
+
+/// Whether only errors are printed, keeping stdout free for another program.
+static ERRORS_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Prints errors only, e.g. while stdout carries messages as JSON for a pipeline.
+pub fn errors_only() {
+    ERRORS_ONLY.store(true, Ordering::Relaxed);
+}
+
+fn emit(output: Output) {
+    if ERRORS_ONLY.load(Ordering::Relaxed) && !matches!(output, Output::Line { error: true, .. }) {
+        return;
+    }
+    if let Some(redirect) = REDIRECT.lock().unwrap().as_ref() {
+        let _ = redirect.send(output);
+        return;
+    }
+    match output {
+        Output::Line { text, error: false } => println!("{}", text),
+        Output::Line { text, error: true } => eprintln!("{}", text),
+        Output::End => {
+            print!("\n> ");
+            io::stdout().flush().unwrap();
+        }
+    }
+}
+
+/// Like `println!`, but redirectable.
+macro_rules! outln {
+    ($($arg:tt)*) => {
+        emit(Output::Line { text: format!($($arg)*), error: false })
+    };
+}
+
+/// Like `eprintln!`, but redirectable.
+macro_rules! errln {
+    ($($arg:tt)*) => {
+        emit(Output::Line { text: format!($($arg)*), error: true })
+    };
+}
+
 /// Handles rendering messages and prompts to the console.
 pub fn print_prompt() {
-    print!("\n> ");
-    io::stdout().flush().unwrap();
+    emit(Output::End);
 }
 
-pub fn print_server_message(msg: &ServerMessage) -> Option<Uuid> {
-    let mut msg_id_to_ack: Option<Uuid> = None;
+pub fn print_server_message(msg: &ServerMessage) {
     match msg {
         ServerMessage::Global { id, content } => {
-            println!("\n[GLOBAL] (id: {})\n", id);
-            println!("{}", content);
-            msg_id_to_ack = Some(*id);
+            outln!("\n[GLOBAL] (id: {})\n", id);
+            outln!("{}", content);
         }
         ServerMessage::Topic {
             id,
             topic,
             sender,
             content,
+            headers,
+            reply_to,
+            retained,
+            ..
         } => {
-            println!("\n[TOPIC:{}] (from: {}, id: {})\n", topic, sender, id);
-            println!("{}", content);
-            msg_id_to_ack = Some(*id);
+            outln!("\n[TOPIC:{}] (from: {}, id: {})", topic, sender, id);
+            if *retained {
+                outln!("📌 retained, sent before you joined");
+            }
+            if let Some(reply_to) = reply_to {
+                outln!("↪ replying to {}", reply_to);
+            }
+            for (name, value) in headers {
+                outln!("{}: {}", name, value);
+            }
+            outln!("\n{}", content);
         }
         ServerMessage::Private { id, content } => {
-            println!("\n[PRIVATE] (id: {})\n", id);
-            println!("{}", content);
-            msg_id_to_ack = Some(*id);
+            outln!("\n[PRIVATE] (id: {})\n", id);
+            outln!("{}", content);
         }
         ServerMessage::Error { message } => {
-            eprintln!("\n[SERVER ERROR] {}\n", message);
+            errln!("\n[SERVER ERROR] {}\n", message);
         }
         ServerMessage::MessageDelivered { msg_id } => {
-            println!("\n[SYSTEM] Message {} delivered\n", msg_id);
+            outln!("\n[SYSTEM] Message {} delivered\n", msg_id);
         }
         ServerMessage::MessageAcknowledged { msg_id, client_id } => {
-            println!("\n[SYSTEM] Message {} acknowledged by client {}]\n", msg_id, client_id);
+            outln!(
+                "\n[SYSTEM] Message {} seen by client {}\n",
+                msg_id,
+                client_id
+            );
+        }
+        ServerMessage::Welcome {
+            client_id, resumed, ..
+        } => {
+            if *resumed {
+                outln!("\n[SYSTEM] Session resumed as {}\n", client_id);
+            } else {
+                outln!("\n[SYSTEM] Connected as {}\n", client_id);
+            }
+        }
+        ServerMessage::TopicMoved { from, to } => {
+            outln!("\n[SYSTEM] Moved from topic '{}' to '{}'\n", from, to);
+        }
+        ServerMessage::Motd { content } => {
+            outln!("\n[MOTD] {}\n", content);
+        }
+        ServerMessage::TopicInfo { topic, rules, .. } => {
+            outln!(
+                "\n[RULES] Topic '{}' asks you to agree to its rules:\n",
+                topic
+            );
+            outln!("{}", rules);
+            outln!("\nAccept them and join the topic? [y/N]");
+        }
+        ServerMessage::LimitExceeded { limit, max } => {
+            let what = match limit {
+                Limit::Connections => "connections to the server",
+                Limit::TopicClients => "clients in this topic",
+            };
+            errln!("\n[SERVER ERROR] Too many {} (limit {})\n", what, max);
+        }
+        ServerMessage::Kicked { reason } => match reason {
+            Some(reason) => errln!("\n[SYSTEM] Kicked by the server: {}\n", reason),
+            None => errln!("\n[SYSTEM] Kicked by the server\n"),
+        },
+        ServerMessage::Fetched {
+            topic,
+            group,
+            messages,
+        } => {
+            outln!(
+                "\n[GROUP:{}] {} message(s) from topic '{}'\n",
+                group,
+                messages.len(),
+                topic
+            );
+            for message in messages {
+                outln!(
+                    "#{} (from: {}) {}",
+                    message.seq,
+                    message.sender,
+                    message.content
+                );
+            }
+        }
+        ServerMessage::OffsetCommitted {
+            topic,
+            group,
+            offset,
+        } => {
+            outln!(
+                "\n[SYSTEM] Group '{}' committed offset {} in topic '{}'\n",
+                group,
+                offset,
+                topic
+            );
+        }
+        ServerMessage::Published { id } => {
+            outln!("\n[DELIVERED] Message {} was accepted by the server\n", id);
+        }
+        ServerMessage::Poll {
+            id,
+            topic,
+            question,
+            options,
+            creator,
+            closes_in_secs,
+        } => {
+            outln!("\n[POLL:{}] (from: {}, id: {})\n", topic, creator, id);
+            outln!("{}", question);
+            for (number, option) in options.iter().enumerate() {
+                outln!("  {}. {}", number + 1, option);
+            }
+            outln!(
+                "\nVote with /vote {} <number>; closes in {}s",
+                id,
+                closes_in_secs
+            );
+        }
+        ServerMessage::PollResults {
+            id,
+            topic,
+            question,
+            options,
+            votes,
+        } => {
+            let total: usize = votes.iter().sum();
+            outln!("\n[POLL RESULTS:{}] (id: {})\n", topic, id);
+            outln!("{}", question);
+            for (option, count) in options.iter().zip(votes) {
+                let share = (count * 100).checked_div(total).unwrap_or(0);
+                outln!("  {:>3}% {} ({} vote(s))", share, option, count);
+            }
+        }
+        ServerMessage::Voted { poll_id, option } => {
+            outln!(
+                "\n[SYSTEM] Voted for option {} in poll {}\n",
+                option + 1,
+                poll_id
+            );
+        }
+        ServerMessage::Request {
+            topic,
+            correlation_id,
+            sender,
+            content,
+        } => {
+            outln!(
+                "\n[REQUEST:{}] (from: {}, id: {})\n",
+                topic,
+                sender,
+                correlation_id
+            );
+            outln!("{}", content);
+            outln!("\nAnswer with /respond {} <text>", correlation_id);
+        }
+        ServerMessage::Response {
+            correlation_id,
+            content,
+        } => {
+            outln!("\n[RESPONSE] (id: {})\n", correlation_id);
+            outln!("{}", content);
+        }
+        ServerMessage::TopicList { topics } => {
+            outln!("\n[TOPICS] {} topic(s) with subscribers\n", topics.len());
+            for topic in topics {
+                outln!("- {} ({} client(s))", topic.name, topic.clients);
+            }
         }
+        ServerMessage::AttachmentStart {
+            id,
+            sender,
+            name,
+            content_type,
+            size,
+            ..
+        } => {
+            outln!(
+                "\n[ATTACHMENT] {} is sending '{}' ({}, {} bytes, id: {})\n",
+                sender,
+                name,
+                content_type,
+                size,
+                id
+            );
+        }
+        ServerMessage::AttachmentComplete { id } => {
+            outln!("\n[ATTACHMENT] {} received\n", id);
+        }
+        ServerMessage::AttachmentAborted { id, reason } => {
+            outln!("\n[ATTACHMENT] {} aborted: {}\n", id, reason);
+        }
+        // Shown by the client once it compared the key with the one it knew.
+        ServerMessage::PublicKey { .. } => return,
+        // Shown by the client, answering a `/whoami`.
+        ServerMessage::Pong { .. } => return,
+        // Collected by the client and shown once the attachment completes.
+        ServerMessage::AttachmentChunk { .. } => return,
+        // Taken care of by the connection.
+        ServerMessage::Negotiated { .. } | ServerMessage::Compressed { .. } => return,
+        // Sent by a newer server; nothing to show.
+        ServerMessage::Unknown => return,
     }
     print_prompt();
-    msg_id_to_ack
+}
+
+/// Shows how far a message we sent with a QoS above fire-and-forget has got.
+pub fn print_delivery_state(msg_id: &Uuid, state: &str) {
+    outln!("\n[{}] {}\n", msg_id, state);
+    print_prompt();
 }
 
 pub fn print_system_message(msg: &str) {
-    println!("\n[SYSTEM] {}\n", msg);
+    outln!("\n[SYSTEM] {}\n", msg);
     print_prompt();
 }
 
 pub fn print_error(msg: &str) {
-    eprintln!("\n[ERROR] {}\n", msg);
+    errln!("\n[ERROR] {}\n", msg);
     print_prompt();
 }
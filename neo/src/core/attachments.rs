@@ -0,0 +1,157 @@
+use super::config_dir;
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+/// How many bytes of a file each binary frame carries; well below the server's default
+/// message size limit.
+pub const CHUNK_SIZE: usize = 16 * 1024;
+/// The bytes each binary chunk frame starts with: the attachment ID and the chunk index.
+pub const CHUNK_HEADER_LEN: usize = 20;
+
+/// Encodes a chunk of an attachment as the binary frame it is sent in.
+pub fn encode_chunk(id: Uuid, index: u32, data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(CHUNK_HEADER_LEN + data.len());
+    frame.extend_from_slice(id.as_bytes());
+    frame.extend_from_slice(&index.to_be_bytes());
+    frame.extend_from_slice(data);
+    frame
+}
+
+/// Splits a binary chunk frame into the attachment ID, the chunk index and its bytes, or
+/// returns `None` if it is too short to be one.
+pub fn decode_chunk(frame: &[u8]) -> Option<(Uuid, u32, &[u8])> {
+    if frame.len() < CHUNK_HEADER_LEN {
+        return None;
+    }
+    let (header, data) = frame.split_at(CHUNK_HEADER_LEN);
+    let id = Uuid::from_slice(&header[..16]).ok()?;
+    let index = u32::from_be_bytes(header[16..].try_into().ok()?);
+    Some((id, index, data))
+}
+
+/// The content type announced for a file, guessed from its extension.
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("txt" | "md" | "log") => "text/plain",
+        Some("json") => "application/json",
+        Some("pdf") => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[derive(Debug)]
+struct Download {
+    name: String,
+    size: u64,
+    data: Vec<u8>,
+}
+
+/// Attachments being received from the topic, saved to a directory once complete.
+#[derive(Debug)]
+pub struct Downloads {
+    dir: PathBuf,
+    open: HashMap<Uuid, Download>,
+}
+
+impl Downloads {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            open: HashMap::new(),
+        }
+    }
+
+    /// Attachments saved under the neo config directory.
+    pub fn in_config_dir() -> Option<Self> {
+        config_dir().map(|dir| Self::new(dir.join("attachments")))
+    }
+
+    pub fn start(&mut self, id: Uuid, name: &str, size: u64) {
+        let download = Download {
+            name: name.to_string(),
+            size,
+            data: Vec::new(),
+        };
+        self.open.insert(id, download);
+    }
+
+    /// Adds a chunk to its attachment. Chunks of unknown attachments, or beyond the size
+    /// one was announced with, are dropped.
+    pub fn chunk(&mut self, id: Uuid, data: &[u8]) {
+        if let Some(download) = self.open.get_mut(&id) {
+            if download.data.len() as u64 + data.len() as u64 <= download.size {
+                download.data.extend_from_slice(data);
+            }
+        }
+    }
+
+    pub fn abort(&mut self, id: Uuid) {
+        self.open.remove(&id);
+    }
+
+    /// Saves a completed attachment and returns where, or `None` if it was not being
+    /// received. A file that is already there is not overwritten.
+    pub fn complete(&mut self, id: Uuid) -> io::Result<Option<PathBuf>> {
+        let Some(download) = self.open.remove(&id) else {
+            return Ok(None);
+        };
+        // Only the file name counts, so a sender cannot write outside the directory.
+        let name = Path::new(&download.name)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| id.to_string());
+        let mut path = self.dir.join(&name);
+        if path.exists() {
+            path = self.dir.join(format!("{}-{}", id, name));
+        }
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(&path, download.data)?;
+        Ok(Some(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_frames_round_trip() {
+        let id = Uuid::new_v4();
+        let frame = encode_chunk(id, 3, b"bytes");
+        assert_eq!(decode_chunk(&frame), Some((id, 3, &b"bytes"[..])));
+        assert_eq!(decode_chunk(&frame[..10]), None);
+    }
+
+    #[test]
+    fn test_downloads_are_saved_inside_their_directory() {
+        let dir = std::env::temp_dir().join(format!("neo-attachments-{}", Uuid::new_v4()));
+        let mut downloads = Downloads::new(dir.clone());
+        let id = Uuid::new_v4();
+        downloads.start(id, "../../escape.txt", 5);
+        downloads.chunk(id, b"hel");
+        downloads.chunk(id, b"lo");
+        downloads.chunk(id, b"!");
+        let path = downloads.complete(id).unwrap().unwrap();
+        assert_eq!(path, dir.join("escape.txt"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+
+        // Neither completed twice nor overwritten.
+        assert_eq!(downloads.complete(id).unwrap(), None);
+        downloads.start(id, "escape.txt", 0);
+        let path = downloads.complete(id).unwrap().unwrap();
+        assert_eq!(path, dir.join(format!("{}-escape.txt", id)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
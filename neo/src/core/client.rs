@@ -1,14 +1,155 @@
+#[cfg(feature = "e2e")]
+use crate::core::e2e::{self, E2e, KeyChange};
+#[cfg(feature = "tls")]
+use crate::core::pins::{KnownServers, PinCheck};
 use crate::{
     cli::{commands, ui},
-    core::msg::ClientMessage,
+    core::{
+        attachments::{self, Downloads, CHUNK_SIZE},
+        drafts::Drafts,
+        events::{ClientHandle, Event, Events, Request},
+        msg::{ClientMessage, CloseCode, Qos, ServerMessage},
+    },
     ws::conn::Connection,
 };
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use tokio::sync::{mpsc, watch};
+use tokio_tungstenite::tungstenite::Error as WsError;
 use url::Url;
+use uuid::Uuid;
+
+/// How many times to try reconnecting after the connection is lost.
+const RECONNECT_ATTEMPTS: u32 = 5;
+/// The delay after the first failed reconnect attempt; doubled after every failure.
+const RECONNECT_BASE_DELAY: Duration = Duration::from_millis(500);
+/// The delay before reconnecting to a server that closed the connection as overloaded,
+/// and after the first failed attempt; doubled after every failure.
+const OVERLOADED_BASE_DELAY: Duration = Duration::from_secs(5);
+/// How often the link is checked for silent failures (e.g. a network change).
+const LINK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// How long the link may be silent before we probe it with a ping. The server pings
+/// every 30 seconds, so a healthy link is never silent for this long.
+const LINK_IDLE_THRESHOLD: Duration = Duration::from_secs(45);
+/// How long to wait for any reply to a probe before reconnecting.
+const LINK_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+/// The header our messages carry the user's name in.
+pub const NAME_HEADER: &str = "name";
+/// How many requests from a `ClientHandle` may wait for the client.
+const REQUEST_BACKLOG: usize = 64;
+/// How many exactly-once message IDs are remembered to drop redeliveries.
+const SEEN_CAPACITY: usize = 1000;
+/// How many private message IDs are remembered to route replies to Morpheus.
+const PRIVATE_CAPACITY: usize = 100;
+
+/// Suspends and resumes a running client, e.g. from a mobile app when the OS moves it to
+/// the background and back. A suspended client reads nothing and holds back its
+/// acknowledgments; resuming catches up with a single `Resync` without ending the
+/// session.
+#[derive(Clone, Debug)]
+pub struct Lifecycle {
+    suspended: Arc<watch::Sender<bool>>,
+}
+
+impl Lifecycle {
+    fn new() -> (Self, watch::Receiver<bool>) {
+        let (suspended, receiver) = watch::channel(false);
+        let lifecycle = Self {
+            suspended: Arc::new(suspended),
+        };
+        (lifecycle, receiver)
+    }
+
+    pub fn suspend(&self) {
+        self.suspended.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.suspended.send_replace(false);
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        *self.suspended.borrow()
+    }
+}
+
+/// A message sent with an ID that the server did not confirm yet, kept as it was sent.
+struct Unconfirmed {
+    id: Uuid,
+    content: String,
+    headers: BTreeMap<String, String>,
+    reply_to: Option<Uuid>,
+}
+
+/// A message typed while the connection was down, kept as typed until it can be sent.
+struct Queued {
+    id: Uuid,
+    topic: String,
+    content: String,
+    reply_to: Option<Uuid>,
+}
 
 /// The main client structure.
 pub struct Client {
+    url: Url,
+    /// The active topic, which the server delivers messages from and sent messages go to.
     topic: String,
+    /// The other topics joined with `/join`, with the highest sequence number seen in
+    /// each, where switching back to one catches up from.
+    joined: BTreeMap<String, u64>,
+    /// The ID the server knows this client by, from its latest welcome.
+    client_id: Option<Uuid>,
+    /// The durable session assigned by the server, used to resume after a reconnect.
+    session_id: Option<Uuid>,
+    /// Topic rules waiting for the user's answer, with their version.
+    pending_terms: Option<(String, u32)>,
+    /// Where unsent messages are kept; drafts are not saved when absent.
+    drafts: Option<Drafts>,
+    /// Where attachments from the topic are saved; they are not kept when absent.
+    downloads: Option<Downloads>,
+    /// The keys for end-to-end encryption; messages are neither encrypted nor decrypted
+    /// when absent.
+    #[cfg(feature = "e2e")]
+    e2e: Option<E2e>,
+    /// Fingerprints that `wss://` servers are checked against; unchecked when absent.
+    #[cfg(feature = "tls")]
+    known_servers: Option<KnownServers>,
+    /// The delivery guarantee our messages are sent with.
+    qos: Qos,
+    /// Whether to ask the server to compress large frames on every connection.
+    compress: bool,
+    /// Sent with our messages in the `name` header; the sender is only known by its ID
+    /// when absent.
+    name: Option<String>,
+    /// Messages sent with a QoS above fire-and-forget that the server has not confirmed
+    /// yet, oldest first. They are sent again after a reconnect.
+    unconfirmed: Vec<Unconfirmed>,
+    /// Messages waiting for the connection to come back, oldest first.
+    outbox: VecDeque<Queued>,
+    /// The IDs of the exactly-once messages received most recently, oldest first.
+    seen: VecDeque<Uuid>,
+    /// The IDs of the private messages from Morpheus received most recently, oldest
+    /// first. Replies to these go to Morpheus instead of the topic.
+    private: VecDeque<Uuid>,
+    /// The window the server asked us to spread reconnects over when it goes away.
+    reconnect_jitter: Duration,
+    /// The highest sequence number seen in the current topic, where a resync resumes.
+    last_seq: u64,
+    /// Acknowledgments held back while suspended, sent with the next resync.
+    pending_acks: Vec<Uuid>,
+    /// The nonce of the `Ping` a `/whoami` waits to be answered, and when it was sent.
+    ping: Option<(u64, Instant)>,
+    /// Where events go while the client runs in the background; server messages are
+    /// printed instead when absent.
+    events: Option<mpsc::UnboundedSender<Event>>,
+    lifecycle: Lifecycle,
+    /// Whether the client is suspended, changed through `lifecycle`.
+    suspended: watch::Receiver<bool>,
     pub connection: Connection,
 }
 
@@ -18,44 +159,532 @@ impl Client {
         url: Url,
         topic: String,
     ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let connection = Connection::connect(url).await?;
-        Ok(Self { topic, connection })
+        let connection = Connection::connect(url.clone()).await?;
+        let (lifecycle, suspended) = Lifecycle::new();
+        Ok(Self {
+            url,
+            topic,
+            joined: BTreeMap::new(),
+            client_id: None,
+            session_id: None,
+            pending_terms: None,
+            drafts: None,
+            downloads: None,
+            #[cfg(feature = "e2e")]
+            e2e: None,
+            #[cfg(feature = "tls")]
+            known_servers: None,
+            qos: Qos::default(),
+            compress: false,
+            name: None,
+            unconfirmed: Vec::new(),
+            outbox: VecDeque::new(),
+            seen: VecDeque::new(),
+            private: VecDeque::new(),
+            reconnect_jitter: Duration::ZERO,
+            last_seq: 0,
+            pending_acks: Vec::new(),
+            ping: None,
+            events: None,
+            lifecycle,
+            suspended,
+            connection,
+        })
     }
 
-    /// Runs the main client loop.
-    pub async fn run<R: AsyncBufRead + Unpin>(
+    /// Creates a new client and connects to the server, refusing to connect if its TLS
+    /// certificate differs from the one recorded in `known_servers`.
+    #[cfg(feature = "tls")]
+    pub async fn new_pinned(
+        url: Url,
+        topic: String,
+        known_servers: KnownServers,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let connection = connect_pinned(url.clone(), &known_servers).await?;
+        let (lifecycle, suspended) = Lifecycle::new();
+        Ok(Self {
+            url,
+            topic,
+            joined: BTreeMap::new(),
+            client_id: None,
+            session_id: None,
+            pending_terms: None,
+            drafts: None,
+            downloads: None,
+            #[cfg(feature = "e2e")]
+            e2e: None,
+            known_servers: Some(known_servers),
+            qos: Qos::default(),
+            compress: false,
+            name: None,
+            unconfirmed: Vec::new(),
+            outbox: VecDeque::new(),
+            seen: VecDeque::new(),
+            private: VecDeque::new(),
+            reconnect_jitter: Duration::ZERO,
+            last_seq: 0,
+            pending_acks: Vec::new(),
+            ping: None,
+            events: None,
+            lifecycle,
+            suspended,
+            connection,
+        })
+    }
+
+    /// Saves unsent messages in `drafts` and offers them again on the next launch or
+    /// reconnect.
+    pub fn with_drafts(mut self, drafts: Drafts) -> Self {
+        self.drafts = Some(drafts);
+        self
+    }
+
+    /// Saves the attachments sent to the topic in `downloads`.
+    pub fn with_downloads(mut self, downloads: Downloads) -> Self {
+        self.downloads = Some(downloads);
+        self
+    }
+
+    /// Announces the key pair in `e2e` to the topic and decrypts messages sent to it,
+    /// encrypting ours while `e2e` is enabled.
+    #[cfg(feature = "e2e")]
+    pub fn with_e2e(mut self, e2e: E2e) -> Self {
+        self.e2e = Some(e2e);
+        self
+    }
+
+    /// Sends our messages with `qos`, showing whether the server confirmed each one.
+    pub fn with_qos(mut self, qos: Qos) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    /// Asks the server to compress large frames, in both directions, if `compress` is set.
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Sends our messages with `name` for the topic to show next to them.
+    pub fn with_name(mut self, name: String) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// A handle that suspends and resumes the client while it runs.
+    pub fn lifecycle(&self) -> Lifecycle {
+        self.lifecycle.clone()
+    }
+
+    /// Returns the session ID assigned by the server, if any.
+    pub fn session_id(&self) -> Option<Uuid> {
+        self.session_id
+    }
+
+    /// Re-establishes the connection and resumes the previous session, which makes the
+    /// server redeliver anything we have not acknowledged yet.
+    pub async fn reconnect(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.reconnect_with_backoff(RECONNECT_BASE_DELAY).await
+    }
+
+    /// Reconnects, keeping the messages still queued as drafts if it gives up.
+    async fn reconnect_with_backoff(
         &mut self,
-        input_reader: &mut R,
+        base_delay: Duration,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        // Send the initial connection message
+        let result = self.try_reconnect(base_delay).await;
+        if result.is_err() {
+            self.save_outbox();
+        }
+        result
+    }
+
+    async fn try_reconnect(
+        &mut self,
+        base_delay: Duration,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut delay = base_delay;
+        for attempt in 1..=RECONNECT_ATTEMPTS {
+            match self.open().await {
+                Ok(connection) => {
+                    self.connection = connection;
+                    self.send_connect().await?;
+                    self.resend_unconfirmed().await?;
+                    self.flush_outbox().await?;
+                    self.offer_draft();
+                    self.notify(Event::Reconnected);
+                    return Ok(());
+                }
+                Err(e) if attempt == RECONNECT_ATTEMPTS => return Err(e),
+                Err(_) => {
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens a new connection to the server, checking its certificate if pinned.
+    async fn open(&self) -> Result<Connection, Box<dyn std::error::Error + Send + Sync>> {
+        #[cfg(feature = "tls")]
+        if let Some(known_servers) = &self.known_servers {
+            return connect_pinned(self.url.clone(), known_servers).await;
+        }
+        Ok(Connection::connect(self.url.clone()).await?)
+    }
+
+    /// Reacts to the server closing the connection according to the reason it gave.
+    /// Returns false if reconnecting would not help, so the client should stop.
+    async fn connection_closed(
+        &mut self,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        self.notify(Event::Disconnected {
+            code: self.connection.close_code(),
+        });
+        match self.connection.close_code() {
+            Some(CloseCode::AuthFailed) => {
+                ui::print_error("The server refused the token. Not reconnecting.");
+                return Ok(false);
+            }
+            Some(CloseCode::Banned | CloseCode::Kicked) => {
+                ui::print_error("Disconnected by the server. Not reconnecting.");
+                return Ok(false);
+            }
+            Some(CloseCode::Overloaded) => {
+                let delay = OVERLOADED_BASE_DELAY + jitter(self.reconnect_jitter);
+                ui::print_error(&format!(
+                    "The server is overloaded. Reconnecting in {:.1} seconds...",
+                    delay.as_secs_f64()
+                ));
+                tokio::time::sleep(delay).await;
+                self.reconnect_with_backoff(OVERLOADED_BASE_DELAY).await?;
+            }
+            // Everyone else is losing the server too, so wait our turn in the window it
+            // advertised instead of coming back at the same moment as them.
+            Some(CloseCode::Draining) => {
+                let delay = jitter(self.reconnect_jitter);
+                ui::print_error(&format!(
+                    "The server is shutting down. Reconnecting in {:.1} seconds...",
+                    delay.as_secs_f64()
+                ));
+                tokio::time::sleep(delay).await;
+                self.reconnect().await?;
+            }
+            _ => {
+                let delay = jitter(self.reconnect_jitter);
+                ui::print_error(&format!(
+                    "Connection lost. Reconnecting in {:.1} seconds...",
+                    delay.as_secs_f64()
+                ));
+                tokio::time::sleep(delay).await;
+                self.reconnect().await?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Probes a silent link and reconnects if the probe goes unanswered.
+    /// `probe_sent` tracks when the outstanding probe, if any, was sent.
+    async fn check_link(
+        &mut self,
+        probe_sent: &mut Option<Instant>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let idle_for = self.connection.idle_for();
+        match *probe_sent {
+            // Something arrived after the probe was sent, so the link is alive.
+            Some(sent) if idle_for < sent.elapsed() => *probe_sent = None,
+            Some(sent) if sent.elapsed() >= LINK_PROBE_TIMEOUT => {
+                *probe_sent = None;
+                ui::print_error("Connection stopped responding. Reconnecting...");
+                self.reconnect().await?;
+            }
+            Some(_) => {}
+            None if idle_for >= LINK_IDLE_THRESHOLD => {
+                *probe_sent = Some(Instant::now());
+                if self.connection.ping().await.is_err() {
+                    *probe_sent = None;
+                    ui::print_error("Connection lost. Reconnecting...");
+                    self.reconnect().await?;
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Keeps text that could not be sent so it is not lost.
+    fn save_draft(&self, topic: &str, draft: &str) {
+        if let Some(drafts) = &self.drafts {
+            if let Err(e) = drafts.save(topic, draft) {
+                ui::print_error(&format!("Could not save the draft: {}", e));
+            }
+        }
+    }
+
+    /// Keeps a line that was still being typed when the client stopped as a draft of the
+    /// current topic.
+    pub(crate) fn save_unsent(&self, line: &str) {
+        let unsent = line.trim();
+        if !unsent.is_empty() {
+            self.save_draft(&self.topic, unsent);
+        }
+    }
+
+    fn clear_draft(&self) {
+        if let Some(drafts) = &self.drafts {
+            if let Err(e) = drafts.clear(&self.topic) {
+                ui::print_error(&format!("Could not discard the draft: {}", e));
+            }
+        }
+    }
+
+    /// Reminds the user of a saved draft for the current topic.
+    fn offer_draft(&self) {
+        let Some(draft) = self.drafts.as_ref().and_then(|d| d.load(&self.topic)) else {
+            return;
+        };
+        ui::print_system_message(&format!(
+            "Unsent draft for '{}':\n{}\nSend it with /draft or discard it with /draft clear.",
+            self.topic, draft
+        ));
+    }
+
+    async fn send_connect(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if self.compress {
+            self.connection.negotiate_compression().await?;
+        }
         self.connection
-            .send(ClientMessage::Connect {
+            .send(ClientMessage::ConnectSession {
                 topic: self.topic.clone(),
+                session_id: self.session_id,
             })
             .await?;
+        #[cfg(feature = "e2e")]
+        self.announce_key().await?;
+        Ok(())
+    }
+
+    /// Handles a message from the server, acknowledging it if required.
+    async fn handle_server_message(
+        &mut self,
+        msg: ServerMessage,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        #[cfg(feature = "e2e")]
+        let msg = self.decrypt(msg);
+        match &msg {
+            ServerMessage::Welcome {
+                client_id,
+                session_id,
+                reconnect_jitter_ms,
+                ..
+            } => {
+                self.client_id = Some(*client_id);
+                self.session_id = Some(*session_id);
+                self.reconnect_jitter = Duration::from_millis(*reconnect_jitter_ms);
+            }
+            ServerMessage::TopicInfo { topic, version, .. } => {
+                self.pending_terms = Some((topic.clone(), *version));
+            }
+            // Keep following the server so a reconnect rejoins the right topic.
+            ServerMessage::TopicMoved { from, to } if *from == self.topic => {
+                self.topic = to.clone();
+                self.last_seq = 0;
+                #[cfg(feature = "e2e")]
+                if let Some(e2e) = &mut self.e2e {
+                    e2e.keyring.clear_peers();
+                }
+            }
+            ServerMessage::TopicMoved { from, to } if self.joined.contains_key(from) => {
+                self.joined.remove(from);
+                self.joined.insert(to.clone(), 0);
+            }
+            #[cfg(feature = "e2e")]
+            ServerMessage::PublicKey { topic, sender, key } if *topic == self.topic => {
+                self.peer_key(sender, key).await?;
+            }
+            ServerMessage::Published { id } => {
+                self.unconfirmed.retain(|unconfirmed| unconfirmed.id != *id);
+            }
+            ServerMessage::Pong { nonce } => {
+                if let Some((sent, at)) = self.ping {
+                    if sent == *nonce {
+                        self.ping = None;
+                        self.print_whoami(at.elapsed());
+                    }
+                }
+            }
+            // Sent both live and in reply to a resync, or redelivered; show it once.
+            ServerMessage::Topic { id, topic, seq, .. }
+                if *topic == self.topic && *seq != 0 && *seq <= self.last_seq =>
+            {
+                self.acknowledge(*id).await?;
+                return Ok(());
+            }
+            ServerMessage::Topic {
+                id,
+                qos: Qos::ExactlyOnce,
+                ..
+            } => {
+                if self.seen.contains(id) {
+                    // A redelivery of a message we already showed; just acknowledge it.
+                    self.acknowledge(*id).await?;
+                    return Ok(());
+                }
+                self.seen.push_back(*id);
+                if self.seen.len() > SEEN_CAPACITY {
+                    self.seen.pop_front();
+                }
+            }
+            ServerMessage::Private { id, .. } => {
+                self.private.push_back(*id);
+                if self.private.len() > PRIVATE_CAPACITY {
+                    self.private.pop_front();
+                }
+            }
+            ServerMessage::AttachmentStart { id, name, size, .. } => {
+                if let Some(downloads) = &mut self.downloads {
+                    downloads.start(*id, name, *size);
+                }
+            }
+            ServerMessage::AttachmentChunk { id, data, .. } => {
+                if let Some(downloads) = &mut self.downloads {
+                    downloads.chunk(*id, data);
+                }
+            }
+            ServerMessage::AttachmentAborted { id, .. } => {
+                if let Some(downloads) = &mut self.downloads {
+                    downloads.abort(*id);
+                }
+            }
+            _ => {}
+        }
+        if let ServerMessage::Topic { topic, seq, .. } = &msg {
+            if *topic == self.topic {
+                self.last_seq = self.last_seq.max(*seq);
+            } else if let Some(last_seq) = self.joined.get_mut(topic) {
+                // Sent just before switching away; do not ask for it again.
+                *last_seq = (*last_seq).max(*seq);
+            }
+        }
+        let ack_id = msg.ack_id();
+        match &self.events {
+            Some(events) => {
+                let _ = events.send(Event::Message(msg.clone()));
+            }
+            None => ui::print_server_message(&msg),
+        }
+        if let Some(msg_id) = ack_id {
+            self.acknowledge(msg_id).await?;
+        }
+        if let ServerMessage::AttachmentComplete { id } = &msg {
+            self.save_attachment(*id);
+        }
+        Ok(())
+    }
+
+    /// Sends an acknowledgment to the server, or holds it back for the next resync while
+    /// suspended.
+    async fn acknowledge(
+        &mut self,
+        msg_id: Uuid,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if *self.suspended.borrow() {
+            self.pending_acks.push(msg_id);
+            return Ok(());
+        }
+        self.connection
+            .send(ClientMessage::MessageReceived { msg_id })
+            .await?;
+        Ok(())
+    }
+
+    /// Reacts to the client being suspended or resumed through its `Lifecycle`.
+    async fn lifecycle_changed(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if *self.suspended.borrow_and_update() {
+            ui::print_system_message("Suspended.");
+            return Ok(());
+        }
+        ui::print_system_message("Resumed, catching up...");
+        self.resync().await
+    }
+
+    /// Catches up after a suspension in one message: acknowledges what was held back and
+    /// asks for the messages of the topic sent since the last one seen. A connection that
+    /// has been silent too long to still be open is replaced first, resuming the session.
+    pub async fn resync(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if self.connection.idle_for() >= LINK_IDLE_THRESHOLD {
+            self.reconnect().await?;
+        }
+        let resync = ClientMessage::Resync {
+            topic: self.topic.clone(),
+            from_seq: self.last_seq + 1,
+            acked: self.pending_acks.clone(),
+        };
+        if self.connection.send(resync).await.is_err() {
+            self.reconnect().await?;
+            self.connection
+                .send(ClientMessage::Resync {
+                    topic: self.topic.clone(),
+                    from_seq: self.last_seq + 1,
+                    acked: self.pending_acks.clone(),
+                })
+                .await?;
+        }
+        self.pending_acks.clear();
+        Ok(())
+    }
+
+    /// Runs the main client loop.
+    pub async fn run<R: AsyncBufRead + Unpin>(
+        &mut self,
+        input_reader: &mut R,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Send the initial connection message
+        self.send_connect().await?;
 
         ui::print_system_message(&format!(
             "Connected to topic '{}'. Type /help for commands.",
             self.topic
         ));
+        self.offer_draft();
 
-        let mut input_buf = String::new();
+        // `read_until` keeps what it read in the buffer when another branch wins, unlike
+        // `read_line`, so a line still being typed is there when the loop ends.
+        let mut input_buf = Vec::new();
+        let mut link_check = tokio::time::interval(LINK_CHECK_INTERVAL);
+        let mut probe_sent = None;
 
         loop {
+            // A suspended client neither reads nor probes the link.
+            let suspended = *self.suspended.borrow();
             tokio::select! {
+                _ = link_check.tick(), if !suspended => self.check_link(&mut probe_sent).await?,
+                Ok(()) = self.suspended.changed() => self.lifecycle_changed().await?,
                 // Handle incoming messages from the server
-                Some(Ok(msg)) = self.connection.recv() => {
-                    if let Some(msg_id) = ui::print_server_message(&msg) {
-                        // Send acknowledgment back to the server
-                        self.connection.send(ClientMessage::MessageReceived { msg_id }).await?;
+                msg = self.connection.recv(), if !suspended => match msg {
+                    Some(Ok(msg)) => {
+                        let kicked = matches!(msg, ServerMessage::Kicked { .. });
+                        self.handle_server_message(msg).await?;
+                        if kicked {
+                            // Reconnecting would just bring the client back.
+                            break;
+                        }
+                    }
+                    Some(Err(_)) => {} // Skip frames that cannot be decoded
+                    None => {
+                        if !self.connection_closed().await? {
+                            break;
+                        }
                     }
                 },
                 // Handle user input from the command line
-                result = input_reader.read_line(&mut input_buf) => {
+                result = input_reader.read_until(b'\n', &mut input_buf) => {
                     match result {
                         Ok(0) => break, // EOF
                         Ok(_) => {
-                            let content = input_buf.trim();
+                            let line = String::from_utf8_lossy(&input_buf);
+                            let content = line.trim();
                             if !content.is_empty() {
                                 self.handle_user_input(content).await?;
                             }
@@ -71,31 +700,179 @@ impl Client {
             }
         }
 
+        self.save_unsent(&String::from_utf8_lossy(&input_buf));
         Ok(())
     }
 
+    /// Runs the client in the background, for apps that embed neo: returns a handle to
+    /// publish and subscribe through and the stream of what happens, which ends when the
+    /// client stops. Server messages arrive as events instead of being printed.
+    pub fn spawn(mut self) -> (ClientHandle, Events) {
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let (requests_tx, requests) = mpsc::channel(REQUEST_BACKLOG);
+        self.events = Some(events_tx);
+        tokio::spawn(async move {
+            if let Err(e) = self.run_background(requests).await {
+                self.notify(Event::Error(e.to_string()));
+            }
+        });
+        (ClientHandle::new(requests_tx), Events::new(events))
+    }
+
+    async fn run_background(
+        &mut self,
+        mut requests: mpsc::Receiver<Request>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.send_connect().await?;
+
+        let mut link_check = tokio::time::interval(LINK_CHECK_INTERVAL);
+        let mut probe_sent = None;
+        // Without handles nothing can be asked of the client, but it keeps delivering events.
+        let mut handles_left = true;
+
+        loop {
+            let suspended = *self.suspended.borrow();
+            tokio::select! {
+                _ = link_check.tick(), if !suspended => self.check_link(&mut probe_sent).await?,
+                Ok(()) = self.suspended.changed() => self.lifecycle_changed().await?,
+                msg = self.connection.recv(), if !suspended => match msg {
+                    Some(Ok(msg)) => {
+                        let kicked = matches!(msg, ServerMessage::Kicked { .. });
+                        self.handle_server_message(msg).await?;
+                        if kicked {
+                            break;
+                        }
+                    }
+                    Some(Err(_)) => {}
+                    None => {
+                        if !self.connection_closed().await? {
+                            break;
+                        }
+                    }
+                },
+                request = requests.recv(), if handles_left => match request {
+                    Some(Request::Publish { content, done }) => {
+                        let result = self.send_message(content, None).await.map_err(|e| e.to_string());
+                        let _ = done.send(result);
+                    }
+                    Some(Request::Subscribe { topic, done }) => {
+                        self.join(topic).await?;
+                        let _ = done.send(Ok(()));
+                    }
+                    Some(Request::Unsubscribe { topic, done }) => {
+                        let result = self.check_leave(&topic);
+                        if result.is_ok() {
+                            self.leave(topic).await?;
+                        }
+                        let _ = done.send(result);
+                    }
+                    Some(Request::Close) => break,
+                    None => handles_left = false,
+                },
+            }
+        }
+        // Ending with a close frame, rather than dropping the connection, tells the server
+        // the client left on purpose.
+        let _ = self.connection.close().await;
+        Ok(())
+    }
+
+    fn notify(&self, event: Event) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
+    }
+
     /// Handles user input from the command line.
     pub async fn handle_user_input(
         &mut self,
         input: &str,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Some((topic, version)) = self.pending_terms.take() {
+            return self.answer_terms(input, topic, version).await;
+        }
         match commands::parse_command(input) {
             commands::Command::Message(content) => {
-                let message = ClientMessage::Message {
-                    topic: self.topic.clone(),
+                self.send_message(content, None).await?;
+            }
+            commands::Command::Reply { msg_id, content } if self.private.contains(&msg_id) => {
+                let message = ClientMessage::ReplyToMorpheus {
+                    original_msg_id: msg_id,
                     content,
                 };
                 self.connection.send(message).await?;
             }
             commands::Command::Reply { msg_id, content } => {
-                let message = ClientMessage::ReplyToMorpheus {
-                    original_msg_id: msg_id,
+                self.send_message(content, Some(msg_id)).await?;
+            }
+            commands::Command::Reconnect => {
+                ui::print_system_message("Reconnecting...");
+                self.reconnect().await?;
+            }
+            commands::Command::SendDraft => {
+                match self.drafts.as_ref().and_then(|d| d.load(&self.topic)) {
+                    Some(draft) => {
+                        self.send_message(draft, None).await?;
+                        self.clear_draft();
+                    }
+                    None => ui::print_error("There is no draft for this topic."),
+                }
+            }
+            commands::Command::ClearDraft => {
+                self.clear_draft();
+                ui::print_system_message("Draft discarded.");
+            }
+            commands::Command::Poll {
+                question,
+                options,
+                duration_secs,
+            } => {
+                let message = ClientMessage::CreatePoll {
+                    question,
+                    options,
+                    duration_secs,
+                };
+                self.connection.send(message).await?;
+            }
+            commands::Command::Vote { poll_id, option } => {
+                self.connection
+                    .send(ClientMessage::Vote { poll_id, option })
+                    .await?;
+            }
+            commands::Command::Request(content) => {
+                let message = ClientMessage::Request {
+                    topic: self.topic.clone(),
+                    correlation_id: Uuid::new_v4(),
                     content,
                 };
                 self.connection.send(message).await?;
             }
+            commands::Command::Respond {
+                correlation_id,
+                content,
+            } => {
+                let message = ClientMessage::Respond {
+                    correlation_id,
+                    content,
+                };
+                self.connection.send(message).await?;
+            }
+            commands::Command::Topics => self.connection.send(ClientMessage::ListTopics).await?,
+            commands::Command::Whoami => {
+                let nonce = Uuid::new_v4().as_u128() as u64;
+                self.connection.send(ClientMessage::Ping { nonce }).await?;
+                self.ping = Some((nonce, Instant::now()));
+            }
+            commands::Command::Join(topic) => self.join(topic).await?,
+            commands::Command::Leave(topic) => self.leave(topic).await?,
+            commands::Command::Switch(topic) => self.switch(topic).await?,
+            commands::Command::Attach(path) => self.send_attachment(&path).await?,
+            command @ (commands::Command::E2e(_)
+            | commands::Command::Keygen
+            | commands::Command::Keys
+            | commands::Command::Verify(_)) => self.handle_e2e_command(command).await?,
             commands::Command::Help => {
-                let help_text = "Commands:\n/h, /help                  - Show this help message\n/m, /msg <text>            - Send a message to the current topic\n/r, /reply <msg_id> <text> - Reply to a message";
+                let help_text = "Commands:\n/h, /help                  - Show this help message\n/m, /msg <text>            - Send a message to the current topic\n/r, /reply <msg_id> <text> - Reply to a topic message, or privately to Morpheus\n/poll \"<question>\" <option>... [--for 5m]\n                           - Open a poll in the current topic\n/vote <poll_id> <n>        - Vote for option n of a poll\n/request <text>            - Ask the current topic for a response\n/respond <id> <text>       - Answer a request by its correlation ID\n/topics                    - List the topics on the server\n/whoami                    - Show this client's ID, topics, server and latency\n/join <topic>              - Join a topic and make it the current one\n/switch <topic>            - Make another joined topic the current one\n/leave <topic>             - Leave a joined topic\n/attach <path>             - Send a file to the current topic\n/e2e on|off                - Encrypt sent messages end to end, or stop\n/keys                      - Show the key fingerprints of this client and the topic\n/verify <fingerprint>      - Mark a key as checked with its owner\n/keygen                    - Replace this client's key pair\n/draft [clear]             - Send or discard the saved draft\n/reconnect                 - Re-establish the connection to the server";
                 ui::print_system_message(help_text);
             }
             commands::Command::Unknown(error_msg) => {
@@ -104,4 +881,578 @@ impl Client {
         }
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Shows who the server knows this client as and how the connection is doing, once
+    /// a `/whoami` ping came back after `round_trip`.
+    fn print_whoami(&self, round_trip: Duration) {
+        let mut server = self.url.clone();
+        // Keep the token off the screen.
+        server.set_query(None);
+        let client_id = self
+            .client_id
+            .map_or_else(|| "not assigned yet".to_string(), |id| id.to_string());
+        let mut topics = self.topic.clone();
+        if !self.joined.is_empty() {
+            let joined: Vec<&str> = self.joined.keys().map(String::as_str).collect();
+            topics.push_str(&format!(" (also joined: {})", joined.join(", ")));
+        }
+        ui::print_system_message(&format!(
+            "Client ID:  {}\nTopic:      {}\nServer:     {}\nConnected:  {}\nRound trip: {} ms",
+            client_id,
+            topics,
+            server,
+            format_uptime(self.connection.uptime()),
+            round_trip.as_millis()
+        ));
+    }
+
+    /// Joins `topic` and makes it the current topic; the topic switched away from stays
+    /// joined.
+    async fn join(
+        &mut self,
+        topic: String,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if topic == self.topic {
+            ui::print_system_message(&format!("Already in topic '{}'.", topic));
+            return Ok(());
+        }
+        self.joined.entry(topic.clone()).or_insert(0);
+        self.switch(topic).await
+    }
+
+    /// Makes the joined `topic` the current one.
+    async fn switch(
+        &mut self,
+        topic: String,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if topic == self.topic {
+            ui::print_system_message(&format!("'{}' is already the current topic.", topic));
+            return Ok(());
+        }
+        let Some(last_seq) = self.joined.remove(&topic) else {
+            ui::print_error(&format!(
+                "Not in topic '{}'. Join it with /join {}.",
+                topic, topic
+            ));
+            return Ok(());
+        };
+        let previous = std::mem::replace(&mut self.topic, topic);
+        let previous_seq = std::mem::replace(&mut self.last_seq, last_seq);
+        self.joined.insert(previous, previous_seq);
+        self.subscribe_current().await
+    }
+
+    /// Leaves a joined `topic`. Leaving the current topic switches to another joined one.
+    async fn leave(
+        &mut self,
+        topic: String,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Err(e) = self.check_leave(&topic) {
+            ui::print_error(&e);
+            return Ok(());
+        }
+        ui::print_system_message(&format!("Left topic '{}'.", topic));
+        if self.joined.remove(&topic).is_some() {
+            return Ok(());
+        }
+        // Another joined topic takes the place of the current one.
+        if let Some((next, last_seq)) = self.joined.pop_first() {
+            self.topic = next;
+            self.last_seq = last_seq;
+            self.subscribe_current().await?;
+        }
+        Ok(())
+    }
+
+    /// Why `topic` cannot be left, if it cannot.
+    fn check_leave(&self, topic: &str) -> Result<(), String> {
+        if topic != self.topic && !self.joined.contains_key(topic) {
+            return Err(format!("Not in topic '{}'.", topic));
+        }
+        if topic == self.topic && self.joined.is_empty() {
+            return Err(format!(
+                "'{}' is the only topic you are in; join another before leaving it.",
+                topic
+            ));
+        }
+        Ok(())
+    }
+
+    /// Moves the server subscription to the current topic and asks for the messages sent
+    /// to it since it was last current. The server delivers one topic per connection, so
+    /// the other joined topics are caught up with when switched back to.
+    async fn subscribe_current(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.connection
+            .send(ClientMessage::Connect {
+                topic: self.topic.clone(),
+            })
+            .await?;
+        if self.last_seq > 0 {
+            self.connection
+                .send(ClientMessage::RequestMissed {
+                    topic: self.topic.clone(),
+                    from_seq: self.last_seq + 1,
+                })
+                .await?;
+        }
+        #[cfg(feature = "e2e")]
+        {
+            if let Some(e2e) = &mut self.e2e {
+                e2e.keyring.clear_peers();
+            }
+            self.announce_key().await?;
+        }
+        ui::print_system_message(&format!("Switched to topic '{}'.", self.topic));
+        self.offer_draft();
+        Ok(())
+    }
+
+    /// Sends a message to the current topic, as a reply to `reply_to` if given. If the
+    /// connection is down, the message is queued and sent once it is back. Returns its ID
+    /// if it waits for the server to confirm it.
+    async fn send_message(
+        &mut self,
+        content: String,
+        reply_to: Option<Uuid>,
+    ) -> Result<Option<Uuid>, Box<dyn std::error::Error + Send + Sync>> {
+        let queued = Queued {
+            id: Uuid::new_v4(),
+            topic: self.topic.clone(),
+            content,
+            reply_to,
+        };
+        let id = (!self.qos.is_fire_and_forget()).then_some(queued.id);
+        if let Ok(sent) = self.transmit(&queued).await {
+            return Ok(id.filter(|_| sent));
+        }
+        ui::print_delivery_state(&queued.id, "queued until the connection is back");
+        self.outbox.push_back(queued);
+        ui::print_error("Connection lost. Reconnecting...");
+        self.reconnect().await?;
+        Ok(id)
+    }
+
+    /// Encrypts and sends a message, keeping it until the server confirms it if its QoS
+    /// asks for that. A message nobody could read is dropped with an error instead, and
+    /// false returned.
+    async fn transmit(&mut self, queued: &Queued) -> Result<bool, WsError> {
+        #[cfg(feature = "e2e")]
+        let (sent, mut headers) = match self.seal(&queued.content) {
+            Ok(sealed) => sealed,
+            Err(e) => {
+                ui::print_error(&e);
+                return Ok(false);
+            }
+        };
+        #[cfg(not(feature = "e2e"))]
+        let (sent, mut headers) = (queued.content.clone(), BTreeMap::new());
+        if let Some(name) = &self.name {
+            headers.insert(NAME_HEADER.to_string(), name.clone());
+        }
+        let message = ClientMessage::Message {
+            topic: queued.topic.clone(),
+            content: sent.clone(),
+            headers: headers.clone(),
+            qos: self.qos,
+            id: Some(queued.id),
+            reply_to: queued.reply_to,
+            retain: false,
+        };
+        self.connection.send(message).await?;
+        if !self.qos.is_fire_and_forget() {
+            ui::print_delivery_state(&queued.id, "sent, waiting for the server to confirm");
+            self.unconfirmed.push(Unconfirmed {
+                id: queued.id,
+                content: sent,
+                headers,
+                reply_to: queued.reply_to,
+            });
+        }
+        Ok(true)
+    }
+
+    /// Sends the messages queued while the connection was down, oldest first.
+    async fn flush_outbox(&mut self) -> Result<(), WsError> {
+        while let Some(queued) = self.outbox.pop_front() {
+            match self.transmit(&queued).await {
+                Ok(true) if self.qos.is_fire_and_forget() => {
+                    ui::print_delivery_state(&queued.id, "sent");
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.outbox.push_front(queued);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps the queued messages as drafts of their topics, so giving up on the
+    /// connection does not lose them.
+    fn save_outbox(&mut self) {
+        let mut drafts: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for queued in self.outbox.drain(..) {
+            drafts.entry(queued.topic).or_default().push(queued.content);
+        }
+        for (topic, messages) in drafts {
+            self.save_draft(&topic, &messages.join("\n"));
+        }
+    }
+
+    /// Tells the user that end-to-end encryption was left out of this build.
+    #[cfg(not(feature = "e2e"))]
+    async fn handle_e2e_command(
+        &mut self,
+        _command: commands::Command,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        ui::print_error("This neo was built without end-to-end encryption.");
+        Ok(())
+    }
+
+    /// Handles the commands that manage end-to-end encryption.
+    #[cfg(feature = "e2e")]
+    async fn handle_e2e_command(
+        &mut self,
+        command: commands::Command,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(e2e) = &mut self.e2e else {
+            ui::print_error("End-to-end encryption needs a config directory to keep keys in.");
+            return Ok(());
+        };
+        match command {
+            commands::Command::E2e(enabled) => {
+                e2e.enabled = enabled;
+                ui::print_system_message(if enabled {
+                    "Messages you send are now encrypted for the topic's announced keys."
+                } else {
+                    "Messages you send are no longer encrypted."
+                });
+            }
+            commands::Command::Keygen => {
+                if let Err(e) = e2e.regenerate() {
+                    ui::print_error(&e);
+                    return Ok(());
+                }
+                ui::print_system_message(&format!(
+                    "New key fingerprint: {}. Others need to verify it again.",
+                    e2e::fingerprint(e2e.identity.public())
+                ));
+                self.announce_key().await?;
+            }
+            commands::Command::Keys => {
+                let mut lines = vec![format!(
+                    "Your key: {}",
+                    e2e::fingerprint(e2e.identity.public())
+                )];
+                for (sender, key) in e2e.keyring.peers() {
+                    let trust = if e2e.keyring.is_verified(key) {
+                        "verified"
+                    } else {
+                        "unverified"
+                    };
+                    lines.push(format!("{}: {} ({})", sender, e2e::fingerprint(key), trust));
+                }
+                ui::print_system_message(&lines.join("\n"));
+            }
+            commands::Command::Verify(fingerprint) => match e2e.keyring.verify(&fingerprint) {
+                Ok(senders) => ui::print_system_message(&format!(
+                    "Verified the key of {}.",
+                    senders.join(", ")
+                )),
+                Err(e) => ui::print_error(&e),
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Announces our public key to the other subscribers of the topic.
+    #[cfg(feature = "e2e")]
+    async fn announce_key(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(e2e) = &self.e2e else {
+            return Ok(());
+        };
+        let message = ClientMessage::PublicKey {
+            topic: self.topic.clone(),
+            key: e2e::encode_key(e2e.identity.public()),
+        };
+        self.connection.send(message).await?;
+        Ok(())
+    }
+
+    /// Records a key another subscriber announced, answering a newcomer with our own.
+    #[cfg(feature = "e2e")]
+    async fn peer_key(
+        &mut self,
+        sender: &str,
+        key: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(e2e) = &mut self.e2e else {
+            return Ok(());
+        };
+        let Some(key) = e2e::decode_key(key) else {
+            ui::print_error(&format!("{} announced an invalid key.", sender));
+            return Ok(());
+        };
+        match e2e.keyring.insert(sender, key) {
+            KeyChange::Known => {}
+            KeyChange::New => {
+                ui::print_system_message(&format!(
+                    "{} uses key {}. Compare it with them, then /verify it.",
+                    sender,
+                    e2e::fingerprint(&key)
+                ));
+                self.announce_key().await?;
+            }
+            KeyChange::Changed => ui::print_error(&format!(
+                "WARNING: {} announced a different key, {}. Verify it before trusting \
+                 their messages.",
+                sender,
+                e2e::fingerprint(&key)
+            )),
+        }
+        Ok(())
+    }
+
+    /// Encrypts `content` for the topic's announced keys if encryption is on, returning
+    /// what to send and its headers. Refuses to when nobody could read it.
+    #[cfg(feature = "e2e")]
+    fn seal(&self, content: &str) -> Result<(String, BTreeMap<String, String>), String> {
+        let mut headers = BTreeMap::new();
+        let Some(e2e) = self.e2e.as_ref().filter(|e2e| e2e.enabled) else {
+            return Ok((content.to_string(), headers));
+        };
+        let recipients = e2e.keyring.keys();
+        if recipients.is_empty() {
+            return Err(
+                "Nobody in the topic announced a key, so nobody could read the \
+                        message. Send it unencrypted with /e2e off."
+                    .to_string(),
+            );
+        }
+        headers.insert(e2e::ENCRYPTION_HEADER.to_string(), e2e::SCHEME.to_string());
+        Ok((e2e.identity.seal(&recipients, content), headers))
+    }
+
+    /// Replaces the content of an encrypted topic message with its text, noting in its
+    /// encryption header whether the sender's key was verified.
+    #[cfg(feature = "e2e")]
+    fn decrypt(&self, mut msg: ServerMessage) -> ServerMessage {
+        let Some(e2e) = &self.e2e else {
+            return msg;
+        };
+        if let ServerMessage::Topic {
+            sender,
+            content,
+            headers,
+            ..
+        } = &mut msg
+        {
+            if headers.get(e2e::ENCRYPTION_HEADER).map(String::as_str) != Some(e2e::SCHEME) {
+                return msg;
+            }
+            let state = match e2e.identity.open(content) {
+                Ok((key, plaintext)) => {
+                    *content = plaintext;
+                    if e2e
+                        .keyring
+                        .key_of(sender)
+                        .is_some_and(|known| *known != key)
+                    {
+                        "end-to-end, NOT the key the sender announced"
+                    } else if e2e.keyring.is_verified(&key) {
+                        "end-to-end, verified key"
+                    } else {
+                        "end-to-end, unverified key"
+                    }
+                }
+                Err(reason) => {
+                    *content = format!("[{}]", reason);
+                    "end-to-end, not decrypted"
+                }
+            };
+            headers.insert(e2e::ENCRYPTION_HEADER.to_string(), state.to_string());
+        }
+        msg
+    }
+
+    /// Sends a file to the current topic: an announcement, its content in binary chunks,
+    /// and the completion the server confirms with `Published`.
+    async fn send_attachment(
+        &mut self,
+        path: &Path,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                ui::print_error(&format!("Could not read {}: {}", path.display(), e));
+                return Ok(());
+            }
+        };
+        let id = Uuid::new_v4();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| id.to_string());
+        self.connection
+            .send(ClientMessage::AttachmentStart {
+                id,
+                topic: self.topic.clone(),
+                name: name.clone(),
+                content_type: attachments::content_type(path).to_string(),
+                size: data.len() as u64,
+            })
+            .await?;
+        for (index, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+            let frame = attachments::encode_chunk(id, index as u32, chunk);
+            self.connection.send_binary(frame).await?;
+        }
+        self.connection
+            .send(ClientMessage::AttachmentComplete { id })
+            .await?;
+        ui::print_delivery_state(&id, &format!("sent '{}' ({} bytes)", name, data.len()));
+        Ok(())
+    }
+
+    /// Saves an attachment that finished arriving, if attachments are kept.
+    fn save_attachment(&mut self, id: Uuid) {
+        let Some(downloads) = &mut self.downloads else {
+            return;
+        };
+        match downloads.complete(id) {
+            Ok(Some(path)) => {
+                ui::print_system_message(&format!("Saved attachment to {}.", path.display()))
+            }
+            Ok(None) => {}
+            Err(e) => ui::print_error(&format!("Could not save the attachment: {}", e)),
+        }
+    }
+
+    /// Sends the messages the server did not confirm before the connection was lost
+    /// again, under their original IDs so that exactly-once ones are not duplicated.
+    async fn resend_unconfirmed(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for unconfirmed in &self.unconfirmed {
+            self.connection
+                .send(ClientMessage::Message {
+                    topic: self.topic.clone(),
+                    content: unconfirmed.content.clone(),
+                    headers: unconfirmed.headers.clone(),
+                    qos: self.qos,
+                    id: Some(unconfirmed.id),
+                    reply_to: unconfirmed.reply_to,
+                    retain: false,
+                })
+                .await?;
+            ui::print_delivery_state(&unconfirmed.id, "sent again after reconnecting");
+        }
+        Ok(())
+    }
+
+    /// Treats the user's input as the answer to a topic's rules prompt.
+    async fn answer_terms(
+        &mut self,
+        answer: &str,
+        topic: String,
+        version: u32,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if matches!(answer.to_lowercase().as_str(), "y" | "yes") {
+            self.connection
+                .send(ClientMessage::AcceptTerms {
+                    topic: topic.clone(),
+                    version,
+                })
+                .await?;
+            ui::print_system_message(&format!("Accepted the rules of '{}'.", topic));
+        } else {
+            ui::print_system_message(&format!(
+                "Rules declined; not subscribed to '{}'. Use /reconnect to be asked again.",
+                topic
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Connects to the server, trusting its certificate on first use and refusing to
+/// connect if it changed since.
+#[cfg(feature = "tls")]
+async fn connect_pinned(
+    url: Url,
+    known_servers: &KnownServers,
+) -> Result<Connection, Box<dyn std::error::Error + Send + Sync>> {
+    Connection::connect_verified(url, |server, fingerprint| {
+        match known_servers.check(server, fingerprint)? {
+            PinCheck::Trusted => {}
+            PinCheck::FirstUse => ui::print_system_message(&format!(
+                "Trusting {} on first use (certificate fingerprint {}).",
+                server, fingerprint
+            )),
+            PinCheck::Replaced { previous } => ui::print_system_message(&format!(
+                "Accepted the new certificate of {} (fingerprint {}, was {}).",
+                server, fingerprint, previous
+            )),
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// A random delay within `window`, so that clients reconnecting at the same moment spread
+/// out over it.
+fn jitter(window: Duration) -> Duration {
+    let window_ms = window.as_millis() as u64;
+    if window_ms == 0 {
+        return Duration::ZERO;
+    }
+    let random = Uuid::new_v4().as_u128() as u64;
+    Duration::from_millis(random % window_ms)
+}
+
+/// `duration` in hours, minutes and seconds, leaving out the larger units while zero.
+fn format_uptime(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, secs)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, secs)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lifecycle_handles_share_their_state() {
+        let (lifecycle, mut suspended) = Lifecycle::new();
+        let handle = lifecycle.clone();
+        handle.suspend();
+        assert!(lifecycle.is_suspended());
+        assert!(suspended.has_changed().unwrap());
+        assert!(*suspended.borrow_and_update());
+        lifecycle.resume();
+        assert!(!handle.is_suspended());
+        assert!(!*suspended.borrow_and_update());
+    }
+
+    #[test]
+    fn test_jitter_stays_within_window() {
+        assert_eq!(jitter(Duration::ZERO), Duration::ZERO);
+        let window = Duration::from_secs(10);
+        let delays: Vec<_> = (0..100).map(|_| jitter(window)).collect();
+        assert!(delays.iter().all(|d| *d < window));
+        // Not every client may pick the same moment.
+        assert!(delays.iter().any(|d| *d != delays[0]));
+    }
+
+    #[test]
+    fn test_format_uptime() {
+        assert_eq!(format_uptime(Duration::from_millis(4200)), "4s");
+        assert_eq!(format_uptime(Duration::from_secs(125)), "2m 5s");
+        assert_eq!(format_uptime(Duration::from_secs(3 * 3600 + 5)), "3h 0m 5s");
+    }
+}
@@ -0,0 +1,50 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use std::io::{Read, Write};
+
+/// Frames we send are compressed from this size on, once the server agreed to it;
+/// deflate and base64 would not make shorter ones much smaller.
+pub const MIN_COMPRESS_BYTES: usize = 1024;
+
+/// The most a `Compressed` frame from the server may inflate to: the largest frame the
+/// WebSocket layer accepts.
+pub const MAX_INFLATED_BYTES: usize = 64 << 20;
+
+/// The payload of a `Compressed` frame carrying `frame`: the frame deflated, in base64.
+pub fn deflate(frame: &str) -> String {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    // Writing to a Vec cannot fail.
+    encoder.write_all(frame.as_bytes()).unwrap();
+    STANDARD.encode(encoder.finish().unwrap())
+}
+
+/// The frame inside the payload of a `Compressed` frame. Fails if the payload is not
+/// deflated base64 text or inflates to more than `MAX_INFLATED_BYTES`.
+pub fn inflate(data: &str) -> Result<String, String> {
+    let bytes = STANDARD
+        .decode(data)
+        .map_err(|e| format!("Invalid compressed frame: {}", e))?;
+    let mut frame = String::new();
+    DeflateDecoder::new(bytes.as_slice())
+        .take(MAX_INFLATED_BYTES as u64 + 1)
+        .read_to_string(&mut frame)
+        .map_err(|e| format!("Invalid compressed frame: {}", e))?;
+    if frame.len() > MAX_INFLATED_BYTES {
+        return Err("Compressed frame too large".to_string());
+    }
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deflate_round_trip() {
+        let frame = r#"{"v":1,"type":"Topic","payload":{"content":"hello"}}"#.repeat(100);
+        let data = deflate(&frame);
+        assert!(data.len() < frame.len() / 4);
+        assert_eq!(inflate(&data).unwrap(), frame);
+        assert!(inflate("not base64!").is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use super::{
+    client::Client,
+    config_dir,
+    events::{ClientHandle, Event},
+    msg::{self, ServerMessage},
+};
+use crate::cli::ui;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        unix::{OwnedReadHalf, OwnedWriteHalf},
+        UnixListener, UnixStream,
+    },
+    sync::broadcast,
+};
+
+/// How many server messages a slow `neo tail` may fall behind before it misses some.
+const TAIL_BACKLOG: usize = 256;
+
+/// A line sent to the daemon's control socket.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum ControlRequest {
+    /// Send a message to the daemon's topic; answered with a `ControlReply`.
+    Send { content: String },
+    /// Stream every server message to this connection, one envelope per line.
+    Tail,
+}
+
+/// The daemon's answer to a `ControlRequest::Send`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum ControlReply {
+    Sent,
+    Failed { reason: String },
+}
+
+/// The control socket used when none is given: `daemon.sock` in the neo config directory.
+pub fn default_socket() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("daemon.sock"))
+}
+
+/// Keeps `client` connected in the background and serves the control socket at `socket`
+/// until the connection is lost for good or the client is kicked.
+pub async fn run(
+    client: Client,
+    socket: &Path,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let listener = bind(socket)?;
+    let (handle, mut events) = client.spawn();
+    let (tail, _) = broadcast::channel(TAIL_BACKLOG);
+    let control = tokio::spawn(serve(listener, handle, tail.clone()));
+    let mut result = Ok(());
+    while let Some(event) = events.next().await {
+        match event {
+            Event::Message(message) => {
+                ui::print_server_message(&message);
+                // Nobody tailing is not an error. Chunks have no JSON form to tail.
+                if !matches!(message, ServerMessage::AttachmentChunk { .. }) {
+                    let _ = tail.send(message);
+                }
+            }
+            Event::Error(e) => result = Err(e.into()),
+            Event::Disconnected { .. } | Event::Reconnected => {}
+        }
+    }
+    control.abort();
+    let _ = std::fs::remove_file(socket);
+    result
+}
+
+/// Binds the control socket, replacing one left behind by a daemon that did not exit
+/// cleanly but refusing to take over from one that is still running.
+fn bind(socket: &Path) -> io::Result<UnixListener> {
+    if let Some(dir) = socket.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    match UnixListener::bind(socket) {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            if std::os::unix::net::UnixStream::connect(socket).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("A daemon is already listening on {}", socket.display()),
+                ));
+            }
+            std::fs::remove_file(socket)?;
+            UnixListener::bind(socket)
+        }
+        result => result,
+    }
+}
+
+async fn serve(
+    listener: UnixListener,
+    client: ClientHandle,
+    events: broadcast::Sender<ServerMessage>,
+) {
+    loop {
+        if let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(handle_control(stream, client.clone(), events.clone()));
+        }
+    }
+}
+
+/// Answers the requests of one control connection until it closes or starts tailing.
+async fn handle_control(
+    stream: UnixStream,
+    client: ClientHandle,
+    events: broadcast::Sender<ServerMessage>,
+) -> io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        let reply = match serde_json::from_str(&line) {
+            Ok(ControlRequest::Send { content }) => match client.publish(content).await {
+                Ok(_) => ControlReply::Sent,
+                Err(reason) => ControlReply::Failed { reason },
+            },
+            Ok(ControlRequest::Tail) => return tail(write, events.subscribe()).await,
+            Err(e) => ControlReply::Failed {
+                reason: format!("Invalid request: {}", e),
+            },
+        };
+        write_line(&mut write, &serde_json::to_string(&reply)?).await?;
+    }
+    Ok(())
+}
+
+async fn tail(
+    mut write: OwnedWriteHalf,
+    mut events: broadcast::Receiver<ServerMessage>,
+) -> io::Result<()> {
+    loop {
+        match events.recv().await {
+            Ok(message) => write_line(&mut write, &msg::encode(&message)?).await?,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+async fn write_line(write: &mut OwnedWriteHalf, line: &str) -> io::Result<()> {
+    write.write_all(line.as_bytes()).await?;
+    write.write_all(b"\n").await
+}
+
+/// A connection to a running daemon's control socket, as used by `neo send` and
+/// `neo tail`.
+pub struct ControlClient {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    write: OwnedWriteHalf,
+}
+
+impl ControlClient {
+    pub async fn connect(socket: &Path) -> io::Result<Self> {
+        let stream = UnixStream::connect(socket).await.map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("No daemon listening on {}: {}", socket.display(), e),
+            )
+        })?;
+        let (read, write) = stream.into_split();
+        Ok(Self {
+            lines: BufReader::new(read).lines(),
+            write,
+        })
+    }
+
+    /// Has the daemon send `content` to its topic, waiting until it was sent.
+    pub async fn send(&mut self, content: String) -> Result<(), String> {
+        let request = ControlRequest::Send { content };
+        let reply = self.request(&request).await.map_err(|e| e.to_string())?;
+        match reply {
+            ControlReply::Sent => Ok(()),
+            ControlReply::Failed { reason } => Err(reason),
+        }
+    }
+
+    async fn request(&mut self, request: &ControlRequest) -> io::Result<ControlReply> {
+        write_line(&mut self.write, &serde_json::to_string(request)?).await?;
+        let line = self.lines.next_line().await?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The daemon closed the connection",
+            )
+        })?;
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    /// Passes every message the daemon receives from now on to `on_message` until the
+    /// daemon exits.
+    pub async fn tail(mut self, mut on_message: impl FnMut(&str, ServerMessage)) -> io::Result<()> {
+        write_line(
+            &mut self.write,
+            &serde_json::to_string(&ControlRequest::Tail)?,
+        )
+        .await?;
+        while let Some(line) = self.lines.next_line().await? {
+            // Skip messages this build does not understand.
+            if let Ok(message) = msg::decode(&line) {
+                on_message(&line, message);
+            }
+        }
+        Ok(())
+    }
+}
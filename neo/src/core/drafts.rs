@@ -0,0 +1,70 @@
+use super::config_dir;
+use std::{io, path::PathBuf};
+
+/// Unsent messages saved per topic, so a composition survives the client exiting or
+/// losing its connection.
+#[derive(Clone, Debug)]
+pub struct Drafts {
+    dir: PathBuf,
+}
+
+impl Drafts {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Drafts kept under the neo config directory.
+    pub fn in_config_dir() -> Option<Self> {
+        config_dir().map(|dir| Self::new(dir.join("drafts")))
+    }
+
+    pub fn load(&self, topic: &str) -> Option<String> {
+        std::fs::read_to_string(self.path(topic))
+            .ok()
+            .filter(|draft| !draft.is_empty())
+    }
+
+    pub fn save(&self, topic: &str, draft: &str) -> io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.path(topic), draft)
+    }
+
+    pub fn clear(&self, topic: &str) -> io::Result<()> {
+        match std::fs::remove_file(self.path(topic)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Topic names are percent-encoded so any of them makes a valid file name.
+    fn path(&self, topic: &str) -> PathBuf {
+        let name: String = url::form_urlencoded::byte_serialize(topic.as_bytes()).collect();
+        self.dir.join(format!("{}.txt", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drafts_are_kept_per_topic() {
+        let dir = std::env::temp_dir().join(format!("neo-drafts-{}", uuid::Uuid::new_v4()));
+        let drafts = Drafts::new(dir.clone());
+        assert_eq!(drafts.load("general"), None);
+
+        drafts.save("general", "a long composition").unwrap();
+        drafts.save("../resistance", "escape plan").unwrap();
+        assert_eq!(
+            drafts.load("general").as_deref(),
+            Some("a long composition")
+        );
+        assert_eq!(drafts.load("../resistance").as_deref(), Some("escape plan"));
+
+        drafts.clear("general").unwrap();
+        drafts.clear("general").unwrap();
+        assert_eq!(drafts.load("general"), None);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
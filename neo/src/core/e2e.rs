@@ -0,0 +1,399 @@
+use super::config_dir;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// The header of a topic message whose content is an encrypted envelope.
+pub const ENCRYPTION_HEADER: &str = "encryption";
+/// The scheme named in the encryption header.
+pub const SCHEME: &str = "x25519-chacha20poly1305";
+
+/// The SHA-256 fingerprint of a public key, as colon-separated hex, for users to compare
+/// out of band.
+pub fn fingerprint(key: &PublicKey) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// A public key as announced on the wire.
+pub fn encode_key(key: &PublicKey) -> String {
+    STANDARD.encode(key.as_bytes())
+}
+
+pub fn decode_key(text: &str) -> Option<PublicKey> {
+    let bytes: [u8; 32] = STANDARD.decode(text).ok()?.try_into().ok()?;
+    Some(PublicKey::from(bytes))
+}
+
+/// The content of an encrypted message: the text encrypted under a fresh content key,
+/// and that key wrapped for every recipient.
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    sender: String,
+    nonce: String,
+    ciphertext: String,
+    keys: Vec<WrappedKey>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WrappedKey {
+    recipient: String,
+    nonce: String,
+    key: String,
+}
+
+/// The cipher that wraps content keys between two parties, keyed by their shared secret.
+fn key_cipher(shared: &[u8; 32], sender: &PublicKey, recipient: &PublicKey) -> ChaCha20Poly1305 {
+    let key = Sha256::new()
+        .chain_update(b"morph-e2e-v1")
+        .chain_update(shared)
+        .chain_update(sender.as_bytes())
+        .chain_update(recipient.as_bytes())
+        .finalize();
+    ChaCha20Poly1305::new(&key)
+}
+
+fn decode_bytes(text: &str, what: &str) -> Result<Vec<u8>, String> {
+    STANDARD
+        .decode(text)
+        .map_err(|_| format!("The {} is not valid base64", what))
+}
+
+/// This client's key pair, which other clients encrypt messages to.
+pub struct Identity {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl Identity {
+    pub fn generate() -> Self {
+        Self::from_secret(StaticSecret::random_from_rng(OsRng))
+    }
+
+    fn from_secret(secret: StaticSecret) -> Self {
+        let public = PublicKey::from(&secret);
+        Self { secret, public }
+    }
+
+    /// Reads the key pair saved at `path`, generating and saving one if there is none.
+    pub fn load_or_generate(path: &Path) -> Result<Self, String> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let identity = Self::generate();
+                identity.save(path)?;
+                return Ok(identity);
+            }
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        let bytes: [u8; 32] = STANDARD
+            .decode(text.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| format!("Invalid key file {}", path.display()))?;
+        Ok(Self::from_secret(StaticSecret::from(bytes)))
+    }
+
+    /// Saves the secret key at `path`, readable by the user only.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        std::fs::write(path, STANDARD.encode(self.secret.to_bytes()))
+            .map_err(|e| format!("Failed to save {}: {}", path.display(), e))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+                .map_err(|e| format!("Failed to protect {}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+
+    pub fn public(&self) -> &PublicKey {
+        &self.public
+    }
+
+    /// Encrypts `plaintext` so that only `recipients` can read it.
+    pub fn seal(&self, recipients: &[PublicKey], plaintext: &str) -> String {
+        let content_key = ChaCha20Poly1305::generate_key(&mut OsRng);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = ChaCha20Poly1305::new(&content_key)
+            .encrypt(&nonce, plaintext.as_bytes())
+            .expect("encrypting in memory cannot fail");
+        let keys = recipients
+            .iter()
+            .map(|recipient| {
+                let shared = self.secret.diffie_hellman(recipient);
+                let wrap_nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+                let wrapped = key_cipher(shared.as_bytes(), &self.public, recipient)
+                    .encrypt(&wrap_nonce, content_key.as_slice())
+                    .expect("encrypting in memory cannot fail");
+                WrappedKey {
+                    recipient: encode_key(recipient),
+                    nonce: STANDARD.encode(wrap_nonce),
+                    key: STANDARD.encode(wrapped),
+                }
+            })
+            .collect();
+        let envelope = Envelope {
+            sender: encode_key(&self.public),
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(ciphertext),
+            keys,
+        };
+        serde_json::to_string(&envelope).expect("an envelope always serializes")
+    }
+
+    /// Decrypts an envelope sealed for this identity, returning the sender's key and the
+    /// text.
+    pub fn open(&self, envelope: &str) -> Result<(PublicKey, String), String> {
+        let envelope: Envelope =
+            serde_json::from_str(envelope).map_err(|_| "Not an encrypted envelope".to_string())?;
+        let sender = decode_key(&envelope.sender).ok_or("The sender key is invalid")?;
+        let own_key = encode_key(&self.public);
+        let wrapped = envelope
+            .keys
+            .iter()
+            .find(|wrapped| wrapped.recipient == own_key)
+            .ok_or("The message was not encrypted for this key")?;
+        let shared = self.secret.diffie_hellman(&sender);
+        let wrap_nonce = decode_bytes(&wrapped.nonce, "key nonce")?;
+        if wrap_nonce.len() != 12 {
+            return Err("The key nonce is malformed".to_string());
+        }
+        let content_key = key_cipher(shared.as_bytes(), &sender, &self.public)
+            .decrypt(
+                Nonce::from_slice(&wrap_nonce),
+                decode_bytes(&wrapped.key, "wrapped key")?.as_slice(),
+            )
+            .map_err(|_| "The message key does not decrypt")?;
+        if content_key.len() != 32 {
+            return Err("The message key is malformed".to_string());
+        }
+        let nonce = decode_bytes(&envelope.nonce, "nonce")?;
+        if nonce.len() != 12 {
+            return Err("The message nonce is malformed".to_string());
+        }
+        let plaintext = ChaCha20Poly1305::new(Key::from_slice(&content_key))
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                decode_bytes(&envelope.ciphertext, "ciphertext")?.as_slice(),
+            )
+            .map_err(|_| "The message does not decrypt")?;
+        let plaintext = String::from_utf8(plaintext).map_err(|_| "The message is not text")?;
+        Ok((sender, plaintext))
+    }
+}
+
+/// How an announced key compared to the one known for its sender.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyChange {
+    /// The key is the one already known.
+    Known,
+    /// The sender was not known before.
+    New,
+    /// The sender announced a different key than before.
+    Changed,
+}
+
+/// The keys other subscribers of the topic announced, by their client ID, and the
+/// fingerprints the user verified, which are kept across launches.
+#[derive(Debug)]
+pub struct Keyring {
+    path: PathBuf,
+    peers: BTreeMap<String, PublicKey>,
+    verified: BTreeSet<String>,
+}
+
+impl Keyring {
+    /// Reads the verified fingerprints saved at `path`, one per line, starting with none
+    /// if the file does not exist.
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let verified = match std::fs::read_to_string(&path) {
+            Ok(text) => text.lines().map(str::to_string).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        Ok(Self {
+            path,
+            peers: BTreeMap::new(),
+            verified,
+        })
+    }
+
+    pub fn insert(&mut self, sender: &str, key: PublicKey) -> KeyChange {
+        match self.peers.insert(sender.to_string(), key) {
+            Some(previous) if previous == key => KeyChange::Known,
+            Some(_) => KeyChange::Changed,
+            None => KeyChange::New,
+        }
+    }
+
+    /// Forgets the peers of the previous topic, keeping the verified fingerprints.
+    pub fn clear_peers(&mut self) {
+        self.peers.clear();
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = (&str, &PublicKey)> {
+        self.peers
+            .iter()
+            .map(|(sender, key)| (sender.as_str(), key))
+    }
+
+    pub fn key_of(&self, sender: &str) -> Option<&PublicKey> {
+        self.peers.get(sender)
+    }
+
+    pub fn keys(&self) -> Vec<PublicKey> {
+        self.peers.values().copied().collect()
+    }
+
+    pub fn is_verified(&self, key: &PublicKey) -> bool {
+        self.verified.contains(&fingerprint(key))
+    }
+
+    /// Marks the peer key with `fingerprint` as verified, ignoring case and separators.
+    /// Returns the senders using it, or an error if no peer does.
+    pub fn verify(&mut self, fingerprint_text: &str) -> Result<Vec<String>, String> {
+        let normalize = |text: &str| {
+            text.chars()
+                .filter(char::is_ascii_hexdigit)
+                .collect::<String>()
+                .to_uppercase()
+        };
+        let wanted = normalize(fingerprint_text);
+        let matching: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|(_, key)| normalize(&fingerprint(key)) == wanted)
+            .map(|(sender, key)| (sender.clone(), fingerprint(key)))
+            .collect();
+        let Some((_, verified)) = matching.first() else {
+            return Err(format!(
+                "No subscriber announced a key with fingerprint {}",
+                fingerprint_text
+            ));
+        };
+        self.verified.insert(verified.clone());
+        self.save()?;
+        Ok(matching.into_iter().map(|(sender, _)| sender).collect())
+    }
+
+    fn save(&self) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        let mut text = String::new();
+        for fingerprint in &self.verified {
+            text.push_str(fingerprint);
+            text.push('\n');
+        }
+        std::fs::write(&self.path, text)
+            .map_err(|e| format!("Failed to save {}: {}", self.path.display(), e))
+    }
+}
+
+/// Where the key pair and the verified fingerprints are kept, and whether outgoing
+/// messages are encrypted.
+pub struct E2e {
+    pub key_path: PathBuf,
+    pub identity: Identity,
+    pub keyring: Keyring,
+    pub enabled: bool,
+}
+
+impl E2e {
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let key_path = dir.join("e2e.key");
+        Ok(Self {
+            identity: Identity::load_or_generate(&key_path)?,
+            keyring: Keyring::load(dir.join("e2e_verified.txt"))?,
+            key_path,
+            enabled: false,
+        })
+    }
+
+    /// The keys kept under the neo config directory.
+    pub fn in_config_dir() -> Option<Result<Self, String>> {
+        config_dir().map(|dir| Self::load(&dir))
+    }
+
+    /// Encrypts outgoing messages from the start.
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Replaces the key pair with a new one, which peers have to verify again.
+    pub fn regenerate(&mut self) -> Result<(), String> {
+        let identity = Identity::generate();
+        identity.save(&self.key_path)?;
+        self.identity = identity;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_recipients_can_open_a_sealed_message() {
+        let alice = Identity::generate();
+        let bob = Identity::generate();
+        let eve = Identity::generate();
+
+        let envelope = alice.seal(&[*bob.public()], "meet at the oracle");
+        assert!(!envelope.contains("oracle"));
+        let (sender, plaintext) = bob.open(&envelope).unwrap();
+        assert_eq!(sender, *alice.public());
+        assert_eq!(plaintext, "meet at the oracle");
+        assert!(eve.open(&envelope).is_err());
+
+        // A key wrapped for Bob by someone else does not open.
+        let forged = envelope.replace(&encode_key(alice.public()), &encode_key(eve.public()));
+        assert!(bob.open(&forged).is_err());
+        assert!(bob.open("plain text").is_err());
+    }
+
+    #[test]
+    fn test_keys_persist_and_fingerprints_verify() {
+        let dir = std::env::temp_dir().join(format!("neo-e2e-{}", uuid::Uuid::new_v4()));
+        let e2e = E2e::load(&dir).unwrap();
+        let reloaded = E2e::load(&dir).unwrap();
+        assert_eq!(e2e.identity.public(), reloaded.identity.public());
+
+        let peer = *Identity::generate().public();
+        let mut keyring = e2e.keyring;
+        assert_eq!(keyring.insert("trinity", peer), KeyChange::New);
+        assert_eq!(keyring.insert("trinity", peer), KeyChange::Known);
+        assert!(!keyring.is_verified(&peer));
+        assert!(keyring.verify("00:11").is_err());
+        let typed = fingerprint(&peer).replace(':', " ").to_lowercase();
+        assert_eq!(keyring.verify(&typed).unwrap(), vec!["trinity".to_string()]);
+        assert!(keyring.is_verified(&peer));
+        assert!(Keyring::load(dir.join("e2e_verified.txt"))
+            .unwrap()
+            .is_verified(&peer));
+        assert_eq!(
+            keyring.insert("trinity", *reloaded.identity.public()),
+            KeyChange::Changed
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+use super::msg::{CloseCode, ServerMessage};
+use futures_util::Stream;
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+/// What happens to a client running in the background, see
+/// [`Client::spawn`](super::client::Client::spawn).
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A message from the server, decrypted if it was encrypted end to end and already
+    /// acknowledged. Errors the server reports arrive as `ServerMessage::Error`.
+    Message(ServerMessage),
+    /// The client stopped because of this error; no more events follow.
+    Error(String),
+    /// The connection was lost or closed by the server, with the reason it gave. The
+    /// client reconnects unless reconnecting would not help, in which case it stops.
+    Disconnected { code: Option<CloseCode> },
+    /// The connection was re-established and the session resumed.
+    Reconnected,
+}
+
+/// What a [`ClientHandle`] asks the background client to do.
+pub(crate) enum Request {
+    Publish {
+        content: String,
+        done: oneshot::Sender<Result<Option<Uuid>, String>>,
+    },
+    Subscribe {
+        topic: String,
+        done: oneshot::Sender<Result<(), String>>,
+    },
+    Unsubscribe {
+        topic: String,
+        done: oneshot::Sender<Result<(), String>>,
+    },
+    Close,
+}
+
+/// Publishes and subscribes through a client running in the background. Clones share
+/// the client; it keeps running when every handle is dropped, until it is closed or
+/// stops on its own.
+#[derive(Clone, Debug)]
+pub struct ClientHandle {
+    requests: mpsc::Sender<Request>,
+}
+
+impl ClientHandle {
+    pub(crate) fn new(requests: mpsc::Sender<Request>) -> Self {
+        Self { requests }
+    }
+
+    /// Sends `content` to the current topic. Returns the message's ID if it was sent with
+    /// a QoS above fire-and-forget; the server confirms it with a `ServerMessage::Published`
+    /// carrying the same ID.
+    pub async fn publish(&self, content: impl Into<String>) -> Result<Option<Uuid>, String> {
+        let content = content.into();
+        self.request(|done| Request::Publish { content, done })
+            .await?
+    }
+
+    /// Joins `topic` and makes it the current one. The topic the client was in stays
+    /// joined and is caught up with when switched back to.
+    pub async fn subscribe(&self, topic: impl Into<String>) -> Result<(), String> {
+        let topic = topic.into();
+        self.request(|done| Request::Subscribe { topic, done })
+            .await?
+    }
+
+    /// Leaves a joined `topic`. Leaving the current topic switches to another joined one.
+    pub async fn unsubscribe(&self, topic: impl Into<String>) -> Result<(), String> {
+        let topic = topic.into();
+        self.request(|done| Request::Unsubscribe { topic, done })
+            .await?
+    }
+
+    /// Closes the connection and stops the client, which ends its event stream.
+    pub async fn close(&self) {
+        let _ = self.requests.send(Request::Close).await;
+    }
+
+    async fn request<T>(
+        &self,
+        request: impl FnOnce(oneshot::Sender<T>) -> Request,
+    ) -> Result<T, String> {
+        let (done, outcome) = oneshot::channel();
+        let stopped = || "The client has stopped".to_string();
+        self.requests
+            .send(request(done))
+            .await
+            .map_err(|_| stopped())?;
+        outcome.await.map_err(|_| stopped())
+    }
+}
+
+/// The events of a client running in the background, ending once it stops.
+#[derive(Debug)]
+pub struct Events {
+    events: mpsc::UnboundedReceiver<Event>,
+}
+
+impl Events {
+    pub(crate) fn new(events: mpsc::UnboundedReceiver<Event>) -> Self {
+        Self { events }
+    }
+}
+
+impl Stream for Events {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.events.poll_recv(cx)
+    }
+}
@@ -1,2 +1,24 @@
+use std::path::{Path, PathBuf};
+
+pub mod attachments;
 pub mod client;
+pub mod compression;
+#[cfg(all(unix, feature = "daemon"))]
+pub mod daemon;
+pub mod drafts;
+#[cfg(feature = "e2e")]
+pub mod e2e;
+pub mod events;
 pub mod msg;
+pub mod pins;
+pub mod profiles;
+
+/// The directory neo keeps its files in: `$XDG_CONFIG_HOME/neo`, falling back to
+/// `~/.config/neo` (or `%APPDATA%\neo` on Windows).
+pub fn config_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(base.join("neo"))
+}
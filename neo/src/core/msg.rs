@@ -1,14 +1,102 @@
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
+/// The protocol version this build speaks.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The wire format of every frame: `{ "v": 1, "type": ..., "payload": {...}, "meta": {...} }`.
+///
+/// Decoding is lenient so that peers on different versions can talk: unknown fields in a
+/// payload are ignored, an unknown `type` decodes to the `Unknown` variant, `meta` may carry
+/// anything and frames without `v` are read as version 1.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Envelope<T> {
+    #[serde(default = "default_version")]
+    pub v: u32,
+    #[serde(flatten)]
+    pub message: T,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub meta: Map<String, Value>,
+}
+
+fn default_version() -> u32 {
+    1
+}
+
+impl<T> Envelope<T> {
+    pub fn new(message: T) -> Self {
+        Self {
+            v: PROTOCOL_VERSION,
+            message,
+            meta: Map::new(),
+        }
+    }
+}
+
+/// Wraps a message in an envelope and serializes it.
+pub fn encode<T: Serialize>(message: &T) -> serde_json::Result<String> {
+    serde_json::to_string(&Envelope::new(message))
+}
+
+/// A frame whose message has not been matched against a message type yet.
+#[derive(Deserialize)]
+struct RawMessage {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    payload: Value,
+}
+
+/// Parses a frame, returning the message inside its envelope.
+pub fn decode<T: DeserializeOwned>(text: &str) -> serde_json::Result<T> {
+    let raw = serde_json::from_str::<Envelope<RawMessage>>(text)?.message;
+    let kind = raw.kind.clone();
+    serde_json::from_value(serde_json::json!({ "type": raw.kind, "payload": raw.payload })).or_else(
+        |error| {
+            // `#[serde(other)]` only matches a bare tag, so retry without the payload
+            // to map types we don't know to `Unknown`; known types keep their error.
+            serde_json::from_value(serde_json::json!({ "type": kind })).map_err(|_| error)
+        },
+    )
+}
+
 /// Messages sent from the client to the server.
 #[derive(Serialize, Deserialize, Debug)]
-#[serde(tag = "type")]
+#[serde(tag = "type", content = "payload")]
 pub enum ClientMessage {
     /// Initial message to connect and subscribe to a topic.
     Connect { topic: String },
+    /// Connects with a durable session, resuming `session_id` if it is still known.
+    ConnectSession {
+        topic: String,
+        session_id: Option<Uuid>,
+    },
     /// A message sent to a topic.
-    Message { topic: String, content: String },
+    Message {
+        topic: String,
+        content: String,
+        /// Custom metadata such as correlation IDs or routing hints, passed on to
+        /// subscribers, history and webhooks untouched.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        headers: BTreeMap<String, String>,
+        /// The delivery guarantee the sender asks for.
+        #[serde(default, skip_serializing_if = "Qos::is_fire_and_forget")]
+        qos: Qos,
+        /// An ID chosen by the sender, required above `FireAndForget` so that the server
+        /// can confirm the message and recognize retries. Subscribers get the message
+        /// under this ID.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<Uuid>,
+        /// The ID of the topic message this one replies to, if it is a reply.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reply_to: Option<Uuid>,
+        /// Keep this message as the topic's retained message, sent to every client that
+        /// subscribes from now on. Retaining an empty message clears it.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        retain: bool,
+    },
     /// A private reply to a message from Morpheus.
     ReplyToMorpheus {
         /// The ID of the message being replied to.
@@ -17,11 +105,95 @@ pub enum ClientMessage {
     },
     /// Acknowledgment that a message was received by the client.
     MessageReceived { msg_id: Uuid },
+    /// Agreement to the rules of a topic, answering `TopicInfo`.
+    AcceptTerms { topic: String, version: u32 },
+    /// Fetches up to `max` messages of the subscribed topic's history for a consumer
+    /// group, continuing where the group's members left off. Answered with `Fetched`.
+    Fetch { group: String, max: usize },
+    /// Records that a consumer group processed its topic up to sequence number `offset`.
+    CommitOffset { group: String, offset: u64 },
+    /// Asks for the retained messages of `topic`, which the client must be subscribed to,
+    /// from sequence number `from_seq` on. They are sent again as `Topic` messages; an
+    /// `Error` reports those no longer retained.
+    RequestMissed { topic: String, from_seq: u64 },
+    /// Catches up a client resuming from the background in one message: acknowledges the
+    /// messages in `acked`, then is answered like `RequestMissed`.
+    Resync {
+        topic: String,
+        from_seq: u64,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        acked: Vec<Uuid>,
+    },
+    /// Opens a poll in the client's topic, closing after `duration_secs` or the server's
+    /// default. Subscribers get it as `Poll`.
+    CreatePoll {
+        question: String,
+        options: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        duration_secs: Option<u64>,
+    },
+    /// Votes for an option of an open poll in the client's topic, by its index counted
+    /// from 0. Voting again replaces the earlier vote. Answered with `Voted`.
+    Vote { poll_id: Uuid, option: usize },
+    /// Asks which topics have subscribers. Answered with `TopicList`.
+    ListTopics,
+    /// Announces a file of `size` bytes for `topic`, which the client must be subscribed
+    /// to. Its content follows in binary frames, each the attachment ID, the chunk's
+    /// index counted from 0 as a big-endian u32 and the chunk's bytes.
+    AttachmentStart {
+        id: Uuid,
+        topic: String,
+        name: String,
+        content_type: String,
+        size: u64,
+    },
+    /// Ends an attachment once all its chunks were sent. Answered with `Published`.
+    AttachmentComplete { id: Uuid },
+    /// Announces the client's public key for end-to-end encryption to the other
+    /// subscribers of `topic`, which the client must be subscribed to.
+    PublicKey { topic: String, key: String },
+    /// Asks the server to answer with a `Pong` carrying the same `nonce`, to measure the
+    /// round trip.
+    Ping { nonce: u64 },
+    /// Presents a token as the first message, for clients that cannot set headers or
+    /// query parameters on the upgrade. The server closes the connection if the token is
+    /// not accepted.
+    Authenticate { token: String },
+    /// Disconnects another client, as `/kick` does on the server console. Only clients
+    /// with the admin role may send it.
+    Kick {
+        client_id: Uuid,
+        reason: Option<String>,
+    },
+    /// Bans another client by its ID, as `/ban` does on the server console. Only clients
+    /// with the admin role may send it.
+    Ban { client_id: Uuid },
+    /// Asks the other subscribers of `topic`, which the client must be subscribed to, to
+    /// respond under `correlation_id`. They get it as `Request`; the first `Respond` is
+    /// sent back as `Response`, or an `Error` if none comes in time.
+    Request {
+        topic: String,
+        correlation_id: Uuid,
+        content: String,
+    },
+    /// Answers a `Request` of the client's topic by its correlation ID.
+    Respond {
+        correlation_id: Uuid,
+        content: String,
+    },
+    /// Asks the server to send large frames as `Compressed` frames from now on; it
+    /// answers with `Negotiated`.
+    Negotiate { compress: bool },
+    /// Another frame, deflated and in base64, for servers that agreed to compression.
+    Compressed { data: String },
+    /// A message type introduced by a newer client.
+    #[serde(other)]
+    Unknown,
 }
 
 /// Messages sent from the server to the client.
 #[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(tag = "type")]
+#[serde(tag = "type", content = "payload")]
 pub enum ServerMessage {
     /// A global message from Morpheus to all clients.
     Global { id: Uuid, content: String },
@@ -29,16 +201,315 @@ pub enum ServerMessage {
     Topic {
         id: Uuid,
         topic: String,
+        /// The message's position in its topic, counted per topic from 1 by the server
+        /// that delivers it. A client that sees a number skipped can ask for the missing
+        /// messages with `RequestMissed`; its own messages are not sent back to it, so
+        /// their numbers are skipped too.
+        #[serde(default)]
+        seq: u64,
         /// The sender of the message.
         sender: String,
         content: String,
+        /// The custom headers the sender attached.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        headers: BTreeMap<String, String>,
+        /// The delivery guarantee the sender asked for. Above `FireAndForget` the message
+        /// is sent again until it is acknowledged.
+        #[serde(default, skip_serializing_if = "Qos::is_fire_and_forget")]
+        qos: Qos,
+        /// The ID of the topic message this one replies to, if it is a reply.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reply_to: Option<Uuid>,
+        /// Whether this is the topic's retained message, sent on subscribing rather than
+        /// as it was published. It carries sequence number 0.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        retained: bool,
     },
     /// A private message from Morpheus.
     Private { id: Uuid, content: String },
     /// Confirmation that a private message was delivered.
     MessageDelivered { msg_id: Uuid },
-    /// Acknowledgment that a message was received by a client.
+    /// A read receipt, sent to the client that published `msg_id` when `client_id`
+    /// acknowledged it.
     MessageAcknowledged { msg_id: Uuid, client_id: Uuid },
     /// An error message from the server.
     Error { message: String },
+    /// The client was moved to another topic by the operator.
+    TopicMoved { from: String, to: String },
+    /// The message of the day, sent after subscribing when the server has one.
+    Motd { content: String },
+    /// The rules of a topic, which the client must accept with `AcceptTerms` before it is
+    /// subscribed.
+    TopicInfo {
+        topic: String,
+        rules: String,
+        version: u32,
+    },
+    /// The server refused the connection or subscription because it is full.
+    LimitExceeded { limit: Limit, max: usize },
+    /// The operator removed the client; the server closes the connection after this.
+    Kicked { reason: Option<String> },
+    /// Reply to `ConnectSession` carrying the client's identity and session.
+    Welcome {
+        client_id: Uuid,
+        session_id: Uuid,
+        /// Whether a previous session was resumed.
+        resumed: bool,
+        /// How long to spread a reconnect over when the server goes away.
+        #[serde(default)]
+        reconnect_jitter_ms: u64,
+    },
+    /// A batch of topic history for a consumer group, oldest first. Empty when the group
+    /// has caught up.
+    Fetched {
+        topic: String,
+        group: String,
+        messages: Vec<FetchedMessage>,
+    },
+    /// Confirmation of `CommitOffset`, carrying the group's committed offset.
+    OffsetCommitted {
+        topic: String,
+        group: String,
+        offset: u64,
+    },
+    /// Confirms that a message sent with a QoS above `FireAndForget` was accepted and
+    /// broadcast. A retry of an exactly-once message is confirmed again but not broadcast.
+    Published { id: Uuid },
+    /// A poll was opened in the topic; vote on it with `Vote`.
+    Poll {
+        id: Uuid,
+        topic: String,
+        question: String,
+        options: Vec<String>,
+        /// The client ID of the subscriber who asked, or "Morpheus".
+        creator: String,
+        closes_in_secs: u64,
+    },
+    /// A poll closed; `votes` holds the number of votes for each option, in order.
+    PollResults {
+        id: Uuid,
+        topic: String,
+        question: String,
+        options: Vec<String>,
+        votes: Vec<usize>,
+    },
+    /// Confirms a `Vote`.
+    Voted { poll_id: Uuid, option: usize },
+    /// The topics that have subscribers on the server, answering `ListTopics`.
+    TopicList { topics: Vec<TopicSummary> },
+    /// A subscriber started sending a file to the topic. Its chunks follow as binary
+    /// frames, relayed as the sender sent them, until `AttachmentComplete` or
+    /// `AttachmentAborted`.
+    AttachmentStart {
+        id: Uuid,
+        topic: String,
+        /// The client ID of the subscriber sending the file.
+        sender: String,
+        name: String,
+        content_type: String,
+        size: u64,
+    },
+    /// Every chunk of the attachment was relayed.
+    AttachmentComplete { id: Uuid },
+    /// The attachment will not be completed, e.g. because its sender disconnected or
+    /// went over the size limit; drop the chunks received so far.
+    AttachmentAborted { id: Uuid, reason: String },
+    /// A subscriber announced its public key for end-to-end encryption. The server relays
+    /// the key, like encrypted content, without looking at it.
+    PublicKey {
+        topic: String,
+        /// The client ID of the subscriber the key belongs to.
+        sender: String,
+        key: String,
+    },
+    /// Answers a `Ping` with its `nonce`.
+    Pong { nonce: u64 },
+    /// A subscriber asks the topic for a response; answer it with `Respond` and the same
+    /// `correlation_id`.
+    Request {
+        topic: String,
+        correlation_id: Uuid,
+        /// The client ID of the subscriber asking.
+        sender: String,
+        content: String,
+    },
+    /// The response to a `Request` the client sent.
+    Response {
+        correlation_id: Uuid,
+        content: String,
+    },
+    /// Answers `Negotiate`: whether large frames come as `Compressed` frames, and whether
+    /// we may send them.
+    Negotiated { compress: bool },
+    /// Another frame, deflated and in base64.
+    Compressed { data: String },
+    /// A chunk of an attachment, decoded from a binary frame. Never encoded as JSON.
+    #[serde(skip)]
+    AttachmentChunk { id: Uuid, index: u32, data: Vec<u8> },
+    /// A message type introduced by a newer server.
+    #[serde(other)]
+    Unknown,
+}
+
+impl ServerMessage {
+    /// The ID the server expects an acknowledgment of once the message was received.
+    pub fn ack_id(&self) -> Option<Uuid> {
+        match self {
+            ServerMessage::Global { id, .. }
+            | ServerMessage::Topic { id, .. }
+            | ServerMessage::Private { id, .. } => Some(*id),
+            _ => None,
+        }
+    }
+}
+
+/// A topic message handed to a consumer group.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FetchedMessage {
+    /// The message's sequence number in its topic, to commit once it is processed.
+    pub seq: u64,
+    pub id: Uuid,
+    pub sender: String,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// A topic and how many clients are subscribed to it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TopicSummary {
+    pub name: String,
+    pub clients: usize,
+}
+
+/// How hard the server tries to get a topic message to every subscriber.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Qos {
+    /// Sent once; lost if the subscriber's connection drops before it arrives.
+    #[default]
+    FireAndForget,
+    /// Confirmed to the sender and sent to subscribers again until they acknowledge it,
+    /// so it may arrive more than once.
+    AtLeastOnce,
+    /// Like `AtLeastOnce`, but a sender's retry is not broadcast again and subscribers
+    /// drop redeliveries they already saw by the message ID.
+    ExactlyOnce,
+}
+
+impl Qos {
+    pub fn is_fire_and_forget(&self) -> bool {
+        *self == Qos::FireAndForget
+    }
+}
+
+impl std::str::FromStr for Qos {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" | "fire-and-forget" => Ok(Qos::FireAndForget),
+            "1" | "at-least-once" => Ok(Qos::AtLeastOnce),
+            "2" | "exactly-once" => Ok(Qos::ExactlyOnce),
+            _ => Err(format!(
+                "Unknown QoS '{}' (expected fire-and-forget, at-least-once or exactly-once)",
+                s
+            )),
+        }
+    }
+}
+
+/// A capacity limit the server enforces.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Limit {
+    /// Concurrent connections to the server.
+    Connections,
+    /// Clients subscribed to a single topic.
+    TopicClients,
+}
+
+/// Why the server closed the connection, as sent in the code of its close frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseCode {
+    /// The token was missing or invalid.
+    AuthFailed = 4001,
+    /// We sent a frame that is not part of the protocol.
+    ProtocolError = 4002,
+    /// We or our address are banned.
+    Banned = 4003,
+    /// The operator disconnected us.
+    Kicked = 4004,
+    /// The server is shutting down.
+    Draining = 4005,
+    /// We stopped answering the server's pings.
+    Idle = 4008,
+    /// The server is full or we could not keep up with its messages.
+    Overloaded = 4013,
+}
+
+impl CloseCode {
+    /// Maps a close frame's code to the reason it stands for, if the server defined one.
+    pub fn from_code(code: u16) -> Option<Self> {
+        Some(match code {
+            4001 => CloseCode::AuthFailed,
+            4002 => CloseCode::ProtocolError,
+            4003 => CloseCode::Banned,
+            4004 => CloseCode::Kicked,
+            4005 => CloseCode::Draining,
+            4008 => CloseCode::Idle,
+            4013 => CloseCode::Overloaded,
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_frames_from_newer_servers() {
+        let unknown = r#"{"v":2,"type":"Hologram","payload":{"scene":"?"},"meta":{"x":1}}"#;
+        assert!(matches!(
+            decode::<ServerMessage>(unknown).unwrap(),
+            ServerMessage::Unknown
+        ));
+
+        let extended = r#"{"v":2,"type":"Error","payload":{"message":"oops","code":4}}"#;
+        assert!(matches!(
+            decode::<ServerMessage>(extended).unwrap(),
+            ServerMessage::Error { message } if message == "oops"
+        ));
+    }
+
+    #[test]
+    fn test_encode_round_trips() {
+        let text = encode(&ClientMessage::Connect {
+            topic: "general".to_string(),
+        })
+        .unwrap();
+        assert!(text.starts_with(&format!("{{\"v\":{}", PROTOCOL_VERSION)));
+        assert!(matches!(
+            decode::<ClientMessage>(&text).unwrap(),
+            ClientMessage::Connect { topic } if topic == "general"
+        ));
+    }
+
+    #[test]
+    fn test_parse_qos() {
+        assert_eq!("exactly-once".parse(), Ok(Qos::ExactlyOnce));
+        assert_eq!("1".parse(), Ok(Qos::AtLeastOnce));
+        assert!("twice".parse::<Qos>().is_err());
+    }
+
+    #[test]
+    fn test_close_codes() {
+        assert_eq!(CloseCode::from_code(4003), Some(CloseCode::Banned));
+        assert_eq!(
+            CloseCode::from_code(CloseCode::Overloaded as u16),
+            Some(CloseCode::Overloaded)
+        );
+        assert_eq!(CloseCode::from_code(1000), None);
+    }
 }
@@ -0,0 +1,154 @@
+use super::config_dir;
+use std::{collections::BTreeMap, path::PathBuf, sync::Mutex};
+
+/// The certificate fingerprints of the servers neo has connected to, keyed by
+/// `host:port`. A server is trusted on first use; a later change of its certificate is
+/// refused unless the user accepts it, protecting against server impersonation.
+#[derive(Debug)]
+pub struct KnownServers {
+    path: PathBuf,
+    fingerprints: Mutex<BTreeMap<String, String>>,
+    accept_new: bool,
+}
+
+/// How a server's fingerprint compared to the one on record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PinCheck {
+    /// The fingerprint matches the recorded one.
+    Trusted,
+    /// The server was not known before; its fingerprint is now recorded.
+    FirstUse,
+    /// The fingerprint changed and the user accepted the new one.
+    Replaced { previous: String },
+}
+
+impl KnownServers {
+    /// Reads the fingerprints saved at `path`, starting empty if the file does not exist.
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let fingerprints = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| format!("Invalid known servers file {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        Ok(Self {
+            path,
+            fingerprints: Mutex::new(fingerprints),
+            accept_new: false,
+        })
+    }
+
+    /// The fingerprints kept under the neo config directory.
+    pub fn in_config_dir() -> Option<Result<Self, String>> {
+        config_dir().map(|dir| Self::load(dir.join("known_servers.json")))
+    }
+
+    /// Replaces the recorded fingerprint of a server whose certificate changed instead of
+    /// refusing to connect.
+    pub fn accept_new(mut self, accept_new: bool) -> Self {
+        self.accept_new = accept_new;
+        self
+    }
+
+    /// Compares a server's fingerprint with the recorded one, recording it if the server
+    /// is new. Returns an error explaining the mismatch if the certificate changed and
+    /// changes are not accepted.
+    pub fn check(&self, server: &str, fingerprint: &str) -> Result<PinCheck, String> {
+        let mut fingerprints = self.fingerprints.lock().unwrap();
+        let check = match fingerprints.get(server) {
+            Some(known) if known == fingerprint => return Ok(PinCheck::Trusted),
+            Some(known) if !self.accept_new => {
+                return Err(format!(
+                    "WARNING: THE CERTIFICATE OF {} HAS CHANGED!\n\
+                     Someone may be impersonating the server, or it may have a new certificate.\n\
+                     Recorded fingerprint: {}\n\
+                     Received fingerprint: {}\n\
+                     Refusing to connect. If you trust the new certificate, run neo again \
+                     with --accept-new-fingerprint.",
+                    server, known, fingerprint
+                ));
+            }
+            Some(known) => PinCheck::Replaced {
+                previous: known.clone(),
+            },
+            None => PinCheck::FirstUse,
+        };
+        fingerprints.insert(server.to_string(), fingerprint.to_string());
+        self.save(&fingerprints)?;
+        Ok(check)
+    }
+
+    fn save(&self, fingerprints: &BTreeMap<String, String>) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(fingerprints).map_err(|e| e.to_string())?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        std::fs::write(&self.path, text)
+            .map_err(|e| format!("Failed to save {}: {}", self.path.display(), e))
+    }
+}
+
+/// The SHA-256 fingerprint of a DER-encoded certificate, as colon-separated hex.
+#[cfg(feature = "tls")]
+pub fn fingerprint(der: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+
+    Sha256::digest(der)
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trust_on_first_use() {
+        let path = std::env::temp_dir().join(format!("neo-known-{}.json", uuid::Uuid::new_v4()));
+        let known = KnownServers::load(path.clone()).unwrap();
+        assert_eq!(
+            known.check("example.com:443", "AA:BB"),
+            Ok(PinCheck::FirstUse)
+        );
+        assert_eq!(
+            known.check("example.com:443", "AA:BB"),
+            Ok(PinCheck::Trusted)
+        );
+
+        // The recorded fingerprint survives a restart and a change is refused.
+        let known = KnownServers::load(path.clone()).unwrap();
+        let error = known.check("example.com:443", "CC:DD").unwrap_err();
+        assert!(error.contains("HAS CHANGED"), "{}", error);
+        assert_eq!(
+            known.check("example.com:443", "AA:BB"),
+            Ok(PinCheck::Trusted)
+        );
+
+        let known = known.accept_new(true);
+        assert_eq!(
+            known.check("example.com:443", "CC:DD"),
+            Ok(PinCheck::Replaced {
+                previous: "AA:BB".to_string()
+            })
+        );
+        let known = KnownServers::load(path.clone()).unwrap();
+        assert_eq!(
+            known.check("example.com:443", "CC:DD"),
+            Ok(PinCheck::Trusted)
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_fingerprint_format() {
+        let fingerprint = fingerprint(b"certificate");
+        assert_eq!(fingerprint.len(), 32 * 3 - 1);
+        assert!(fingerprint
+            .split(':')
+            .all(|byte| byte.len() == 2 && u8::from_str_radix(byte, 16).is_ok()));
+    }
+}
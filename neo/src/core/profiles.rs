@@ -0,0 +1,122 @@
+use super::{config_dir, msg::Qos};
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::Path};
+
+/// Connection settings saved under a name, so they need not be typed on every run. Flags
+/// given on the command line win over the profile's.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub address: Option<String>,
+    pub topic: Option<String>,
+    /// Sent with every message in a `name` header, for the topic to show.
+    pub name: Option<String>,
+    pub token: Option<String>,
+    /// `fire-and-forget`, `at-least-once` or `exactly-once`, like `--qos`.
+    pub qos: Option<String>,
+    /// Ask the server to compress large messages, like `--compress`.
+    pub compress: bool,
+    pub e2e: bool,
+    /// Trust a `wss://` server whose certificate changed since the last connection.
+    pub accept_new_fingerprint: bool,
+}
+
+impl Profile {
+    pub fn qos(&self) -> Option<Qos> {
+        // Checked when the file is read.
+        self.qos.as_deref().and_then(|qos| qos.parse().ok())
+    }
+}
+
+/// The neo config file: profiles by name, and the one used when none is asked for.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub default_profile: Option<String>,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    /// Reads the config file at `path`, which may not exist.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        Self::parse(&text).map_err(|e| format!("Invalid config file {}: {}", path.display(), e))
+    }
+
+    /// The config file under the neo config directory: `config.toml`.
+    pub fn in_config_dir() -> Option<Result<Self, String>> {
+        config_dir().map(|dir| Self::load(&dir.join("config.toml")))
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(text).map_err(|e| e.to_string())?;
+        for (name, profile) in &config.profiles {
+            if let Some(qos) = &profile.qos {
+                qos.parse::<Qos>()
+                    .map_err(|e| format!("profile '{}': {}", name, e))?;
+            }
+        }
+        if let Some(default) = &config.default_profile {
+            if !config.profiles.contains_key(default) {
+                return Err(format!("default_profile '{}' is not defined", default));
+            }
+        }
+        Ok(config)
+    }
+
+    /// The profile called `name`, or the default profile if no name is given. Asking for
+    /// a profile that does not exist is an error; having no default is not.
+    pub fn profile(&self, name: Option<&str>) -> Result<Option<&Profile>, String> {
+        match name.or(self.default_profile.as_deref()) {
+            Some(name) => self
+                .profiles
+                .get(name)
+                .map(Some)
+                .ok_or_else(|| format!("There is no profile '{}' in the config file", name)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiles_are_picked_by_name_or_default() {
+        let config = Config::parse(
+            r#"
+            default_profile = "work"
+
+            [profiles.work]
+            address = "wss://chat.example.com"
+            topic = "ops"
+            name = "alice"
+            qos = "at-least-once"
+
+            [profiles.home]
+            address = "ws://127.0.0.1:8080"
+            e2e = true
+            "#,
+        )
+        .unwrap();
+
+        let work = config.profile(None).unwrap().unwrap();
+        assert_eq!(work.topic.as_deref(), Some("ops"));
+        assert_eq!(work.qos(), Some(Qos::AtLeastOnce));
+        assert!(config.profile(Some("home")).unwrap().unwrap().e2e);
+        assert!(config.profile(Some("nowhere")).is_err());
+        assert_eq!(Config::default().profile(None), Ok(None));
+    }
+
+    #[test]
+    fn test_invalid_profiles_are_refused() {
+        assert!(Config::parse("[profiles.work]\nqos = \"sometimes\"").is_err());
+        assert!(Config::parse("[profiles.work]\nadress = \"ws://typo\"").is_err());
+        assert!(Config::parse("default_profile = \"work\"").is_err());
+    }
+}
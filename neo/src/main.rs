@@ -1,31 +1,167 @@
-use neo::core::client::Client;
-use clap::Parser;
-use url::Url;
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use neo::cli::ui;
+#[cfg(all(unix, feature = "daemon"))]
+use neo::core::daemon::{self, ControlClient};
+#[cfg(feature = "e2e")]
+use neo::core::e2e::E2e;
+#[cfg(feature = "tls")]
+use neo::core::pins::KnownServers;
+use neo::core::{
+    attachments::Downloads, client::Client, drafts::Drafts, msg::Qos, profiles::Config,
+};
+#[cfg(all(unix, feature = "daemon"))]
+use std::path::PathBuf;
 use tokio::io::{self, BufReader};
+use url::Url;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[cfg(all(unix, feature = "daemon"))]
+    #[command(subcommand)]
+    mode: Option<Mode>,
+
+    /// Profile in the config file to take connection settings from [default: the config
+    /// file's default_profile]
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
     /// Server address to connect to (e.g., ws://127.0.0.1:8080)
-    #[arg(short, long)]
-    address: String,
+    #[arg(short, long, global = true)]
+    address: Option<String>,
 
     /// Topic to subscribe to
-    #[arg(short, long)]
-    topic: String,
+    #[arg(short, long, global = true)]
+    topic: Option<String>,
+
+    /// Authentication token, for servers that require one
+    #[arg(long, global = true)]
+    token: Option<String>,
+
+    /// Name shown to the topic with your messages
+    #[arg(long, global = true)]
+    name: Option<String>,
+
+    /// Delivery guarantee for sent messages: fire-and-forget, at-least-once or exactly-once
+    /// [default: fire-and-forget]
+    #[arg(long, global = true)]
+    qos: Option<Qos>,
+
+    /// Ask the server to compress large messages in both directions
+    #[arg(long, global = true)]
+    compress: bool,
+
+    /// Trust a `wss://` server whose certificate changed since the last connection
+    #[cfg(feature = "tls")]
+    #[arg(long, global = true)]
+    accept_new_fingerprint: bool,
+
+    /// Encrypt sent messages end to end for the topic's other neo clients
+    #[cfg(feature = "e2e")]
+    #[arg(long, global = true)]
+    e2e: bool,
+
+    /// Show messages in a scrollable pane above a separate input line
+    #[cfg(feature = "tui")]
+    #[arg(long, conflicts_with = "pipe")]
+    tui: bool,
+
+    /// Publish every line of stdin to the topic and print incoming messages as JSON
+    /// lines, exiting at the end of stdin
+    #[arg(long)]
+    pipe: bool,
+}
+
+/// What neo does instead of the interactive client.
+#[cfg(all(unix, feature = "daemon"))]
+#[derive(clap::Subcommand, Debug)]
+enum Mode {
+    /// Stay connected in the background and accept `send` and `tail` on a control socket
+    Daemon {
+        /// Control socket to listen on [default: daemon.sock in the neo config directory]
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+    /// Send a message through a running daemon; reads one message per line from stdin
+    /// when none is given
+    Send {
+        /// Control socket of the daemon
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        /// The message to send
+        message: Vec<String>,
+    },
+    /// Print the messages a running daemon receives
+    Tail {
+        /// Control socket of the daemon
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        /// Print every message as the raw JSON envelope
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+impl Args {
+    /// Fills in what was not given on the command line from the chosen profile.
+    fn apply_profile(&mut self) -> Result<(), String> {
+        let config = Config::in_config_dir().transpose()?.unwrap_or_default();
+        let Some(profile) = config.profile(self.profile.as_deref())? else {
+            return Ok(());
+        };
+        self.address = self.address.take().or_else(|| profile.address.clone());
+        self.topic = self.topic.take().or_else(|| profile.topic.clone());
+        self.token = self.token.take().or_else(|| profile.token.clone());
+        self.name = self.name.take().or_else(|| profile.name.clone());
+        self.qos = self.qos.or(profile.qos());
+        self.compress |= profile.compress;
+        #[cfg(feature = "tls")]
+        {
+            self.accept_new_fingerprint |= profile.accept_new_fingerprint;
+        }
+        #[cfg(feature = "e2e")]
+        {
+            self.e2e |= profile.e2e;
+        }
+        Ok(())
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    let args = Args::parse();
+    let mut args = Args::parse();
 
-    match Url::parse(&args.address) {
+    #[cfg(all(unix, feature = "daemon"))]
+    match &args.mode {
+        Some(Mode::Send { socket, message }) => {
+            return exit_on_error(send(control_socket(socket), message.join(" ")).await);
+        }
+        Some(Mode::Tail { socket, json }) => {
+            return exit_on_error(tail(control_socket(socket), *json).await);
+        }
+        Some(Mode::Daemon { .. }) | None => {}
+    }
+
+    if let Err(e) = args.apply_profile() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    let address = required(&args.address, "--address <ADDRESS>");
+    let topic = required(&args.topic, "--topic <TOPIC>");
+    match Url::parse(address) {
         Ok(base_url) => {
             // The server listens on the /ws path
             match base_url.join("ws") {
-                Ok(ws_url) => {
-                    println!("Connecting to {} on topic '{}'...", ws_url, args.topic);
-                    if let Err(e) = run_client(ws_url, args.topic).await {
+                Ok(mut ws_url) => {
+                    if args.pipe {
+                        ui::errors_only();
+                    } else {
+                        println!("Connecting to {} on topic '{}'...", ws_url, topic);
+                    }
+                    if let Some(token) = &args.token {
+                        ws_url.query_pairs_mut().append_pair("token", token);
+                    }
+                    if let Err(e) = run_client(ws_url, topic.clone(), &args).await {
                         eprintln!("Client error: {}", e);
                     }
                 }
@@ -40,8 +176,146 @@ async fn main() {
     }
 }
 
-async fn run_client(url: Url, topic: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut client = Client::new(url, topic).await?;
+/// The value of an argument that only `send` and `tail` can do without, exiting with
+/// clap's usage error when it is missing.
+fn required<'a>(value: &'a Option<String>, arg: &str) -> &'a String {
+    value.as_ref().unwrap_or_else(|| {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                format!(
+                    "the following required arguments were not provided:\n  {}",
+                    arg
+                ),
+            )
+            .exit()
+    })
+}
+
+async fn run_client(
+    url: Url,
+    topic: String,
+    args: &Args,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = connect(url, topic, args)
+        .await?
+        .with_qos(args.qos.unwrap_or_default())
+        .with_compression(args.compress);
+    if let Some(name) = &args.name {
+        client = client.with_name(name.clone());
+    }
+    #[cfg(feature = "e2e")]
+    if let Some(e2e) = E2e::in_config_dir() {
+        client = client.with_e2e(e2e?.enabled(args.e2e));
+    }
+    #[cfg(all(unix, feature = "daemon"))]
+    if let Some(Mode::Daemon { socket }) = &args.mode {
+        let socket = control_socket(socket);
+        println!("Listening for control connections on {}", socket.display());
+        return daemon::run(client, &socket).await;
+    }
+    if args.pipe {
+        let mut stdin = BufReader::new(io::stdin());
+        return neo::cli::pipe::run(client, &mut stdin, &mut io::stdout()).await;
+    }
+    if let Some(drafts) = Drafts::in_config_dir() {
+        client = client.with_drafts(drafts);
+    }
+    if let Some(downloads) = Downloads::in_config_dir() {
+        client = client.with_downloads(downloads);
+    }
+    #[cfg(feature = "tui")]
+    if args.tui {
+        return neo::cli::tui::run(&mut client).await;
+    }
     let mut stdin = BufReader::new(io::stdin());
     client.run(&mut stdin).await
 }
+
+/// The control socket given with `--socket`, or else the one in the neo config directory.
+/// Exits when there is no config directory: a path relative to wherever neo was started
+/// would not be found by `send` and `tail` run from elsewhere.
+#[cfg(all(unix, feature = "daemon"))]
+fn control_socket(socket: &Option<PathBuf>) -> PathBuf {
+    socket
+        .clone()
+        .or_else(daemon::default_socket)
+        .unwrap_or_else(|| {
+            Args::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "the neo config directory is unknown, as neither XDG_CONFIG_HOME nor HOME \
+                     is set; give the control socket with --socket <PATH>",
+                )
+                .exit()
+        })
+}
+
+#[cfg(all(unix, feature = "daemon"))]
+fn exit_on_error(result: Result<(), Box<dyn std::error::Error + Send + Sync>>) {
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Sends `message`, or every line of stdin when it is empty, through the daemon.
+#[cfg(all(unix, feature = "daemon"))]
+async fn send(
+    socket: PathBuf,
+    message: String,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use tokio::io::AsyncBufReadExt;
+
+    let mut control = ControlClient::connect(&socket).await?;
+    if !message.trim().is_empty() {
+        return Ok(control.send(message).await?);
+    }
+    let mut lines = BufReader::new(io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        if !line.trim().is_empty() {
+            control.send(line).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(unix, feature = "daemon"))]
+async fn tail(socket: PathBuf, json: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let control = ControlClient::connect(&socket).await?;
+    control
+        .tail(|line, message| {
+            if json {
+                println!("{}", line);
+            } else {
+                ui::print_server_message(&message);
+            }
+        })
+        .await?;
+    Ok(())
+}
+
+/// Connects to the server, checking its certificate against the ones seen before.
+#[cfg(feature = "tls")]
+async fn connect(
+    url: Url,
+    topic: String,
+    args: &Args,
+) -> Result<Client, Box<dyn std::error::Error + Send + Sync>> {
+    match KnownServers::in_config_dir() {
+        Some(known_servers) => {
+            let known_servers = known_servers?.accept_new(args.accept_new_fingerprint);
+            Client::new_pinned(url, topic, known_servers).await
+        }
+        None => Client::new(url, topic).await,
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+async fn connect(
+    url: Url,
+    topic: String,
+    _args: &Args,
+) -> Result<Client, Box<dyn std::error::Error + Send + Sync>> {
+    Client::new(url, topic).await
+}
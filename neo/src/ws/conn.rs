@@ -1,12 +1,21 @@
-use crate::core::msg::{ClientMessage, ServerMessage};
+use crate::core::{
+    attachments::decode_chunk,
+    compression::{self, MIN_COMPRESS_BYTES},
+    msg::{decode, encode, ClientMessage, CloseCode, ServerMessage},
+};
 use futures_util::{
     stream::{SplitSink, SplitStream},
     SinkExt, StreamExt,
 };
+use serde::de::Error as _;
+use std::time::{Duration, Instant};
 use tokio::net::TcpStream;
 use tokio_tungstenite::{
     connect_async,
-    tungstenite::{Error as WsError, Message},
+    tungstenite::{
+        client::IntoClientRequest, handshake::client::Request, http::HeaderValue, Error as WsError,
+        Message,
+    },
     MaybeTlsStream, WebSocketStream,
 };
 use url::Url;
@@ -18,29 +27,167 @@ type WsStream = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
 pub struct Connection {
     write: WsSink,
     read: WsStream,
+    connected_at: Instant,
+    last_activity: Instant,
+    /// The code of the server's close frame, once it closed the connection.
+    close_code: Option<u16>,
+    /// Whether the server agreed to compression, so large frames go out compressed.
+    compress: bool,
+}
+
+/// Tells the server which client this is in the handshake `request`.
+fn identify(mut request: Request) -> Request {
+    request.headers_mut().insert(
+        "user-agent",
+        HeaderValue::from_static(concat!("neo/", env!("CARGO_PKG_VERSION"))),
+    );
+    request
 }
 
 impl Connection {
     /// Attempts to connect to the specified URL.
     pub async fn connect(url: Url) -> Result<Self, WsError> {
-        let (ws_stream, _) = connect_async(url).await?;
+        let (ws_stream, _) = connect_async(identify(url.as_str().into_client_request()?)).await?;
+        Ok(Self::from_stream(ws_stream))
+    }
+
+    /// Connects like `connect`, but for a `wss://` URL first passes the server's
+    /// `host:port` and certificate fingerprint to `verify`, and gives up without sending
+    /// the WebSocket handshake (and any token in it) if it returns an error.
+    #[cfg(feature = "tls")]
+    pub async fn connect_verified(
+        url: Url,
+        verify: impl FnOnce(&str, &str) -> Result<(), String>,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        if url.scheme() != "wss" {
+            return Ok(Self::connect(url).await?);
+        }
+        let host = url
+            .host_str()
+            .ok_or("The server address has no host")?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let port = url.port_or_known_default().unwrap_or(443);
+        let tcp = TcpStream::connect((host, port)).await?;
+        let connector = tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
+        let tls = connector.connect(host, tcp).await?;
+        let certificate = tls
+            .get_ref()
+            .peer_certificate()?
+            .ok_or("The server sent no certificate")?;
+        let fingerprint = crate::core::pins::fingerprint(&certificate.to_der()?);
+        verify(&format!("{}:{}", host, port), &fingerprint)?;
+        let (ws_stream, _) = tokio_tungstenite::client_async(
+            identify(url.as_str().into_client_request()?),
+            MaybeTlsStream::NativeTls(tls),
+        )
+        .await?;
+        Ok(Self::from_stream(ws_stream))
+    }
+
+    fn from_stream(ws_stream: WebSocketStream<MaybeTlsStream<TcpStream>>) -> Self {
         let (write, read) = ws_stream.split();
-        Ok(Self { write, read })
+        Self {
+            write,
+            read,
+            connected_at: Instant::now(),
+            last_activity: Instant::now(),
+            close_code: None,
+            compress: false,
+        }
+    }
+
+    /// How long ago the connection was established.
+    pub fn uptime(&self) -> Duration {
+        self.connected_at.elapsed()
+    }
+
+    /// How long it has been since any frame was received from the server.
+    pub fn idle_for(&self) -> Duration {
+        self.last_activity.elapsed()
+    }
+
+    /// Why the server closed the connection, if it said so in a code we know.
+    pub fn close_code(&self) -> Option<CloseCode> {
+        self.close_code.and_then(CloseCode::from_code)
     }
 
-    /// Sends a `ClientMessage` to the server.
+    /// Sends a WebSocket ping; the server's pong counts as activity.
+    pub async fn ping(&mut self) -> Result<(), WsError> {
+        self.write.send(Message::Ping(Vec::new())).await
+    }
+
+    /// Sends a `ClientMessage` to the server, compressed if the server agreed to
+    /// compression and that makes it smaller.
     pub async fn send(&mut self, msg: ClientMessage) -> Result<(), WsError> {
-        let json_msg = serde_json::to_string(&msg).unwrap();
+        let mut json_msg = encode(&msg).unwrap();
+        if self.compress && json_msg.len() >= MIN_COMPRESS_BYTES {
+            let data = compression::deflate(&json_msg);
+            let compressed = encode(&ClientMessage::Compressed { data }).unwrap();
+            if compressed.len() < json_msg.len() {
+                json_msg = compressed;
+            }
+        }
         self.write.send(Message::Text(json_msg)).await
     }
 
-    /// Receives a `ServerMessage` from the server.
-    /// Returns `None` if the connection is closed.
+    /// Asks the server to compress large frames; `recv` takes note of its answer.
+    pub async fn negotiate_compression(&mut self) -> Result<(), WsError> {
+        self.send(ClientMessage::Negotiate { compress: true }).await
+    }
+
+    /// Decodes a text frame, inflating it first if it is a `Compressed` frame.
+    fn decode_text(&mut self, text: &str) -> Result<ServerMessage, serde_json::Error> {
+        match decode(text)? {
+            ServerMessage::Compressed { data } => {
+                let frame = compression::inflate(&data).map_err(serde_json::Error::custom)?;
+                decode(&frame)
+            }
+            ServerMessage::Negotiated { compress } => {
+                self.compress = compress;
+                Ok(ServerMessage::Negotiated { compress })
+            }
+            message => Ok(message),
+        }
+    }
+
+    /// Ends the connection with a close frame.
+    pub async fn close(&mut self) -> Result<(), WsError> {
+        self.write.close().await
+    }
+
+    /// Sends a binary frame, such as an attachment chunk, to the server.
+    pub async fn send_binary(&mut self, frame: Vec<u8>) -> Result<(), WsError> {
+        self.write.send(Message::Binary(frame)).await
+    }
+
+    /// Receives a `ServerMessage` from the server, answering pings along the way.
+    /// Binary frames are attachment chunks. Returns `None` if the connection is closed.
     pub async fn recv(&mut self) -> Option<Result<ServerMessage, serde_json::Error>> {
         loop {
-            match self.read.next().await {
-                Some(Ok(Message::Text(text))) => return Some(serde_json::from_str(&text)),
-                Some(Ok(Message::Close(_))) => return None,
+            let frame = self.read.next().await;
+            if let Some(Ok(_)) = frame {
+                self.last_activity = Instant::now();
+            }
+            match frame {
+                Some(Ok(Message::Text(text))) => return Some(self.decode_text(&text)),
+                Some(Ok(Message::Binary(frame))) => {
+                    if let Some((id, index, data)) = decode_chunk(&frame) {
+                        let data = data.to_vec();
+                        return Some(Ok(ServerMessage::AttachmentChunk { id, index, data }));
+                    }
+                }
+                Some(Ok(Message::Close(frame))) => {
+                    self.close_code = frame.map(|frame| frame.code.into());
+                    return None;
+                }
+                Some(Ok(Message::Ping(_))) => {
+                    // tungstenite queues the pong reply itself; flush it right away so the
+                    // server's heartbeat sees us as alive even when we have nothing to send.
+                    if self.write.flush().await.is_err() {
+                        return None;
+                    }
+                }
                 Some(Err(_)) => return None,
                 Some(Ok(_)) => continue, // Ignore other message types
                 None => return None,     // Stream is closed
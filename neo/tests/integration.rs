@@ -2,25 +2,21 @@
 // the server stays alive for all of them.
 
 use anyhow::Result;
-use futures_util::{SinkExt, StreamExt};
-use morpheus::core::{client_manager::ClientManager, msg::ServerMessage, storage::InMemoryStorage};
+use futures_util::StreamExt;
+use morpheus::{
+    core::msg::ServerMessage,
+    testing::{TestClient, TestHarness},
+};
 use neo::core::{client::Client, msg::ClientMessage as NeoClientMessage};
 use std::{
     pin::Pin,
-    sync::Arc,
     task::{Context, Poll},
     time::Duration,
 };
 use tokio::io::{AsyncRead, BufReader};
-use tokio::net::TcpListener;
-use tokio_tungstenite::{
-    connect_async,
-    tungstenite::{protocol::Message, Error as WsError},
-    MaybeTlsStream, WebSocketStream,
-};
+use tokio::sync::OnceCell;
 use url::Url;
 use uuid::Uuid;
-use warp::Filter;
 
 // A mock reader that never returns any data, keeping the client's run loop pending.
 struct PendingReader;
@@ -35,91 +31,10 @@ impl AsyncRead for PendingReader {
     }
 }
 
-// --- Test Harness for Morpheus Server ---
-struct TestHarness {
-    port: u16,
-    client_manager: Arc<ClientManager>,
-}
-
-static HARNESS: tokio::sync::OnceCell<TestHarness> = tokio::sync::OnceCell::const_new();
+static HARNESS: OnceCell<TestHarness> = OnceCell::const_new();
 
 async fn setup_server() -> &'static TestHarness {
-    HARNESS
-        .get_or_init(|| async {
-            let port = find_free_port().await;
-            let addr = format!("127.0.0.1:{}", port);
-            let addr_clone = addr.clone();
-
-            let storage = Arc::new(InMemoryStorage::new());
-            let client_manager = Arc::new(ClientManager::new(storage));
-            let server_client_manager = client_manager.clone();
-
-            tokio::spawn(async move {
-                let ws_route = warp::path("ws")
-                    .and(warp::ws())
-                    .and(warp::any().map(move || server_client_manager.clone()))
-                    .map(|ws: warp::ws::Ws, manager| {
-                        ws.on_upgrade(move |socket| {
-                            morpheus::ws::handler::client_connected(socket, manager)
-                        })
-                    });
-
-                warp::serve(ws_route)
-                    .run(addr_clone.parse::<std::net::SocketAddr>().unwrap())
-                    .await;
-            });
-
-            for _ in 0..10 {
-                if tokio::net::TcpStream::connect(&addr).await.is_ok() {
-                    break;
-                }
-                tokio::time::sleep(Duration::from_millis(50)).await;
-            }
-
-            TestHarness {
-                port,
-                client_manager,
-            }
-        })
-        .await
-}
-
-async fn find_free_port() -> u16 {
-    TcpListener::bind("127.0.0.1:0")
-        .await
-        .unwrap()
-        .local_addr()
-        .unwrap()
-        .port()
-}
-
-// --- Listener Client ---
-struct ListenerClient {
-    ws: WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>,
-}
-
-impl ListenerClient {
-    async fn new(port: u16, topic: &str) -> Result<Self> {
-        let url = format!("ws://127.0.0.1:{}/ws", port);
-        let (mut ws, _) = connect_async(&url).await?;
-
-        let connect_msg = morpheus::core::msg::ClientMessage::Connect {
-            topic: topic.to_string(),
-        };
-        let connect_msg_str = serde_json::to_string(&connect_msg)?;
-        ws.send(Message::Text(connect_msg_str)).await?;
-
-        Ok(Self { ws })
-    }
-
-    async fn recv(&mut self) -> Result<Option<ServerMessage>, WsError> {
-        let msg = self.ws.next().await;
-        match msg {
-            Some(Ok(Message::Text(text))) => Ok(Some(serde_json::from_str(&text).unwrap())),
-            Some(Err(e)) => Err(e),
-            _ => Ok(None),
-        }
-    }
+    HARNESS.get_or_init(TestHarness::start).await
 }
 
 #[tokio::test]
@@ -134,6 +49,37 @@ async fn all_integration_tests() -> Result<()> {
     test_client_sends_topic_message(harness).await?;
     println!("--- Finished test_client_sends_topic_message ---");
 
+    println!("--- Running test_client_sends_with_qos ---");
+    test_client_sends_with_qos(harness).await?;
+    println!("--- Finished test_client_sends_with_qos ---");
+
+    println!("--- Running test_client_compresses_large_messages ---");
+    test_client_compresses_large_messages(harness).await?;
+    println!("--- Finished test_client_compresses_large_messages ---");
+
+    println!("--- Running test_client_queues_messages_while_disconnected ---");
+    test_client_queues_messages_while_disconnected(harness).await?;
+    println!("--- Finished test_client_queues_messages_while_disconnected ---");
+
+    println!("--- Running test_spawned_client_publishes_and_streams_events ---");
+    test_spawned_client_publishes_and_streams_events(harness).await?;
+    println!("--- Finished test_spawned_client_publishes_and_streams_events ---");
+
+    println!("--- Running test_unsent_input_is_saved_as_a_draft ---");
+    test_unsent_input_is_saved_as_a_draft(harness).await?;
+    println!("--- Finished test_unsent_input_is_saved_as_a_draft ---");
+
+    println!("--- Running test_pipe_publishes_lines_until_eof ---");
+    test_pipe_publishes_lines_until_eof(harness).await?;
+    println!("--- Finished test_pipe_publishes_lines_until_eof ---");
+
+    #[cfg(all(unix, feature = "daemon"))]
+    {
+        println!("--- Running test_daemon_sends_and_tails ---");
+        test_daemon_sends_and_tails(harness).await?;
+        println!("--- Finished test_daemon_sends_and_tails ---");
+    }
+
     Ok(())
 }
 
@@ -171,7 +117,7 @@ async fn test_client_sends_topic_message(harness: &TestHarness) -> Result<()> {
         .join("ws")?;
 
     // 1. Create a listener client
-    let mut listener = ListenerClient::new(harness.port, &topic).await?;
+    let mut listener = TestClient::connect(harness.port, &topic).await?;
 
     // 2. Create the Neo client instance
     let mut neo_client = Client::new(url, topic.to_string())
@@ -215,3 +161,364 @@ async fn test_client_sends_topic_message(harness: &TestHarness) -> Result<()> {
 
     Ok(())
 }
+
+async fn test_client_sends_with_qos(harness: &TestHarness) -> Result<()> {
+    use neo::core::msg::{Qos, ServerMessage as NeoServerMessage};
+
+    let topic = format!("test-topic-{}", Uuid::new_v4());
+    let url = format!("ws://127.0.0.1:{}", harness.port)
+        .parse::<Url>()?
+        .join("ws")?;
+    let mut listener = TestClient::connect(harness.port, &topic).await?;
+    let mut neo_client = Client::new(url, topic.to_string())
+        .await
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?
+        .with_qos(Qos::AtLeastOnce);
+    neo_client
+        .connection
+        .send(NeoClientMessage::Connect {
+            topic: topic.to_string(),
+        })
+        .await?;
+    for _ in 0..20 {
+        if harness.client_manager.get_clients_by_topic(&topic).len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    neo_client
+        .handle_user_input("confirm this")
+        .await
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+
+    let Some(ServerMessage::Topic { id, qos, .. }) = listener.recv().await? else {
+        panic!("Incorrect message type received by listener");
+    };
+    assert_eq!(qos, morpheus::core::msg::Qos::AtLeastOnce);
+    let confirmed = tokio::time::timeout(Duration::from_secs(2), neo_client.connection.recv())
+        .await?
+        .expect("The server closed the connection")?;
+    assert!(
+        matches!(confirmed, NeoServerMessage::Published { id: published } if published == id),
+        "Unexpected message {:?}",
+        confirmed
+    );
+    Ok(())
+}
+
+async fn test_client_compresses_large_messages(harness: &TestHarness) -> Result<()> {
+    use neo::core::msg::ServerMessage as NeoServerMessage;
+
+    let topic = format!("test-topic-{}", Uuid::new_v4());
+    let url = format!("ws://127.0.0.1:{}", harness.port)
+        .parse::<Url>()?
+        .join("ws")?;
+    let mut listener = TestClient::connect(harness.port, &topic).await?;
+    let mut neo_client = Client::new(url, topic.to_string())
+        .await
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?
+        .with_compression(true);
+    neo_client.connection.negotiate_compression().await?;
+    let negotiated = tokio::time::timeout(Duration::from_secs(2), neo_client.connection.recv())
+        .await?
+        .expect("The server closed the connection")?;
+    assert!(
+        matches!(negotiated, NeoServerMessage::Negotiated { compress: true }),
+        "Unexpected message {:?}",
+        negotiated
+    );
+    neo_client
+        .connection
+        .send(NeoClientMessage::Connect {
+            topic: topic.to_string(),
+        })
+        .await?;
+    for _ in 0..20 {
+        if harness.client_manager.get_clients_by_topic(&topic).len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    // Sent compressed; the server inflates it for the listener, which did not negotiate.
+    let content = "All work and no play makes Jack a dull boy.".repeat(200);
+    neo_client
+        .handle_user_input(&content)
+        .await
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+    let received = listener.recv().await?;
+    assert!(
+        matches!(&received, Some(ServerMessage::Topic { content: c, .. }) if *c == content),
+        "Unexpected message {:?}",
+        received
+    );
+
+    // Received compressed, and inflated by the connection.
+    let reply = content.to_uppercase();
+    listener.send_message(&topic, &reply).await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), neo_client.connection.recv())
+        .await?
+        .expect("The server closed the connection")?;
+    assert!(
+        matches!(&received, NeoServerMessage::Topic { content, .. } if *content == reply),
+        "Unexpected message {:?}",
+        received
+    );
+    Ok(())
+}
+
+async fn test_client_queues_messages_while_disconnected(harness: &TestHarness) -> Result<()> {
+    let topic = format!("test-topic-{}", Uuid::new_v4());
+    let url = format!("ws://127.0.0.1:{}", harness.port)
+        .parse::<Url>()?
+        .join("ws")?;
+    let mut listener = TestClient::connect(harness.port, &topic).await?;
+    let mut neo_client = Client::new(url, topic.to_string())
+        .await
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+
+    // The connection is gone before the message is sent, so it waits for the reconnect
+    // and goes out right after it instead of failing.
+    neo_client.connection.close().await?;
+    neo_client
+        .handle_user_input("sent after reconnecting")
+        .await
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+
+    match tokio::time::timeout(Duration::from_secs(2), listener.recv())
+        .await??
+        .expect("Listener did not receive message")
+    {
+        ServerMessage::Topic { content, .. } => assert_eq!(content, "sent after reconnecting"),
+        other => panic!("Unexpected message: {:?}", other),
+    }
+    Ok(())
+}
+
+async fn test_spawned_client_publishes_and_streams_events(harness: &TestHarness) -> Result<()> {
+    use futures_util::Stream;
+    use neo::core::{events::Event, msg::ServerMessage as NeoServerMessage};
+
+    async fn next_topic_message(
+        events: &mut (impl Stream<Item = Event> + Unpin),
+    ) -> Result<(String, String)> {
+        loop {
+            match tokio::time::timeout(Duration::from_secs(2), events.next()).await? {
+                Some(Event::Message(NeoServerMessage::Topic { topic, content, .. })) => {
+                    return Ok((topic, content))
+                }
+                Some(Event::Message(_)) => {}
+                other => panic!("Unexpected event: {:?}", other),
+            }
+        }
+    }
+
+    let topic = format!("test-topic-{}", Uuid::new_v4());
+    let other_topic = format!("test-topic-{}", Uuid::new_v4());
+    let url = format!("ws://127.0.0.1:{}", harness.port)
+        .parse::<Url>()?
+        .join("ws")?;
+    let mut listener = TestClient::connect(harness.port, &other_topic).await?;
+    let (client, mut events) = Client::new(url, topic.clone())
+        .await
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?
+        .spawn();
+
+    // 1. Subscribing moves the client to the topic, where its messages go from then on.
+    client
+        .subscribe(other_topic.clone())
+        .await
+        .map_err(|e| anyhow::anyhow!(e))?;
+    for _ in 0..20 {
+        if harness
+            .client_manager
+            .get_clients_by_topic(&other_topic)
+            .len()
+            == 2
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(client.publish("from the library").await, Ok(None));
+    match listener
+        .recv()
+        .await?
+        .expect("Listener did not receive message")
+    {
+        ServerMessage::Topic { content, .. } => assert_eq!(content, "from the library"),
+        other => panic!("Unexpected message: {:?}", other),
+    }
+
+    // 2. Messages from others arrive as events.
+    listener
+        .send_message(&other_topic, "to the library")
+        .await?;
+    assert_eq!(
+        next_topic_message(&mut events).await?,
+        (other_topic.clone(), "to the library".to_string())
+    );
+
+    // 3. Leaving a topic that was never joined fails; closing ends the stream.
+    assert!(client.unsubscribe("nowhere").await.is_err());
+    client.close().await;
+    while let Some(event) = tokio::time::timeout(Duration::from_secs(2), events.next()).await? {
+        assert!(
+            matches!(event, Event::Message(_)),
+            "Unexpected event {:?}",
+            event
+        );
+    }
+    Ok(())
+}
+
+async fn test_unsent_input_is_saved_as_a_draft(harness: &TestHarness) -> Result<()> {
+    use neo::core::drafts::Drafts;
+    use tokio::io::AsyncWriteExt;
+
+    let topic = format!("test-topic-{}", Uuid::new_v4());
+    let url = format!("ws://127.0.0.1:{}", harness.port)
+        .parse::<Url>()?
+        .join("ws")?;
+    let drafts = Drafts::new(std::env::temp_dir().join(format!("neo-drafts-{}", Uuid::new_v4())));
+    let mut neo_client = Client::new(url, topic.to_string())
+        .await
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?
+        .with_drafts(drafts.clone());
+
+    // Half a line is typed, and the client stops before it is finished.
+    let (mut typed, stdin) = tokio::io::duplex(64);
+    typed.write_all(b"unsent dra").await?;
+    let client_task = tokio::spawn(async move {
+        let mut stdin = BufReader::new(stdin);
+        neo_client.run(&mut stdin).await.map_err(|e| e.to_string())
+    });
+    let mut subscribed = Vec::new();
+    for _ in 0..20 {
+        subscribed = harness.client_manager.get_clients_by_topic(&topic);
+        if !subscribed.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(harness.client_manager.kick_client(&subscribed[0].id, None));
+    tokio::time::timeout(Duration::from_secs(5), client_task)
+        .await??
+        .map_err(anyhow::Error::msg)?;
+
+    assert_eq!(drafts.load(&topic).as_deref(), Some("unsent dra"));
+    drafts.clear(&topic)?;
+    drop(typed);
+    Ok(())
+}
+
+async fn test_pipe_publishes_lines_until_eof(harness: &TestHarness) -> Result<()> {
+    use neo::core::msg::{self as neo_msg, Qos, ServerMessage as NeoServerMessage};
+
+    let topic = format!("test-topic-{}", Uuid::new_v4());
+    let url = format!("ws://127.0.0.1:{}", harness.port)
+        .parse::<Url>()?
+        .join("ws")?;
+    let mut listener = TestClient::connect(harness.port, &topic).await?;
+    let neo_client = Client::new(url, topic.to_string())
+        .await
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?
+        .with_qos(Qos::AtLeastOnce);
+
+    let mut input: &[u8] = b"first\n\nsecond\n";
+    let mut output = Vec::new();
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        neo::cli::pipe::run(neo_client, &mut input, &mut output),
+    )
+    .await?
+    .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+
+    for expected in ["first", "second"] {
+        match listener
+            .recv()
+            .await?
+            .expect("Listener did not receive message")
+        {
+            ServerMessage::Topic { content, .. } => assert_eq!(content, expected),
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+    // The pipe only stopped once both messages were confirmed, and printed one JSON
+    // message per line.
+    let published = String::from_utf8(output)?
+        .lines()
+        .map(|line| neo_msg::decode::<NeoServerMessage>(line).unwrap())
+        .filter(|message| matches!(message, NeoServerMessage::Published { .. }))
+        .count();
+    assert_eq!(published, 2);
+    Ok(())
+}
+
+#[cfg(all(unix, feature = "daemon"))]
+async fn test_daemon_sends_and_tails(harness: &TestHarness) -> Result<()> {
+    use neo::core::{daemon, daemon::ControlClient, msg::ServerMessage as NeoServerMessage};
+
+    let topic = format!("test-topic-{}", Uuid::new_v4());
+    let url = format!("ws://127.0.0.1:{}", harness.port)
+        .parse::<Url>()?
+        .join("ws")?;
+    let socket = std::env::temp_dir().join(format!("neo-daemon-{}.sock", Uuid::new_v4()));
+    let mut listener = TestClient::connect(harness.port, &topic).await?;
+
+    let daemon_socket = socket.clone();
+    let daemon_topic = topic.clone();
+    let daemon_task = tokio::spawn(async move {
+        let client = Client::new(url, daemon_topic).await.unwrap();
+        daemon::run(client, &daemon_socket).await.unwrap();
+    });
+    for _ in 0..20 {
+        if socket.exists() && harness.client_manager.get_clients_by_topic(&topic).len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    // 1. A message sent through the control socket reaches the topic.
+    let mut control = ControlClient::connect(&socket).await?;
+    control
+        .send("sent through the daemon".to_string())
+        .await
+        .map_err(|e| anyhow::anyhow!(e))?;
+    match listener
+        .recv()
+        .await?
+        .expect("Listener did not receive message")
+    {
+        ServerMessage::Topic { content, .. } => assert_eq!(content, "sent through the daemon"),
+        other => panic!("Unexpected message: {:?}", other),
+    }
+
+    // 2. A tail sees what the daemon receives.
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let tailer = ControlClient::connect(&socket).await?;
+    let tail_task = tokio::spawn(tailer.tail(move |_, message| {
+        let _ = tx.send(message);
+    }));
+    let mut received = None;
+    for attempt in 0..20 {
+        listener
+            .send_message(&topic, &format!("to the daemon {}", attempt))
+            .await?;
+        if let Ok(Some(message)) = tokio::time::timeout(Duration::from_millis(100), rx.recv()).await
+        {
+            received = Some(message);
+            break;
+        }
+    }
+    match received.expect("Tail did not receive the message") {
+        NeoServerMessage::Topic { content, .. } => assert!(content.starts_with("to the daemon")),
+        other => panic!("Unexpected message: {:?}", other),
+    }
+
+    tail_task.abort();
+    daemon_task.abort();
+    let _ = std::fs::remove_file(&socket);
+    Ok(())
+}
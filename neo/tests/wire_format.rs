@@ -0,0 +1,30 @@
+//! Checks neo's copy of the protocol against the golden frames recorded by the server's
+//! wire format tests, so the two cannot drift apart unnoticed. Every frame must decode to
+//! a known message and encode back to the same text.
+
+use neo::core::msg::{self, ClientMessage, ServerMessage};
+use serde::{de::DeserializeOwned, Serialize};
+
+const CLIENT_FRAMES: &str = include_str!("../../morpheus/tests/golden/client_messages.jsonl");
+const SERVER_FRAMES: &str = include_str!("../../morpheus/tests/golden/server_messages.jsonl");
+
+fn check_round_trip<T>(frames: &str, is_unknown: fn(&T) -> bool)
+where
+    T: Serialize + DeserializeOwned + std::fmt::Debug,
+{
+    for frame in frames.lines() {
+        let decoded: T = msg::decode(frame).unwrap_or_else(|e| panic!("{}: {}", frame, e));
+        assert!(!is_unknown(&decoded), "neo does not know {}", frame);
+        assert_eq!(msg::encode(&decoded).unwrap(), frame);
+    }
+}
+
+#[test]
+fn test_client_messages_match_server_golden_frames() {
+    check_round_trip::<ClientMessage>(CLIENT_FRAMES, |m| matches!(m, ClientMessage::Unknown));
+}
+
+#[test]
+fn test_server_messages_match_server_golden_frames() {
+    check_round_trip::<ServerMessage>(SERVER_FRAMES, |m| matches!(m, ServerMessage::Unknown));
+}